@@ -0,0 +1,104 @@
+//! Parser throughput benchmarks. Run with `cargo bench`.
+use btle::bytes::Storage;
+use btle::hci::event::{EventCode, EventPacket};
+use btle::hci::le::report::AdvertisingReport;
+use btle::hci::le::{MetaEvent, RawMetaEvent};
+use btle::hci::packet::{PacketType, RawPacket};
+use btle::hci::stream::Filter;
+use btle::le::advertisement::RawAdvertisement;
+use btle::le::report::ReportInfo;
+use btle::le::service_filter::ServiceFilter;
+use btle::uuid::UUID16;
+use core::convert::TryFrom;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+/// AD data seen from a typical sensor: Flags, a 16-bit service UUID, a short local name and
+/// manufacturer specific data.
+const AD_DATA: [u8; 21] = [
+    0x02, 0x01, 0x06, // Flags
+    0x03, 0x03, 0x0F, 0x18, // Complete 16-bit UUIDs (Battery Service)
+    0x05, 0x09, b'b', b't', b'l', b'e', // Complete Local Name
+    0x07, 0xFF, 0x4C, 0x00, 0x01, 0x02, 0x03, 0x04, // Manufacturer Specific Data
+];
+/// Captured `HCI_LE_Advertising_Report` event (without the H4 packet type byte) with
+/// `num_reports` reports of `AD_DATA`.
+fn advertising_report_event(num_reports: u8) -> Vec<u8> {
+    let n = usize::from(num_reports);
+    let mut params = vec![0x02, num_reports];
+    params.extend(core::iter::repeat(0x00).take(n)); // ADV_IND
+    params.extend(core::iter::repeat(0x01).take(n)); // Random address
+    for i in 0..num_reports {
+        params.extend_from_slice(&[i, 0x22, 0x33, 0x44, 0x55, 0xC6]);
+    }
+    params.extend(core::iter::repeat(AD_DATA.len() as u8).take(n));
+    for _ in 0..n {
+        params.extend_from_slice(&AD_DATA);
+    }
+    params.extend(core::iter::repeat(0xC4).take(n)); // RSSI -60 dBm
+    let mut event = vec![EventCode::LEMeta.into(), params.len() as u8];
+    event.extend_from_slice(&params);
+    event
+}
+fn parse_report(event: &[u8]) -> usize {
+    let packet = RawPacket {
+        packet_type: PacketType::Event,
+        buf: event,
+    };
+    let report = EventPacket::try_from(packet)
+        .and_then(RawMetaEvent::try_from)
+        .and_then(AdvertisingReport::<Vec<ReportInfo>>::meta_unpack_packet)
+        .expect("valid advertising report");
+    report.reports.len()
+}
+fn bench_event_parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("event_parsing");
+    for &num_reports in [1_u8, 4].iter() {
+        let event = advertising_report_event(num_reports);
+        group.throughput(Throughput::Bytes(event.len() as u64));
+        group.bench_function(format!("advertising_report_{}", num_reports), |b| {
+            b.iter(|| parse_report(black_box(&event)))
+        });
+    }
+    group.finish();
+}
+fn bench_ad_iteration(c: &mut Criterion) {
+    let advertisement = RawAdvertisement(&AD_DATA[..]);
+    let mut group = c.benchmark_group("ad_structures");
+    group.throughput(Throughput::Bytes(AD_DATA.len() as u64));
+    group.bench_function("iterate", |b| {
+        b.iter(|| {
+            black_box(&advertisement)
+                .iter()
+                .map(|ad| ad.buf.len())
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+fn bench_service_filter(c: &mut Criterion) {
+    let mut filter = ServiceFilter::new();
+    for uuid in 0x1800..0x1820 {
+        filter.add_uuid16(UUID16(uuid));
+    }
+    filter.add_uuid128(0x6E40_0001_B5A3_F393_E0A9_E50E_24DC_CA9E);
+    c.bench_function("service_filter_matches", |b| {
+        b.iter(|| black_box(&filter).matches(black_box(&AD_DATA)))
+    });
+}
+fn bench_filter_packing(c: &mut Criterion) {
+    let mut filter = Filter::default();
+    filter.enable_type(PacketType::Event);
+    filter.enable_event(EventCode::LEMeta);
+    filter.enable_event(EventCode::CommandComplete);
+    c.bench_function("filter_pack_unpack", |b| {
+        b.iter(|| Filter::unpack(&black_box(&filter).pack()))
+    });
+}
+criterion_group!(
+    benches,
+    bench_event_parsing,
+    bench_ad_iteration,
+    bench_service_filter,
+    bench_filter_packing
+);
+criterion_main!(benches);
@@ -0,0 +1,151 @@
+//! Scans for advertisements and publishes them as JSON to an MQTT broker.
+//!
+//! `cargo run --example mqtt_bridge --features bluez_socket -- <adapter id> <broker:port> <topic>`
+//!
+//! Each batch of reports is one MQTT message holding a JSON array. Only QoS 0 publishing is
+//! implemented, just enough MQTT 3.1.1 to show a [`ReportSink`].
+use btle::hci::adapter;
+use btle::le::report::ReportInfo;
+use btle::le::report_sink::{self, OverflowPolicy, ReportBatcher, ReportSink};
+use btle::le::scan::Observer;
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+pub fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let adapter_id: u16 = args.next().unwrap_or_else(|| "0".to_owned()).parse()?;
+    let broker = args.next().unwrap_or_else(|| "127.0.0.1:1883".to_owned());
+    let topic = args.next().unwrap_or_else(|| "btle/reports".to_owned());
+    let sink = MqttSink::connect(&broker, "btle-bridge", topic)?;
+    let mut runtime = tokio::runtime::Builder::new()
+        .enable_all()
+        .build()
+        .expect("can't make async runtime");
+    runtime.block_on(run(adapter_id, sink))
+}
+#[cfg(feature = "bluez_socket")]
+async fn run(adapter_id: u16, mut sink: MqttSink) -> Result<(), Box<dyn std::error::Error>> {
+    use btle::error::StdError;
+    use std::convert::TryFrom;
+    let manager = btle::hci::bluez_socket::Manager::new().map_err(StdError)?;
+    let socket = manager
+        .get_adapter_socket(btle::hci::bluez_socket::AdapterID(adapter_id))
+        .map_err(StdError)?;
+    let socket = btle::hci::bluez_socket::AsyncHCISocket::try_from(socket)?;
+    let stream = btle::hci::stream::Stream::new(Box::pin(socket));
+    bridge(stream, &mut sink).await.map_err(StdError)?;
+    Ok(())
+}
+#[cfg(not(feature = "bluez_socket"))]
+async fn run(_adapter_id: u16, _sink: MqttSink) -> Result<(), Box<dyn std::error::Error>> {
+    eprintln!("this example needs the `bluez_socket` feature");
+    Ok(())
+}
+#[allow(dead_code)]
+async fn bridge<A: adapter::Adapter>(
+    adapter: A,
+    sink: &mut MqttSink,
+) -> Result<(), adapter::Error> {
+    let adapter = btle::hci::adapters::Adapter::new(adapter);
+    let mut le = adapter.le();
+    le.adapter.reset().await?;
+    le.set_scan_parameters(btle::le::scan::ScanParameters::DEFAULT)
+        .await?;
+    le.set_scan_enable(true, false).await?;
+    let reports = Observer::advertisement_stream(&mut le).await?;
+    let mut batcher = ReportBatcher::new(1024, OverflowPolicy::DropOldest);
+    match report_sink::forward(reports, sink, &mut batcher).await {
+        Ok(stats) => println!("scan ended: {:?}", stats),
+        Err(report_sink::ForwardError::Stream(e)) => return Err(e),
+        Err(report_sink::ForwardError::Sink(e)) => eprintln!("broker error: {}", e),
+    }
+    Ok(())
+}
+/// Publishes batches as JSON over a (blocking) MQTT connection.
+pub struct MqttSink {
+    stream: TcpStream,
+    topic: String,
+}
+impl MqttSink {
+    pub fn connect(broker: &str, client_id: &str, topic: String) -> io::Result<MqttSink> {
+        let mut stream = TcpStream::connect(broker)?;
+        // CONNECT: protocol "MQTT" level 4, clean session, 60 s keep alive.
+        let mut body = Vec::new();
+        put_str(&mut body, "MQTT");
+        body.extend_from_slice(&[0x04, 0x02, 0x00, 0x3C]);
+        put_str(&mut body, client_id);
+        stream.write_all(&packet(0x10, &body))?;
+        let mut connack = [0_u8; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 || connack[3] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "broker refused the connection",
+            ));
+        }
+        Ok(MqttSink { stream, topic })
+    }
+}
+impl ReportSink for MqttSink {
+    type Error = io::Error;
+
+    fn publish<'a>(&'a mut self, batch: &'a [ReportInfo]) -> LocalBoxFuture<'a, io::Result<()>> {
+        async move {
+            let mut body = Vec::new();
+            put_str(&mut body, &self.topic);
+            body.extend_from_slice(to_json(batch).as_bytes());
+            self.stream.write_all(&packet(0x30, &body))
+        }
+        .boxed_local()
+    }
+}
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+/// MQTT fixed header (with the variable length remaining length) followed by `body`.
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![header];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+    out.extend_from_slice(body);
+    out
+}
+fn to_json(batch: &[ReportInfo]) -> String {
+    let mut json = String::from("[");
+    for (i, report) in batch.iter().enumerate() {
+        if i != 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            r#"{{"address":"{}","address_type":{},"event_type":"{}","rssi":"#,
+            report.address,
+            u8::from(report.address_type),
+            report.event_type.as_str(),
+        );
+        match report.rssi {
+            Some(rssi) => {
+                let _ = write!(json, "{}", i8::from(rssi));
+            }
+            None => json.push_str("null"),
+        }
+        json.push_str(r#","data":""#);
+        for byte in report.data.as_ref() {
+            let _ = write!(json, "{:02x}", byte);
+        }
+        json.push_str("\"}");
+    }
+    json.push(']');
+    json
+}
@@ -0,0 +1,33 @@
+//! Driving the crate from a completion based event loop with `PushAdapter`. The loop here fakes
+//! the IO backend: every command the adapter queues is "submitted" and the matching Command
+//! Complete event is pushed back like a finished read would be. A real backend (io_uring, DMA)
+//! would submit `pop_outgoing` buffers as writes and call `push_packet` from its completions.
+use btle::hci::adapters::push::{PacketPusher, PushAdapter};
+use btle::hci::adapters::Adapter;
+use futures_util::task::noop_waker_ref;
+use std::future::Future;
+use std::task::{Context, Poll};
+
+/// Fake controller: answers every command with a successful Command Complete.
+fn complete_io(pusher: &PacketPusher) {
+    while let Some(command) = pusher.pop_outgoing() {
+        println!("submit write {:02X?}", &command[..]);
+        // Event packet, Command Complete, 4 bytes, 1 command allowed, opcode, status Ok.
+        let event = [0x04, 0x0E, 0x04, 0x01, command[1], command[2], 0x00];
+        pusher.push_packet(&event).expect("valid event");
+    }
+}
+pub fn main() {
+    let (push_adapter, pusher) = PushAdapter::new();
+    let mut adapter = Adapter::new(push_adapter);
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let mut reset = Box::pin(adapter.reset());
+    // One iteration per event loop wake up: poll the HCI future, then run IO completions.
+    let result = loop {
+        if let Poll::Ready(result) = reset.as_mut().poll(&mut cx) {
+            break result;
+        }
+        complete_io(&pusher);
+    };
+    println!("reset: {:?}", result);
+}
@@ -33,8 +33,7 @@ async fn main_async() -> Result<(), Box<dyn std::error::Error>> {
             println!("using {:?}", device);
             match device.open() {
                 Ok(handle) => break handle,
-                Err(usbw::libusb::error::Error::NotSupported) => (),
-                Err(e) => Err(e)?,
+                Err(e) => println!("skipping: {}", usb::winusb::classify_open_error(e)),
             };
         }
     };
@@ -321,6 +321,17 @@ pub trait Storage<T: Copy + Default>: AsRef<[T]> + AsMut<[T]> + Unpin {
         out.as_mut().copy_from_slice(buf);
         out
     }
+    /// Like [`Storage::from_slice`] but returns `None` instead of panicking if `buf` doesn't fit.
+    fn try_from_slice(buf: &[T]) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        if buf.len() <= Self::max_len() {
+            Some(Self::from_slice(buf))
+        } else {
+            None
+        }
+    }
     fn max_len() -> usize;
     fn space_left(&self) -> usize {
         Self::max_len() - self.len()
@@ -394,3 +405,52 @@ impl<T: Copy + Unpin + Default, ArrayBuf: AsRef<[T]> + AsMut<[T]> + Default + Co
         self.len
     }
 }
+/// Formats bytes as space separated hex without allocating. At most `limit` bytes are written,
+/// the rest are summarized as `... (+N)` so log lines stay bounded.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct HexDump<'a> {
+    bytes: &'a [u8],
+    limit: usize,
+}
+impl<'a> HexDump<'a> {
+    pub const DEFAULT_LIMIT: usize = 32;
+    pub fn new(bytes: &'a [u8]) -> HexDump<'a> {
+        HexDump {
+            bytes,
+            limit: Self::DEFAULT_LIMIT,
+        }
+    }
+    #[must_use]
+    pub fn with_limit(self, limit: usize) -> HexDump<'a> {
+        HexDump { limit, ..self }
+    }
+}
+impl core::fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let shown = self.bytes.len().min(self.limit);
+        for (i, b) in self.bytes[..shown].iter().enumerate() {
+            if i != 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        if shown < self.bytes.len() {
+            write!(f, " ... (+{})", self.bytes.len() - shown)?;
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::HexDump;
+    #[test]
+    fn test_hex_dump() {
+        let bytes = [0x01_u8, 0xAB, 0x00, 0xFF];
+        assert_eq!(alloc::format!("{}", HexDump::new(&bytes)), "01 ab 00 ff");
+        assert_eq!(
+            alloc::format!("{}", HexDump::new(&bytes).with_limit(2)),
+            "01 ab ... (+2)"
+        );
+        assert_eq!(alloc::format!("{}", HexDump::new(&[])), "");
+    }
+}
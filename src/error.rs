@@ -58,27 +58,115 @@ pub enum IOError {
     NotImplemented,
     NotConnected,
     Interrupted,
+    /// The operation would block (`EAGAIN`), try again later.
+    WouldBlock,
     IllegalCall,
     AlreadyExists,
     Refused,
     Pipe,
     Overflow,
+    /// The radio is turned off by a kill switch (rfkill on Linux).
+    Blocked(Rfkill),
+    /// Someone else has the device.
+    Busy(BusyOwner),
     Other,
     Code(i32),
 }
+impl IOError {
+    /// What the user can do about the error, for the errors opening a transport usually runs
+    /// into.
+    pub fn remediation(&self) -> Option<Remediation> {
+        match *self {
+            IOError::PermissionDenied | IOError::AccessDenied => Some(Remediation::GrantAccess),
+            IOError::Blocked(rfkill) => Some(Remediation::UnblockRadio(rfkill)),
+            IOError::Busy(owner) => Some(Remediation::ReleaseDevice(owner)),
+            _ => None,
+        }
+    }
+}
 impl core::fmt::Display for IOError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "{:?}", self)
+        match self.remediation() {
+            Some(remediation) => write!(f, "{:?} ({})", self, remediation.hint()),
+            None => write!(f, "{:?}", self),
+        }
     }
 }
 impl Error for IOError {}
+/// Kill switch blocking the radio.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum Rfkill {
+    /// Blocked in software (`rfkill block`, airplane mode).
+    Soft,
+    /// Blocked by a hardware switch (whether or not it is soft blocked too).
+    Hard,
+    /// The platform didn't say which.
+    Unknown,
+}
+/// Who has a busy device.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum BusyOwner {
+    /// The OS Bluetooth stack (BlueZ with `bluetoothd`) has the adapter up.
+    HostStack,
+    /// A kernel driver (like `btusb`) has the device claimed.
+    KernelDriver,
+    /// Another process has the device (or port) open.
+    OtherProcess,
+    Unknown,
+}
+/// How to fix an error opening a transport. See [`IOError::remediation`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum Remediation {
+    /// Run with more privileges or grant access to the device.
+    GrantAccess,
+    /// Unblock the radio.
+    UnblockRadio(Rfkill),
+    /// Stop the owner of the device.
+    ReleaseDevice(BusyOwner),
+}
+impl Remediation {
+    /// Short hint on how to fix the error on this platform.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Remediation::GrantAccess if cfg!(windows) => {
+                "run as administrator or install the WinUSB driver for the device"
+            }
+            Remediation::GrantAccess => {
+                "run as root or grant CAP_NET_ADMIN and CAP_NET_RAW (`setcap`) for BlueZ sockets, \
+                 add a udev rule for USB devices or join the `dialout` group for serial ports"
+            }
+            Remediation::UnblockRadio(Rfkill::Hard) => {
+                "the radio is turned off by a hardware switch, turn it back on"
+            }
+            Remediation::UnblockRadio(_) => {
+                "the radio is blocked, unblock it with `rfkill unblock bluetooth` or turn off \
+                 airplane mode"
+            }
+            Remediation::ReleaseDevice(BusyOwner::HostStack) => {
+                "the OS Bluetooth stack is using the adapter, power it off (`btmgmt power off`) \
+                 or stop it (`systemctl stop bluetooth`)"
+            }
+            Remediation::ReleaseDevice(BusyOwner::KernelDriver) => {
+                "a kernel driver has the device claimed, detach it (`btusb`) first"
+            }
+            Remediation::ReleaseDevice(_) => {
+                "the device is in use by another driver or process, close it first"
+            }
+        }
+    }
+}
+impl core::fmt::Display for Remediation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.hint())
+    }
+}
 
 #[cfg(feature = "std")]
 impl From<std::io::ErrorKind> for IOError {
     fn from(e: std::io::ErrorKind) -> Self {
         match e {
             std::io::ErrorKind::NotFound => IOError::NotFound,
-            std::io::ErrorKind::PermissionDenied => IOError::NotFound,
+            std::io::ErrorKind::PermissionDenied => IOError::PermissionDenied,
             std::io::ErrorKind::ConnectionRefused => IOError::Refused,
             std::io::ErrorKind::ConnectionReset => IOError::NotConnected,
             std::io::ErrorKind::ConnectionAborted => IOError::NotConnected,
@@ -87,7 +175,7 @@ impl From<std::io::ErrorKind> for IOError {
             std::io::ErrorKind::AddrNotAvailable => IOError::Refused,
             std::io::ErrorKind::BrokenPipe => IOError::Closed,
             std::io::ErrorKind::AlreadyExists => IOError::AlreadyExists,
-            std::io::ErrorKind::WouldBlock => IOError::Other,
+            std::io::ErrorKind::WouldBlock => IOError::WouldBlock,
             std::io::ErrorKind::InvalidInput => IOError::InvalidArgument,
             std::io::ErrorKind::InvalidData => IOError::InvalidData,
             std::io::ErrorKind::TimedOut => IOError::TimedOut,
@@ -99,19 +187,58 @@ impl From<std::io::ErrorKind> for IOError {
         }
     }
 }
+/// `ERFKILL`: the operation isn't possible because of rfkill.
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub const ERFKILL: i32 = 132;
+/// `EBUSY` (`io::ErrorKind::ResourceBusy` needs Rust 1.83).
+#[cfg(all(feature = "std", unix))]
+pub const EBUSY: i32 = 16;
+/// Errors without a more specific kind keep their OS error code (`IOError::Code`).
 #[cfg(feature = "std")]
 impl From<&std::io::Error> for IOError {
     fn from(e: &std::io::Error) -> Self {
-        e.kind().into()
+        match (IOError::from(e.kind()), e.raw_os_error()) {
+            #[cfg(target_os = "linux")]
+            (_, Some(ERFKILL)) => IOError::Blocked(Rfkill::Unknown),
+            #[cfg(unix)]
+            (_, Some(EBUSY)) => IOError::Busy(BusyOwner::Unknown),
+            (IOError::Other, Some(code)) => IOError::Code(code),
+            (kind, _) => kind,
+        }
     }
 }
 
 #[cfg(feature = "std")]
 impl From<std::io::Error> for IOError {
     fn from(e: std::io::Error) -> Self {
-        e.kind().into()
+        IOError::from(&e)
     }
 }
 
 #[cfg(feature = "std")]
 impl std::error::Error for IOError {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_classify_io_errors() {
+        let denied = IOError::from(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        assert_eq!(denied, IOError::PermissionDenied);
+        assert_eq!(denied.remediation(), Some(Remediation::GrantAccess));
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            IOError::from(std::io::Error::from_raw_os_error(ERFKILL)).remediation(),
+            Some(Remediation::UnblockRadio(Rfkill::Unknown))
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            IOError::from(std::io::Error::from_raw_os_error(EBUSY)),
+            IOError::Busy(BusyOwner::Unknown)
+        );
+        let busy = IOError::Busy(BusyOwner::HostStack);
+        assert!(busy.to_string().contains("systemctl stop bluetooth"));
+        assert_eq!(IOError::TimedOut.remediation(), None);
+    }
+}
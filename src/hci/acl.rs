@@ -0,0 +1,236 @@
+//! HCI ACL data packets. Carry L2CAP (and so ATT/GATT and SMP) traffic between the host and the
+//! controller. [`Stream::send_acl`](crate::hci::stream::Stream::send_acl) writes them and
+//! [`Stream::read_incoming`](crate::hci::stream::Stream::read_incoming) reads them together with
+//! events (and ISO/SCO data).
+use crate::bytes::{HexDump, Storage};
+use crate::hci::event::EventPacket;
+use crate::hci::iso::ISOPacket;
+use crate::hci::packet::{PacketType, RawPacket};
+use crate::hci::sco::SCOPacket;
+use crate::le::connection::ConnectionHandle;
+use crate::{ConversionError, PackError};
+use core::convert::{TryFrom, TryInto};
+
+/// Handle + flags (2 bytes) and data length (2 bytes).
+pub const ACL_HEADER_LEN: usize = 4;
+/// Packet Boundary flag. Says if the packet starts or continues an L2CAP PDU.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum PacketBoundary {
+    /// First fragment of a non-automatically-flushable PDU (host to controller only).
+    FirstNonFlushable = 0b00,
+    Continuing = 0b01,
+    /// First fragment of an automatically flushable PDU. Used for all LE PDUs.
+    FirstFlushable = 0b10,
+    /// Complete automatically flushable PDU (BR/EDR loopback only).
+    Complete = 0b11,
+}
+impl From<PacketBoundary> for u8 {
+    fn from(boundary: PacketBoundary) -> Self {
+        boundary as u8
+    }
+}
+impl TryFrom<u8> for PacketBoundary {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0b00 => Ok(PacketBoundary::FirstNonFlushable),
+            0b01 => Ok(PacketBoundary::Continuing),
+            0b10 => Ok(PacketBoundary::FirstFlushable),
+            0b11 => Ok(PacketBoundary::Complete),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// Broadcast flag. LE only uses `PointToPoint`.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum BroadcastFlag {
+    PointToPoint = 0b00,
+    /// BR/EDR Active Peripheral Broadcast.
+    BREDRBroadcast = 0b01,
+}
+impl From<BroadcastFlag> for u8 {
+    fn from(flag: BroadcastFlag) -> Self {
+        flag as u8
+    }
+}
+impl TryFrom<u8> for BroadcastFlag {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0b00 => Ok(BroadcastFlag::PointToPoint),
+            0b01 => Ok(BroadcastFlag::BREDRBroadcast),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// HCI ACL data packet (without the H4 packet type).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct ACLPacket<Buf> {
+    pub connection_handle: ConnectionHandle,
+    pub boundary: PacketBoundary,
+    pub broadcast: BroadcastFlag,
+    pub data: Buf,
+}
+impl<Buf: AsRef<[u8]>> ACLPacket<Buf> {
+    /// Point to point packet.
+    pub fn new(connection_handle: ConnectionHandle, boundary: PacketBoundary, data: Buf) -> Self {
+        ACLPacket {
+            connection_handle,
+            boundary,
+            broadcast: BroadcastFlag::PointToPoint,
+            data,
+        }
+    }
+    pub fn as_ref(&self) -> ACLPacket<&[u8]> {
+        ACLPacket {
+            connection_handle: self.connection_handle,
+            boundary: self.boundary,
+            broadcast: self.broadcast,
+            data: self.data.as_ref(),
+        }
+    }
+    pub fn to_new_storage<NewBuf: Storage<u8>>(&self) -> ACLPacket<NewBuf> {
+        ACLPacket {
+            connection_handle: self.connection_handle,
+            boundary: self.boundary,
+            broadcast: self.broadcast,
+            data: NewBuf::from_slice(self.data.as_ref()),
+        }
+    }
+    /// Header + data len.
+    pub fn byte_len(&self) -> usize {
+        ACL_HEADER_LEN + self.data.as_ref().len()
+    }
+    pub fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        let data = self.data.as_ref();
+        let data_len = u16::try_from(data.len()).map_err(|_| PackError::InvalidFields)?;
+        let handle = u16::from(self.connection_handle)
+            | (u16::from(u8::from(self.boundary)) << 12)
+            | (u16::from(u8::from(self.broadcast)) << 14);
+        let (header, rest) = buf.split_at_mut(ACL_HEADER_LEN);
+        header[..2].copy_from_slice(&handle.to_le_bytes());
+        header[2..].copy_from_slice(&data_len.to_le_bytes());
+        rest.copy_from_slice(data);
+        Ok(())
+    }
+    /// Packs the H4 framed packet (packet type first).
+    pub fn pack_as_raw_packet<NewBuf: Storage<u8>>(&self) -> Result<NewBuf, PackError> {
+        let len = self.byte_len() + 1;
+        if NewBuf::max_len() < len {
+            return Err(PackError::BadLength {
+                expected: len,
+                got: NewBuf::max_len(),
+            });
+        }
+        let mut out = NewBuf::with_size(len);
+        let (packet_type, rest) = out
+            .as_mut()
+            .split_first_mut()
+            .ok_or(PackError::bad_index(0))?;
+        *packet_type = PacketType::ACLData.into();
+        self.pack_into(rest)?;
+        Ok(out)
+    }
+}
+impl<'a> ACLPacket<&'a [u8]> {
+    pub fn unpack_from(buf: &'a [u8]) -> Result<Self, PackError> {
+        PackError::atleast_length(ACL_HEADER_LEN, buf)?;
+        let (header, data) = buf.split_at(ACL_HEADER_LEN);
+        let handle = u16::from_le_bytes([header[0], header[1]]);
+        let data_len = u16::from_le_bytes([header[2], header[3]]);
+        if usize::from(data_len) != data.len() {
+            return Err(PackError::BadLength {
+                expected: ACL_HEADER_LEN + usize::from(data_len),
+                got: buf.len(),
+            });
+        }
+        let connection_handle =
+            ConnectionHandle::new_checked(handle & 0x0FFF).ok_or(PackError::bad_index(0))?;
+        let boundary = PacketBoundary::try_from(((handle >> 12) & 0b11) as u8)
+            .map_err(|_| PackError::bad_index(1))?;
+        let broadcast = ((handle >> 14) as u8)
+            .try_into()
+            .map_err(|_| PackError::bad_index(1))?;
+        Ok(ACLPacket {
+            connection_handle,
+            boundary,
+            broadcast,
+            data,
+        })
+    }
+}
+impl<'a> TryFrom<RawPacket<&'a [u8]>> for ACLPacket<&'a [u8]> {
+    type Error = PackError;
+
+    fn try_from(packet: RawPacket<&'a [u8]>) -> Result<Self, Self::Error> {
+        if packet.packet_type != PacketType::ACLData {
+            Err(PackError::BadOpcode)
+        } else {
+            ACLPacket::unpack_from(packet.buf)
+        }
+    }
+}
+/// `Handle Boundary [len] data` with the data hex dumped (see [`HexDump`]).
+impl<Buf: AsRef<[u8]>> core::fmt::Display for ACLPacket<Buf> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let data = self.data.as_ref();
+        write!(
+            f,
+            "{:?} {:?} [{}] {}",
+            self.connection_handle,
+            self.boundary,
+            data.len(),
+            HexDump::new(data)
+        )
+    }
+}
+/// Packet read by [`Stream::read_incoming`](crate::hci::stream::Stream::read_incoming).
+pub enum IncomingPacket<Buf> {
+    Event(EventPacket<Buf>),
+    ACL(ACLPacket<Buf>),
+    ISO(ISOPacket<Buf>),
+    SCO(SCOPacket<Buf>),
+}
+impl<Buf: AsRef<[u8]> + core::fmt::Debug> core::fmt::Debug for IncomingPacket<Buf> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            IncomingPacket::Event(event) => f.debug_tuple("Event").field(event).finish(),
+            IncomingPacket::ACL(acl) => f.debug_tuple("ACL").field(acl).finish(),
+            IncomingPacket::ISO(iso) => f.debug_tuple("ISO").field(iso).finish(),
+            IncomingPacket::SCO(sco) => f.debug_tuple("SCO").field(sco).finish(),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::stream::Stream;
+    use crate::testing::mock::MockTransport;
+    use futures_util::FutureExt;
+
+    #[test]
+    fn test_acl_round_trip() {
+        let (transport, handle) = MockTransport::new();
+        let mut stream = Stream::new(Box::pin(transport));
+        // ATT Exchange MTU Request on the ATT channel.
+        let l2cap = [0x03, 0x00, 0x04, 0x00, 0x02, 0x17, 0x00];
+        let packet = ACLPacket::new(
+            ConnectionHandle::new(0x0040),
+            PacketBoundary::FirstFlushable,
+            &l2cap[..],
+        );
+        stream.send_acl(packet).now_or_never().unwrap().unwrap();
+        let written = handle.take_written();
+        assert_eq!(&written[..5], &[0x02, 0x40, 0x20, 0x07, 0x00]);
+
+        handle.inject(&written);
+        match stream.read_incoming::<Box<[u8]>>().now_or_never().unwrap() {
+            Ok(IncomingPacket::ACL(acl)) => assert_eq!(acl.as_ref(), packet),
+            other => panic!("expected an ACL packet, got {:?}", other),
+        }
+        assert!(ACLPacket::unpack_from(&written[1..6]).is_err());
+    }
+}
@@ -1,7 +1,9 @@
 use crate::bytes::Storage;
-use crate::error::IOError;
+use crate::error::{IOError, Remediation};
+use crate::hci::acl::ACLPacket;
 use crate::hci::command::{Command, CommandPacket};
 use crate::hci::event::EventPacket;
+use crate::hci::packet::PacketType;
 use crate::hci::stream::HCI_EVENT_READ_TRIES;
 use crate::hci::StreamError;
 use crate::{hci, LocalBoxFuture};
@@ -13,10 +15,28 @@ pub enum Error {
     IOError(IOError),
     StreamError(hci::StreamError),
     ErrorCode(hci::ErrorCode),
+    /// The controller implements an older spec version than the command needs (see
+    /// [`Adapter::read_local_version`](crate::hci::adapters::Adapter::read_local_version)).
+    UnsupportedByController {
+        required: hci::Version,
+        actual: hci::Version,
+    },
+}
+impl Error {
+    /// See [`IOError::remediation`].
+    pub fn remediation(&self) -> Option<Remediation> {
+        match self {
+            Error::IOError(e) => e.remediation(),
+            _ => None,
+        }
+    }
 }
 impl core::fmt::Display for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "hci adapter error {:?}", self)
+        match self.remediation() {
+            Some(remediation) => write!(f, "hci adapter error {:?} ({})", self, remediation),
+            None => write!(f, "hci adapter error {:?}", self),
+        }
     }
 }
 impl From<IOError> for Error {
@@ -40,10 +60,42 @@ impl From<hci::usb::Error> for Error {
         Error::IOError(e.0)
     }
 }
+/// Keeps the OS error code of errors without a more specific [`IOError`] and turns the ways a
+/// socket or port goes away into `StreamError::Disconnected`.
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::BrokenPipe
+            | std::io::ErrorKind::NotConnected => {
+                Error::StreamError(hci::StreamError::Disconnected)
+            }
+            _ => Error::IOError(IOError::from(e)),
+        }
+    }
+}
 #[cfg(feature = "std")]
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IOError(e) => Some(e),
+            Error::StreamError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
-impl crate::error::Error for Error {}
+impl crate::error::Error for Error {
+    fn source(&self) -> Option<&(dyn crate::error::Error + 'static)> {
+        match self {
+            Error::IOError(e) => Some(e),
+            Error::StreamError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 ///WIP HCI Adapter trait
 pub trait Adapter {
     fn write_command<'s, 'p: 's>(
@@ -54,6 +106,17 @@ pub trait Adapter {
     fn read_event<'s, 'p: 's, S: Storage<u8> + 'p>(
         &'s mut self,
     ) -> LocalBoxFuture<'s, Result<EventPacket<S>, Error>>;
+
+    /// Adapters that can't send ACL data return `UnsupportedPacketType`.
+    fn write_acl<'s, 'p: 's>(
+        &'s mut self,
+        packet: ACLPacket<&'p [u8]>,
+    ) -> LocalBoxFuture<'s, Result<(), Error>> {
+        let _ = packet;
+        Box::pin(async {
+            Err(StreamError::UnsupportedPacketType(PacketType::ACLData.into()).into())
+        })
+    }
 }
 
 /// Dummy HCI Adapter that panics with `unimplemented!` on any function call.
@@ -0,0 +1,297 @@
+//! Multi consumer HCI event [`Dispatcher`]. Any number of components can
+//! [`Dispatcher::subscribe`] with an [`EventFilter`] and get a stream of just the events they care
+//! about. Dropping the [`Subscription`] unsubscribes.
+use crate::hci::adapter;
+use crate::hci::adapters::UnrecognizedEventHandler;
+use crate::hci::event::{EventCode, EventPacket};
+use crate::hci::le::MetaEventCode;
+use crate::le::connection::ConnectionHandle;
+use crate::Stream;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::convert::TryFrom;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+/// Which events a [`Subscription`] receives. Every set condition has to match. An empty filter
+/// matches everything.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct EventFilter {
+    event_codes: Vec<EventCode>,
+    meta_events: Vec<MetaEventCode>,
+    connection_handle: Option<ConnectionHandle>,
+}
+impl EventFilter {
+    pub fn all() -> EventFilter {
+        EventFilter::default()
+    }
+    /// Also match `event_code`.
+    #[must_use]
+    pub fn event_code(mut self, event_code: EventCode) -> EventFilter {
+        self.event_codes.push(event_code);
+        self
+    }
+    /// Also match LE Meta events with sub event `meta_event`.
+    #[must_use]
+    pub fn meta_event(mut self, meta_event: MetaEventCode) -> EventFilter {
+        self.meta_events.push(meta_event);
+        self
+    }
+    /// Only match events about `connection_handle`.
+    #[must_use]
+    pub fn connection_handle(mut self, connection_handle: ConnectionHandle) -> EventFilter {
+        self.connection_handle = Some(connection_handle);
+        self
+    }
+    pub fn matches<B: AsRef<[u8]>>(&self, event: &EventPacket<B>) -> bool {
+        let event = event.as_ref();
+        let code_matches = (self.event_codes.is_empty() && self.meta_events.is_empty())
+            || self.event_codes.contains(&event.event_code)
+            || (event.event_code == EventCode::LEMeta
+                && matches!(meta_event_code(event.parameters), Some(c) if self.meta_events.contains(&c)));
+        code_matches
+            && match self.connection_handle {
+                Some(handle) => connection_handle_of(event) == Some(handle),
+                None => true,
+            }
+    }
+}
+fn meta_event_code(parameters: &[u8]) -> Option<MetaEventCode> {
+    MetaEventCode::try_from(*parameters.get(0)?).ok()
+}
+fn handle_at(parameters: &[u8], index: usize) -> Option<ConnectionHandle> {
+    let bytes = parameters.get(index..index + 2)?;
+    ConnectionHandle::new_checked(u16::from_le_bytes([bytes[0], bytes[1]]) & 0x0FFF)
+}
+/// Connection handle an event is about (if it is about one and the event is known).
+pub fn connection_handle_of(event: EventPacket<&[u8]>) -> Option<ConnectionHandle> {
+    let parameters = event.parameters;
+    match event.event_code {
+        EventCode::DisconnectionComplete
+        | EventCode::EncryptionChange
+        | EventCode::ReadRemoteSupportedFeaturesComplete
+        | EventCode::ReadRemoteVersionInformationComplete
+        | EventCode::EncryptionKeyRefreshComplete => handle_at(parameters, 1),
+        EventCode::LEMeta => match meta_event_code(parameters)? {
+            MetaEventCode::ConnectionComplete
+            | MetaEventCode::ConnectionUpdateComplete
+            | MetaEventCode::ReadRemoteFeatures
+            | MetaEventCode::EnhancedConnectionComplete
+            | MetaEventCode::PHYUpdateCompleteEvent => handle_at(parameters, 2),
+            MetaEventCode::LongTermKeyRequest
+            | MetaEventCode::RemoteConnectionParametersRequest
+            | MetaEventCode::DataLengthChange => handle_at(parameters, 1),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+struct Subscriber {
+    id: u64,
+    filter: EventFilter,
+    queue: VecDeque<EventPacket<Box<[u8]>>>,
+    waker: Option<Waker>,
+}
+#[derive(Default)]
+struct Registry {
+    next_id: u64,
+    subscribers: Vec<Subscriber>,
+    closed: bool,
+}
+/// Fans HCI events out to every matching [`Subscription`]. Cloning gives another handle to the
+/// same subscribers. Feed it events with [`Dispatcher::pump`] or by using it as the
+/// `UnrecognizedEventHandler` of an [`Adapter`](crate::hci::adapters::Adapter).
+///
+/// Queues are unbounded and dispatching never waits, so a subscriber that falls behind only
+/// grows its own queue and never delays the others. For bounded queues (and handles usable from
+/// other threads) use the [`EventRouter`](crate::hci::adapters::router::EventRouter), which takes
+/// the same [`EventFilter`]s.
+#[derive(Clone, Default)]
+pub struct Dispatcher {
+    registry: Rc<RefCell<Registry>>,
+}
+impl Dispatcher {
+    pub fn new() -> Dispatcher {
+        Dispatcher::default()
+    }
+    pub fn subscribe(&self, filter: EventFilter) -> Subscription {
+        let mut registry = self.registry.borrow_mut();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.subscribers.push(Subscriber {
+            id,
+            filter,
+            queue: VecDeque::new(),
+            waker: None,
+        });
+        Subscription {
+            id,
+            registry: self.registry.clone(),
+        }
+    }
+    pub fn subscriber_count(&self) -> usize {
+        self.registry.borrow().subscribers.len()
+    }
+    /// Queues `event` for every matching subscriber. Returns how many subscribers got it.
+    pub fn dispatch<B: AsRef<[u8]>>(&self, event: &EventPacket<B>) -> usize {
+        let mut registry = self.registry.borrow_mut();
+        let mut delivered = 0;
+        for subscriber in &mut registry.subscribers {
+            if subscriber.filter.matches(event) {
+                subscriber.queue.push_back(event.to_new_storage());
+                if let Some(waker) = subscriber.waker.take() {
+                    waker.wake();
+                }
+                delivered += 1;
+            }
+        }
+        delivered
+    }
+    /// Reads one event from `adapter` and dispatches it.
+    pub async fn pump<A: adapter::Adapter>(
+        &self,
+        adapter: &mut A,
+    ) -> Result<usize, adapter::Error> {
+        let event: EventPacket<Box<[u8]>> = adapter.read_event().await?;
+        Ok(self.dispatch(&event))
+    }
+    /// Ends every subscription stream (after their queued events).
+    pub fn close(&self) {
+        let mut registry = self.registry.borrow_mut();
+        registry.closed = true;
+        for subscriber in &mut registry.subscribers {
+            if let Some(waker) = subscriber.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+impl UnrecognizedEventHandler for Dispatcher {
+    type Buf = Box<[u8]>;
+
+    fn handle(&mut self, event: EventPacket<Self::Buf>) -> Result<(), adapter::Error> {
+        self.dispatch(&event);
+        Ok(())
+    }
+    fn close(&mut self) {
+        Dispatcher::close(self);
+    }
+}
+/// Stream of the events matching one [`EventFilter`]. Unsubscribes when dropped.
+pub struct Subscription {
+    id: u64,
+    registry: Rc<RefCell<Registry>>,
+}
+impl Subscription {
+    /// Takes the next queued event without waiting.
+    pub fn try_next(&mut self) -> Option<EventPacket<Box<[u8]>>> {
+        let mut registry = self.registry.borrow_mut();
+        let id = self.id;
+        registry
+            .subscribers
+            .iter_mut()
+            .find(|s| s.id == id)?
+            .queue
+            .pop_front()
+    }
+}
+impl Stream for Subscription {
+    type Item = EventPacket<Box<[u8]>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut registry = self.registry.borrow_mut();
+        let closed = registry.closed;
+        let id = self.id;
+        let subscriber = match registry.subscribers.iter_mut().find(|s| s.id == id) {
+            Some(subscriber) => subscriber,
+            None => return Poll::Ready(None),
+        };
+        match subscriber.queue.pop_front() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if closed => Poll::Ready(None),
+            None => {
+                subscriber.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let id = self.id;
+        self.registry
+            .borrow_mut()
+            .subscribers
+            .retain(|s| s.id != id);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::task::noop_waker_ref;
+    #[test]
+    fn test_filtered_subscriptions() {
+        let dispatcher = Dispatcher::new();
+        let mut all = dispatcher.subscribe(EventFilter::all());
+        let mut link = dispatcher.subscribe(
+            EventFilter::all()
+                .event_code(EventCode::DisconnectionComplete)
+                .meta_event(MetaEventCode::ConnectionUpdateComplete)
+                .connection_handle(ConnectionHandle::new(0x0040)),
+        );
+        let disconnect =
+            EventPacket::new(EventCode::DisconnectionComplete, [0x00, 0x40, 0x00, 0x13]);
+        let other_link =
+            EventPacket::new(EventCode::DisconnectionComplete, [0x00, 0x41, 0x00, 0x13]);
+        let update = EventPacket::new(
+            EventCode::LEMeta,
+            [0x03, 0x00, 0x40, 0x20, 0x18, 0x00, 0x00, 0x00, 0x48, 0x00],
+        );
+        assert_eq!(dispatcher.dispatch(&disconnect), 2);
+        assert_eq!(dispatcher.dispatch(&other_link), 1);
+        assert_eq!(dispatcher.dispatch(&update), 2);
+
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut received = Vec::new();
+        while let Poll::Ready(Some(event)) = Pin::new(&mut link).poll_next(&mut cx) {
+            received.push(event.event_code);
+        }
+        assert_eq!(
+            received,
+            [EventCode::DisconnectionComplete, EventCode::LEMeta]
+        );
+        assert_eq!(all.try_next().map(|e| e.parameters[1]), Some(0x40));
+
+        drop(link);
+        assert_eq!(dispatcher.subscriber_count(), 1);
+        dispatcher.close();
+        all.try_next();
+        all.try_next();
+        assert!(matches!(
+            Pin::new(&mut all).poll_next(&mut cx),
+            Poll::Ready(None)
+        ));
+    }
+    #[test]
+    fn test_lagging_subscriber() {
+        let dispatcher = Dispatcher::new();
+        // Never drained.
+        let scan =
+            dispatcher.subscribe(EventFilter::all().meta_event(MetaEventCode::AdvertisingReport));
+        let mut link = dispatcher
+            .subscribe(EventFilter::all().connection_handle(ConnectionHandle::new(0x0040)));
+        let report = EventPacket::new(EventCode::LEMeta, [0x02, 0x00]);
+        let disconnect =
+            EventPacket::new(EventCode::DisconnectionComplete, [0x00, 0x40, 0x00, 0x13]);
+        for _ in 0..1000 {
+            assert_eq!(dispatcher.dispatch(&report), 1);
+            assert_eq!(dispatcher.dispatch(&disconnect), 1);
+            assert!(link.try_next().is_some());
+        }
+        assert_eq!(dispatcher.subscriber_count(), 2);
+        drop(scan);
+    }
+}
@@ -0,0 +1,219 @@
+//! Controller error statistics. [`ControllerErrorStats`] counts Hardware Error and Data Buffer
+//! Overflow events and disconnects caused by link layer (LL/LMP) problems so failing dongles and
+//! RF trouble show up in telemetry. Counters are forwarded to a [`MetricsRecorder`] and the stats
+//! themselves are a `Copy` snapshot for diagnostics.
+use crate::hci::adapter;
+use crate::hci::adapters::UnrecognizedEventHandler;
+use crate::hci::event::{EventCode, EventPacket};
+use crate::hci::ErrorCode;
+use core::convert::TryFrom;
+
+/// Sink for counters (a metrics crate, a telemetry uploader, ...).
+pub trait MetricsRecorder {
+    fn increment_counter(&mut self, name: &'static str, value: u64);
+}
+/// Discards every counter.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct NoMetrics;
+impl MetricsRecorder for NoMetrics {
+    fn increment_counter(&mut self, _name: &'static str, _value: u64) {}
+}
+/// Why a connection was lost, for the disconnect reasons that point at a link problem.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum LinkFailure {
+    /// Supervision timeout (`ErrorCode::ConnectionTimeout`).
+    SupervisionTimeout,
+    /// LL/LMP procedure failures (response timeouts, collisions, instant passed, ...).
+    LinkLayerError,
+    MICFailure,
+    /// The connection was never established (`ErrorCode::ConnectionFailedToBeEstablished`).
+    FailedToEstablish,
+}
+impl LinkFailure {
+    /// `None` if `reason` is a normal disconnect (for example the user ending the connection).
+    pub fn from_reason(reason: ErrorCode) -> Option<LinkFailure> {
+        match reason {
+            ErrorCode::ConnectionTimeout => Some(LinkFailure::SupervisionTimeout),
+            ErrorCode::LMPResponseTimeout
+            | ErrorCode::LMPErrorTransactionCollision
+            | ErrorCode::LMPPDUNotAllowed
+            | ErrorCode::UnknownLMPPDU
+            | ErrorCode::InvalidLMPParameters
+            | ErrorCode::UnsupportedLMPParameter
+            | ErrorCode::InstantPassed
+            | ErrorCode::TransactionCollision => Some(LinkFailure::LinkLayerError),
+            ErrorCode::ConnectionTerminatedMICFailure => Some(LinkFailure::MICFailure),
+            ErrorCode::ConnectionFailedToBeEstablished => Some(LinkFailure::FailedToEstablish),
+            _ => None,
+        }
+    }
+}
+/// One counted error.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ControllerError {
+    HardwareError(u8),
+    /// ACL (`true`) or synchronous (`false`) data buffer overflowed.
+    DataBufferOverflow {
+        acl: bool,
+    },
+    Disconnect(LinkFailure),
+}
+impl ControllerError {
+    pub fn metric_name(self) -> &'static str {
+        match self {
+            ControllerError::HardwareError(_) => "btle.controller.hardware_errors",
+            ControllerError::DataBufferOverflow { acl: true } => {
+                "btle.controller.acl_buffer_overflows"
+            }
+            ControllerError::DataBufferOverflow { acl: false } => {
+                "btle.controller.sync_buffer_overflows"
+            }
+            ControllerError::Disconnect(LinkFailure::SupervisionTimeout) => {
+                "btle.link.supervision_timeouts"
+            }
+            ControllerError::Disconnect(LinkFailure::LinkLayerError) => {
+                "btle.link.link_layer_errors"
+            }
+            ControllerError::Disconnect(LinkFailure::MICFailure) => "btle.link.mic_failures",
+            ControllerError::Disconnect(LinkFailure::FailedToEstablish) => {
+                "btle.link.failed_to_establish"
+            }
+        }
+    }
+    /// The error reported by `event` (if any).
+    pub fn from_event(event: EventPacket<&[u8]>) -> Option<ControllerError> {
+        let parameters = event.parameters;
+        match event.event_code {
+            EventCode::HardwareError => Some(ControllerError::HardwareError(*parameters.first()?)),
+            EventCode::DataBufferOverflow => Some(ControllerError::DataBufferOverflow {
+                acl: *parameters.first()? == 0x01,
+            }),
+            EventCode::DisconnectionComplete => {
+                if ErrorCode::try_from(*parameters.first()?).ok()? != ErrorCode::Ok {
+                    return None;
+                }
+                LinkFailure::from_reason(ErrorCode::try_from(*parameters.get(3)?).ok()?)
+                    .map(ControllerError::Disconnect)
+            }
+            _ => None,
+        }
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct ControllerErrorStats {
+    pub hardware_errors: u64,
+    pub last_hardware_code: Option<u8>,
+    pub acl_buffer_overflows: u64,
+    pub sync_buffer_overflows: u64,
+    pub supervision_timeouts: u64,
+    pub link_layer_errors: u64,
+    pub mic_failures: u64,
+    pub failed_to_establish: u64,
+}
+impl ControllerErrorStats {
+    pub fn new() -> ControllerErrorStats {
+        ControllerErrorStats::default()
+    }
+    /// Sum of every counter.
+    pub fn total(&self) -> u64 {
+        self.hardware_errors
+            + self.acl_buffer_overflows
+            + self.sync_buffer_overflows
+            + self.supervision_timeouts
+            + self.link_layer_errors
+            + self.mic_failures
+            + self.failed_to_establish
+    }
+    pub fn add(&mut self, error: ControllerError) {
+        match error {
+            ControllerError::HardwareError(code) => {
+                self.hardware_errors += 1;
+                self.last_hardware_code = Some(code);
+            }
+            ControllerError::DataBufferOverflow { acl: true } => self.acl_buffer_overflows += 1,
+            ControllerError::DataBufferOverflow { acl: false } => self.sync_buffer_overflows += 1,
+            ControllerError::Disconnect(LinkFailure::SupervisionTimeout) => {
+                self.supervision_timeouts += 1;
+            }
+            ControllerError::Disconnect(LinkFailure::LinkLayerError) => {
+                self.link_layer_errors += 1;
+            }
+            ControllerError::Disconnect(LinkFailure::MICFailure) => self.mic_failures += 1,
+            ControllerError::Disconnect(LinkFailure::FailedToEstablish) => {
+                self.failed_to_establish += 1;
+            }
+        }
+    }
+    /// Counts the error in `event` (if any) and forwards it to `recorder`.
+    pub fn record<B: AsRef<[u8]>, R: MetricsRecorder>(
+        &mut self,
+        event: &EventPacket<B>,
+        recorder: &mut R,
+    ) -> Option<ControllerError> {
+        let error = ControllerError::from_event(event.as_ref())?;
+        self.add(error);
+        recorder.increment_counter(error.metric_name(), 1);
+        Some(error)
+    }
+}
+/// [`UnrecognizedEventHandler`] that counts errors in the events passing through before handing
+/// them to the inner handler.
+pub struct ErrorStatsHandler<H: UnrecognizedEventHandler, R: MetricsRecorder = NoMetrics> {
+    pub stats: ControllerErrorStats,
+    pub recorder: R,
+    pub inner: H,
+}
+impl<H: UnrecognizedEventHandler, R: MetricsRecorder> ErrorStatsHandler<H, R> {
+    pub fn new(inner: H, recorder: R) -> Self {
+        ErrorStatsHandler {
+            stats: ControllerErrorStats::new(),
+            recorder,
+            inner,
+        }
+    }
+    /// Diagnostics snapshot of the counters.
+    pub fn snapshot(&self) -> ControllerErrorStats {
+        self.stats
+    }
+}
+impl<H: UnrecognizedEventHandler, R: MetricsRecorder> UnrecognizedEventHandler
+    for ErrorStatsHandler<H, R>
+{
+    type Buf = H::Buf;
+
+    fn handle(&mut self, event: EventPacket<Self::Buf>) -> Result<(), adapter::Error> {
+        self.stats.record(&event, &mut self.recorder);
+        self.inner.handle(event)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[derive(Default)]
+    struct Counter(u64);
+    impl MetricsRecorder for Counter {
+        fn increment_counter(&mut self, _name: &'static str, value: u64) {
+            self.0 += value;
+        }
+    }
+    #[test]
+    fn test_error_stats() {
+        let mut stats = ControllerErrorStats::new();
+        let mut counter = Counter::default();
+        let events: [(EventCode, &[u8]); 4] = [
+            (EventCode::HardwareError, &[0x42]),
+            (EventCode::DataBufferOverflow, &[0x01]),
+            (EventCode::DisconnectionComplete, &[0x00, 0x40, 0x00, 0x08]),
+            // User ended the connection, not an error.
+            (EventCode::DisconnectionComplete, &[0x00, 0x40, 0x00, 0x13]),
+        ];
+        for (code, parameters) in events.iter() {
+            stats.record(&EventPacket::new(*code, *parameters), &mut counter);
+        }
+        assert_eq!(stats.last_hardware_code, Some(0x42));
+        assert_eq!(stats.acl_buffer_overflows, 1);
+        assert_eq!(stats.supervision_timeouts, 1);
+        assert_eq!(stats.total(), 3);
+        assert_eq!(counter.0, 3);
+    }
+}
@@ -0,0 +1,155 @@
+//! RAII guards for legacy scanning and advertising. [`LEAdapter::start_scanning`] and
+//! [`LEAdapter::start_advertising`] return a [`ScanGuard`]/[`AdvGuard`] that stops the radio
+//! again when dropped, so an early return (or `?`) can't leave the controller scanning forever.
+//!
+//! Drop can't `.await`, so dropping a guard only records the disable in the [`Adapter`]'s
+//! [`PendingDisables`]. They are sent before the next command goes out (or explicitly with
+//! [`Adapter::flush_pending_disables`]). [`ScanGuard::stop`]/[`AdvGuard::stop`] disable right
+//! away and report errors.
+use crate::hci::adapter;
+use crate::hci::adapters::le::LEAdapter;
+use crate::hci::adapters::{Adapter, UnrecognizedEventHandler};
+use core::ops::{Deref, DerefMut};
+
+/// Disable commands still to be sent for dropped guards.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct PendingDisables {
+    pub scanning: bool,
+    pub advertising: bool,
+}
+impl PendingDisables {
+    pub fn is_empty(self) -> bool {
+        !self.scanning && !self.advertising
+    }
+}
+macro_rules! radio_guard {
+    ($(#[$meta:meta])* $name:ident, $pending:ident, $disable:ident($($arg:expr),*)) => {
+        $(#[$meta])*
+        pub struct $name<'a, A: adapter::Adapter, H: UnrecognizedEventHandler> {
+            adapter: Option<&'a mut LEAdapter<A, H>>,
+        }
+        impl<'a, A: adapter::Adapter, H: UnrecognizedEventHandler> $name<'a, A, H> {
+            pub(crate) fn new(adapter: &'a mut LEAdapter<A, H>) -> Self {
+                Self {
+                    adapter: Some(adapter),
+                }
+            }
+            /// Disables right away.
+            pub async fn stop(mut self) -> Result<(), adapter::Error> {
+                let adapter = self.adapter.take().expect("only taken when consumed");
+                adapter.$disable($($arg),*).await
+            }
+            /// Gives the adapter back without disabling.
+            pub fn keep(mut self) -> &'a mut LEAdapter<A, H> {
+                self.adapter.take().expect("only taken when consumed")
+            }
+        }
+        impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Deref for $name<'_, A, H> {
+            type Target = LEAdapter<A, H>;
+
+            fn deref(&self) -> &Self::Target {
+                self.adapter.as_deref().expect("only taken when consumed")
+            }
+        }
+        impl<A: adapter::Adapter, H: UnrecognizedEventHandler> DerefMut for $name<'_, A, H> {
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                self.adapter.as_deref_mut().expect("only taken when consumed")
+            }
+        }
+        impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Drop for $name<'_, A, H> {
+            fn drop(&mut self) {
+                if let Some(adapter) = self.adapter.take() {
+                    adapter.adapter.pending_disables.$pending = true;
+                }
+            }
+        }
+    };
+}
+radio_guard!(
+    /// Legacy scanning is enabled while this lives. Derefs to the [`LEAdapter`] so reports can be
+    /// read through it.
+    ScanGuard,
+    scanning,
+    set_scan_enable(false, false)
+);
+radio_guard!(
+    /// Legacy advertising is enabled while this lives.
+    AdvGuard,
+    advertising,
+    set_advertising_enable(false)
+);
+impl<A: adapter::Adapter, H: UnrecognizedEventHandler> LEAdapter<A, H> {
+    /// Enables legacy scanning until the returned guard is dropped (or stopped).
+    pub async fn start_scanning(
+        &mut self,
+        filter_duplicates: bool,
+    ) -> Result<ScanGuard<'_, A, H>, adapter::Error> {
+        self.set_scan_enable(true, filter_duplicates).await?;
+        Ok(ScanGuard::new(self))
+    }
+    /// Enables legacy advertising until the returned guard is dropped (or stopped).
+    pub async fn start_advertising(&mut self) -> Result<AdvGuard<'_, A, H>, adapter::Error> {
+        self.set_advertising_enable(true).await?;
+        Ok(AdvGuard::new(self))
+    }
+}
+impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Adapter<A, H> {
+    pub fn pending_disables(&self) -> PendingDisables {
+        self.pending_disables
+    }
+    /// Sends the disables of dropped guards. Done automatically before every command.
+    pub async fn flush_pending_disables(&mut self) -> Result<(), adapter::Error> {
+        let pending = core::mem::take(&mut self.pending_disables);
+        if pending.advertising {
+            self.send_command_now(crate::hci::le::commands::SetAdvertisingEnable {
+                is_enabled: false,
+            })
+            .await?
+            .params
+            .status
+            .error()?;
+        }
+        if pending.scanning {
+            self.send_command_now(crate::hci::le::commands::SetScanEnable {
+                is_enabled: false,
+                filter_duplicates: false,
+            })
+            .await?
+            .params
+            .status
+            .error()?;
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::stream::Stream;
+    use crate::testing::virtual_controller::VirtualController;
+    use crate::BTAddress;
+    use futures_util::FutureExt;
+
+    #[test]
+    fn test_dropped_guard_disables() {
+        let (controller, handle) = VirtualController::new(BTAddress([1, 2, 3, 4, 5, 6]));
+        let mut adapter = Adapter::new(Stream::new(Box::pin(controller))).le();
+        let result: Result<(), adapter::Error> = async {
+            let _scanning = adapter.start_scanning(false).await?;
+            // Early return while scanning.
+            Err(adapter::Error::BadParameter)
+        }
+        .now_or_never()
+        .unwrap();
+        assert!(result.is_err());
+        assert!(handle.is_scanning());
+        assert!(adapter.adapter.pending_disables().scanning);
+
+        let advertising = adapter.start_advertising().now_or_never().unwrap().unwrap();
+        assert!(!handle.is_scanning());
+        assert!(handle.is_advertising());
+        advertising.stop().now_or_never().unwrap().unwrap();
+        assert!(!handle.is_advertising());
+        assert!(adapter.adapter.pending_disables().is_empty());
+    }
+}
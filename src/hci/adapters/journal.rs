@@ -0,0 +1,236 @@
+//! Write ahead journal of the last commands sent and events received. Wrap an adapter in a
+//! [`JournalAdapter`] and print its [`Journal`] in a panic hook or bug report to see the exact
+//! command sequence a controller saw (for example when it stops responding during init). Key
+//! material is removed by a [`Redactor`] before anything is recorded.
+use crate::bytes::{HexDump, Storage};
+use crate::hci::acl::ACLPacket;
+use crate::hci::adapter;
+use crate::hci::command::CommandPacket;
+use crate::hci::event::{EventCode, EventPacket};
+use crate::hci::Opcode;
+use crate::LocalBoxFuture;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum JournalRecord {
+    Command {
+        opcode: Opcode,
+        parameters: Box<[u8]>,
+    },
+    Event {
+        event_code: EventCode,
+        parameters: Box<[u8]>,
+    },
+}
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct JournalEntry {
+    /// Increases by one for every record (so gaps show how many were dropped).
+    pub sequence: u64,
+    pub record: JournalRecord,
+}
+impl core::fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.record {
+            JournalRecord::Command { opcode, parameters } => write!(
+                f,
+                "#{} > {} {}",
+                self.sequence,
+                opcode,
+                HexDump::new(parameters)
+            ),
+            JournalRecord::Event {
+                event_code,
+                parameters,
+            } => write!(
+                f,
+                "#{} < {:?} {}",
+                self.sequence,
+                event_code,
+                HexDump::new(parameters)
+            ),
+        }
+    }
+}
+/// Removes secrets from parameters before they are journaled.
+pub trait Redactor {
+    fn redact_command(&self, _opcode: Opcode, _parameters: &mut [u8]) {}
+    fn redact_event(&self, _event_code: EventCode, _parameters: &mut [u8]) {}
+}
+/// Zeroes the keys in the standard HCI commands and events that carry them (LE Encrypt, LE Start
+/// Encryption, LE Long Term Key Request Reply, LE Add Device To Resolving List, Link Key Request
+/// Reply and Link Key Notification).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct KeyRedactor;
+fn zero(parameters: &mut [u8], range: core::ops::Range<usize>) {
+    let end = range.end.min(parameters.len());
+    if range.start < end {
+        parameters[range.start..end].iter_mut().for_each(|b| *b = 0);
+    }
+}
+impl Redactor for KeyRedactor {
+    fn redact_command(&self, opcode: Opcode, parameters: &mut [u8]) {
+        match u16::from(opcode) {
+            // LE Encrypt: Key.
+            0x2017 => zero(parameters, 0..16),
+            // LE Start Encryption: Long Term Key.
+            0x2019 => zero(parameters, 12..28),
+            // LE Long Term Key Request Reply: Long Term Key.
+            0x201A => zero(parameters, 2..18),
+            // LE Add Device To Resolving List: Peer and Local IRKs.
+            0x2027 => zero(parameters, 7..39),
+            // Link Key Request Reply: Link Key.
+            0x040B => zero(parameters, 6..22),
+            _ => (),
+        }
+    }
+    fn redact_event(&self, event_code: EventCode, parameters: &mut [u8]) {
+        if event_code == EventCode::LinkKeyNotification {
+            zero(parameters, 6..22);
+        }
+    }
+}
+/// Keeps the last `capacity` records.
+#[derive(Clone, Debug)]
+pub struct Journal<R: Redactor = KeyRedactor> {
+    entries: VecDeque<JournalEntry>,
+    capacity: usize,
+    next_sequence: u64,
+    redactor: R,
+}
+impl Journal<KeyRedactor> {
+    pub fn new(capacity: usize) -> Self {
+        Journal::with_redactor(capacity, KeyRedactor)
+    }
+}
+impl<R: Redactor> Journal<R> {
+    pub fn with_redactor(capacity: usize, redactor: R) -> Self {
+        Journal {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            next_sequence: 0,
+            redactor,
+        }
+    }
+    fn push(&mut self, record: JournalRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(JournalEntry {
+            sequence: self.next_sequence,
+            record,
+        });
+        self.next_sequence += 1;
+    }
+    pub fn record_command(&mut self, packet: CommandPacket<&[u8]>) {
+        let mut parameters: Box<[u8]> = packet.parameters.into();
+        self.redactor.redact_command(packet.opcode, &mut parameters);
+        self.push(JournalRecord::Command {
+            opcode: packet.opcode,
+            parameters,
+        });
+    }
+    pub fn record_event(&mut self, event: EventPacket<&[u8]>) {
+        let mut parameters: Box<[u8]> = event.parameters().into();
+        self.redactor
+            .redact_event(event.event_code(), &mut parameters);
+        self.push(JournalRecord::Event {
+            event_code: event.event_code(),
+            parameters,
+        });
+    }
+    /// Oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = &JournalEntry> + '_ {
+        self.entries.iter()
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+/// One entry per line, oldest first.
+impl<R: Redactor> core::fmt::Display for Journal<R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+/// [`adapter::Adapter`] that journals every command written and event read by the inner adapter.
+pub struct JournalAdapter<A: adapter::Adapter, R: Redactor = KeyRedactor> {
+    pub adapter: A,
+    pub journal: Journal<R>,
+}
+impl<A: adapter::Adapter, R: Redactor> JournalAdapter<A, R> {
+    pub fn new(adapter: A, journal: Journal<R>) -> Self {
+        JournalAdapter { adapter, journal }
+    }
+}
+impl<A: adapter::Adapter, R: Redactor> adapter::Adapter for JournalAdapter<A, R> {
+    fn write_command<'s, 'p: 's>(
+        &'s mut self,
+        packet: CommandPacket<&'p [u8]>,
+    ) -> LocalBoxFuture<'s, Result<(), adapter::Error>> {
+        // Journaled before writing so a command that hangs the controller is still recorded.
+        self.journal.record_command(packet.as_ref());
+        self.adapter.write_command(packet)
+    }
+
+    fn read_event<'s, 'p: 's, S: Storage<u8> + 'p>(
+        &'s mut self,
+    ) -> LocalBoxFuture<'s, Result<EventPacket<S>, adapter::Error>> {
+        Box::pin(async move {
+            let event: EventPacket<S> = self.adapter.read_event().await?;
+            self.journal.record_event(event.as_ref());
+            Ok(event)
+        })
+    }
+
+    fn write_acl<'s, 'p: 's>(
+        &'s mut self,
+        packet: ACLPacket<&'p [u8]>,
+    ) -> LocalBoxFuture<'s, Result<(), adapter::Error>> {
+        self.adapter.write_acl(packet)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::TryFrom;
+    #[test]
+    fn test_journal_redacts_and_wraps() {
+        let mut journal = Journal::new(2);
+        let ltk_reply = Opcode::try_from(0x201A_u16).unwrap();
+        let mut parameters = [0xAA_u8; 18];
+        parameters[..2].copy_from_slice(&[0x40, 0x00]);
+        journal.record_command(CommandPacket {
+            opcode: ltk_reply,
+            parameters: &parameters[..],
+        });
+        journal.record_event(EventPacket::new(EventCode::CommandComplete, &[0x01][..]));
+        journal.record_event(EventPacket::new(EventCode::CommandComplete, &[0x02][..]));
+        assert_eq!(journal.len(), 2);
+        assert_eq!(journal.entries().next().unwrap().sequence, 1);
+        let mut journal = Journal::new(1);
+        journal.record_command(CommandPacket {
+            opcode: ltk_reply,
+            parameters: &parameters[..],
+        });
+        match &journal.entries().next().unwrap().record {
+            JournalRecord::Command { parameters, .. } => {
+                assert_eq!(&parameters[..2], &[0x40, 0x00]);
+                assert!(parameters[2..].iter().all(|&b| b == 0));
+            }
+            JournalRecord::Event { .. } => panic!("expected a command"),
+        };
+    }
+}
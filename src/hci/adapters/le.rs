@@ -1,5 +1,7 @@
+use crate::hci::adapters::power::PowerControl;
 use crate::hci::adapters::{Adapter, UnrecognizedEventHandler};
 use crate::hci::baseband::{EventMask, EventMaskFlags};
+use crate::hci::flow::DataCredits;
 use crate::hci::le::mask::{MetaEventMask, SetMetaEventMask};
 use crate::hci::le::MetaEventCode;
 use crate::le::advertiser::Advertiser;
@@ -9,28 +11,94 @@ use crate::{
     hci::{
         adapter,
         event::{EventCode, EventPacket},
-        le::{self, random::RAND_LEN, report::AdvertisingReport, MetaEvent, RawMetaEvent},
-        StreamError,
+        le::{
+            self,
+            data_length::DataLength,
+            extended_advertise::{
+                AdvertisingHandles, AdvertisingSetEnable, DataOperation, ExtendedAdvertisingData,
+                ExtendedAdvertisingParameters, FragmentPreference, TerminationReason,
+                MAX_ADVERTISING_DATA_FRAGMENT_LEN, MAX_EXTENDED_ADVERTISING_DATA_LEN,
+            },
+            random::RAND_LEN,
+            report::AdvertisingReport,
+            MetaEvent, RawMetaEvent,
+        },
+        ErrorCode, StreamError, Version,
     },
     le::{
         advertisement::{StaticAdvBuffer, MAX_ADV_LEN},
-        advertiser::AdvertisingParameters,
+        advertiser::{AdvertisingParameters, PeerAddressType},
+        connection::{ConnectionHandle, ConnectionParameters},
+        privacy::{LocalAddresses, ResolvingList},
         report::ReportInfo,
-        scan::ScanParameters,
+        scan::{
+            ExtendedScanError, ExtendedScanParameters, FilterDuplicates, OwnAddressType,
+            ScanParameters, ScanningFilterPolicy,
+        },
+        whitelist::{FilterLocation, Whitelist, WhitelistEntry},
     },
-    Stream,
+    BTAddress, Stream,
 };
+use alloc::vec::Vec;
 use core::convert::TryFrom;
+use core::time::Duration;
 use futures_util::future::LocalBoxFuture;
 use futures_util::stream::LocalBoxStream;
 use futures_util::{FutureExt, StreamExt};
 
+/// What [`LEAdapter::shutdown`] cleans up besides advertising and scanning.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ShutdownOptions<'a> {
+    /// The controller was driven with the extended advertising and scanning commands. Controllers
+    /// reject legacy commands after extended ones were used (and the other way around), so only
+    /// the matching disables are sent.
+    pub extended: bool,
+    /// Connections to disconnect.
+    pub connections: &'a [ConnectionHandle],
+    pub disconnect_reason: ErrorCode,
+}
+impl Default for ShutdownOptions<'_> {
+    fn default() -> Self {
+        ShutdownOptions {
+            extended: false,
+            connections: &[],
+            disconnect_reason: ErrorCode::OtherEndTerminatedConnectionAboutToPowerOff,
+        }
+    }
+}
 pub struct LEAdapter<A: adapter::Adapter, H: UnrecognizedEventHandler> {
     pub adapter: Adapter<A, H>,
+    /// Checked against the own address type of scanning, advertising and connection commands.
+    pub local_addresses: LocalAddresses,
 }
 impl<A: adapter::Adapter, H: UnrecognizedEventHandler> LEAdapter<A, H> {
     pub fn new(adapter: Adapter<A, H>) -> Self {
-        Self { adapter }
+        Self {
+            adapter,
+            local_addresses: LocalAddresses::default(),
+        }
+    }
+    fn check_own_address_type(
+        &self,
+        own_address_type: OwnAddressType,
+    ) -> Result<(), adapter::Error> {
+        self.local_addresses
+            .check(own_address_type)
+            .map_err(|_| adapter::Error::BadParameter)
+    }
+    /// Sets the random device address and records it in `local_addresses`.
+    pub async fn set_random_address(
+        &mut self,
+        random_address: BTAddress,
+    ) -> Result<(), adapter::Error> {
+        self.adapter
+            .hci_send_command(le::commands::SetRandomAddress { random_address })
+            .await?
+            .params
+            .status
+            .error()?;
+        self.local_addresses.random_address = Some(random_address);
+        Ok(())
     }
     /// Read the advertising channel TX power in dBm. See [`le::advertise::TxPowerLevel`] for more.
     pub async fn get_advertising_tx_power(
@@ -62,10 +130,18 @@ impl<A: adapter::Adapter, H: UnrecognizedEventHandler> LEAdapter<A, H> {
         Ok(())
     }
     /// Set advertisement scanning parameters. See [`le::commands::SetScanParameters`] for more.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if the scan window is larger than the scan interval
+    /// or the own address type needs a random address or local IRK missing from
+    /// `local_addresses`.
     pub async fn set_scan_parameters(
         &mut self,
         scan_parameters: ScanParameters,
     ) -> Result<(), adapter::Error> {
+        if !scan_parameters.is_valid() {
+            return Err(adapter::Error::BadParameter);
+        }
+        self.check_own_address_type(scan_parameters.own_address_type)?;
         self.adapter
             .hci_send_command(le::scan::SetScanParameters(scan_parameters))
             .await?
@@ -74,6 +150,80 @@ impl<A: adapter::Adapter, H: UnrecognizedEventHandler> LEAdapter<A, H> {
             .error()?;
         Ok(())
     }
+    /// Set extended scanning parameters (per PHY) without checking controller support. See
+    /// [`LEAdapter::configure_extended_scan`] for the checked version.
+    pub async fn set_extended_scan_parameters(
+        &mut self,
+        scan_parameters: ExtendedScanParameters,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        scan_parameters
+            .validate()
+            .map_err(|_| adapter::Error::BadParameter)?;
+        self.check_own_address_type(scan_parameters.own_address_type)?;
+        self.adapter
+            .hci_send_command(le::commands::SetExtendedScanParameters(scan_parameters))
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Checks `scan_parameters` against the controller's supported features and then sets them.
+    /// # Errors
+    /// Returns `adapter::Error::ErrorCode(ErrorCode::UnsupportedFeatureOrParameterValue)` if the
+    /// controller can't extended scan (or can't scan a requested PHY) and
+    /// `adapter::Error::BadParameter` if the parameters are invalid.
+    pub async fn configure_extended_scan(
+        &mut self,
+        scan_parameters: ExtendedScanParameters,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        let features = self.read_local_supported_features().await?;
+        match scan_parameters.check_supported(features) {
+            Ok(()) => (),
+            Err(
+                ExtendedScanError::ExtendedScanningUnsupported
+                | ExtendedScanError::CodedPHYUnsupported,
+            ) => {
+                return Err(adapter::Error::ErrorCode(
+                    ErrorCode::UnsupportedFeatureOrParameterValue,
+                ))
+            }
+            Err(_) => return Err(adapter::Error::BadParameter),
+        }
+        self.set_extended_scan_parameters(scan_parameters).await
+    }
+    /// Enable or disable extended scanning. `duration` (10 ms units) and `period` (1.28 s units)
+    /// of `0` scan continuously until disabled.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if the controller wouldn't take the duration and
+    /// period (see [`le::commands::SetExtendedScanEnable::is_valid`]).
+    pub async fn set_extended_scan_enable(
+        &mut self,
+        is_enabled: bool,
+        filter_duplicates: FilterDuplicates,
+        duration: u16,
+        period: u16,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        let command = le::commands::SetExtendedScanEnable {
+            is_enabled,
+            filter_duplicates,
+            duration,
+            period,
+        };
+        if !command.is_valid() {
+            return Err(adapter::Error::BadParameter);
+        }
+        self.adapter
+            .hci_send_command(command)
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
     /// Enable or disable advertising. Make sure to set advertising parameters
     /// ([`LEAdapter::set_advertising_parameters`]) and advertising data
     /// ([`LEAdapter::set_advertising_data`]) before calling this function.
@@ -87,10 +237,14 @@ impl<A: adapter::Adapter, H: UnrecognizedEventHandler> LEAdapter<A, H> {
         Ok(())
     }
     /// Set advertising parameters. See [`le::commands::SetAdvertisingParameters`] for more.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if the own address type needs a random address or
+    /// local IRK missing from `local_addresses`.
     pub async fn set_advertising_parameters(
         &mut self,
         parameters: AdvertisingParameters,
     ) -> Result<(), adapter::Error> {
+        self.check_own_address_type(parameters.own_address_type)?;
         self.adapter
             .hci_send_command(le::commands::SetAdvertisingParameters(parameters))
             .await?
@@ -99,6 +253,202 @@ impl<A: adapter::Adapter, H: UnrecognizedEventHandler> LEAdapter<A, H> {
             .error()?;
         Ok(())
     }
+    /// Starts initiating a connection. Completion is reported by an LE Connection Complete event.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if the own address type needs a random address or
+    /// local IRK missing from `local_addresses` or if the scan or connection parameters are
+    /// inconsistent (see [`le::connection::CreateConnection::validate`]).
+    pub async fn create_connection(
+        &mut self,
+        create_connection: le::connection::CreateConnection,
+    ) -> Result<(), adapter::Error> {
+        self.check_own_address_type(create_connection.own_address_type)?;
+        create_connection
+            .validate()
+            .map_err(|_| adapter::Error::BadParameter)?;
+        self.adapter
+            .hci_send_command(create_connection)
+            .await?
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Cancels a pending (extended) create connection. The controller then reports an LE
+    /// Connection Complete event with `UnknownConnectionIdentifier`.
+    pub async fn create_connection_cancel(&mut self) -> Result<(), adapter::Error> {
+        self.adapter
+            .hci_send_command(le::commands::CreateConnectionCancel {})
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Asks for new parameters on the connection. Completion is reported by an LE Connection
+    /// Update Complete event.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if `parameters` are inconsistent.
+    pub async fn connection_update(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        parameters: ConnectionParameters,
+    ) -> Result<(), adapter::Error> {
+        parameters
+            .validate()
+            .map_err(|_| adapter::Error::BadParameter)?;
+        self.adapter
+            .hci_send_command(le::commands::ConnectionUpdate {
+                connection_handle,
+                parameters,
+            })
+            .await?
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Accepts an LE Remote Connection Parameter Request with `parameters`.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if `parameters` are inconsistent.
+    pub async fn remote_connection_parameter_request_reply(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        parameters: ConnectionParameters,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth4v1)?;
+        parameters
+            .validate()
+            .map_err(|_| adapter::Error::BadParameter)?;
+        self.adapter
+            .hci_send_command(le::commands::RemoteConnectionParameterRequestReply {
+                connection_handle,
+                parameters,
+            })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    pub async fn remote_connection_parameter_request_negative_reply(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        reason: ErrorCode,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth4v1)?;
+        self.adapter
+            .hci_send_command(
+                le::commands::RemoteConnectionParameterRequestNegativeReply {
+                    connection_handle,
+                    reason,
+                },
+            )
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Answers `request` with `accept`'s parameters or rejects it with its error code (for
+    /// example [`le::connection::RemoteConnectionParameterRequest::reply`] accepts consistent
+    /// requests as they are).
+    pub async fn answer_connection_parameter_request(
+        &mut self,
+        request: &le::connection::RemoteConnectionParameterRequest,
+        accept: impl FnOnce(&ConnectionParameters) -> Result<ConnectionParameters, ErrorCode>,
+    ) -> Result<(), adapter::Error> {
+        match accept(&request.parameters()) {
+            Ok(parameters) => {
+                self.remote_connection_parameter_request_reply(
+                    request.connection_handle,
+                    parameters,
+                )
+                .await
+            }
+            Err(reason) => {
+                self.remote_connection_parameter_request_negative_reply(
+                    request.connection_handle,
+                    reason,
+                )
+                .await
+            }
+        }
+    }
+    /// Suggests sending up to `tx` on the connection. The negotiated lengths are reported by an
+    /// LE Data Length Change event (if they changed).
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if `tx` is out of range.
+    pub async fn set_data_length(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        tx: DataLength,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth4v2)?;
+        if !tx.is_valid() {
+            return Err(adapter::Error::BadParameter);
+        }
+        self.adapter
+            .hci_send_command(le::commands::SetDataLength {
+                connection_handle,
+                tx,
+            })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    pub async fn read_suggested_default_data_length(
+        &mut self,
+    ) -> Result<DataLength, adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth4v2)?;
+        let r = self
+            .adapter
+            .hci_send_command(le::commands::ReadSuggestedDefaultDataLength {})
+            .await?;
+        r.params.status.error()?;
+        Ok(r.params.suggested_max_tx)
+    }
+    /// Sets the transmit data length new connections start negotiating with (for example
+    /// [`DataLength::MAX_1M`] for 251 byte PDUs).
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if `suggested_max_tx` is out of range.
+    pub async fn write_suggested_default_data_length(
+        &mut self,
+        suggested_max_tx: DataLength,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth4v2)?;
+        if !suggested_max_tx.is_valid() {
+            return Err(adapter::Error::BadParameter);
+        }
+        self.adapter
+            .hci_send_command(le::commands::WriteSuggestedDefaultDataLength { suggested_max_tx })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Starts initiating a connection on each PHY with parameters. Completion is reported by an
+    /// LE Enhanced Connection Complete event.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` for an unusable own address type or inconsistent
+    /// parameters (see [`le::connection::ExtendedCreateConnection::validate`]).
+    pub async fn extended_create_connection(
+        &mut self,
+        create_connection: le::connection::ExtendedCreateConnection,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        self.check_own_address_type(create_connection.own_address_type)?;
+        create_connection
+            .validate()
+            .map_err(|_| adapter::Error::BadParameter)?;
+        self.adapter
+            .hci_send_command(create_connection)
+            .await?
+            .status
+            .error()?;
+        Ok(())
+    }
     /// Get `RAND_LEN` (8) bytes from the HCI Controller.
     pub async fn get_rand(&mut self) -> Result<[u8; RAND_LEN], adapter::Error> {
         let r = self.adapter.hci_send_command(le::commands::Rand {}).await?;
@@ -116,11 +466,29 @@ impl<A: adapter::Adapter, H: UnrecognizedEventHandler> LEAdapter<A, H> {
         r.params.status.error()?;
         Ok(r.params)
     }
+    /// Reads the LE ACL buffers of the controller and from then on only sends ACL packets while
+    /// one is free (see [`Adapter::hci_send_acl`]).
+    ///
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if the controller has no dedicated LE buffers
+    /// (shares them with BR/EDR).
+    pub async fn enable_acl_flow_control(
+        &mut self,
+    ) -> Result<le::connection::BufferSizeV1, adapter::Error> {
+        let size = self.read_buffer_size_v1().await?;
+        if size.total_num_le_acl_data_packets == 0 {
+            return Err(adapter::Error::BadParameter);
+        }
+        let total = u16::from(size.total_num_le_acl_data_packets);
+        self.adapter.set_acl_credits(Some(DataCredits::new(total)));
+        Ok(size)
+    }
     /// Returns max number of ACL packets and packet length PLUS Max number of Isochronous packets
     /// and packet length.
     pub async fn read_buffer_size_v2(
         &mut self,
     ) -> Result<le::connection::BufferSizeV2, adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v2)?;
         let r = self
             .adapter
             .hci_send_command(le::commands::ReadBufferSizeV2())
@@ -164,6 +532,146 @@ impl<A: adapter::Adapter, H: UnrecognizedEventHandler> LEAdapter<A, H> {
             .error()?;
         Ok(())
     }
+    pub async fn read_local_supported_features(
+        &mut self,
+    ) -> Result<le::features::LEFeatures, adapter::Error> {
+        let r = self
+            .adapter
+            .hci_send_command(le::commands::ReadLocalSupportedFeatures {})
+            .await?;
+        r.params.status.error()?;
+        Ok(r.params.features)
+    }
+    /// Returns the number of entries the controller white list can hold.
+    pub async fn read_whitelist_size(&mut self) -> Result<u8, adapter::Error> {
+        let r = self
+            .adapter
+            .hci_send_command(le::commands::ReadWhitelistSize {})
+            .await?;
+        r.params.status.error()?;
+        Ok(r.params.whitelist_size)
+    }
+    pub async fn clear_whitelist(&mut self) -> Result<(), adapter::Error> {
+        self.adapter
+            .hci_send_command(le::commands::ClearWhitelist {})
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    pub async fn add_device_to_whitelist(
+        &mut self,
+        entry: WhitelistEntry,
+    ) -> Result<(), adapter::Error> {
+        self.adapter
+            .hci_send_command(le::commands::AddDeviceToWhitelist { entry })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    pub async fn remove_device_from_whitelist(
+        &mut self,
+        entry: WhitelistEntry,
+    ) -> Result<(), adapter::Error> {
+        self.adapter
+            .hci_send_command(le::commands::RemoveDeviceFromWhitelist { entry })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Routes one direction of an established CIS or BIS over HCI or a controller internal path.
+    pub async fn setup_iso_data_path(
+        &mut self,
+        path: le::iso::SetupISODataPath,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v2)?;
+        self.adapter
+            .hci_send_command(path)
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Reads when the controller sent the last SDU of the CIS or BIS `connection_handle`. See
+    /// [`le::iso::ISOTXSyncReturn`] for time stamping later SDUs.
+    pub async fn read_iso_tx_sync(
+        &mut self,
+        connection_handle: ConnectionHandle,
+    ) -> Result<le::iso::ISOTXSyncReturn, adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v2)?;
+        let r = self
+            .adapter
+            .hci_send_command(le::iso::ReadISOTXSync { connection_handle })
+            .await?;
+        r.params.status.error()?;
+        Ok(r.params)
+    }
+    pub async fn remove_iso_data_path(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        directions: le::iso::DataPathDirections,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v2)?;
+        self.adapter
+            .hci_send_command(le::iso::RemoveISODataPath {
+                connection_handle,
+                directions,
+            })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Loads `whitelist` into the controller if the controller supports a white list big enough to
+    /// hold it. Otherwise `whitelist` is switched to host filtering and the controller white list
+    /// is cleared. Returns where filtering ends up.
+    pub async fn apply_whitelist(
+        &mut self,
+        whitelist: &mut Whitelist,
+    ) -> Result<FilterLocation, adapter::Error> {
+        let capacity = match self.read_whitelist_size().await {
+            Ok(size) => Some(usize::from(size)),
+            Err(adapter::Error::ErrorCode(_)) => None,
+            Err(e) => return Err(e),
+        };
+        whitelist.set_controller_capacity(capacity);
+        let mut location = whitelist.location();
+        if location == FilterLocation::Controller {
+            self.clear_whitelist().await?;
+            for &entry in whitelist.entries() {
+                match self.add_device_to_whitelist(entry).await {
+                    Ok(()) => (),
+                    // Controller said it had room but ran out anyways (or it doesn't take that
+                    // address type). Fall back to filtering everything on the host.
+                    Err(adapter::Error::ErrorCode(_)) => {
+                        location = FilterLocation::Host;
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            if location == FilterLocation::Host {
+                whitelist.set_controller_capacity(None);
+            }
+        }
+        if location == FilterLocation::Host {
+            // Entries left from an earlier list (or a partly loaded one) would still filter
+            // whatever uses the controller white list. A controller without one has nothing to
+            // clear.
+            match self.clear_whitelist().await {
+                Ok(()) | Err(adapter::Error::ErrorCode(_)) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(location)
+    }
     /// Returns a Stream of `RawMetaEvent`s without setting the HCI `EventMask`.
     pub fn meta_event_stream_without_mask<'a, 'b: 'a, Buf: Storage<u8> + 'b>(
         &'a mut self,
@@ -259,6 +767,550 @@ impl<A: adapter::Adapter, H: UnrecognizedEventHandler> LEAdapter<A, H> {
             )
             .flatten())
     }
+    /// Same as [`LEAdapter::advertisement_stream`] but resolvable private addresses are replaced
+    /// by identity addresses using `resolving_list` when it's doing host resolution.
+    pub async fn resolved_advertisement_stream<
+        'a,
+        'b: 'a,
+        Buf: Storage<ReportInfo<StaticAdvBuffer>> + 'b,
+    >(
+        &'a mut self,
+        resolving_list: &'a ResolvingList,
+    ) -> Result<
+        impl Stream<Item = Result<ReportInfo<StaticAdvBuffer>, adapter::Error>> + 'a,
+        adapter::Error,
+    > {
+        Ok(self.advertisement_stream::<Buf>().await?.map(move |r| {
+            r.map(|mut report| {
+                resolving_list.resolve_report(&mut report);
+                report
+            })
+        }))
+    }
+    /// Same as [`LEAdapter::advertisement_stream`] but drops reports `whitelist` doesn't allow
+    /// under `policy`. Reports are only filtered here when `whitelist` is being emulated on the
+    /// host so the results are the same wherever the filtering happens. Scanning should be set up
+    /// with [`Whitelist::scanning_filter_policy`].
+    pub async fn whitelisted_advertisement_stream<
+        'a,
+        'b: 'a,
+        Buf: Storage<ReportInfo<StaticAdvBuffer>> + 'b,
+    >(
+        &'a mut self,
+        whitelist: &'a Whitelist,
+        policy: ScanningFilterPolicy,
+    ) -> Result<
+        impl Stream<Item = Result<ReportInfo<StaticAdvBuffer>, adapter::Error>> + 'a,
+        adapter::Error,
+    > {
+        Ok(self.advertisement_stream::<Buf>().await?.filter(move |r| {
+            futures_util::future::ready(match r {
+                Ok(report) => whitelist.allows(policy, report),
+                Err(_) => true,
+            })
+        }))
+    }
+    /// Enable or disable extended advertising sets. See
+    /// [`le::extended_advertise::SetExtendedAdvertisingEnable`].
+    pub async fn set_extended_advertising_enable(
+        &mut self,
+        is_enabled: bool,
+        sets: Vec<AdvertisingSetEnable>,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        self.adapter
+            .hci_send_command(le::commands::SetExtendedAdvertisingEnable { is_enabled, sets })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Changes the data of advertising set `set.advertising_handle` to `data`, sending nothing
+    /// if the controller already has it. `is_enabled` says if the set is currently advertising.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if `data` is too long (or needs more than one
+    /// fragment while the set is enabled).
+    pub async fn set_extended_advertising_data(
+        &mut self,
+        set: &mut ExtendedAdvertisingData,
+        data: &[u8],
+        is_enabled: bool,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        let commands = set
+            .update(data, is_enabled)
+            .map_err(|_| adapter::Error::BadParameter)?;
+        if commands.is_empty() {
+            return Ok(());
+        }
+        // Fragments after a failed one would be rejected, so the set's data is unknown.
+        set.clear();
+        for command in commands {
+            self.adapter
+                .hci_send_command(command)
+                .await?
+                .params
+                .status
+                .error()?;
+        }
+        set.commit(data);
+        Ok(())
+    }
+    /// Enables advertising set `set` (with its duration and event limit) and waits for the
+    /// controller to report it terminated. Other events go to the `UnrecognizedEventHandler`.
+    /// The LE Advertising Set Terminated event has to be enabled in the meta event mask.
+    pub async fn advertise_limited(
+        &mut self,
+        set: AdvertisingSetEnable,
+    ) -> Result<TerminationReason, adapter::Error> {
+        self.set_extended_advertising_enable(true, alloc::vec![set])
+            .await?;
+        loop {
+            let event: EventPacket<H::Buf> = self.adapter.hci_read_event().await?;
+            match RawMetaEvent::try_from(event.as_ref())
+                .and_then(le::events::AdvertisingSetTerminated::meta_unpack_packet)
+            {
+                Ok(terminated) if terminated.advertising_handle == set.advertising_handle => {
+                    return Ok(terminated.reason())
+                }
+                _ => self.adapter.event_handler.handle(event)?,
+            }
+        }
+    }
+    /// Advertises set `advertising_handle` for `duration` (10 ms resolution). Resolves with
+    /// `TerminationReason::Timeout` or, if a connection was created first,
+    /// `TerminationReason::Connected`.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if `duration` is zero or longer than ~655 s.
+    pub async fn advertise_for(
+        &mut self,
+        advertising_handle: u8,
+        duration: Duration,
+    ) -> Result<TerminationReason, adapter::Error> {
+        let set = AdvertisingSetEnable::new(advertising_handle)
+            .with_duration(duration)
+            .ok_or(adapter::Error::BadParameter)?;
+        self.advertise_limited(set).await
+    }
+    /// Creates (or changes the parameters of) advertising set `advertising_handle`. Returns the
+    /// transmit power the controller picked in dBm. Random own address types use the set's
+    /// address (see [`LEAdapter::set_advertising_set_random_address`]).
+    pub async fn set_extended_advertising_parameters(
+        &mut self,
+        advertising_handle: u8,
+        parameters: ExtendedAdvertisingParameters,
+    ) -> Result<i8, adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        let ret = self
+            .adapter
+            .hci_send_command(le::commands::SetExtendedAdvertisingParameters {
+                advertising_handle,
+                parameters,
+            })
+            .await?
+            .params;
+        ret.status.error()?;
+        Ok(ret.selected_tx_power)
+    }
+    pub async fn set_advertising_set_random_address(
+        &mut self,
+        advertising_handle: u8,
+        random_address: BTAddress,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        self.adapter
+            .hci_send_command(le::commands::SetAdvertisingSetRandomAddress {
+                advertising_handle,
+                random_address,
+            })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Sets the scan response data of a scannable advertising set, fragmented if it doesn't fit
+    /// one command.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if `data` is longer than
+    /// [`MAX_EXTENDED_ADVERTISING_DATA_LEN`].
+    pub async fn set_extended_scan_response_data(
+        &mut self,
+        advertising_handle: u8,
+        data: &[u8],
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        if data.len() > MAX_EXTENDED_ADVERTISING_DATA_LEN {
+            return Err(adapter::Error::BadParameter);
+        }
+        let mut fragments: Vec<&[u8]> = data.chunks(MAX_ADVERTISING_DATA_FRAGMENT_LEN).collect();
+        if fragments.is_empty() {
+            // Clearing the response is one empty `Complete` fragment.
+            fragments.push(data);
+        }
+        let last = fragments.len() - 1;
+        for (i, fragment) in fragments.into_iter().enumerate() {
+            self.adapter
+                .hci_send_command(le::commands::SetExtendedScanResponseData {
+                    advertising_handle,
+                    operation: DataOperation::of_fragment(i, last),
+                    fragment_preference: FragmentPreference::default(),
+                    data: fragment.to_vec(),
+                })
+                .await?
+                .params
+                .status
+                .error()?;
+        }
+        Ok(())
+    }
+    /// Removes advertising set `advertising_handle`. The set has to be disabled.
+    pub async fn remove_advertising_set(
+        &mut self,
+        advertising_handle: u8,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        self.adapter
+            .hci_send_command(le::commands::RemoveAdvertisingSet { advertising_handle })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Removes every advertising set. Every set has to be disabled.
+    pub async fn clear_advertising_sets(&mut self) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        self.adapter
+            .hci_send_command(le::commands::ClearAdvertisingSets {})
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// [`AdvertisingHandles`] for the number of sets the controller supports.
+    pub async fn advertising_handles(&mut self) -> Result<AdvertisingHandles, adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        let ret = self
+            .adapter
+            .hci_send_command(le::commands::ReadNumberOfSupportedAdvertisingSets {})
+            .await?
+            .params;
+        ret.status.error()?;
+        Ok(AdvertisingHandles::new(ret.num_supported_advertising_sets))
+    }
+    /// Sets the periodic advertising parameters of a (non connectable, non scannable)
+    /// advertising set.
+    pub async fn set_periodic_advertising_parameters(
+        &mut self,
+        parameters: le::periodic::SetPeriodicAdvertisingParameters,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        self.adapter
+            .hci_send_command(parameters)
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Sets the periodic advertising data of an advertising set, fragmented if it doesn't fit
+    /// one command. Fragmented data can only be set while periodic advertising is disabled.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if `data` is longer than
+    /// [`MAX_EXTENDED_ADVERTISING_DATA_LEN`].
+    pub async fn set_periodic_advertising_data(
+        &mut self,
+        advertising_handle: u8,
+        data: &[u8],
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        if data.len() > MAX_EXTENDED_ADVERTISING_DATA_LEN {
+            return Err(adapter::Error::BadParameter);
+        }
+        let mut fragments: Vec<&[u8]> = data
+            .chunks(le::periodic::MAX_PERIODIC_ADVERTISING_DATA_FRAGMENT_LEN)
+            .collect();
+        if fragments.is_empty() {
+            fragments.push(data);
+        }
+        let last = fragments.len() - 1;
+        for (i, fragment) in fragments.into_iter().enumerate() {
+            self.adapter
+                .hci_send_command(le::commands::SetPeriodicAdvertisingData {
+                    advertising_handle,
+                    operation: DataOperation::of_fragment(i, last),
+                    data: fragment.to_vec(),
+                })
+                .await?
+                .params
+                .status
+                .error()?;
+        }
+        Ok(())
+    }
+    /// Starts or stops periodic advertising of an advertising set. The set's extended
+    /// advertising has to be enabled too for scanners to find the train.
+    pub async fn set_periodic_advertising_enable(
+        &mut self,
+        advertising_handle: u8,
+        enable: le::periodic::PeriodicAdvertisingEnable,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        self.adapter
+            .hci_send_command(le::commands::SetPeriodicAdvertisingEnable {
+                enable,
+                advertising_handle,
+            })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Starts synchronizing to a periodic advertising train. The result comes as a
+    /// [`le::periodic::PeriodicAdvertisingSyncEstablished`] event.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if a field of `sync` is out of range.
+    pub async fn periodic_advertising_create_sync(
+        &mut self,
+        sync: le::periodic::PeriodicAdvertisingCreateSync,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        if !sync.is_valid() {
+            return Err(adapter::Error::BadParameter);
+        }
+        self.adapter.hci_send_command(sync).await?.status.error()?;
+        Ok(())
+    }
+    pub async fn periodic_advertising_create_sync_cancel(&mut self) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        self.adapter
+            .hci_send_command(le::commands::PeriodicAdvertisingCreateSyncCancel {})
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    pub async fn periodic_advertising_terminate_sync(
+        &mut self,
+        sync_handle: u16,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        self.adapter
+            .hci_send_command(le::commands::PeriodicAdvertisingTerminateSync { sync_handle })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    pub async fn add_device_to_periodic_advertiser_list(
+        &mut self,
+        advertiser_address_type: PeerAddressType,
+        advertiser_address: BTAddress,
+        advertising_sid: u8,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        self.adapter
+            .hci_send_command(le::commands::AddDeviceToPeriodicAdvertiserList {
+                advertiser_address_type,
+                advertiser_address,
+                advertising_sid,
+            })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    pub async fn remove_device_from_periodic_advertiser_list(
+        &mut self,
+        advertiser_address_type: PeerAddressType,
+        advertiser_address: BTAddress,
+        advertising_sid: u8,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        self.adapter
+            .hci_send_command(le::commands::RemoveDeviceFromPeriodicAdvertiserList {
+                advertiser_address_type,
+                advertiser_address,
+                advertising_sid,
+            })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    pub async fn clear_periodic_advertiser_list(&mut self) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        self.adapter
+            .hci_send_command(le::commands::ClearPeriodicAdvertiserList {})
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    pub async fn read_periodic_advertiser_list_size(&mut self) -> Result<u8, adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        let ret = self
+            .adapter
+            .hci_send_command(le::commands::ReadPeriodicAdvertiserListSize {})
+            .await?
+            .params;
+        ret.status.error()?;
+        Ok(ret.periodic_advertiser_list_size)
+    }
+    /// Lowest and highest transmit power the controller supports.
+    pub async fn read_transmit_power(
+        &mut self,
+    ) -> Result<le::power::ReadTransmitPowerReturn, adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v0)?;
+        let ret = self
+            .adapter
+            .hci_send_command(le::power::ReadTransmitPower {})
+            .await?
+            .params;
+        ret.status.error()?;
+        Ok(ret)
+    }
+    /// Antennas and switching capabilities of the controller (for direction finding).
+    pub async fn read_antenna_information(
+        &mut self,
+    ) -> Result<le::cte::ReadAntennaInformationReturn, adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v1)?;
+        let ret = self
+            .adapter
+            .hci_send_command(le::cte::ReadAntennaInformation {})
+            .await?
+            .params;
+        ret.status.error()?;
+        Ok(ret)
+    }
+    /// Power control of the connection `connection_handle`.
+    pub fn power_control(&mut self, connection_handle: ConnectionHandle) -> PowerControl<'_, A, H> {
+        PowerControl {
+            adapter: self,
+            connection_handle,
+        }
+    }
+    /// Sets the periodic advertising (with responses) parameters of an advertising set.
+    pub async fn set_periodic_advertising_parameters_v2(
+        &mut self,
+        parameters: le::pawr::SetPeriodicAdvertisingParametersV2,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v4)?;
+        self.adapter
+            .hci_send_command(parameters)
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    pub async fn set_periodic_advertising_subevent_data(
+        &mut self,
+        advertising_handle: u8,
+        subevents: Vec<le::pawr::SubeventData>,
+    ) -> Result<(), adapter::Error> {
+        self.adapter.require_version(Version::Bluetooth5v4)?;
+        for command in le::pawr::subevent_data_commands(advertising_handle, subevents) {
+            self.adapter
+                .hci_send_command(command)
+                .await?
+                .params
+                .status
+                .error()?;
+        }
+        Ok(())
+    }
+    /// Serves a PAwR advertising set until `scheduler` is done. Subevent data requests for
+    /// `advertising_handle` are answered with data from `scheduler` and response reports are
+    /// passed to it. Other events go to the `UnrecognizedEventHandler`. The LE Meta events have
+    /// to be enabled in the event masks and periodic advertising started first.
+    pub async fn run_pawr<S: le::pawr::SubeventScheduler>(
+        &mut self,
+        advertising_handle: u8,
+        num_subevents: u8,
+        scheduler: &mut S,
+    ) -> Result<(), adapter::Error> {
+        while !scheduler.is_done() {
+            let event: EventPacket<H::Buf> = self.adapter.hci_read_event().await?;
+            match RawMetaEvent::try_from(event.as_ref()).map(|meta| meta.code) {
+                Ok(MetaEventCode::PeriodicAdvertisingSubeventDataRequest) => {
+                    let request = RawMetaEvent::try_from(event.as_ref())
+                        .and_then(
+                            le::pawr::PeriodicAdvertisingSubeventDataRequest::meta_unpack_packet,
+                        )
+                        .map_err(StreamError::EventError)?;
+                    if request.advertising_handle != advertising_handle {
+                        continue;
+                    }
+                    let subevents = request
+                        .subevents(num_subevents)
+                        .filter_map(|subevent| scheduler.subevent_data(subevent))
+                        .collect::<Vec<_>>();
+                    self.set_periodic_advertising_subevent_data(advertising_handle, subevents)
+                        .await?;
+                }
+                Ok(MetaEventCode::PeriodicAdvertisingResponseReport) => {
+                    let report = RawMetaEvent::try_from(event.as_ref())
+                        .and_then(le::pawr::PeriodicAdvertisingResponseReport::meta_unpack_packet)
+                        .map_err(StreamError::EventError)?;
+                    if report.advertising_handle == advertising_handle {
+                        scheduler.response_report(report);
+                    }
+                }
+                _ => self.adapter.event_handler.handle(event)?,
+            }
+        }
+        Ok(())
+    }
+    /// Leaves the controller idle: disables advertising (every extended advertising set with
+    /// `options.extended`) and scanning, disconnects `options.connections` and closes the event
+    /// handler, which ends the loops reading from it (see
+    /// [`UnrecognizedEventHandler::close`]). Every step is tried even if an earlier one fails and
+    /// the first error is returned. Call it before dropping the adapter so the controller isn't
+    /// left advertising or scanning after the process exits.
+    pub async fn shutdown(&mut self, options: ShutdownOptions<'_>) -> Result<(), adapter::Error> {
+        let mut first_error = None;
+        let mut keep_first = |r: Result<(), adapter::Error>| {
+            if let Err(e) = r {
+                first_error.get_or_insert(e);
+            }
+        };
+        if options.extended {
+            // No sets means all sets.
+            keep_first(
+                self.set_extended_advertising_enable(false, Vec::new())
+                    .await,
+            );
+            keep_first(
+                self.set_extended_scan_enable(false, FilterDuplicates::Disabled, 0, 0)
+                    .await,
+            );
+        } else {
+            keep_first(self.set_advertising_enable(false).await);
+            keep_first(self.set_scan_enable(false, false).await);
+        }
+        for &handle in options.connections {
+            keep_first(
+                self.adapter
+                    .disconnect(handle, options.disconnect_reason)
+                    .await,
+            );
+        }
+        self.adapter.event_handler.close();
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
 }
 
 impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Advertiser for LEAdapter<A, H> {
@@ -329,3 +1381,270 @@ impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Observer for LEAdapter<A,
         )
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::adapters::dispatcher::{Dispatcher, EventFilter};
+    use crate::hci::command::Command;
+    use crate::hci::io::HCIPort;
+    use crate::hci::le::commands::{
+        AddDeviceToWhitelist, ClearWhitelist, ReadWhitelistSize, SetAdvertisingEnable,
+        SetExtendedAdvertisingEnable, SetExtendedScanEnable, SetScanEnable,
+    };
+    use crate::hci::link_control::Disconnect;
+    use crate::le::whitelist::WhitelistAddressType;
+    use crate::testing::mock::{command_complete, event_packet, MockTransport};
+
+    #[test]
+    fn test_shutdown() {
+        let (transport, handle) = MockTransport::new();
+        let dispatcher = Dispatcher::new();
+        let mut subscription = dispatcher.subscribe(EventFilter::all());
+        let mut le = LEAdapter::new(Adapter::new_with_handler(
+            HCIPort::new(transport),
+            dispatcher,
+        ));
+        handle.expect_command(
+            &SetAdvertisingEnable { is_enabled: false },
+            &[&command_complete(SetAdvertisingEnable::opcode(), &[0x00])],
+        );
+        handle.expect_command(
+            &SetScanEnable {
+                is_enabled: false,
+                filter_duplicates: false,
+            },
+            &[&command_complete(SetScanEnable::opcode(), &[0x00])],
+        );
+        assert_eq!(
+            le.shutdown(ShutdownOptions::default())
+                .now_or_never()
+                .unwrap(),
+            Ok(())
+        );
+        handle.assert_done();
+        // The loop reading the dispatcher ends.
+        assert!(matches!(subscription.next().now_or_never(), Some(None)));
+    }
+    #[test]
+    fn test_shutdown_extended() {
+        let (transport, handle) = MockTransport::new();
+        let mut le = LEAdapter::new(Adapter::new(HCIPort::new(transport)));
+        let connection = ConnectionHandle::new(0x0040);
+        handle.expect_command(
+            &SetExtendedAdvertisingEnable {
+                is_enabled: false,
+                sets: Vec::new(),
+            },
+            // Errors don't stop the other steps.
+            &[&command_complete(
+                SetExtendedAdvertisingEnable::opcode(),
+                &[ErrorCode::CommandDisallowed.into()],
+            )],
+        );
+        handle.expect_command(
+            &SetExtendedScanEnable {
+                is_enabled: false,
+                filter_duplicates: FilterDuplicates::Disabled,
+                duration: 0,
+                period: 0,
+            },
+            &[&command_complete(SetExtendedScanEnable::opcode(), &[0x00])],
+        );
+        handle.expect_command(
+            &Disconnect {
+                connection_handle: connection,
+                reason: ErrorCode::OtherEndTerminatedConnectionAboutToPowerOff,
+            },
+            &[&event_packet(
+                EventCode::CommandStatus,
+                &[0x00, 0x01, 0x06, 0x04],
+            )],
+        );
+        let options = ShutdownOptions {
+            extended: true,
+            connections: &[connection],
+            ..ShutdownOptions::default()
+        };
+        assert_eq!(
+            le.shutdown(options).now_or_never().unwrap(),
+            Err(adapter::Error::ErrorCode(ErrorCode::CommandDisallowed))
+        );
+        handle.assert_done();
+    }
+    fn whitelist() -> Whitelist {
+        let mut whitelist = Whitelist::new(None);
+        for address in 1..=2 {
+            whitelist.add(WhitelistEntry::new(
+                WhitelistAddressType::Public,
+                BTAddress::from_u64(address),
+            ));
+        }
+        whitelist
+    }
+    #[test]
+    fn test_apply_whitelist() {
+        let (transport, handle) = MockTransport::new();
+        let mut le = LEAdapter::new(Adapter::new(HCIPort::new(transport)));
+        let mut whitelist = whitelist();
+        handle.expect_command(
+            &ReadWhitelistSize {},
+            &[&command_complete(
+                ReadWhitelistSize::opcode(),
+                &[0x00, 0x08],
+            )],
+        );
+        handle.expect_command(
+            &ClearWhitelist {},
+            &[&command_complete(ClearWhitelist::opcode(), &[0x00])],
+        );
+        for &entry in whitelist.entries() {
+            handle.expect_command(
+                &AddDeviceToWhitelist { entry },
+                &[&command_complete(AddDeviceToWhitelist::opcode(), &[0x00])],
+            );
+        }
+        assert_eq!(
+            le.apply_whitelist(&mut whitelist).now_or_never().unwrap(),
+            Ok(FilterLocation::Controller)
+        );
+        handle.assert_done();
+    }
+    #[test]
+    fn test_apply_whitelist_too_small() {
+        let (transport, handle) = MockTransport::new();
+        let mut le = LEAdapter::new(Adapter::new(HCIPort::new(transport)));
+        let mut whitelist = whitelist();
+        handle.expect_command(
+            &ReadWhitelistSize {},
+            &[&command_complete(
+                ReadWhitelistSize::opcode(),
+                &[0x00, 0x01],
+            )],
+        );
+        // An earlier list mustn't stay in the controller.
+        handle.expect_command(
+            &ClearWhitelist {},
+            &[&command_complete(ClearWhitelist::opcode(), &[0x00])],
+        );
+        assert_eq!(
+            le.apply_whitelist(&mut whitelist).now_or_never().unwrap(),
+            Ok(FilterLocation::Host)
+        );
+        handle.assert_done();
+        assert_eq!(
+            whitelist.scanning_filter_policy(ScanningFilterPolicy::Whitelisted),
+            ScanningFilterPolicy::All
+        );
+    }
+    #[test]
+    fn test_apply_whitelist_partly_loaded() {
+        let (transport, handle) = MockTransport::new();
+        let mut le = LEAdapter::new(Adapter::new(HCIPort::new(transport)));
+        let mut whitelist = whitelist();
+        let entries: Vec<_> = whitelist.entries().copied().collect();
+        handle.expect_command(
+            &ReadWhitelistSize {},
+            &[&command_complete(
+                ReadWhitelistSize::opcode(),
+                &[0x00, 0x02],
+            )],
+        );
+        let cleared = command_complete(ClearWhitelist::opcode(), &[0x00]);
+        handle.expect_command(&ClearWhitelist {}, &[&cleared]);
+        handle.expect_command(
+            &AddDeviceToWhitelist { entry: entries[0] },
+            &[&command_complete(AddDeviceToWhitelist::opcode(), &[0x00])],
+        );
+        handle.expect_command(
+            &AddDeviceToWhitelist { entry: entries[1] },
+            &[&command_complete(
+                AddDeviceToWhitelist::opcode(),
+                &[ErrorCode::MemoryFull.into()],
+            )],
+        );
+        // The first entry is taken out again.
+        handle.expect_command(&ClearWhitelist {}, &[&cleared]);
+        assert_eq!(
+            le.apply_whitelist(&mut whitelist).now_or_never().unwrap(),
+            Ok(FilterLocation::Host)
+        );
+        handle.assert_done();
+        assert_eq!(whitelist.location(), FilterLocation::Host);
+    }
+    #[test]
+    fn test_apply_whitelist_unsupported() {
+        let (transport, handle) = MockTransport::new();
+        let mut le = LEAdapter::new(Adapter::new(HCIPort::new(transport)));
+        let mut whitelist = whitelist();
+        let unknown = [ErrorCode::UnknownHCICommand.into()];
+        handle.expect_command(
+            &ReadWhitelistSize {},
+            &[&command_complete(
+                ReadWhitelistSize::opcode(),
+                &[unknown[0], 0x00],
+            )],
+        );
+        handle.expect_command(
+            &ClearWhitelist {},
+            &[&command_complete(ClearWhitelist::opcode(), &unknown)],
+        );
+        assert_eq!(
+            le.apply_whitelist(&mut whitelist).now_or_never().unwrap(),
+            Ok(FilterLocation::Host)
+        );
+        handle.assert_done();
+    }
+    #[test]
+    fn test_advertise_for() {
+        let (transport, handle) = MockTransport::new();
+        let dispatcher = Dispatcher::new();
+        let mut others = dispatcher.subscribe(EventFilter::all());
+        let mut le = LEAdapter::new(Adapter::new_with_handler(
+            HCIPort::new(transport),
+            dispatcher,
+        ));
+        let set = AdvertisingSetEnable::new(1)
+            .with_duration(Duration::from_secs(1))
+            .unwrap();
+        let terminated = |handle| {
+            event_packet(
+                EventCode::LEMeta,
+                &[
+                    0x12,
+                    ErrorCode::AdvertisingTimeout.into(),
+                    handle,
+                    0x00,
+                    0x00,
+                    0x00,
+                ],
+            )
+        };
+        handle.expect_command(
+            &SetExtendedAdvertisingEnable {
+                is_enabled: true,
+                sets: vec![set],
+            },
+            &[
+                &command_complete(SetExtendedAdvertisingEnable::opcode(), &[0x00]),
+                // Another set ending goes to the event handler.
+                &terminated(0x02),
+                &terminated(0x01),
+            ],
+        );
+        assert_eq!(
+            le.advertise_for(1, Duration::from_secs(1))
+                .now_or_never()
+                .unwrap(),
+            Ok(TerminationReason::Timeout)
+        );
+        handle.assert_done();
+        let other = others.next().now_or_never().unwrap().unwrap();
+        assert_eq!(other.parameters[2], 0x02);
+        assert_eq!(
+            le.advertise_for(1, Duration::from_secs(0))
+                .now_or_never()
+                .unwrap(),
+            Err(adapter::Error::BadParameter)
+        );
+    }
+}
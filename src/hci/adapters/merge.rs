@@ -0,0 +1,146 @@
+//! Single task event loops. [`Merge`] combines an HCI event stream (usually a dispatcher
+//! [`Subscription`](crate::hci::adapters::dispatcher::Subscription)) with the user's own event
+//! sources (shutdown signals, periodic ticks, ..) into one stream of [`LoopEvent`]s, so a loop
+//! only has to poll one thing and no executor `select` machinery is needed.
+use crate::Stream;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_util::stream::FusedStream;
+
+/// Item of a [`Merge`] stream.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum LoopEvent<E, U> {
+    HCI(E),
+    User(U),
+}
+/// Merges `events` and `user`. Both sources take turns going first so a busy one can't starve
+/// the other. Ends once both ended.
+#[derive(Debug)]
+pub struct Merge<E, U> {
+    events: Option<E>,
+    user: Option<U>,
+    user_first: bool,
+}
+impl<E: Stream + Unpin, U: Stream + Unpin> Merge<E, U> {
+    pub fn new(events: E, user: U) -> Merge<E, U> {
+        Merge {
+            events: Some(events),
+            user: Some(user),
+            user_first: false,
+        }
+    }
+    /// The HCI event stream (`None` once it ended).
+    pub fn events(&mut self) -> Option<&mut E> {
+        self.events.as_mut()
+    }
+    /// The user stream (`None` once it ended).
+    pub fn user(&mut self) -> Option<&mut U> {
+        self.user.as_mut()
+    }
+    fn poll_events(&mut self, cx: &mut Context<'_>) -> Poll<Option<LoopEvent<E::Item, U::Item>>> {
+        poll_source(&mut self.events, cx).map(|e| e.map(LoopEvent::HCI))
+    }
+    fn poll_user(&mut self, cx: &mut Context<'_>) -> Poll<Option<LoopEvent<E::Item, U::Item>>> {
+        poll_source(&mut self.user, cx).map(|e| e.map(LoopEvent::User))
+    }
+}
+/// Polls `source` and drops it once it ended. `Ready(None)` means it's gone.
+fn poll_source<S: Stream + Unpin>(
+    source: &mut Option<S>,
+    cx: &mut Context<'_>,
+) -> Poll<Option<S::Item>> {
+    let stream = match source.as_mut() {
+        Some(stream) => stream,
+        None => return Poll::Ready(None),
+    };
+    match Pin::new(stream).poll_next(cx) {
+        Poll::Ready(Some(item)) => Poll::Ready(Some(item)),
+        Poll::Ready(None) => {
+            *source = None;
+            Poll::Ready(None)
+        }
+        Poll::Pending => Poll::Pending,
+    }
+}
+impl<E: Stream + Unpin, U: Stream + Unpin> Stream for Merge<E, U> {
+    type Item = LoopEvent<E::Item, U::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let user_first = this.user_first;
+        this.user_first = !user_first;
+        let first = if user_first {
+            this.poll_user(cx)
+        } else {
+            this.poll_events(cx)
+        };
+        if let Poll::Ready(Some(item)) = first {
+            return Poll::Ready(Some(item));
+        }
+        let second = if user_first {
+            this.poll_events(cx)
+        } else {
+            this.poll_user(cx)
+        };
+        match (first, second) {
+            (_, Poll::Ready(Some(item))) => Poll::Ready(Some(item)),
+            (Poll::Ready(None), Poll::Ready(None)) => Poll::Ready(None),
+            _ => Poll::Pending,
+        }
+    }
+}
+impl<E: Stream + Unpin, U: Stream + Unpin> FusedStream for Merge<E, U> {
+    fn is_terminated(&self) -> bool {
+        self.events.is_none() && self.user.is_none()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::adapters::dispatcher::{Dispatcher, EventFilter};
+    use crate::hci::event::{EventCode, EventPacket};
+    use futures_util::task::noop_waker_ref;
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    enum User {
+        Tick,
+        Shutdown,
+    }
+
+    #[test]
+    fn test_merged_loop() {
+        let dispatcher = Dispatcher::new();
+        let events = dispatcher.subscribe(EventFilter::all());
+        let user = futures_util::stream::iter(vec![User::Tick, User::Tick, User::Shutdown]);
+        let mut merged = Merge::new(events, user);
+        for _ in 0..3 {
+            dispatcher.dispatch(&EventPacket::new(EventCode::HardwareError, [0x01]));
+        }
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut seen = Vec::new();
+        while let Poll::Ready(Some(event)) = Pin::new(&mut merged).poll_next(&mut cx) {
+            match event {
+                LoopEvent::HCI(event) => seen.push(Err(event.event_code)),
+                LoopEvent::User(User::Shutdown) => break,
+                LoopEvent::User(user) => seen.push(Ok(user)),
+            }
+        }
+        // Both sources take turns.
+        assert_eq!(
+            seen,
+            [
+                Err(EventCode::HardwareError),
+                Ok(User::Tick),
+                Err(EventCode::HardwareError),
+                Ok(User::Tick),
+                Err(EventCode::HardwareError),
+            ]
+        );
+        dispatcher.close();
+        assert!(matches!(
+            Pin::new(&mut merged).poll_next(&mut cx),
+            Poll::Ready(None)
+        ));
+        assert!(merged.is_terminated());
+    }
+}
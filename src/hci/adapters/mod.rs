@@ -1,19 +1,49 @@
 //! Contains logic for HCI Adapters (usually byte streams).
 pub mod buffer;
+pub mod dispatcher;
+pub mod error_stats;
+pub mod guard;
+pub mod journal;
 pub mod le;
+pub mod merge;
+pub mod power;
+pub mod push;
+pub mod recovery;
+#[cfg(feature = "std")]
+pub mod router;
+#[cfg(feature = "std")]
+pub mod shared;
 
 use crate::bytes::Storage;
+use crate::hci::acl::ACLPacket;
 use crate::hci::adapter;
 use crate::hci::adapters::le::LEAdapter;
 use crate::hci::baseband::{EventMask, Reset, SetEventMask};
 use crate::hci::command::Command;
 use crate::hci::event::EventPacket;
+use crate::hci::flow::{CommandCredits, DataCredits};
+use crate::hci::informational::{
+    BufferSizeReturn, ControllerDelayReturn, ControllerInfo, ReadBufferSize,
+    ReadLocalSupportedControllerDelay, ReadLocalVersionInformation,
+};
+use crate::hci::link_control::Disconnect;
+use crate::hci::retry::{self, Retried, RetryHooks, RetryPolicy};
+use crate::hci::status::{ReadClock, ReadClockReturn, WhichClock};
+use crate::hci::stream::HCI_EVENT_READ_TRIES;
+use crate::hci::timer::{timeout_after, Timer};
+use crate::hci::{ErrorCode, StreamError, Version};
+use crate::le::connection::ConnectionHandle;
 use crate::Stream;
+use core::time::Duration;
 
 // TODO: Make this more generic
 pub trait UnrecognizedEventHandler {
     type Buf: Storage<u8>;
     fn handle(&mut self, event: EventPacket<Self::Buf>) -> Result<(), adapter::Error>;
+    /// No more events will be handled (called by
+    /// [`LEAdapter::shutdown`](le::LEAdapter::shutdown)). Handlers feeding other tasks end them
+    /// here.
+    fn close(&mut self) {}
 }
 pub struct DummyUnrecognizedEventHandler<Buf = Box<[u8]>>(core::marker::PhantomData<Buf>);
 impl<Buf> DummyUnrecognizedEventHandler<Buf> {
@@ -35,6 +65,12 @@ impl<Buf: Storage<u8>> UnrecognizedEventHandler for DummyUnrecognizedEventHandle
 pub struct Adapter<A: adapter::Adapter, H: UnrecognizedEventHandler> {
     pub adapter: A,
     pub event_handler: H,
+    pending_disables: guard::PendingDisables,
+    command_credits: CommandCredits,
+    acl_credits: Option<DataCredits>,
+    command_timeout: Option<(Box<dyn Timer + Send>, Duration)>,
+    retry: Option<(RetryPolicy, Box<dyn RetryHooks<A> + Send>)>,
+    controller_info: Option<ControllerInfo>,
 }
 impl<A: adapter::Adapter> Adapter<A, DummyUnrecognizedEventHandler<Box<[u8]>>> {
     pub fn new(adapter: A) -> Self {
@@ -49,33 +85,238 @@ impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Adapter<A, H> {
         Self {
             adapter,
             event_handler,
+            pending_disables: guard::PendingDisables::default(),
+            command_credits: CommandCredits::default(),
+            acl_credits: None,
+            command_timeout: None,
+            retry: None,
+            controller_info: None,
         }
     }
     pub fn le(self) -> le::LEAdapter<A, H> {
         LEAdapter::new(self)
     }
+    /// Sends `cmd` and waits for its return. Disables of dropped [`guard`]s are sent first. Waits
+    /// for the controller to accept another command when it's out of command credits.
+    ///
+    /// # Errors
+    /// With a [`Adapter::set_command_timeout`], returns `StreamError::Timeout` if the return
+    /// doesn't arrive in time.
     pub async fn hci_send_command<'a, 'c: 'a, Cmd: Command + 'c>(
         &mut self,
         cmd: Cmd,
     ) -> Result<Cmd::Return, adapter::Error> {
-        let event_handler = &mut self.event_handler;
-        adapter::send_command::<_, _, H::Buf, _>(
-            &mut self.adapter,
-            cmd,
-            Some(|e| event_handler.handle(e)),
-        )
-        .await
+        if !self.pending_disables.is_empty() {
+            self.flush_pending_disables().await?;
+        }
+        self.send_command_now(cmd).await
+    }
+    /// Gives up on commands (with `StreamError::Timeout`) whose return doesn't arrive within
+    /// `duration`, instead of waiting forever on a wedged controller.
+    pub fn set_command_timeout<T: Timer + Send + 'static>(&mut self, timer: T, duration: Duration) {
+        self.command_timeout = Some((Box::new(timer), duration));
+    }
+    pub fn clear_command_timeout(&mut self) {
+        self.command_timeout = None;
+    }
+    /// Retries command writes and event reads that fail with transient transport errors and
+    /// reconnects the transport (sending the command again) when it's lost. See [`retry`].
+    pub fn set_retry<R: RetryHooks<A> + Send + 'static>(&mut self, policy: RetryPolicy, hooks: R) {
+        self.set_retry_boxed(policy, Box::new(hooks));
+    }
+    pub(crate) fn set_retry_boxed(
+        &mut self,
+        policy: RetryPolicy,
+        hooks: Box<dyn RetryHooks<A> + Send>,
+    ) {
+        self.retry = Some((policy, hooks));
+    }
+    pub fn clear_retry(&mut self) {
+        self.retry = None;
+    }
+    /// Handles the `failures`th transport error in a row with the retry policy, if there is one.
+    async fn retry_after(
+        &mut self,
+        error: adapter::Error,
+        failures: u8,
+    ) -> Result<Retried, adapter::Error> {
+        let (policy, hooks) = match self.retry.as_mut() {
+            Some((policy, hooks)) => (*policy, hooks),
+            None => return Err(error),
+        };
+        let retried =
+            retry::retry_after(&policy, &mut **hooks, &mut self.adapter, error, failures).await?;
+        if retried == Retried::Reconnected {
+            // A new transport (or a controller that was power cycled) starts from scratch.
+            self.command_credits = CommandCredits::default();
+            self.controller_info = None;
+        }
+        Ok(retried)
+    }
+    /// Reads the controller versions or returns the cached ones. Once they are read, methods
+    /// needing a newer spec version than the controller implements fail with
+    /// `adapter::Error::UnsupportedByController` instead of sending a command the controller
+    /// doesn't know. The cache is dropped on reset (a patch load changes the subversions) and
+    /// reconnect.
+    pub async fn read_local_version(&mut self) -> Result<ControllerInfo, adapter::Error> {
+        if let Some(info) = self.controller_info {
+            return Ok(info);
+        }
+        let ret = self
+            .hci_send_command(ReadLocalVersionInformation {})
+            .await?
+            .params;
+        ret.status.error()?;
+        let info = ControllerInfo::from(ret);
+        self.controller_info = Some(info);
+        Ok(info)
+    }
+    /// ACL buffers shared by BR/EDR and LE (LE only has its own if LE Read Buffer Size says so).
+    pub async fn read_buffer_size(&mut self) -> Result<BufferSizeReturn, adapter::Error> {
+        let ret = self.hci_send_command(ReadBufferSize {}).await?.params;
+        ret.status.error()?;
+        Ok(ret)
+    }
+    /// Cached controller versions (see [`Adapter::read_local_version`]).
+    pub fn controller_info(&self) -> Option<ControllerInfo> {
+        self.controller_info
+    }
+    /// Fails with `adapter::Error::UnsupportedByController` if the controller is known to
+    /// implement an older spec version than `required`. Passes if the version wasn't read.
+    pub fn require_version(&self, required: Version) -> Result<(), adapter::Error> {
+        let older = self
+            .controller_info
+            .filter(|info| !info.supports(required))
+            .and_then(|info| info.version());
+        match older {
+            Some(actual) => {
+                hci_debug!("{:?} needed, controller is {:?}", required, actual);
+                Err(adapter::Error::UnsupportedByController { required, actual })
+            }
+            None => Ok(()),
+        }
+    }
+    /// Number of commands the controller accepts right now (see [`CommandCredits`]).
+    pub fn command_credits(&self) -> u8 {
+        self.command_credits.available()
+    }
+    /// ACL data flow control. `None` (the default) sends ACL packets without waiting for the
+    /// controller to have buffers for them. See
+    /// [`LEAdapter::enable_acl_flow_control`](le::LEAdapter::enable_acl_flow_control).
+    pub fn acl_credits(&self) -> Option<&DataCredits> {
+        self.acl_credits.as_ref()
+    }
+    pub fn set_acl_credits(&mut self, credits: Option<DataCredits>) {
+        self.acl_credits = credits;
+    }
+    /// Updates the command and data credits from `event`.
+    fn track_credits<Buf: AsRef<[u8]>>(&mut self, event: &EventPacket<Buf>) {
+        if !self.command_credits.update(event.as_ref()) {
+            if let Some(credits) = self.acl_credits.as_mut() {
+                credits.update(event.as_ref());
+            }
+        }
+    }
+    /// Reads, tracks and handles one event.
+    async fn handle_next_event(&mut self) -> Result<(), adapter::Error> {
+        let event = self.adapter.read_event::<H::Buf>().await?;
+        self.track_credits(&event);
+        self.event_handler.handle(event)
+    }
+    /// Reads (and handles) events until the controller accepts another command.
+    async fn acquire_command_credit(&mut self) -> Result<(), adapter::Error> {
+        while !self.command_credits.try_acquire() {
+            hci_trace!("waiting for a command credit");
+            self.handle_next_event().await?;
+        }
+        Ok(())
+    }
+    async fn send_command_now<Cmd: Command>(
+        &mut self,
+        cmd: Cmd,
+    ) -> Result<Cmd::Return, adapter::Error> {
+        let delay = match self.command_timeout.as_mut() {
+            Some((timer, duration)) => timer.delay(*duration),
+            None => return self.send_command_untimed(cmd).await,
+        };
+        match timeout_after(delay, self.send_command_untimed(cmd)).await {
+            Ok(result) => result,
+            Err(e) => {
+                hci_warn!("command {} timed out", Cmd::opcode());
+                // Don't wait on credits of a controller that stopped answering.
+                self.command_credits = CommandCredits::default();
+                Err(e.into())
+            }
+        }
+    }
+    async fn send_command_untimed<Cmd: Command>(
+        &mut self,
+        cmd: Cmd,
+    ) -> Result<Cmd::Return, adapter::Error> {
+        let packet = cmd
+            .pack_command_packet::<H::Buf>()
+            .map_err(StreamError::CommandError)?;
+        let mut failures = 0_u8;
+        'send: loop {
+            self.acquire_command_credit().await?;
+            if let Err(e) = self.adapter.write_command(packet.as_ref()).await {
+                failures = failures.saturating_add(1);
+                match self.retry_after(e, failures).await? {
+                    // The credit wasn't used.
+                    Retried::Backoff => self.command_credits.release(),
+                    Retried::Reconnected => (),
+                }
+                continue;
+            }
+            for _ in 0..HCI_EVENT_READ_TRIES {
+                let event = match self.adapter.read_event::<H::Buf>().await {
+                    Ok(event) => event,
+                    Err(e) => {
+                        failures = failures.saturating_add(1);
+                        match self.retry_after(e, failures).await? {
+                            Retried::Backoff => continue,
+                            Retried::Reconnected => continue 'send,
+                        }
+                    }
+                };
+                self.track_credits(&event);
+                if let Some(ret) =
+                    Cmd::unpack_return(event.as_ref()).map_err(StreamError::EventError)?
+                {
+                    return Ok(ret);
+                }
+                self.event_handler.handle(event)?;
+            }
+            return Err(StreamError::StreamFailed.into());
+        }
     }
     pub async fn hci_read_event<Buf: Storage<u8>>(
         &mut self,
     ) -> Result<EventPacket<Buf>, adapter::Error> {
-        self.adapter.read_event().await
+        let event: EventPacket<Buf> = self.adapter.read_event().await?;
+        self.track_credits(&event);
+        Ok(event)
     }
+    /// Sends `packet`. With ACL flow control enabled, first reads (and handles) events until the
+    /// controller has a buffer free for it.
+    pub async fn hci_send_acl(&mut self, packet: ACLPacket<&[u8]>) -> Result<(), adapter::Error> {
+        let handle = packet.connection_handle;
+        while let Some(credits) = self.acl_credits.as_mut() {
+            if credits.try_acquire(handle) {
+                break;
+            }
+            hci_trace!("waiting for an ACL credit for {:?}", handle);
+            self.handle_next_event().await?;
+        }
+        self.adapter.write_acl(packet).await
+    }
+    /// Stream of events read with [`Adapter::hci_read_event`] (so credits returned by them are
+    /// tracked).
     pub fn hci_event_stream<'a, 'b: 'a, Buf: Storage<u8> + 'b>(
         &'a mut self,
     ) -> impl Stream<Item = Result<EventPacket<Buf>, adapter::Error>> + 'a {
         futures_util::stream::unfold(self, move |s| async move {
-            Some((s.adapter.read_event().await, s))
+            Some((s.hci_read_event().await, s))
         })
     }
     pub async fn set_event_mask(&mut self, mask: EventMask) -> Result<(), adapter::Error> {
@@ -87,9 +328,92 @@ impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Adapter<A, H> {
         Ok(())
     }
     pub async fn reset(&mut self) -> Result<(), adapter::Error> {
+        self.controller_info = None;
         self.hci_send_command(Reset).await?.params.status.error()?;
         Ok(())
     }
+    /// Start disconnecting `connection_handle`. Completion is reported by a Disconnection
+    /// Complete event.
+    pub async fn disconnect(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        reason: ErrorCode,
+    ) -> Result<(), adapter::Error> {
+        self.hci_send_command(Disconnect {
+            connection_handle,
+            reason,
+        })
+        .await?
+        .status
+        .error()?;
+        Ok(())
+    }
+    /// Read the local controller clock or the piconet clock of `connection_handle`. Pair the
+    /// returned clock with a host timestamp in a [`crate::hci::status::ClockMapping`] to convert
+    /// between the two.
+    pub async fn read_clock(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        which_clock: WhichClock,
+    ) -> Result<ReadClockReturn, adapter::Error> {
+        let r = self
+            .hci_send_command(ReadClock {
+                connection_handle,
+                which_clock,
+            })
+            .await?;
+        r.params.status.error()?;
+        Ok(r.params)
+    }
+    /// Read the min and max delay the controller adds when processing data with a codec
+    /// configuration.
+    pub async fn read_local_supported_controller_delay(
+        &mut self,
+        command: ReadLocalSupportedControllerDelay,
+    ) -> Result<ControllerDelayReturn, adapter::Error> {
+        let r = self.hci_send_command(command).await?;
+        r.params.status.error()?;
+        Ok(r.params)
+    }
+    /// Make the device discoverable (inquiry scan) and/or connectable (page scan) over BR/EDR.
+    #[cfg(feature = "bredr")]
+    pub async fn write_scan_enable(
+        &mut self,
+        scan_enable: crate::hci::bredr::ScanEnable,
+    ) -> Result<(), adapter::Error> {
+        self.hci_send_command(crate::hci::bredr::WriteScanEnable { scan_enable })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    #[cfg(feature = "bredr")]
+    pub async fn write_class_of_device(
+        &mut self,
+        class_of_device: crate::hci::bredr::ClassOfDevice,
+    ) -> Result<(), adapter::Error> {
+        self.hci_send_command(crate::hci::bredr::WriteClassOfDevice { class_of_device })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Set the BR/EDR device name.
+    /// # Errors
+    /// Returns `adapter::Error::BadParameter` if `name` is longer than 248 bytes.
+    #[cfg(feature = "bredr")]
+    pub async fn write_local_name(&mut self, name: &str) -> Result<(), adapter::Error> {
+        let local_name =
+            crate::hci::bredr::LocalName::new(name).ok_or(adapter::Error::BadParameter)?;
+        self.hci_send_command(crate::hci::bredr::WriteLocalName { local_name })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
 }
 
 
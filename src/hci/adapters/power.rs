@@ -0,0 +1,168 @@
+//! Transmit power management of one LE connection. [`PowerControl`] bundles the LE Power Control
+//! commands for a connection handle. An adaptive transmit power policy enables path loss
+//! reporting, waits for [`PowerEvent`]s with [`PowerControl::next_event`] and lowers the power in
+//! the low zone or raises it in the high zone (through vendor commands, HCI has no command to set
+//! the power of a connection).
+use crate::hci::adapter;
+use crate::hci::adapters::le::LEAdapter;
+use crate::hci::adapters::UnrecognizedEventHandler;
+use crate::hci::event::EventPacket;
+use crate::hci::le::power::{
+    EnhancedReadTransmitPowerLevel, PathLossThreshold, PowerControlPHY,
+    ReadRemoteTransmitPowerLevel, SetPathLossReportingEnable, SetPathLossReportingParameters,
+    SetTransmitPowerReportingEnable, TransmitPowerReporting,
+};
+use crate::hci::le::{MetaEvent, MetaEventCode, RawMetaEvent};
+use crate::hci::StreamError;
+use crate::le::connection::ConnectionHandle;
+use core::convert::TryFrom;
+
+/// Transmit power of the local side of a connection on one PHY, in dBm.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct LocalTxPower {
+    /// `None` if the PHY isn't in use.
+    pub current: Option<i8>,
+    pub max: i8,
+}
+/// Path loss zone boundaries in dB. See
+/// [`SetPathLossReportingParameters`] for the meaning of each field.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct PathLossParameters {
+    pub high_threshold: u8,
+    pub high_hysteresis: u8,
+    pub low_threshold: u8,
+    pub low_hysteresis: u8,
+    /// In connection events.
+    pub min_time_spent: u16,
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum PowerEvent {
+    PathLoss(PathLossThreshold),
+    TransmitPower(TransmitPowerReporting),
+}
+impl PowerEvent {
+    pub fn connection_handle(&self) -> ConnectionHandle {
+        match self {
+            PowerEvent::PathLoss(e) => e.connection_handle,
+            PowerEvent::TransmitPower(e) => e.connection_handle,
+        }
+    }
+}
+/// Power control of `connection_handle`. Made with [`LEAdapter::power_control`].
+pub struct PowerControl<'a, A: adapter::Adapter, H: UnrecognizedEventHandler> {
+    pub adapter: &'a mut LEAdapter<A, H>,
+    pub connection_handle: ConnectionHandle,
+}
+impl<A: adapter::Adapter, H: UnrecognizedEventHandler> PowerControl<'_, A, H> {
+    pub async fn local_tx_power(
+        &mut self,
+        phy: PowerControlPHY,
+    ) -> Result<LocalTxPower, adapter::Error> {
+        let ret = self
+            .adapter
+            .adapter
+            .hci_send_command(EnhancedReadTransmitPowerLevel {
+                connection_handle: self.connection_handle,
+                phy,
+            })
+            .await?
+            .params;
+        ret.status.error()?;
+        Ok(LocalTxPower {
+            current: ret.current(),
+            max: ret.max_tx_power_level,
+        })
+    }
+    /// Asks the remote for its transmit power. The answer is a [`PowerEvent::TransmitPower`].
+    pub async fn request_remote_tx_power(
+        &mut self,
+        phy: PowerControlPHY,
+    ) -> Result<(), adapter::Error> {
+        self.adapter
+            .adapter
+            .hci_send_command(ReadRemoteTransmitPowerLevel {
+                connection_handle: self.connection_handle,
+                phy,
+            })
+            .await?
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Sets the path loss zones and enables (or disables with `None`) path loss reporting.
+    pub async fn set_path_loss_reporting(
+        &mut self,
+        parameters: Option<PathLossParameters>,
+    ) -> Result<(), adapter::Error> {
+        if let Some(parameters) = parameters {
+            self.adapter
+                .adapter
+                .hci_send_command(SetPathLossReportingParameters {
+                    connection_handle: self.connection_handle,
+                    high_threshold: parameters.high_threshold,
+                    high_hysteresis: parameters.high_hysteresis,
+                    low_threshold: parameters.low_threshold,
+                    low_hysteresis: parameters.low_hysteresis,
+                    min_time_spent: parameters.min_time_spent,
+                })
+                .await?
+                .params
+                .status
+                .error()?;
+        }
+        self.adapter
+            .adapter
+            .hci_send_command(SetPathLossReportingEnable {
+                connection_handle: self.connection_handle,
+                enable: parameters.is_some(),
+            })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    pub async fn set_tx_power_reporting(
+        &mut self,
+        local: bool,
+        remote: bool,
+    ) -> Result<(), adapter::Error> {
+        self.adapter
+            .adapter
+            .hci_send_command(SetTransmitPowerReportingEnable {
+                connection_handle: self.connection_handle,
+                local_enable: local,
+                remote_enable: remote,
+            })
+            .await?
+            .params
+            .status
+            .error()?;
+        Ok(())
+    }
+    /// Waits for the next power event of this connection. Other events (including power events
+    /// of other connections) go to the `UnrecognizedEventHandler`. The LE Meta events have to be
+    /// enabled in the event masks.
+    pub async fn next_event(&mut self) -> Result<PowerEvent, adapter::Error> {
+        loop {
+            let event: EventPacket<H::Buf> = self.adapter.adapter.hci_read_event().await?;
+            let power_event = match RawMetaEvent::try_from(event.as_ref()) {
+                Ok(meta) if meta.code == MetaEventCode::PathLossThreshold => {
+                    Some(PathLossThreshold::meta_unpack_packet(meta).map(PowerEvent::PathLoss))
+                }
+                Ok(meta) if meta.code == MetaEventCode::TransmitPowerReporting => Some(
+                    TransmitPowerReporting::meta_unpack_packet(meta).map(PowerEvent::TransmitPower),
+                ),
+                _ => None,
+            }
+            .transpose()
+            .map_err(StreamError::EventError)?;
+            match power_event {
+                Some(power_event) if power_event.connection_handle() == self.connection_handle => {
+                    return Ok(power_event)
+                }
+                _ => self.adapter.adapter.event_handler.handle(event)?,
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Push based HCI adapter for completion based IO (io_uring, embedded DMA drivers, ...). Instead
+//! of the adapter pulling bytes from an `AsyncRead` style stream, the IO backend hands complete
+//! packets to [`PacketPusher::push_packet`] when its reads complete and takes packed commands
+//! (and ACL data) from [`PacketPusher::pop_outgoing`] to submit as writes. [`PushAdapter`] implements
+//! [`adapter::Adapter`] on top of the queues so the rest of the crate (and a
+//! [`Dispatcher`](crate::hci::adapters::dispatcher::Dispatcher)) works unchanged.
+use crate::bytes::Storage;
+use crate::hci::acl::ACLPacket;
+use crate::hci::adapter;
+use crate::hci::command::CommandPacket;
+use crate::hci::event::EventPacket;
+use crate::hci::packet::RawPacket;
+use crate::hci::StreamError;
+use crate::{LocalBoxFuture, PackError};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::convert::TryFrom;
+use core::task::{Poll, Waker};
+use futures_util::future::poll_fn;
+
+#[derive(Default)]
+struct Queues {
+    incoming: VecDeque<EventPacket<Box<[u8]>>>,
+    outgoing: VecDeque<Box<[u8]>>,
+    reader: Option<Waker>,
+    closed: bool,
+}
+/// IO backend side of a [`PushAdapter`]. Cloning gives another handle to the same queues.
+#[derive(Clone)]
+pub struct PacketPusher {
+    queues: Rc<RefCell<Queues>>,
+}
+impl PacketPusher {
+    /// Queues one complete H4 packet (packet type byte first) read from the controller. Only
+    /// event packets are accepted.
+    pub fn push_packet(&self, packet: &[u8]) -> Result<(), PackError> {
+        let raw = RawPacket::try_from(packet).map_err(|_| PackError::bad_index(0))?;
+        self.push_event(&EventPacket::try_from(raw)?);
+        Ok(())
+    }
+    /// Queues an already parsed event.
+    pub fn push_event<B: AsRef<[u8]>>(&self, event: &EventPacket<B>) {
+        let mut queues = self.queues.borrow_mut();
+        queues.incoming.push_back(event.to_new_storage());
+        if let Some(waker) = queues.reader.take() {
+            waker.wake();
+        }
+    }
+    /// Next packed H4 command packet (packet type byte first) to write to the controller.
+    pub fn pop_outgoing(&self) -> Option<Box<[u8]>> {
+        self.queues.borrow_mut().outgoing.pop_front()
+    }
+    pub fn outgoing_len(&self) -> usize {
+        self.queues.borrow().outgoing.len()
+    }
+    pub fn incoming_len(&self) -> usize {
+        self.queues.borrow().incoming.len()
+    }
+    /// The transport is gone. Pending reads fail with `adapter::Error::ChannelClosed` once the
+    /// queued events are read.
+    pub fn close(&self) {
+        let mut queues = self.queues.borrow_mut();
+        queues.closed = true;
+        if let Some(waker) = queues.reader.take() {
+            waker.wake();
+        }
+    }
+}
+/// HCI adapter fed by a [`PacketPusher`].
+pub struct PushAdapter {
+    queues: Rc<RefCell<Queues>>,
+}
+impl PushAdapter {
+    pub fn new() -> (PushAdapter, PacketPusher) {
+        let queues = Rc::new(RefCell::new(Queues::default()));
+        (
+            PushAdapter {
+                queues: queues.clone(),
+            },
+            PacketPusher { queues },
+        )
+    }
+    pub fn pusher(&self) -> PacketPusher {
+        PacketPusher {
+            queues: self.queues.clone(),
+        }
+    }
+}
+impl adapter::Adapter for PushAdapter {
+    fn write_command<'s, 'p: 's>(
+        &'s mut self,
+        packet: CommandPacket<&'p [u8]>,
+    ) -> LocalBoxFuture<'s, Result<(), adapter::Error>> {
+        let mut queues = self.queues.borrow_mut();
+        let result = if queues.closed {
+            Err(adapter::Error::ChannelClosed)
+        } else {
+            packet
+                .pack_as_raw_packet()
+                .map(|out| queues.outgoing.push_back(out))
+                .map_err(|e| StreamError::CommandError(e).into())
+        };
+        Box::pin(async move { result })
+    }
+
+    fn read_event<'s, 'p: 's, S: Storage<u8> + 'p>(
+        &'s mut self,
+    ) -> LocalBoxFuture<'s, Result<EventPacket<S>, adapter::Error>> {
+        Box::pin(poll_fn(move |cx| {
+            let mut queues = self.queues.borrow_mut();
+            match queues.incoming.pop_front() {
+                Some(event) => Poll::Ready(Ok(event.to_new_storage())),
+                None if queues.closed => Poll::Ready(Err(adapter::Error::ChannelClosed)),
+                None => {
+                    queues.reader = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }))
+    }
+    fn write_acl<'s, 'p: 's>(
+        &'s mut self,
+        packet: ACLPacket<&'p [u8]>,
+    ) -> LocalBoxFuture<'s, Result<(), adapter::Error>> {
+        let mut queues = self.queues.borrow_mut();
+        let result = if queues.closed {
+            Err(adapter::Error::ChannelClosed)
+        } else {
+            packet
+                .pack_as_raw_packet()
+                .map(|out| queues.outgoing.push_back(out))
+                .map_err(|e| StreamError::DataError(e).into())
+        };
+        Box::pin(async move { result })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::adapter::Adapter;
+    use crate::hci::baseband::Reset;
+    use crate::hci::command::Command;
+    use futures_util::task::noop_waker_ref;
+    #[test]
+    fn test_push_adapter() {
+        let (mut adapter, pusher) = PushAdapter::new();
+        let mut cx = core::task::Context::from_waker(noop_waker_ref());
+        let command = Reset.pack_command_packet::<Box<[u8]>>().unwrap();
+        let mut write = adapter.write_command(command.as_ref());
+        assert_eq!(write.as_mut().poll(&mut cx), Poll::Ready(Ok(())));
+        drop(write);
+        assert_eq!(
+            pusher.pop_outgoing().as_deref(),
+            Some(&[0x01, 0x03, 0x0C, 0x00][..])
+        );
+        {
+            let mut read = adapter.read_event::<Box<[u8]>>();
+            assert!(read.as_mut().poll(&mut cx).is_pending());
+            // Command Complete for Reset.
+            pusher
+                .push_packet(&[0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00])
+                .unwrap();
+            match read.as_mut().poll(&mut cx) {
+                Poll::Ready(Ok(event)) => assert_eq!(event.parameters(), &[0x01, 0x03, 0x0C, 0x00]),
+                _ => panic!("event not read"),
+            }
+        }
+        assert!(pusher.push_packet(&[0x02, 0x00]).is_err());
+        pusher.close();
+        let mut read = adapter.read_event::<Box<[u8]>>();
+        assert!(matches!(
+            read.as_mut().poll(&mut cx),
+            Poll::Ready(Err(adapter::Error::ChannelClosed))
+        ));
+    }
+}
@@ -0,0 +1,432 @@
+//! Controller reset recovery. A controller that stopped answering is brought back with
+//! increasingly heavy steps: the HCI Reset command, a vendor specific reset and finally a
+//! transport reset (power or port cycling a USB dongle, toggling a UART controller's reset pin).
+//! [`ResetRecovery`] is the state machine going through them. [`ResetRecovery::run`] drives it
+//! with a [`ResetHooks`] that provides the vendor and transport steps and gets a [`ResetEvent`]
+//! for every step so progress can be shown or logged. [`ResetRecovery::send_command`] starts it
+//! on its own once commands keep timing out (see [`Adapter::set_command_timeout`]).
+use crate::error::IOError;
+use crate::hci::adapter;
+use crate::hci::adapters::{Adapter, UnrecognizedEventHandler};
+use crate::hci::command::Command;
+use crate::hci::StreamError;
+use core::time::Duration;
+use futures_util::future::{self, Either, LocalBoxFuture};
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ResetStep {
+    HCIReset,
+    VendorReset,
+    TransportReset,
+}
+impl ResetStep {
+    fn next(self) -> Option<ResetStep> {
+        match self {
+            ResetStep::HCIReset => Some(ResetStep::VendorReset),
+            ResetStep::VendorReset => Some(ResetStep::TransportReset),
+            ResetStep::TransportReset => None,
+        }
+    }
+}
+/// How many times each step is tried and how long a try may take.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ResetPolicy {
+    pub hci_reset_attempts: u8,
+    pub vendor_reset_attempts: u8,
+    pub transport_reset_attempts: u8,
+    pub step_timeout: Duration,
+    /// Command timeouts in a row after which [`ResetRecovery::send_command`] starts recovery.
+    pub timeouts_before_reset: u8,
+}
+impl ResetPolicy {
+    pub const DEFAULT: ResetPolicy = ResetPolicy {
+        hci_reset_attempts: 2,
+        vendor_reset_attempts: 1,
+        transport_reset_attempts: 1,
+        step_timeout: Duration::from_secs(2),
+        timeouts_before_reset: 3,
+    };
+    pub fn attempts(&self, step: ResetStep) -> u8 {
+        match step {
+            ResetStep::HCIReset => self.hci_reset_attempts,
+            ResetStep::VendorReset => self.vendor_reset_attempts,
+            ResetStep::TransportReset => self.transport_reset_attempts,
+        }
+    }
+}
+impl Default for ResetPolicy {
+    fn default() -> Self {
+        ResetPolicy::DEFAULT
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum RecoveryState {
+    Idle,
+    /// Trying `step` for the `attempt`th time (starting at 1).
+    Attempting {
+        step: ResetStep,
+        attempt: u8,
+    },
+    /// The controller answers again after `step`.
+    Recovered(ResetStep),
+    /// Every step failed.
+    Failed,
+}
+/// How a step went.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum StepResult {
+    Succeeded,
+    Failed(adapter::Error),
+    /// There is no way to do this step (no vendor reset for this controller for example).
+    Unsupported,
+}
+/// Reported to [`ResetHooks::event`] as recovery goes on.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ResetEvent {
+    Attempting {
+        step: ResetStep,
+        attempt: u8,
+    },
+    StepFailed {
+        step: ResetStep,
+        attempt: u8,
+        error: adapter::Error,
+    },
+    StepUnsupported(ResetStep),
+    Recovered(ResetStep),
+    GaveUp,
+}
+/// The parts of recovery that depend on the controller, the transport and the runtime.
+pub trait ResetHooks<A: adapter::Adapter, H: UnrecognizedEventHandler> {
+    /// Resets the controller with a vendor specific command. Return `Ok(false)` if there is none.
+    fn vendor_reset<'a>(
+        &'a mut self,
+        _adapter: &'a mut Adapter<A, H>,
+    ) -> LocalBoxFuture<'a, Result<bool, adapter::Error>> {
+        Box::pin(future::ready(Ok(false)))
+    }
+    /// Resets the transport (re-enumerating a USB device for example). Return `Ok(false)` if it
+    /// can't be done.
+    fn transport_reset<'a>(
+        &'a mut self,
+        _adapter: &'a mut Adapter<A, H>,
+    ) -> LocalBoxFuture<'a, Result<bool, adapter::Error>> {
+        Box::pin(future::ready(Ok(false)))
+    }
+    /// Finishes after `duration`. Used to time out steps.
+    fn delay(&mut self, duration: Duration) -> LocalBoxFuture<'static, ()>;
+    fn event(&mut self, _event: ResetEvent) {}
+}
+/// Reset recovery state machine. See the module docs.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ResetRecovery {
+    policy: ResetPolicy,
+    state: RecoveryState,
+    timeouts: u8,
+}
+impl ResetRecovery {
+    pub fn new(policy: ResetPolicy) -> ResetRecovery {
+        ResetRecovery {
+            policy,
+            state: RecoveryState::Idle,
+            timeouts: 0,
+        }
+    }
+    pub fn state(&self) -> RecoveryState {
+        self.state
+    }
+    pub fn policy(&self) -> &ResetPolicy {
+        &self.policy
+    }
+    /// Counts command timeouts in a row. Returns `true` once there were
+    /// `timeouts_before_reset` of them (and starts counting again), any other result resets the
+    /// count.
+    pub fn command_result<T>(&mut self, result: &Result<T, adapter::Error>) -> bool {
+        if !matches!(
+            result,
+            Err(adapter::Error::StreamError(StreamError::Timeout))
+        ) {
+            self.timeouts = 0;
+            return false;
+        }
+        self.timeouts = self.timeouts.saturating_add(1);
+        if self.timeouts < self.policy.timeouts_before_reset {
+            return false;
+        }
+        self.timeouts = 0;
+        true
+    }
+    /// Sends `cmd` on `adapter` and [`ResetRecovery::run`]s recovery once commands timed out
+    /// `timeouts_before_reset` times in a row. The command isn't sent again after recovery, its
+    /// timeout is returned either way.
+    ///
+    /// # Errors
+    /// Returns the recovery error instead if the controller couldn't be brought back.
+    pub async fn send_command<A, H, R, Cmd>(
+        &mut self,
+        adapter: &mut Adapter<A, H>,
+        hooks: &mut R,
+        cmd: Cmd,
+    ) -> Result<Cmd::Return, adapter::Error>
+    where
+        A: adapter::Adapter,
+        H: UnrecognizedEventHandler,
+        R: ResetHooks<A, H>,
+        Cmd: Command,
+    {
+        let result = adapter.hci_send_command(cmd).await;
+        if self.command_result(&result) {
+            hci_debug!("commands keep timing out, resetting the controller");
+            self.run(adapter, hooks).await?;
+        }
+        result
+    }
+    /// Starts (or restarts) recovery from the first step.
+    pub fn start(&mut self) -> RecoveryState {
+        self.state = self.first_attempt(ResetStep::HCIReset);
+        self.state
+    }
+    fn first_attempt(&self, step: ResetStep) -> RecoveryState {
+        let mut step = Some(step);
+        while let Some(s) = step {
+            if self.policy.attempts(s) > 0 {
+                return RecoveryState::Attempting {
+                    step: s,
+                    attempt: 1,
+                };
+            }
+            step = s.next();
+        }
+        RecoveryState::Failed
+    }
+    /// Moves on after the current attempt finished with `result`. Does nothing unless a step is
+    /// being attempted.
+    pub fn advance(&mut self, result: StepResult) -> RecoveryState {
+        let (step, attempt) = match self.state {
+            RecoveryState::Attempting { step, attempt } => (step, attempt),
+            state => return state,
+        };
+        self.state = match result {
+            StepResult::Succeeded => RecoveryState::Recovered(step),
+            StepResult::Failed(_) if attempt < self.policy.attempts(step) => {
+                RecoveryState::Attempting {
+                    step,
+                    attempt: attempt + 1,
+                }
+            }
+            StepResult::Failed(_) | StepResult::Unsupported => match step.next() {
+                Some(next) => self.first_attempt(next),
+                None => RecoveryState::Failed,
+            },
+        };
+        self.state
+    }
+    /// Runs recovery until the controller answers an HCI Reset again or every step failed.
+    /// Vendor and transport resets are followed by an HCI Reset to check the controller is back.
+    pub async fn run<A, H, R>(
+        &mut self,
+        adapter: &mut Adapter<A, H>,
+        hooks: &mut R,
+    ) -> Result<ResetStep, adapter::Error>
+    where
+        A: adapter::Adapter,
+        H: UnrecognizedEventHandler,
+        R: ResetHooks<A, H>,
+    {
+        let mut last_error = IOError::TimedOut.into();
+        let mut state = self.start();
+        loop {
+            let (step, attempt) = match state {
+                RecoveryState::Attempting { step, attempt } => (step, attempt),
+                RecoveryState::Recovered(step) => {
+                    hooks.event(ResetEvent::Recovered(step));
+                    return Ok(step);
+                }
+                RecoveryState::Idle | RecoveryState::Failed => {
+                    hooks.event(ResetEvent::GaveUp);
+                    return Err(last_error);
+                }
+            };
+            hooks.event(ResetEvent::Attempting { step, attempt });
+            let timeout = hooks.delay(self.policy.step_timeout);
+            let result =
+                match future::select(Box::pin(attempt_step(step, adapter, hooks)), timeout).await {
+                    Either::Left((result, _)) => result,
+                    Either::Right(((), _)) => StepResult::Failed(IOError::TimedOut.into()),
+                };
+            match result {
+                StepResult::Failed(error) => {
+                    last_error = error;
+                    hooks.event(ResetEvent::StepFailed {
+                        step,
+                        attempt,
+                        error,
+                    });
+                }
+                StepResult::Unsupported => hooks.event(ResetEvent::StepUnsupported(step)),
+                StepResult::Succeeded => (),
+            }
+            state = self.advance(result);
+        }
+    }
+}
+impl Default for ResetRecovery {
+    fn default() -> Self {
+        ResetRecovery::new(ResetPolicy::DEFAULT)
+    }
+}
+async fn attempt_step<A, H, R>(
+    step: ResetStep,
+    adapter: &mut Adapter<A, H>,
+    hooks: &mut R,
+) -> StepResult
+where
+    A: adapter::Adapter,
+    H: UnrecognizedEventHandler,
+    R: ResetHooks<A, H>,
+{
+    let supported = match step {
+        ResetStep::HCIReset => Ok(true),
+        ResetStep::VendorReset => hooks.vendor_reset(adapter).await,
+        ResetStep::TransportReset => hooks.transport_reset(adapter).await,
+    };
+    match supported {
+        Ok(true) => match adapter.reset().await {
+            Ok(()) => StepResult::Succeeded,
+            Err(e) => StepResult::Failed(e),
+        },
+        Ok(false) => StepResult::Unsupported,
+        Err(e) => StepResult::Failed(e),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::baseband::Reset;
+    use crate::hci::informational::ReadLocalVersionInformation;
+    use crate::hci::stream::Stream;
+    use crate::hci::timer::Timer;
+    use crate::hci::ErrorCode;
+    use crate::testing::mock::{command_complete, MockTransport};
+    use alloc::vec::Vec;
+    use futures_util::FutureExt;
+
+    struct Hooks(Vec<ResetEvent>);
+    impl<A: adapter::Adapter, H: UnrecognizedEventHandler> ResetHooks<A, H> for Hooks {
+        fn transport_reset<'a>(
+            &'a mut self,
+            _adapter: &'a mut Adapter<A, H>,
+        ) -> LocalBoxFuture<'a, Result<bool, adapter::Error>> {
+            Box::pin(future::ready(Ok(true)))
+        }
+        fn delay(&mut self, _duration: Duration) -> LocalBoxFuture<'static, ()> {
+            Box::pin(future::pending())
+        }
+        fn event(&mut self, event: ResetEvent) {
+            self.0.push(event);
+        }
+    }
+    #[test]
+    fn test_recovery_escalates() {
+        let (transport, handle) = MockTransport::new();
+        let busy = command_complete(Reset::opcode(), &[ErrorCode::ControllerBusy.into()]);
+        let ok = command_complete(Reset::opcode(), &[0x00]);
+        for response in [&busy, &busy, &ok] {
+            handle.expect_command(&Reset {}, &[response]);
+        }
+        let mut adapter = Adapter::new(Stream::new(Box::pin(transport)));
+        let mut hooks = Hooks(Vec::new());
+        let mut recovery = ResetRecovery::default();
+        let step = recovery
+            .run(&mut adapter, &mut hooks)
+            .now_or_never()
+            .unwrap();
+        assert_eq!(step, Ok(ResetStep::TransportReset));
+        handle.assert_done();
+        let error = adapter::Error::ErrorCode(ErrorCode::ControllerBusy);
+        assert_eq!(
+            hooks.0,
+            [
+                ResetEvent::Attempting {
+                    step: ResetStep::HCIReset,
+                    attempt: 1
+                },
+                ResetEvent::StepFailed {
+                    step: ResetStep::HCIReset,
+                    attempt: 1,
+                    error
+                },
+                ResetEvent::Attempting {
+                    step: ResetStep::HCIReset,
+                    attempt: 2
+                },
+                ResetEvent::StepFailed {
+                    step: ResetStep::HCIReset,
+                    attempt: 2,
+                    error
+                },
+                ResetEvent::Attempting {
+                    step: ResetStep::VendorReset,
+                    attempt: 1
+                },
+                ResetEvent::StepUnsupported(ResetStep::VendorReset),
+                ResetEvent::Attempting {
+                    step: ResetStep::TransportReset,
+                    attempt: 1
+                },
+                ResetEvent::Recovered(ResetStep::TransportReset),
+            ]
+        );
+        assert_eq!(
+            recovery.state(),
+            RecoveryState::Recovered(ResetStep::TransportReset)
+        );
+    }
+    #[test]
+    fn test_recovery_after_timeouts() {
+        struct Expired;
+        impl Timer for Expired {
+            fn delay(&mut self, _duration: Duration) -> LocalBoxFuture<'static, ()> {
+                Box::pin(future::ready(()))
+            }
+        }
+        let (transport, handle) = MockTransport::new();
+        let mut adapter = Adapter::new(Stream::new(Box::pin(transport)));
+        adapter.set_command_timeout(Expired, Duration::from_secs(1));
+        let mut hooks = Hooks(Vec::new());
+        let mut recovery = ResetRecovery::new(ResetPolicy {
+            timeouts_before_reset: 2,
+            ..ResetPolicy::DEFAULT
+        });
+        let timeout = Err(adapter::Error::StreamError(StreamError::Timeout));
+        let version = ReadLocalVersionInformation {};
+        handle.expect_command(&version, &[]);
+        assert_eq!(
+            recovery
+                .send_command(&mut adapter, &mut hooks, version)
+                .now_or_never()
+                .unwrap(),
+            timeout
+        );
+        assert_eq!(recovery.state(), RecoveryState::Idle);
+        handle.expect_command(&version, &[]);
+        handle.expect_command(&Reset {}, &[&command_complete(Reset::opcode(), &[0x00])]);
+        assert_eq!(
+            recovery
+                .send_command(&mut adapter, &mut hooks, version)
+                .now_or_never()
+                .unwrap(),
+            timeout
+        );
+        handle.assert_done();
+        assert_eq!(
+            recovery.state(),
+            RecoveryState::Recovered(ResetStep::HCIReset)
+        );
+        // Any other result starts counting again.
+        assert!(!recovery.command_result(&timeout));
+        assert!(!recovery.command_result(&Ok(())));
+        assert!(!recovery.command_result(&timeout));
+        assert!(recovery.command_result(&timeout));
+    }
+}
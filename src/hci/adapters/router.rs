@@ -0,0 +1,402 @@
+//! Thread safe HCI event [`EventRouter`] with bounded queues. A reader task runs
+//! [`EventRouter::run`] on the [`EventReader`] half of a split stream and every [`RouteReceiver`]
+//! gets the events matching its [`EventFilter`] (event codes, LE Meta sub events, connection
+//! handle), so a scanner, a connection manager and command completion logic can all consume
+//! events of the same adapter. It is the bounded, `Send` counterpart of the
+//! [`Dispatcher`](crate::hci::adapters::dispatcher::Dispatcher), which queues without bounds on
+//! a single task.
+//!
+//! Queues are bounded. Each receiver's [`OverflowPolicy`] decides what happens when its queue is
+//! full: `Block` receivers make routing wait until they have room, so a slow receiver slows the
+//! reader task down instead of growing memory or losing events, and lossy receivers drop events
+//! instead. Dropped receivers stop counting right away.
+//!
+//! Latency: an event is queued for (and wakes) every receiver with room as soon as it is routed,
+//! before routing waits for any full `Block` receiver. Lossy receivers never make routing wait.
+//! So as long as only lossy receivers can fall behind (subscribe advertising reports with
+//! `DropOldest`), every receiver is woken within the routing of the event it waits for, however
+//! saturated the others are.
+use crate::hci::adapter;
+use crate::hci::adapters::dispatcher::EventFilter;
+use crate::hci::event::EventPacket;
+use crate::hci::split::EventReader;
+use crate::hci::stream::HCIReader;
+use crate::le::report_sink::OverflowPolicy;
+use crate::sync::Mutex;
+use crate::Stream;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use futures_util::future::poll_fn;
+use std::sync::Arc;
+
+struct Receiver {
+    id: u64,
+    filter: EventFilter,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    queue: VecDeque<EventPacket<Box<[u8]>>>,
+    dropped: u64,
+    waker: Option<Waker>,
+}
+#[derive(Default)]
+struct Routes {
+    next_id: u64,
+    receivers: Vec<Receiver>,
+    /// Woken when a receiver takes an event or goes away.
+    router_waker: Option<Waker>,
+    closed: bool,
+}
+#[derive(Default)]
+struct Inner {
+    routes: Mutex<Routes>,
+}
+impl Inner {
+    fn lock<R>(&self, f: impl FnOnce(&mut Routes) -> R) -> R {
+        self.routes.lock(f)
+    }
+}
+/// Routes HCI events to [`RouteReceiver`]s. Cloning gives another handle to the same receivers.
+#[derive(Clone, Default)]
+pub struct EventRouter {
+    inner: Arc<Inner>,
+}
+impl EventRouter {
+    pub fn new() -> EventRouter {
+        EventRouter::default()
+    }
+    /// Receives the events matching `filter`. At most `capacity` (at least 1) events are queued
+    /// and routing waits for room when the queue is full.
+    pub fn subscribe(&self, filter: EventFilter, capacity: usize) -> RouteReceiver {
+        self.subscribe_with(filter, capacity, OverflowPolicy::Block)
+    }
+    /// Like [`EventRouter::subscribe`] but `overflow` decides what happens when the queue is
+    /// full. Lossy receivers count their dropped events (see [`RouteReceiver::dropped`]).
+    pub fn subscribe_with(
+        &self,
+        filter: EventFilter,
+        capacity: usize,
+        overflow: OverflowPolicy,
+    ) -> RouteReceiver {
+        hci_debug!(
+            "route added: {:?} (capacity {}, {:?})",
+            filter,
+            capacity,
+            overflow
+        );
+        let id = self.inner.lock(|routes| {
+            let id = routes.next_id;
+            routes.next_id += 1;
+            routes.receivers.push(Receiver {
+                id,
+                filter,
+                capacity: capacity.max(1),
+                overflow,
+                queue: VecDeque::new(),
+                dropped: 0,
+                waker: None,
+            });
+            id
+        });
+        RouteReceiver {
+            id,
+            inner: self.inner.clone(),
+        }
+    }
+    pub fn receiver_count(&self) -> usize {
+        self.inner.lock(|routes| routes.receivers.len())
+    }
+    /// Queues `event` for every matching receiver. Receivers with room get it right away, then
+    /// routing waits until the full `Block` receivers have room. Returns how many receivers got
+    /// it (events a lossy receiver dropped on arrival don't count).
+    pub async fn route<B: AsRef<[u8]>>(&self, event: &EventPacket<B>) -> usize {
+        let mut handled = Vec::new();
+        let mut delivered = 0;
+        poll_fn(|cx| {
+            self.inner
+                .lock(|routes| Self::poll_route(routes, event, &mut handled, &mut delivered, cx))
+        })
+        .await
+    }
+    /// Delivers `event` to the matching receivers not `handled` yet.
+    fn poll_route<B: AsRef<[u8]>>(
+        routes: &mut Routes,
+        event: &EventPacket<B>,
+        handled: &mut Vec<u64>,
+        delivered: &mut usize,
+        cx: &mut Context<'_>,
+    ) -> Poll<usize> {
+        let mut waiting = false;
+        for receiver in &mut routes.receivers {
+            if !receiver.filter.matches(event) || handled.contains(&receiver.id) {
+                continue;
+            }
+            if receiver.queue.len() >= receiver.capacity {
+                match receiver.overflow {
+                    OverflowPolicy::Block => {
+                        waiting = true;
+                        continue;
+                    }
+                    OverflowPolicy::DropOldest => {
+                        receiver.queue.pop_front();
+                        receiver.dropped += 1;
+                    }
+                    OverflowPolicy::DropNewest => {
+                        receiver.dropped += 1;
+                        handled.push(receiver.id);
+                        continue;
+                    }
+                }
+            }
+            receiver.queue.push_back(event.to_new_storage());
+            if let Some(waker) = receiver.waker.take() {
+                waker.wake();
+            }
+            handled.push(receiver.id);
+            *delivered += 1;
+        }
+        if waiting {
+            routes.router_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(*delivered)
+    }
+    /// Reads and routes events from `reader` until reading fails or the router is closed.
+    pub async fn run<S: HCIReader, B: Deref<Target = S> + DerefMut>(
+        &self,
+        reader: &mut EventReader<B>,
+    ) -> Result<(), adapter::Error> {
+        while !self.is_closed() {
+            let event: EventPacket<Box<[u8]>> = reader.read_event().await?;
+            self.route(&event).await;
+        }
+        Ok(())
+    }
+    pub fn is_closed(&self) -> bool {
+        self.inner.lock(|routes| routes.closed)
+    }
+    /// Ends every receiver stream (after their queued events) and stops [`EventRouter::run`]
+    /// after the event it is reading.
+    pub fn close(&self) {
+        self.inner.lock(|routes| {
+            routes.closed = true;
+            for receiver in &mut routes.receivers {
+                if let Some(waker) = receiver.waker.take() {
+                    waker.wake();
+                }
+            }
+        });
+    }
+}
+/// Stream of the events matching one [`EventFilter`]. Unsubscribes when dropped.
+pub struct RouteReceiver {
+    id: u64,
+    inner: Arc<Inner>,
+}
+impl RouteReceiver {
+    /// Events dropped because the queue was full (only lossy receivers drop events).
+    pub fn dropped(&self) -> u64 {
+        let id = self.id;
+        self.inner.lock(|routes| {
+            routes
+                .receivers
+                .iter()
+                .find(|r| r.id == id)
+                .map_or(0, |r| r.dropped)
+        })
+    }
+    /// Takes the next queued event without waiting.
+    pub fn try_next(&mut self) -> Option<EventPacket<Box<[u8]>>> {
+        let id = self.id;
+        self.inner.lock(|routes| {
+            let event = routes
+                .receivers
+                .iter_mut()
+                .find(|r| r.id == id)?
+                .queue
+                .pop_front()?;
+            if let Some(waker) = routes.router_waker.take() {
+                waker.wake();
+            }
+            Some(event)
+        })
+    }
+}
+impl Stream for RouteReceiver {
+    type Item = EventPacket<Box<[u8]>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let id = self.id;
+        self.inner.lock(|routes| {
+            let closed = routes.closed;
+            let receiver = match routes.receivers.iter_mut().find(|r| r.id == id) {
+                Some(receiver) => receiver,
+                None => return Poll::Ready(None),
+            };
+            match receiver.queue.pop_front() {
+                Some(event) => {
+                    if let Some(waker) = routes.router_waker.take() {
+                        waker.wake();
+                    }
+                    Poll::Ready(Some(event))
+                }
+                None if closed => Poll::Ready(None),
+                None => {
+                    receiver.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+impl Drop for RouteReceiver {
+    fn drop(&mut self) {
+        let id = self.id;
+        self.inner.lock(|routes| {
+            routes.receivers.retain(|r| r.id != id);
+            if let Some(waker) = routes.router_waker.take() {
+                waker.wake();
+            }
+        });
+        hci_debug!("route {} removed", id);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::event::EventCode;
+    use crate::hci::le::MetaEventCode;
+    use crate::le::connection::ConnectionHandle;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use futures_util::task::{noop_waker_ref, waker, ArcWake};
+    use futures_util::FutureExt;
+
+    #[derive(Default)]
+    struct WakeCount(AtomicUsize);
+    impl ArcWake for WakeCount {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_bounded_routes() {
+        let router = EventRouter::new();
+        let mut completes =
+            router.subscribe(EventFilter::all().event_code(EventCode::CommandComplete), 1);
+        let mut reports = router.subscribe(
+            EventFilter::all().meta_event(MetaEventCode::AdvertisingReport),
+            4,
+        );
+        let complete = EventPacket::new(EventCode::CommandComplete, [0x01, 0x03, 0x0C, 0x00]);
+        let report = EventPacket::new(EventCode::LEMeta, [0x02, 0x00]);
+        let update = EventPacket::new(EventCode::LEMeta, [0x03, 0x00]);
+        assert_eq!(router.route(&complete).now_or_never(), Some(1));
+        assert_eq!(router.route(&report).now_or_never(), Some(1));
+        assert_eq!(router.route(&update).now_or_never(), Some(0));
+
+        // `completes` is full so the next one waits for room.
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut pending = Box::pin(router.route(&complete));
+        assert!(pending.poll_unpin(&mut cx).is_pending());
+        assert!(completes.try_next().is_some());
+        assert_eq!(pending.poll_unpin(&mut cx), Poll::Ready(1));
+
+        assert_eq!(
+            reports.try_next().map(|e| e.parameters.to_vec()),
+            Some(vec![0x02, 0x00])
+        );
+        drop(completes);
+        assert_eq!(router.receiver_count(), 1);
+        router.close();
+        assert!(matches!(
+            Pin::new(&mut reports).poll_next(&mut cx),
+            Poll::Ready(None)
+        ));
+    }
+
+    #[test]
+    fn test_saturated_receivers_dont_delay_others() {
+        let router = EventRouter::new();
+        // Never drained: a scanner that fell behind and a stuck command waiter.
+        let scan = router.subscribe_with(
+            EventFilter::all().meta_event(MetaEventCode::AdvertisingReport),
+            8,
+            OverflowPolicy::DropOldest,
+        );
+        let stuck = router.subscribe(EventFilter::all().event_code(EventCode::CommandComplete), 1);
+        let mut link = router.subscribe(
+            EventFilter::all().event_code(EventCode::NumberOfCompletedPackets),
+            4,
+        );
+        let mut all_completes =
+            router.subscribe(EventFilter::all().event_code(EventCode::CommandComplete), 4);
+        let report = EventPacket::new(EventCode::LEMeta, [0x02, 0x00]);
+        let completed = EventPacket::new(
+            EventCode::NumberOfCompletedPackets,
+            [0x01, 0x40, 0x00, 0x01, 0x00],
+        );
+
+        // Worst case number of polls between routing a link event and waking its receiver,
+        // under a flood of reports for the saturated scanner.
+        let wakes = Arc::new(WakeCount::default());
+        let link_waker = waker(wakes.clone());
+        let mut link_cx = Context::from_waker(&link_waker);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut worst_polls = 0;
+        for i in 0..10_000 {
+            if i % 10 != 0 {
+                assert!(router.route(&report).now_or_never().is_some());
+                continue;
+            }
+            assert!(Pin::new(&mut link).poll_next(&mut link_cx).is_pending());
+            let woken = wakes.0.load(Ordering::SeqCst);
+            let mut routing = Box::pin(router.route(&completed));
+            let mut polls = 0;
+            while wakes.0.load(Ordering::SeqCst) == woken {
+                polls += 1;
+                assert!(polls < 16, "link receiver starved");
+                let _ = routing.poll_unpin(&mut cx);
+            }
+            worst_polls = polls.max(worst_polls);
+            assert!(link.try_next().is_some());
+        }
+        assert_eq!(worst_polls, 1);
+        assert_eq!(scan.dropped(), 9_000 - 8);
+
+        // A full `Block` receiver only holds up routing, the others get the event right away.
+        let complete = EventPacket::new(EventCode::CommandComplete, [0x01, 0x03, 0x0C, 0x00]);
+        assert_eq!(router.route(&complete).now_or_never(), Some(2));
+        let mut pending = Box::pin(router.route(&complete));
+        assert!(pending.poll_unpin(&mut cx).is_pending());
+        assert!(all_completes.try_next().is_some());
+        assert!(all_completes.try_next().is_some());
+        drop(stuck);
+        assert_eq!(pending.poll_unpin(&mut cx), Poll::Ready(1));
+        assert!(all_completes.try_next().is_none());
+    }
+    #[test]
+    fn test_connection_routes() {
+        let router = EventRouter::new();
+        let mut link = router.subscribe(
+            EventFilter::all()
+                .event_code(EventCode::DisconnectionComplete)
+                .connection_handle(ConnectionHandle::new(0x0040)),
+            2,
+        );
+        let disconnect =
+            EventPacket::new(EventCode::DisconnectionComplete, [0x00, 0x40, 0x00, 0x13]);
+        let other_link =
+            EventPacket::new(EventCode::DisconnectionComplete, [0x00, 0x41, 0x00, 0x13]);
+        assert_eq!(router.route(&other_link).now_or_never(), Some(0));
+        assert_eq!(router.route(&disconnect).now_or_never(), Some(1));
+        assert_eq!(
+            link.try_next().map(|e| e.parameters.to_vec()),
+            Some(vec![0x00, 0x40, 0x00, 0x13])
+        );
+        assert!(link.try_next().is_none());
+    }
+}
@@ -0,0 +1,494 @@
+//! Cloneable, thread safe handles to an [`Adapter`]. [`CommandRunner::new`] moves the adapter
+//! into an [`AdapterDriver`] and returns the first handle. The driver is a future running the
+//! requests of every handle one after another, so commands from different tasks never interleave
+//! on the HCI stream. Run it on the thread owning the adapter (`block_on`, a `LocalSet`, ..).
+//!
+//! The adapter futures are local (`LocalBoxFuture`) and never leave the driver, so the handles
+//! and the futures they return are `Send + Sync` (as long as the commands and their returns are)
+//! even if the adapter isn't. They can be moved into `tokio::spawn` on the multi threaded
+//! scheduler.
+//!
+//! [`SharedGattClient`] shares the [`AttributeIndex`] of a connection between tasks and sends
+//! the ATT requests it builds over the connection's fixed ATT channel.
+use crate::hci::acl::{ACLPacket, PacketBoundary};
+use crate::hci::adapter;
+use crate::hci::adapters::{Adapter, UnrecognizedEventHandler};
+use crate::hci::command::Command;
+use crate::hci::status::{ReadClockReturn, WhichClock};
+use crate::hci::ErrorCode;
+use crate::le::att::attribute::Handle;
+use crate::le::connection::ConnectionHandle;
+use crate::le::gatt::client::{AttributeIndex, ClientError};
+use crate::le::l2cap::{BasicFrame, ATT_CID};
+use crate::sync::Mutex;
+use crate::LocalBoxFuture;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use futures_util::future;
+use futures_util::task::AtomicWaker;
+use std::sync::Arc;
+
+type Job<A, H> =
+    Box<dyn for<'a> FnOnce(&'a mut Adapter<A, H>) -> LocalBoxFuture<'a, ()> + Send + 'static>;
+struct Queue<A: adapter::Adapter, H: UnrecognizedEventHandler> {
+    jobs: VecDeque<Job<A, H>>,
+    handles: usize,
+    /// The driver was dropped.
+    closed: bool,
+}
+struct Shared<A: adapter::Adapter, H: UnrecognizedEventHandler> {
+    queue: Mutex<Queue<A, H>>,
+    driver: AtomicWaker,
+}
+/// Return value of one job. Dropping the sender without a value closes it.
+struct Reply<T> {
+    value: Mutex<Option<T>>,
+    closed: AtomicBool,
+    waker: AtomicWaker,
+}
+struct ReplySender<T>(Arc<Reply<T>>);
+impl<T> ReplySender<T> {
+    fn send(self, value: T) {
+        self.0.value.lock(|slot| *slot = Some(value));
+    }
+}
+impl<T> Drop for ReplySender<T> {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Release);
+        self.0.waker.wake();
+    }
+}
+impl<T> Reply<T> {
+    fn poll_reply(&self, cx: &mut Context<'_>) -> Poll<Result<T, adapter::Error>> {
+        self.waker.register(cx.waker());
+        let closed = self.closed.load(Ordering::Acquire);
+        match self.value.lock(Option::take) {
+            Some(value) => Poll::Ready(Ok(value)),
+            None if closed => Poll::Ready(Err(adapter::Error::ChannelClosed)),
+            None => Poll::Pending,
+        }
+    }
+}
+/// Handle for sending commands through an [`AdapterDriver`].
+pub struct CommandRunner<A: adapter::Adapter, H: UnrecognizedEventHandler> {
+    shared: Arc<Shared<A, H>>,
+}
+impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Clone for CommandRunner<A, H> {
+    fn clone(&self) -> Self {
+        self.shared.queue.lock(|queue| queue.handles += 1);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Drop for CommandRunner<A, H> {
+    fn drop(&mut self) {
+        if self.shared.queue.lock(|queue| {
+            queue.handles -= 1;
+            queue.handles == 0
+        }) {
+            self.shared.driver.wake();
+        }
+    }
+}
+impl<A: adapter::Adapter + 'static, H: UnrecognizedEventHandler + 'static> CommandRunner<A, H> {
+    /// Returns the first handle and the driver running the requests. The driver resolves to the
+    /// adapter once every handle is dropped.
+    pub fn new(adapter: Adapter<A, H>) -> (Self, AdapterDriver<A, H>) {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                jobs: VecDeque::new(),
+                handles: 1,
+                closed: false,
+            }),
+            driver: AtomicWaker::new(),
+        });
+        (
+            CommandRunner {
+                shared: shared.clone(),
+            },
+            AdapterDriver {
+                shared,
+                adapter: Some(adapter),
+                running: None,
+            },
+        )
+    }
+    /// Runs `f` with exclusive access to the adapter on the driver. Use it to run a sequence of
+    /// commands that must not be interleaved with commands from other handles.
+    /// # Errors
+    /// returns `adapter::Error::ChannelClosed` if the driver was dropped.
+    pub async fn run<R, F>(&self, f: F) -> Result<R, adapter::Error>
+    where
+        R: Send + 'static,
+        F: for<'a> FnOnce(&'a mut Adapter<A, H>) -> LocalBoxFuture<'a, R> + Send + 'static,
+    {
+        let reply = Arc::new(Reply {
+            value: Mutex::new(None),
+            closed: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        });
+        let sender = ReplySender(reply.clone());
+        let job: Job<A, H> = Box::new(move |adapter| {
+            Box::pin(async move {
+                let value = f(adapter).await;
+                sender.send(value);
+            })
+        });
+        // A job pushed after the driver was dropped is dropped right away (closing the reply).
+        let job = self.shared.queue.lock(|queue| {
+            if queue.closed {
+                Some(job)
+            } else {
+                queue.jobs.push_back(job);
+                None
+            }
+        });
+        drop(job);
+        self.shared.driver.wake();
+        future::poll_fn(|cx| reply.poll_reply(cx)).await
+    }
+    /// Send `cmd` and wait for its return, serialized with every other handle.
+    pub async fn hci_send_command<Cmd>(&self, cmd: Cmd) -> Result<Cmd::Return, adapter::Error>
+    where
+        Cmd: Command + Send + 'static,
+        Cmd::Return: Send + 'static,
+    {
+        self.run(move |adapter| Box::pin(adapter.hci_send_command(cmd)))
+            .await?
+    }
+    /// Handle for commands about one connection.
+    pub fn connection(&self, connection_handle: ConnectionHandle) -> SharedConnection<A, H> {
+        SharedConnection {
+            runner: self.clone(),
+            connection_handle,
+        }
+    }
+    /// Number of handles (including connection handles) sharing the adapter.
+    pub fn handle_count(&self) -> usize {
+        self.shared.queue.lock(|queue| queue.handles)
+    }
+}
+/// Runs the requests of the [`CommandRunner`] handles on the adapter. Resolves to the adapter
+/// once every handle is dropped. Dropping it fails the queued (and later) requests with
+/// `adapter::Error::ChannelClosed`.
+pub struct AdapterDriver<A: adapter::Adapter, H: UnrecognizedEventHandler> {
+    shared: Arc<Shared<A, H>>,
+    adapter: Option<Adapter<A, H>>,
+    /// The job being run, which owns the adapter until it is done.
+    running: Option<LocalBoxFuture<'static, Adapter<A, H>>>,
+}
+// The adapter is moved into (and out of) the boxed job, never pinned in place.
+impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Unpin for AdapterDriver<A, H> {}
+impl<A: adapter::Adapter + 'static, H: UnrecognizedEventHandler + 'static> Future
+    for AdapterDriver<A, H>
+{
+    type Output = Adapter<A, H>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            if let Some(running) = this.running.as_mut() {
+                match running.as_mut().poll(cx) {
+                    Poll::Ready(adapter) => {
+                        this.adapter = Some(adapter);
+                        this.running = None;
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            this.shared.driver.register(cx.waker());
+            let (job, handles) = this
+                .shared
+                .queue
+                .lock(|queue| (queue.jobs.pop_front(), queue.handles));
+            match job {
+                Some(job) => {
+                    let mut adapter = this.adapter.take().expect("no job is running");
+                    this.running = Some(Box::pin(async move {
+                        job(&mut adapter).await;
+                        adapter
+                    }));
+                }
+                None if handles == 0 => {
+                    return Poll::Ready(this.adapter.take().expect("driver polled after ready"))
+                }
+                None => return Poll::Pending,
+            }
+        }
+    }
+}
+impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Drop for AdapterDriver<A, H> {
+    fn drop(&mut self) {
+        let jobs = self.shared.queue.lock(|queue| {
+            queue.closed = true;
+            core::mem::take(&mut queue.jobs)
+        });
+        // Outside of the lock, dropping a job wakes its handle.
+        drop(jobs);
+    }
+}
+impl<A: adapter::Adapter, H: UnrecognizedEventHandler> core::fmt::Debug for AdapterDriver<A, H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AdapterDriver")
+            .field("running", &self.running.is_some())
+            .finish_non_exhaustive()
+    }
+}
+/// [`CommandRunner`] handle bound to one [`ConnectionHandle`].
+pub struct SharedConnection<A: adapter::Adapter, H: UnrecognizedEventHandler> {
+    runner: CommandRunner<A, H>,
+    connection_handle: ConnectionHandle,
+}
+impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Clone for SharedConnection<A, H> {
+    fn clone(&self) -> Self {
+        Self {
+            runner: self.runner.clone(),
+            connection_handle: self.connection_handle,
+        }
+    }
+}
+impl<A: adapter::Adapter + 'static, H: UnrecognizedEventHandler + 'static> SharedConnection<A, H> {
+    pub fn connection_handle(&self) -> ConnectionHandle {
+        self.connection_handle
+    }
+    pub fn runner(&self) -> &CommandRunner<A, H> {
+        &self.runner
+    }
+    /// See [`Adapter::disconnect`].
+    pub async fn disconnect(&self, reason: ErrorCode) -> Result<(), adapter::Error> {
+        let connection_handle = self.connection_handle;
+        self.runner
+            .run(move |adapter| Box::pin(adapter.disconnect(connection_handle, reason)))
+            .await?
+    }
+    /// Read the piconet clock of the connection. See [`Adapter::read_clock`].
+    pub async fn read_clock(&self) -> Result<ReadClockReturn, adapter::Error> {
+        let connection_handle = self.connection_handle;
+        self.runner
+            .run(move |adapter| {
+                Box::pin(adapter.read_clock(connection_handle, WhichClock::Piconet))
+            })
+            .await?
+    }
+    /// GATT client handle for the connection with an empty [`AttributeIndex`].
+    pub fn gatt_client(&self) -> SharedGattClient<A, H> {
+        SharedGattClient {
+            connection: self.clone(),
+            index: Arc::new(Mutex::new(AttributeIndex::new())),
+        }
+    }
+}
+/// Error of a [`SharedGattClient`] request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SharedGattError {
+    Client(ClientError),
+    Adapter(adapter::Error),
+}
+impl From<ClientError> for SharedGattError {
+    fn from(e: ClientError) -> Self {
+        SharedGattError::Client(e)
+    }
+}
+impl From<adapter::Error> for SharedGattError {
+    fn from(e: adapter::Error) -> Self {
+        SharedGattError::Adapter(e)
+    }
+}
+impl core::fmt::Display for SharedGattError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SharedGattError::Client(e) => core::fmt::Display::fmt(e, f),
+            SharedGattError::Adapter(e) => core::fmt::Display::fmt(e, f),
+        }
+    }
+}
+impl crate::error::Error for SharedGattError {}
+impl std::error::Error for SharedGattError {}
+/// GATT client of one connection. Clones share the [`AttributeIndex`], so discovery results
+/// added by one task are checked against by the others. Requests are sent unfragmented, so they
+/// have to fit in one ACL buffer (the default 23 byte ATT MTU always does). Responses arrive
+/// through the adapter's ACL stream like any other data.
+pub struct SharedGattClient<A: adapter::Adapter, H: UnrecognizedEventHandler> {
+    connection: SharedConnection<A, H>,
+    index: Arc<Mutex<AttributeIndex>>,
+}
+impl<A: adapter::Adapter, H: UnrecognizedEventHandler> Clone for SharedGattClient<A, H> {
+    fn clone(&self) -> Self {
+        Self {
+            connection: self.connection.clone(),
+            index: self.index.clone(),
+        }
+    }
+}
+impl<A: adapter::Adapter + 'static, H: UnrecognizedEventHandler + 'static> SharedGattClient<A, H> {
+    pub fn connection(&self) -> &SharedConnection<A, H> {
+        &self.connection
+    }
+    /// Snapshot of the shared index (cheap, the index is copy on write).
+    pub fn index(&self) -> AttributeIndex {
+        self.index.lock(|index| index.clone())
+    }
+    /// Updates the shared index, for example with discovery responses.
+    pub fn update_index<R>(&self, f: impl FnOnce(&mut AttributeIndex) -> R) -> R {
+        self.index.lock(f)
+    }
+    /// Sends `pdu` on the ATT channel of the connection.
+    pub async fn send_pdu(&self, pdu: Vec<u8>) -> Result<(), adapter::Error> {
+        let frame = BasicFrame::new(ATT_CID, pdu)
+            .pack()
+            .map_err(|_| adapter::Error::BadParameter)?;
+        let connection_handle = self.connection.connection_handle;
+        self.connection
+            .runner
+            .run(move |adapter| {
+                Box::pin(async move {
+                    let packet = ACLPacket::new(
+                        connection_handle,
+                        PacketBoundary::FirstNonFlushable,
+                        frame.as_slice(),
+                    );
+                    adapter.hci_send_acl(packet).await
+                })
+            })
+            .await?
+    }
+    /// Sends a Read Request for the characteristic value. See [`AttributeIndex::read_request`].
+    pub async fn read(&self, value_handle: Handle) -> Result<(), SharedGattError> {
+        let pdu = self.index().read_request(value_handle)?;
+        Ok(self.send_pdu(pdu).await?)
+    }
+    /// Sends a Write Request (or Write Command). See [`AttributeIndex::write_request`].
+    pub async fn write(
+        &self,
+        value_handle: Handle,
+        value: &[u8],
+        with_response: bool,
+    ) -> Result<(), SharedGattError> {
+        let pdu = self
+            .index()
+            .write_request(value_handle, value, with_response)?;
+        Ok(self.send_pdu(pdu).await?)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::adapters::DummyUnrecognizedEventHandler;
+    use crate::hci::baseband::Reset;
+    use crate::hci::io::HCIPort;
+    use crate::le::gatt::client::CharacteristicOperation;
+    use crate::testing::block_on;
+    use crate::testing::mock::{command_complete, MockTransport};
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync + Clone>() {}
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn test_handles_on_threads() {
+        type Runner = CommandRunner<HCIPort<MockTransport>, DummyUnrecognizedEventHandler>;
+        assert_send_sync::<Runner>();
+        assert_send_sync::<SharedConnection<HCIPort<MockTransport>, DummyUnrecognizedEventHandler>>(
+        );
+        assert_send_sync::<SharedGattClient<HCIPort<MockTransport>, DummyUnrecognizedEventHandler>>(
+        );
+        // The mock transport isn't `Send`, it stays on this thread with the driver.
+        let (transport, handle) = MockTransport::new();
+        let complete = command_complete(Reset::opcode(), &[0x00]);
+        for _ in 0..4 {
+            handle.expect_command(&Reset {}, &[&complete]);
+        }
+        let (runner, driver): (Runner, _) =
+            CommandRunner::new(Adapter::new(HCIPort::new(transport)));
+        assert_send(&runner.hci_send_command(Reset {}));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let runner = runner.clone();
+                thread::spawn(move || {
+                    block_on(runner.hci_send_command(Reset {}))
+                        .unwrap()
+                        .params
+                        .status
+                })
+            })
+            .collect();
+        drop(runner);
+        // Resolves once every thread dropped its handles.
+        let _adapter = block_on(driver);
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), ErrorCode::Ok);
+        }
+        handle.assert_done();
+    }
+    #[test]
+    fn test_driver_dropped() {
+        let (transport, _handle) = MockTransport::new();
+        let (runner, driver) = CommandRunner::new(Adapter::new(HCIPort::new(transport)));
+        let connection = runner.connection(ConnectionHandle::new(1));
+        assert_eq!(runner.handle_count(), 2);
+        drop(driver);
+        assert_eq!(
+            block_on(
+                connection.disconnect(ErrorCode::OtherEndTerminatedConnectionUserEndedConnection)
+            ),
+            Err(adapter::Error::ChannelClosed)
+        );
+    }
+    #[test]
+    fn test_gatt_client_tasks() {
+        let (transport, handle) = MockTransport::new();
+        // Write Command to 0x0012 then Read Request of 0x0014 on connection 0x0040.
+        handle.expect(
+            &[
+                0x02, 0x40, 0x00, 0x08, 0x00, 0x04, 0x00, 0x04, 0x00, 0x52, 0x12, 0x00, 0x01,
+            ],
+            &[],
+        );
+        handle.expect(
+            &[
+                0x02, 0x40, 0x00, 0x07, 0x00, 0x03, 0x00, 0x04, 0x00, 0x0A, 0x14, 0x00,
+            ],
+            &[],
+        );
+        let (runner, driver) = CommandRunner::new(Adapter::new(HCIPort::new(transport)));
+        let client = runner.connection(ConnectionHandle::new(0x40)).gatt_client();
+        drop(runner);
+        let other = client.clone();
+        // Discovery by one handle is seen by the other.
+        client
+            .update_index(|index| {
+                index.add_characteristics_from_response(&[
+                    7, 0x11, 0x00, 0x04, 0x12, 0x00, 0x19, 0x2A, 0x13, 0x00, 0x02, 0x14, 0x00,
+                    0x29, 0x2A,
+                ])
+            })
+            .unwrap();
+        assert_eq!(
+            other
+                .index()
+                .check(Handle::new(0x14), CharacteristicOperation::Read)
+                .map(|c| c.declaration),
+            Ok(Handle::new(0x13))
+        );
+        let writer = async move {
+            let done = client.write(Handle::new(0x12), &[0x01], false).await;
+            // Not writable with a response.
+            let refused = client.write(Handle::new(0x12), &[0x01], true).await;
+            (done, refused)
+        };
+        let reader = async move { other.read(Handle::new(0x14)).await };
+        let (_adapter, (done, refused), read) = block_on(future::join3(driver, writer, reader));
+        assert_eq!(done, Ok(()));
+        assert!(matches!(
+            refused,
+            Err(SharedGattError::Client(ClientError::NotPermitted { .. }))
+        ));
+        assert_eq!(read, Ok(()));
+        handle.assert_done();
+    }
+}
@@ -12,6 +12,9 @@ pub enum ControllerBasebandOpcode {
     ReadPIN = 0x0009,
     WritePIN = 0x000A,
     ReadStoredLinkKey = 0x000D,
+    WriteLocalName = 0x0013,
+    WriteScanEnable = 0x001A,
+    WriteClassOfDevice = 0x0024,
 }
 impl From<ControllerBasebandOpcode> for u16 {
     fn from(opcode: ControllerBasebandOpcode) -> Self {
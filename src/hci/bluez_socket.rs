@@ -1,5 +1,5 @@
 //! BlueZ socket layer. Interacts with the BlueZ driver over socket AF_BLUETOOTH.
-use crate::hci::stream::{Filter, HCIFilterable, HCIReader, HCIWriter, FILTER_LEN};
+use crate::hci::stream::{Filter, HCIFilterable, HCIReader, HCIWriter, Stream, FILTER_LEN};
 use crate::BTAddress;
 use core::convert::TryFrom;
 use core::fmt::{Display, Formatter};
@@ -10,9 +10,11 @@ use std::os::unix::{
     net::UnixStream,
 };
 
-use crate::error::IOError;
+use crate::error::{BusyOwner, IOError, Rfkill};
 use crate::hci::adapter::Error;
+use crate::hci::device_id::{ControllerInfo, DeviceId, TransportKind};
 use crate::hci::packet::PacketType;
+use crate::hci::rfkill;
 use futures_util::task::{Context, Poll};
 use std::sync::Mutex;
 
@@ -134,10 +136,14 @@ pub fn handle_libc_error(i: RawFd) -> Result<i32, IOError> {
         Ok(i)
     }
 }
+/// `EBUSY` means the kernel host stack has the adapter (it is up, the user channel needs it down)
+/// and `ERFKILL` that the radio is blocked (see [`rfkill::controller_block`] for which block).
 pub fn handle_errno(err: i32) -> IOError {
     match err {
-        -1 | 1 => IOError::PermissionDenied,
-        -16 | 16 => IOError::Refused,
+        -1 | 1 | -13 | 13 => IOError::PermissionDenied,
+        -11 | 11 => IOError::WouldBlock,
+        -16 | 16 => IOError::Busy(BusyOwner::HostStack),
+        -132 | 132 => IOError::Blocked(Rfkill::Unknown),
         e => IOError::Code(e),
     }
 }
@@ -154,7 +160,8 @@ pub enum HCISocketOption {
 const SOL_HCI: i32 = 0;
 impl HCISocket {
     /// Creates an `HCISocket` based on a `libc` file_descriptor (`i32`). Returns an error if could
-    /// not bind to the `adapter_id`.
+    /// not bind to the `adapter_id`. `HCIChannel::User` only binds if the adapter is down (see
+    /// [`Manager::get_user_channel_socket`]).
     pub fn new_channel(adapter_id: AdapterID, channel: HCIChannel) -> Result<HCISocket, IOError> {
         let adapter_fd = handle_libc_error(unsafe {
             libc::socket(
@@ -177,9 +184,13 @@ impl HCISocket {
         })?;
         let stream = unsafe { UnixStream::from_raw_fd(adapter_fd) };
         let out = HCISocket(stream);
-        let mut filter = Filter::all_events();
-        filter.enable_type(PacketType::Command);
-        out.set_socket_filter(&filter)?;
+        // Only the raw channel has a socket filter. The user channel passes every packet through
+        // (with its H4 packet type byte) so `Stream::read_event` skips the data packets itself.
+        if channel == HCIChannel::Raw {
+            let mut filter = Filter::all_events();
+            filter.enable_type(PacketType::Command);
+            out.set_socket_filter(&filter)?;
+        }
         Ok(out)
     }
     pub unsafe fn new_unchecked(stream: UnixStream) -> HCISocket {
@@ -235,6 +246,7 @@ fn hci_to_socket_error(err: nix::Error) -> IOError {
 #[derive(Debug)]
 pub struct Manager {
     control_fd: Mutex<i32>,
+    unblock_rfkill: bool,
 }
 impl Manager {
     pub fn new() -> Result<Manager, IOError> {
@@ -246,8 +258,14 @@ impl Manager {
                     BTProtocol::HCI.into(),
                 )
             })?),
+            unblock_rfkill: false,
         })
     }
+    /// Lift soft rfkill blocks before opening sockets (see [`rfkill::prepare`]). Needs write
+    /// access to `/dev/rfkill`.
+    pub fn set_unblock_rfkill(&mut self, unblock: bool) {
+        self.unblock_rfkill = unblock;
+    }
     pub fn device_up(&self, adapter_id: AdapterID) -> Result<(), IOError> {
         let control_lock = self
             .control_fd
@@ -274,7 +292,13 @@ impl Manager {
     fn raw_device_up(ctl_fd: i32, adapter_id: AdapterID) -> Result<(), IOError> {
         unsafe {
             ioctl::hci_device_up(ctl_fd, adapter_id.0 as nix::sys::ioctl::ioctl_param_type)
-                .map_err(hci_to_socket_error)?;
+                .map_err(|e| match hci_to_socket_error(e) {
+                    IOError::Blocked(Rfkill::Unknown) => {
+                        let block = rfkill::controller_block(adapter_id.0);
+                        IOError::Blocked(block.ok().flatten().unwrap_or(Rfkill::Unknown))
+                    }
+                    e => e,
+                })?;
         }
         Ok(())
     }
@@ -284,25 +308,120 @@ impl Manager {
             .lock()
             .expect("mutexs only fail when poisoned");
         let ctl_fd = *control_lock.deref();
+        rfkill::prepare(adapter_id.0, self.unblock_rfkill)?;
         Self::raw_device_down(ctl_fd, adapter_id)?;
         Self::raw_device_up(ctl_fd, adapter_id)?;
         HCISocket::new_channel(adapter_id, HCIChannel::Raw)
     }
+    /// Takes exclusive control of the controller with `HCI_CHANNEL_USER`, bypassing the kernel
+    /// host stack. The adapter is brought down first (the kernel requires it) and stays down for
+    /// the rest of the system until the socket is closed and [`Manager::device_up`] is called.
+    pub fn get_user_channel_socket(&self, adapter_id: AdapterID) -> Result<HCISocket, IOError> {
+        let control_lock = self
+            .control_fd
+            .lock()
+            .expect("mutexs only fail when poisoned");
+        rfkill::prepare(adapter_id.0, self.unblock_rfkill)?;
+        Self::raw_device_down(*control_lock.deref(), adapter_id)?;
+        HCISocket::new_channel(adapter_id, HCIChannel::User)
+    }
+}
+/// BlueZ adapters (`/sys/class/bluetooth/hciN`). Empty if the kernel has no Bluetooth support.
+pub fn controllers() -> Result<Vec<ControllerInfo>, IOError> {
+    let entries = match std::fs::read_dir("/sys/class/bluetooth") {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut controllers = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name();
+        // Connections show up as `hciN:HANDLE`.
+        let index = match name.to_str().and_then(|name| name.strip_prefix("hci")) {
+            Some(index) => match index.parse() {
+                Ok(index) => index,
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+        controllers.push(ControllerInfo {
+            id: DeviceId::Index(index),
+            transport: TransportKind::BlueZ,
+            index,
+            address: None,
+            name: None,
+            usb: None,
+        });
+    }
+    controllers.sort();
+    Ok(controllers)
 }
-
 impl TryFrom<HCISocket> for AsyncHCISocket {
     type Error = std::io::Error;
 
     /// Returns `std::io::Error` if it can't bind the `UnixStream` to the tokio Event
     /// loop. Usually safe to `.unwrap()/.expect()` unless bad file descriptor.
     fn try_from(socket: HCISocket) -> Result<Self, Self::Error> {
-        Ok(AsyncHCISocket(tokio::net::UnixStream::from_std(
-            socket.into(),
-        )?))
+        let stream: UnixStream = socket.into();
+        // The tokio reactor expects non blocking sockets.
+        stream.set_nonblocking(true)?;
+        Ok(AsyncHCISocket(tokio::net::UnixStream::from_std(stream)?))
     }
 }
+/// HCI [`Stream`] over an [`AsyncHCISocket`].
+pub type HCISocketStream = Stream<AsyncHCISocket, Box<AsyncHCISocket>>;
 #[derive(Debug)]
 pub struct AsyncHCISocket(pub tokio::net::UnixStream);
+impl AsyncHCISocket {
+    /// Opens (after resetting it with a device down/up) the raw HCI socket of `adapter_id`.
+    /// Needs `CAP_NET_RAW` (or root).
+    pub fn open(adapter_id: AdapterID) -> Result<AsyncHCISocket, IOError> {
+        let socket = Manager::new()?.get_adapter_socket(adapter_id)?;
+        AsyncHCISocket::try_from(socket).map_err(IOError::from)
+    }
+    /// [`AsyncHCISocket::open`] for [`DeviceId::Index`]. BlueZ only knows the other IDs once
+    /// the adapter is up, so they give `IOError::InvalidArgument`.
+    pub fn open_device(id: &DeviceId) -> Result<AsyncHCISocket, IOError> {
+        match id {
+            DeviceId::Index(index) => Self::open(AdapterID(*index)),
+            _ => Err(IOError::InvalidArgument),
+        }
+    }
+    /// Opens `adapter_id` with `HCI_CHANNEL_USER`. See [`Manager::get_user_channel_socket`].
+    pub fn open_user_channel(adapter_id: AdapterID) -> Result<AsyncHCISocket, IOError> {
+        let socket = Manager::new()?.get_user_channel_socket(adapter_id)?;
+        AsyncHCISocket::try_from(socket).map_err(IOError::from)
+    }
+    pub fn into_stream(self) -> HCISocketStream {
+        Stream::new(Box::pin(self))
+    }
+}
+impl tokio::io::AsyncRead for AsyncHCISocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+impl tokio::io::AsyncWrite for AsyncHCISocket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
 impl HCIFilterable for AsyncHCISocket {
     fn set_filter(self: Pin<&mut Self>, filter: &Filter) -> Result<(), Error> {
         HCISocket::set_filter_raw(self.0.as_raw_fd(), filter).map_err(Error::IOError)
@@ -343,3 +462,47 @@ impl HCIWriter for AsyncHCISocket {
             .map_err(|e| Error::IOError(e.into()))
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::adapters::Adapter;
+    use crate::hci::baseband::Reset;
+    use crate::hci::command::Command;
+    use crate::testing::mock::command_complete;
+    use std::io::{Read, Write};
+
+    /// Connected `SOCK_SEQPACKET` pair, which keeps packet boundaries like HCI sockets do.
+    fn packet_pair() -> (UnixStream, UnixStream) {
+        let mut fds = [0; 2];
+        let r =
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) };
+        assert_eq!(r, 0, "socketpair failed");
+        unsafe {
+            (
+                UnixStream::from_raw_fd(fds[0]),
+                UnixStream::from_raw_fd(fds[1]),
+            )
+        }
+    }
+    #[test]
+    fn test_async_socket() {
+        let (socket, mut controller) = packet_pair();
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_io()
+            .build()
+            .unwrap();
+        let mut adapter = runtime.enter(|| {
+            socket.set_nonblocking(true).unwrap();
+            let socket = AsyncHCISocket(tokio::net::UnixStream::from_std(socket).unwrap());
+            Adapter::new(socket.into_stream())
+        });
+        controller
+            .write_all(&command_complete(Reset::opcode(), &[0x00]))
+            .unwrap();
+        runtime.block_on(adapter.reset()).unwrap();
+        let mut written = [0_u8; 8];
+        let len = controller.read(&mut written).unwrap();
+        assert_eq!(written[..len], [0x01, 0x03, 0x0C, 0x00]);
+    }
+}
@@ -0,0 +1,234 @@
+//! BR/EDR discoverability commands for dual mode controllers ([`WriteScanEnable`],
+//! [`WriteClassOfDevice`] and [`WriteLocalName`]). Enabled with the `bredr` feature.
+use crate::hci::baseband::ControllerBasebandOpcode;
+use crate::hci::definitions::HCIField;
+use crate::hci::event::{CommandComplete, StatusReturn};
+use crate::ConversionError;
+use core::convert::TryFrom;
+
+/// Which BR/EDR scans the controller runs. Inquiry scan makes the device discoverable and page
+/// scan makes it connectable.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ScanEnable {
+    NoScans = 0x00,
+    InquiryScan = 0x01,
+    PageScan = 0x02,
+    InquiryAndPageScan = 0x03,
+}
+impl ScanEnable {
+    pub fn new(discoverable: bool, connectable: bool) -> ScanEnable {
+        match (discoverable, connectable) {
+            (false, false) => ScanEnable::NoScans,
+            (true, false) => ScanEnable::InquiryScan,
+            (false, true) => ScanEnable::PageScan,
+            (true, true) => ScanEnable::InquiryAndPageScan,
+        }
+    }
+    pub fn discoverable(self) -> bool {
+        matches!(
+            self,
+            ScanEnable::InquiryScan | ScanEnable::InquiryAndPageScan
+        )
+    }
+    pub fn connectable(self) -> bool {
+        matches!(self, ScanEnable::PageScan | ScanEnable::InquiryAndPageScan)
+    }
+}
+impl From<ScanEnable> for u8 {
+    fn from(s: ScanEnable) -> Self {
+        s as u8
+    }
+}
+impl TryFrom<u8> for ScanEnable {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(ScanEnable::NoScans),
+            0x01 => Ok(ScanEnable::InquiryScan),
+            0x02 => Ok(ScanEnable::PageScan),
+            0x03 => Ok(ScanEnable::InquiryAndPageScan),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+crate::hci_field_u8!(ScanEnable);
+/// 24-bit Class of Device (Assigned Numbers, Baseband). Bits 2-7 are the minor device class,
+/// bits 8-12 the major device class and bits 13-23 the major service classes.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct ClassOfDevice(u32);
+impl ClassOfDevice {
+    pub const BYTE_LEN: usize = 3;
+    pub const MAX: u32 = 0x00FF_FFFF;
+    /// # Panics
+    /// Panics if `class > ClassOfDevice::MAX`.
+    pub fn new(class: u32) -> ClassOfDevice {
+        ClassOfDevice::new_checked(class).expect("class of device is only 24 bits")
+    }
+    pub fn new_checked(class: u32) -> Option<ClassOfDevice> {
+        if class <= Self::MAX {
+            Some(ClassOfDevice(class))
+        } else {
+            None
+        }
+    }
+    pub fn from_parts(major_service_classes: u16, major_device_class: u8, minor: u8) -> Self {
+        ClassOfDevice(
+            (u32::from(major_service_classes & 0x07FF) << 13)
+                | (u32::from(major_device_class & 0x1F) << 8)
+                | (u32::from(minor & 0x3F) << 2),
+        )
+    }
+    pub fn major_service_classes(self) -> u16 {
+        u16::try_from(self.0 >> 13).expect("only 11 bits")
+    }
+    pub fn major_device_class(self) -> u8 {
+        u8::try_from((self.0 >> 8) & 0x1F).expect("only 5 bits")
+    }
+    pub fn minor_device_class(self) -> u8 {
+        u8::try_from((self.0 >> 2) & 0x3F).expect("only 6 bits")
+    }
+}
+impl From<ClassOfDevice> for u32 {
+    fn from(c: ClassOfDevice) -> Self {
+        c.0
+    }
+}
+impl HCIField for ClassOfDevice {
+    const BYTE_LEN: usize = ClassOfDevice::BYTE_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.0.to_le_bytes()[..Self::BYTE_LEN]);
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        Some(ClassOfDevice(u32::from_le_bytes([
+            buf[0], buf[1], buf[2], 0,
+        ])))
+    }
+}
+/// UTF-8 device name, null padded to [`LocalName::MAX_LEN`] bytes.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct LocalName([u8; LocalName::MAX_LEN]);
+impl LocalName {
+    pub const MAX_LEN: usize = 248;
+    /// Returns `None` if `name` is longer than [`LocalName::MAX_LEN`] bytes.
+    pub fn new(name: &str) -> Option<LocalName> {
+        if name.len() > Self::MAX_LEN {
+            return None;
+        }
+        let mut buf = [0_u8; Self::MAX_LEN];
+        buf[..name.len()].copy_from_slice(name.as_bytes());
+        Some(LocalName(buf))
+    }
+    /// Name bytes without the null padding.
+    pub fn as_bytes(&self) -> &[u8] {
+        let len = self.0.iter().position(|&b| b == 0).unwrap_or(Self::MAX_LEN);
+        &self.0[..len]
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        core::str::from_utf8(self.as_bytes()).ok()
+    }
+}
+impl Default for LocalName {
+    fn default() -> Self {
+        LocalName([0_u8; Self::MAX_LEN])
+    }
+}
+impl HCIField for LocalName {
+    const BYTE_LEN: usize = LocalName::MAX_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.0[..]);
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        let mut name = [0_u8; Self::MAX_LEN];
+        name.copy_from_slice(buf);
+        Some(LocalName(name))
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct WriteScanEnable: ControllerBasebandOpcode::WriteScanEnable
+        => CommandComplete<StatusReturn> {
+        pub scan_enable: ScanEnable,
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct WriteClassOfDevice: ControllerBasebandOpcode::WriteClassOfDevice
+        => CommandComplete<StatusReturn> {
+        pub class_of_device: ClassOfDevice,
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct WriteLocalName: ControllerBasebandOpcode::WriteLocalName
+        => CommandComplete<StatusReturn> {
+        pub local_name: LocalName,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::adapter;
+    use crate::hci::adapters::Adapter;
+    use crate::hci::command::Command;
+    use crate::hci::io::HCIPort;
+    use crate::testing::mock::{command_complete, MockTransport};
+    use futures_util::FutureExt;
+
+    #[test]
+    fn test_bredr_commands() {
+        let scan = WriteScanEnable {
+            scan_enable: ScanEnable::new(true, false),
+        };
+        let mut buf = [0_u8; 1];
+        scan.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x01]);
+        assert_eq!(WriteScanEnable::unpack_from(&buf), Ok(scan));
+        assert!(WriteScanEnable::unpack_from(&[0x04]).is_err());
+
+        // Phone, smartphone with the telephony and object transfer services.
+        let class = ClassOfDevice::from_parts(0x0210, 0x02, 0x03);
+        assert_eq!(u32::from(class), 0x42_020C);
+        assert_eq!(class.major_service_classes(), 0x0210);
+        assert_eq!(class.major_device_class(), 0x02);
+        assert_eq!(class.minor_device_class(), 0x03);
+        assert!(ClassOfDevice::new_checked(0x0100_0000).is_none());
+        let write = WriteClassOfDevice {
+            class_of_device: class,
+        };
+        let mut buf = [0_u8; ClassOfDevice::BYTE_LEN];
+        write.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x0C, 0x02, 0x42]);
+        assert_eq!(WriteClassOfDevice::unpack_from(&buf), Ok(write));
+
+        let name = WriteLocalName {
+            local_name: LocalName::new("btle").unwrap(),
+        };
+        let mut buf = [0_u8; LocalName::MAX_LEN];
+        name.pack_into(&mut buf).unwrap();
+        assert_eq!(buf[..5], *b"btle\0");
+        let unpacked = WriteLocalName::unpack_from(&buf).unwrap();
+        assert_eq!(unpacked.local_name.as_str(), Some("btle"));
+    }
+    #[test]
+    fn test_write_local_name() {
+        let (transport, handle) = MockTransport::new();
+        let mut adapter = Adapter::new(HCIPort::new(transport));
+        let long = "n".repeat(LocalName::MAX_LEN + 1);
+        assert_eq!(
+            adapter.write_local_name(&long).now_or_never().unwrap(),
+            Err(adapter::Error::BadParameter)
+        );
+        handle.expect_command(
+            &WriteLocalName {
+                local_name: LocalName::new("btle").unwrap(),
+            },
+            &[&command_complete(WriteLocalName::opcode(), &[0x00])],
+        );
+        assert_eq!(
+            adapter.write_local_name("btle").now_or_never().unwrap(),
+            Ok(())
+        );
+        handle.assert_done();
+    }
+}
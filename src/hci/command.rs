@@ -1,12 +1,19 @@
 //! HCI Command and command utilities.
-use crate::bytes::Storage;
+use crate::bytes::{HexDump, Storage};
 use crate::hci::event::{Event, EventPacket, ReturnEvent};
 use crate::hci::packet::{PacketType, RawPacket};
 use crate::hci::{Opcode, OPCODE_LEN};
 use crate::PackError;
 use core::convert::TryFrom;
-use core::convert::TryInto;
 
+/// Max length of HCI command parameters (the length field has 8 bits).
+pub const MAX_COMMAND_PARAMETERS_LEN: usize = 0xFF;
+/// Parameter length field for `len` bytes of parameters.
+/// # Errors
+/// returns `PackError::ParametersTooLong` if `len > MAX_COMMAND_PARAMETERS_LEN`.
+pub fn parameters_len(len: usize) -> Result<u8, PackError> {
+    u8::try_from(len).map_err(|_| PackError::ParametersTooLong { len })
+}
 /// Raw HCI Command Packet. Stores command [`Opcode`] and `parameters` (byte buffer).
 /// [`Opcode`]: crate::hci::Opcode;
 pub struct CommandPacket<Buf> {
@@ -20,31 +27,40 @@ impl<Buf: AsRef<[u8]>> CommandPacket<Buf> {
             parameters: self.parameters.as_ref(),
         }
     }
-    pub fn to_raw_packet<NewStorage: Storage<u8>>(&self) -> RawPacket<NewStorage> {
-        let para_len = self.parameters.as_ref().len();
-        let len = para_len + 1 + OPCODE_LEN;
+    /// # Errors
+    /// returns `PackError::ParametersTooLong` if the parameters don't fit in a command packet.
+    pub fn to_raw_packet<NewStorage: Storage<u8>>(
+        &self,
+    ) -> Result<RawPacket<NewStorage>, PackError> {
+        let para_len = parameters_len(self.parameters.as_ref().len())?;
+        let len = usize::from(para_len) + 1 + OPCODE_LEN;
         let mut buf = NewStorage::with_size(len);
         buf.as_mut()[OPCODE_LEN + 1..].copy_from_slice(self.parameters.as_ref());
-        self.opcode
-            .pack(&mut buf.as_mut()[..OPCODE_LEN])
-            .expect("given a hardcoded length buf");
-        buf.as_mut()[2] = para_len.try_into().expect("len bigger than an u8");
-        RawPacket {
+        self.opcode.pack(&mut buf.as_mut()[..OPCODE_LEN])?;
+        buf.as_mut()[2] = para_len;
+        Ok(RawPacket {
             packet_type: PacketType::Command,
             buf,
-        }
+        })
     }
-    pub fn pack_as_raw_packet<NewStorage: Storage<u8>>(&self) -> NewStorage {
-        let para_len = self.parameters.as_ref().len();
-        let len = para_len + OPCODE_LEN + 1 + 1;
+    /// Packs the H4 framed packet (packet type first).
+    /// # Errors
+    /// returns `PackError::ParametersTooLong` if the parameters don't fit in a command packet.
+    pub fn pack_as_raw_packet<NewStorage: Storage<u8>>(&self) -> Result<NewStorage, PackError> {
+        let para_len = parameters_len(self.parameters.as_ref().len())?;
+        let len = usize::from(para_len) + OPCODE_LEN + 1 + 1;
+        if NewStorage::max_len() < len {
+            return Err(PackError::BadLength {
+                expected: len,
+                got: NewStorage::max_len(),
+            });
+        }
         let mut out = NewStorage::with_size(len);
         out.as_mut()[0] = PacketType::Command.into();
-        self.opcode
-            .pack(&mut out.as_mut()[1..1 + OPCODE_LEN])
-            .expect("given a hardcoded length buf");
-        out.as_mut()[1 + OPCODE_LEN] = para_len.try_into().expect("len bigger than an u8");
+        self.opcode.pack(&mut out.as_mut()[1..1 + OPCODE_LEN])?;
+        out.as_mut()[1 + OPCODE_LEN] = para_len;
         out.as_mut()[1 + 1 + OPCODE_LEN..].copy_from_slice(self.parameters.as_ref());
-        out
+        Ok(out)
     }
 }
 impl<Storage: AsRef<[u8]>> core::fmt::Debug for CommandPacket<Storage> {
@@ -55,6 +71,19 @@ impl<Storage: AsRef<[u8]>> core::fmt::Debug for CommandPacket<Storage> {
             .finish()
     }
 }
+/// `Opcode [len] parameters` with the parameters hex dumped (see [`HexDump`]).
+impl<Storage: AsRef<[u8]>> core::fmt::Display for CommandPacket<Storage> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let parameters = self.parameters.as_ref();
+        write!(
+            f,
+            "{} [{}] {}",
+            self.opcode,
+            parameters.len(),
+            HexDump::new(parameters)
+        )
+    }
+}
 pub struct CommandHeader {
     pub opcode: Opcode,
     pub len: u8,
@@ -72,20 +101,24 @@ pub trait Command {
     /// # Errors
     ///
     fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError>;
+    /// Packs opcode, parameter length and parameters. Returns the packed length.
+    /// # Errors
+    /// returns `PackError::ParametersTooLong` if the parameters don't fit in a command packet or
+    /// `PackError::BadLength` if `buf` is shorter than [`Command::full_len`].
     fn pack_full(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        let para_len = parameters_len(self.byte_len())?;
         let full = self.full_len();
+        PackError::atleast_length(full, buf)?;
         // Trim buf to correct length
         let buf = &mut buf[..full];
-        PackError::expect_length(full, buf)?;
         self.pack_into(&mut buf[3..full])?;
         Self::opcode().pack(&mut buf[..OPCODE_LEN])?;
-        buf[2] = u8::try_from(self.byte_len())
-            .ok()
-            .ok_or(PackError::InvalidFields)?;
+        buf[2] = para_len;
         Ok(full)
     }
     fn pack_command_packet<S: Storage<u8>>(&self) -> Result<CommandPacket<S>, PackError> {
         let len = self.byte_len();
+        parameters_len(len)?;
         let mut buf = S::with_size(len);
         self.pack_into(buf.as_mut())?;
         Ok(CommandPacket {
@@ -111,6 +144,7 @@ pub trait Command {
     }
 
     fn packet_pack_into(&self, buf: &mut [u8]) -> Result<usize, PackError> {
+        PackError::atleast_length(1, buf)?;
         let len = self.pack_full(&mut buf[1..])?;
         buf[0] = PacketType::Command.into();
         Ok(len + 1)
@@ -126,3 +160,56 @@ pub trait Command {
         Ok(None)
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::event::{CommandComplete, StatusReturn};
+    use crate::hci::le::LEControllerOpcode;
+
+    struct Oversized(usize);
+    impl Command for Oversized {
+        type Return = CommandComplete<StatusReturn>;
+
+        fn opcode() -> Opcode {
+            LEControllerOpcode::SetEventMask.into()
+        }
+
+        fn byte_len(&self) -> usize {
+            self.0
+        }
+
+        fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+            PackError::expect_length(self.0, buf)
+        }
+
+        fn unpack_from(buf: &[u8]) -> Result<Self, PackError> {
+            Ok(Oversized(buf.len()))
+        }
+    }
+
+    #[test]
+    fn test_parameters_too_long() {
+        let mut buf = [0_u8; 300];
+        let too_long = Err(PackError::ParametersTooLong { len: 256 });
+        assert_eq!(Oversized(256).pack_full(&mut buf), too_long);
+        assert!(matches!(
+            Oversized(256).pack_command_packet::<Box<[u8]>>(),
+            Err(PackError::ParametersTooLong { len: 256 })
+        ));
+        let packet = CommandPacket {
+            opcode: Oversized::opcode(),
+            parameters: &buf[..256],
+        };
+        assert!(packet.pack_as_raw_packet::<Box<[u8]>>().is_err());
+        // Short buffers are an error, not a panic.
+        assert_eq!(
+            Oversized(10).pack_full(&mut buf[..5]),
+            Err(PackError::BadLength {
+                expected: 13,
+                got: 5
+            })
+        );
+        assert_eq!(Oversized(255).pack_full(&mut buf), Ok(258));
+        assert_eq!(buf[2], 0xFF);
+    }
+}
@@ -0,0 +1,418 @@
+//! Declarative HCI command/return definitions. [`hci_command!`] and [`hci_return!`] generate the
+//! parameter struct, `OPCODE`, `BYTE_LEN` and the packers from a field list so fixed length
+//! commands don't have to be written out by hand. Every field type has to implement [`HCIField`].
+//!
+//! ```ignore
+//! hci_command! {
+//!     #[derive(Copy, Clone, Debug)]
+//!     pub struct ReadClock: StatusParametersOpcode::ReadClock => CommandComplete<ReadClockReturn> {
+//!         pub connection_handle: ConnectionHandle,
+//!         pub which_clock: WhichClock,
+//!     }
+//! }
+//! hci_return! {
+//!     #[derive(Copy, Clone, Debug)]
+//!     pub struct ReadClockReturn {
+//!         pub connection_handle: ConnectionHandle,
+//!         pub clock: ControllerClock,
+//!         pub accuracy: u16,
+//!     }
+//! }
+//! ```
+//!
+//! Returns with variable length parts (counted lists, tails running to the end of the event)
+//! implement `ReturnParameters` by hand with a [`FieldReader`] and a [`FieldWriter`].
+use crate::bytes::ToFromBytesEndian;
+use crate::hci::ErrorCode;
+use crate::le::connection::{ConnectionHandle, ConnectionParameters};
+use crate::{BTAddress, PackError};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// Fixed length HCI parameter. `buf` is always exactly `BYTE_LEN` long.
+pub trait HCIField: Sized {
+    const BYTE_LEN: usize;
+    fn pack_field(&self, buf: &mut [u8]);
+    /// Returns `None` if `buf` isn't a valid value.
+    fn unpack_field(buf: &[u8]) -> Option<Self>;
+}
+macro_rules! implement_hci_field_int {
+    ( $( $t:ty ), *) => {
+        $(
+            impl HCIField for $t {
+                const BYTE_LEN: usize = core::mem::size_of::<$t>();
+                fn pack_field(&self, buf: &mut [u8]) {
+                    buf.copy_from_slice(&self.to_bytes_le()[..]);
+                }
+                fn unpack_field(buf: &[u8]) -> Option<Self> {
+                    <$t>::from_bytes_le(buf)
+                }
+            }
+        )*
+    };
+}
+implement_hci_field_int!(u8, u16, u32, u64, i8);
+/// Implements [`HCIField`] for one byte types with `From<T> for u8` and `TryFrom<u8> for T`.
+#[macro_export]
+macro_rules! hci_field_u8 {
+    ( $( $t:ty ), *) => {
+        $(
+            impl $crate::hci::definitions::HCIField for $t {
+                const BYTE_LEN: usize = 1;
+                fn pack_field(&self, buf: &mut [u8]) {
+                    buf[0] = (*self).into();
+                }
+                fn unpack_field(buf: &[u8]) -> Option<Self> {
+                    core::convert::TryFrom::try_from(buf[0]).ok()
+                }
+            }
+        )*
+    };
+}
+hci_field_u8!(
+    crate::le::scan::ScanType,
+    crate::le::scan::OwnAddressType,
+    crate::le::scan::ScanningFilterPolicy,
+    crate::le::scan::FilterDuplicates,
+    crate::le::advertiser::PeerAddressType,
+    crate::le::connection::Role,
+    crate::le::whitelist::WhitelistAddressType,
+    crate::hci::status::WhichClock
+);
+impl HCIField for bool {
+    const BYTE_LEN: usize = 1;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf[0] = (*self).into();
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        match buf[0] {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+impl HCIField for ErrorCode {
+    const BYTE_LEN: usize = ErrorCode::BYTE_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf[0] = (*self).into();
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        ErrorCode::try_from(buf[0]).ok()
+    }
+}
+impl HCIField for BTAddress {
+    const BYTE_LEN: usize = BTAddress::LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.0[..]);
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        BTAddress::unpack_from(buf).ok()
+    }
+}
+impl HCIField for ConnectionHandle {
+    const BYTE_LEN: usize = ConnectionHandle::BYTE_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        u16::from(*self).pack_field(buf);
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        ConnectionHandle::new_checked(u16::unpack_field(buf)?)
+    }
+}
+impl HCIField for ConnectionParameters {
+    const BYTE_LEN: usize = ConnectionParameters::BYTE_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        self.pack_into(buf).expect("field length checked by caller");
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        ConnectionParameters::unpack_from(buf).ok()
+    }
+}
+impl<T: HCIField + Copy + Default, const N: usize> HCIField for [T; N] {
+    const BYTE_LEN: usize = T::BYTE_LEN * N;
+    fn pack_field(&self, buf: &mut [u8]) {
+        for (item, out) in self.iter().zip(buf.chunks_exact_mut(T::BYTE_LEN)) {
+            item.pack_field(out);
+        }
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        let mut out = [T::default(); N];
+        for (item, chunk) in out.iter_mut().zip(buf.chunks_exact(T::BYTE_LEN)) {
+            *item = T::unpack_field(chunk)?;
+        }
+        Some(out)
+    }
+}
+/// Reads [`HCIField`]s front to back. Errors point at the offset of the bad or missing field.
+#[derive(Copy, Clone, Debug)]
+pub struct FieldReader<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+impl<'a> FieldReader<'a> {
+    pub fn new(buf: &'a [u8]) -> FieldReader<'a> {
+        FieldReader { buf, offset: 0 }
+    }
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    /// Bytes not read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.offset..]
+    }
+    pub fn is_empty(&self) -> bool {
+        self.remaining().is_empty()
+    }
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], PackError> {
+        let out = self
+            .buf
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| PackError::bad_index(self.offset))?;
+        self.offset += len;
+        Ok(out)
+    }
+    pub fn field<T: HCIField>(&mut self) -> Result<T, PackError> {
+        let start = self.offset;
+        T::unpack_field(self.bytes(T::BYTE_LEN)?).ok_or_else(|| PackError::bad_index(start))
+    }
+    pub fn list<T: HCIField>(&mut self, count: usize) -> Result<Vec<T>, PackError> {
+        (0..count).map(|_| self.field()).collect()
+    }
+    /// List after a one byte count.
+    pub fn counted_list<T: HCIField>(&mut self) -> Result<Vec<T>, PackError> {
+        let count = self.field::<u8>()?;
+        self.list(usize::from(count))
+    }
+    /// Everything not read yet.
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = self.remaining();
+        self.offset = self.buf.len();
+        rest
+    }
+    /// # Errors
+    /// returns `PackError::BadLength` if there are bytes left.
+    pub fn finish(&self) -> Result<(), PackError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(PackError::BadLength {
+                expected: self.offset,
+                got: self.buf.len(),
+            })
+        }
+    }
+}
+/// Writes [`HCIField`]s front to back, the counterpart of [`FieldReader`].
+#[derive(Debug)]
+pub struct FieldWriter<'a> {
+    buf: &'a mut [u8],
+    offset: usize,
+}
+impl<'a> FieldWriter<'a> {
+    pub fn new(buf: &'a mut [u8]) -> FieldWriter<'a> {
+        FieldWriter { buf, offset: 0 }
+    }
+    pub fn bytes(&mut self, bytes: &[u8]) -> Result<(), PackError> {
+        let offset = self.offset;
+        let out = self
+            .buf
+            .get_mut(self.offset..self.offset + bytes.len())
+            .ok_or_else(|| PackError::bad_index(offset))?;
+        out.copy_from_slice(bytes);
+        self.offset += bytes.len();
+        Ok(())
+    }
+    pub fn field<T: HCIField>(&mut self, field: &T) -> Result<(), PackError> {
+        let offset = self.offset;
+        let out = self
+            .buf
+            .get_mut(self.offset..self.offset + T::BYTE_LEN)
+            .ok_or_else(|| PackError::bad_index(offset))?;
+        field.pack_field(out);
+        self.offset += T::BYTE_LEN;
+        Ok(())
+    }
+    /// `list` after a one byte count.
+    /// # Errors
+    /// returns `PackError::InvalidFields` for lists longer than 255.
+    pub fn counted_list<T: HCIField>(&mut self, list: &[T]) -> Result<(), PackError> {
+        let count = u8::try_from(list.len()).map_err(|_| PackError::InvalidFields)?;
+        self.field(&count)?;
+        list.iter().try_for_each(|item| self.field(item))
+    }
+    /// # Errors
+    /// returns `PackError::BadLength` if `buf` isn't filled exactly.
+    pub fn finish(&self) -> Result<(), PackError> {
+        if self.offset == self.buf.len() {
+            Ok(())
+        } else {
+            Err(PackError::BadLength {
+                expected: self.offset,
+                got: self.buf.len(),
+            })
+        }
+    }
+}
+/// Packs `fields` back to back into `buf`. Used by the generated packers.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! hci_pack_fields {
+    ($self:ident, $buf:ident, $offset:expr, $( $field:ident : $ty:ty ),*) => {{
+        #[allow(unused_mut, unused_assignments)]
+        let mut _offset: usize = $offset;
+        $(
+            let end = _offset + <$ty as $crate::hci::definitions::HCIField>::BYTE_LEN;
+            $crate::hci::definitions::HCIField::pack_field(&$self.$field, &mut $buf[_offset..end]);
+            _offset = end;
+        )*
+    }};
+}
+/// Defines a fixed length HCI command. See the module docs.
+#[macro_export]
+macro_rules! hci_command {
+    (
+        $( #[$meta:meta] )*
+        $vis:vis struct $name:ident : $opcode_ty:ident :: $opcode:ident => $ret:ty {
+            $( $( #[$field_meta:meta] )* $field_vis:vis $field:ident : $ty:ty ),* $(,)?
+        }
+    ) => {
+        $( #[$meta] )*
+        $vis struct $name {
+            $( $( #[$field_meta] )* $field_vis $field: $ty ),*
+        }
+        impl $name {
+            pub const OPCODE: $opcode_ty = $opcode_ty::$opcode;
+            pub const BYTE_LEN: usize =
+                0 $( + <$ty as $crate::hci::definitions::HCIField>::BYTE_LEN )*;
+        }
+        impl $crate::hci::command::Command for $name {
+            type Return = $ret;
+
+            fn opcode() -> $crate::hci::Opcode {
+                Self::OPCODE.into()
+            }
+
+            fn byte_len(&self) -> usize {
+                Self::BYTE_LEN
+            }
+
+            fn pack_into(&self, buf: &mut [u8]) -> Result<(), $crate::PackError> {
+                $crate::PackError::expect_length(Self::BYTE_LEN, buf)?;
+                $crate::hci_pack_fields!(self, buf, 0, $( $field : $ty ),*);
+                Ok(())
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn unpack_from(buf: &[u8]) -> Result<Self, $crate::PackError>
+            where
+                Self: Sized,
+            {
+                $crate::PackError::expect_length(Self::BYTE_LEN, buf)?;
+                let mut offset = 0_usize;
+                Ok($name {
+                    $( $field: {
+                        let start = offset;
+                        offset += <$ty as $crate::hci::definitions::HCIField>::BYTE_LEN;
+                        <$ty as $crate::hci::definitions::HCIField>::unpack_field(&buf[start..offset])
+                            .ok_or_else(|| $crate::PackError::bad_index(start))?
+                    } ),*
+                })
+            }
+        }
+    };
+}
+/// Defines the return parameters of a command. A leading `status: ErrorCode` field is added.
+#[macro_export]
+macro_rules! hci_return {
+    (
+        $( #[$meta:meta] )*
+        $vis:vis struct $name:ident {
+            $( $( #[$field_meta:meta] )* $field_vis:vis $field:ident : $ty:ty ),* $(,)?
+        }
+    ) => {
+        $( #[$meta] )*
+        $vis struct $name {
+            pub status: $crate::hci::ErrorCode,
+            $( $( #[$field_meta] )* $field_vis $field: $ty ),*
+        }
+        impl $name {
+            pub const BYTE_LEN: usize = $crate::hci::ErrorCode::BYTE_LEN
+                $( + <$ty as $crate::hci::definitions::HCIField>::BYTE_LEN )*;
+        }
+        impl $crate::hci::event::ReturnParameters for $name {
+            fn byte_len(&self) -> usize {
+                Self::BYTE_LEN
+            }
+
+            fn pack_into(&self, buf: &mut [u8]) -> Result<(), $crate::PackError> {
+                $crate::PackError::expect_length(Self::BYTE_LEN, buf)?;
+                buf[0] = self.status.into();
+                $crate::hci_pack_fields!(self, buf, 1, $( $field : $ty ),*);
+                Ok(())
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn unpack_from(buf: &[u8]) -> Result<Self, $crate::PackError>
+            where
+                Self: Sized,
+            {
+                $crate::PackError::expect_length(Self::BYTE_LEN, buf)?;
+                let mut offset = 1_usize;
+                Ok($name {
+                    status: core::convert::TryFrom::try_from(buf[0])
+                        .map_err(|_| $crate::PackError::bad_index(0))?,
+                    $( $field: {
+                        let start = offset;
+                        offset += <$ty as $crate::hci::definitions::HCIField>::BYTE_LEN;
+                        <$ty as $crate::hci::definitions::HCIField>::unpack_field(&buf[start..offset])
+                            .ok_or_else(|| $crate::PackError::bad_index(start))?
+                    } ),*
+                })
+            }
+        }
+    };
+}
+#[cfg(test)]
+mod tests {
+    use crate::hci::command::Command;
+    use crate::hci::event::ReturnParameters;
+    use crate::hci::le::scan::SetExtendedScanEnable;
+    use crate::hci::le::whitelist::{AddDeviceToWhitelist, WhitelistSizeReturn};
+    use crate::hci::ErrorCode;
+    use crate::le::scan::FilterDuplicates;
+    use crate::le::whitelist::{WhitelistAddressType, WhitelistEntry};
+    use crate::{BTAddress, PackError};
+    #[test]
+    fn test_generated_packers() {
+        let add = AddDeviceToWhitelist {
+            entry: WhitelistEntry::new(WhitelistAddressType::Random, BTAddress([1, 2, 3, 4, 5, 6])),
+        };
+        let mut buf = [0_u8; AddDeviceToWhitelist::BYTE_LEN];
+        add.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [1, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(AddDeviceToWhitelist::unpack_from(&buf), Ok(add));
+        assert_eq!(
+            AddDeviceToWhitelist::unpack_from(&[2, 1, 2, 3, 4, 5, 6]),
+            Err(PackError::bad_index(0))
+        );
+
+        let enable = SetExtendedScanEnable {
+            is_enabled: true,
+            filter_duplicates: FilterDuplicates::ResetEachPeriod,
+            duration: 0x0102,
+            period: 0x0304,
+        };
+        let mut buf = [0_u8; SetExtendedScanEnable::BYTE_LEN];
+        enable.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 2, 1, 4, 3]);
+        assert_eq!(SetExtendedScanEnable::unpack_from(&buf), Ok(enable));
+
+        assert_eq!(
+            WhitelistSizeReturn::unpack_from(&[0, 8]),
+            Ok(WhitelistSizeReturn {
+                status: ErrorCode::Ok,
+                whitelist_size: 8
+            })
+        );
+    }
+}
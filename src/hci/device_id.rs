@@ -0,0 +1,202 @@
+//! Transport independent controller selection. A [`DeviceId`] names a controller the way a user
+//! would (`hci0`, its address, `usb:0a12:0001` or a serial port/socket path) and parses from and
+//! formats to the same strings, so applications can take it straight from a command line or config
+//! file. [`enumerate`] lists the controllers the enabled transports can see and
+//! [`ControllerInfo::matches`] picks one.
+use crate::error::IOError;
+use crate::{BTAddress, ConversionError};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+use core::str::FromStr;
+
+/// Identifies one controller.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum DeviceId {
+    /// Host stack index (`hciN` on BlueZ, the Nth USB controller otherwise).
+    Index(u16),
+    /// Public device address.
+    Address(BTAddress),
+    /// USB vendor and product ID. `serial` tells apart identical dongles.
+    Usb {
+        vendor_id: u16,
+        product_id: u16,
+        serial: Option<String>,
+    },
+    /// Serial port device or socket address.
+    Path(String),
+}
+impl DeviceId {
+    /// `true` if a USB device with these IDs is `self`. A `serial` of `None` (not read yet)
+    /// matches any serial number.
+    pub fn matches_usb(&self, vendor: u16, product: u16, serial: Option<&str>) -> bool {
+        match self {
+            DeviceId::Usb {
+                vendor_id,
+                product_id,
+                serial: expected,
+            } => {
+                *vendor_id == vendor
+                    && *product_id == product
+                    && match (expected, serial) {
+                        (Some(expected), Some(serial)) => expected == serial,
+                        _ => true,
+                    }
+            }
+            _ => false,
+        }
+    }
+}
+impl Display for DeviceId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DeviceId::Index(index) => write!(f, "hci{}", index),
+            DeviceId::Address(address) => write!(f, "{}", address),
+            DeviceId::Usb {
+                vendor_id,
+                product_id,
+                serial,
+            } => {
+                write!(f, "usb:{:04x}:{:04x}", vendor_id, product_id)?;
+                match serial {
+                    Some(serial) => write!(f, ":{}", serial),
+                    None => Ok(()),
+                }
+            }
+            DeviceId::Path(path) => f.write_str(path),
+        }
+    }
+}
+impl FromStr for DeviceId {
+    type Err = ConversionError;
+
+    /// `hciN`, `AA:BB:CC:DD:EE:FF`, `usb:VID:PID[:SERIAL]` (hex IDs). Anything else is a path.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(index) = s.strip_prefix("hci") {
+            if let Ok(index) = index.parse() {
+                return Ok(DeviceId::Index(index));
+            }
+        }
+        if let Some(usb) = s.strip_prefix("usb:") {
+            let mut parts = usb.splitn(3, ':');
+            let mut id = || {
+                parts
+                    .next()
+                    .and_then(|id| u16::from_str_radix(id, 16).ok())
+                    .ok_or(ConversionError(()))
+            };
+            let vendor_id = id()?;
+            let product_id = id()?;
+            return Ok(DeviceId::Usb {
+                vendor_id,
+                product_id,
+                serial: parts.next().map(ToString::to_string),
+            });
+        }
+        if let Ok(address) = BTAddress::from_str(s) {
+            return Ok(DeviceId::Address(address));
+        }
+        Ok(DeviceId::Path(s.to_string()))
+    }
+}
+impl From<BTAddress> for DeviceId {
+    fn from(address: BTAddress) -> Self {
+        DeviceId::Address(address)
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum TransportKind {
+    BlueZ,
+    USB,
+    Serial,
+}
+/// A controller found by [`enumerate`].
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ControllerInfo {
+    /// The most specific ID the transport knows, pass it to the transport's `open_device`.
+    pub id: DeviceId,
+    pub transport: TransportKind,
+    /// Position among the controllers of the same transport.
+    pub index: u16,
+    /// Only known if the transport reports it without opening the controller.
+    pub address: Option<BTAddress>,
+    /// Product name or description.
+    pub name: Option<String>,
+    /// [`DeviceId::Usb`] of the device behind the controller (USB dongles and USB to serial
+    /// adapters).
+    pub usb: Option<DeviceId>,
+}
+impl ControllerInfo {
+    /// `true` if `id` selects this controller.
+    pub fn matches(&self, id: &DeviceId) -> bool {
+        if &self.id == id {
+            return true;
+        }
+        match (id, &self.usb) {
+            (DeviceId::Index(index), _) => *index == self.index,
+            (DeviceId::Address(address), _) => self.address == Some(*address),
+            (
+                DeviceId::Usb { .. },
+                Some(DeviceId::Usb {
+                    vendor_id,
+                    product_id,
+                    serial,
+                }),
+            ) => id.matches_usb(*vendor_id, *product_id, serial.as_deref()),
+            _ => false,
+        }
+    }
+}
+/// First controller in `controllers` matching `id`.
+pub fn select<'a>(controllers: &'a [ControllerInfo], id: &DeviceId) -> Option<&'a ControllerInfo> {
+    controllers.iter().find(|c| c.matches(id))
+}
+/// Lists the controllers of every enabled transport (BlueZ sockets, USB and serial ports).
+/// # Errors
+/// returns the first transport error. Transports that aren't available on this system (no
+/// BlueZ for example) are skipped.
+pub fn enumerate() -> Result<Vec<ControllerInfo>, IOError> {
+    #[allow(unused_mut)]
+    let mut controllers = Vec::new();
+    #[cfg(all(unix, feature = "bluez_socket"))]
+    controllers.extend(crate::hci::bluez_socket::controllers()?);
+    #[cfg(feature = "hci_usb")]
+    controllers.extend(crate::hci::usb::device::controllers()?);
+    #[cfg(feature = "serialport")]
+    controllers.extend(crate::hci::serial::controllers()?);
+    Ok(controllers)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_match() {
+        for s in [
+            "hci1",
+            "01:02:03:04:05:06",
+            "usb:0a12:0001:ABC",
+            "/dev/ttyACM0",
+        ] {
+            assert_eq!(s.parse::<DeviceId>().unwrap().to_string(), s);
+        }
+        assert!("usb:0a12".parse::<DeviceId>().is_err());
+        let info = ControllerInfo {
+            id: DeviceId::Path("/dev/ttyUSB1".to_string()),
+            transport: TransportKind::Serial,
+            index: 1,
+            address: None,
+            name: None,
+            usb: Some(DeviceId::Usb {
+                vendor_id: 0x0a12,
+                product_id: 0x0001,
+                serial: None,
+            }),
+        };
+        assert!(info.matches(&"usb:0a12:0001:ABC".parse().unwrap()));
+        assert!(info.matches(&DeviceId::Index(1)));
+        assert!(info.matches(&"/dev/ttyUSB1".parse().unwrap()));
+        assert!(!info.matches(&"usb:0a12:0002".parse().unwrap()));
+        assert!(!info.matches(&DeviceId::Address(BTAddress::ZEROED)));
+    }
+}
@@ -1,5 +1,16 @@
 //! HCI Event and event utilities.
-use crate::bytes::{StaticBuf, Storage};
+#![forbid(unsafe_code)]
+#![cfg_attr(
+    all(feature = "panic-free", not(test)),
+    deny(
+        clippy::indexing_slicing,
+        clippy::expect_used,
+        clippy::unwrap_used,
+        clippy::panic,
+        clippy::unreachable
+    )
+)]
+use crate::bytes::{HexDump, StaticBuf, Storage};
 use crate::hci::packet::{PacketType, RawPacket};
 use crate::hci::{ErrorCode, Opcode, EVENT_CODE_LEN, OPCODE_LEN};
 use crate::ConversionError;
@@ -25,6 +36,7 @@ pub enum EventCode {
     QoSSetupComplete = 0x0D,
     CommandComplete = 0x0E,
     CommandStatus = 0x0F,
+    HardwareError = 0x10,
     FlushOccurred = 0x11,
     RoleChange = 0x12,
     NumberOfCompletedPackets = 0x13,
@@ -105,6 +117,7 @@ impl TryFrom<u8> for EventCode {
             0x0D => Ok(EventCode::QoSSetupComplete),
             0x0E => Ok(EventCode::CommandComplete),
             0x0F => Ok(EventCode::CommandStatus),
+            0x10 => Ok(EventCode::HardwareError),
             0x11 => Ok(EventCode::FlushOccurred),
             0x12 => Ok(EventCode::RoleChange),
             0x13 => Ok(EventCode::NumberOfCompletedPackets),
@@ -244,13 +257,18 @@ impl<Storage: AsRef<[u8]>> EventPacket<Storage> {
     pub fn take_parameters(self) -> Storage {
         self.parameters
     }
+    /// # Panics
+    /// Panics if the parameters are longer than 255 bytes (only possible for events built by the
+    /// host, not ones read from a controller).
+    #[allow(clippy::expect_used)]
     pub fn to_raw_packet<NewStorage: crate::bytes::Storage<u8>>(&self) -> RawPacket<NewStorage> {
-        let para_len = self.parameters.as_ref().len();
-        let len = para_len + 1 + 1;
-        let mut buf = NewStorage::with_size(len);
-        buf.as_mut()[2..].copy_from_slice(self.parameters.as_ref());
-        buf.as_mut()[0] = self.event_code.into();
-        buf.as_mut()[1] = para_len.try_into().expect("len bigger than an u8");
+        let parameters = self.parameters.as_ref();
+        let mut buf = NewStorage::with_size(parameters.len() + 1 + 1);
+        if let [code, len, rest @ ..] = buf.as_mut() {
+            *code = self.event_code.into();
+            *len = parameters.len().try_into().expect("len bigger than an u8");
+            rest.copy_from_slice(parameters);
+        }
         RawPacket {
             packet_type: PacketType::Event,
             buf,
@@ -270,29 +288,23 @@ impl<'a> TryFrom<RawPacket<&'a [u8]>> for EventPacket<&'a [u8]> {
         if packet.packet_type != PacketType::Event {
             Err(PackError::BadOpcode)
         } else {
-            let code = match packet.buf.get(0) {
-                None => {
+            let (code, len, parameters) = match packet.buf {
+                [code, len, parameters @ ..] => (*code, *len, parameters),
+                _ => {
                     return Err(PackError::BadLength {
                         expected: 2,
-                        got: 0,
+                        got: packet.buf.len(),
                     })
                 }
-                Some(&b) => EventCode::try_from(b).ok().ok_or(PackError::bad_index(0))?,
             };
-            let len = match packet.buf.get(1) {
-                None => {
-                    return Err(PackError::BadLength {
-                        expected: 2,
-                        got: 1,
-                    })
-                }
-                Some(l) => *l,
-            };
-            if usize::from(len) != (packet.buf.len() - 2) {
+            let code = EventCode::try_from(code)
+                .ok()
+                .ok_or(PackError::bad_index(0))?;
+            if usize::from(len) != parameters.len() {
                 // Packet length is incorrect
                 Err(PackError::InvalidFields)
             } else {
-                Ok(EventPacket::new(code, &packet.buf[2..]))
+                Ok(EventPacket::new(code, parameters))
             }
         }
     }
@@ -305,6 +317,19 @@ impl<Storage: AsRef<[u8]>> core::fmt::Debug for EventPacket<Storage> {
             .finish()
     }
 }
+/// `EventCode [len] parameters` with the parameters hex dumped (see [`HexDump`]).
+impl<Storage: AsRef<[u8]>> core::fmt::Display for EventPacket<Storage> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let parameters = self.parameters.as_ref();
+        write!(
+            f,
+            "{:?} [{}] {}",
+            self.event_code,
+            parameters.len(),
+            HexDump::new(parameters)
+        )
+    }
+}
 pub trait ReturnParameters {
     fn byte_len(&self) -> usize;
     fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError>;
@@ -321,19 +346,31 @@ impl ReturnParameters for StatusReturn {
     }
 
     fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
-        PackError::expect_length(1, buf)?;
-        buf[0] = self.status.into();
-        Ok(())
+        match buf {
+            [status] => {
+                *status = self.status.into();
+                Ok(())
+            }
+            _ => Err(PackError::BadLength {
+                expected: 1,
+                got: buf.len(),
+            }),
+        }
     }
 
     fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
     where
         Self: Sized,
     {
-        PackError::expect_length(1, buf)?;
-        Ok(StatusReturn {
-            status: ErrorCode::try_from(buf[0]).map_err(|_| PackError::bad_index(0))?,
-        })
+        match buf {
+            [status] => Ok(StatusReturn {
+                status: ErrorCode::try_from(*status).map_err(|_| PackError::bad_index(0))?,
+            }),
+            _ => Err(PackError::BadLength {
+                expected: 1,
+                got: buf.len(),
+            }),
+        }
     }
 }
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
@@ -355,26 +392,27 @@ impl<Params: ReturnParameters> Event for CommandComplete<Params> {
     where
         Self: Sized,
     {
-        if buf.len() < COMMAND_COMPLETE_HEADER_LEN {
-            Err(PackError::BadLength {
+        match buf {
+            [num_command_packets, o0, o1, params @ ..] => Ok(CommandComplete {
+                num_command_packets: *num_command_packets,
+                opcode: Opcode::unpack(&[*o0, *o1])?,
+                params: Params::unpack_from(params)?,
+            }),
+            _ => Err(PackError::BadLength {
                 expected: COMMAND_COMPLETE_HEADER_LEN,
                 got: buf.len(),
-            })
-        } else {
-            let opcode = Opcode::unpack(&buf[1..3])?;
-            Ok(CommandComplete {
-                num_command_packets: buf[0],
-                opcode,
-                params: Params::unpack_from(&buf[3..])?,
-            })
+            }),
         }
     }
 
     fn event_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
         PackError::expect_length(self.event_byte_len(), buf)?;
-        self.params.pack_into(&mut buf[3..])?;
-        self.opcode.pack(&mut buf[1..3])?;
-        buf[0] = self.num_command_packets;
+        let (header, params) = buf.split_at_mut(COMMAND_COMPLETE_HEADER_LEN.min(buf.len()));
+        self.params.pack_into(params)?;
+        if let [num_command_packets, opcode @ ..] = header {
+            self.opcode.pack(opcode)?;
+            *num_command_packets = self.num_command_packets;
+        }
         Ok(())
     }
 }
@@ -387,11 +425,7 @@ impl<Params: ReturnParameters> ReturnEvent for CommandComplete<Params> {
         self.opcode
     }
     fn guess_command_opcode(buf: &[u8]) -> Option<Opcode> {
-        if buf.len() >= 3 {
-            Opcode::unpack(&buf[1..3]).ok()
-        } else {
-            None
-        }
+        Opcode::unpack(buf.get(1..3)?).ok()
     }
 }
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
@@ -412,23 +446,33 @@ impl Event for CommandStatus {
     where
         Self: Sized,
     {
-        PackError::expect_length(COMMAND_STATUS_LEN, buf)?;
-        let opcode = Opcode::unpack(&buf[2..4])?;
-        let status =
-            ErrorCode::try_from(buf[0]).map_err(|_| PackError::BadBytes { index: Some(0) })?;
-        Ok(CommandStatus {
-            status,
-            num_command_packets: buf[1],
-            opcode,
-        })
+        match buf {
+            [status, num_command_packets, o0, o1] => Ok(CommandStatus {
+                status: ErrorCode::try_from(*status)
+                    .map_err(|_| PackError::BadBytes { index: Some(0) })?,
+                num_command_packets: *num_command_packets,
+                opcode: Opcode::unpack(&[*o0, *o1])?,
+            }),
+            _ => Err(PackError::BadLength {
+                expected: COMMAND_STATUS_LEN,
+                got: buf.len(),
+            }),
+        }
     }
 
     fn event_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
-        PackError::expect_length(COMMAND_STATUS_LEN, buf)?;
-        self.opcode.pack(&mut buf[2..4])?;
-        buf[0] = self.status.into();
-        buf[1] = self.num_command_packets;
-        Ok(())
+        match buf {
+            [status, num_command_packets, opcode @ ..] if opcode.len() == OPCODE_LEN => {
+                self.opcode.pack(opcode)?;
+                *status = self.status.into();
+                *num_command_packets = self.num_command_packets;
+                Ok(())
+            }
+            _ => Err(PackError::BadLength {
+                expected: COMMAND_STATUS_LEN,
+                got: buf.len(),
+            }),
+        }
     }
 }
 
@@ -437,10 +481,6 @@ impl ReturnEvent for CommandStatus {
         self.opcode
     }
     fn guess_command_opcode(buf: &[u8]) -> Option<Opcode> {
-        if buf.len() >= COMMAND_STATUS_LEN {
-            Opcode::unpack(&buf[2..4]).ok()
-        } else {
-            None
-        }
+        Opcode::unpack(buf.get(2..4)?).ok()
     }
 }
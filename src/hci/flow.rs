@@ -0,0 +1,353 @@
+//! Host side HCI data flow control. The controller has a fixed number of data buffers (see
+//! [`ReadBufferSizeV1`](crate::hci::le::connection::ReadBufferSizeV1)). Every packet sent to it
+//! takes one until a Number Of Completed Packets event returns it and disconnecting a link frees
+//! the buffers of all its packets. [`DataCredits`] does the book keeping.
+//!
+//! Commands are paced the same way with [`CommandCredits`]: every Command Complete/Status event
+//! tells how many more commands the controller accepts (Num_HCI_Command_Packets).
+use crate::hci::event::{EventCode, EventPacket};
+use crate::le::connection::ConnectionHandle;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::task::{Context, Poll, Waker};
+
+/// Controller data buffer credits, per connection handle.
+#[derive(Clone, Debug, Default)]
+pub struct DataCredits {
+    total: u16,
+    in_flight: u16,
+    outstanding: BTreeMap<ConnectionHandle, u16>,
+    waiters: Vec<Waker>,
+}
+impl DataCredits {
+    /// `total` is the controller's total number of data packets.
+    pub fn new(total: u16) -> DataCredits {
+        DataCredits {
+            total,
+            ..DataCredits::default()
+        }
+    }
+    pub fn total(&self) -> u16 {
+        self.total
+    }
+    /// Changes the number of controller buffers (after reading them again). Packets in flight
+    /// stay counted.
+    pub fn set_total(&mut self, total: u16) {
+        self.total = total;
+        self.wake_waiters();
+    }
+    pub fn available(&self) -> u16 {
+        self.total.saturating_sub(self.in_flight)
+    }
+    pub fn in_flight(&self) -> u16 {
+        self.in_flight
+    }
+    /// Packets of `handle` the controller hasn't completed yet.
+    pub fn outstanding(&self, handle: ConnectionHandle) -> u16 {
+        self.outstanding.get(&handle).copied().unwrap_or(0)
+    }
+    /// Takes a credit for a packet to `handle` if one is available.
+    pub fn try_acquire(&mut self, handle: ConnectionHandle) -> bool {
+        if self.available() == 0 {
+            return false;
+        }
+        self.in_flight += 1;
+        *self.outstanding.entry(handle).or_insert(0) += 1;
+        true
+    }
+    /// Takes a credit or registers `cx` to be woken once one is freed.
+    pub fn poll_acquire(&mut self, handle: ConnectionHandle, cx: &mut Context<'_>) -> Poll<()> {
+        if self.try_acquire(handle) {
+            Poll::Ready(())
+        } else {
+            if !self.waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                self.waiters.push(cx.waker().clone());
+            }
+            Poll::Pending
+        }
+    }
+    /// The controller completed `count` packets of `handle` (Number Of Completed Packets).
+    /// Counts over what's outstanding (a misbehaving controller) are ignored. Returns the number
+    /// of credits freed.
+    pub fn complete(&mut self, handle: ConnectionHandle, count: u16) -> u16 {
+        let freed = match self.outstanding.get_mut(&handle) {
+            Some(outstanding) => {
+                let freed = count.min(*outstanding);
+                *outstanding -= freed;
+                if *outstanding == 0 {
+                    self.outstanding.remove(&handle);
+                }
+                freed
+            }
+            None => 0,
+        };
+        self.free(freed);
+        freed
+    }
+    /// `handle` disconnected. The controller drops its packets without completing them. Returns
+    /// the number of credits freed.
+    pub fn disconnect(&mut self, handle: ConnectionHandle) -> u16 {
+        let freed = self.outstanding.remove(&handle).unwrap_or(0);
+        self.free(freed);
+        freed
+    }
+    /// Frees the credits returned by Number Of Completed Packets and Disconnection Complete
+    /// events. Returns `true` if `event` was one of them.
+    pub fn update(&mut self, event: EventPacket<&[u8]>) -> bool {
+        let parameters = event.parameters;
+        match event.event_code {
+            EventCode::NumberOfCompletedPackets => {
+                let num_handles = match parameters.split_first() {
+                    Some((&num_handles, _)) => usize::from(num_handles),
+                    None => return false,
+                };
+                // Connection_Handle and Num_Completed_Packets pairs.
+                for entry in parameters[1..].chunks_exact(4).take(num_handles) {
+                    let handle = u16::from_le_bytes([entry[0], entry[1]]) & 0x0FFF;
+                    if let Some(handle) = ConnectionHandle::new_checked(handle) {
+                        self.complete(handle, u16::from_le_bytes([entry[2], entry[3]]));
+                    }
+                }
+                true
+            }
+            EventCode::DisconnectionComplete => match parameters {
+                [0x00, h0, h1, ..] => {
+                    let handle = u16::from_le_bytes([*h0, *h1]) & 0x0FFF;
+                    if let Some(handle) = ConnectionHandle::new_checked(handle) {
+                        self.disconnect(handle);
+                    }
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+    fn free(&mut self, freed: u16) {
+        if freed != 0 {
+            self.in_flight -= freed;
+            self.wake_waiters();
+        }
+    }
+    fn wake_waiters(&mut self) {
+        if self.available() != 0 {
+            for waker in self.waiters.drain(..) {
+                waker.wake();
+            }
+        }
+    }
+}
+/// Number of commands the controller accepts, from the last Num_HCI_Command_Packets seen. Starts
+/// at 1 (what the host may assume after power on or reset).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct CommandCredits {
+    available: u8,
+}
+impl Default for CommandCredits {
+    fn default() -> Self {
+        CommandCredits { available: 1 }
+    }
+}
+impl CommandCredits {
+    pub fn available(&self) -> u8 {
+        self.available
+    }
+    /// Takes a credit for sending a command if the controller has room for one.
+    pub fn try_acquire(&mut self) -> bool {
+        if self.available == 0 {
+            return false;
+        }
+        self.available -= 1;
+        true
+    }
+    /// Gives back a credit taken for a command that was never sent.
+    pub fn release(&mut self) {
+        self.available = self.available.saturating_add(1);
+    }
+    /// Takes the new credit count from Command Complete/Status events (the count replaces the
+    /// old one, it isn't added). Returns `true` if `event` was one of them.
+    pub fn update(&mut self, event: EventPacket<&[u8]>) -> bool {
+        let index = match event.event_code {
+            EventCode::CommandComplete => 0,
+            EventCode::CommandStatus => 1,
+            _ => return false,
+        };
+        match event.parameters.get(index) {
+            Some(&num_command_packets) => {
+                self.available = num_command_packets;
+                true
+            }
+            None => false,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::acl::{ACLPacket, PacketBoundary};
+    use crate::hci::adapters::Adapter;
+    use crate::hci::baseband::Reset;
+    use crate::hci::command::Command;
+    use crate::hci::io::HCIPort;
+    use crate::hci::{Opcode, OCF, OGF};
+    use crate::testing::mock::{command_complete, MockTransport};
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use futures_util::task::{waker, ArcWake};
+    use futures_util::FutureExt;
+    use proptest::prelude::*;
+
+    #[derive(Default)]
+    struct WakeCount(AtomicUsize);
+    impl ArcWake for WakeCount {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    #[derive(Copy, Clone, Debug)]
+    enum Op {
+        Send(u16),
+        Complete(u16, u16),
+        Disconnect(u16),
+    }
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            4 => (0..3_u16).prop_map(Op::Send),
+            3 => (0..3_u16, 0..5_u16).prop_map(|(h, n)| Op::Complete(h, n)),
+            1 => (0..3_u16).prop_map(Op::Disconnect),
+        ]
+    }
+
+    proptest! {
+        /// Models the controller's buffers: sends only succeed while it has room, completions
+        /// and disconnects return exactly the packets it held, blocked senders are always woken
+        /// when room frees up and everything drains back to `total`.
+        #[test]
+        fn test_credits_model(total in 1..8_u16, ops in proptest::collection::vec(op(), 0..200)) {
+            let mut credits = DataCredits::new(total);
+            let mut controller = [0_u16; 3];
+            let wakes = Arc::new(WakeCount::default());
+            let waker = waker(wakes.clone());
+            let mut blocked = false;
+            for op in ops {
+                match op {
+                    Op::Send(h) => {
+                        let handle = ConnectionHandle::new(h);
+                        let room = controller.iter().sum::<u16>() < total;
+                        let mut cx = Context::from_waker(&waker);
+                        let ready = credits.poll_acquire(handle, &mut cx).is_ready();
+                        prop_assert_eq!(ready, room);
+                        if ready {
+                            controller[usize::from(h)] += 1;
+                        } else {
+                            blocked = true;
+                        }
+                    }
+                    Op::Complete(h, n) => {
+                        let done = n.min(controller[usize::from(h)]);
+                        controller[usize::from(h)] -= done;
+                        let before = wakes.0.load(Ordering::SeqCst);
+                        // The controller never reports more than it holds.
+                        prop_assert_eq!(credits.complete(ConnectionHandle::new(h), done), done);
+                        if blocked && done != 0 {
+                            prop_assert!(wakes.0.load(Ordering::SeqCst) > before);
+                            blocked = false;
+                        }
+                    }
+                    Op::Disconnect(h) => {
+                        let dropped = controller[usize::from(h)];
+                        controller[usize::from(h)] = 0;
+                        let before = wakes.0.load(Ordering::SeqCst);
+                        prop_assert_eq!(credits.disconnect(ConnectionHandle::new(h)), dropped);
+                        if blocked && dropped != 0 {
+                            prop_assert!(wakes.0.load(Ordering::SeqCst) > before);
+                            blocked = false;
+                        }
+                    }
+                }
+                prop_assert!(credits.in_flight() <= total);
+                prop_assert_eq!(credits.in_flight(), controller.iter().sum::<u16>());
+                for (h, &held) in controller.iter().enumerate() {
+                    prop_assert_eq!(credits.outstanding(ConnectionHandle::new(h as u16)), held);
+                }
+            }
+            for (h, &held) in controller.iter().enumerate() {
+                credits.complete(ConnectionHandle::new(h as u16), held);
+            }
+            prop_assert_eq!(credits.available(), total);
+        }
+    }
+
+    #[test]
+    fn test_command_pacing() {
+        let (transport, handle) = MockTransport::new();
+        let mut adapter = Adapter::new(HCIPort::new(transport));
+        // The controller takes no more commands after this one.
+        let mut complete = command_complete(Reset::opcode(), &[0x00]);
+        complete[3] = 0;
+        handle.inject(&complete);
+        adapter.reset().now_or_never().unwrap().unwrap();
+        assert_eq!(adapter.command_credits(), 0);
+        handle.take_written();
+
+        let mut reset = Box::pin(adapter.reset());
+        assert!(reset.as_mut().now_or_never().is_none());
+        assert!(handle.take_written().is_empty());
+        // NOP Command Complete gives the credit back.
+        handle.inject(&command_complete(Opcode(OGF::NOP, OCF::new(0)), &[]));
+        handle.inject(&command_complete(Reset::opcode(), &[0x00]));
+        reset.now_or_never().unwrap().unwrap();
+        assert_eq!(handle.take_written(), [0x01, 0x03, 0x0C, 0x00]);
+    }
+
+    #[test]
+    fn test_acl_blocks_until_completed() {
+        let (transport, handle) = MockTransport::new();
+        let mut adapter = Adapter::new(HCIPort::new(transport));
+        adapter.set_acl_credits(Some(DataCredits::new(1)));
+        let link = ConnectionHandle::new(0x0040);
+        let packet = ACLPacket::new(link, PacketBoundary::FirstNonFlushable, &[0xAA][..]);
+        adapter
+            .hci_send_acl(packet)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(handle.take_written(), [0x02, 0x40, 0x00, 0x01, 0x00, 0xAA]);
+
+        let mut send = Box::pin(adapter.hci_send_acl(packet));
+        assert!(send.as_mut().now_or_never().is_none());
+        assert!(handle.take_written().is_empty());
+        // Number Of Completed Packets: 1 handle, 0x0040 completed 1.
+        handle.inject(&[0x04, 0x13, 0x05, 0x01, 0x40, 0x00, 0x01, 0x00]);
+        send.now_or_never().unwrap().unwrap();
+        assert_eq!(handle.take_written(), [0x02, 0x40, 0x00, 0x01, 0x00, 0xAA]);
+        assert_eq!(adapter.acl_credits().map(DataCredits::in_flight), Some(1));
+    }
+
+    #[test]
+    fn test_event_stream_tracks_credits() {
+        use futures_util::StreamExt;
+        let (transport, handle) = MockTransport::new();
+        let mut adapter = Adapter::new(HCIPort::new(transport));
+        adapter.set_acl_credits(Some(DataCredits::new(1)));
+        let link = ConnectionHandle::new(0x0040);
+        let packet = ACLPacket::new(link, PacketBoundary::FirstNonFlushable, &[0xAA][..]);
+        adapter
+            .hci_send_acl(packet)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let mut complete = command_complete(Opcode(OGF::NOP, OCF::new(0)), &[]);
+        complete[3] = 0;
+        handle.inject(&complete);
+        handle.inject(&[0x04, 0x13, 0x05, 0x01, 0x40, 0x00, 0x01, 0x00]);
+        {
+            let mut events = Box::pin(adapter.hci_event_stream::<Box<[u8]>>());
+            for _ in 0..2 {
+                events.next().now_or_never().unwrap().unwrap().unwrap();
+            }
+        }
+        assert_eq!(adapter.command_credits(), 0);
+        assert_eq!(adapter.acl_credits().map(DataCredits::in_flight), Some(0));
+    }
+}
@@ -0,0 +1,537 @@
+//! HCI Informational Parameters commands (fixed controller information) and the codec types
+//! shared with the ISO data path commands in [`crate::hci::le::iso`].
+use crate::hci::command::Command;
+use crate::hci::definitions::{FieldReader, FieldWriter, HCIField};
+use crate::hci::event::{CommandComplete, ReturnParameters};
+use crate::hci::{ErrorCode, Opcode, Version, OCF, OGF};
+use crate::{ConversionError, PackError};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::time::Duration;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+#[repr(u16)]
+pub enum InformationalParametersOpcode {
+    ReadLocalVersionInformation = 0x0001,
+    ReadLocalSupportedCommands = 0x0002,
+    ReadLocalSupportedFeatures = 0x0003,
+    ReadBufferSize = 0x0005,
+    ReadBDADDR = 0x0009,
+    ReadLocalSupportedCodecs = 0x000B,
+    ReadLocalSupportedControllerDelay = 0x000F,
+}
+impl InformationalParametersOpcode {
+    pub const fn ogf() -> OGF {
+        OGF::InformationalParameters
+    }
+}
+impl TryFrom<OCF> for InformationalParametersOpcode {
+    type Error = ConversionError;
+
+    fn try_from(ocf: OCF) -> Result<Self, Self::Error> {
+        match u16::from(ocf) {
+            0x0001 => Ok(InformationalParametersOpcode::ReadLocalVersionInformation),
+            0x0002 => Ok(InformationalParametersOpcode::ReadLocalSupportedCommands),
+            0x0003 => Ok(InformationalParametersOpcode::ReadLocalSupportedFeatures),
+            0x0005 => Ok(InformationalParametersOpcode::ReadBufferSize),
+            0x0009 => Ok(InformationalParametersOpcode::ReadBDADDR),
+            0x000B => Ok(InformationalParametersOpcode::ReadLocalSupportedCodecs),
+            0x000F => Ok(InformationalParametersOpcode::ReadLocalSupportedControllerDelay),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+impl From<InformationalParametersOpcode> for OCF {
+    fn from(opcode: InformationalParametersOpcode) -> Self {
+        OCF::new(opcode as u16)
+    }
+}
+impl From<InformationalParametersOpcode> for Opcode {
+    fn from(opcode: InformationalParametersOpcode) -> Self {
+        Opcode(OGF::InformationalParameters, opcode.into())
+    }
+}
+crate::hci_command! {
+    /// Reads the HCI and LMP/Link Layer versions of the controller (see [`ControllerInfo`]).
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ReadLocalVersionInformation: InformationalParametersOpcode::ReadLocalVersionInformation
+        => CommandComplete<LocalVersionReturn> {}
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct LocalVersionReturn {
+        pub hci_version: u8,
+        pub hci_subversion: u16,
+        pub lmp_version: u8,
+        pub company_id: u16,
+        pub lmp_subversion: u16,
+    }
+}
+/// Fixed controller information, read once and cached by the
+/// [`Adapter`](crate::hci::adapters::Adapter).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ControllerInfo {
+    pub hci_version: u8,
+    pub hci_subversion: u16,
+    pub lmp_version: u8,
+    pub company_id: u16,
+    pub lmp_subversion: u16,
+}
+impl ControllerInfo {
+    /// HCI version. `None` for versions newer than [`Version`] knows.
+    pub fn version(&self) -> Option<Version> {
+        Version::try_from(self.hci_version).ok()
+    }
+    /// Whether the controller implements (at least) `required`. Unknown versions are newer.
+    pub fn supports(&self, required: Version) -> bool {
+        self.hci_version >= u8::from(required)
+    }
+}
+impl From<LocalVersionReturn> for ControllerInfo {
+    fn from(ret: LocalVersionReturn) -> Self {
+        ControllerInfo {
+            hci_version: ret.hci_version,
+            hci_subversion: ret.hci_subversion,
+            lmp_version: ret.lmp_version,
+            company_id: ret.company_id,
+            lmp_subversion: ret.lmp_subversion,
+        }
+    }
+}
+crate::hci_command! {
+    /// Reads the ACL and synchronous data buffers of the controller. LE traffic uses the ACL ones
+    /// when LE Read Buffer Size reports no dedicated LE buffers.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ReadBufferSize: InformationalParametersOpcode::ReadBufferSize
+        => CommandComplete<BufferSizeReturn> {}
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct BufferSizeReturn {
+        pub acl_data_packet_len: u16,
+        pub synchronous_data_packet_len: u8,
+        pub total_num_acl_data_packets: u16,
+        pub total_num_synchronous_data_packets: u16,
+    }
+}
+/// Max length of codec specific configuration.
+pub const MAX_CODEC_CONFIGURATION_LEN: usize = 0xFF;
+/// Coding format of a codec (Assigned Numbers).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+#[repr(u8)]
+pub enum CodingFormat {
+    ULawLog = 0x00,
+    ALawLog = 0x01,
+    CVSD = 0x02,
+    /// Data is passed through untouched (ISO data over HCI for example).
+    Transparent = 0x03,
+    LinearPCM = 0x04,
+    MSBC = 0x05,
+    LC3 = 0x06,
+    G729A = 0x07,
+    /// See [`CodecId::company_id`] and [`CodecId::vendor_codec_id`].
+    VendorSpecific = 0xFF,
+}
+impl From<CodingFormat> for u8 {
+    fn from(format: CodingFormat) -> Self {
+        format as u8
+    }
+}
+impl TryFrom<u8> for CodingFormat {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(CodingFormat::ULawLog),
+            0x01 => Ok(CodingFormat::ALawLog),
+            0x02 => Ok(CodingFormat::CVSD),
+            0x03 => Ok(CodingFormat::Transparent),
+            0x04 => Ok(CodingFormat::LinearPCM),
+            0x05 => Ok(CodingFormat::MSBC),
+            0x06 => Ok(CodingFormat::LC3),
+            0x07 => Ok(CodingFormat::G729A),
+            0xFF => Ok(CodingFormat::VendorSpecific),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// Codec_ID. `company_id` and `vendor_codec_id` are only used with
+/// [`CodingFormat::VendorSpecific`] and are `0` otherwise.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct CodecId {
+    pub coding_format: CodingFormat,
+    pub company_id: u16,
+    pub vendor_codec_id: u16,
+}
+impl CodecId {
+    pub const BYTE_LEN: usize = 5;
+    pub const TRANSPARENT: CodecId = CodecId::new(CodingFormat::Transparent);
+    pub const fn new(coding_format: CodingFormat) -> CodecId {
+        CodecId {
+            coding_format,
+            company_id: 0,
+            vendor_codec_id: 0,
+        }
+    }
+    pub const fn vendor(company_id: u16, vendor_codec_id: u16) -> CodecId {
+        CodecId {
+            coding_format: CodingFormat::VendorSpecific,
+            company_id,
+            vendor_codec_id,
+        }
+    }
+}
+impl Default for CodecId {
+    fn default() -> Self {
+        CodecId::TRANSPARENT
+    }
+}
+impl HCIField for CodecId {
+    const BYTE_LEN: usize = CodecId::BYTE_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf[0] = self.coding_format.into();
+        self.company_id.pack_field(&mut buf[1..3]);
+        self.vendor_codec_id.pack_field(&mut buf[3..5]);
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        Some(CodecId {
+            coding_format: CodingFormat::try_from(buf[0]).ok()?,
+            company_id: u16::unpack_field(&buf[1..3])?,
+            vendor_codec_id: u16::unpack_field(&buf[3..5])?,
+        })
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ReadLocalSupportedCodecs: InformationalParametersOpcode::ReadLocalSupportedCodecs
+        => CommandComplete<LocalSupportedCodecsReturn> {}
+}
+/// Return of [`ReadLocalSupportedCodecs`]. Both lists are counted, so the length varies.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct LocalSupportedCodecsReturn {
+    pub status: ErrorCode,
+    /// Coding formats (see [`CodingFormat`]) of the standard codecs.
+    pub standard_codecs: Vec<u8>,
+    pub vendor_codecs: Vec<CodecId>,
+}
+/// Company ID and vendor codec ID of a vendor codec in [`LocalSupportedCodecsReturn`].
+const VENDOR_CODEC_LEN: usize = 4;
+impl ReturnParameters for LocalSupportedCodecsReturn {
+    fn byte_len(&self) -> usize {
+        ErrorCode::BYTE_LEN
+            + 1
+            + self.standard_codecs.len()
+            + 1
+            + VENDOR_CODEC_LEN * self.vendor_codecs.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        let vendor_count =
+            u8::try_from(self.vendor_codecs.len()).map_err(|_| PackError::InvalidFields)?;
+        let mut writer = FieldWriter::new(buf);
+        writer.field(&self.status)?;
+        writer.counted_list(&self.standard_codecs)?;
+        writer.field(&vendor_count)?;
+        for codec in &self.vendor_codecs {
+            writer.field(&codec.company_id)?;
+            writer.field(&codec.vendor_codec_id)?;
+        }
+        writer.finish()
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError> {
+        let mut reader = FieldReader::new(buf);
+        let status = reader.field::<ErrorCode>()?;
+        // Errors may come with the status only.
+        if !status.is_ok() && reader.is_empty() {
+            return Ok(LocalSupportedCodecsReturn {
+                status,
+                standard_codecs: Vec::new(),
+                vendor_codecs: Vec::new(),
+            });
+        }
+        let standard_codecs = reader.counted_list::<u8>()?;
+        let vendor_count = reader.field::<u8>()?;
+        let vendor_codecs = (0..vendor_count)
+            .map(|_| Ok(CodecId::vendor(reader.field()?, reader.field()?)))
+            .collect::<Result<Vec<_>, PackError>>()?;
+        reader.finish()?;
+        Ok(LocalSupportedCodecsReturn {
+            status,
+            standard_codecs,
+            vendor_codecs,
+        })
+    }
+}
+/// Direction of a data path, seen from the controller.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+#[repr(u8)]
+pub enum DataPathDirection {
+    /// Host to controller (the data is sent over the air).
+    Input = 0x00,
+    /// Controller to host (the data was received over the air).
+    Output = 0x01,
+}
+impl From<DataPathDirection> for u8 {
+    fn from(direction: DataPathDirection) -> Self {
+        direction as u8
+    }
+}
+impl TryFrom<u8> for DataPathDirection {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(DataPathDirection::Input),
+            0x01 => Ok(DataPathDirection::Output),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+#[repr(u8)]
+pub enum LogicalTransportType {
+    BREDRACL = 0x00,
+    /// SCO or eSCO.
+    BREDRSynchronous = 0x01,
+    /// LE Connected Isochronous Stream.
+    LECIS = 0x02,
+    /// LE Broadcast Isochronous Stream.
+    LEBIS = 0x03,
+}
+impl From<LogicalTransportType> for u8 {
+    fn from(transport: LogicalTransportType) -> Self {
+        transport as u8
+    }
+}
+impl TryFrom<u8> for LogicalTransportType {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(LogicalTransportType::BREDRACL),
+            0x01 => Ok(LogicalTransportType::BREDRSynchronous),
+            0x02 => Ok(LogicalTransportType::LECIS),
+            0x03 => Ok(LogicalTransportType::LEBIS),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+crate::hci_field_u8!(CodingFormat, DataPathDirection, LogicalTransportType);
+/// Controller processing delay (24 bit microseconds, at most 4 s).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct ControllerDelay(u32);
+impl ControllerDelay {
+    pub const BYTE_LEN: usize = 3;
+    pub const MAX_MICROS: u32 = 4_000_000;
+    pub const ZERO: ControllerDelay = ControllerDelay(0);
+    pub const MAX: ControllerDelay = ControllerDelay(Self::MAX_MICROS);
+    pub fn new(micros: u32) -> Option<ControllerDelay> {
+        if micros <= Self::MAX_MICROS {
+            Some(ControllerDelay(micros))
+        } else {
+            None
+        }
+    }
+    /// Returns `None` if `duration` is over 4 s. Rounds down to whole microseconds.
+    pub fn from_duration(duration: Duration) -> Option<ControllerDelay> {
+        Self::new(u32::try_from(duration.as_micros()).ok()?)
+    }
+    pub fn micros(self) -> u32 {
+        self.0
+    }
+    pub fn as_duration(self) -> Duration {
+        Duration::from_micros(u64::from(self.0))
+    }
+}
+impl HCIField for ControllerDelay {
+    const BYTE_LEN: usize = ControllerDelay::BYTE_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.0.to_le_bytes()[..3]);
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        ControllerDelay::new(u32::from_le_bytes([buf[0], buf[1], buf[2], 0]))
+    }
+}
+/// Packs `configuration` with its length byte into `buf`, which has to be exactly
+/// `1 + configuration.len()` long.
+pub(crate) fn pack_codec_configuration(
+    configuration: &[u8],
+    buf: &mut [u8],
+    index: usize,
+) -> Result<(), PackError> {
+    buf[0] = u8::try_from(configuration.len()).map_err(|_| PackError::bad_index(index))?;
+    buf[1..].copy_from_slice(configuration);
+    Ok(())
+}
+/// Unpacks a length prefixed codec configuration filling the rest of `buf`.
+pub(crate) fn unpack_codec_configuration(buf: &[u8], index: usize) -> Result<Vec<u8>, PackError> {
+    let (&len, configuration) = buf.split_first().ok_or(PackError::bad_index(index))?;
+    if configuration.len() == usize::from(len) {
+        Ok(configuration.to_vec())
+    } else {
+        Err(PackError::bad_index(index))
+    }
+}
+/// Asks the range of delay the controller adds when processing data for the codec
+/// configuration. The host picks a value in this range for
+/// [`SetupISODataPath::controller_delay`](crate::hci::le::iso::SetupISODataPath::controller_delay).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ReadLocalSupportedControllerDelay {
+    pub codec_id: CodecId,
+    pub logical_transport_type: LogicalTransportType,
+    pub direction: DataPathDirection,
+    pub codec_configuration: Vec<u8>,
+}
+impl ReadLocalSupportedControllerDelay {
+    pub const OPCODE: InformationalParametersOpcode =
+        InformationalParametersOpcode::ReadLocalSupportedControllerDelay;
+    pub const HEADER_LEN: usize = CodecId::BYTE_LEN + 2;
+}
+impl Command for ReadLocalSupportedControllerDelay {
+    type Return = CommandComplete<ControllerDelayReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::HEADER_LEN + 1 + self.codec_configuration.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        self.codec_id.pack_field(&mut buf[..CodecId::BYTE_LEN]);
+        self.logical_transport_type
+            .pack_field(&mut buf[CodecId::BYTE_LEN..=CodecId::BYTE_LEN]);
+        self.direction
+            .pack_field(&mut buf[CodecId::BYTE_LEN + 1..Self::HEADER_LEN]);
+        pack_codec_configuration(
+            &self.codec_configuration,
+            &mut buf[Self::HEADER_LEN..],
+            Self::HEADER_LEN,
+        )
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::atleast_length(Self::HEADER_LEN + 1, buf)?;
+        Ok(ReadLocalSupportedControllerDelay {
+            codec_id: CodecId::unpack_field(&buf[..CodecId::BYTE_LEN])
+                .ok_or(PackError::bad_index(0))?,
+            logical_transport_type: LogicalTransportType::unpack_field(
+                &buf[CodecId::BYTE_LEN..=CodecId::BYTE_LEN],
+            )
+            .ok_or(PackError::bad_index(CodecId::BYTE_LEN))?,
+            direction: DataPathDirection::unpack_field(
+                &buf[CodecId::BYTE_LEN + 1..Self::HEADER_LEN],
+            )
+            .ok_or(PackError::bad_index(CodecId::BYTE_LEN + 1))?,
+            codec_configuration: unpack_codec_configuration(
+                &buf[Self::HEADER_LEN..],
+                Self::HEADER_LEN,
+            )?,
+        })
+    }
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ControllerDelayReturn {
+        pub min_controller_delay: ControllerDelay,
+        pub max_controller_delay: ControllerDelay,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::adapter;
+    use crate::hci::adapters::Adapter;
+    use crate::hci::io::HCIPort;
+    use crate::testing::mock::{command_complete, MockTransport};
+    use futures_util::FutureExt;
+
+    #[test]
+    fn test_read_buffer_size() {
+        let (transport, handle) = MockTransport::new();
+        let mut adapter = Adapter::new(HCIPort::new(transport));
+        handle.expect_command(
+            &ReadBufferSize {},
+            &[&command_complete(
+                ReadBufferSize::opcode(),
+                &[0x00, 0xFD, 0x03, 0x40, 0x08, 0x00, 0x02, 0x00],
+            )],
+        );
+        let size = adapter.read_buffer_size().now_or_never().unwrap().unwrap();
+        assert_eq!(size.acl_data_packet_len, 1021);
+        assert_eq!(size.synchronous_data_packet_len, 0x40);
+        assert_eq!(size.total_num_acl_data_packets, 8);
+        assert_eq!(size.total_num_synchronous_data_packets, 2);
+        let mut buf = [0_u8; BufferSizeReturn::BYTE_LEN];
+        size.pack_into(&mut buf).unwrap();
+        assert_eq!(BufferSizeReturn::unpack_from(&buf), Ok(size));
+        handle.assert_done();
+    }
+    #[test]
+    fn test_local_version_cache() {
+        let (transport, handle) = MockTransport::new();
+        let mut adapter = Adapter::new(HCIPort::new(transport));
+        assert_eq!(adapter.require_version(Version::Bluetooth5v2), Ok(()));
+        handle.inject(&command_complete(
+            ReadLocalVersionInformation::opcode(),
+            &[0x00, 0x09, 0x34, 0x12, 0x09, 0x0F, 0x00, 0x78, 0x56],
+        ));
+        let info = adapter
+            .read_local_version()
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.version(), Some(Version::Bluetooth5v0));
+        assert_eq!((info.company_id, info.lmp_subversion), (0x000F, 0x5678));
+        handle.take_written();
+        // Cached.
+        assert_eq!(
+            adapter.read_local_version().now_or_never().unwrap(),
+            Ok(info)
+        );
+        assert!(handle.take_written().is_empty());
+        assert_eq!(adapter.require_version(Version::Bluetooth5v0), Ok(()));
+        assert_eq!(
+            adapter.require_version(Version::Bluetooth5v2),
+            Err(adapter::Error::UnsupportedByController {
+                required: Version::Bluetooth5v2,
+                actual: Version::Bluetooth5v0,
+            })
+        );
+    }
+    #[test]
+    fn test_local_supported_codecs() {
+        let params = [
+            0x00, // status
+            0x02, 0x02, 0x05, // CVSD, mSBC
+            0x01, 0x0F, 0x00, 0x01, 0x00, // vendor codec 0x0001 of company 0x000F
+        ];
+        let ret = LocalSupportedCodecsReturn::unpack_from(&params).unwrap();
+        assert_eq!(
+            ret.standard_codecs,
+            [u8::from(CodingFormat::CVSD), u8::from(CodingFormat::MSBC)]
+        );
+        assert_eq!(ret.vendor_codecs, [CodecId::vendor(0x000F, 0x0001)]);
+        let mut buf = vec![0_u8; ret.byte_len()];
+        ret.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, params);
+        // Count says 2 vendor codecs, only one is there.
+        let mut truncated = params;
+        truncated[4] = 0x02;
+        assert_eq!(
+            LocalSupportedCodecsReturn::unpack_from(&truncated),
+            Err(PackError::bad_index(9))
+        );
+        assert_eq!(
+            LocalSupportedCodecsReturn::unpack_from(&[0x01]).map(|ret| ret.status),
+            Ok(ErrorCode::UnknownHCICommand)
+        );
+    }
+}
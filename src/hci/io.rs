@@ -0,0 +1,306 @@
+//! `async` counterparts of [`HCIReader`] and [`HCIWriter`] with named (GAT) future types. Every
+//! poll based transport gets them for free and transports built on `async` code (embedded
+//! executors for example) can implement them directly. [`HCIPort`] owns such a transport and
+//! works as an [`adapter::Adapter`] without `Pin<Box<..>>` or `Deref` wrapping.
+//!
+//! [`DynHCIReader`]/[`DynHCIWriter`] are the object safe versions (boxed futures) for transports
+//! chosen at runtime: `HCIPort<Box<dyn DynHCITransport>>` works with any of them.
+use crate::bytes::Storage;
+use crate::error::IOError;
+use crate::hci::acl::ACLPacket;
+use crate::hci::command::CommandPacket;
+use crate::hci::event::{EventPacket, StaticHCIBuffer, MAX_HCI_PACKET_SIZE};
+use crate::hci::packet::{PacketType, RawPacket};
+use crate::hci::stream::{HCIReader, HCIWriter, HCI_EVENT_READ_TRIES};
+use crate::hci::{adapter, MalformedPacket, StreamError};
+use core::convert::TryFrom;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_util::future::LocalBoxFuture;
+
+/// Reads one H4 packet (packet type byte first) per `read`. The read future has to be cancel
+/// safe like [`HCIReader`]: partial packets are kept by the reader, not the future.
+pub trait AsyncHCIReader {
+    type ReadFuture<'a>: Future<Output = Result<usize, adapter::Error>> + 'a
+    where
+        Self: 'a;
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Self::ReadFuture<'a>;
+}
+/// Writes whole H4 packets. Unlike reads, a write dropped after part of the packet went out
+/// leaves the controller with half a packet. Write futures should run to completion, after a
+/// command timeout fired mid write bring the controller back with a
+/// [`recovery`](crate::hci::adapters::recovery) reset.
+pub trait AsyncHCIWriter {
+    type WriteFuture<'a>: Future<Output = Result<(), adapter::Error>> + 'a
+    where
+        Self: 'a;
+    fn write_all<'a>(&'a mut self, buf: &'a [u8]) -> Self::WriteFuture<'a>;
+}
+/// [`AsyncHCIReader::read`] future of [`HCIReader`]s.
+pub struct Read<'a, S: ?Sized> {
+    reader: &'a mut S,
+    buf: &'a mut [u8],
+}
+impl<S: HCIReader + ?Sized> Future for Read<'_, S> {
+    type Output = Result<usize, adapter::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        Pin::new(&mut *this.reader).poll_read(cx, this.buf)
+    }
+}
+impl<S: HCIReader> AsyncHCIReader for S {
+    type ReadFuture<'a>
+        = Read<'a, S>
+    where
+        S: 'a;
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Read<'a, S> {
+        Read { reader: self, buf }
+    }
+}
+/// [`AsyncHCIWriter::write_all`] future of [`HCIWriter`]s. Flushes after the last byte. Fails
+/// with `IOError::Closed` if the writer stops taking bytes (writes `0` of them).
+pub struct WriteAll<'a, S: ?Sized> {
+    writer: &'a mut S,
+    buf: &'a [u8],
+}
+impl<S: HCIWriter + Unpin + ?Sized> Future for WriteAll<'_, S> {
+    type Output = Result<(), adapter::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        while !this.buf.is_empty() {
+            match Pin::new(&mut *this.writer).poll_write(cx, this.buf) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(IOError::Closed.into())),
+                Poll::Ready(Ok(amount)) => this.buf = &this.buf[amount..],
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Pin::new(&mut *this.writer).poll_flush(cx)
+    }
+}
+impl<S: HCIWriter + Unpin> AsyncHCIWriter for S {
+    type WriteFuture<'a>
+        = WriteAll<'a, S>
+    where
+        S: 'a;
+    fn write_all<'a>(&'a mut self, buf: &'a [u8]) -> WriteAll<'a, S> {
+        WriteAll { writer: self, buf }
+    }
+}
+/// Object safe [`AsyncHCIReader`].
+pub trait DynHCIReader {
+    fn read_boxed<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+    ) -> LocalBoxFuture<'a, Result<usize, adapter::Error>>;
+}
+impl<T: AsyncHCIReader> DynHCIReader for T {
+    fn read_boxed<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+    ) -> LocalBoxFuture<'a, Result<usize, adapter::Error>> {
+        Box::pin(self.read(buf))
+    }
+}
+/// Object safe [`AsyncHCIWriter`].
+pub trait DynHCIWriter {
+    fn write_all_boxed<'a>(
+        &'a mut self,
+        buf: &'a [u8],
+    ) -> LocalBoxFuture<'a, Result<(), adapter::Error>>;
+}
+impl<T: AsyncHCIWriter> DynHCIWriter for T {
+    fn write_all_boxed<'a>(
+        &'a mut self,
+        buf: &'a [u8],
+    ) -> LocalBoxFuture<'a, Result<(), adapter::Error>> {
+        Box::pin(self.write_all(buf))
+    }
+}
+/// Reading and writing transport for `Box<dyn DynHCITransport>`.
+pub trait DynHCITransport: DynHCIReader + DynHCIWriter {}
+impl<T: DynHCIReader + DynHCIWriter> DynHCITransport for T {}
+impl AsyncHCIReader for Box<dyn DynHCITransport + '_> {
+    type ReadFuture<'a>
+        = LocalBoxFuture<'a, Result<usize, adapter::Error>>
+    where
+        Self: 'a;
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Self::ReadFuture<'a> {
+        (**self).read_boxed(buf)
+    }
+}
+impl AsyncHCIWriter for Box<dyn DynHCITransport + '_> {
+    type WriteFuture<'a>
+        = LocalBoxFuture<'a, Result<(), adapter::Error>>
+    where
+        Self: 'a;
+    fn write_all<'a>(&'a mut self, buf: &'a [u8]) -> Self::WriteFuture<'a> {
+        (**self).write_all_boxed(buf)
+    }
+}
+/// Owns an `async` transport and reads/writes HCI packets on it.
+#[derive(Clone, Debug, Default)]
+pub struct HCIPort<T> {
+    pub transport: T,
+}
+impl<T> HCIPort<T> {
+    pub fn new(transport: T) -> HCIPort<T> {
+        HCIPort { transport }
+    }
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+impl<T: AsyncHCIReader> HCIPort<T> {
+    /// Reads the next event into `buf`, skipping other packet types. Cancel safe if the
+    /// transport is.
+    pub async fn read_event_into<'b>(
+        &mut self,
+        buf: &'b mut [u8],
+    ) -> Result<EventPacket<&'b [u8]>, adapter::Error> {
+        for _ in 0..HCI_EVENT_READ_TRIES {
+            let len = self.transport.read(buf).await?;
+            let packet_type = RawPacket::try_from(&buf[..len])
+                .map_err(|_| StreamError::BadPacketCode)?
+                .packet_type;
+            if packet_type == PacketType::Event {
+                let packet =
+                    RawPacket::try_from(&buf[..len]).map_err(|_| StreamError::BadPacketCode)?;
+                return EventPacket::try_from(packet).map_err(|e| {
+                    StreamError::Malformed(MalformedPacket::new(e, &buf[..len])).into()
+                });
+            }
+        }
+        Err(StreamError::StreamFailed.into())
+    }
+}
+impl<T: AsyncHCIWriter> HCIPort<T> {
+    pub async fn send_command_packet(
+        &mut self,
+        packet: CommandPacket<&[u8]>,
+    ) -> Result<(), adapter::Error> {
+        let out = packet
+            .pack_as_raw_packet::<StaticHCIBuffer>()
+            .map_err(StreamError::CommandError)?;
+        self.transport.write_all(out.as_ref()).await
+    }
+    pub async fn send_acl(&mut self, packet: ACLPacket<&[u8]>) -> Result<(), adapter::Error> {
+        let out = packet
+            .pack_as_raw_packet::<StaticHCIBuffer>()
+            .map_err(StreamError::DataError)?;
+        self.transport.write_all(out.as_ref()).await
+    }
+}
+impl<T: AsyncHCIReader + AsyncHCIWriter> adapter::Adapter for HCIPort<T> {
+    fn write_command<'s, 'p: 's>(
+        &'s mut self,
+        packet: CommandPacket<&'p [u8]>,
+    ) -> LocalBoxFuture<'s, Result<(), adapter::Error>> {
+        Box::pin(self.send_command_packet(packet))
+    }
+
+    fn read_event<'s, 'p: 's, Buf: Storage<u8> + 'p>(
+        &'s mut self,
+    ) -> LocalBoxFuture<'s, Result<EventPacket<Buf>, adapter::Error>> {
+        Box::pin(async move {
+            let mut buf = StaticHCIBuffer::with_size(MAX_HCI_PACKET_SIZE);
+            let event = self.read_event_into(buf.as_mut()).await?;
+            Ok(event.to_new_storage())
+        })
+    }
+
+    fn write_acl<'s, 'p: 's>(
+        &'s mut self,
+        packet: ACLPacket<&'p [u8]>,
+    ) -> LocalBoxFuture<'s, Result<(), adapter::Error>> {
+        Box::pin(self.send_acl(packet))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::adapters::{Adapter, DummyUnrecognizedEventHandler};
+    use crate::hci::baseband::Reset;
+    use crate::testing::mock::{command_complete, MockTransport};
+    use crate::testing::virtual_controller::VirtualController;
+    use crate::BTAddress;
+    use futures_util::FutureExt;
+
+    /// A host layer owning its transport generically, no lifetimes or boxing involved.
+    struct Owner<T: AsyncHCIReader + AsyncHCIWriter> {
+        adapter: Adapter<HCIPort<T>, DummyUnrecognizedEventHandler<Box<[u8]>>>,
+    }
+
+    #[test]
+    fn test_owned_transport() {
+        let (transport, handle) = MockTransport::new();
+        let mut owner = Owner {
+            adapter: Adapter::new(HCIPort::new(transport)),
+        };
+        handle.inject(&command_complete(Reset::OPCODE.into(), &[0x00]));
+        owner.adapter.reset().now_or_never().unwrap().unwrap();
+        assert_eq!(handle.take_written(), [0x01, 0x03, 0x0C, 0x00]);
+    }
+
+    #[test]
+    fn test_dyn_transport() {
+        let (mock, handle) = MockTransport::new();
+        handle.inject(&command_complete(Reset::OPCODE.into(), &[0x00]));
+        let (controller, _) = VirtualController::new(BTAddress::from_u64(0x11));
+        // Picked at runtime, one adapter type for both.
+        let transports: Vec<Box<dyn DynHCITransport>> = vec![Box::new(mock), Box::new(controller)];
+        for transport in transports {
+            let mut adapter = Adapter::new(HCIPort::new(transport));
+            adapter.reset().now_or_never().unwrap().unwrap();
+        }
+        assert_eq!(handle.take_written(), [0x01, 0x03, 0x0C, 0x00]);
+    }
+    #[test]
+    fn test_malformed_event() {
+        let (transport, handle) = MockTransport::new();
+        let mut port = HCIPort::new(transport);
+        // Claims 5 parameter bytes, has 2.
+        handle.inject(&[0x04, 0x0E, 0x05, 0x01, 0x03]);
+        let mut buf = [0_u8; MAX_HCI_PACKET_SIZE];
+        let error = match port.read_event_into(&mut buf).now_or_never().unwrap() {
+            Err(adapter::Error::StreamError(StreamError::Malformed(malformed))) => malformed,
+            other => panic!("expected a malformed packet, got {:?}", other.map(|_| ())),
+        };
+        assert_eq!(error.bytes(), [0x04, 0x0E, 0x05, 0x01, 0x03]);
+        assert!(StreamError::Malformed(error)
+            .to_string()
+            .starts_with("malformed packet [5] 04 0e 05 01 03"));
+    }
+    #[test]
+    fn test_write_zero() {
+        /// Takes one byte then nothing.
+        struct Stalled(bool);
+        impl HCIWriter for Stalled {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                _cx: &mut Context<'_>,
+                _buf: &[u8],
+            ) -> Poll<Result<usize, adapter::Error>> {
+                let this = self.get_mut();
+                let amount = usize::from(this.0);
+                this.0 = false;
+                Poll::Ready(Ok(amount))
+            }
+            fn poll_flush(
+                self: Pin<&mut Self>,
+                _cx: &mut Context<'_>,
+            ) -> Poll<Result<(), adapter::Error>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+        let mut writer = Stalled(true);
+        assert_eq!(
+            writer.write_all(&[0x01, 0x03, 0x0C, 0x00]).now_or_never(),
+            Some(Err(IOError::Closed.into()))
+        );
+        assert_eq!(writer.write_all(&[]).now_or_never(), Some(Ok(())));
+    }
+}
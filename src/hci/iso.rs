@@ -0,0 +1,268 @@
+//! HCI ISO data packets. Carry the SDUs of connected (CIS) and broadcast (BIS) isochronous
+//! streams when their data path is HCI (see [`crate::hci::le::iso`]).
+use crate::bytes::Storage;
+use crate::hci::packet::{PacketType, RawPacket};
+use crate::le::connection::ConnectionHandle;
+use crate::{ConversionError, PackError};
+use core::convert::TryFrom;
+
+/// Handle + flags (2 bytes) and data load length (2 bytes).
+pub const ISO_HEADER_LEN: usize = 4;
+pub const TIME_STAMP_LEN: usize = 4;
+/// Longest ISO data load (the length field has 14 bits).
+pub const MAX_ISO_DATA_LOAD_LEN: usize = 0x3FFF;
+/// Packet Boundary flag of ISO data packets.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ISOBoundary {
+    FirstFragment = 0b00,
+    ContinuationFragment = 0b01,
+    CompleteSDU = 0b10,
+    LastFragment = 0b11,
+}
+impl ISOBoundary {
+    /// The packet starts an SDU (and so has an [`ISOSDUHeader`]).
+    pub fn starts_sdu(self) -> bool {
+        matches!(self, ISOBoundary::FirstFragment | ISOBoundary::CompleteSDU)
+    }
+}
+impl From<ISOBoundary> for u8 {
+    fn from(boundary: ISOBoundary) -> Self {
+        boundary as u8
+    }
+}
+impl TryFrom<u8> for ISOBoundary {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0b00 => Ok(ISOBoundary::FirstFragment),
+            0b01 => Ok(ISOBoundary::ContinuationFragment),
+            0b10 => Ok(ISOBoundary::CompleteSDU),
+            0b11 => Ok(ISOBoundary::LastFragment),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// Packet Status flag. Only set by the controller (host to controller packets use `Valid`).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ISOPacketStatus {
+    Valid = 0b00,
+    /// Some data may have errors.
+    PossiblyInvalid = 0b01,
+    /// Parts of the SDU were lost.
+    LostData = 0b10,
+}
+impl From<ISOPacketStatus> for u8 {
+    fn from(status: ISOPacketStatus) -> Self {
+        status as u8
+    }
+}
+impl TryFrom<u8> for ISOPacketStatus {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0b00 => Ok(ISOPacketStatus::Valid),
+            0b01 => Ok(ISOPacketStatus::PossiblyInvalid),
+            0b10 => Ok(ISOPacketStatus::LostData),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// Fields in front of the first fragment of every SDU.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ISOSDUHeader {
+    pub sequence_number: u16,
+    /// Length of the whole SDU (12 bits).
+    pub sdu_len: u16,
+    pub status: ISOPacketStatus,
+}
+impl ISOSDUHeader {
+    pub const BYTE_LEN: usize = 4;
+    pub const MAX_SDU_LEN: u16 = 0x0FFF;
+}
+/// HCI ISO data packet (without the H4 packet type).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct ISOPacket<Buf> {
+    pub connection_handle: ConnectionHandle,
+    pub boundary: ISOBoundary,
+    /// Time stamp in microseconds. Sets the TS flag.
+    pub time_stamp: Option<u32>,
+    /// Present exactly when [`ISOBoundary::starts_sdu`].
+    pub sdu_header: Option<ISOSDUHeader>,
+    /// The SDU fragment.
+    pub data: Buf,
+}
+impl<Buf: AsRef<[u8]>> ISOPacket<Buf> {
+    pub fn as_ref(&self) -> ISOPacket<&[u8]> {
+        ISOPacket {
+            connection_handle: self.connection_handle,
+            boundary: self.boundary,
+            time_stamp: self.time_stamp,
+            sdu_header: self.sdu_header,
+            data: self.data.as_ref(),
+        }
+    }
+    pub fn to_new_storage<NewBuf: Storage<u8>>(&self) -> ISOPacket<NewBuf> {
+        ISOPacket {
+            connection_handle: self.connection_handle,
+            boundary: self.boundary,
+            time_stamp: self.time_stamp,
+            sdu_header: self.sdu_header,
+            data: NewBuf::from_slice(self.data.as_ref()),
+        }
+    }
+    /// Time stamp, SDU header and data len.
+    pub fn data_load_len(&self) -> usize {
+        self.time_stamp.map_or(0, |_| TIME_STAMP_LEN)
+            + self.sdu_header.map_or(0, |_| ISOSDUHeader::BYTE_LEN)
+            + self.data.as_ref().len()
+    }
+    pub fn byte_len(&self) -> usize {
+        ISO_HEADER_LEN + self.data_load_len()
+    }
+    pub fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        if self.boundary.starts_sdu() != self.sdu_header.is_some()
+            || self.data_load_len() > MAX_ISO_DATA_LOAD_LEN
+        {
+            return Err(PackError::InvalidFields);
+        }
+        let handle = u16::from(self.connection_handle)
+            | (u16::from(u8::from(self.boundary)) << 12)
+            | (u16::from(self.time_stamp.is_some()) << 14);
+        buf[..2].copy_from_slice(&handle.to_le_bytes());
+        buf[2..4].copy_from_slice(&(self.data_load_len() as u16).to_le_bytes());
+        let mut rest = &mut buf[ISO_HEADER_LEN..];
+        if let Some(time_stamp) = self.time_stamp {
+            rest[..TIME_STAMP_LEN].copy_from_slice(&time_stamp.to_le_bytes());
+            rest = &mut rest[TIME_STAMP_LEN..];
+        }
+        if let Some(header) = self.sdu_header {
+            if header.sdu_len > ISOSDUHeader::MAX_SDU_LEN {
+                return Err(PackError::InvalidFields);
+            }
+            let len = header.sdu_len | (u16::from(u8::from(header.status)) << 14);
+            rest[..2].copy_from_slice(&header.sequence_number.to_le_bytes());
+            rest[2..4].copy_from_slice(&len.to_le_bytes());
+            rest = &mut rest[ISOSDUHeader::BYTE_LEN..];
+        }
+        rest.copy_from_slice(self.data.as_ref());
+        Ok(())
+    }
+    /// Packs the H4 framed packet (packet type first).
+    pub fn pack_as_raw_packet<NewBuf: Storage<u8>>(&self) -> Result<NewBuf, PackError> {
+        let len = self.byte_len() + 1;
+        if NewBuf::max_len() < len {
+            return Err(PackError::BadLength {
+                expected: len,
+                got: NewBuf::max_len(),
+            });
+        }
+        let mut out = NewBuf::with_size(len);
+        let (packet_type, rest) = out
+            .as_mut()
+            .split_first_mut()
+            .ok_or(PackError::bad_index(0))?;
+        *packet_type = PacketType::ISOData.into();
+        self.pack_into(rest)?;
+        Ok(out)
+    }
+}
+impl<'a> ISOPacket<&'a [u8]> {
+    pub fn unpack_from(buf: &'a [u8]) -> Result<Self, PackError> {
+        PackError::atleast_length(ISO_HEADER_LEN, buf)?;
+        let (header, mut data) = buf.split_at(ISO_HEADER_LEN);
+        let handle = u16::from_le_bytes([header[0], header[1]]);
+        let data_load_len = usize::from(u16::from_le_bytes([header[2], header[3]]) & 0x3FFF);
+        if data_load_len != data.len() {
+            return Err(PackError::BadLength {
+                expected: ISO_HEADER_LEN + data_load_len,
+                got: buf.len(),
+            });
+        }
+        let connection_handle =
+            ConnectionHandle::new_checked(handle & 0x0FFF).ok_or(PackError::bad_index(0))?;
+        let boundary = ISOBoundary::try_from(((handle >> 12) & 0b11) as u8)
+            .map_err(|_| PackError::bad_index(1))?;
+        let time_stamp = if handle & (1 << 14) != 0 {
+            PackError::atleast_length(TIME_STAMP_LEN, data)?;
+            let (time_stamp, rest) = data.split_at(TIME_STAMP_LEN);
+            data = rest;
+            Some(u32::from_le_bytes([
+                time_stamp[0],
+                time_stamp[1],
+                time_stamp[2],
+                time_stamp[3],
+            ]))
+        } else {
+            None
+        };
+        let sdu_header = if boundary.starts_sdu() {
+            PackError::atleast_length(ISOSDUHeader::BYTE_LEN, data)?;
+            let (header, rest) = data.split_at(ISOSDUHeader::BYTE_LEN);
+            data = rest;
+            let len = u16::from_le_bytes([header[2], header[3]]);
+            Some(ISOSDUHeader {
+                sequence_number: u16::from_le_bytes([header[0], header[1]]),
+                sdu_len: len & ISOSDUHeader::MAX_SDU_LEN,
+                status: ISOPacketStatus::try_from((len >> 14) as u8)
+                    .map_err(|_| PackError::InvalidFields)?,
+            })
+        } else {
+            None
+        };
+        Ok(ISOPacket {
+            connection_handle,
+            boundary,
+            time_stamp,
+            sdu_header,
+            data,
+        })
+    }
+}
+impl<'a> TryFrom<RawPacket<&'a [u8]>> for ISOPacket<&'a [u8]> {
+    type Error = PackError;
+
+    fn try_from(packet: RawPacket<&'a [u8]>) -> Result<Self, Self::Error> {
+        if packet.packet_type != PacketType::ISOData {
+            Err(PackError::BadOpcode)
+        } else {
+            ISOPacket::unpack_from(packet.buf)
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iso_packet_round_trip() {
+        // Complete SDU with a time stamp, as a controller reports a received CIS SDU.
+        let bytes = [
+            0x60, 0x60, 0x0B, 0x00, 0x10, 0x27, 0x00, 0x00, 0x05, 0x00, 0x03, 0x40, 0xAA, 0xBB,
+            0xCC,
+        ];
+        let packet = ISOPacket::unpack_from(&bytes).unwrap();
+        assert_eq!(packet.connection_handle, ConnectionHandle::new(0x0060));
+        assert_eq!(packet.boundary, ISOBoundary::CompleteSDU);
+        assert_eq!(packet.time_stamp, Some(10_000));
+        assert_eq!(
+            packet.sdu_header,
+            Some(ISOSDUHeader {
+                sequence_number: 5,
+                sdu_len: 3,
+                status: ISOPacketStatus::PossiblyInvalid,
+            })
+        );
+        assert_eq!(packet.data, &[0xAA, 0xBB, 0xCC]);
+        let mut out = [0_u8; 15];
+        packet.pack_into(&mut out).unwrap();
+        assert_eq!(out, bytes);
+
+        // Continuation fragments have no SDU header.
+        let fragment = ISOPacket::unpack_from(&[0x60, 0x10, 0x01, 0x00, 0xDD]).unwrap();
+        assert_eq!(fragment.sdu_header, None);
+        assert_eq!(fragment.data, &[0xDD]);
+    }
+}
@@ -0,0 +1,199 @@
+//! Lock free single producer single consumer byte ring for interrupt driven HCI transports (UART
+//! on microcontrollers). The interrupt handler writes received bytes with [`Producer::push`] and
+//! the async side reads them through [`Consumer`] (which is an [`HCIReader`]). No allocation or
+//! executor support is needed and bytes that don't fit are counted instead of blocking the ISR.
+//!
+//! ```ignore
+//! static RING: IsrRing<512> = IsrRing::new();
+//! // In the UART interrupt (after taking the producer once at start up):
+//! producer.push(&uart_rx_bytes);
+//! ```
+use crate::hci::adapter;
+use crate::hci::stream::HCIReader;
+use core::cell::UnsafeCell;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+
+/// Ring of `N` bytes (a power of two). Put it in a `static` and [`IsrRing::split`] it once.
+pub struct IsrRing<const N: usize> {
+    buf: UnsafeCell<[u8; N]>,
+    /// Total bytes written. Only changed by the producer.
+    head: AtomicUsize,
+    /// Total bytes read. Only changed by the consumer.
+    tail: AtomicUsize,
+    overflowed: AtomicUsize,
+    reader: AtomicWaker,
+    split: AtomicBool,
+}
+// Safety: the producer only writes the free part of `buf` and the consumer only reads the filled
+// part, with `head`/`tail` (release/acquire) handing bytes over. `split` makes sure there is only
+// one of each.
+unsafe impl<const N: usize> Sync for IsrRing<N> {}
+impl<const N: usize> IsrRing<N> {
+    pub const CAPACITY: usize = N;
+    /// `head`/`tail` wrap around `usize::MAX`, which only keeps the slots in place if `N`
+    /// divides it.
+    const POWER_OF_TWO: () = assert!(N.is_power_of_two(), "IsrRing size must be a power of two");
+    const MASK: usize = N - 1;
+    pub const fn new() -> IsrRing<N> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::POWER_OF_TWO;
+        IsrRing {
+            buf: UnsafeCell::new([0_u8; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            overflowed: AtomicUsize::new(0),
+            reader: AtomicWaker::new(),
+            split: AtomicBool::new(false),
+        }
+    }
+    /// Returns the producer and consumer halves. Only succeeds the first time.
+    pub fn split(&self) -> Option<(Producer<'_, N>, Consumer<'_, N>)> {
+        if self.split.swap(true, Ordering::AcqRel) {
+            None
+        } else {
+            Some((Producer { ring: self }, Consumer { ring: self }))
+        }
+    }
+    /// Bytes waiting to be read.
+    pub fn len(&self) -> usize {
+        self.head
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.tail.load(Ordering::Acquire))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Bytes dropped because the ring was full.
+    pub fn overflowed(&self) -> usize {
+        self.overflowed.load(Ordering::Relaxed)
+    }
+}
+impl<const N: usize> Default for IsrRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+/// Writing half. Meant to live in the interrupt handler.
+pub struct Producer<'a, const N: usize> {
+    ring: &'a IsrRing<N>,
+}
+impl<const N: usize> Producer<'_, N> {
+    /// Copies as much of `bytes` as fits and wakes the reader. Returns the bytes written. The
+    /// rest is dropped and added to [`IsrRing::overflowed`]. Never blocks.
+    pub fn push(&mut self, bytes: &[u8]) -> usize {
+        let ring = self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        let free = N - head.wrapping_sub(ring.tail.load(Ordering::Acquire));
+        let amount = bytes.len().min(free);
+        let buf = ring.buf.get().cast::<u8>();
+        for (i, &b) in bytes[..amount].iter().enumerate() {
+            // Safety: `head + i` is in the free part of the ring which the consumer doesn't read.
+            unsafe {
+                buf.add(head.wrapping_add(i) & IsrRing::<N>::MASK)
+                    .write_volatile(b);
+            }
+        }
+        ring.head
+            .store(head.wrapping_add(amount), Ordering::Release);
+        if amount < bytes.len() {
+            ring.overflowed
+                .fetch_add(bytes.len() - amount, Ordering::Relaxed);
+        }
+        if amount > 0 {
+            ring.reader.wake();
+        }
+        amount
+    }
+}
+/// Reading half.
+pub struct Consumer<'a, const N: usize> {
+    ring: &'a IsrRing<N>,
+}
+impl<const N: usize> Consumer<'_, N> {
+    /// Copies waiting bytes into `out` without waiting. Returns the bytes read.
+    pub fn pop(&mut self, out: &mut [u8]) -> usize {
+        let ring = self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        let available = ring.head.load(Ordering::Acquire).wrapping_sub(tail);
+        let amount = out.len().min(available);
+        let buf = ring.buf.get().cast::<u8>();
+        for (i, o) in out[..amount].iter_mut().enumerate() {
+            // Safety: `tail + i` is in the filled part of the ring which the producer doesn't
+            // write.
+            *o = unsafe {
+                buf.add(tail.wrapping_add(i) & IsrRing::<N>::MASK)
+                    .read_volatile()
+            };
+        }
+        ring.tail
+            .store(tail.wrapping_add(amount), Ordering::Release);
+        amount
+    }
+    pub fn ring(&self) -> &IsrRing<N> {
+        self.ring
+    }
+}
+impl<const N: usize> HCIReader for Consumer<'_, N> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        this.ring.reader.register(cx.waker());
+        match this.pop(buf) {
+            0 => Poll::Pending,
+            amount => Poll::Ready(Ok(amount)),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::task::noop_waker_ref;
+    #[test]
+    fn test_isr_ring() {
+        static RING: IsrRing<8> = IsrRing::new();
+        let (mut producer, mut consumer) = RING.split().unwrap();
+        assert!(RING.split().is_none());
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut out = [0_u8; 8];
+        assert!(Pin::new(&mut consumer)
+            .poll_read(&mut cx, &mut out)
+            .is_pending());
+        assert_eq!(producer.push(&[1, 2, 3, 4, 5, 6]), 6);
+        assert_eq!(consumer.pop(&mut out[..4]), 4);
+        // Wraps around the end and drops what doesn't fit.
+        assert_eq!(producer.push(&[7, 8, 9, 10, 11, 12, 13]), 6);
+        assert_eq!(RING.overflowed(), 1);
+        assert_eq!(
+            Pin::new(&mut consumer).poll_read(&mut cx, &mut out),
+            Poll::Ready(Ok(8))
+        );
+        assert_eq!(out, [5, 6, 7, 8, 9, 10, 11, 12]);
+        assert!(RING.is_empty());
+    }
+    #[test]
+    fn test_counter_wrap() {
+        let ring = IsrRing::<4>::new();
+        ring.head.store(usize::MAX - 1, Ordering::Relaxed);
+        ring.tail.store(usize::MAX - 1, Ordering::Relaxed);
+        let (mut producer, mut consumer) = ring.split().unwrap();
+        let mut out = [0_u8; 4];
+        for round in 0..3_u8 {
+            assert_eq!(producer.push(&[round, 1, 2]), 3);
+            assert_eq!(consumer.pop(&mut out), 3);
+            assert_eq!(out[..3], [round, 1, 2]);
+        }
+        assert_eq!(producer.push(&[1, 2, 3, 4, 5]), 4);
+        assert_eq!(ring.len(), 4);
+        assert_eq!(consumer.pop(&mut out), 4);
+        assert_eq!(out, [1, 2, 3, 4]);
+    }
+}
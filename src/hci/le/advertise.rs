@@ -74,23 +74,46 @@ impl Command for SetAdvertisingData {
         Ok(())
     }
 
-    fn unpack_from(_buf: &[u8]) -> Result<Self, PackError>
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
     where
         Self: Sized,
     {
-        unimplemented!()
+        PackError::expect_length(Self::COMMAND_BYTE_LEN, buf)?;
+        let len = usize::from(buf[0]);
+        if len > ADVERTISING_DATA_MAX_LEN {
+            return Err(PackError::bad_index(0));
+        }
+        Self::try_from(&buf[1..][..len])
     }
 }
 impl SetAdvertisingData {
     const COMMAND_BYTE_LEN: usize = ADVERTISING_DATA_MAX_LEN + 1;
+    /// Creates a new `SetAdvertisingData`.
+    /// # Panics
+    /// Panics if `data.len() > 0x1F`. Use `SetAdvertisingData::try_from` to check instead.
     pub fn new(data: &[u8]) -> SetAdvertisingData {
-        assert!(data.len() <= ADVERTISING_DATA_MAX_LEN);
+        Self::try_from(data).expect("advertising data too long")
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[..usize::from(self.len)]
+    }
+}
+impl<'a> TryFrom<&'a [u8]> for SetAdvertisingData {
+    type Error = PackError;
+
+    fn try_from(data: &'a [u8]) -> Result<Self, Self::Error> {
+        if data.len() > ADVERTISING_DATA_MAX_LEN {
+            return Err(PackError::BadLength {
+                expected: ADVERTISING_DATA_MAX_LEN,
+                got: data.len(),
+            });
+        }
         let mut buf = [0_u8; ADVERTISING_DATA_MAX_LEN];
         buf[..data.len()].copy_from_slice(data);
-        SetAdvertisingData {
+        Ok(SetAdvertisingData {
             data: buf,
             len: data.len().try_into().expect("data max len 0x1F"),
-        }
+        })
     }
 }
 #[derive(Copy, Clone, Debug)]
@@ -108,16 +131,7 @@ impl Command for SetAdvertisingParameters {
 
     fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
         PackError::expect_length(AdvertisingParameters::BYTE_LEN, buf)?;
-        if self.0.advertising_type == AdvertisingType::AdvNonnConnInd {
-            if self.0.interval_min < AdvertisingInterval::MIN_NON_CONN
-                || self.0.interval_max < AdvertisingInterval::MIN_NON_CONN
-            {
-                return Err(PackError::InvalidFields);
-            }
-        }
-        if self.0.interval_max < self.0.interval_min {
-            return Err(PackError::InvalidFields);
-        }
+        self.0.validate()?;
         buf[0..2].copy_from_slice(&u16::from(self.0.interval_min).to_bytes_le()[..]);
         buf[2..4].copy_from_slice(&u16::from(self.0.interval_max).to_bytes_le()[..]);
         buf[4] = self.0.advertising_type.into();
@@ -136,7 +150,7 @@ impl Command for SetAdvertisingParameters {
         Self: Sized,
     {
         PackError::expect_length(AdvertisingParameters::BYTE_LEN, buf)?;
-        Ok(SetAdvertisingParameters(AdvertisingParameters {
+        let parameters = AdvertisingParameters {
             interval_min: AdvertisingInterval::try_from(
                 u16::from_bytes_le(&buf[0..2]).expect("hardcoded length"),
             )
@@ -156,7 +170,9 @@ impl Command for SetAdvertisingParameters {
                 .map_err(|_| PackError::bad_index(7 + BT_ADDRESS_LEN))?,
             filter_policy: FilterPolicy::try_from(buf[8 + BT_ADDRESS_LEN])
                 .map_err(|_| PackError::bad_index(8 + BT_ADDRESS_LEN))?,
-        }))
+        };
+        parameters.validate()?;
+        Ok(SetAdvertisingParameters(parameters))
     }
 }
 
@@ -276,3 +292,40 @@ impl ReturnParameters for TxPowerLevelReturn {
         })
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack<C: Command>(command: C) -> Result<Vec<u8>, PackError> {
+        let mut buf = vec![0_u8; command.byte_len()];
+        command.pack_into(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_advertising_command_validation() {
+        let data = SetAdvertisingData::try_from(&[0x02, 0x01, 0x06][..]).unwrap();
+        let packed = pack(data).unwrap();
+        assert_eq!(packed.len(), 32);
+        assert_eq!(SetAdvertisingData::unpack_from(&packed).unwrap(), data);
+        assert!(SetAdvertisingData::try_from(&[0_u8; 32][..]).is_err());
+
+        let mut parameters = AdvertisingParameters::DEFAULT;
+        let packed = pack(SetAdvertisingParameters(parameters)).unwrap();
+        assert_eq!(
+            SetAdvertisingParameters::unpack_from(&packed).unwrap().0,
+            parameters
+        );
+        parameters.channel_map = ChannelMap::ZEROED;
+        assert_eq!(
+            pack(SetAdvertisingParameters(parameters)),
+            Err(PackError::InvalidFields)
+        );
+        parameters.channel_map = ChannelMap::new(0x01);
+        parameters.advertising_type = AdvertisingType::AdvNonnConnInd;
+        parameters.interval_min = AdvertisingInterval::MIN;
+        assert!(pack(SetAdvertisingParameters(parameters)).is_err());
+        parameters.advertising_type = AdvertisingType::AdvDirectIndHighDutyCycle;
+        assert!(pack(SetAdvertisingParameters(parameters)).is_ok());
+    }
+}
@@ -1,11 +1,13 @@
 use crate::hci::command::Command;
-use crate::hci::event::{CommandComplete, CommandStatus, ReturnParameters};
-use crate::hci::le::{LEControllerOpcode, MetaEventCode};
+use crate::hci::event::{CommandComplete, CommandStatus, ReturnParameters, StatusReturn};
+use crate::hci::le::power::ConnectionHandleReturn;
+use crate::hci::le::{LEControllerOpcode, MetaEvent, MetaEventCode};
 use crate::hci::{ErrorCode, Opcode};
 use crate::le::advertiser::PeerAddressType;
 use crate::le::connection::{
-    CELength, ConnectionHandle, ConnectionInterval, ConnectionLatency, InitiatorFilterPolicy,
-    MasterClockAccuracy, Role, SupervisionTimeout,
+    CELength, ConnectionHandle, ConnectionInterval, ConnectionLatency, ConnectionParameters,
+    ConnectionParametersError, InitiatorFilterPolicy, MasterClockAccuracy, Role,
+    SupervisionTimeout,
 };
 use crate::le::scan::{OwnAddressType, ScanInterval, ScanWindow};
 use crate::{BTAddress, PackError, BT_ADDRESS_LEN};
@@ -175,10 +177,24 @@ impl CreateConnection {
         + InitiatorFilterPolicy::BYTE_LEN
         + PeerAddressType::BYTE_LEN
         + BT_ADDRESS_LEN
-        + ScanInterval::BYTE_LEN * 2
-        + ConnectionLatency::BYTE_LEN
-        + SupervisionTimeout::BYTE_LEN
-        + CELength::BYTE_LEN * 2;
+        + OwnAddressType::BYTE_LEN
+        + ConnectionParameters::BYTE_LEN;
+    pub fn connection_parameters(&self) -> ConnectionParameters {
+        ConnectionParameters {
+            interval_min: self.connection_interval_min,
+            interval_max: self.connection_interval_max,
+            latency: self.connection_latency,
+            supervision_timeout: self.supervision_timeout,
+            min_ce_len: self.min_ce_len,
+            max_ce_len: self.max_ce_len,
+        }
+    }
+    pub fn validate(&self) -> Result<(), ConnectionParametersError> {
+        if u16::from(self.le_scan_window) > u16::from(self.le_scan_interval) {
+            return Err(ConnectionParametersError::WindowLargerThanInterval);
+        }
+        self.connection_parameters().validate()
+    }
 }
 impl Command for CreateConnection {
     type Return = CommandStatus;
@@ -193,27 +209,14 @@ impl Command for CreateConnection {
 
     fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
         PackError::expect_length(Self::BYTE_LEN, buf)?;
+        self.validate().map_err(|_| PackError::InvalidFields)?;
         buf[0..2].copy_from_slice(u16::from(self.le_scan_interval).to_le_bytes().as_ref());
         buf[2..4].copy_from_slice(u16::from(self.le_scan_window).to_le_bytes().as_ref());
         buf[4] = self.initiator_filter_policy.into();
         buf[5] = self.peer_address_type.into();
         buf[6..12].copy_from_slice(self.peer_address.0.as_ref());
         buf[12] = self.own_address_type.into();
-        buf[13..15].copy_from_slice(
-            u16::from(self.connection_interval_min)
-                .to_le_bytes()
-                .as_ref(),
-        );
-        buf[15..17].copy_from_slice(
-            u16::from(self.connection_interval_max)
-                .to_le_bytes()
-                .as_ref(),
-        );
-        buf[17..19].copy_from_slice(u16::from(self.connection_latency).to_le_bytes().as_ref());
-        buf[19..21].copy_from_slice(u16::from(self.supervision_timeout).to_le_bytes().as_ref());
-        buf[21..23].copy_from_slice(u16::from(self.min_ce_len).to_le_bytes().as_ref());
-        buf[23..25].copy_from_slice(u16::from(self.max_ce_len).to_le_bytes().as_ref());
-        Ok(())
+        self.connection_parameters().pack_into(&mut buf[13..25])
     }
 
     fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
@@ -221,7 +224,189 @@ impl Command for CreateConnection {
         Self: Sized,
     {
         PackError::expect_length(Self::BYTE_LEN, buf)?;
-        todo!("implement unpack from for CreateConnection")
+        let interval = u16::from_le_bytes([buf[0], buf[1]]);
+        let window = u16::from_le_bytes([buf[2], buf[3]]);
+        let parameters =
+            ConnectionParameters::unpack_from(&buf[13..25]).map_err(|e| e.offset_index(13))?;
+        let create_connection = CreateConnection {
+            le_scan_interval: ScanInterval::new_checked(interval).ok_or(PackError::bad_index(0))?,
+            le_scan_window: ScanWindow::new_checked(window).ok_or(PackError::bad_index(2))?,
+            initiator_filter_policy: InitiatorFilterPolicy::try_from(buf[4])
+                .map_err(|_| PackError::bad_index(4))?,
+            peer_address_type: PeerAddressType::try_from(buf[5])
+                .map_err(|_| PackError::bad_index(5))?,
+            peer_address: BTAddress::unpack_from(&buf[6..12])?,
+            own_address_type: OwnAddressType::try_from(buf[12])
+                .map_err(|_| PackError::bad_index(12))?,
+            connection_interval_min: parameters.interval_min,
+            connection_interval_max: parameters.interval_max,
+            connection_latency: parameters.latency,
+            supervision_timeout: parameters.supervision_timeout,
+            min_ce_len: parameters.min_ce_len,
+            max_ce_len: parameters.max_ce_len,
+        };
+        create_connection
+            .validate()
+            .map_err(|_| PackError::InvalidFields)?;
+        Ok(create_connection)
+    }
+}
+crate::hci_command! {
+    /// Stops initiating. Fails with `CommandDisallowed` if no LE Create Connection is pending.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct CreateConnectionCancel: LEControllerOpcode::CreateConnectionCancel
+        => CommandComplete<StatusReturn> {}
+}
+/// Scan interval and window and the requested connection parameters for one initiating PHY.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct InitiatingPHYParameters {
+    pub scan_interval: ScanInterval,
+    pub scan_window: ScanWindow,
+    pub connection: ConnectionParameters,
+}
+impl InitiatingPHYParameters {
+    pub const BYTE_LEN: usize =
+        ScanInterval::BYTE_LEN + ScanWindow::BYTE_LEN + ConnectionParameters::BYTE_LEN;
+}
+/// LE Extended Create Connection. Each PHY with parameters is initiated on (connection
+/// parameters of `le_2m` are used if the connection ends up on LE 2M, it isn't scanned on).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ExtendedCreateConnection {
+    pub initiator_filter_policy: InitiatorFilterPolicy,
+    pub own_address_type: OwnAddressType,
+    pub peer_address_type: PeerAddressType,
+    pub peer_address: BTAddress,
+    pub le_1m: Option<InitiatingPHYParameters>,
+    pub le_2m: Option<InitiatingPHYParameters>,
+    pub le_coded: Option<InitiatingPHYParameters>,
+}
+impl ExtendedCreateConnection {
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::ExtendedCreateConnection;
+    pub const HEADER_LEN: usize = InitiatorFilterPolicy::BYTE_LEN
+        + OwnAddressType::BYTE_LEN
+        + PeerAddressType::BYTE_LEN
+        + BT_ADDRESS_LEN
+        + 1;
+    pub const LE_1M_BIT: u8 = 0x01;
+    pub const LE_2M_BIT: u8 = 0x02;
+    pub const LE_CODED_BIT: u8 = 0x04;
+    /// `Initiating_PHYs` bit field.
+    pub fn initiating_phys(&self) -> u8 {
+        let mut phys = 0;
+        if self.le_1m.is_some() {
+            phys |= Self::LE_1M_BIT;
+        }
+        if self.le_2m.is_some() {
+            phys |= Self::LE_2M_BIT;
+        }
+        if self.le_coded.is_some() {
+            phys |= Self::LE_CODED_BIT;
+        }
+        phys
+    }
+    /// Parameters for each initiating PHY in the order they are sent to the controller.
+    pub fn phys(&self) -> impl Iterator<Item = &InitiatingPHYParameters> + '_ {
+        self.le_1m
+            .iter()
+            .chain(self.le_2m.iter())
+            .chain(self.le_coded.iter())
+    }
+    pub fn validate(&self) -> Result<(), ConnectionParametersError> {
+        if self.le_1m.is_none() && self.le_coded.is_none() {
+            return Err(ConnectionParametersError::NoInitiatingPHYs);
+        }
+        for phy in self.phys() {
+            if u16::from(phy.scan_window) > u16::from(phy.scan_interval) {
+                return Err(ConnectionParametersError::WindowLargerThanInterval);
+            }
+            phy.connection.validate()?;
+        }
+        Ok(())
+    }
+}
+impl Command for ExtendedCreateConnection {
+    type Return = CommandStatus;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::HEADER_LEN + self.phys().count() * InitiatingPHYParameters::BYTE_LEN
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        self.validate().map_err(|_| PackError::InvalidFields)?;
+        buf[0] = self.initiator_filter_policy.into();
+        buf[1] = self.own_address_type.into();
+        buf[2] = self.peer_address_type.into();
+        buf[3..9].copy_from_slice(self.peer_address.0.as_ref());
+        buf[9] = self.initiating_phys();
+        for (phy, out) in self
+            .phys()
+            .zip(buf[Self::HEADER_LEN..].chunks_exact_mut(InitiatingPHYParameters::BYTE_LEN))
+        {
+            out[0..2].copy_from_slice(&u16::from(phy.scan_interval).to_le_bytes());
+            out[2..4].copy_from_slice(&u16::from(phy.scan_window).to_le_bytes());
+            phy.connection.pack_into(&mut out[4..])?;
+        }
+        Ok(())
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        if buf.len() < Self::HEADER_LEN {
+            return Err(PackError::BadLength {
+                expected: Self::HEADER_LEN,
+                got: buf.len(),
+            });
+        }
+        let phys = buf[9];
+        if phys & !(Self::LE_1M_BIT | Self::LE_2M_BIT | Self::LE_CODED_BIT) != 0 {
+            return Err(PackError::bad_index(9));
+        }
+        let count = phys.count_ones() as usize;
+        PackError::expect_length(
+            Self::HEADER_LEN + count * InitiatingPHYParameters::BYTE_LEN,
+            buf,
+        )?;
+        let mut chunks = buf[Self::HEADER_LEN..]
+            .chunks_exact(InitiatingPHYParameters::BYTE_LEN)
+            .enumerate();
+        let mut next = |bit: u8| -> Result<Option<InitiatingPHYParameters>, PackError> {
+            if phys & bit == 0 {
+                return Ok(None);
+            }
+            let (i, chunk) = chunks.next().ok_or(PackError::InvalidFields)?;
+            let index = Self::HEADER_LEN + i * InitiatingPHYParameters::BYTE_LEN;
+            let interval = u16::from_le_bytes([chunk[0], chunk[1]]);
+            let window = u16::from_le_bytes([chunk[2], chunk[3]]);
+            Ok(Some(InitiatingPHYParameters {
+                scan_interval: ScanInterval::new_checked(interval)
+                    .ok_or_else(|| PackError::bad_index(index))?,
+                scan_window: ScanWindow::new_checked(window)
+                    .ok_or_else(|| PackError::bad_index(index + 2))?,
+                connection: ConnectionParameters::unpack_from(&chunk[4..])
+                    .map_err(|e| e.offset_index(index + 4))?,
+            }))
+        };
+        let create = ExtendedCreateConnection {
+            initiator_filter_policy: InitiatorFilterPolicy::try_from(buf[0])
+                .map_err(|_| PackError::bad_index(0))?,
+            own_address_type: OwnAddressType::try_from(buf[1])
+                .map_err(|_| PackError::bad_index(1))?,
+            peer_address_type: PeerAddressType::try_from(buf[2])
+                .map_err(|_| PackError::bad_index(2))?,
+            peer_address: BTAddress::unpack_from(&buf[3..9])?,
+            le_1m: next(Self::LE_1M_BIT)?,
+            le_2m: next(Self::LE_2M_BIT)?,
+            le_coded: next(Self::LE_CODED_BIT)?,
+        };
+        create.validate().map_err(|_| PackError::InvalidFields)?;
+        Ok(create)
     }
 }
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
@@ -248,3 +433,277 @@ impl ConnectionCompleteEvent {
         + SupervisionTimeout::BYTE_LEN
         + MasterClockAccuracy::BYTE_LEN;
 }
+crate::hci_command! {
+    /// Asks for new connection parameters. As central the controller applies them, as
+    /// peripheral it requests them from the central. Completion is reported by an LE Connection
+    /// Update Complete event.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ConnectionUpdate: LEControllerOpcode::ConnectionUpdate => CommandStatus {
+        pub connection_handle: ConnectionHandle,
+        pub parameters: ConnectionParameters,
+    }
+}
+crate::hci_command! {
+    /// Accepts an LE Remote Connection Parameter Request with `parameters` (usually the
+    /// requested ones).
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct RemoteConnectionParameterRequestReply:
+        LEControllerOpcode::RemoteConnectionParameterRequestReply
+        => CommandComplete<ConnectionHandleReturn> {
+        pub connection_handle: ConnectionHandle,
+        pub parameters: ConnectionParameters,
+    }
+}
+crate::hci_command! {
+    /// Rejects an LE Remote Connection Parameter Request. `reason` is usually
+    /// `UnacceptableConnectionParameters`.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct RemoteConnectionParameterRequestNegativeReply:
+        LEControllerOpcode::RemoteConnectionParameterRequestNegativeReply
+        => CommandComplete<ConnectionHandleReturn> {
+        pub connection_handle: ConnectionHandle,
+        pub reason: ErrorCode,
+    }
+}
+/// LE Connection Update Complete event. The connection parameters changed (or the update
+/// failed, see `status`).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ConnectionUpdateComplete {
+    pub status: ErrorCode,
+    pub connection_handle: ConnectionHandle,
+    pub connection_interval: ConnectionInterval,
+    pub connection_latency: ConnectionLatency,
+    pub supervision_timeout: SupervisionTimeout,
+}
+impl ConnectionUpdateComplete {
+    pub const BYTE_LEN: usize = ErrorCode::BYTE_LEN
+        + ConnectionHandle::BYTE_LEN
+        + ConnectionInterval::BYTE_LEN
+        + ConnectionLatency::BYTE_LEN
+        + SupervisionTimeout::BYTE_LEN;
+}
+impl MetaEvent for ConnectionUpdateComplete {
+    const META_CODE: MetaEventCode = MetaEventCode::ConnectionUpdateComplete;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        let field = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        Ok(ConnectionUpdateComplete {
+            status: ErrorCode::try_from(buf[0]).map_err(|_| PackError::bad_index(0))?,
+            connection_handle: ConnectionHandle::new_checked(field(1))
+                .ok_or(PackError::bad_index(1))?,
+            connection_interval: ConnectionInterval::new_checked(field(3))
+                .ok_or(PackError::bad_index(3))?,
+            connection_latency: ConnectionLatency::new_checked(field(5))
+                .ok_or(PackError::bad_index(5))?,
+            supervision_timeout: SupervisionTimeout::new_checked(field(7))
+                .ok_or(PackError::bad_index(7))?,
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf[0] = self.status.into();
+        buf[1..3].copy_from_slice(&u16::from(self.connection_handle).to_le_bytes());
+        buf[3..5].copy_from_slice(&u16::from(self.connection_interval).to_le_bytes());
+        buf[5..7].copy_from_slice(&u16::from(self.connection_latency).to_le_bytes());
+        buf[7..9].copy_from_slice(&u16::from(self.supervision_timeout).to_le_bytes());
+        Ok(())
+    }
+}
+/// LE Remote Connection Parameter Request event. The peer asks for new connection parameters,
+/// answer with [`RemoteConnectionParameterRequest::reply`] or
+/// [`RemoteConnectionParameterRequest::reject`]. The request has no connection event lengths.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct RemoteConnectionParameterRequest {
+    pub connection_handle: ConnectionHandle,
+    pub interval_min: ConnectionInterval,
+    pub interval_max: ConnectionInterval,
+    pub max_latency: ConnectionLatency,
+    pub supervision_timeout: SupervisionTimeout,
+}
+impl RemoteConnectionParameterRequest {
+    pub const BYTE_LEN: usize = ConnectionHandle::BYTE_LEN
+        + ConnectionInterval::BYTE_LEN * 2
+        + ConnectionLatency::BYTE_LEN
+        + SupervisionTimeout::BYTE_LEN;
+    /// The requested parameters with zero connection event lengths.
+    pub fn parameters(&self) -> ConnectionParameters {
+        ConnectionParameters {
+            interval_min: self.interval_min,
+            interval_max: self.interval_max,
+            latency: self.max_latency,
+            supervision_timeout: self.supervision_timeout,
+            min_ce_len: CELength::MIN,
+            max_ce_len: CELength::MIN,
+        }
+    }
+    /// Accepts the requested parameters. Requests with inconsistent parameters are rejected
+    /// with `UnacceptableConnectionParameters` instead.
+    pub fn reply(
+        &self,
+    ) -> Result<RemoteConnectionParameterRequestReply, RemoteConnectionParameterRequestNegativeReply>
+    {
+        let parameters = self.parameters();
+        match parameters.validate() {
+            Ok(()) => Ok(RemoteConnectionParameterRequestReply {
+                connection_handle: self.connection_handle,
+                parameters,
+            }),
+            Err(_) => Err(self.reject(ErrorCode::UnacceptableConnectionParameters)),
+        }
+    }
+    pub fn reject(&self, reason: ErrorCode) -> RemoteConnectionParameterRequestNegativeReply {
+        RemoteConnectionParameterRequestNegativeReply {
+            connection_handle: self.connection_handle,
+            reason,
+        }
+    }
+}
+impl MetaEvent for RemoteConnectionParameterRequest {
+    const META_CODE: MetaEventCode = MetaEventCode::RemoteConnectionParametersRequest;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        let field = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        Ok(RemoteConnectionParameterRequest {
+            connection_handle: ConnectionHandle::new_checked(field(0))
+                .ok_or(PackError::bad_index(0))?,
+            interval_min: ConnectionInterval::new_checked(field(2))
+                .ok_or(PackError::bad_index(2))?,
+            interval_max: ConnectionInterval::new_checked(field(4))
+                .ok_or(PackError::bad_index(4))?,
+            max_latency: ConnectionLatency::new_checked(field(6)).ok_or(PackError::bad_index(6))?,
+            supervision_timeout: SupervisionTimeout::new_checked(field(8))
+                .ok_or(PackError::bad_index(8))?,
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        for (out, value) in buf.chunks_exact_mut(2).zip([
+            u16::from(self.connection_handle),
+            u16::from(self.interval_min),
+            u16::from(self.interval_max),
+            u16::from(self.max_latency),
+            u16::from(self.supervision_timeout),
+        ]) {
+            out.copy_from_slice(&value.to_le_bytes());
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_connection() {
+        let create = CreateConnection {
+            le_scan_interval: ScanInterval::new(0x0060),
+            le_scan_window: ScanWindow::new(0x0030),
+            initiator_filter_policy: InitiatorFilterPolicy::PeerAddress,
+            peer_address_type: PeerAddressType::Random,
+            peer_address: BTAddress([0x01, 0x02, 0x03, 0x04, 0x05, 0xC6]),
+            own_address_type: OwnAddressType::Public,
+            connection_interval_min: ConnectionInterval::new_checked(0x0018).unwrap(),
+            connection_interval_max: ConnectionInterval::new_checked(0x0028).unwrap(),
+            connection_latency: ConnectionLatency::new_checked(0).unwrap(),
+            supervision_timeout: SupervisionTimeout::new_checked(0x01F4).unwrap(),
+            min_ce_len: CELength(0),
+            max_ce_len: CELength(0),
+        };
+        let mut buf = [0_u8; CreateConnection::BYTE_LEN];
+        create.pack_into(&mut buf).unwrap();
+        assert_eq!(
+            &buf[13..21],
+            &[0x18, 0x00, 0x28, 0x00, 0x00, 0x00, 0xF4, 0x01]
+        );
+        assert_eq!(CreateConnection::unpack_from(&buf), Ok(create));
+        // 100 ms timeout, 50 ms interval with a latency of 1: (1 + 1) * 50 ms * 2 = 200 ms.
+        let short_timeout = CreateConnection {
+            connection_latency: ConnectionLatency::new_checked(1).unwrap(),
+            supervision_timeout: SupervisionTimeout::new_checked(0x000A).unwrap(),
+            ..create
+        };
+        assert_eq!(
+            short_timeout.validate(),
+            Err(ConnectionParametersError::SupervisionTimeoutTooShort)
+        );
+        assert_eq!(
+            short_timeout.pack_into(&mut buf),
+            Err(PackError::InvalidFields)
+        );
+
+        let extended = ExtendedCreateConnection {
+            initiator_filter_policy: InitiatorFilterPolicy::PeerAddress,
+            own_address_type: OwnAddressType::Public,
+            peer_address_type: PeerAddressType::Public,
+            peer_address: BTAddress([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]),
+            le_1m: Some(InitiatingPHYParameters::default()),
+            le_2m: None,
+            le_coded: Some(InitiatingPHYParameters::default()),
+        };
+        let mut buf = [0_u8; ExtendedCreateConnection::HEADER_LEN + 32];
+        assert_eq!(extended.byte_len(), buf.len());
+        extended.pack_into(&mut buf).unwrap();
+        assert_eq!(buf[9], 0x05);
+        assert_eq!(ExtendedCreateConnection::unpack_from(&buf), Ok(extended));
+        let only_2m = ExtendedCreateConnection {
+            le_1m: None,
+            le_2m: Some(InitiatingPHYParameters::default()),
+            le_coded: None,
+            ..extended
+        };
+        assert_eq!(
+            only_2m.validate(),
+            Err(ConnectionParametersError::NoInitiatingPHYs)
+        );
+    }
+    #[test]
+    fn test_connection_parameter_request() {
+        let event = [0x40, 0x00, 0x06, 0x00, 0x0C, 0x00, 0x04, 0x00, 0x2C, 0x01];
+        let request = RemoteConnectionParameterRequest::meta_unpack_from(&event).unwrap();
+        assert_eq!(u16::from(request.interval_max), 0x000C);
+        let mut buf = [0_u8; RemoteConnectionParameterRequest::BYTE_LEN];
+        request.meta_pack_into(&mut buf).unwrap();
+        assert_eq!(buf, event);
+        let reply = request.reply().unwrap();
+        let mut buf = [0_u8; 14];
+        assert_eq!(reply.byte_len(), buf.len());
+        reply.pack_into(&mut buf).unwrap();
+        assert_eq!(buf[..10], event);
+        assert_eq!(buf[10..], [0; 4]);
+        // 100 ms timeout, 15 ms interval with a latency of 4: (1 + 4) * 15 ms * 2 = 150 ms.
+        let too_short = RemoteConnectionParameterRequest {
+            supervision_timeout: SupervisionTimeout::new_checked(0x000A).unwrap(),
+            ..request
+        };
+        assert_eq!(
+            too_short.reply(),
+            Err(RemoteConnectionParameterRequestNegativeReply {
+                connection_handle: request.connection_handle,
+                reason: ErrorCode::UnacceptableConnectionParameters,
+            })
+        );
+
+        let complete = [0x00, 0x40, 0x00, 0x0C, 0x00, 0x04, 0x00, 0x2C, 0x01];
+        let complete = ConnectionUpdateComplete::meta_unpack_from(&complete).unwrap();
+        assert_eq!(complete.status, ErrorCode::Ok);
+        assert_eq!(u16::from(complete.supervision_timeout), 0x012C);
+    }
+}
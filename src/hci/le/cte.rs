@@ -0,0 +1,419 @@
+//! Connection oriented direction finding (Bluetooth 5.1). One side of a connection sends a
+//! Constant Tone Extension (CTE) after its packets, the other switches antennas while sampling it
+//! and reports the IQ samples ([`ConnectionIQReport`]) to the host, which estimates the angle of
+//! arrival (AoA) or departure (AoD) from them.
+//!
+//! The receiver enables sampling with [`SetConnectionCTEReceiveParameters`] and asks for CTEs with
+//! [`ConnectionCTERequestEnable`]. The transmitter sets which CTEs it can send with
+//! [`SetConnectionCTETransmitParameters`] and answers requests once
+//! [`ConnectionCTEResponseEnable`] is enabled.
+use crate::hci::command::Command;
+use crate::hci::event::CommandComplete;
+use crate::hci::le::power::ConnectionHandleReturn;
+use crate::hci::le::{LEControllerOpcode, MetaEvent, MetaEventCode};
+use crate::hci::{ErrorCode, Opcode};
+use crate::le::connection::{ConnectionEventCounter, ConnectionHandle};
+use crate::{ConversionError, PackError};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// Antenna switching pattern lengths allowed by the spec.
+pub const MIN_SWITCHING_PATTERN_LEN: usize = 2;
+pub const MAX_SWITCHING_PATTERN_LEN: usize = 0x4B;
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum CTEType {
+    AoA = 0x00,
+    /// AoD with 1 µs switching and sampling slots.
+    AoD1us = 0x01,
+    /// AoD with 2 µs switching and sampling slots.
+    AoD2us = 0x02,
+}
+impl From<CTEType> for u8 {
+    fn from(cte_type: CTEType) -> Self {
+        cte_type as u8
+    }
+}
+impl TryFrom<u8> for CTEType {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(CTEType::AoA),
+            0x01 => Ok(CTEType::AoD1us),
+            0x02 => Ok(CTEType::AoD2us),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// Set of [`CTEType`]s (bit `n` is the type with value `n`).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct CTETypes(pub u8);
+impl CTETypes {
+    pub const ALL: CTETypes = CTETypes(0b111);
+    pub fn with(self, cte_type: CTEType) -> CTETypes {
+        CTETypes(self.0 | 1 << u8::from(cte_type))
+    }
+    pub fn contains(self, cte_type: CTEType) -> bool {
+        self.0 & 1 << u8::from(cte_type) != 0
+    }
+}
+/// Sampling slot length of an AoA receiver.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum SlotDurations {
+    Us1 = 0x01,
+    Us2 = 0x02,
+}
+impl From<SlotDurations> for u8 {
+    fn from(slot_durations: SlotDurations) -> Self {
+        slot_durations as u8
+    }
+}
+impl TryFrom<u8> for SlotDurations {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(SlotDurations::Us1),
+            0x02 => Ok(SlotDurations::Us2),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+crate::hci_field_u8!(CTEType, SlotDurations);
+/// Packs `antenna_ids` as a `Switching_Pattern_Length` followed by the IDs.
+fn pack_antenna_ids(antenna_ids: &[u8], buf: &mut [u8]) -> Result<(), PackError> {
+    if !(MIN_SWITCHING_PATTERN_LEN..=MAX_SWITCHING_PATTERN_LEN).contains(&antenna_ids.len()) {
+        return Err(PackError::InvalidFields);
+    }
+    buf[0] = u8::try_from(antenna_ids.len()).expect("checked above");
+    buf[1..].copy_from_slice(antenna_ids);
+    Ok(())
+}
+fn unpack_antenna_ids(buf: &[u8], index: usize) -> Result<Vec<u8>, PackError> {
+    match buf.split_first() {
+        Some((&len, ids)) if ids.len() == usize::from(len) => Ok(ids.to_vec()),
+        _ => Err(PackError::bad_index(index)),
+    }
+}
+/// Enables (or disables) IQ sampling of the CTEs received on a connection. `antenna_ids` is the
+/// switching pattern used for AoA (ignored for AoD).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct SetConnectionCTEReceiveParameters {
+    pub connection_handle: ConnectionHandle,
+    pub sampling_enable: bool,
+    pub slot_durations: SlotDurations,
+    pub antenna_ids: Vec<u8>,
+}
+impl SetConnectionCTEReceiveParameters {
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetConnectionCTEReceiveParameters;
+    const HEADER_LEN: usize = 4;
+}
+impl Command for SetConnectionCTEReceiveParameters {
+    type Return = CommandComplete<ConnectionHandleReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::HEADER_LEN + 1 + self.antenna_ids.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        buf[0..2].copy_from_slice(&u16::from(self.connection_handle).to_le_bytes());
+        buf[2] = self.sampling_enable.into();
+        buf[3] = self.slot_durations.into();
+        pack_antenna_ids(&self.antenna_ids, &mut buf[Self::HEADER_LEN..])
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::atleast_length(Self::HEADER_LEN + 1, buf)?;
+        Ok(SetConnectionCTEReceiveParameters {
+            connection_handle: ConnectionHandle::new_checked(u16::from_le_bytes([buf[0], buf[1]]))
+                .ok_or(PackError::bad_index(0))?,
+            sampling_enable: match buf[2] {
+                0 => false,
+                1 => true,
+                _ => return Err(PackError::bad_index(2)),
+            },
+            slot_durations: SlotDurations::try_from(buf[3]).map_err(|_| PackError::bad_index(3))?,
+            antenna_ids: unpack_antenna_ids(&buf[Self::HEADER_LEN..], Self::HEADER_LEN)?,
+        })
+    }
+}
+/// Sets the CTE types the local side sends in response to CTE requests. `antenna_ids` is the
+/// switching pattern used for AoD (ignored if only AoA is allowed).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct SetConnectionCTETransmitParameters {
+    pub connection_handle: ConnectionHandle,
+    pub cte_types: CTETypes,
+    pub antenna_ids: Vec<u8>,
+}
+impl SetConnectionCTETransmitParameters {
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetConnectionCTETransmitParameters;
+    const HEADER_LEN: usize = 3;
+}
+impl Command for SetConnectionCTETransmitParameters {
+    type Return = CommandComplete<ConnectionHandleReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::HEADER_LEN + 1 + self.antenna_ids.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        buf[0..2].copy_from_slice(&u16::from(self.connection_handle).to_le_bytes());
+        buf[2] = self.cte_types.0;
+        pack_antenna_ids(&self.antenna_ids, &mut buf[Self::HEADER_LEN..])
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::atleast_length(Self::HEADER_LEN + 1, buf)?;
+        Ok(SetConnectionCTETransmitParameters {
+            connection_handle: ConnectionHandle::new_checked(u16::from_le_bytes([buf[0], buf[1]]))
+                .ok_or(PackError::bad_index(0))?,
+            cte_types: CTETypes(buf[2]),
+            antenna_ids: unpack_antenna_ids(&buf[Self::HEADER_LEN..], Self::HEADER_LEN)?,
+        })
+    }
+}
+crate::hci_command! {
+    /// Starts (or stops) asking the peer for a CTE every `cte_request_interval` connection events
+    /// (`0` for only once). `requested_cte_length` is in 8 µs units (`0x02..=0x14`).
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ConnectionCTERequestEnable: LEControllerOpcode::ConnectionCTERequestEnable
+        => CommandComplete<ConnectionHandleReturn> {
+        pub connection_handle: ConnectionHandle,
+        pub enable: bool,
+        pub cte_request_interval: u16,
+        pub requested_cte_length: u8,
+        pub requested_cte_type: CTEType,
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ConnectionCTEResponseEnable: LEControllerOpcode::ConnectionCTEResponseEnable
+        => CommandComplete<ConnectionHandleReturn> {
+        pub connection_handle: ConnectionHandle,
+        pub enable: bool,
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ReadAntennaInformation: LEControllerOpcode::ReadAntennaInformation
+        => CommandComplete<ReadAntennaInformationReturn> {}
+}
+crate::hci_return! {
+    /// `supported_switching_sampling_rates` bits: 1 µs switching for AoD transmission, 1 µs
+    /// sampling for AoD reception and 1 µs switching and sampling for AoA reception.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ReadAntennaInformationReturn {
+        pub supported_switching_sampling_rates: u8,
+        pub num_antennae: u8,
+        pub max_switching_pattern_length: u8,
+        /// In 8 µs units.
+        pub max_cte_length: u8,
+    }
+}
+/// How good the CTE the IQ samples come from was.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum IQPacketStatus {
+    CRCOk = 0x00,
+    /// Bad CRC, the CTE length and type came from the CTEInfo field.
+    CRCBadCTEInfo = 0x01,
+    /// Bad CRC, the CTE length and type were guessed some other way.
+    CRCBadOther = 0x02,
+    /// The controller couldn't sample (there are no samples).
+    InsufficientResources = 0xFF,
+}
+impl TryFrom<u8> for IQPacketStatus {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(IQPacketStatus::CRCOk),
+            0x01 => Ok(IQPacketStatus::CRCBadCTEInfo),
+            0x02 => Ok(IQPacketStatus::CRCBadOther),
+            0xFF => Ok(IQPacketStatus::InsufficientResources),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// One IQ sample. [`IQSample::NONE`] parts mean the sample wasn't valid.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct IQSample {
+    pub i: i8,
+    pub q: i8,
+}
+impl IQSample {
+    pub const NONE: i8 = i8::MIN;
+    pub fn is_valid(self) -> bool {
+        self.i != Self::NONE && self.q != Self::NONE
+    }
+}
+/// IQ samples of a CTE received on a connection.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ConnectionIQReport {
+    pub connection_handle: ConnectionHandle,
+    pub rx_phy: u8,
+    pub data_channel_index: u8,
+    /// In 0.1 dBm.
+    pub rssi: i16,
+    pub rssi_antenna_id: u8,
+    pub cte_type: CTEType,
+    pub slot_durations: SlotDurations,
+    pub packet_status: IQPacketStatus,
+    pub connection_event_counter: ConnectionEventCounter,
+    pub samples: Vec<IQSample>,
+}
+impl ConnectionIQReport {
+    const HEADER_LEN: usize = 13;
+}
+impl MetaEvent for ConnectionIQReport {
+    const META_CODE: MetaEventCode = MetaEventCode::ConnectionIQReport;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::HEADER_LEN + self.samples.len() * 2
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::atleast_length(Self::HEADER_LEN, buf)?;
+        let sample_count = usize::from(buf[12]);
+        PackError::expect_length(Self::HEADER_LEN + sample_count * 2, buf)?;
+        Ok(ConnectionIQReport {
+            connection_handle: ConnectionHandle::new_checked(u16::from_le_bytes([buf[0], buf[1]]))
+                .ok_or(PackError::bad_index(0))?,
+            rx_phy: buf[2],
+            data_channel_index: buf[3],
+            rssi: i16::from_le_bytes([buf[4], buf[5]]),
+            rssi_antenna_id: buf[6],
+            cte_type: CTEType::try_from(buf[7]).map_err(|_| PackError::bad_index(7))?,
+            slot_durations: SlotDurations::try_from(buf[8]).map_err(|_| PackError::bad_index(8))?,
+            packet_status: IQPacketStatus::try_from(buf[9]).map_err(|_| PackError::bad_index(9))?,
+            connection_event_counter: ConnectionEventCounter(u16::from_le_bytes([
+                buf[10], buf[11],
+            ])),
+            samples: buf[Self::HEADER_LEN..]
+                .chunks_exact(2)
+                .map(|sample| IQSample {
+                    i: i8::from_le_bytes([sample[0]]),
+                    q: i8::from_le_bytes([sample[1]]),
+                })
+                .collect(),
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.meta_byte_len(), buf)?;
+        buf[0..2].copy_from_slice(&u16::from(self.connection_handle).to_le_bytes());
+        buf[2] = self.rx_phy;
+        buf[3] = self.data_channel_index;
+        buf[4..6].copy_from_slice(&self.rssi.to_le_bytes());
+        buf[6] = self.rssi_antenna_id;
+        buf[7] = self.cte_type.into();
+        buf[8] = self.slot_durations.into();
+        buf[9] = self.packet_status as u8;
+        buf[10..12].copy_from_slice(&self.connection_event_counter.0.to_le_bytes());
+        buf[12] = u8::try_from(self.samples.len()).map_err(|_| PackError::bad_index(12))?;
+        for (out, sample) in buf[Self::HEADER_LEN..]
+            .chunks_exact_mut(2)
+            .zip(&self.samples)
+        {
+            out[0] = sample.i.to_le_bytes()[0];
+            out[1] = sample.q.to_le_bytes()[0];
+        }
+        Ok(())
+    }
+}
+/// The peer didn't answer a CTE request (or answered without a CTE).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct CTERequestFailed {
+    pub status: ErrorCode,
+    pub connection_handle: ConnectionHandle,
+}
+impl CTERequestFailed {
+    pub const BYTE_LEN: usize = 3;
+}
+impl MetaEvent for CTERequestFailed {
+    const META_CODE: MetaEventCode = MetaEventCode::CTERequestFailed;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        Ok(CTERequestFailed {
+            status: ErrorCode::try_from(buf[0]).map_err(|_| PackError::bad_index(0))?,
+            connection_handle: ConnectionHandle::new_checked(u16::from_le_bytes([buf[1], buf[2]]))
+                .ok_or(PackError::bad_index(1))?,
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf[0] = self.status.into();
+        buf[1..3].copy_from_slice(&u16::from(self.connection_handle).to_le_bytes());
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_cte_packets() {
+        let handle = ConnectionHandle::new(0x0040);
+        let receive = SetConnectionCTEReceiveParameters {
+            connection_handle: handle,
+            sampling_enable: true,
+            slot_durations: SlotDurations::Us2,
+            antenna_ids: vec![0, 1, 2, 3],
+        };
+        let mut buf = vec![0_u8; receive.byte_len()];
+        receive.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x40, 0x00, 0x01, 0x02, 0x04, 0, 1, 2, 3]);
+        assert_eq!(
+            SetConnectionCTEReceiveParameters::unpack_from(&buf).unwrap(),
+            receive
+        );
+        let transmit = SetConnectionCTETransmitParameters {
+            connection_handle: handle,
+            cte_types: CTETypes::default().with(CTEType::AoA),
+            antenna_ids: vec![0],
+        };
+        let mut buf = vec![0_u8; transmit.byte_len()];
+        assert_eq!(transmit.pack_into(&mut buf), Err(PackError::InvalidFields));
+
+        let mut report = vec![
+            0x40, 0x00, 0x01, 0x05, 0xA6, 0xFD, 0x00, 0x00, 0x01, 0x00, 0x10, 0x00, 0x02,
+        ];
+        report.extend_from_slice(&[0x05, 0xFB, 0x80, 0x10]);
+        let event = ConnectionIQReport::meta_unpack_from(&report).unwrap();
+        assert_eq!(event.rssi, -602);
+        assert_eq!(event.connection_event_counter, ConnectionEventCounter(16));
+        assert_eq!(event.samples[0], IQSample { i: 5, q: -5 });
+        assert!(!event.samples[1].is_valid());
+        let mut buf = vec![0_u8; event.meta_byte_len()];
+        event.meta_pack_into(&mut buf).unwrap();
+        assert_eq!(buf, report);
+        assert!(ConnectionIQReport::meta_unpack_from(&report[..15]).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! LE Data Length Extension (Bluetooth 4.2). Lets a connection use link layer data PDUs with up
+//! to 251 byte payloads instead of 27, which is most of the throughput gain on LE links.
+//! [`DataLengthChange`] reports the lengths in use after the link layer negotiated them.
+use crate::hci::definitions::HCIField;
+use crate::hci::event::{CommandComplete, StatusReturn};
+use crate::hci::le::power::ConnectionHandleReturn;
+use crate::hci::le::{LEControllerOpcode, MetaEvent, MetaEventCode};
+use crate::le::connection::ConnectionHandle;
+use crate::PackError;
+
+/// Most payload octets of a link layer data PDU and the time (in µs) to send (or receive) one.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct DataLength {
+    pub octets: u16,
+    pub time: u16,
+}
+impl DataLength {
+    pub const BYTE_LEN: usize = 4;
+    pub const MIN_OCTETS: u16 = 0x001B;
+    pub const MAX_OCTETS: u16 = 0x00FB;
+    pub const MIN_TIME: u16 = 0x0148;
+    /// Time of the longest PDU on the LE Coded PHY (S=8).
+    pub const MAX_TIME: u16 = 0x4290;
+    /// 27 octets, what every controller supports.
+    pub const MIN: DataLength = DataLength::le_1m(Self::MIN_OCTETS);
+    /// 251 octets on the LE 1M PHY.
+    pub const MAX_1M: DataLength = DataLength::le_1m(Self::MAX_OCTETS);
+    /// `octets` with the time it takes on the LE 1M PHY (8 µs per octet plus 14 octets of
+    /// preamble, access address, header, MIC and CRC).
+    pub const fn le_1m(octets: u16) -> DataLength {
+        DataLength {
+            octets,
+            time: (octets + 14) * 8,
+        }
+    }
+    pub fn is_valid(&self) -> bool {
+        (Self::MIN_OCTETS..=Self::MAX_OCTETS).contains(&self.octets)
+            && (Self::MIN_TIME..=Self::MAX_TIME).contains(&self.time)
+    }
+}
+impl Default for DataLength {
+    fn default() -> Self {
+        Self::MIN
+    }
+}
+impl HCIField for DataLength {
+    const BYTE_LEN: usize = DataLength::BYTE_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        self.octets.pack_field(&mut buf[0..2]);
+        self.time.pack_field(&mut buf[2..4]);
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        Some(DataLength {
+            octets: u16::unpack_field(&buf[0..2])?,
+            time: u16::unpack_field(&buf[2..4])?,
+        })
+    }
+}
+crate::hci_command! {
+    /// Suggests the most the controller should send on the connection. The link layer
+    /// negotiates the lengths with the peer and reports them with [`DataLengthChange`].
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct SetDataLength: LEControllerOpcode::SetDataLength
+        => CommandComplete<ConnectionHandleReturn> {
+        pub connection_handle: ConnectionHandle,
+        pub tx: DataLength,
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ReadSuggestedDefaultDataLength: LEControllerOpcode::ReadSuggestedDefaultDataLength
+        => CommandComplete<SuggestedDefaultDataLengthReturn> {}
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct SuggestedDefaultDataLengthReturn {
+        pub suggested_max_tx: DataLength,
+    }
+}
+crate::hci_command! {
+    /// Default transmit data length for new connections.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct WriteSuggestedDefaultDataLength: LEControllerOpcode::WriteSuggestedDefaultDataLength
+        => CommandComplete<StatusReturn> {
+        pub suggested_max_tx: DataLength,
+    }
+}
+/// LE Data Length Change event. The most the link layer now sends (`max_tx`) and expects to
+/// receive (`max_rx`) on the connection.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct DataLengthChange {
+    pub connection_handle: ConnectionHandle,
+    pub max_tx: DataLength,
+    pub max_rx: DataLength,
+}
+impl DataLengthChange {
+    pub const BYTE_LEN: usize = ConnectionHandle::BYTE_LEN + DataLength::BYTE_LEN * 2;
+}
+impl MetaEvent for DataLengthChange {
+    const META_CODE: MetaEventCode = MetaEventCode::DataLengthChange;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        Ok(DataLengthChange {
+            connection_handle: ConnectionHandle::unpack_field(&buf[0..2])
+                .ok_or(PackError::bad_index(0))?,
+            max_tx: DataLength::unpack_field(&buf[2..6]).ok_or(PackError::bad_index(2))?,
+            max_rx: DataLength::unpack_field(&buf[6..10]).ok_or(PackError::bad_index(6))?,
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        self.connection_handle.pack_field(&mut buf[0..2]);
+        self.max_tx.pack_field(&mut buf[2..6]);
+        self.max_rx.pack_field(&mut buf[6..10]);
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::command::Command;
+
+    #[test]
+    fn test_data_length() {
+        assert_eq!(DataLength::MIN.time, DataLength::MIN_TIME);
+        assert_eq!(DataLength::MAX_1M.time, 2120);
+        assert!(DataLength::MAX_1M.is_valid());
+        assert!(!DataLength::le_1m(300).is_valid());
+
+        let set = SetDataLength {
+            connection_handle: ConnectionHandle::new_checked(0x0040).unwrap(),
+            tx: DataLength::MAX_1M,
+        };
+        let mut buf = [0_u8; 6];
+        assert_eq!(set.byte_len(), buf.len());
+        set.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x40, 0x00, 0xFB, 0x00, 0x48, 0x08]);
+        assert_eq!(SetDataLength::unpack_from(&buf), Ok(set));
+
+        let event = [0x40, 0x00, 0xFB, 0x00, 0x48, 0x08, 0x1B, 0x00, 0x48, 0x01];
+        let change = DataLengthChange::meta_unpack_from(&event).unwrap();
+        assert_eq!(change.max_tx, DataLength::MAX_1M);
+        assert_eq!(change.max_rx, DataLength::MIN);
+    }
+}
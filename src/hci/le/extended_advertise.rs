@@ -0,0 +1,981 @@
+//! LE extended advertising (Bluetooth 5.0). Advertising sets are created with
+//! [`SetExtendedAdvertisingParameters`] under an advertising handle (see
+//! [`AdvertisingHandles`]), get their data from fragmented [`SetExtendedAdvertisingData`] and
+//! [`SetExtendedScanResponseData`] updates (see [`ExtendedAdvertisingData`]), are enabled with
+//! [`SetExtendedAdvertisingEnable`] (with per set duration and event limits) and removed with
+//! [`RemoveAdvertisingSet`]/[`ClearAdvertisingSets`]. The [`AdvertisingSetTerminated`] event
+//! reports why a set stopped.
+use crate::hci::command::Command;
+use crate::hci::definitions::{FieldReader, FieldWriter, HCIField};
+use crate::hci::event::{CommandComplete, StatusReturn};
+use crate::hci::le::{LEControllerOpcode, MetaEvent, MetaEventCode};
+use crate::hci::{ErrorCode, Opcode};
+use crate::le::advertiser::{
+    AdvertisingInterval, ChannelMap, FilterPolicy, OwnAddressType, PeerAddressType,
+};
+use crate::le::connection::ConnectionHandle;
+use crate::{BTAddress, ConversionError, PackError};
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::time::Duration;
+
+/// Enables one advertising set. A `duration` of `0` advertises until disabled. Otherwise the set
+/// stops after `duration * 10 ms`. A `max_extended_advertising_events` of `0` means no limit.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct AdvertisingSetEnable {
+    pub advertising_handle: u8,
+    pub duration: u16,
+    pub max_extended_advertising_events: u8,
+}
+impl AdvertisingSetEnable {
+    pub const BYTE_LEN: usize = 4;
+    pub const DURATION_UNIT: Duration = Duration::from_millis(10);
+    pub fn new(advertising_handle: u8) -> AdvertisingSetEnable {
+        AdvertisingSetEnable {
+            advertising_handle,
+            duration: 0,
+            max_extended_advertising_events: 0,
+        }
+    }
+    /// Stop advertising after `duration` (rounded up to 10 ms, at most ~655 s). Returns `None`
+    /// if `duration` is zero or too long.
+    pub fn with_duration(self, duration: Duration) -> Option<AdvertisingSetEnable> {
+        let (micros, unit) = (duration.as_micros(), Self::DURATION_UNIT.as_micros());
+        let units = micros / unit + u128::from(micros % unit != 0);
+        match u16::try_from(units) {
+            Ok(0) | Err(_) => None,
+            Ok(duration) => Some(AdvertisingSetEnable { duration, ..self }),
+        }
+    }
+    #[must_use]
+    pub fn with_max_events(self, max_extended_advertising_events: u8) -> AdvertisingSetEnable {
+        AdvertisingSetEnable {
+            max_extended_advertising_events,
+            ..self
+        }
+    }
+}
+impl HCIField for AdvertisingSetEnable {
+    const BYTE_LEN: usize = AdvertisingSetEnable::BYTE_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf[0] = self.advertising_handle;
+        self.duration.pack_field(&mut buf[1..3]);
+        buf[3] = self.max_extended_advertising_events;
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        Some(AdvertisingSetEnable {
+            advertising_handle: buf[0],
+            duration: u16::unpack_field(&buf[1..3])?,
+            max_extended_advertising_events: buf[3],
+        })
+    }
+}
+/// Enables or disables advertising sets. Disabling with no `sets` disables every set.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct SetExtendedAdvertisingEnable {
+    pub is_enabled: bool,
+    pub sets: Vec<AdvertisingSetEnable>,
+}
+impl SetExtendedAdvertisingEnable {
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetExtendedAdvertisingEnable;
+}
+impl Command for SetExtendedAdvertisingEnable {
+    type Return = CommandComplete<StatusReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        2 + self.sets.len() * AdvertisingSetEnable::BYTE_LEN
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        buf[0] = self.is_enabled.into();
+        buf[1] = u8::try_from(self.sets.len()).map_err(|_| PackError::bad_index(1))?;
+        for (set, out) in self
+            .sets
+            .iter()
+            .zip(buf[2..].chunks_exact_mut(AdvertisingSetEnable::BYTE_LEN))
+        {
+            set.pack_field(out);
+        }
+        Ok(())
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::atleast_length(2, buf)?;
+        PackError::expect_length(
+            2 + usize::from(buf[1]) * AdvertisingSetEnable::BYTE_LEN,
+            buf,
+        )?;
+        Ok(SetExtendedAdvertisingEnable {
+            is_enabled: bool::unpack_field(&buf[..1]).ok_or(PackError::bad_index(0))?,
+            sets: buf[2..]
+                .chunks_exact(AdvertisingSetEnable::BYTE_LEN)
+                .filter_map(AdvertisingSetEnable::unpack_field)
+                .collect(),
+        })
+    }
+}
+/// Longest advertising data of one advertising set.
+pub const MAX_EXTENDED_ADVERTISING_DATA_LEN: usize = 1650;
+/// Most advertising data one [`SetExtendedAdvertisingData`] command carries.
+pub const MAX_ADVERTISING_DATA_FRAGMENT_LEN: usize = 251;
+/// Which part of the advertising data a [`SetExtendedAdvertisingData`] command carries.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum DataOperation {
+    IntermediateFragment = 0x00,
+    /// Replaces the old data.
+    FirstFragment = 0x01,
+    LastFragment = 0x02,
+    /// Replaces the old data with a single fragment.
+    Complete = 0x03,
+    /// Keeps the data but updates the Advertising DID so scanners read it again. Only allowed
+    /// while the set is enabled.
+    UnchangedData = 0x04,
+}
+impl DataOperation {
+    /// Operation of fragment `index` when the data is split into fragments `0..=last`.
+    pub fn of_fragment(index: usize, last: usize) -> DataOperation {
+        match index {
+            _ if last == 0 => DataOperation::Complete,
+            0 => DataOperation::FirstFragment,
+            i if i == last => DataOperation::LastFragment,
+            _ => DataOperation::IntermediateFragment,
+        }
+    }
+}
+impl From<DataOperation> for u8 {
+    fn from(operation: DataOperation) -> Self {
+        operation as u8
+    }
+}
+impl TryFrom<u8> for DataOperation {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(DataOperation::IntermediateFragment),
+            0x01 => Ok(DataOperation::FirstFragment),
+            0x02 => Ok(DataOperation::LastFragment),
+            0x03 => Ok(DataOperation::Complete),
+            0x04 => Ok(DataOperation::UnchangedData),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// If the controller may fragment the data over the air.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum FragmentPreference {
+    MayFragment = 0x00,
+    /// Prefer as few fragments as possible.
+    ShouldNotFragment = 0x01,
+}
+impl Default for FragmentPreference {
+    fn default() -> Self {
+        FragmentPreference::MayFragment
+    }
+}
+impl From<FragmentPreference> for u8 {
+    fn from(preference: FragmentPreference) -> Self {
+        preference as u8
+    }
+}
+impl TryFrom<u8> for FragmentPreference {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(FragmentPreference::MayFragment),
+            0x01 => Ok(FragmentPreference::ShouldNotFragment),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// LE Set Extended Advertising Data. `data` holds at most
+/// [`MAX_ADVERTISING_DATA_FRAGMENT_LEN`] bytes.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct SetExtendedAdvertisingData {
+    pub advertising_handle: u8,
+    pub operation: DataOperation,
+    pub fragment_preference: FragmentPreference,
+    pub data: Vec<u8>,
+}
+impl SetExtendedAdvertisingData {
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetExtendedAdvertisingData;
+}
+impl Command for SetExtendedAdvertisingData {
+    type Return = CommandComplete<StatusReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        FRAGMENT_HEADER_LEN + self.data.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        pack_fragment(
+            self.advertising_handle,
+            self.operation,
+            self.fragment_preference,
+            &self.data,
+            buf,
+        )
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        let (advertising_handle, operation, fragment_preference, data) = unpack_fragment(buf)?;
+        Ok(SetExtendedAdvertisingData {
+            advertising_handle,
+            operation,
+            fragment_preference,
+            data,
+        })
+    }
+}
+/// Length of the handle, operation, fragment preference and data length in front of the data of
+/// [`SetExtendedAdvertisingData`] and [`SetExtendedScanResponseData`].
+const FRAGMENT_HEADER_LEN: usize = 4;
+fn pack_fragment(
+    advertising_handle: u8,
+    operation: DataOperation,
+    fragment_preference: FragmentPreference,
+    data: &[u8],
+    buf: &mut [u8],
+) -> Result<(), PackError> {
+    PackError::expect_length(FRAGMENT_HEADER_LEN + data.len(), buf)?;
+    if advertising_handle > MAX_ADVERTISING_HANDLE {
+        return Err(PackError::bad_index(0));
+    }
+    buf[0] = advertising_handle;
+    buf[1] = operation.into();
+    buf[2] = fragment_preference.into();
+    buf[3] = u8::try_from(data.len())
+        .ok()
+        .filter(|&len| usize::from(len) <= MAX_ADVERTISING_DATA_FRAGMENT_LEN)
+        .ok_or(PackError::bad_index(3))?;
+    buf[FRAGMENT_HEADER_LEN..].copy_from_slice(data);
+    Ok(())
+}
+fn unpack_fragment(
+    buf: &[u8],
+) -> Result<(u8, DataOperation, FragmentPreference, Vec<u8>), PackError> {
+    PackError::atleast_length(FRAGMENT_HEADER_LEN, buf)?;
+    PackError::expect_length(FRAGMENT_HEADER_LEN + usize::from(buf[3]), buf)?;
+    Ok((
+        buf[0],
+        DataOperation::try_from(buf[1]).map_err(|_| PackError::bad_index(1))?,
+        FragmentPreference::try_from(buf[2]).map_err(|_| PackError::bad_index(2))?,
+        buf[FRAGMENT_HEADER_LEN..].to_vec(),
+    ))
+}
+/// LE Set Extended Scan Response Data. Same layout as [`SetExtendedAdvertisingData`] but the
+/// controller doesn't take `DataOperation::UnchangedData` and scannable sets only take the
+/// whole response while disabled.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct SetExtendedScanResponseData {
+    pub advertising_handle: u8,
+    pub operation: DataOperation,
+    pub fragment_preference: FragmentPreference,
+    pub data: Vec<u8>,
+}
+impl SetExtendedScanResponseData {
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetExtendedScanResponseData;
+}
+impl Command for SetExtendedScanResponseData {
+    type Return = CommandComplete<StatusReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        FRAGMENT_HEADER_LEN + self.data.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        if self.operation == DataOperation::UnchangedData {
+            return Err(PackError::bad_index(1));
+        }
+        pack_fragment(
+            self.advertising_handle,
+            self.operation,
+            self.fragment_preference,
+            &self.data,
+            buf,
+        )
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        let (advertising_handle, operation, fragment_preference, data) = unpack_fragment(buf)?;
+        if operation == DataOperation::UnchangedData {
+            return Err(PackError::bad_index(1));
+        }
+        Ok(SetExtendedScanResponseData {
+            advertising_handle,
+            operation,
+            fragment_preference,
+            data,
+        })
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum AdvertisingDataError {
+    /// Longer than [`MAX_EXTENDED_ADVERTISING_DATA_LEN`].
+    TooLong,
+    /// Controllers only take data changes of a single fragment while the set is enabled.
+    FragmentedWhileEnabled,
+}
+impl core::fmt::Display for AdvertisingDataError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "advertising data error {:?}", self)
+    }
+}
+impl crate::error::Error for AdvertisingDataError {}
+#[cfg(feature = "std")]
+impl std::error::Error for AdvertisingDataError {}
+/// Advertising data of one set as last sent to the controller. Plans the
+/// [`SetExtendedAdvertisingData`] commands for the next update so unchanged data isn't sent
+/// again.
+///
+/// Every `FirstFragment`/`Complete` operation replaces the whole data, so changed data is
+/// always sent in full. While the set is enabled the controller only takes a single `Complete`
+/// fragment (or `UnchangedData`), so longer data can only change while the set is disabled.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ExtendedAdvertisingData {
+    pub advertising_handle: u8,
+    pub fragment_preference: FragmentPreference,
+    current: Option<Vec<u8>>,
+}
+impl ExtendedAdvertisingData {
+    pub fn new(advertising_handle: u8) -> ExtendedAdvertisingData {
+        ExtendedAdvertisingData {
+            advertising_handle,
+            fragment_preference: FragmentPreference::default(),
+            current: None,
+        }
+    }
+    /// The data the controller has (after [`ExtendedAdvertisingData::commit`]).
+    pub fn current(&self) -> Option<&[u8]> {
+        self.current.as_deref()
+    }
+    fn command(&self, operation: DataOperation, data: &[u8]) -> SetExtendedAdvertisingData {
+        SetExtendedAdvertisingData {
+            advertising_handle: self.advertising_handle,
+            operation,
+            fragment_preference: self.fragment_preference,
+            data: data.to_vec(),
+        }
+    }
+    /// Commands that change the controller's data to `data`. Empty if `data` is what the
+    /// controller already has.
+    pub fn update(
+        &self,
+        data: &[u8],
+        is_enabled: bool,
+    ) -> Result<Vec<SetExtendedAdvertisingData>, AdvertisingDataError> {
+        if data.len() > MAX_EXTENDED_ADVERTISING_DATA_LEN {
+            return Err(AdvertisingDataError::TooLong);
+        }
+        if self.current.as_deref() == Some(data) {
+            return Ok(Vec::new());
+        }
+        if data.len() <= MAX_ADVERTISING_DATA_FRAGMENT_LEN {
+            return Ok(alloc::vec![self.command(DataOperation::Complete, data)]);
+        }
+        if is_enabled {
+            return Err(AdvertisingDataError::FragmentedWhileEnabled);
+        }
+        let fragments = data.chunks(MAX_ADVERTISING_DATA_FRAGMENT_LEN);
+        let last = fragments.len() - 1;
+        Ok(fragments
+            .enumerate()
+            .map(|(i, fragment)| self.command(DataOperation::of_fragment(i, last), fragment))
+            .collect())
+    }
+    /// Command that keeps the data but makes scanners read it again (new Advertising DID).
+    pub fn refresh(&self) -> SetExtendedAdvertisingData {
+        self.command(DataOperation::UnchangedData, &[])
+    }
+    /// Records `data` as sent. Call after every command of [`ExtendedAdvertisingData::update`]
+    /// succeeded.
+    pub fn commit(&mut self, data: &[u8]) {
+        self.current = Some(data.to_vec());
+    }
+    /// Forgets the data (for example after the controller was reset).
+    pub fn clear(&mut self) {
+        self.current = None;
+    }
+}
+/// Highest advertising handle. Controllers support fewer sets (see
+/// [`ReadNumberOfSupportedAdvertisingSets`]).
+pub const MAX_ADVERTISING_HANDLE: u8 = 0xEF;
+/// Hands out advertising handles. Sets are created by
+/// [`SetExtendedAdvertisingParameters`] with a free handle and freed by [`RemoveAdvertisingSet`]
+/// (or all at once by [`ClearAdvertisingSets`]).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct AdvertisingHandles {
+    used: BTreeSet<u8>,
+    supported_sets: u8,
+}
+impl AdvertisingHandles {
+    /// `supported_sets` is the controller's `num_supported_advertising_sets`.
+    pub fn new(supported_sets: u8) -> AdvertisingHandles {
+        AdvertisingHandles {
+            used: BTreeSet::new(),
+            supported_sets: supported_sets.min(MAX_ADVERTISING_HANDLE + 1),
+        }
+    }
+    /// Lowest free handle. `None` if every supported set is in use.
+    pub fn allocate(&mut self) -> Option<u8> {
+        let handle = (0..self.supported_sets).find(|h| !self.used.contains(h))?;
+        self.used.insert(handle);
+        Some(handle)
+    }
+    /// Returns `false` if `handle` wasn't allocated.
+    pub fn release(&mut self, handle: u8) -> bool {
+        self.used.remove(&handle)
+    }
+    pub fn is_allocated(&self, handle: u8) -> bool {
+        self.used.contains(&handle)
+    }
+    /// Frees every handle (after [`ClearAdvertisingSets`] or a reset).
+    pub fn clear(&mut self) {
+        self.used.clear();
+    }
+}
+/// Advertising event properties of an advertising set. Legacy PDUs are only allowed in the
+/// `ADV_*` combinations.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct AdvertisingEventProperties(pub u16);
+impl AdvertisingEventProperties {
+    pub const CONNECTABLE: u16 = 0x0001;
+    pub const SCANNABLE: u16 = 0x0002;
+    pub const DIRECTED: u16 = 0x0004;
+    /// High duty cycle connectable directed advertising (legacy PDUs only).
+    pub const HIGH_DUTY_CYCLE: u16 = 0x0008;
+    pub const LEGACY: u16 = 0x0010;
+    /// Omit the advertiser's address from all PDUs (extended PDUs only).
+    pub const ANONYMOUS: u16 = 0x0020;
+    pub const INCLUDE_TX_POWER: u16 = 0x0040;
+    pub const ALL: u16 = 0x007F;
+    pub const ADV_IND: AdvertisingEventProperties = AdvertisingEventProperties(0x0013);
+    pub const ADV_DIRECT_IND_LOW_DUTY: AdvertisingEventProperties =
+        AdvertisingEventProperties(0x0015);
+    pub const ADV_DIRECT_IND_HIGH_DUTY: AdvertisingEventProperties =
+        AdvertisingEventProperties(0x001D);
+    pub const ADV_SCAN_IND: AdvertisingEventProperties = AdvertisingEventProperties(0x0012);
+    pub const ADV_NONCONN_IND: AdvertisingEventProperties = AdvertisingEventProperties(0x0010);
+    pub const fn contains(self, bits: u16) -> bool {
+        self.0 & bits == bits
+    }
+    /// `true` if the combination is allowed.
+    pub fn is_valid(self) -> bool {
+        if self.0 & !Self::ALL != 0 {
+            return false;
+        }
+        if self.contains(Self::LEGACY) {
+            return !self.contains(Self::ANONYMOUS)
+                && !self.contains(Self::INCLUDE_TX_POWER)
+                && [
+                    Self::ADV_IND,
+                    Self::ADV_DIRECT_IND_LOW_DUTY,
+                    Self::ADV_DIRECT_IND_HIGH_DUTY,
+                    Self::ADV_SCAN_IND,
+                    Self::ADV_NONCONN_IND,
+                ]
+                .contains(&self);
+        }
+        // Extended PDUs can't be connectable and scannable at once or high duty cycle.
+        !(self.contains(Self::CONNECTABLE | Self::SCANNABLE)
+            || self.contains(Self::HIGH_DUTY_CYCLE)
+            || (self.contains(Self::ANONYMOUS) && self.contains(Self::CONNECTABLE)))
+    }
+}
+impl HCIField for AdvertisingEventProperties {
+    const BYTE_LEN: usize = 2;
+    fn pack_field(&self, buf: &mut [u8]) {
+        self.0.pack_field(buf);
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        Some(AdvertisingEventProperties(u16::unpack_field(buf)?)).filter(|p| p.0 & !Self::ALL == 0)
+    }
+}
+/// Primary advertising interval of an advertising set in units of 0.625 ms. 24 bits wide, up to
+/// ~10485 s.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ExtendedAdvertisingInterval(u32);
+impl ExtendedAdvertisingInterval {
+    pub const BYTE_LEN: usize = 3;
+    pub const MIN_U32: u32 = 0x0000_0020;
+    pub const MAX_U32: u32 = 0x00FF_FFFF;
+    pub const MIN: ExtendedAdvertisingInterval = ExtendedAdvertisingInterval(Self::MIN_U32);
+    pub const MAX: ExtendedAdvertisingInterval = ExtendedAdvertisingInterval(Self::MAX_U32);
+    pub const DEFAULT: ExtendedAdvertisingInterval =
+        ExtendedAdvertisingInterval(AdvertisingInterval::DEFAULT_U16 as u32);
+    pub const fn as_microseconds(self) -> u64 {
+        self.0 as u64 * 625
+    }
+    pub const fn as_duration(self) -> Duration {
+        Duration::from_micros(self.as_microseconds())
+    }
+}
+impl Default for ExtendedAdvertisingInterval {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+impl From<AdvertisingInterval> for ExtendedAdvertisingInterval {
+    fn from(interval: AdvertisingInterval) -> Self {
+        ExtendedAdvertisingInterval(u32::from(u16::from(interval)))
+    }
+}
+impl From<ExtendedAdvertisingInterval> for u32 {
+    fn from(interval: ExtendedAdvertisingInterval) -> Self {
+        interval.0
+    }
+}
+impl TryFrom<u32> for ExtendedAdvertisingInterval {
+    type Error = ConversionError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        if (Self::MIN_U32..=Self::MAX_U32).contains(&value) {
+            Ok(ExtendedAdvertisingInterval(value))
+        } else {
+            Err(ConversionError(()))
+        }
+    }
+}
+impl HCIField for ExtendedAdvertisingInterval {
+    const BYTE_LEN: usize = ExtendedAdvertisingInterval::BYTE_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.0.to_le_bytes()[..3]);
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        Self::try_from(u32::from_le_bytes([buf[0], buf[1], buf[2], 0])).ok()
+    }
+}
+/// PHY advertising PDUs are sent on. The primary channels only use [`AdvertisingPHY::LE1M`] and
+/// [`AdvertisingPHY::LECoded`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub enum AdvertisingPHY {
+    #[default]
+    LE1M = 0x01,
+    LE2M = 0x02,
+    LECoded = 0x03,
+}
+impl From<AdvertisingPHY> for u8 {
+    fn from(phy: AdvertisingPHY) -> Self {
+        phy as u8
+    }
+}
+impl TryFrom<u8> for AdvertisingPHY {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(AdvertisingPHY::LE1M),
+            0x02 => Ok(AdvertisingPHY::LE2M),
+            0x03 => Ok(AdvertisingPHY::LECoded),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+crate::hci_field_u8!(AdvertisingPHY, ChannelMap, FilterPolicy);
+/// Highest advertising SID.
+pub const MAX_ADVERTISING_SID: u8 = 0x0F;
+/// `advertising_tx_power` value letting the controller pick.
+pub const TX_POWER_NO_PREFERENCE: i8 = 0x7F;
+/// Parameters of one advertising set.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ExtendedAdvertisingParameters {
+    pub event_properties: AdvertisingEventProperties,
+    pub interval_min: ExtendedAdvertisingInterval,
+    pub interval_max: ExtendedAdvertisingInterval,
+    pub channel_map: ChannelMap,
+    pub own_address_type: OwnAddressType,
+    pub peer_address_type: PeerAddressType,
+    pub peer_address: BTAddress,
+    pub filter_policy: FilterPolicy,
+    /// Highest transmit power in dBm or [`TX_POWER_NO_PREFERENCE`].
+    pub advertising_tx_power: i8,
+    pub primary_phy: AdvertisingPHY,
+    /// Advertising events the controller may skip sending `AUX_ADV_IND` in.
+    pub secondary_max_skip: u8,
+    pub secondary_phy: AdvertisingPHY,
+    /// Advertising set ID scanners use to tell sets apart (up to [`MAX_ADVERTISING_SID`]).
+    pub sid: u8,
+    pub scan_request_notification: bool,
+}
+impl ExtendedAdvertisingParameters {
+    pub const BYTE_LEN: usize = 24;
+    pub const DEFAULT: ExtendedAdvertisingParameters = ExtendedAdvertisingParameters {
+        event_properties: AdvertisingEventProperties::ADV_IND,
+        interval_min: ExtendedAdvertisingInterval::DEFAULT,
+        interval_max: ExtendedAdvertisingInterval::DEFAULT,
+        channel_map: ChannelMap::ALL,
+        own_address_type: OwnAddressType::DEFAULT,
+        peer_address_type: PeerAddressType::DEFAULT,
+        peer_address: BTAddress::ZEROED,
+        filter_policy: FilterPolicy::DEFAULT,
+        advertising_tx_power: TX_POWER_NO_PREFERENCE,
+        primary_phy: AdvertisingPHY::LE1M,
+        secondary_max_skip: 0,
+        secondary_phy: AdvertisingPHY::LE1M,
+        sid: 0,
+        scan_request_notification: false,
+    };
+    /// # Errors
+    /// returns `PackError::InvalidFields` for invalid event property combinations, an inverted
+    /// interval range, an empty channel map, a 2M primary PHY, legacy advertising off the 1M PHY
+    /// or an out of range SID.
+    pub fn validate(&self) -> Result<(), PackError> {
+        let legacy = self
+            .event_properties
+            .contains(AdvertisingEventProperties::LEGACY);
+        let high_duty = self
+            .event_properties
+            .contains(AdvertisingEventProperties::HIGH_DUTY_CYCLE);
+        if !self.event_properties.is_valid()
+            || (!high_duty && self.interval_max < self.interval_min)
+            || self.channel_map.is_empty()
+            || self.primary_phy == AdvertisingPHY::LE2M
+            || (legacy && self.primary_phy != AdvertisingPHY::LE1M)
+            || self.sid > MAX_ADVERTISING_SID
+        {
+            return Err(PackError::InvalidFields);
+        }
+        Ok(())
+    }
+}
+impl Default for ExtendedAdvertisingParameters {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+/// LE Set Extended Advertising Parameters. Creates advertising set `advertising_handle` if it
+/// doesn't exist yet.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct SetExtendedAdvertisingParameters {
+    pub advertising_handle: u8,
+    pub parameters: ExtendedAdvertisingParameters,
+}
+impl SetExtendedAdvertisingParameters {
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetExtendedAdvertisingParameters;
+    pub const BYTE_LEN: usize = 1 + ExtendedAdvertisingParameters::BYTE_LEN;
+}
+impl Command for SetExtendedAdvertisingParameters {
+    type Return = CommandComplete<SetExtendedAdvertisingParametersReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        if self.advertising_handle > MAX_ADVERTISING_HANDLE {
+            return Err(PackError::bad_index(0));
+        }
+        let p = &self.parameters;
+        p.validate()?;
+        let mut writer = FieldWriter::new(buf);
+        writer.field(&self.advertising_handle)?;
+        writer.field(&p.event_properties)?;
+        writer.field(&p.interval_min)?;
+        writer.field(&p.interval_max)?;
+        writer.field(&p.channel_map)?;
+        writer.field(&p.own_address_type)?;
+        writer.field(&p.peer_address_type)?;
+        writer.field(&p.peer_address)?;
+        writer.field(&p.filter_policy)?;
+        writer.field(&p.advertising_tx_power)?;
+        writer.field(&p.primary_phy)?;
+        writer.field(&p.secondary_max_skip)?;
+        writer.field(&p.secondary_phy)?;
+        writer.field(&p.sid)?;
+        writer.field(&p.scan_request_notification)?;
+        writer.finish()
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        let mut reader = FieldReader::new(buf);
+        let command = SetExtendedAdvertisingParameters {
+            advertising_handle: reader.field()?,
+            parameters: ExtendedAdvertisingParameters {
+                event_properties: reader.field()?,
+                interval_min: reader.field()?,
+                interval_max: reader.field()?,
+                channel_map: reader.field()?,
+                own_address_type: reader.field()?,
+                peer_address_type: reader.field()?,
+                peer_address: reader.field()?,
+                filter_policy: reader.field()?,
+                advertising_tx_power: reader.field()?,
+                primary_phy: reader.field()?,
+                secondary_max_skip: reader.field()?,
+                secondary_phy: reader.field()?,
+                sid: reader.field()?,
+                scan_request_notification: reader.field()?,
+            },
+        };
+        reader.finish()?;
+        command.parameters.validate()?;
+        Ok(command)
+    }
+}
+crate::hci_return! {
+    /// Transmit power the controller picked for the set, in dBm.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct SetExtendedAdvertisingParametersReturn {
+        pub selected_tx_power: i8,
+    }
+}
+crate::hci_command! {
+    /// Sets the random address advertising set `advertising_handle` uses.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct SetAdvertisingSetRandomAddress: LEControllerOpcode::SetAdvertisingSetRandomAddress
+        => CommandComplete<StatusReturn> {
+        pub advertising_handle: u8,
+        pub random_address: BTAddress,
+    }
+}
+crate::hci_command! {
+    /// Longest advertising data the controller takes for one set.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ReadMaximumAdvertisingDataLength:
+        LEControllerOpcode::ReadMaximumAdvertisingDataLength
+        => CommandComplete<ReadMaximumAdvertisingDataLengthReturn> {}
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ReadMaximumAdvertisingDataLengthReturn {
+        pub max_advertising_data_length: u16,
+    }
+}
+crate::hci_command! {
+    /// Number of advertising sets the controller supports at once.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ReadNumberOfSupportedAdvertisingSets:
+        LEControllerOpcode::ReadNumberOfSupportedAdvertisingSets
+        => CommandComplete<ReadNumberOfSupportedAdvertisingSetsReturn> {}
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ReadNumberOfSupportedAdvertisingSetsReturn {
+        pub num_supported_advertising_sets: u8,
+    }
+}
+crate::hci_command! {
+    /// Removes a (disabled) advertising set.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct RemoveAdvertisingSet: LEControllerOpcode::RemoveAdvertisingSet
+        => CommandComplete<StatusReturn> {
+        pub advertising_handle: u8,
+    }
+}
+crate::hci_command! {
+    /// Removes every advertising set. Fails if any set is enabled.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ClearAdvertisingSets: LEControllerOpcode::ClearAdvertisingSets
+        => CommandComplete<StatusReturn> {}
+}
+/// Why an advertising set stopped.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum TerminationReason {
+    /// `duration` ran out.
+    Timeout,
+    /// `max_extended_advertising_events` were sent.
+    LimitReached,
+    /// A connection was created from the (connectable) advertisement.
+    Connected(ConnectionHandle),
+    Other(ErrorCode),
+}
+/// LE Advertising Set Terminated event.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct AdvertisingSetTerminated {
+    pub status: ErrorCode,
+    pub advertising_handle: u8,
+    /// Only meaningful if `status` is `ErrorCode::Ok`.
+    pub connection_handle: u16,
+    pub num_completed_extended_advertising_events: u8,
+}
+impl AdvertisingSetTerminated {
+    pub const BYTE_LEN: usize = 5;
+    pub fn reason(&self) -> TerminationReason {
+        match self.status {
+            ErrorCode::Ok => match ConnectionHandle::new_checked(self.connection_handle) {
+                Some(handle) => TerminationReason::Connected(handle),
+                None => TerminationReason::Other(ErrorCode::Ok),
+            },
+            ErrorCode::AdvertisingTimeout => TerminationReason::Timeout,
+            ErrorCode::LimitReached => TerminationReason::LimitReached,
+            e => TerminationReason::Other(e),
+        }
+    }
+}
+impl MetaEvent for AdvertisingSetTerminated {
+    const META_CODE: MetaEventCode = MetaEventCode::AdvertisingSetTerminated;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        Ok(AdvertisingSetTerminated {
+            status: ErrorCode::try_from(buf[0]).map_err(|_| PackError::bad_index(0))?,
+            advertising_handle: buf[1],
+            connection_handle: u16::from_le_bytes([buf[2], buf[3]]),
+            num_completed_extended_advertising_events: buf[4],
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf[0] = self.status.into();
+        buf[1] = self.advertising_handle;
+        buf[2..4].copy_from_slice(&self.connection_handle.to_le_bytes());
+        buf[4] = self.num_completed_extended_advertising_events;
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_advertising_data_update() {
+        let mut set = ExtendedAdvertisingData::new(2);
+        let data = alloc::vec![0xAB_u8; 600];
+        let commands = set.update(&data, false).unwrap();
+        assert_eq!(
+            commands.iter().map(|c| c.operation).collect::<Vec<_>>(),
+            [
+                DataOperation::FirstFragment,
+                DataOperation::IntermediateFragment,
+                DataOperation::LastFragment
+            ]
+        );
+        assert_eq!(
+            commands[2].data.len(),
+            600 - 2 * MAX_ADVERTISING_DATA_FRAGMENT_LEN
+        );
+        let mut buf = alloc::vec![0_u8; commands[0].byte_len()];
+        commands[0].pack_into(&mut buf).unwrap();
+        assert_eq!(&buf[..4], &[2, 0x01, 0x00, 251]);
+        assert_eq!(
+            SetExtendedAdvertisingData::unpack_from(&buf).unwrap(),
+            commands[0]
+        );
+        set.commit(&data);
+
+        assert!(set.update(&data, true).unwrap().is_empty());
+        assert_eq!(
+            set.update(&data[1..], true),
+            Err(AdvertisingDataError::FragmentedWhileEnabled)
+        );
+        let commands = set.update(&data[..20], true).unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].operation, DataOperation::Complete);
+    }
+    #[test]
+    fn test_extended_advertising_parameters() {
+        let mut parameters = ExtendedAdvertisingParameters::DEFAULT;
+        parameters.sid = 3;
+        let command = SetExtendedAdvertisingParameters {
+            advertising_handle: 1,
+            parameters,
+        };
+        let mut buf = [0_u8; SetExtendedAdvertisingParameters::BYTE_LEN];
+        command.pack_into(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..9],
+            &[1, 0x13, 0x00, 0x00, 0x08, 0x00, 0x00, 0x08, 0x00]
+        );
+        assert_eq!(&buf[19..], &[0x7F, 0x01, 0x00, 0x01, 0x03, 0x00]);
+        assert_eq!(
+            SetExtendedAdvertisingParameters::unpack_from(&buf).unwrap(),
+            command
+        );
+
+        // Legacy PDUs can't go out on the coded PHY.
+        parameters.primary_phy = AdvertisingPHY::LECoded;
+        assert!(parameters.validate().is_err());
+        parameters.event_properties =
+            AdvertisingEventProperties(AdvertisingEventProperties::CONNECTABLE);
+        assert!(parameters.validate().is_ok());
+        parameters.event_properties = AdvertisingEventProperties(
+            AdvertisingEventProperties::CONNECTABLE | AdvertisingEventProperties::SCANNABLE,
+        );
+        assert!(parameters.validate().is_err());
+
+        let mut handles = AdvertisingHandles::new(2);
+        assert_eq!(handles.allocate(), Some(0));
+        assert_eq!(handles.allocate(), Some(1));
+        assert_eq!(handles.allocate(), None);
+        assert!(handles.release(0));
+        assert_eq!(handles.allocate(), Some(0));
+    }
+    #[test]
+    fn test_limited_advertising() {
+        let set = AdvertisingSetEnable::new(1)
+            .with_duration(Duration::from_millis(1001))
+            .unwrap()
+            .with_max_events(3);
+        // Rounded up to the next 10 ms.
+        assert_eq!(set.duration, 101);
+        assert!(AdvertisingSetEnable::new(1)
+            .with_duration(Duration::from_secs(0))
+            .is_none());
+        assert!(AdvertisingSetEnable::new(1)
+            .with_duration(Duration::from_secs(656))
+            .is_none());
+        let enable = SetExtendedAdvertisingEnable {
+            is_enabled: true,
+            sets: alloc::vec![set],
+        };
+        let mut buf = alloc::vec![0_u8; enable.byte_len()];
+        enable.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x01, 0x01, 0x01, 0x65, 0x00, 0x03]);
+        assert_eq!(SetExtendedAdvertisingEnable::unpack_from(&buf), Ok(enable));
+
+        let event = [0x00, 0x01, 0x40, 0x00, 0x02];
+        let terminated = AdvertisingSetTerminated::meta_unpack_from(&event).unwrap();
+        assert_eq!(
+            terminated.reason(),
+            TerminationReason::Connected(ConnectionHandle::new(0x0040))
+        );
+        let mut buf = [0_u8; AdvertisingSetTerminated::BYTE_LEN];
+        terminated.meta_pack_into(&mut buf).unwrap();
+        assert_eq!(buf, event);
+        let timeout = AdvertisingSetTerminated {
+            status: ErrorCode::AdvertisingTimeout,
+            ..terminated
+        };
+        assert_eq!(timeout.reason(), TerminationReason::Timeout);
+    }
+}
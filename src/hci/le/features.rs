@@ -0,0 +1,72 @@
+//! LE [`ReadLocalSupportedFeatures`] and the [`LEFeatures`] bit mask.
+use crate::hci::definitions::HCIField;
+use crate::hci::event::CommandComplete;
+use crate::hci::le::LEControllerOpcode;
+
+/// LE Link Layer features supported by a controller (Core Spec Vol 6, Part B, 4.6).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default)]
+pub struct LEFeatures(pub u64);
+impl LEFeatures {
+    pub const BYTE_LEN: usize = 8;
+    pub const ENCRYPTION: u8 = 0;
+    pub const CONNECTION_PARAMETERS_REQUEST: u8 = 1;
+    pub const EXTENDED_REJECT_INDICATION: u8 = 2;
+    pub const SLAVE_INITIATED_FEATURES_EXCHANGE: u8 = 3;
+    pub const PING: u8 = 4;
+    pub const DATA_PACKET_LENGTH_EXTENSION: u8 = 5;
+    pub const LL_PRIVACY: u8 = 6;
+    pub const EXTENDED_SCANNER_FILTER_POLICIES: u8 = 7;
+    pub const LE_2M_PHY: u8 = 8;
+    pub const STABLE_MODULATION_INDEX_TX: u8 = 9;
+    pub const STABLE_MODULATION_INDEX_RX: u8 = 10;
+    pub const LE_CODED_PHY: u8 = 11;
+    pub const EXTENDED_ADVERTISING: u8 = 12;
+    pub const PERIODIC_ADVERTISING: u8 = 13;
+    pub const CHANNEL_SELECTION_ALGORITHM_2: u8 = 14;
+    pub const POWER_CLASS_1: u8 = 15;
+    pub const MIN_USED_CHANNELS_PROCEDURE: u8 = 16;
+    pub fn new(features: u64) -> LEFeatures {
+        LEFeatures(features)
+    }
+    /// Returns `true` if feature `bit` is set.
+    pub fn supports(self, bit: u8) -> bool {
+        bit < 64 && self.0 & (1_u64 << bit) != 0
+    }
+    pub fn ll_privacy(self) -> bool {
+        self.supports(Self::LL_PRIVACY)
+    }
+    pub fn le_2m_phy(self) -> bool {
+        self.supports(Self::LE_2M_PHY)
+    }
+    pub fn le_coded_phy(self) -> bool {
+        self.supports(Self::LE_CODED_PHY)
+    }
+    pub fn extended_advertising(self) -> bool {
+        self.supports(Self::EXTENDED_ADVERTISING)
+    }
+}
+impl From<LEFeatures> for u64 {
+    fn from(f: LEFeatures) -> Self {
+        f.0
+    }
+}
+impl HCIField for LEFeatures {
+    const BYTE_LEN: usize = LEFeatures::BYTE_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        self.0.pack_field(buf);
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        u64::unpack_field(buf).map(LEFeatures)
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ReadLocalSupportedFeatures: LEControllerOpcode::ReadLocalSupportedFeatures
+        => CommandComplete<LocalSupportedFeaturesReturn> {}
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct LocalSupportedFeaturesReturn {
+        pub features: LEFeatures,
+    }
+}
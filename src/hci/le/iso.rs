@@ -0,0 +1,367 @@
+//! LE ISO data path configuration. Each direction of a CIS or BIS is routed either over HCI (ISO
+//! data packets to and from the host) or over a vendor specific path inside the controller (an
+//! audio DSP for example) with [`SetupISODataPath`] and torn down with [`RemoveISODataPath`].
+//! [`ReadISOTXSync`] reads when the controller sent an SDU, which [`ISOTXSyncReturn`] turns into
+//! time stamps for later SDUs.
+use crate::hci::command::Command;
+use crate::hci::definitions::HCIField;
+use crate::hci::event::CommandComplete;
+use crate::hci::informational::{
+    pack_codec_configuration, unpack_codec_configuration, CodecId, ControllerDelay,
+    DataPathDirection,
+};
+use crate::hci::le::LEControllerOpcode;
+use crate::hci::Opcode;
+use crate::le::connection::ConnectionHandle;
+use crate::PackError;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// Where ISO data of one direction goes.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum DataPathId {
+    /// ISO data packets over HCI.
+    HCI,
+    /// Vendor specific controller internal path (`0x01..=0xFE`).
+    Vendor(u8),
+}
+impl Default for DataPathId {
+    fn default() -> Self {
+        DataPathId::HCI
+    }
+}
+impl HCIField for DataPathId {
+    const BYTE_LEN: usize = 1;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf[0] = match *self {
+            DataPathId::HCI => 0x00,
+            DataPathId::Vendor(id) => id,
+        };
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        match buf[0] {
+            0x00 => Some(DataPathId::HCI),
+            0xFF => None,
+            id => Some(DataPathId::Vendor(id)),
+        }
+    }
+}
+/// Routes one direction of the CIS or BIS `connection_handle`. For [`DataPathId::HCI`] use
+/// [`CodecId::TRANSPARENT`], no configuration and [`ControllerDelay::ZERO`]. The controller delay
+/// should be in the range returned by
+/// [`ReadLocalSupportedControllerDelay`](crate::hci::informational::ReadLocalSupportedControllerDelay).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct SetupISODataPath {
+    pub connection_handle: ConnectionHandle,
+    pub direction: DataPathDirection,
+    pub data_path_id: DataPathId,
+    pub codec_id: CodecId,
+    pub controller_delay: ControllerDelay,
+    pub codec_configuration: Vec<u8>,
+}
+impl SetupISODataPath {
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetupISODataPath;
+    pub const HEADER_LEN: usize = 12;
+    /// ISO data over HCI in `direction`.
+    pub fn hci(connection_handle: ConnectionHandle, direction: DataPathDirection) -> Self {
+        SetupISODataPath {
+            connection_handle,
+            direction,
+            data_path_id: DataPathId::HCI,
+            codec_id: CodecId::TRANSPARENT,
+            controller_delay: ControllerDelay::ZERO,
+            codec_configuration: Vec::new(),
+        }
+    }
+}
+impl Command for SetupISODataPath {
+    type Return = CommandComplete<ISODataPathReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::HEADER_LEN + 1 + self.codec_configuration.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        self.connection_handle.pack_field(&mut buf[0..2]);
+        self.direction.pack_field(&mut buf[2..3]);
+        self.data_path_id.pack_field(&mut buf[3..4]);
+        self.codec_id.pack_field(&mut buf[4..9]);
+        self.controller_delay.pack_field(&mut buf[9..12]);
+        pack_codec_configuration(
+            &self.codec_configuration,
+            &mut buf[Self::HEADER_LEN..],
+            Self::HEADER_LEN,
+        )
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::atleast_length(Self::HEADER_LEN + 1, buf)?;
+        Ok(SetupISODataPath {
+            connection_handle: ConnectionHandle::unpack_field(&buf[0..2])
+                .ok_or(PackError::bad_index(0))?,
+            direction: DataPathDirection::unpack_field(&buf[2..3])
+                .ok_or(PackError::bad_index(2))?,
+            data_path_id: DataPathId::unpack_field(&buf[3..4]).ok_or(PackError::bad_index(3))?,
+            codec_id: CodecId::unpack_field(&buf[4..9]).ok_or(PackError::bad_index(4))?,
+            controller_delay: ControllerDelay::unpack_field(&buf[9..12])
+                .ok_or(PackError::bad_index(9))?,
+            codec_configuration: unpack_codec_configuration(
+                &buf[Self::HEADER_LEN..],
+                Self::HEADER_LEN,
+            )?,
+        })
+    }
+}
+/// Which directions [`RemoveISODataPath`] removes.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct DataPathDirections {
+    pub input: bool,
+    pub output: bool,
+}
+impl DataPathDirections {
+    pub const BOTH: DataPathDirections = DataPathDirections {
+        input: true,
+        output: true,
+    };
+    pub fn contains(self, direction: DataPathDirection) -> bool {
+        match direction {
+            DataPathDirection::Input => self.input,
+            DataPathDirection::Output => self.output,
+        }
+    }
+}
+impl From<DataPathDirection> for DataPathDirections {
+    fn from(direction: DataPathDirection) -> Self {
+        DataPathDirections {
+            input: direction == DataPathDirection::Input,
+            output: direction == DataPathDirection::Output,
+        }
+    }
+}
+impl HCIField for DataPathDirections {
+    const BYTE_LEN: usize = 1;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf[0] = u8::from(self.input) | u8::from(self.output) << 1;
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        if buf[0] & !0b11 != 0 {
+            return None;
+        }
+        Some(DataPathDirections {
+            input: buf[0] & 0b01 != 0,
+            output: buf[0] & 0b10 != 0,
+        })
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct RemoveISODataPath: LEControllerOpcode::RemoveISODataPath
+        => CommandComplete<ISODataPathReturn> {
+        pub connection_handle: ConnectionHandle,
+        pub directions: DataPathDirections,
+    }
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ISODataPathReturn {
+        pub connection_handle: ConnectionHandle,
+    }
+}
+crate::hci_command! {
+    /// Reads the sequence number and time stamp of the last SDU the controller sent on the CIS
+    /// or BIS `connection_handle` (data path has to be HCI).
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ReadISOTXSync: LEControllerOpcode::ReadISOTXSync
+        => CommandComplete<ISOTXSyncReturn> {
+        pub connection_handle: ConnectionHandle,
+    }
+}
+/// 24 bit microsecond time offset.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct TimeOffset(u32);
+impl TimeOffset {
+    pub const BYTE_LEN: usize = 3;
+    pub const MAX_MICROS: u32 = 0x00FF_FFFF;
+    pub fn new(micros: u32) -> Option<TimeOffset> {
+        if micros <= Self::MAX_MICROS {
+            Some(TimeOffset(micros))
+        } else {
+            None
+        }
+    }
+    pub fn micros(self) -> u32 {
+        self.0
+    }
+}
+impl HCIField for TimeOffset {
+    const BYTE_LEN: usize = TimeOffset::BYTE_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.0.to_le_bytes()[..3]);
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        Some(TimeOffset(u32::from_le_bytes([buf[0], buf[1], buf[2], 0])))
+    }
+}
+crate::hci_return! {
+    /// `tx_time_stamp` is the CIG reference point or BIG anchor point (controller clock, µs)
+    /// of the SDU `packet_sequence_number`, `time_offset` how long before that point the SDU
+    /// synchronization reference is.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ISOTXSyncReturn {
+        pub connection_handle: ConnectionHandle,
+        pub packet_sequence_number: u16,
+        pub tx_time_stamp: u32,
+        pub time_offset: TimeOffset,
+    }
+}
+impl ISOTXSyncReturn {
+    /// SDU synchronization reference of SDU `packet_sequence_number` (controller clock, µs).
+    pub fn sdu_reference(&self) -> u32 {
+        self.tx_time_stamp.wrapping_sub(self.time_offset.micros())
+    }
+    /// Time stamp (controller clock, µs) for the SDU `sequence_number` of a stream with
+    /// `sdu_interval`, for [`ISOPacket::time_stamp`](crate::hci::iso::ISOPacket::time_stamp).
+    /// Sequence numbers and time stamps wrap around, so `sequence_number` has to be within
+    /// 32767 SDUs of `packet_sequence_number` (earlier or later).
+    pub fn time_stamp_of(&self, sequence_number: u16, sdu_interval: Duration) -> u32 {
+        let sdus = sequence_number.wrapping_sub(self.packet_sequence_number) as i16;
+        let interval = sdu_interval.as_micros() as u32;
+        self.sdu_reference()
+            .wrapping_add((i32::from(sdus) as u32).wrapping_mul(interval))
+    }
+    /// When a receiver renders SDU `sequence_number`: its time stamp plus the stream's
+    /// `presentation_delay`.
+    pub fn presentation_time_of(
+        &self,
+        sequence_number: u16,
+        sdu_interval: Duration,
+        presentation_delay: Duration,
+    ) -> u32 {
+        self.time_stamp_of(sequence_number, sdu_interval)
+            .wrapping_add(presentation_delay.as_micros() as u32)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::event::ReturnParameters;
+    use crate::hci::informational::{
+        CodingFormat, LogicalTransportType, ReadLocalSupportedControllerDelay,
+    };
+    use core::time::Duration;
+
+    #[test]
+    fn test_iso_data_path_commands() {
+        let handle = ConnectionHandle::new_checked(0x0060).unwrap();
+        let setup = SetupISODataPath {
+            data_path_id: DataPathId::Vendor(0x01),
+            codec_id: CodecId::new(CodingFormat::LC3),
+            controller_delay: ControllerDelay::from_duration(Duration::from_millis(10)).unwrap(),
+            codec_configuration: vec![0x02, 0x01, 0x08],
+            ..SetupISODataPath::hci(handle, DataPathDirection::Output)
+        };
+        let mut buf = vec![0_u8; setup.byte_len()];
+        setup.pack_into(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            [
+                0x60, 0x00, 0x01, 0x01, 0x06, 0x00, 0x00, 0x00, 0x00, 0x10, 0x27, 0x00, 0x03, 0x02,
+                0x01, 0x08
+            ]
+        );
+        assert_eq!(SetupISODataPath::unpack_from(&buf), Ok(setup));
+        buf[12] = 0x04;
+        assert!(SetupISODataPath::unpack_from(&buf).is_err());
+
+        let remove = RemoveISODataPath {
+            connection_handle: handle,
+            directions: DataPathDirections::BOTH,
+        };
+        let mut buf = [0_u8; RemoveISODataPath::BYTE_LEN];
+        remove.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x60, 0x00, 0x03]);
+        assert!(RemoveISODataPath::unpack_from(&[0x60, 0x00, 0x04]).is_err());
+
+        let delay = ReadLocalSupportedControllerDelay {
+            codec_id: CodecId::vendor(0x05F1, 0x0001),
+            logical_transport_type: LogicalTransportType::LECIS,
+            direction: DataPathDirection::Input,
+            codec_configuration: Vec::new(),
+        };
+        let mut buf = vec![0_u8; delay.byte_len()];
+        delay.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0xFF, 0xF1, 0x05, 0x01, 0x00, 0x02, 0x00, 0x00]);
+        assert_eq!(
+            ReadLocalSupportedControllerDelay::unpack_from(&buf),
+            Ok(delay)
+        );
+        assert_eq!(ControllerDelay::unpack_field(&[0x01, 0x09, 0x3D]), None);
+    }
+    #[test]
+    fn test_iso_tx_sync() {
+        let handle = ConnectionHandle::new_checked(0x0060).unwrap();
+        let read = ReadISOTXSync {
+            connection_handle: handle,
+        };
+        let mut buf = [0_u8; ReadISOTXSync::BYTE_LEN];
+        read.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x60, 0x00]);
+        assert_eq!(ReadISOTXSync::unpack_from(&buf), Ok(read));
+
+        let raw = [
+            0x00, 0x60, 0x00, 0x0A, 0x00, 0x40, 0x42, 0x0F, 0x00, 0xE8, 0x03, 0x00,
+        ];
+        let sync = ISOTXSyncReturn::unpack_from(&raw).unwrap();
+        assert_eq!(sync.connection_handle, handle);
+        assert_eq!(sync.packet_sequence_number, 10);
+        assert_eq!(sync.tx_time_stamp, 1_000_000);
+        assert_eq!(sync.time_offset.micros(), 1_000);
+        let mut buf = [0_u8; ISOTXSyncReturn::BYTE_LEN];
+        sync.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, raw);
+        assert!(ISOTXSyncReturn::unpack_from(&raw[..11]).is_err());
+        assert_eq!(TimeOffset::new(0x0100_0000), None);
+        let mut buf = [0_u8; TimeOffset::BYTE_LEN];
+        TimeOffset::new(TimeOffset::MAX_MICROS)
+            .unwrap()
+            .pack_field(&mut buf);
+        assert_eq!(buf, [0xFF, 0xFF, 0xFF]);
+    }
+    #[test]
+    fn test_sdu_time_stamps() {
+        let sync = ISOTXSyncReturn::unpack_from(&[
+            0x00, 0x60, 0x00, 0x0A, 0x00, 0x40, 0x42, 0x0F, 0x00, 0xE8, 0x03, 0x00,
+        ])
+        .unwrap();
+        assert_eq!(sync.sdu_reference(), 999_000);
+        let interval = Duration::from_millis(10);
+        assert_eq!(sync.time_stamp_of(10, interval), 999_000);
+        assert_eq!(sync.time_stamp_of(12, interval), 1_019_000);
+        assert_eq!(sync.time_stamp_of(9, interval), 989_000);
+        assert_eq!(
+            sync.presentation_time_of(10, interval, Duration::from_micros(40_000)),
+            1_039_000
+        );
+
+        // Sequence numbers and the controller clock wrap around.
+        let sync = ISOTXSyncReturn {
+            packet_sequence_number: 0xFFFF,
+            tx_time_stamp: 0xFFFF_FF00,
+            time_offset: TimeOffset::new(0).unwrap(),
+            ..sync
+        };
+        assert_eq!(sync.time_stamp_of(1, Duration::from_micros(0x100)), 0x0100);
+        assert_eq!(
+            sync.time_stamp_of(0xFFFE, Duration::from_micros(0x100)),
+            0xFFFF_FE00
+        );
+    }
+}
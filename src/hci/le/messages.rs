@@ -6,12 +6,54 @@ pub mod commands {
             ReadAdvertisingChannelTxPower, SetAdvertisingData, SetAdvertisingEnable,
             SetAdvertisingParameters,
         },
-        connection::{ReadBufferSizeV1, ReadBufferSizeV2},
+        connection::{
+            ConnectionUpdate, CreateConnection, CreateConnectionCancel, ExtendedCreateConnection,
+            ReadBufferSizeV1, ReadBufferSizeV2, RemoteConnectionParameterRequestNegativeReply,
+            RemoteConnectionParameterRequestReply,
+        },
+        data_length::{
+            ReadSuggestedDefaultDataLength, SetDataLength, WriteSuggestedDefaultDataLength,
+        },
+        extended_advertise::{
+            ClearAdvertisingSets, ReadMaximumAdvertisingDataLength,
+            ReadNumberOfSupportedAdvertisingSets, RemoveAdvertisingSet,
+            SetAdvertisingSetRandomAddress, SetExtendedAdvertisingData,
+            SetExtendedAdvertisingEnable, SetExtendedAdvertisingParameters,
+            SetExtendedScanResponseData,
+        },
+        features::ReadLocalSupportedFeatures,
         mask::SetMetaEventMask,
-        random::Rand,
-        scan::{SetScanEnable, SetScanParameters, SetScanResponseData},
+        pawr::{
+            SetPeriodicAdvertisingParametersV2, SetPeriodicAdvertisingResponseData,
+            SetPeriodicAdvertisingSubeventData, SetPeriodicSyncSubevent,
+        },
+        periodic::{
+            AddDeviceToPeriodicAdvertiserList, ClearPeriodicAdvertiserList,
+            PeriodicAdvertisingCreateSync, PeriodicAdvertisingCreateSyncCancel,
+            PeriodicAdvertisingTerminateSync, ReadPeriodicAdvertiserListSize,
+            RemoveDeviceFromPeriodicAdvertiserList, SetPeriodicAdvertisingData,
+            SetPeriodicAdvertisingEnable, SetPeriodicAdvertisingParameters,
+        },
+        random::{Rand, SetRandomAddress},
+        scan::{
+            SetExtendedScanEnable, SetExtendedScanParameters, SetScanEnable, SetScanParameters,
+            SetScanResponseData,
+        },
+        whitelist::{
+            AddDeviceToWhitelist, ClearWhitelist, ReadWhitelistSize, RemoveDeviceFromWhitelist,
+        },
     };
 }
 pub mod events {
+    pub use super::connection::{ConnectionUpdateComplete, RemoteConnectionParameterRequest};
+    pub use super::data_length::DataLengthChange;
+    pub use super::extended_advertise::AdvertisingSetTerminated;
+    pub use super::pawr::{
+        PeriodicAdvertisingResponseReport, PeriodicAdvertisingSubeventDataRequest,
+    };
+    pub use super::periodic::{
+        PeriodicAdvertisingReport, PeriodicAdvertisingSyncEstablished, PeriodicAdvertisingSyncLost,
+    };
     pub use super::report::AdvertisingReport;
+    pub use super::scan::ScanTimeout;
 }
@@ -1,12 +1,21 @@
 //! HCI LE Layer. Handles everything from advertising, scanning, LE links, etc.
 pub mod advertise;
+pub mod cte;
+pub mod data_length;
+pub mod extended_advertise;
+pub mod features;
+pub mod iso;
 pub mod mask;
 pub mod messages;
+pub mod pawr;
+pub mod periodic;
+pub mod power;
 pub mod report;
 pub use messages::*;
 pub mod connection;
 pub mod random;
 pub mod scan;
+pub mod whitelist;
 use crate::bytes::Storage;
 use crate::hci::event::{Event, EventCode, EventPacket};
 use crate::hci::{Opcode, OCF, OGF};
@@ -49,6 +58,51 @@ pub enum LEControllerOpcode {
     ReceiverTest = 0x001D,
     TransmitterTest = 0x001E,
     TestEnd = 0x001F,
+    RemoteConnectionParameterRequestReply = 0x0020,
+    RemoteConnectionParameterRequestNegativeReply = 0x0021,
+    SetDataLength = 0x0022,
+    ReadSuggestedDefaultDataLength = 0x0023,
+    WriteSuggestedDefaultDataLength = 0x0024,
+    SetAdvertisingSetRandomAddress = 0x0035,
+    SetExtendedAdvertisingParameters = 0x0036,
+    SetExtendedAdvertisingData = 0x0037,
+    SetExtendedScanResponseData = 0x0038,
+    SetExtendedAdvertisingEnable = 0x0039,
+    ReadMaximumAdvertisingDataLength = 0x003A,
+    ReadNumberOfSupportedAdvertisingSets = 0x003B,
+    RemoveAdvertisingSet = 0x003C,
+    ClearAdvertisingSets = 0x003D,
+    SetPeriodicAdvertisingParameters = 0x003E,
+    SetPeriodicAdvertisingData = 0x003F,
+    SetPeriodicAdvertisingEnable = 0x0040,
+    SetExtendedScanParameters = 0x0041,
+    SetExtendedScanEnable = 0x0042,
+    ExtendedCreateConnection = 0x0043,
+    PeriodicAdvertisingCreateSync = 0x0044,
+    PeriodicAdvertisingCreateSyncCancel = 0x0045,
+    PeriodicAdvertisingTerminateSync = 0x0046,
+    AddDeviceToPeriodicAdvertiserList = 0x0047,
+    RemoveDeviceFromPeriodicAdvertiserList = 0x0048,
+    ClearPeriodicAdvertiserList = 0x0049,
+    ReadPeriodicAdvertiserListSize = 0x004A,
+    ReadTransmitPower = 0x004B,
+    SetConnectionCTEReceiveParameters = 0x0054,
+    SetConnectionCTETransmitParameters = 0x0055,
+    ConnectionCTERequestEnable = 0x0056,
+    ConnectionCTEResponseEnable = 0x0057,
+    ReadAntennaInformation = 0x0058,
+    ReadISOTXSync = 0x0061,
+    SetupISODataPath = 0x006E,
+    RemoveISODataPath = 0x006F,
+    EnhancedReadTransmitPowerLevel = 0x0076,
+    ReadRemoteTransmitPowerLevel = 0x0077,
+    SetPathLossReportingParameters = 0x0078,
+    SetPathLossReportingEnable = 0x0079,
+    SetTransmitPowerReportingEnable = 0x007A,
+    SetPeriodicAdvertisingSubeventData = 0x0082,
+    SetPeriodicAdvertisingResponseData = 0x0083,
+    SetPeriodicSyncSubevent = 0x0084,
+    SetPeriodicAdvertisingParametersV2 = 0x0086,
 }
 impl TryFrom<OCF> for LEControllerOpcode {
     type Error = ConversionError;
@@ -56,8 +110,8 @@ impl TryFrom<OCF> for LEControllerOpcode {
     fn try_from(ocf: OCF) -> Result<Self, Self::Error> {
         match u16::from(ocf) {
             0x0001 => Ok(LEControllerOpcode::SetEventMask),
-            0x0060 => Ok(LEControllerOpcode::ReadBufferSizeV1),
-            0x0002 => Ok(LEControllerOpcode::ReadBufferSizeV2),
+            0x0002 => Ok(LEControllerOpcode::ReadBufferSizeV1),
+            0x0060 => Ok(LEControllerOpcode::ReadBufferSizeV2),
             0x0003 => Ok(LEControllerOpcode::ReadLocalSupportedFeatures),
             0x0005 => Ok(LEControllerOpcode::SetRandomAddress),
             0x0006 => Ok(LEControllerOpcode::SetAdvertisingParameters),
@@ -86,6 +140,51 @@ impl TryFrom<OCF> for LEControllerOpcode {
             0x001D => Ok(LEControllerOpcode::ReceiverTest),
             0x001E => Ok(LEControllerOpcode::TransmitterTest),
             0x001F => Ok(LEControllerOpcode::TestEnd),
+            0x0020 => Ok(LEControllerOpcode::RemoteConnectionParameterRequestReply),
+            0x0021 => Ok(LEControllerOpcode::RemoteConnectionParameterRequestNegativeReply),
+            0x0022 => Ok(LEControllerOpcode::SetDataLength),
+            0x0023 => Ok(LEControllerOpcode::ReadSuggestedDefaultDataLength),
+            0x0024 => Ok(LEControllerOpcode::WriteSuggestedDefaultDataLength),
+            0x0035 => Ok(LEControllerOpcode::SetAdvertisingSetRandomAddress),
+            0x0036 => Ok(LEControllerOpcode::SetExtendedAdvertisingParameters),
+            0x0037 => Ok(LEControllerOpcode::SetExtendedAdvertisingData),
+            0x0038 => Ok(LEControllerOpcode::SetExtendedScanResponseData),
+            0x0039 => Ok(LEControllerOpcode::SetExtendedAdvertisingEnable),
+            0x003A => Ok(LEControllerOpcode::ReadMaximumAdvertisingDataLength),
+            0x003B => Ok(LEControllerOpcode::ReadNumberOfSupportedAdvertisingSets),
+            0x003C => Ok(LEControllerOpcode::RemoveAdvertisingSet),
+            0x003D => Ok(LEControllerOpcode::ClearAdvertisingSets),
+            0x003E => Ok(LEControllerOpcode::SetPeriodicAdvertisingParameters),
+            0x003F => Ok(LEControllerOpcode::SetPeriodicAdvertisingData),
+            0x0040 => Ok(LEControllerOpcode::SetPeriodicAdvertisingEnable),
+            0x0041 => Ok(LEControllerOpcode::SetExtendedScanParameters),
+            0x0042 => Ok(LEControllerOpcode::SetExtendedScanEnable),
+            0x0043 => Ok(LEControllerOpcode::ExtendedCreateConnection),
+            0x0044 => Ok(LEControllerOpcode::PeriodicAdvertisingCreateSync),
+            0x0045 => Ok(LEControllerOpcode::PeriodicAdvertisingCreateSyncCancel),
+            0x0046 => Ok(LEControllerOpcode::PeriodicAdvertisingTerminateSync),
+            0x0047 => Ok(LEControllerOpcode::AddDeviceToPeriodicAdvertiserList),
+            0x0048 => Ok(LEControllerOpcode::RemoveDeviceFromPeriodicAdvertiserList),
+            0x0049 => Ok(LEControllerOpcode::ClearPeriodicAdvertiserList),
+            0x004A => Ok(LEControllerOpcode::ReadPeriodicAdvertiserListSize),
+            0x004B => Ok(LEControllerOpcode::ReadTransmitPower),
+            0x0054 => Ok(LEControllerOpcode::SetConnectionCTEReceiveParameters),
+            0x0055 => Ok(LEControllerOpcode::SetConnectionCTETransmitParameters),
+            0x0056 => Ok(LEControllerOpcode::ConnectionCTERequestEnable),
+            0x0057 => Ok(LEControllerOpcode::ConnectionCTEResponseEnable),
+            0x0058 => Ok(LEControllerOpcode::ReadAntennaInformation),
+            0x0061 => Ok(LEControllerOpcode::ReadISOTXSync),
+            0x006E => Ok(LEControllerOpcode::SetupISODataPath),
+            0x006F => Ok(LEControllerOpcode::RemoveISODataPath),
+            0x0076 => Ok(LEControllerOpcode::EnhancedReadTransmitPowerLevel),
+            0x0077 => Ok(LEControllerOpcode::ReadRemoteTransmitPowerLevel),
+            0x0078 => Ok(LEControllerOpcode::SetPathLossReportingParameters),
+            0x0079 => Ok(LEControllerOpcode::SetPathLossReportingEnable),
+            0x007A => Ok(LEControllerOpcode::SetTransmitPowerReportingEnable),
+            0x0082 => Ok(LEControllerOpcode::SetPeriodicAdvertisingSubeventData),
+            0x0083 => Ok(LEControllerOpcode::SetPeriodicAdvertisingResponseData),
+            0x0084 => Ok(LEControllerOpcode::SetPeriodicSyncSubevent),
+            0x0086 => Ok(LEControllerOpcode::SetPeriodicAdvertisingParametersV2),
             _ => Err(ConversionError(())),
         }
     }
@@ -142,10 +241,16 @@ pub enum MetaEventCode {
     PathLossThreshold = 0x20,
     TransmitPowerReporting = 0x21,
     BIGInfoAdvertisingReport = 0x22,
+    SubrateChange = 0x23,
+    PeriodicAdvertisingSyncEstablishedV2 = 0x24,
+    PeriodicAdvertisingReportV2 = 0x25,
+    PeriodicAdvertisingSyncTransferReceivedV2 = 0x26,
+    PeriodicAdvertisingSubeventDataRequest = 0x27,
+    PeriodicAdvertisingResponseReport = 0x28,
 }
 impl MetaEventCode {
     /// The `MetaEventCode` with the highest value.
-    pub const MAX_CODE: MetaEventCode = MetaEventCode::BIGInfoAdvertisingReport;
+    pub const MAX_CODE: MetaEventCode = MetaEventCode::PeriodicAdvertisingResponseReport;
 }
 impl From<MetaEventCode> for u8 {
     fn from(c: MetaEventCode) -> Self {
@@ -191,6 +296,12 @@ impl TryFrom<u8> for MetaEventCode {
             0x20 => Ok(MetaEventCode::PathLossThreshold),
             0x21 => Ok(MetaEventCode::TransmitPowerReporting),
             0x22 => Ok(MetaEventCode::BIGInfoAdvertisingReport),
+            0x23 => Ok(MetaEventCode::SubrateChange),
+            0x24 => Ok(MetaEventCode::PeriodicAdvertisingSyncEstablishedV2),
+            0x25 => Ok(MetaEventCode::PeriodicAdvertisingReportV2),
+            0x26 => Ok(MetaEventCode::PeriodicAdvertisingSyncTransferReceivedV2),
+            0x27 => Ok(MetaEventCode::PeriodicAdvertisingSubeventDataRequest),
+            0x28 => Ok(MetaEventCode::PeriodicAdvertisingResponseReport),
             _ => Err(ConversionError(())),
         }
     }
@@ -250,15 +361,13 @@ impl<'a> TryFrom<EventPacket<&'a [u8]>> for RawMetaEvent<&'a [u8]> {
         if value.event_code != EventCode::LEMeta {
             return Err(PackError::BadOpcode);
         }
-        let code =
-            MetaEventCode::try_from(*value.parameters.get(0).ok_or(PackError::BadLength {
-                expected: 1,
-                got: 0,
-            })?)
-            .map_err(|_| PackError::BadOpcode)?;
+        let (&code, parameters) = value.parameters.split_first().ok_or(PackError::BadLength {
+            expected: 1,
+            got: 0,
+        })?;
         Ok(RawMetaEvent {
-            code,
-            parameters: &value.parameters[1..],
+            code: MetaEventCode::try_from(code).map_err(|_| PackError::BadOpcode)?,
+            parameters,
         })
     }
 }
@@ -273,13 +382,12 @@ impl<M: MetaEvent> Event for M {
     where
         Self: Sized,
     {
-        if u8::from(Self::META_CODE)
-            == *buf.get(0).ok_or(PackError::BadLength {
-                expected: 1,
-                got: 0,
-            })?
-        {
-            MetaEvent::meta_unpack_from(&buf[1..])
+        let (&code, parameters) = buf.split_first().ok_or(PackError::BadLength {
+            expected: 1,
+            got: 0,
+        })?;
+        if u8::from(Self::META_CODE) == code {
+            MetaEvent::meta_unpack_from(parameters)
         } else {
             Err(PackError::bad_index(0))
         }
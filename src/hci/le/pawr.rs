@@ -0,0 +1,557 @@
+//! Periodic Advertising with Responses (PAwR, Bluetooth 5.4). The advertiser splits each
+//! periodic advertising interval into subevents. The controller asks the host for subevent data
+//! ([`PeriodicAdvertisingSubeventDataRequest`]) ahead of time and reports what synchronized
+//! devices sent back in the response slots ([`PeriodicAdvertisingResponseReport`]).
+use crate::hci::command::Command;
+use crate::hci::event::CommandComplete;
+use crate::hci::le::{LEControllerOpcode, MetaEvent, MetaEventCode};
+use crate::hci::Opcode;
+use crate::{PackError, RSSI};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// Max data in one subevent or response slot.
+pub const MAX_SUBEVENT_DATA_LEN: usize = 251;
+pub use crate::hci::command::MAX_COMMAND_PARAMETERS_LEN;
+
+crate::hci_command! {
+    /// Sets the periodic advertising parameters of an advertising set including the PAwR
+    /// subevent and response slot layout. Intervals are in 1.25 ms units, `subevent_interval` and
+    /// `response_slot_delay` in 1.25 ms units and `response_slot_spacing` in 0.125 ms units.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct SetPeriodicAdvertisingParametersV2: LEControllerOpcode::SetPeriodicAdvertisingParametersV2
+        => CommandComplete<AdvertisingHandleReturn> {
+        pub advertising_handle: u8,
+        pub interval_min: u16,
+        pub interval_max: u16,
+        pub properties: u16,
+        pub num_subevents: u8,
+        pub subevent_interval: u8,
+        pub response_slot_delay: u8,
+        pub response_slot_spacing: u8,
+        pub num_response_slots: u8,
+    }
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct AdvertisingHandleReturn {
+        pub advertising_handle: u8,
+    }
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct SyncHandleReturn {
+        pub sync_handle: u16,
+    }
+}
+/// Data to send in one subevent and which response slots to listen to.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct SubeventData {
+    pub subevent: u8,
+    pub response_slot_start: u8,
+    pub response_slot_count: u8,
+    pub data: Vec<u8>,
+}
+impl SubeventData {
+    pub const HEADER_LEN: usize = 4;
+    pub fn byte_len(&self) -> usize {
+        Self::HEADER_LEN + self.data.len()
+    }
+}
+/// Sets the data of one or more subevents (usually in response to a
+/// [`PeriodicAdvertisingSubeventDataRequest`]).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct SetPeriodicAdvertisingSubeventData {
+    pub advertising_handle: u8,
+    pub subevents: Vec<SubeventData>,
+}
+impl SetPeriodicAdvertisingSubeventData {
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetPeriodicAdvertisingSubeventData;
+}
+impl Command for SetPeriodicAdvertisingSubeventData {
+    type Return = CommandComplete<AdvertisingHandleReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        2 + self
+            .subevents
+            .iter()
+            .map(SubeventData::byte_len)
+            .sum::<usize>()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        buf[0] = self.advertising_handle;
+        buf[1] = u8::try_from(self.subevents.len()).map_err(|_| PackError::bad_index(1))?;
+        let mut index = 2;
+        for subevent in &self.subevents {
+            if subevent.data.len() > MAX_SUBEVENT_DATA_LEN {
+                return Err(PackError::bad_index(index + 3));
+            }
+            buf[index] = subevent.subevent;
+            buf[index + 1] = subevent.response_slot_start;
+            buf[index + 2] = subevent.response_slot_count;
+            buf[index + 3] = u8::try_from(subevent.data.len()).expect("checked above");
+            buf[index + 4..index + subevent.byte_len()].copy_from_slice(&subevent.data);
+            index += subevent.byte_len();
+        }
+        Ok(())
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::atleast_length(2, buf)?;
+        let mut subevents = Vec::with_capacity(usize::from(buf[1]));
+        let mut index = 2;
+        for _ in 0..buf[1] {
+            let header = buf
+                .get(index..index + SubeventData::HEADER_LEN)
+                .ok_or(PackError::bad_index(index))?;
+            let end = index + SubeventData::HEADER_LEN + usize::from(header[3]);
+            let data = buf
+                .get(index + SubeventData::HEADER_LEN..end)
+                .ok_or(PackError::bad_index(index + 3))?;
+            subevents.push(SubeventData {
+                subevent: header[0],
+                response_slot_start: header[1],
+                response_slot_count: header[2],
+                data: data.to_vec(),
+            });
+            index = end;
+        }
+        PackError::expect_length(index, buf)?;
+        Ok(SetPeriodicAdvertisingSubeventData {
+            advertising_handle: buf[0],
+            subevents,
+        })
+    }
+}
+/// Sets the data a synchronized device sends in a response slot.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct SetPeriodicAdvertisingResponseData {
+    pub sync_handle: u16,
+    pub request_event: u16,
+    pub request_subevent: u8,
+    pub response_subevent: u8,
+    pub response_slot: u8,
+    pub data: Vec<u8>,
+}
+impl SetPeriodicAdvertisingResponseData {
+    pub const HEADER_LEN: usize = 8;
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetPeriodicAdvertisingResponseData;
+}
+impl Command for SetPeriodicAdvertisingResponseData {
+    type Return = CommandComplete<SyncHandleReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::HEADER_LEN + self.data.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        if self.data.len() > MAX_SUBEVENT_DATA_LEN {
+            return Err(PackError::bad_index(7));
+        }
+        buf[..2].copy_from_slice(&self.sync_handle.to_le_bytes());
+        buf[2..4].copy_from_slice(&self.request_event.to_le_bytes());
+        buf[4] = self.request_subevent;
+        buf[5] = self.response_subevent;
+        buf[6] = self.response_slot;
+        buf[7] = u8::try_from(self.data.len()).expect("checked above");
+        buf[Self::HEADER_LEN..].copy_from_slice(&self.data);
+        Ok(())
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::atleast_length(Self::HEADER_LEN, buf)?;
+        PackError::expect_length(Self::HEADER_LEN + usize::from(buf[7]), buf)?;
+        Ok(SetPeriodicAdvertisingResponseData {
+            sync_handle: u16::from_le_bytes([buf[0], buf[1]]),
+            request_event: u16::from_le_bytes([buf[2], buf[3]]),
+            request_subevent: buf[4],
+            response_subevent: buf[5],
+            response_slot: buf[6],
+            data: buf[Self::HEADER_LEN..].to_vec(),
+        })
+    }
+}
+/// Selects which subevents a synchronized device listens to.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct SetPeriodicSyncSubevent {
+    pub sync_handle: u16,
+    pub periodic_advertising_properties: u16,
+    pub subevents: Vec<u8>,
+}
+impl SetPeriodicSyncSubevent {
+    pub const HEADER_LEN: usize = 5;
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetPeriodicSyncSubevent;
+}
+impl Command for SetPeriodicSyncSubevent {
+    type Return = CommandComplete<SyncHandleReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::HEADER_LEN + self.subevents.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        buf[..2].copy_from_slice(&self.sync_handle.to_le_bytes());
+        buf[2..4].copy_from_slice(&self.periodic_advertising_properties.to_le_bytes());
+        buf[4] = u8::try_from(self.subevents.len()).map_err(|_| PackError::bad_index(4))?;
+        buf[Self::HEADER_LEN..].copy_from_slice(&self.subevents);
+        Ok(())
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::atleast_length(Self::HEADER_LEN, buf)?;
+        PackError::expect_length(Self::HEADER_LEN + usize::from(buf[4]), buf)?;
+        Ok(SetPeriodicSyncSubevent {
+            sync_handle: u16::from_le_bytes([buf[0], buf[1]]),
+            periodic_advertising_properties: u16::from_le_bytes([buf[2], buf[3]]),
+            subevents: buf[Self::HEADER_LEN..].to_vec(),
+        })
+    }
+}
+/// The controller wants data for `subevent_data_count` subevents starting at `subevent_start`.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct PeriodicAdvertisingSubeventDataRequest {
+    pub advertising_handle: u8,
+    pub subevent_start: u8,
+    pub subevent_data_count: u8,
+}
+impl PeriodicAdvertisingSubeventDataRequest {
+    pub const BYTE_LEN: usize = 3;
+    /// Requested subevents. Subevent numbers wrap around at `num_subevents`.
+    pub fn subevents(&self, num_subevents: u8) -> impl Iterator<Item = u8> {
+        let start = self.subevent_start;
+        (0..self.subevent_data_count).map(move |i| match num_subevents {
+            0 => start.wrapping_add(i),
+            n => u8::try_from((u16::from(start) + u16::from(i)) % u16::from(n))
+                .expect("less than num_subevents"),
+        })
+    }
+}
+impl MetaEvent for PeriodicAdvertisingSubeventDataRequest {
+    const META_CODE: MetaEventCode = MetaEventCode::PeriodicAdvertisingSubeventDataRequest;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        Ok(PeriodicAdvertisingSubeventDataRequest {
+            advertising_handle: buf[0],
+            subevent_start: buf[1],
+            subevent_data_count: buf[2],
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf[0] = self.advertising_handle;
+        buf[1] = self.subevent_start;
+        buf[2] = self.subevent_data_count;
+        Ok(())
+    }
+}
+/// Whether a response was received completely.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ResponseDataStatus {
+    Complete = 0x00,
+    Truncated = 0x01,
+    /// The controller didn't receive anything in the slot.
+    NotReceived = 0xFF,
+}
+impl From<ResponseDataStatus> for u8 {
+    fn from(s: ResponseDataStatus) -> Self {
+        s as u8
+    }
+}
+impl TryFrom<u8> for ResponseDataStatus {
+    type Error = crate::ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(ResponseDataStatus::Complete),
+            0x01 => Ok(ResponseDataStatus::Truncated),
+            0xFF => Ok(ResponseDataStatus::NotReceived),
+            _ => Err(crate::ConversionError(())),
+        }
+    }
+}
+/// One response slot of a [`PeriodicAdvertisingResponseReport`].
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct PeriodicAdvertisingResponse {
+    /// Transmit power in dBm or `None` if not available.
+    pub tx_power: Option<i8>,
+    pub rssi: Option<RSSI>,
+    pub cte_type: u8,
+    pub response_slot: u8,
+    pub data_status: ResponseDataStatus,
+    pub data: Vec<u8>,
+}
+impl PeriodicAdvertisingResponse {
+    pub const HEADER_LEN: usize = 6;
+    pub const NOT_AVAILABLE: i8 = 0x7F;
+    pub fn byte_len(&self) -> usize {
+        Self::HEADER_LEN + self.data.len()
+    }
+}
+/// Responses received in one subevent. `tx_status` is `false` if the controller didn't transmit
+/// the subevent data (so there are no responses).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct PeriodicAdvertisingResponseReport {
+    pub advertising_handle: u8,
+    pub subevent: u8,
+    pub tx_status: bool,
+    pub responses: Vec<PeriodicAdvertisingResponse>,
+}
+impl PeriodicAdvertisingResponseReport {
+    pub const HEADER_LEN: usize = 4;
+    pub const TX_FAILED: u8 = 0x01;
+}
+impl MetaEvent for PeriodicAdvertisingResponseReport {
+    const META_CODE: MetaEventCode = MetaEventCode::PeriodicAdvertisingResponseReport;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::HEADER_LEN
+            + self
+                .responses
+                .iter()
+                .map(PeriodicAdvertisingResponse::byte_len)
+                .sum::<usize>()
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::atleast_length(Self::HEADER_LEN, buf)?;
+        let mut responses = Vec::with_capacity(usize::from(buf[3]));
+        let mut index = Self::HEADER_LEN;
+        for _ in 0..buf[3] {
+            let header = buf
+                .get(index..index + PeriodicAdvertisingResponse::HEADER_LEN)
+                .ok_or(PackError::bad_index(index))?;
+            let data_start = index + PeriodicAdvertisingResponse::HEADER_LEN;
+            let end = data_start + usize::from(header[5]);
+            let data = buf
+                .get(data_start..end)
+                .ok_or(PackError::bad_index(index + 5))?;
+            let [tx_power, rssi] = [header[0], header[1]].map(|b| i8::from_le_bytes([b]));
+            responses.push(PeriodicAdvertisingResponse {
+                tx_power: match tx_power {
+                    PeriodicAdvertisingResponse::NOT_AVAILABLE => None,
+                    p => Some(p),
+                },
+                rssi: RSSI::maybe_rssi(rssi).map_err(|_| PackError::bad_index(index + 1))?,
+                cte_type: header[2],
+                response_slot: header[3],
+                data_status: ResponseDataStatus::try_from(header[4])
+                    .map_err(|_| PackError::bad_index(index + 4))?,
+                data: data.to_vec(),
+            });
+            index = end;
+        }
+        PackError::expect_length(index, buf)?;
+        Ok(PeriodicAdvertisingResponseReport {
+            advertising_handle: buf[0],
+            subevent: buf[1],
+            tx_status: match buf[2] {
+                0x00 => true,
+                Self::TX_FAILED => false,
+                _ => return Err(PackError::bad_index(2)),
+            },
+            responses,
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.meta_byte_len(), buf)?;
+        buf[0] = self.advertising_handle;
+        buf[1] = self.subevent;
+        buf[2] = if self.tx_status {
+            0x00
+        } else {
+            Self::TX_FAILED
+        };
+        buf[3] = u8::try_from(self.responses.len()).map_err(|_| PackError::bad_index(3))?;
+        let mut index = Self::HEADER_LEN;
+        for response in &self.responses {
+            buf[index] = response
+                .tx_power
+                .unwrap_or(PeriodicAdvertisingResponse::NOT_AVAILABLE)
+                .to_le_bytes()[0];
+            buf[index + 1] = response
+                .rssi
+                .map_or(RSSI::UNSUPPORTED_RSSI, i8::from)
+                .to_le_bytes()[0];
+            buf[index + 2] = response.cte_type;
+            buf[index + 3] = response.response_slot;
+            buf[index + 4] = response.data_status.into();
+            buf[index + 5] =
+                u8::try_from(response.data.len()).map_err(|_| PackError::bad_index(index + 5))?;
+            let data_start = index + PeriodicAdvertisingResponse::HEADER_LEN;
+            buf[data_start..data_start + response.data.len()].copy_from_slice(&response.data);
+            index += response.byte_len();
+        }
+        Ok(())
+    }
+}
+/// Host side of a PAwR advertiser. [`LEAdapter::run_pawr`] calls it whenever the controller
+/// needs subevent data or reports responses.
+///
+/// [`LEAdapter::run_pawr`]: crate::hci::adapters::le::LEAdapter::run_pawr
+pub trait SubeventScheduler {
+    /// Data to send in `subevent` or `None` to leave the subevent empty this time.
+    fn subevent_data(&mut self, subevent: u8) -> Option<SubeventData>;
+    /// Called for every response report.
+    fn response_report(&mut self, report: PeriodicAdvertisingResponseReport);
+    /// Return `true` to stop [`LEAdapter::run_pawr`].
+    ///
+    /// [`LEAdapter::run_pawr`]: crate::hci::adapters::le::LEAdapter::run_pawr
+    fn is_done(&self) -> bool {
+        false
+    }
+}
+/// Splits `subevents` into as few [`SetPeriodicAdvertisingSubeventData`] commands as fit in
+/// [`MAX_COMMAND_PARAMETERS_LEN`].
+pub fn subevent_data_commands(
+    advertising_handle: u8,
+    subevents: Vec<SubeventData>,
+) -> Vec<SetPeriodicAdvertisingSubeventData> {
+    let mut out = Vec::new();
+    let mut current = SetPeriodicAdvertisingSubeventData {
+        advertising_handle,
+        subevents: Vec::new(),
+    };
+    for subevent in subevents {
+        if !current.subevents.is_empty()
+            && current.byte_len() + subevent.byte_len() > MAX_COMMAND_PARAMETERS_LEN
+        {
+            out.push(core::mem::replace(
+                &mut current,
+                SetPeriodicAdvertisingSubeventData {
+                    advertising_handle,
+                    subevents: Vec::new(),
+                },
+            ));
+        }
+        current.subevents.push(subevent);
+    }
+    if !current.subevents.is_empty() {
+        out.push(current);
+    }
+    out
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_response_report_and_subevent_split() {
+        let report = PeriodicAdvertisingResponseReport {
+            advertising_handle: 1,
+            subevent: 4,
+            tx_status: true,
+            responses: alloc::vec![
+                PeriodicAdvertisingResponse {
+                    tx_power: None,
+                    rssi: Some(RSSI::new(-60)),
+                    cte_type: 0xFF,
+                    response_slot: 2,
+                    data_status: ResponseDataStatus::Complete,
+                    data: alloc::vec![1, 2, 3],
+                },
+                PeriodicAdvertisingResponse {
+                    tx_power: Some(-4),
+                    rssi: None,
+                    cte_type: 0xFF,
+                    response_slot: 3,
+                    data_status: ResponseDataStatus::NotReceived,
+                    data: Vec::new(),
+                },
+            ],
+        };
+        let mut buf = alloc::vec![0_u8; report.meta_byte_len()];
+        report.meta_pack_into(&mut buf).unwrap();
+        assert_eq!(
+            PeriodicAdvertisingResponseReport::meta_unpack_from(&buf).unwrap(),
+            report
+        );
+        let request = PeriodicAdvertisingSubeventDataRequest {
+            advertising_handle: 1,
+            subevent_start: 6,
+            subevent_data_count: 3,
+        };
+        assert_eq!(request.subevents(8).collect::<Vec<_>>(), [6, 7, 0]);
+        let subevents = (0..3)
+            .map(|subevent| SubeventData {
+                subevent,
+                response_slot_start: 0,
+                response_slot_count: 4,
+                data: alloc::vec![0; 100],
+            })
+            .collect();
+        let commands = subevent_data_commands(1, subevents);
+        assert_eq!(commands.len(), 2);
+        assert!(commands
+            .iter()
+            .all(|c| c.byte_len() <= MAX_COMMAND_PARAMETERS_LEN));
+    }
+    #[test]
+    fn test_response_report_from_controller() {
+        // Subevent data transmitted and one response (Tx_Power not available, RSSI -60 dBm,
+        // no CTE, slot 2, complete, 2 bytes).
+        let raw = [
+            0x01, 0x04, 0x00, 0x01, 0x7F, 0xC4, 0xFF, 0x02, 0x00, 0x02, 0xAA, 0xBB,
+        ];
+        let report = PeriodicAdvertisingResponseReport::meta_unpack_from(&raw).unwrap();
+        assert!(report.tx_status);
+        assert_eq!(
+            report.responses,
+            [PeriodicAdvertisingResponse {
+                tx_power: None,
+                rssi: Some(RSSI::new(-60)),
+                cte_type: 0xFF,
+                response_slot: 2,
+                data_status: ResponseDataStatus::Complete,
+                data: alloc::vec![0xAA, 0xBB],
+            }]
+        );
+        // Not transmitted.
+        let report =
+            PeriodicAdvertisingResponseReport::meta_unpack_from(&[0x01, 0x05, 0x01, 0x00]).unwrap();
+        assert!(!report.tx_status);
+        let mut buf = [0_u8; PeriodicAdvertisingResponseReport::HEADER_LEN];
+        report.meta_pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x01, 0x05, 0x01, 0x00]);
+        assert_eq!(
+            PeriodicAdvertisingResponseReport::meta_unpack_from(&[0x01, 0x05, 0xFF, 0x00]),
+            Err(PackError::bad_index(2))
+        );
+    }
+}
@@ -0,0 +1,583 @@
+//! Periodic advertising (Bluetooth 5.0). An extended advertising set (see
+//! [`crate::hci::le::extended_advertise`]) can also send data at a fixed interval
+//! ([`SetPeriodicAdvertisingParameters`], [`SetPeriodicAdvertisingData`] and
+//! [`SetPeriodicAdvertisingEnable`]). Scanners synchronize to the train with
+//! [`PeriodicAdvertisingCreateSync`], either to one advertiser or to any advertiser on the
+//! periodic advertiser list, and then get a [`PeriodicAdvertisingReport`] per event without
+//! scanning until they [`PeriodicAdvertisingTerminateSync`] or the sync is lost.
+use crate::hci::command::Command;
+use crate::hci::definitions::{FieldReader, FieldWriter, HCIField};
+use crate::hci::event::{CommandComplete, CommandStatus, StatusReturn};
+use crate::hci::le::extended_advertise::{AdvertisingPHY, DataOperation, MAX_ADVERTISING_HANDLE};
+use crate::hci::le::{LEControllerOpcode, MetaEvent, MetaEventCode};
+use crate::hci::{ErrorCode, Opcode};
+use crate::le::advertiser::PeerAddressType;
+use crate::{BTAddress, ConversionError, PackError, RSSI};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// Shortest periodic advertising interval (7.5 ms) in 1.25 ms units.
+pub const MIN_PERIODIC_ADVERTISING_INTERVAL: u16 = 0x0006;
+/// Most periodic advertising data one [`SetPeriodicAdvertisingData`] command carries.
+pub const MAX_PERIODIC_ADVERTISING_DATA_FRAGMENT_LEN: usize = 252;
+/// Largest advertising SID.
+pub const MAX_ADVERTISING_SID: u8 = 0x0F;
+
+/// LE Set Periodic Advertising Parameters. Intervals are in 1.25 ms units. The advertising set
+/// has to exist and be non connectable and non scannable.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct SetPeriodicAdvertisingParameters {
+    pub advertising_handle: u8,
+    pub interval_min: u16,
+    pub interval_max: u16,
+    pub properties: u16,
+}
+impl SetPeriodicAdvertisingParameters {
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetPeriodicAdvertisingParameters;
+    pub const BYTE_LEN: usize = 7;
+    /// Include the transmit power in the periodic advertising PDUs.
+    pub const INCLUDE_TX_POWER: u16 = 0x0040;
+    /// # Errors
+    /// returns `PackError::BadIndex` pointing at the bad field (handle, an interval below
+    /// [`MIN_PERIODIC_ADVERTISING_INTERVAL`] or above `interval_max` or unknown properties).
+    pub fn validate(&self) -> Result<(), PackError> {
+        if self.advertising_handle > MAX_ADVERTISING_HANDLE {
+            return Err(PackError::bad_index(0));
+        }
+        if self.interval_min < MIN_PERIODIC_ADVERTISING_INTERVAL {
+            return Err(PackError::bad_index(1));
+        }
+        if self.interval_max < self.interval_min {
+            return Err(PackError::bad_index(3));
+        }
+        if self.properties & !Self::INCLUDE_TX_POWER != 0 {
+            return Err(PackError::bad_index(5));
+        }
+        Ok(())
+    }
+}
+impl Command for SetPeriodicAdvertisingParameters {
+    type Return = CommandComplete<StatusReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        self.validate()?;
+        let mut writer = FieldWriter::new(buf);
+        writer.field(&self.advertising_handle)?;
+        writer.field(&self.interval_min)?;
+        writer.field(&self.interval_max)?;
+        writer.field(&self.properties)?;
+        writer.finish()
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        let mut reader = FieldReader::new(buf);
+        let command = SetPeriodicAdvertisingParameters {
+            advertising_handle: reader.field()?,
+            interval_min: reader.field()?,
+            interval_max: reader.field()?,
+            properties: reader.field()?,
+        };
+        command.validate()?;
+        Ok(command)
+    }
+}
+/// LE Set Periodic Advertising Data. `data` holds at most
+/// [`MAX_PERIODIC_ADVERTISING_DATA_FRAGMENT_LEN`] bytes. Like extended advertising data, longer
+/// data is split into fragments while periodic advertising is disabled.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct SetPeriodicAdvertisingData {
+    pub advertising_handle: u8,
+    pub operation: DataOperation,
+    pub data: Vec<u8>,
+}
+impl SetPeriodicAdvertisingData {
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetPeriodicAdvertisingData;
+    pub const HEADER_LEN: usize = 3;
+}
+impl Command for SetPeriodicAdvertisingData {
+    type Return = CommandComplete<StatusReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::HEADER_LEN + self.data.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        if self.advertising_handle > MAX_ADVERTISING_HANDLE {
+            return Err(PackError::bad_index(0));
+        }
+        buf[0] = self.advertising_handle;
+        buf[1] = self.operation.into();
+        buf[2] = u8::try_from(self.data.len())
+            .ok()
+            .filter(|&len| usize::from(len) <= MAX_PERIODIC_ADVERTISING_DATA_FRAGMENT_LEN)
+            .ok_or(PackError::bad_index(2))?;
+        buf[Self::HEADER_LEN..].copy_from_slice(&self.data);
+        Ok(())
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::atleast_length(Self::HEADER_LEN, buf)?;
+        PackError::expect_length(Self::HEADER_LEN + usize::from(buf[2]), buf)?;
+        Ok(SetPeriodicAdvertisingData {
+            advertising_handle: buf[0],
+            operation: DataOperation::try_from(buf[1]).map_err(|_| PackError::bad_index(1))?,
+            data: buf[Self::HEADER_LEN..].to_vec(),
+        })
+    }
+}
+/// Enable field of [`SetPeriodicAdvertisingEnable`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct PeriodicAdvertisingEnable {
+    pub enable: bool,
+    /// Include the ADI field (Bluetooth 5.3) so scanners can filter duplicates.
+    pub include_adi: bool,
+}
+impl PeriodicAdvertisingEnable {
+    pub const ENABLE: u8 = 0x01;
+    pub const INCLUDE_ADI: u8 = 0x02;
+    pub const ENABLED: PeriodicAdvertisingEnable = PeriodicAdvertisingEnable {
+        enable: true,
+        include_adi: false,
+    };
+    pub const DISABLED: PeriodicAdvertisingEnable = PeriodicAdvertisingEnable {
+        enable: false,
+        include_adi: false,
+    };
+}
+impl HCIField for PeriodicAdvertisingEnable {
+    const BYTE_LEN: usize = 1;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf[0] = if self.enable { Self::ENABLE } else { 0 }
+            | if self.include_adi {
+                Self::INCLUDE_ADI
+            } else {
+                0
+            };
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        if buf[0] & !(Self::ENABLE | Self::INCLUDE_ADI) != 0 {
+            return None;
+        }
+        Some(PeriodicAdvertisingEnable {
+            enable: buf[0] & Self::ENABLE != 0,
+            include_adi: buf[0] & Self::INCLUDE_ADI != 0,
+        })
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct SetPeriodicAdvertisingEnable: LEControllerOpcode::SetPeriodicAdvertisingEnable
+        => CommandComplete<StatusReturn> {
+        pub enable: PeriodicAdvertisingEnable,
+        pub advertising_handle: u8,
+    }
+}
+/// Options of [`PeriodicAdvertisingCreateSync`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct SyncOptions(pub u8);
+impl SyncOptions {
+    /// Sync to any advertiser on the periodic advertiser list instead of the given one.
+    pub const USE_PERIODIC_ADVERTISER_LIST: u8 = 0x01;
+    /// Don't send [`PeriodicAdvertisingReport`]s until enabled (Bluetooth 5.1).
+    pub const REPORTING_INITIALLY_DISABLED: u8 = 0x02;
+    /// Filter duplicate reports by ADI (Bluetooth 5.3).
+    pub const DUPLICATE_FILTERING: u8 = 0x04;
+    pub const ALL: u8 = 0x07;
+    pub const fn contains(self, bits: u8) -> bool {
+        self.0 & bits == bits
+    }
+}
+impl HCIField for SyncOptions {
+    const BYTE_LEN: usize = 1;
+    fn pack_field(&self, buf: &mut [u8]) {
+        buf[0] = self.0;
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        Some(SyncOptions(buf[0])).filter(|o| o.0 & !Self::ALL == 0)
+    }
+}
+crate::hci_command! {
+    /// Starts synchronizing to a periodic advertising train. The controller scans (scanning
+    /// has to be enabled) until it finds the train and reports the result with
+    /// [`PeriodicAdvertisingSyncEstablished`]. `skip` is the number of events that may be
+    /// skipped (up to 0x01F3) and `sync_timeout` is in 10 ms units (0x000A to 0x4000).
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct PeriodicAdvertisingCreateSync: LEControllerOpcode::PeriodicAdvertisingCreateSync
+        => CommandStatus {
+        pub options: SyncOptions,
+        pub advertising_sid: u8,
+        pub advertiser_address_type: PeerAddressType,
+        pub advertiser_address: BTAddress,
+        pub skip: u16,
+        pub sync_timeout: u16,
+        pub sync_cte_type: u8,
+    }
+}
+impl PeriodicAdvertisingCreateSync {
+    pub const MAX_SKIP: u16 = 0x01F3;
+    pub const MIN_SYNC_TIMEOUT: u16 = 0x000A;
+    pub const MAX_SYNC_TIMEOUT: u16 = 0x4000;
+    /// `true` if every field is in range.
+    pub fn is_valid(&self) -> bool {
+        self.advertising_sid <= MAX_ADVERTISING_SID
+            && self.skip <= Self::MAX_SKIP
+            && (Self::MIN_SYNC_TIMEOUT..=Self::MAX_SYNC_TIMEOUT).contains(&self.sync_timeout)
+    }
+}
+crate::hci_command! {
+    /// Cancels a pending [`PeriodicAdvertisingCreateSync`]. The controller still sends a
+    /// [`PeriodicAdvertisingSyncEstablished`] with `ErrorCode::OperationCancelledByHost`.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct PeriodicAdvertisingCreateSyncCancel: LEControllerOpcode::PeriodicAdvertisingCreateSyncCancel
+        => CommandComplete<StatusReturn> {}
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct PeriodicAdvertisingTerminateSync: LEControllerOpcode::PeriodicAdvertisingTerminateSync
+        => CommandComplete<StatusReturn> {
+        pub sync_handle: u16,
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct AddDeviceToPeriodicAdvertiserList: LEControllerOpcode::AddDeviceToPeriodicAdvertiserList
+        => CommandComplete<StatusReturn> {
+        pub advertiser_address_type: PeerAddressType,
+        pub advertiser_address: BTAddress,
+        pub advertising_sid: u8,
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct RemoveDeviceFromPeriodicAdvertiserList: LEControllerOpcode::RemoveDeviceFromPeriodicAdvertiserList
+        => CommandComplete<StatusReturn> {
+        pub advertiser_address_type: PeerAddressType,
+        pub advertiser_address: BTAddress,
+        pub advertising_sid: u8,
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ClearPeriodicAdvertiserList: LEControllerOpcode::ClearPeriodicAdvertiserList
+        => CommandComplete<StatusReturn> {}
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ReadPeriodicAdvertiserListSize: LEControllerOpcode::ReadPeriodicAdvertiserListSize
+        => CommandComplete<PeriodicAdvertiserListSizeReturn> {}
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct PeriodicAdvertiserListSizeReturn {
+        pub periodic_advertiser_list_size: u8,
+    }
+}
+/// LE Periodic Advertising Sync Established event. `sync_handle` is only valid if `status` is
+/// `ErrorCode::Ok`. Controllers usually zero the other fields when the sync failed (or was
+/// cancelled).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct PeriodicAdvertisingSyncEstablished {
+    pub status: ErrorCode,
+    pub sync_handle: u16,
+    pub advertising_sid: u8,
+    /// Raw address type (`0x02`/`0x03` for resolved identity addresses).
+    pub advertiser_address_type: u8,
+    pub advertiser_address: BTAddress,
+    /// `None` if the sync failed and the field isn't a valid PHY.
+    pub advertiser_phy: Option<AdvertisingPHY>,
+    /// Periodic advertising interval in 1.25 ms units.
+    pub interval: u16,
+    pub advertiser_clock_accuracy: u8,
+}
+impl PeriodicAdvertisingSyncEstablished {
+    pub const BYTE_LEN: usize = 15;
+}
+impl MetaEvent for PeriodicAdvertisingSyncEstablished {
+    const META_CODE: MetaEventCode = MetaEventCode::PeriodicAdvertisingSyncEstablished;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        let mut reader = FieldReader::new(buf);
+        let status: ErrorCode = reader.field()?;
+        let sync_handle = reader.field()?;
+        let advertising_sid = reader.field()?;
+        let advertiser_address_type = reader.field()?;
+        let advertiser_address = reader.field()?;
+        let phy_index = reader.offset();
+        let advertiser_phy = AdvertisingPHY::try_from(reader.field::<u8>()?).ok();
+        if status == ErrorCode::Ok && advertiser_phy.is_none() {
+            return Err(PackError::bad_index(phy_index));
+        }
+        Ok(PeriodicAdvertisingSyncEstablished {
+            status,
+            sync_handle,
+            advertising_sid,
+            advertiser_address_type,
+            advertiser_address,
+            advertiser_phy,
+            interval: reader.field()?,
+            advertiser_clock_accuracy: reader.field()?,
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        let mut writer = FieldWriter::new(buf);
+        writer.field(&self.status)?;
+        writer.field(&self.sync_handle)?;
+        writer.field(&self.advertising_sid)?;
+        writer.field(&self.advertiser_address_type)?;
+        writer.field(&self.advertiser_address)?;
+        writer.field(&self.advertiser_phy.map_or(0, u8::from))?;
+        writer.field(&self.interval)?;
+        writer.field(&self.advertiser_clock_accuracy)?;
+        writer.finish()
+    }
+}
+/// Whether a [`PeriodicAdvertisingReport`] holds the end of the advertising data.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum PeriodicDataStatus {
+    Complete = 0x00,
+    /// More data follows in the next report.
+    Incomplete = 0x01,
+    /// The controller failed to receive the rest.
+    Truncated = 0x02,
+}
+impl From<PeriodicDataStatus> for u8 {
+    fn from(s: PeriodicDataStatus) -> Self {
+        s as u8
+    }
+}
+impl TryFrom<u8> for PeriodicDataStatus {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(PeriodicDataStatus::Complete),
+            0x01 => Ok(PeriodicDataStatus::Incomplete),
+            0x02 => Ok(PeriodicDataStatus::Truncated),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+crate::hci_field_u8!(PeriodicDataStatus);
+/// LE Periodic Advertising Report event. Data longer than one report is split over several
+/// reports with [`PeriodicDataStatus::Incomplete`].
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct PeriodicAdvertisingReport {
+    pub sync_handle: u16,
+    /// Transmit power in dBm or `None` if not available.
+    pub tx_power: Option<i8>,
+    pub rssi: Option<RSSI>,
+    /// `0xFF` if there was no Constant Tone Extension.
+    pub cte_type: u8,
+    pub data_status: PeriodicDataStatus,
+    pub data: Vec<u8>,
+}
+impl PeriodicAdvertisingReport {
+    pub const HEADER_LEN: usize = 7;
+    pub const NOT_AVAILABLE: i8 = 0x7F;
+}
+impl MetaEvent for PeriodicAdvertisingReport {
+    const META_CODE: MetaEventCode = MetaEventCode::PeriodicAdvertisingReport;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::HEADER_LEN + self.data.len()
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::atleast_length(Self::HEADER_LEN, buf)?;
+        PackError::expect_length(Self::HEADER_LEN + usize::from(buf[6]), buf)?;
+        let mut reader = FieldReader::new(buf);
+        let sync_handle = reader.field()?;
+        let tx_power = reader.field()?;
+        let rssi = RSSI::maybe_rssi(reader.field()?).map_err(|_| PackError::bad_index(3))?;
+        let cte_type = reader.field()?;
+        let data_status = reader.field()?;
+        let _data_len: u8 = reader.field()?;
+        Ok(PeriodicAdvertisingReport {
+            sync_handle,
+            tx_power: Some(tx_power).filter(|&p| p != Self::NOT_AVAILABLE),
+            rssi,
+            cte_type,
+            data_status,
+            data: reader.rest().to_vec(),
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.meta_byte_len(), buf)?;
+        let data_len = u8::try_from(self.data.len()).map_err(|_| PackError::bad_index(6))?;
+        let mut writer = FieldWriter::new(buf);
+        writer.field(&self.sync_handle)?;
+        writer.field(&self.tx_power.unwrap_or(Self::NOT_AVAILABLE))?;
+        writer.field(&self.rssi.map_or(RSSI::UNSUPPORTED_RSSI, i8::from))?;
+        writer.field(&self.cte_type)?;
+        writer.field(&self.data_status)?;
+        writer.field(&data_len)?;
+        writer.bytes(&self.data)?;
+        writer.finish()
+    }
+}
+/// LE Periodic Advertising Sync Lost event. The controller didn't receive the train for the
+/// sync timeout and `sync_handle` is freed.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct PeriodicAdvertisingSyncLost {
+    pub sync_handle: u16,
+}
+impl PeriodicAdvertisingSyncLost {
+    pub const BYTE_LEN: usize = 2;
+}
+impl MetaEvent for PeriodicAdvertisingSyncLost {
+    const META_CODE: MetaEventCode = MetaEventCode::PeriodicAdvertisingSyncLost;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        Ok(PeriodicAdvertisingSyncLost {
+            sync_handle: u16::from_le_bytes([buf[0], buf[1]]),
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf.copy_from_slice(&self.sync_handle.to_le_bytes());
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack<C: Command>(command: &C) -> Result<Vec<u8>, PackError> {
+        let mut buf = alloc::vec![0_u8; command.byte_len()];
+        command.pack_into(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_periodic_advertising_commands() {
+        let mut parameters = SetPeriodicAdvertisingParameters {
+            advertising_handle: 1,
+            interval_min: 0x0050,
+            interval_max: 0x0060,
+            properties: SetPeriodicAdvertisingParameters::INCLUDE_TX_POWER,
+        };
+        let buf = pack(&parameters).unwrap();
+        assert_eq!(buf, [0x01, 0x50, 0x00, 0x60, 0x00, 0x40, 0x00]);
+        assert_eq!(
+            SetPeriodicAdvertisingParameters::unpack_from(&buf).unwrap(),
+            parameters
+        );
+        parameters.interval_min = 0x0005;
+        assert!(pack(&parameters).is_err());
+
+        let data = SetPeriodicAdvertisingData {
+            advertising_handle: 1,
+            operation: DataOperation::Complete,
+            data: alloc::vec![0xAB; MAX_PERIODIC_ADVERTISING_DATA_FRAGMENT_LEN + 1],
+        };
+        assert!(pack(&data).is_err());
+
+        let enable = SetPeriodicAdvertisingEnable {
+            enable: PeriodicAdvertisingEnable {
+                enable: true,
+                include_adi: true,
+            },
+            advertising_handle: 2,
+        };
+        assert_eq!(pack(&enable).unwrap(), [0x03, 0x02]);
+
+        let sync = PeriodicAdvertisingCreateSync {
+            options: SyncOptions(SyncOptions::USE_PERIODIC_ADVERTISER_LIST),
+            advertising_sid: 0,
+            advertiser_address_type: PeerAddressType::Public,
+            advertiser_address: BTAddress::ZEROED,
+            skip: 0,
+            sync_timeout: 0x0100,
+            sync_cte_type: 0,
+        };
+        assert!(sync.is_valid());
+        assert_eq!(pack(&sync).unwrap().len(), 14);
+    }
+
+    #[test]
+    fn test_periodic_advertising_report() {
+        let buf = [0x01, 0x00, 0x7F, 0xC4, 0xFF, 0x01, 0x02, 0xAA, 0xBB];
+        let report = PeriodicAdvertisingReport::meta_unpack_from(&buf).unwrap();
+        assert_eq!(report.sync_handle, 1);
+        assert_eq!(report.tx_power, None);
+        assert_eq!(report.data_status, PeriodicDataStatus::Incomplete);
+        assert_eq!(report.data, [0xAA, 0xBB]);
+        let mut packed = [0_u8; 9];
+        report.meta_pack_into(&mut packed).unwrap();
+        assert_eq!(packed, buf);
+    }
+    #[test]
+    fn test_sync_established() {
+        let buf = [
+            0x00, 0x01, 0x00, 0x03, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x02, 0x50, 0x00,
+            0x05,
+        ];
+        let established = PeriodicAdvertisingSyncEstablished::meta_unpack_from(&buf).unwrap();
+        assert_eq!(established.advertising_sid, 3);
+        assert_eq!(established.advertiser_phy, Some(AdvertisingPHY::LE2M));
+        assert_eq!(established.interval, 0x50);
+        let mut packed = [0_u8; PeriodicAdvertisingSyncEstablished::BYTE_LEN];
+        established.meta_pack_into(&mut packed).unwrap();
+        assert_eq!(packed, buf);
+
+        // After LE Periodic Advertising Create Sync Cancel.
+        let mut cancelled = [0_u8; PeriodicAdvertisingSyncEstablished::BYTE_LEN];
+        cancelled[0] = ErrorCode::OperationCancelledByHost.into();
+        let established = PeriodicAdvertisingSyncEstablished::meta_unpack_from(&cancelled).unwrap();
+        assert_eq!(established.status, ErrorCode::OperationCancelledByHost);
+        assert_eq!(established.advertiser_phy, None);
+        established.meta_pack_into(&mut packed).unwrap();
+        assert_eq!(packed, cancelled);
+
+        let mut bad_phy = buf;
+        bad_phy[11] = 0x00;
+        assert_eq!(
+            PeriodicAdvertisingSyncEstablished::meta_unpack_from(&bad_phy),
+            Err(PackError::bad_index(11))
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! LE Power Control (Bluetooth 5.2). Reads the local and remote transmit power of a connection and
+//! reports path loss zone changes ([`PathLossThreshold`]) and transmit power changes
+//! ([`TransmitPowerReporting`]) so the host can adapt its transmit power.
+use crate::hci::definitions::HCIField;
+use crate::hci::event::{CommandComplete, CommandStatus};
+use crate::hci::le::advertise::TxPowerLevel;
+use crate::hci::le::{LEControllerOpcode, MetaEvent, MetaEventCode};
+use crate::hci::ErrorCode;
+use crate::le::connection::ConnectionHandle;
+use crate::{ConversionError, PackError};
+use core::convert::TryFrom;
+
+/// PHY a transmit power level applies to.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum PowerControlPHY {
+    LE1M = 0x01,
+    LE2M = 0x02,
+    LECodedS8 = 0x03,
+    LECodedS2 = 0x04,
+}
+impl Default for PowerControlPHY {
+    fn default() -> Self {
+        PowerControlPHY::LE1M
+    }
+}
+impl From<PowerControlPHY> for u8 {
+    fn from(phy: PowerControlPHY) -> Self {
+        phy as u8
+    }
+}
+impl TryFrom<u8> for PowerControlPHY {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(PowerControlPHY::LE1M),
+            0x02 => Ok(PowerControlPHY::LE2M),
+            0x03 => Ok(PowerControlPHY::LECodedS8),
+            0x04 => Ok(PowerControlPHY::LECodedS2),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+crate::hci_field_u8!(PowerControlPHY, TxPowerLevel);
+/// Transmit power level value meaning the level isn't available.
+pub const TX_POWER_NOT_AVAILABLE: i8 = 0x7F;
+/// Remote transmit power level value meaning the remote isn't managing its power levels.
+pub const TX_POWER_NOT_MANAGED: i8 = 0x7E;
+/// Turns the `0x7E`/`0x7F` transmit power sentinels into `None`.
+fn tx_power(value: i8) -> Option<i8> {
+    match value {
+        TX_POWER_NOT_AVAILABLE | TX_POWER_NOT_MANAGED => None,
+        value => Some(value),
+    }
+}
+crate::hci_command! {
+    /// Reads the lowest and highest transmit power the controller supports (on any PHY).
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ReadTransmitPower: LEControllerOpcode::ReadTransmitPower
+        => CommandComplete<ReadTransmitPowerReturn> {}
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ReadTransmitPowerReturn {
+        pub min_tx_power: TxPowerLevel,
+        pub max_tx_power: TxPowerLevel,
+    }
+}
+crate::hci_command! {
+    /// Reads the current and max transmit power of the local side of a connection on `phy`.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct EnhancedReadTransmitPowerLevel: LEControllerOpcode::EnhancedReadTransmitPowerLevel
+        => CommandComplete<EnhancedReadTransmitPowerLevelReturn> {
+        pub connection_handle: ConnectionHandle,
+        pub phy: PowerControlPHY,
+    }
+}
+crate::hci_return! {
+    /// `current_tx_power_level` is [`TX_POWER_NOT_AVAILABLE`] if the PHY isn't in use.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct EnhancedReadTransmitPowerLevelReturn {
+        pub connection_handle: ConnectionHandle,
+        pub phy: PowerControlPHY,
+        pub current_tx_power_level: i8,
+        pub max_tx_power_level: i8,
+    }
+}
+impl EnhancedReadTransmitPowerLevelReturn {
+    pub fn current(&self) -> Option<i8> {
+        tx_power(self.current_tx_power_level)
+    }
+}
+crate::hci_command! {
+    /// Asks the remote for its transmit power on `phy`. The answer comes as a
+    /// [`TransmitPowerReporting`] event with [`TransmitPowerReportingReason::ReadRemoteComplete`].
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ReadRemoteTransmitPowerLevel: LEControllerOpcode::ReadRemoteTransmitPowerLevel
+        => CommandStatus {
+        pub connection_handle: ConnectionHandle,
+        pub phy: PowerControlPHY,
+    }
+}
+crate::hci_command! {
+    /// Path loss zones, all in dB. Path loss above `high_threshold` is the high zone, below
+    /// `low_threshold` the low zone. A zone is only entered once the path loss crossed its
+    /// threshold by the hysteresis and stayed there for `min_time_spent` connection events.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct SetPathLossReportingParameters: LEControllerOpcode::SetPathLossReportingParameters
+        => CommandComplete<ConnectionHandleReturn> {
+        pub connection_handle: ConnectionHandle,
+        /// `0xFF` to not use the high zone.
+        pub high_threshold: u8,
+        pub high_hysteresis: u8,
+        pub low_threshold: u8,
+        pub low_hysteresis: u8,
+        pub min_time_spent: u16,
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct SetPathLossReportingEnable: LEControllerOpcode::SetPathLossReportingEnable
+        => CommandComplete<ConnectionHandleReturn> {
+        pub connection_handle: ConnectionHandle,
+        pub enable: bool,
+    }
+}
+crate::hci_command! {
+    /// Enables [`TransmitPowerReporting`] events for local and/or remote transmit power changes.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct SetTransmitPowerReportingEnable: LEControllerOpcode::SetTransmitPowerReportingEnable
+        => CommandComplete<ConnectionHandleReturn> {
+        pub connection_handle: ConnectionHandle,
+        pub local_enable: bool,
+        pub remote_enable: bool,
+    }
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct ConnectionHandleReturn {
+        pub connection_handle: ConnectionHandle,
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum PathLossZone {
+    Low = 0x00,
+    Middle = 0x01,
+    High = 0x02,
+}
+impl TryFrom<u8> for PathLossZone {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(PathLossZone::Low),
+            0x01 => Ok(PathLossZone::Middle),
+            0x02 => Ok(PathLossZone::High),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// The path loss of a connection entered a new zone.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct PathLossThreshold {
+    pub connection_handle: ConnectionHandle,
+    /// Path loss in dB. `None` if unavailable.
+    pub current_path_loss: Option<u8>,
+    pub zone_entered: PathLossZone,
+}
+impl PathLossThreshold {
+    pub const BYTE_LEN: usize = 4;
+}
+impl MetaEvent for PathLossThreshold {
+    const META_CODE: MetaEventCode = MetaEventCode::PathLossThreshold;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        Ok(PathLossThreshold {
+            connection_handle: ConnectionHandle::unpack_field(&buf[0..2])
+                .ok_or(PackError::bad_index(0))?,
+            current_path_loss: match buf[2] {
+                0xFF => None,
+                path_loss => Some(path_loss),
+            },
+            zone_entered: PathLossZone::try_from(buf[3]).map_err(|_| PackError::bad_index(3))?,
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        self.connection_handle.pack_field(&mut buf[0..2]);
+        buf[2] = self.current_path_loss.unwrap_or(0xFF);
+        buf[3] = self.zone_entered as u8;
+        Ok(())
+    }
+}
+/// Why a [`TransmitPowerReporting`] event was sent.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum TransmitPowerReportingReason {
+    LocalChanged = 0x00,
+    RemoteChanged = 0x01,
+    ReadRemoteComplete = 0x02,
+}
+impl TryFrom<u8> for TransmitPowerReportingReason {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(TransmitPowerReportingReason::LocalChanged),
+            0x01 => Ok(TransmitPowerReportingReason::RemoteChanged),
+            0x02 => Ok(TransmitPowerReportingReason::ReadRemoteComplete),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// Local or remote transmit power of a connection changed (or was read).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct TransmitPowerReporting {
+    pub status: ErrorCode,
+    pub connection_handle: ConnectionHandle,
+    pub reason: TransmitPowerReportingReason,
+    pub phy: PowerControlPHY,
+    /// `None` if unavailable or the remote isn't managing its power.
+    pub tx_power_level: Option<i8>,
+    pub at_min: bool,
+    pub at_max: bool,
+    /// Change since the last report in dB. `None` if unavailable.
+    pub delta: Option<i8>,
+}
+impl TransmitPowerReporting {
+    pub const BYTE_LEN: usize = 8;
+}
+impl MetaEvent for TransmitPowerReporting {
+    const META_CODE: MetaEventCode = MetaEventCode::TransmitPowerReporting;
+
+    fn meta_byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        Ok(TransmitPowerReporting {
+            status: ErrorCode::try_from(buf[0]).map_err(|_| PackError::bad_index(0))?,
+            connection_handle: ConnectionHandle::unpack_field(&buf[1..3])
+                .ok_or(PackError::bad_index(1))?,
+            reason: TransmitPowerReportingReason::try_from(buf[3])
+                .map_err(|_| PackError::bad_index(3))?,
+            phy: PowerControlPHY::try_from(buf[4]).map_err(|_| PackError::bad_index(4))?,
+            tx_power_level: tx_power(i8::from_le_bytes([buf[5]])),
+            at_min: buf[6] & 0b01 != 0,
+            at_max: buf[6] & 0b10 != 0,
+            delta: match i8::from_le_bytes([buf[7]]) {
+                TX_POWER_NOT_AVAILABLE => None,
+                delta => Some(delta),
+            },
+        })
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf[0] = self.status.into();
+        self.connection_handle.pack_field(&mut buf[1..3]);
+        buf[3] = self.reason as u8;
+        buf[4] = self.phy.into();
+        buf[5] = self
+            .tx_power_level
+            .unwrap_or(TX_POWER_NOT_AVAILABLE)
+            .to_le_bytes()[0];
+        buf[6] = u8::from(self.at_min) | u8::from(self.at_max) << 1;
+        buf[7] = self.delta.unwrap_or(TX_POWER_NOT_AVAILABLE).to_le_bytes()[0];
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::command::Command;
+    use crate::hci::event::ReturnParameters;
+
+    #[test]
+    fn test_power_control_packets() {
+        let handle = ConnectionHandle::new_checked(0x0040).unwrap();
+        let parameters = SetPathLossReportingParameters {
+            connection_handle: handle,
+            high_threshold: 70,
+            high_hysteresis: 5,
+            low_threshold: 40,
+            low_hysteresis: 5,
+            min_time_spent: 8,
+        };
+        let mut buf = [0_u8; SetPathLossReportingParameters::BYTE_LEN];
+        parameters.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x40, 0x00, 70, 5, 40, 5, 0x08, 0x00]);
+
+        let ret = EnhancedReadTransmitPowerLevelReturn::unpack_from(&[
+            0x00, 0x40, 0x00, 0x03, 0x7F, 0x08,
+        ])
+        .unwrap();
+        assert_eq!(ret.phy, PowerControlPHY::LECodedS8);
+        assert_eq!(ret.current(), None);
+        assert_eq!(ret.max_tx_power_level, 8);
+        assert!(ReadTransmitPowerReturn::unpack_from(&[0x00, 0x80, 0x15]).is_err());
+
+        let report = [0x00, 0x40, 0x00, 0x01, 0x02, 0xF6, 0x01, 0xFB];
+        let event = TransmitPowerReporting::meta_unpack_from(&report).unwrap();
+        assert_eq!(event.reason, TransmitPowerReportingReason::RemoteChanged);
+        assert_eq!(event.tx_power_level, Some(-10));
+        assert!(event.at_min && !event.at_max);
+        assert_eq!(event.delta, Some(-5));
+        let mut buf = [0_u8; TransmitPowerReporting::BYTE_LEN];
+        event.meta_pack_into(&mut buf).unwrap();
+        assert_eq!(buf, report);
+
+        let event = PathLossThreshold::meta_unpack_from(&[0x40, 0x00, 0xFF, 0x02]).unwrap();
+        assert_eq!(event.current_path_loss, None);
+        assert_eq!(event.zone_entered, PathLossZone::High);
+    }
+}
@@ -1,9 +1,9 @@
-//! LE [`Rand`] command and return parameters.
+//! LE [`Rand`] command and return parameters and [`SetRandomAddress`].
 use crate::hci::command::Command;
-use crate::hci::event::{CommandComplete, ReturnParameters};
+use crate::hci::event::{CommandComplete, ReturnParameters, StatusReturn};
 use crate::hci::le::LEControllerOpcode;
 use crate::hci::{ErrorCode, Opcode};
-use crate::PackError;
+use crate::{BTAddress, PackError};
 use core::convert::{TryFrom, TryInto};
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default)]
@@ -62,3 +62,12 @@ impl ReturnParameters for RandReturn {
         })
     }
 }
+crate::hci_command! {
+    /// Sets the random device address used by the `Random` and `PrivateOrRandom`
+    /// [`OwnAddressType`](crate::le::scan::OwnAddressType)s.
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct SetRandomAddress: LEControllerOpcode::SetRandomAddress
+        => CommandComplete<StatusReturn> {
+        pub random_address: BTAddress,
+    }
+}
@@ -1,10 +1,27 @@
 //! LE [`AdvertisingReport`] and [`ReportInfo`] types.
+#![forbid(unsafe_code)]
+#![cfg_attr(
+    all(feature = "panic-free", not(test)),
+    deny(
+        clippy::indexing_slicing,
+        clippy::expect_used,
+        clippy::unwrap_used,
+        clippy::panic,
+        clippy::unreachable
+    )
+)]
 use crate::bytes::Storage;
 use crate::hci::le::{MetaEvent, MetaEventCode};
 use crate::le::advertisement::{RawAdvertisement, StaticAdvBuffer, MAX_ADV_LEN};
 use crate::le::report::{AddressType, EventType, NumReports, ReportInfo};
 use crate::{BTAddress, PackError, BT_ADDRESS_LEN, RSSI};
 use core::convert::TryFrom;
+use core::ops::Range;
+
+fn slot(buf: &mut [u8], range: Range<usize>) -> Result<&mut [u8], PackError> {
+    let start = range.start;
+    buf.get_mut(range).ok_or(PackError::bad_index(start))
+}
 
 #[derive(Copy, Clone, Debug)]
 pub struct AdvertisingReport<T: AsRef<[ReportInfo<B>]>, B: AsRef<[u8]> = StaticAdvBuffer> {
@@ -49,15 +66,18 @@ impl<T: Storage<ReportInfo<B>>, B: Storage<u8> + Default + Copy> MetaEvent
     where
         Self: Sized,
     {
-        let num_reports = NumReports::try_from(*buf.get(0).ok_or(PackError::BadLength {
+        let num_reports = NumReports::try_from(*buf.first().ok_or(PackError::BadLength {
             expected: 1,
             got: 0,
         })?)
         .map_err(|_| PackError::bad_index(0))?;
         let reports_len = usize::from(u8::from(num_reports));
+        if reports_len > T::max_len() {
+            return Err(PackError::bad_index(0));
+        }
         let mut out = AdvertisingReport::new(T::with_size(reports_len));
         let mut total_data_len = 0usize;
-        for i in 0..reports_len {
+        for (i, report) in out.reports.as_mut().iter_mut().enumerate() {
             let event_type_index = i + 1;
             let address_type_index = event_type_index + reports_len;
             let address_index = address_type_index + reports_len;
@@ -75,34 +95,41 @@ impl<T: Storage<ReportInfo<B>>, B: Storage<u8> + Default + Copy> MetaEvent
                 _ => return Err(PackError::bad_index(address_type_index)),
             };
 
-            let address =
-                BTAddress::unpack_from(&buf[address_index..address_index + BT_ADDRESS_LEN])?;
+            let address = BTAddress::unpack_from(
+                buf.get(address_index..address_index + BT_ADDRESS_LEN)
+                    .ok_or(PackError::bad_index(address_index))?,
+            )?;
             let data_len = buf
                 .get(data_len_index)
-                .map(|e| *e)
+                .copied()
                 .ok_or(PackError::bad_index(data_len_index))?;
             let data_index_end = data_index + 1 + usize::from(data_len);
             if usize::from(data_len) > MAX_ADV_LEN {
                 return Err(PackError::bad_index(data_len_index));
             }
-            let data = &buf[data_index + 1..data_index_end];
-            PackError::expect_length(data_len.into(), data)?;
-            out.reports.as_mut()[i] = ReportInfo {
+            let data = buf
+                .get(data_index + 1..data_index_end)
+                .ok_or(PackError::bad_index(data_index + 1))?;
+            *report = ReportInfo {
                 event_type,
                 address_type,
                 address,
-                data: RawAdvertisement(B::from_slice(data)),
+                data: RawAdvertisement(
+                    B::try_from_slice(data).ok_or(PackError::bad_index(data_len_index))?,
+                ),
                 rssi: None,
             };
             total_data_len += usize::from(data_len);
         }
-        for i in 0..reports_len {
+        for (i, report) in out.reports.as_mut().iter_mut().enumerate() {
             let rssi_index = 1 + (1 + 1 + 1 + BT_ADDRESS_LEN) * reports_len + total_data_len + i;
-            out.reports.as_mut()[i].rssi =
-                match buf.get(rssi_index).map(|val| RSSI::maybe_rssi(*val as i8)) {
-                    Some(Ok(maybe_rssi)) => maybe_rssi,
-                    _ => return Err(PackError::bad_index(rssi_index)),
-                }
+            report.rssi = match buf
+                .get(rssi_index)
+                .map(|val| RSSI::maybe_rssi(i8::from_le_bytes([*val])))
+            {
+                Some(Ok(maybe_rssi)) => maybe_rssi,
+                _ => return Err(PackError::bad_index(rssi_index)),
+            }
         }
         Ok(out)
     }
@@ -115,35 +142,35 @@ impl<T: Storage<ReportInfo<B>>, B: Storage<u8> + Default + Copy> MetaEvent
         let full = self.byte_len();
         PackError::expect_length(full, buf)?;
         let mut total_data_len = 0usize;
-        for i in 0..reports_len {
-            let report = &reports[i];
+        // Same layout `meta_unpack_from` reads.
+        for (i, report) in reports.iter().enumerate() {
             let data = report.data.as_ref();
-            let data_len = data.len();
-            if data_len > MAX_ADV_LEN {
+            let data_len = u8::try_from(data.len()).map_err(|_| PackError::InvalidFields)?;
+            if data.len() > MAX_ADV_LEN {
                 return Err(PackError::InvalidFields);
             }
             let event_type_index = i + 1;
             let address_type_index = event_type_index + reports_len;
             let address_index = address_type_index + reports_len;
             let data_len_index = address_index + BT_ADDRESS_LEN * reports_len;
-            let data_index = data_len_index + total_data_len;
-            let data_index_end = data_index + usize::from(data_len);
-            buf[event_type_index] = report.event_type.into();
-            buf[address_type_index] = report.address_type.into();
+            let data_index = data_len_index + total_data_len + 1;
+            slot(buf, event_type_index..event_type_index + 1)?
+                .copy_from_slice(&[report.event_type.into()]);
+            slot(buf, address_type_index..address_type_index + 1)?
+                .copy_from_slice(&[report.address_type.into()]);
             report
                 .address
-                .pack_into(&mut buf[address_type_index..address_type_index + BT_ADDRESS_LEN])?;
-            buf[data_index..data_index_end].copy_from_slice(data);
-            total_data_len += data_len;
+                .pack_into(slot(buf, address_index..address_index + BT_ADDRESS_LEN)?)?;
+            slot(buf, data_len_index..data_len_index + 1)?.copy_from_slice(&[data_len]);
+            slot(buf, data_index..data_index + data.len())?.copy_from_slice(data);
+            total_data_len += data.len();
         }
-        for i in 0..reports_len {
+        for (i, report) in reports.iter().enumerate() {
             let rssi_index = 1 + (1 + 1 + 1 + BT_ADDRESS_LEN) * reports_len + total_data_len + i;
-            buf[rssi_index] = reports[i]
-                .rssi
-                .map(i8::from)
-                .unwrap_or(RSSI::UNSUPPORTED_RSSI) as u8;
+            let rssi = report.rssi.map_or(RSSI::UNSUPPORTED_RSSI, i8::from);
+            slot(buf, rssi_index..rssi_index + 1)?.copy_from_slice(&rssi.to_le_bytes());
         }
-        buf[0] = num_reports.into();
+        slot(buf, 0..1)?.copy_from_slice(&[num_reports.into()]);
         Ok(())
     }
 }
@@ -175,3 +202,64 @@ impl<Buf: AsRef<[ReportInfo<ReportBuf>]>, ReportBuf: AsRef<[u8]> + Clone> Iterat
         Some(report.clone())
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::event::{
+        CommandComplete, CommandStatus, Event, EventPacket, ReturnEvent, StatusReturn,
+    };
+    use crate::hci::le::RawMetaEvent;
+    use crate::hci::packet::RawPacket;
+    use alloc::vec::Vec;
+    /// Runs every parser a radio/controller packet goes through.
+    fn parse_all(bytes: &[u8]) {
+        let _ = RawAdvertisement(bytes).iter().count();
+        let event = match RawPacket::try_from(bytes).map(EventPacket::try_from) {
+            Ok(Ok(event)) => event,
+            _ => return,
+        };
+        let _ = CommandComplete::<StatusReturn>::unpack_event_packet(&event);
+        let _ = CommandStatus::unpack_event_packet(&event);
+        let _ = CommandComplete::<StatusReturn>::guess_command_opcode(event.parameters());
+        let _ = CommandStatus::guess_command_opcode(event.parameters());
+        if let Ok(meta) = RawMetaEvent::try_from(event) {
+            let _ = AdvertisingReport::<Vec<ReportInfo>>::meta_unpack_packet(meta);
+        }
+    }
+    #[test]
+    fn test_malformed_input_never_panics() {
+        // LE Advertising Report with one report (flags AD structure, -56 dBm).
+        let report = [
+            0x04, 0x3E, 0x0F, 0x02, 0x01, 0x00, 0x00, 1, 2, 3, 4, 5, 6, 0x03, 0x02, 0x01, 0x06,
+            0xC8,
+        ];
+        let meta = RawMetaEvent::try_from(
+            EventPacket::try_from(RawPacket::try_from(&report[..]).unwrap()).unwrap(),
+        )
+        .unwrap();
+        let parsed = AdvertisingReport::<Vec<ReportInfo>>::meta_unpack_packet(meta).unwrap();
+        let mut packed = [0_u8; 15];
+        parsed.meta_pack_into(&mut packed[1..]).unwrap();
+        assert_eq!(packed[1..], report[4..]);
+
+        let alphabet = [0x00, 0x01, 0x02, 0x04, 0x0E, 0x0F, 0x3E, 0xFF];
+        for a in alphabet.iter() {
+            for b in alphabet.iter() {
+                for c in alphabet.iter() {
+                    parse_all(&[*a, *b, *c]);
+                    parse_all(&[*a, *b, *c, 0x02, 0xFF]);
+                }
+            }
+        }
+        for len in 0..=report.len() {
+            parse_all(&report[..len]);
+        }
+        for i in 0..report.len() {
+            for value in alphabet.iter() {
+                let mut mutated = report;
+                mutated[i] = *value;
+                parse_all(&mutated);
+            }
+        }
+    }
+}
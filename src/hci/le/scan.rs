@@ -1,12 +1,18 @@
-//! LE [`SetScanEnable`], [`SetScanParameters`], and other primitive scan types.
+//! LE [`SetScanEnable`], [`SetScanParameters`], their extended versions and other primitive scan
+//! types.
 use crate::bytes::ToFromBytesEndian;
 use crate::hci::command::Command;
 use crate::hci::event::{CommandComplete, StatusReturn};
-use crate::hci::le::LEControllerOpcode;
+use crate::hci::le::{LEControllerOpcode, MetaEvent, MetaEventCode};
 use crate::hci::Opcode;
-use crate::le::scan::ScanParameters;
+use crate::le::scan::{
+    ExtendedScanInterval, ExtendedScanParameters, ExtendedScanWindow, FilterDuplicates,
+    OwnAddressType, PHYScanParameters, ScanInterval, ScanParameters, ScanType, ScanWindow,
+    ScanningFilterPolicy,
+};
 use crate::PackError;
 use core::convert::{TryFrom, TryInto};
+use core::time::Duration;
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
 pub struct SetScanEnable {
@@ -79,25 +85,38 @@ impl Command for SetScanParameters {
     fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
         PackError::expect_length(SET_SCAN_PARAMETERS_LEN, buf)?;
         let scan_parameters = &self.0;
-        let window = u16::from(scan_parameters.scan_window);
-        let interval = u16::from(scan_parameters.scan_interval);
-        if window > interval {
+        if !scan_parameters.is_valid() {
             // The scan window should always be less than or equal to the scan interval.
             return Err(PackError::InvalidFields);
         }
         buf[0] = scan_parameters.scan_type.into();
-        buf[1..3].copy_from_slice(&interval.to_bytes_le()[..]);
-        buf[3..5].copy_from_slice(&window.to_bytes_le()[..]);
+        buf[1..3].copy_from_slice(&u16::from(scan_parameters.scan_interval).to_bytes_le()[..]);
+        buf[3..5].copy_from_slice(&u16::from(scan_parameters.scan_window).to_bytes_le()[..]);
         buf[5] = scan_parameters.own_address_type.into();
         buf[6] = scan_parameters.scanning_filter_policy.into();
         Ok(())
     }
 
-    fn unpack_from(_buf: &[u8]) -> Result<Self, PackError>
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
     where
         Self: Sized,
     {
-        unimplemented!()
+        PackError::expect_length(SET_SCAN_PARAMETERS_LEN, buf)?;
+        let interval = u16::from_bytes_le(&buf[1..3]).expect("length checked above");
+        let window = u16::from_bytes_le(&buf[3..5]).expect("length checked above");
+        let scan_parameters = ScanParameters {
+            scan_type: ScanType::try_from(buf[0]).map_err(|_| PackError::bad_index(0))?,
+            scan_interval: ScanInterval::new_checked(interval).ok_or(PackError::bad_index(1))?,
+            scan_window: ScanWindow::new_checked(window).ok_or(PackError::bad_index(3))?,
+            own_address_type: OwnAddressType::try_from(buf[5])
+                .map_err(|_| PackError::bad_index(5))?,
+            scanning_filter_policy: ScanningFilterPolicy::try_from(buf[6])
+                .map_err(|_| PackError::bad_index(6))?,
+        };
+        if !scan_parameters.is_valid() {
+            return Err(PackError::InvalidFields);
+        }
+        Ok(SetScanParameters(scan_parameters))
     }
 }
 pub const MAX_RESPONSE_DATA_LEN: usize = 31;
@@ -172,8 +191,267 @@ impl Command for SetScanResponseData {
     {
         PackError::expect_length(Self::BYTE_LEN, buf)?;
         let len = buf[0];
+        if usize::from(len) > MAX_RESPONSE_DATA_LEN {
+            return Err(PackError::bad_index(0));
+        }
         let mut data = [0_u8; MAX_RESPONSE_DATA_LEN];
         data.copy_from_slice(&buf[1..]);
         Ok(SetScanResponseData { len, data })
     }
 }
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct SetExtendedScanParameters(pub ExtendedScanParameters);
+impl SetExtendedScanParameters {
+    pub const OPCODE: LEControllerOpcode = LEControllerOpcode::SetExtendedScanParameters;
+    pub const HEADER_LEN: usize = 3;
+}
+impl Command for SetExtendedScanParameters {
+    type Return = CommandComplete<StatusReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::HEADER_LEN + self.0.phys().count() * PHYScanParameters::BYTE_LEN
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        self.0.validate().map_err(|_| PackError::InvalidFields)?;
+        buf[0] = self.0.own_address_type.into();
+        buf[1] = self.0.scanning_filter_policy.into();
+        buf[2] = self.0.scanning_phys();
+        for (phy, out) in self
+            .0
+            .phys()
+            .zip(buf[Self::HEADER_LEN..].chunks_exact_mut(PHYScanParameters::BYTE_LEN))
+        {
+            out[0] = phy.scan_type.into();
+            out[1..3].copy_from_slice(&u16::from(phy.scan_interval).to_bytes_le()[..]);
+            out[3..5].copy_from_slice(&u16::from(phy.scan_window).to_bytes_le()[..]);
+        }
+        Ok(())
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        if buf.len() < Self::HEADER_LEN {
+            return Err(PackError::BadLength {
+                expected: Self::HEADER_LEN,
+                got: buf.len(),
+            });
+        }
+        let own_address_type =
+            OwnAddressType::try_from(buf[0]).map_err(|_| PackError::bad_index(0))?;
+        let scanning_filter_policy =
+            ScanningFilterPolicy::try_from(buf[1]).map_err(|_| PackError::bad_index(1))?;
+        let phys = buf[2];
+        if phys & !(ExtendedScanParameters::LE_1M_BIT | ExtendedScanParameters::LE_CODED_BIT) != 0 {
+            return Err(PackError::bad_index(2));
+        }
+        let count = phys.count_ones() as usize;
+        PackError::expect_length(Self::HEADER_LEN + count * PHYScanParameters::BYTE_LEN, buf)?;
+        let mut chunks = buf[Self::HEADER_LEN..]
+            .chunks_exact(PHYScanParameters::BYTE_LEN)
+            .enumerate();
+        let mut next = || -> Result<PHYScanParameters, PackError> {
+            let (i, chunk) = chunks.next().ok_or(PackError::InvalidFields)?;
+            let index = Self::HEADER_LEN + i * PHYScanParameters::BYTE_LEN;
+            let interval = u16::from_bytes_le(&chunk[1..3]).expect("length checked above");
+            let window = u16::from_bytes_le(&chunk[3..5]).expect("length checked above");
+            Ok(PHYScanParameters {
+                scan_type: ScanType::try_from(chunk[0]).map_err(|_| PackError::bad_index(index))?,
+                scan_interval: ExtendedScanInterval::new_checked(interval)
+                    .ok_or_else(|| PackError::bad_index(index + 1))?,
+                scan_window: ExtendedScanWindow::new_checked(window)
+                    .ok_or_else(|| PackError::bad_index(index + 3))?,
+            })
+        };
+        let le_1m = if phys & ExtendedScanParameters::LE_1M_BIT != 0 {
+            Some(next()?)
+        } else {
+            None
+        };
+        let le_coded = if phys & ExtendedScanParameters::LE_CODED_BIT != 0 {
+            Some(next()?)
+        } else {
+            None
+        };
+        Ok(SetExtendedScanParameters(ExtendedScanParameters {
+            own_address_type,
+            scanning_filter_policy,
+            le_1m,
+            le_coded,
+        }))
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct SetExtendedScanEnable: LEControllerOpcode::SetExtendedScanEnable
+        => CommandComplete<StatusReturn> {
+        pub is_enabled: bool,
+        pub filter_duplicates: FilterDuplicates,
+        /// Scan duration in 10 ms units. `0` scans until disabled.
+        pub duration: u16,
+        /// Time between the start of scan durations in 1.28 s units. `0` scans continuously.
+        pub period: u16,
+    }
+}
+impl SetExtendedScanEnable {
+    /// 1.28 s periods in 10 ms duration units.
+    const DURATION_PER_PERIOD: u32 = 128;
+    pub fn new(is_enabled: bool, filter_duplicates: FilterDuplicates) -> SetExtendedScanEnable {
+        SetExtendedScanEnable {
+            is_enabled,
+            filter_duplicates,
+            duration: 0,
+            period: 0,
+        }
+    }
+    /// Scans for `duration` (rounded up to 10 ms) every `period` (rounded down to 1.28 s) or only
+    /// once if `period` is zero. The controller sends a [`ScanTimeout`] when it stops.
+    pub fn limited(
+        filter_duplicates: FilterDuplicates,
+        duration: Duration,
+        period: Duration,
+    ) -> SetExtendedScanEnable {
+        let duration = duration.as_millis().saturating_add(9) / 10;
+        let period = period.as_millis() / 1280;
+        SetExtendedScanEnable {
+            is_enabled: true,
+            filter_duplicates,
+            duration: u16::try_from(duration.max(1)).unwrap_or(u16::MAX),
+            period: u16::try_from(period).unwrap_or(u16::MAX),
+        }
+    }
+    /// `true` if the controller takes the duration and period: a period needs a (shorter)
+    /// duration and [`FilterDuplicates::ResetEachPeriod`] needs both. Disabling ignores them.
+    pub fn is_valid(&self) -> bool {
+        if !self.is_enabled {
+            return true;
+        }
+        if self.period != 0
+            && (self.duration == 0
+                || u32::from(self.duration) >= u32::from(self.period) * Self::DURATION_PER_PERIOD)
+        {
+            return false;
+        }
+        self.filter_duplicates != FilterDuplicates::ResetEachPeriod || self.period != 0
+    }
+}
+/// LE Scan Timeout event. Extended scanning with a duration (and no period) ended.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct ScanTimeout;
+impl MetaEvent for ScanTimeout {
+    const META_CODE: MetaEventCode = MetaEventCode::ScanTimeout;
+
+    fn meta_byte_len(&self) -> usize {
+        0
+    }
+
+    fn meta_unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(0, buf)?;
+        Ok(ScanTimeout)
+    }
+
+    fn meta_pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(0, buf)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_scan_commands() {
+        let parameters = SetScanParameters(ScanParameters {
+            scan_type: ScanType::Active,
+            scan_interval: ScanInterval::new(0x0060),
+            scan_window: ScanWindow::new(0x0030),
+            own_address_type: OwnAddressType::Random,
+            scanning_filter_policy: ScanningFilterPolicy::All,
+        });
+        let mut buf = [0_u8; SET_SCAN_PARAMETERS_LEN];
+        parameters.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x01, 0x60, 0x00, 0x30, 0x00, 0x01, 0x00]);
+        assert_eq!(SetScanParameters::unpack_from(&buf), Ok(parameters));
+        // Window larger than the interval.
+        buf[3] = 0x70;
+        assert_eq!(
+            SetScanParameters::unpack_from(&buf),
+            Err(PackError::InvalidFields)
+        );
+
+        let enable = SetScanEnable {
+            is_enabled: true,
+            filter_duplicates: false,
+        };
+        let mut buf = [0_u8; SetScanEnable::BYTE_LEN];
+        enable.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x01, 0x00]);
+        assert_eq!(SetScanEnable::unpack_from(&buf), Ok(enable));
+        assert!(SetScanEnable::unpack_from(&[0x02, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_extended_scan_parameters() {
+        let parameters = SetExtendedScanParameters(ExtendedScanParameters {
+            own_address_type: OwnAddressType::Random,
+            scanning_filter_policy: ScanningFilterPolicy::All,
+            le_1m: Some(PHYScanParameters {
+                scan_type: ScanType::Active,
+                scan_interval: ExtendedScanInterval::new(0x8000),
+                scan_window: ExtendedScanWindow::new(0x4800),
+            }),
+            le_coded: Some(PHYScanParameters::DEFAULT),
+        });
+        let mut buf = [0_u8; 13];
+        assert_eq!(parameters.byte_len(), buf.len());
+        parameters.pack_into(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            [0x01, 0x00, 0x05, 0x01, 0x00, 0x80, 0x00, 0x48, 0x00, 0x10, 0x00, 0x10, 0x00]
+        );
+        assert_eq!(SetExtendedScanParameters::unpack_from(&buf), Ok(parameters));
+        // Interval below 0x0004.
+        buf[4..6].copy_from_slice(&[0x03, 0x00]);
+        assert_eq!(
+            SetExtendedScanParameters::unpack_from(&buf),
+            Err(PackError::bad_index(4))
+        );
+        assert_eq!(
+            ExtendedScanInterval::from(ScanInterval::MAX),
+            ExtendedScanInterval::new(0x4000)
+        );
+    }
+
+    #[test]
+    fn test_extended_scan_enable_duration() {
+        let enable = SetExtendedScanEnable::limited(
+            FilterDuplicates::ResetEachPeriod,
+            Duration::from_millis(1005),
+            Duration::from_secs(3),
+        );
+        assert_eq!((enable.duration, enable.period), (101, 2));
+        assert!(enable.is_valid());
+        // The duration has to be shorter than the period.
+        let too_long = SetExtendedScanEnable {
+            duration: 256,
+            ..enable
+        };
+        assert!(!too_long.is_valid());
+        let no_period = SetExtendedScanEnable {
+            period: 0,
+            ..enable
+        };
+        assert!(!no_period.is_valid());
+        assert!(SetExtendedScanEnable::new(true, FilterDuplicates::Enabled).is_valid());
+        assert_eq!(ScanTimeout::meta_unpack_from(&[]), Ok(ScanTimeout));
+    }
+}
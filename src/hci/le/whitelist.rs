@@ -0,0 +1,81 @@
+//! LE White List commands. See [`crate::le::whitelist`] for host side emulation.
+use crate::hci::definitions::HCIField;
+use crate::hci::event::{CommandComplete, StatusReturn};
+use crate::hci::le::LEControllerOpcode;
+use crate::le::whitelist::{WhitelistAddressType, WhitelistEntry};
+use crate::BTAddress;
+
+impl HCIField for WhitelistEntry {
+    const BYTE_LEN: usize = WhitelistEntry::BYTE_LEN;
+    fn pack_field(&self, buf: &mut [u8]) {
+        self.address_type.pack_field(&mut buf[..1]);
+        self.address.pack_field(&mut buf[1..]);
+    }
+    fn unpack_field(buf: &[u8]) -> Option<Self> {
+        Some(WhitelistEntry {
+            address_type: WhitelistAddressType::unpack_field(&buf[..1])?,
+            address: BTAddress::unpack_field(&buf[1..])?,
+        })
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ReadWhitelistSize: LEControllerOpcode::ReadWhitelistSize
+        => CommandComplete<WhitelistSizeReturn> {}
+}
+crate::hci_return! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct WhitelistSizeReturn {
+        pub whitelist_size: u8,
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+    pub struct ClearWhitelist: LEControllerOpcode::ClearWhitelist
+        => CommandComplete<StatusReturn> {}
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct AddDeviceToWhitelist: LEControllerOpcode::AddDeviceToWhitelist
+        => CommandComplete<StatusReturn> {
+        pub entry: WhitelistEntry,
+    }
+}
+crate::hci_command! {
+    #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+    pub struct RemoveDeviceFromWhitelist: LEControllerOpcode::RemoveDeviceFromWhitelist
+        => CommandComplete<StatusReturn> {
+        pub entry: WhitelistEntry,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::command::Command;
+    use crate::hci::event::ReturnParameters;
+
+    #[test]
+    fn test_whitelist_commands() {
+        let add = AddDeviceToWhitelist {
+            entry: WhitelistEntry::new(WhitelistAddressType::Random, BTAddress([1, 2, 3, 4, 5, 6])),
+        };
+        let mut buf = [0_u8; WhitelistEntry::BYTE_LEN];
+        assert_eq!(add.byte_len(), buf.len());
+        add.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x01, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(AddDeviceToWhitelist::unpack_from(&buf), Ok(add));
+        buf[0] = 0xFF;
+        assert_eq!(
+            RemoveDeviceFromWhitelist::unpack_from(&buf).map(|r| r.entry.address_type),
+            Ok(WhitelistAddressType::Anonymous)
+        );
+        buf[0] = 0x02;
+        assert!(AddDeviceToWhitelist::unpack_from(&buf).is_err());
+
+        let size = WhitelistSizeReturn::unpack_from(&[0x00, 0x10]).unwrap();
+        assert_eq!(size.whitelist_size, 0x10);
+        let mut buf = [0_u8; WhitelistSizeReturn::BYTE_LEN];
+        size.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x00, 0x10]);
+    }
+}
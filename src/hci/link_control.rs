@@ -1,5 +1,10 @@
 //! Link Controller module (WIP).
-use crate::hci::{Opcode, OCF, OGF};
+use crate::hci::command::Command;
+use crate::hci::event::CommandStatus;
+use crate::hci::{ErrorCode, Opcode, OCF, OGF};
+use crate::le::connection::ConnectionHandle;
+use crate::PackError;
+use core::convert::{TryFrom, TryInto};
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
 #[repr(u16)]
@@ -42,3 +47,49 @@ impl From<LinkControlOpcode> for Opcode {
         Self(OGF::LinkControl, opcode.into())
     }
 }
+/// Terminate an existing connection. The controller responds with a `CommandStatus` and later a
+/// Disconnection Complete event.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct Disconnect {
+    pub connection_handle: ConnectionHandle,
+    /// Usually `ErrorCode::OtherEndTerminatedConnectionUserEndedConnection`.
+    pub reason: ErrorCode,
+}
+impl Disconnect {
+    pub const BYTE_LEN: usize = ConnectionHandle::BYTE_LEN + ErrorCode::BYTE_LEN;
+    pub const OPCODE: LinkControlOpcode = LinkControlOpcode::Disconnect;
+}
+impl Command for Disconnect {
+    type Return = CommandStatus;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf[0..2].copy_from_slice(&u16::from(self.connection_handle).to_le_bytes()[..]);
+        buf[2] = self.reason.into();
+        Ok(())
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        let connection_handle = ConnectionHandle::new_checked(u16::from_le_bytes(
+            (&buf[0..2]).try_into().expect("len checked above"),
+        ))
+        .ok_or_else(|| PackError::bad_index(0))?;
+        let reason = ErrorCode::try_from(buf[2]).map_err(|_| PackError::bad_index(2))?;
+        Ok(Disconnect {
+            connection_handle,
+            reason,
+        })
+    }
+}
@@ -1,32 +1,135 @@
 //! HCI Layer (where most the magic happens). Implements a Bluetooth Adapter for any controller
 //! supporting HCI streams.
 //! (HCI Layer is Little Endian).
+pub mod acl;
 pub mod adapter;
 pub mod adapters;
 pub mod baseband;
 #[cfg(all(unix, feature = "bluez_socket"))]
 pub mod bluez_socket;
+#[cfg(feature = "bredr")]
+pub mod bredr;
 pub mod command;
+pub mod definitions;
+pub mod device_id;
 pub mod event;
+pub mod flow;
+pub mod informational;
+pub mod io;
+pub mod iso;
+pub mod isr_ring;
 pub mod le;
 pub mod link_control;
 pub mod packet;
+#[cfg(feature = "bytes")]
+pub mod pool;
+#[cfg(feature = "std")]
+pub mod split;
 #[cfg(feature = "remote")]
 pub mod remote;
+pub mod retry;
+#[cfg(all(target_os = "linux", feature = "rfkill"))]
+pub mod rfkill;
+pub mod sco;
+#[cfg(feature = "serialport")]
+pub mod serial;
+pub mod status;
 pub mod stream;
+pub mod timer;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+#[cfg(feature = "uart")]
+pub mod uart;
 #[cfg(feature = "hci_usb")]
 pub mod usb;
+pub mod vendor;
 
 #[derive(Copy, Clone, PartialOrd, PartialEq, Ord, Eq, Hash, Debug)]
 pub enum StreamError {
     EventError(PackError),
     CommandError(PackError),
+    /// Bad ACL (or other data) packet.
+    DataError(PackError),
     UnsupportedPacketType(u8),
     BadOpcode,
     BadEventCode,
     BadPacketCode,
     StreamClosed,
     StreamFailed,
+    /// The controller didn't answer in time (see [`timer`]).
+    Timeout,
+    /// The transport went away (the other end of a socket closed, a dongle unplugged).
+    Disconnected,
+    /// A received packet that doesn't parse.
+    Malformed(MalformedPacket),
+}
+impl core::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            StreamError::EventError(e) => write!(f, "bad event: {:?}", e),
+            StreamError::CommandError(e) => write!(f, "bad command: {:?}", e),
+            StreamError::DataError(e) => write!(f, "bad data packet: {:?}", e),
+            StreamError::UnsupportedPacketType(t) => {
+                write!(f, "unsupported packet type 0x{:02X}", t)
+            }
+            StreamError::BadOpcode => f.write_str("bad opcode"),
+            StreamError::BadEventCode => f.write_str("bad event code"),
+            StreamError::BadPacketCode => f.write_str("bad packet indicator"),
+            StreamError::StreamClosed => f.write_str("stream closed"),
+            StreamError::StreamFailed => f.write_str("stream failed"),
+            StreamError::Timeout => f.write_str("controller timed out"),
+            StreamError::Disconnected => f.write_str("transport disconnected"),
+            StreamError::Malformed(packet) => write!(f, "malformed packet {}", packet),
+        }
+    }
+}
+/// A received packet that didn't parse. Keeps why, its length and first
+/// [`MalformedPacket::HEAD_LEN`] bytes (it has to stay `Copy` like the other errors) for logs and
+/// bug reports.
+#[derive(Copy, Clone, PartialOrd, PartialEq, Ord, Eq, Hash, Debug)]
+pub struct MalformedPacket {
+    pub error: PackError,
+    len: usize,
+    head: [u8; MalformedPacket::HEAD_LEN],
+}
+impl MalformedPacket {
+    pub const HEAD_LEN: usize = 16;
+    pub fn new(error: PackError, bytes: &[u8]) -> MalformedPacket {
+        let mut head = [0_u8; MalformedPacket::HEAD_LEN];
+        let kept = bytes.len().min(MalformedPacket::HEAD_LEN);
+        head[..kept].copy_from_slice(&bytes[..kept]);
+        MalformedPacket {
+            error,
+            len: bytes.len(),
+            head,
+        }
+    }
+    /// Length of the whole packet.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// The kept bytes (the whole packet if it was short enough).
+    pub fn bytes(&self) -> &[u8] {
+        &self.head[..self.len.min(MalformedPacket::HEAD_LEN)]
+    }
+}
+/// `[len] bytes (error)` with the kept bytes hex dumped, `..` if some were cut.
+impl core::fmt::Display for MalformedPacket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "[{}] {}",
+            self.len,
+            crate::bytes::HexDump::new(self.bytes())
+        )?;
+        if self.len > MalformedPacket::HEAD_LEN {
+            f.write_str(" ..")?;
+        }
+        write!(f, " ({:?})", self.error)
+    }
 }
 use crate::bytes::ToFromBytesEndian;
 use crate::ConversionError;
@@ -78,6 +181,9 @@ pub enum Version {
     Bluetooth5v0 = 9,
     Bluetooth5v1 = 10,
     Bluetooth5v2 = 11,
+    Bluetooth5v3 = 12,
+    Bluetooth5v4 = 13,
+    Bluetooth6v0 = 14,
 }
 impl From<Version> for u8 {
     fn from(v: Version) -> Self {
@@ -101,6 +207,9 @@ impl TryFrom<u8> for Version {
             9 => Ok(Version::Bluetooth5v0),
             10 => Ok(Version::Bluetooth5v1),
             11 => Ok(Version::Bluetooth5v2),
+            12 => Ok(Version::Bluetooth5v3),
+            13 => Ok(Version::Bluetooth5v4),
+            14 => Ok(Version::Bluetooth6v0),
             _ => Err(ConversionError(())),
         }
     }
@@ -163,6 +272,18 @@ pub enum ErrorCode {
     EIRTooLarge = 0x36,
     SimplePairingNotSupported = 0x37,
     HostBusyPairing = 0x38,
+    ConnectionRejectedNoSuitableChannelFound = 0x39,
+    ControllerBusy = 0x3A,
+    UnacceptableConnectionParameters = 0x3B,
+    AdvertisingTimeout = 0x3C,
+    ConnectionTerminatedMICFailure = 0x3D,
+    ConnectionFailedToBeEstablished = 0x3E,
+    CoarseClockAdjustmentRejected = 0x40,
+    Type0SubmapNotDefined = 0x41,
+    UnknownAdvertisingIdentifier = 0x42,
+    LimitReached = 0x43,
+    OperationCancelledByHost = 0x44,
+    PacketTooLong = 0x45,
 }
 impl ErrorCode {
     pub const BYTE_LEN: usize = 1;
@@ -242,6 +363,18 @@ impl ErrorCode {
             ErrorCode::EIRTooLarge => "EIRTooLarge",
             ErrorCode::SimplePairingNotSupported => "SimplePairingNotSupported",
             ErrorCode::HostBusyPairing => "HostBusyPairing",
+            ErrorCode::ConnectionRejectedNoSuitableChannelFound => "ConnectionRejectedNoSuitableChannelFound",
+            ErrorCode::ControllerBusy => "ControllerBusy",
+            ErrorCode::UnacceptableConnectionParameters => "UnacceptableConnectionParameters",
+            ErrorCode::AdvertisingTimeout => "AdvertisingTimeout",
+            ErrorCode::ConnectionTerminatedMICFailure => "ConnectionTerminatedMICFailure",
+            ErrorCode::ConnectionFailedToBeEstablished => "ConnectionFailedToBeEstablished",
+            ErrorCode::CoarseClockAdjustmentRejected => "CoarseClockAdjustmentRejected",
+            ErrorCode::Type0SubmapNotDefined => "Type0SubmapNotDefined",
+            ErrorCode::UnknownAdvertisingIdentifier => "UnknownAdvertisingIdentifier",
+            ErrorCode::LimitReached => "LimitReached",
+            ErrorCode::OperationCancelledByHost => "OperationCancelledByHost",
+            ErrorCode::PacketTooLong => "PacketTooLong",
         }
     }
 }
@@ -302,6 +435,30 @@ impl TryFrom<u8> for ErrorCode {
             0x27 => Ok(ErrorCode::QoSNotSupported),
             0x28 => Ok(ErrorCode::InstantPassed),
             0x29 => Ok(ErrorCode::PairingWithUnitKeyNotSupported),
+            0x2A => Ok(ErrorCode::TransactionCollision),
+            0x2C => Ok(ErrorCode::QOSUnacceptableParameter),
+            0x2D => Ok(ErrorCode::QOSRejected),
+            0x2E => Ok(ErrorCode::ClassificationNotSupported),
+            0x2F => Ok(ErrorCode::InsufficientSecurity),
+            0x30 => Ok(ErrorCode::ParameterOutOfRange),
+            0x32 => Ok(ErrorCode::RoleSwitchPending),
+            0x34 => Ok(ErrorCode::SlotViolation),
+            0x35 => Ok(ErrorCode::RoleSwitchFailed),
+            0x36 => Ok(ErrorCode::EIRTooLarge),
+            0x37 => Ok(ErrorCode::SimplePairingNotSupported),
+            0x38 => Ok(ErrorCode::HostBusyPairing),
+            0x39 => Ok(ErrorCode::ConnectionRejectedNoSuitableChannelFound),
+            0x3A => Ok(ErrorCode::ControllerBusy),
+            0x3B => Ok(ErrorCode::UnacceptableConnectionParameters),
+            0x3C => Ok(ErrorCode::AdvertisingTimeout),
+            0x3D => Ok(ErrorCode::ConnectionTerminatedMICFailure),
+            0x3E => Ok(ErrorCode::ConnectionFailedToBeEstablished),
+            0x40 => Ok(ErrorCode::CoarseClockAdjustmentRejected),
+            0x41 => Ok(ErrorCode::Type0SubmapNotDefined),
+            0x42 => Ok(ErrorCode::UnknownAdvertisingIdentifier),
+            0x43 => Ok(ErrorCode::LimitReached),
+            0x44 => Ok(ErrorCode::OperationCancelledByHost),
+            0x45 => Ok(ErrorCode::PacketTooLong),
             _ => Err(ConversionError(())),
         }
     }
@@ -407,6 +564,12 @@ impl Opcode {
         self.0 == OGF::NOP
     }
 }
+/// `OGF/OCF`, for example `LEController/0x00C`.
+impl core::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}/0x{:03X}", self.0, (self.1).0)
+    }
+}
 impl From<Opcode> for u16 {
     fn from(opcode: Opcode) -> Self {
         (opcode.1).0 | (u16::from(u8::from(opcode.0)) << 10)
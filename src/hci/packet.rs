@@ -1,6 +1,21 @@
 //! HCI Packet type. Packets are sent and received from an HCI Controller.
+#![forbid(unsafe_code)]
+#![cfg_attr(
+    all(feature = "panic-free", not(test)),
+    deny(
+        clippy::indexing_slicing,
+        clippy::expect_used,
+        clippy::unwrap_used,
+        clippy::panic,
+        clippy::unreachable
+    )
+)]
+use crate::bytes::{HexDump, Storage};
+use crate::error::IOError;
+use crate::hci::adapter;
+use crate::hci::StreamError;
+use crate::ConversionError;
 use crate::PackError;
-use crate::{bytes::Storage, ConversionError};
 use core::convert::{TryFrom, TryInto};
 
 /// HCI Packet Type.
@@ -11,6 +26,7 @@ pub enum PacketType {
     ACLData = 0x02,
     SCOData = 0x03,
     Event = 0x04,
+    ISOData = 0x05,
     Vendor = 0xFF,
 }
 impl From<PacketType> for u8 {
@@ -32,11 +48,175 @@ impl TryFrom<u8> for PacketType {
             0x02 => Ok(PacketType::ACLData),
             0x03 => Ok(PacketType::SCOData),
             0x04 => Ok(PacketType::Event),
+            0x05 => Ok(PacketType::ISOData),
             0xFF => Ok(PacketType::Vendor),
             _ => Err(ConversionError(())),
         }
     }
 }
+/// Packet indicator of ISO data packets.
+pub const ISO_DATA_INDICATOR: u8 = PacketType::ISOData as u8;
+/// Bytes needed for the H4 framed packet (indicator included) at the start of `buf` to be
+/// complete. Until `buf` holds the whole header that's the header length. Errors on an unknown
+/// packet indicator.
+pub fn h4_frame_len(buf: &[u8]) -> Result<usize, StreamError> {
+    let indicator = match buf.first() {
+        Some(&indicator) => indicator,
+        None => return Ok(1),
+    };
+    let (header_len, short_len) = match PacketType::try_from(indicator) {
+        Ok(PacketType::Command | PacketType::SCOData) => (4, true),
+        Ok(PacketType::Event) => (3, true),
+        Ok(PacketType::ACLData | PacketType::ISOData) => (5, false),
+        _ => return Err(StreamError::UnsupportedPacketType(indicator)),
+    };
+    let payload_len = match buf.get(..header_len) {
+        Some([.., len]) if short_len => usize::from(*len),
+        // ISO data uses the top two bits for flags.
+        Some([.., l0, l1]) if indicator == ISO_DATA_INDICATOR => {
+            usize::from(u16::from_le_bytes([*l0, *l1]) & 0x3FFF)
+        }
+        Some([.., l0, l1]) => usize::from(u16::from_le_bytes([*l0, *l1])),
+        _ => return Ok(header_len),
+    };
+    Ok(header_len + payload_len)
+}
+/// What the H4 transports do after a framing error (an unknown packet indicator or a length
+/// that doesn't fit the receive buffer).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum FramingRecovery {
+    /// Drop the buffered bytes and return the error. The stream most likely stays out of sync
+    /// until the controller is reset (see [`crate::hci::adapters::recovery`]).
+    Fail,
+    /// Skip forward to the next plausible packet start (see [`h4_resync_offset`]) and keep
+    /// reading. Whatever packet the glitch hit is lost.
+    Resync,
+}
+impl Default for FramingRecovery {
+    fn default() -> Self {
+        FramingRecovery::Fail
+    }
+}
+/// Offset of the next plausible H4 packet start in `buf`, skipping at least the first byte.
+/// Plausible means a controller to host packet indicator, a known event code (or vendor event)
+/// and a length that fits [`MAX_FRAME_SIZE`](crate::hci::MAX_FRAME_SIZE). Bytes that haven't
+/// arrived yet can't rule a start out. `buf.len()` if nothing in `buf` is plausible.
+pub fn h4_resync_offset(buf: &[u8]) -> usize {
+    (1..buf.len())
+        .find(|&offset| {
+            let candidate = buf.get(offset..).unwrap_or_default();
+            let plausible_start = match candidate.first().map(|&i| PacketType::try_from(i)) {
+                Some(Ok(PacketType::Event)) => candidate.get(1).map_or(true, |&code| {
+                    code == 0xFF || crate::hci::event::EventCode::try_from(code).is_ok()
+                }),
+                Some(Ok(PacketType::ACLData | PacketType::SCOData | PacketType::ISOData)) => true,
+                _ => false,
+            };
+            plausible_start
+                && h4_frame_len(candidate).map_or(false, |len| len <= crate::hci::MAX_FRAME_SIZE)
+        })
+        .unwrap_or(buf.len())
+}
+/// Receive side of H4 framing shared by the byte stream transports. Bytes read into
+/// [`H4Receiver::unfilled`] are buffered until a whole packet is there. Framing errors are
+/// handled with the [`FramingRecovery`] mode.
+#[derive(Clone, Debug)]
+pub struct H4Receiver<B> {
+    rx: B,
+    /// Bytes in `rx`. Can go past the current packet.
+    rx_len: usize,
+    recovery: FramingRecovery,
+}
+impl<B: AsRef<[u8]> + AsMut<[u8]>> H4Receiver<B> {
+    pub fn new(rx: B) -> H4Receiver<B> {
+        H4Receiver {
+            rx,
+            rx_len: 0,
+            recovery: FramingRecovery::default(),
+        }
+    }
+    pub fn recovery(&self) -> FramingRecovery {
+        self.recovery
+    }
+    /// What to do after a framing error. [`FramingRecovery::Fail`] by default.
+    pub fn set_recovery(&mut self, recovery: FramingRecovery) {
+        self.recovery = recovery;
+    }
+    /// Buffered bytes, starting with the current packet.
+    pub fn buffered(&self) -> &[u8] {
+        self.rx.as_ref().get(..self.rx_len).unwrap_or_default()
+    }
+    /// Drops everything buffered (after a controller reset for example).
+    pub fn reset(&mut self) {
+        self.rx_len = 0;
+    }
+    /// Free part of the buffer. Report bytes read into it with [`H4Receiver::filled`].
+    pub fn unfilled(&mut self) -> &mut [u8] {
+        let rx_len = self.rx_len;
+        self.rx.as_mut().get_mut(rx_len..).unwrap_or_default()
+    }
+    pub fn filled(&mut self, amount: usize) {
+        self.rx_len = (self.rx_len + amount).min(self.rx.as_ref().len());
+    }
+    /// Length of the packet at the start of the buffer once all of it is buffered, `None` while
+    /// bytes are missing. Framing errors are skipped when resynchronizing.
+    ///
+    /// # Errors
+    /// Returns framing errors with [`FramingRecovery::Fail`] (dropping the buffered bytes).
+    pub fn packet_len(&mut self) -> Result<Option<usize>, adapter::Error> {
+        loop {
+            let needed = match h4_frame_len(self.buffered()) {
+                Ok(needed) if needed <= self.rx.as_ref().len() => Ok(needed),
+                Ok(_) => Err(IOError::Overflow.into()),
+                Err(e) => Err(e.into()),
+            };
+            match needed {
+                Ok(needed) if self.rx_len >= needed => return Ok(Some(needed)),
+                Ok(_) => return Ok(None),
+                Err(e) => self.framing_error(e)?,
+            }
+        }
+    }
+    /// Copies the next whole packet to the start of `buf` and removes it from the buffer.
+    /// Returns `None` while bytes are missing.
+    ///
+    /// # Errors
+    /// Returns `IOError::Overflow` if `buf` is too small (the packet is dropped) and framing
+    /// errors (see [`H4Receiver::packet_len`]).
+    pub fn read_packet(&mut self, buf: &mut [u8]) -> Result<Option<usize>, adapter::Error> {
+        match self.packet_len()? {
+            Some(len) => {
+                let result = match (buf.get_mut(..len), self.buffered().get(..len)) {
+                    (Some(out), Some(packet)) => {
+                        out.copy_from_slice(packet);
+                        Ok(Some(len))
+                    }
+                    _ => Err(IOError::Overflow.into()),
+                };
+                self.consume(len);
+                result
+            }
+            None => Ok(None),
+        }
+    }
+    /// Removes `len` bytes from the start of the buffer.
+    pub fn consume(&mut self, len: usize) {
+        let len = len.min(self.rx_len);
+        self.rx.as_mut().copy_within(len..self.rx_len, 0);
+        self.rx_len -= len;
+    }
+    /// Handles a framing error. Returns the error unless resynchronizing.
+    fn framing_error(&mut self, e: adapter::Error) -> Result<(), adapter::Error> {
+        if self.recovery == FramingRecovery::Fail {
+            self.reset();
+            return Err(e);
+        }
+        let skip = h4_resync_offset(self.buffered());
+        hci_warn!("h4 rx framing error {:?}, skipping {} bytes", e, skip);
+        self.consume(skip);
+        Ok(())
+    }
+}
 /// Raw HCI Packet. Stores the [`PacketType`] + packet data buf (bytes).
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
 pub struct RawPacket<Buf> {
@@ -51,6 +231,19 @@ impl<Buf: AsRef<[u8]>> RawPacket<Buf> {
         }
     }
 }
+/// `PacketType [len] bytes` with the bytes hex dumped (see [`HexDump`]).
+impl<Buf: AsRef<[u8]>> core::fmt::Display for RawPacket<Buf> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let buf = self.buf.as_ref();
+        write!(
+            f,
+            "{:?} [{}] {}",
+            self.packet_type,
+            buf.len(),
+            HexDump::new(buf)
+        )
+    }
+}
 impl<Buf: AsRef<[u8]>> RawPacket<Buf> {
     pub fn clone_buf<S: Storage<u8>>(&self) -> RawPacket<S> {
         RawPacket {
@@ -64,8 +257,9 @@ impl<Buf: AsRef<[u8]>> RawPacket<Buf> {
     }
     pub fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
         PackError::expect_length(self.total_len(), buf)?;
-        buf[0] = self.packet_type.into();
-        buf[1..].copy_from_slice(self.buf.as_ref());
+        let (packet_type, rest) = buf.split_first_mut().ok_or(PackError::bad_index(0))?;
+        *packet_type = self.packet_type.into();
+        rest.copy_from_slice(self.buf.as_ref());
         Ok(())
     }
     pub fn pack<NewBuf: Storage<u8>>(&self) -> Option<NewBuf> {
@@ -83,9 +277,10 @@ impl<'a> TryFrom<&'a [u8]> for RawPacket<&'a [u8]> {
     type Error = ConversionError;
 
     fn try_from(buf: &'a [u8]) -> Result<Self, Self::Error> {
+        let (&packet_type, buf) = buf.split_first().ok_or(ConversionError(()))?;
         Ok(RawPacket {
-            packet_type: (*buf.get(0).ok_or(ConversionError(()))?).try_into()?,
-            buf: &buf[1..],
+            packet_type: packet_type.try_into()?,
+            buf,
         })
     }
 }
@@ -110,10 +305,14 @@ pub trait Packet {
     /// Automatically trims `buf` to the correct len.
     fn packet_pack_full(&self, buf: &mut [u8]) -> Result<usize, PackError> {
         let full = self.packet_byte_len() + 1;
-        let buf = &mut buf[..full];
-        PackError::expect_length(full, buf)?;
-        self.packet_pack_into(&mut buf[1..])?;
-        buf[0] = Self::PACKET_TYPE.into();
+        let got = buf.len();
+        let buf = buf.get_mut(..full).ok_or(PackError::BadLength {
+            expected: full,
+            got,
+        })?;
+        let (packet_type, rest) = buf.split_first_mut().ok_or(PackError::bad_index(0))?;
+        self.packet_pack_into(rest)?;
+        *packet_type = Self::PACKET_TYPE.into();
         Ok(full)
     }
     fn try_from<S: AsRef<[u8]>>(value: &RawPacket<S>) -> Result<Self, PackError>
@@ -127,3 +326,57 @@ pub trait Packet {
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_h4_frame_len() {
+        assert_eq!(h4_frame_len(&[]), Ok(1));
+        assert_eq!(h4_frame_len(&[0x04, 0x0E]), Ok(3));
+        assert_eq!(h4_frame_len(&[0x04, 0x0E, 0x04]), Ok(7));
+        assert_eq!(h4_frame_len(&[0x02, 0x40, 0x00, 0x1B]), Ok(5));
+        // The whole ACL length field counts, the ISO one has flags in its top two bits.
+        assert_eq!(
+            h4_frame_len(&[0x02, 0x40, 0x00, 0x00, 0x40]),
+            Ok(5 + 0x4000)
+        );
+        assert_eq!(h4_frame_len(&[0x05, 0x40, 0x00, 0x10, 0x40]), Ok(5 + 0x10));
+        assert_eq!(
+            h4_frame_len(&[0x17]),
+            Err(StreamError::UnsupportedPacketType(0x17))
+        );
+    }
+    #[test]
+    fn test_h4_receiver() {
+        let event = [0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00];
+        let mut receiver = H4Receiver::new([0_u8; 16]);
+        let mut out = [0_u8; 16];
+        receiver.unfilled()[..4].copy_from_slice(&event[..4]);
+        receiver.filled(4);
+        assert_eq!(receiver.read_packet(&mut out), Ok(None));
+        // The rest of the event and a glitched byte.
+        receiver.unfilled()[..4].copy_from_slice(&[0x03, 0x0C, 0x00, 0x17]);
+        receiver.filled(4);
+        assert_eq!(receiver.read_packet(&mut out), Ok(Some(event.len())));
+        assert_eq!(out[..event.len()], event);
+        assert_eq!(
+            receiver.read_packet(&mut out),
+            Err(StreamError::UnsupportedPacketType(0x17).into())
+        );
+        assert!(receiver.buffered().is_empty());
+        // Resynchronizing skips the glitch and the ACL packet that can't fit.
+        receiver.set_recovery(FramingRecovery::Resync);
+        receiver.unfilled()[..6].copy_from_slice(&[0x17, 0x02, 0x40, 0x00, 0x20, 0x00]);
+        receiver.filled(6);
+        assert_eq!(receiver.packet_len(), Ok(None));
+        assert!(receiver.buffered().is_empty());
+        receiver.unfilled()[..event.len()].copy_from_slice(&event);
+        receiver.filled(event.len());
+        assert_eq!(
+            receiver.read_packet(&mut out[..4]),
+            Err(IOError::Overflow.into())
+        );
+        assert!(receiver.buffered().is_empty());
+    }
+}
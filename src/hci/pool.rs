@@ -0,0 +1,98 @@
+//! Pooled event buffers (`bytes` feature). [`Stream::read_event`] copies every event into a fresh
+//! buffer. [`Stream::read_event_pooled`] reads into an [`EventPool`] instead and hands out
+//! `EventPacket<Bytes>`s that share the pool's allocation, so parsing (and keeping) the
+//! parameters is zero-copy. The pool reuses its allocation once every event read from it is
+//! dropped, so a busy scan settles on a few allocations instead of one per advertising report.
+use crate::hci::adapter;
+use crate::hci::event::{EventPacket, MAX_HCI_PACKET_SIZE};
+use crate::hci::stream::{HCIReader, Stream};
+use ::bytes::{Bytes, BytesMut};
+use core::ops::{Deref, DerefMut};
+
+/// H4 packet type, event code and parameter length in front of the parameters.
+const EVENT_HEADER_LEN: usize = 3;
+
+#[derive(Debug)]
+pub struct EventPool {
+    buf: BytesMut,
+    capacity: usize,
+}
+impl EventPool {
+    /// Pool with room for `events` events before it has to allocate (or reclaim).
+    pub fn new(events: usize) -> EventPool {
+        let capacity = events.max(1) * MAX_HCI_PACKET_SIZE;
+        EventPool {
+            buf: BytesMut::with_capacity(capacity),
+            capacity,
+        }
+    }
+    /// Buffer for the next event.
+    fn next_buffer(&mut self) -> &mut [u8] {
+        if self.buf.capacity() < MAX_HCI_PACKET_SIZE {
+            // Takes the allocation back if all events from it were dropped.
+            self.buf.reserve(self.capacity);
+        }
+        self.buf.resize(MAX_HCI_PACKET_SIZE, 0);
+        &mut self.buf[..]
+    }
+    /// Splits `len` bytes of the event just read off the pool.
+    fn take(&mut self, len: usize) -> Bytes {
+        let packet = self.buf.split_to(len).freeze();
+        self.buf.clear();
+        packet
+    }
+}
+impl Default for EventPool {
+    fn default() -> Self {
+        EventPool::new(16)
+    }
+}
+impl<S: HCIReader, B: Deref<Target = S> + DerefMut> Stream<S, B> {
+    /// Like [`Stream::read_event`] but the event parameters are a slice of `pool`.
+    pub async fn read_event_pooled(
+        &mut self,
+        pool: &mut EventPool,
+    ) -> Result<EventPacket<Bytes>, adapter::Error> {
+        let (event_code, len) = {
+            let event = self.read_event_into(pool.next_buffer()).await?;
+            (event.event_code, event.parameters.len())
+        };
+        let packet = pool.take(EVENT_HEADER_LEN + len);
+        Ok(EventPacket::new(
+            event_code,
+            packet.slice(EVENT_HEADER_LEN..),
+        ))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::event::EventCode;
+    use crate::testing::mock::MockTransport;
+    use futures_util::FutureExt;
+
+    #[test]
+    fn test_pooled_events_share_allocation() {
+        let (transport, handle) = MockTransport::new();
+        let mut stream = Stream::new(Box::pin(transport));
+        let mut pool = EventPool::new(4);
+        handle.inject_event(EventCode::HardwareError, &[0x03]);
+        handle.inject_event(EventCode::HardwareError, &[0x04]);
+        let first = stream
+            .read_event_pooled(&mut pool)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let second = stream
+            .read_event_pooled(&mut pool)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.event_code, EventCode::HardwareError);
+        assert_eq!(first.parameters(), [0x03]);
+        assert_eq!(second.parameters(), [0x04]);
+        // The second event starts right after the first one, in the same allocation.
+        let distance = second.parameters().as_ptr() as usize - first.parameters().as_ptr() as usize;
+        assert_eq!(distance, EVENT_HEADER_LEN + 1);
+    }
+}
@@ -0,0 +1,351 @@
+//! Framing for tunneling HCI packets to a remote host. Packets are batched into frames so an
+//! advertising report flood doesn't cost one TCP segment per report, optionally compressed (the
+//! reports in a batch mostly repeat addresses and AD structures) and numbered so the host can
+//! tell how many frames were lost.
+//!
+//! Frame layout (little endian):
+//!
+//! ```text
+//! magic (0xB7) | flags | sequence (u16) | packet count (u8) | payload len (u16) | payload
+//! ```
+//!
+//! The payload is every packet prefixed with its `u16` length. With [`FLAG_COMPRESSED`] the
+//! payload is LZ77 compressed: a token `0b0nnnnnnn` is followed by `n + 1` literal bytes and
+//! `0b1nnnnnnn` by a `u16` offset back into the output to copy `n + 4` bytes from.
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+pub const MAGIC: u8 = 0xB7;
+pub const FLAG_COMPRESSED: u8 = 0x01;
+pub const HEADER_LEN: usize = 7;
+pub const MAX_PAYLOAD_LEN: usize = u16::MAX as usize;
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = 0x7F + MIN_MATCH;
+const MAX_LITERALS: usize = 0x80;
+const HASH_BITS: u32 = 12;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum FrameError {
+    BadMagic(u8),
+    UnknownFlags(u8),
+    /// A packet doesn't fit in a frame.
+    PacketTooLong(usize),
+    /// The payload doesn't decompress or the packets don't add up to the payload.
+    BadPayload,
+}
+impl crate::error::Error for FrameError {}
+#[cfg(feature = "std")]
+impl std::error::Error for FrameError {}
+impl core::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+fn hash(bytes: &[u8]) -> usize {
+    let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (word.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+fn push_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        out.push(u8::try_from(chunk.len() - 1).expect("chunks are at most 128 bytes"));
+        out.extend_from_slice(chunk);
+    }
+}
+/// Greedy LZ77 with a one entry hash table. Fast rather than small.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let (mut i, mut literal_start) = (0, 0);
+    while i + MIN_MATCH <= input.len() {
+        let slot = &mut table[hash(&input[i..])];
+        let candidate = core::mem::replace(slot, i);
+        if candidate == usize::MAX
+            || i - candidate > MAX_PAYLOAD_LEN
+            || input[candidate..candidate + MIN_MATCH] != input[i..i + MIN_MATCH]
+        {
+            i += 1;
+            continue;
+        }
+        let mut len = MIN_MATCH;
+        while len < MAX_MATCH && i + len < input.len() && input[candidate + len] == input[i + len] {
+            len += 1;
+        }
+        push_literals(&mut out, &input[literal_start..i]);
+        out.push(0x80 | u8::try_from(len - MIN_MATCH).expect("len is at most MAX_MATCH"));
+        out.extend_from_slice(
+            &u16::try_from(i - candidate)
+                .expect("offset checked above")
+                .to_le_bytes(),
+        );
+        i += len;
+        literal_start = i;
+    }
+    push_literals(&mut out, &input[literal_start..]);
+    out
+}
+/// Returns `None` if `input` is malformed or would decompress to more than `max_len` bytes.
+pub fn decompress(mut input: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 2);
+    while let Some((&token, rest)) = input.split_first() {
+        if token & 0x80 == 0 {
+            let len = usize::from(token) + 1;
+            out.extend_from_slice(rest.get(..len)?);
+            input = &rest[len..];
+        } else {
+            let len = usize::from(token & 0x7F) + MIN_MATCH;
+            let offset = usize::from(u16::from_le_bytes([*rest.first()?, *rest.get(1)?]));
+            if offset == 0 || offset > out.len() || out.len() + len > max_len {
+                return None;
+            }
+            let start = out.len() - offset;
+            for k in start..start + len {
+                out.push(out[k]);
+            }
+            input = &rest[2..];
+        }
+        if out.len() > max_len {
+            return None;
+        }
+    }
+    Some(out)
+}
+/// Batches packets into frames.
+#[derive(Clone, Debug)]
+pub struct FrameEncoder {
+    sequence: u16,
+    compression: bool,
+    max_payload_len: usize,
+    payload: Vec<u8>,
+    count: u8,
+}
+impl FrameEncoder {
+    /// `max_payload_len` (at most [`MAX_PAYLOAD_LEN`]) bounds the uncompressed payload of a
+    /// frame.
+    pub fn new(max_payload_len: usize) -> FrameEncoder {
+        FrameEncoder {
+            sequence: 0,
+            compression: false,
+            max_payload_len: max_payload_len.min(MAX_PAYLOAD_LEN),
+            payload: Vec::new(),
+            count: 0,
+        }
+    }
+    #[must_use]
+    pub fn with_compression(self, compression: bool) -> FrameEncoder {
+        FrameEncoder {
+            compression,
+            ..self
+        }
+    }
+    /// Sequence number of the next frame.
+    pub fn sequence(&self) -> u16 {
+        self.sequence
+    }
+    pub fn pending_packets(&self) -> usize {
+        usize::from(self.count)
+    }
+    /// Adds `packet` to the batch. Returns the previous batch as a frame if `packet` didn't fit
+    /// in it.
+    pub fn push(&mut self, packet: &[u8]) -> Result<Option<Vec<u8>>, FrameError> {
+        let len = u16::try_from(packet.len())
+            .ok()
+            .filter(|_| packet.len() + 2 <= self.max_payload_len)
+            .ok_or(FrameError::PacketTooLong(packet.len()))?;
+        let frame = if self.payload.len() + packet.len() + 2 > self.max_payload_len
+            || self.count == u8::MAX
+        {
+            self.flush()
+        } else {
+            None
+        };
+        self.payload.extend_from_slice(&len.to_le_bytes());
+        self.payload.extend_from_slice(packet);
+        self.count += 1;
+        Ok(frame)
+    }
+    /// Turns the batch into a frame. Returns `None` if there are no packets. Call this
+    /// periodically so packets don't wait on a batch filling up.
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        if self.count == 0 {
+            return None;
+        }
+        let compressed = if self.compression {
+            Some(compress(&self.payload)).filter(|c| c.len() < self.payload.len())
+        } else {
+            None
+        };
+        let (flags, payload) = match &compressed {
+            Some(compressed) => (FLAG_COMPRESSED, compressed.as_slice()),
+            None => (0, self.payload.as_slice()),
+        };
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+        frame.extend_from_slice(&[MAGIC, flags]);
+        frame.extend_from_slice(&self.sequence.to_le_bytes());
+        frame.push(self.count);
+        frame.extend_from_slice(
+            &u16::try_from(payload.len())
+                .expect("payload is at most MAX_PAYLOAD_LEN")
+                .to_le_bytes(),
+        );
+        frame.extend_from_slice(payload);
+        self.payload.clear();
+        self.count = 0;
+        self.sequence = self.sequence.wrapping_add(1);
+        Some(frame)
+    }
+}
+/// One decoded frame.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct Frame {
+    pub sequence: u16,
+    /// Frames missing between the previous frame and this one.
+    pub lost: u16,
+    /// The frame is older than one already received (reordered or duplicated).
+    pub stale: bool,
+    pub packets: Vec<Vec<u8>>,
+}
+/// Tracks frame sequence numbers to count lost frames.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct SequenceTracker {
+    expected: Option<u16>,
+    lost: u64,
+}
+impl SequenceTracker {
+    /// Returns how many frames were skipped before `sequence` and whether `sequence` is stale.
+    pub fn observe(&mut self, sequence: u16) -> (u16, bool) {
+        let gap = match self.expected {
+            Some(expected) => sequence.wrapping_sub(expected),
+            None => 0,
+        };
+        if gap > u16::MAX / 2 {
+            return (0, true);
+        }
+        self.expected = Some(sequence.wrapping_add(1));
+        self.lost += u64::from(gap);
+        (gap, false)
+    }
+    pub fn total_lost(&self) -> u64 {
+        self.lost
+    }
+}
+/// Splits a byte stream (a TCP connection for example) back into frames.
+#[derive(Clone, Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    tracker: SequenceTracker,
+}
+impl FrameDecoder {
+    pub fn new() -> FrameDecoder {
+        FrameDecoder::default()
+    }
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+    pub fn tracker(&self) -> &SequenceTracker {
+        &self.tracker
+    }
+    /// Returns the next complete frame if there is one. After an error the stream can't be
+    /// trusted anymore and the buffered bytes are dropped.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, FrameError> {
+        let result = self.decode();
+        if result.is_err() {
+            self.buf.clear();
+        }
+        result
+    }
+    fn decode(&mut self) -> Result<Option<Frame>, FrameError> {
+        let header = match self.buf.get(..HEADER_LEN) {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        if header[0] != MAGIC {
+            return Err(FrameError::BadMagic(header[0]));
+        }
+        if header[1] & !FLAG_COMPRESSED != 0 {
+            return Err(FrameError::UnknownFlags(header[1]));
+        }
+        let sequence = u16::from_le_bytes([header[2], header[3]]);
+        let count = header[4];
+        let end = HEADER_LEN + usize::from(u16::from_le_bytes([header[5], header[6]]));
+        let payload = match self.buf.get(HEADER_LEN..end) {
+            Some(payload) => payload,
+            None => return Ok(None),
+        };
+        let decompressed;
+        let mut payload = if header[1] & FLAG_COMPRESSED == 0 {
+            payload
+        } else {
+            decompressed = decompress(payload, MAX_PAYLOAD_LEN).ok_or(FrameError::BadPayload)?;
+            decompressed.as_slice()
+        };
+        let mut packets = Vec::with_capacity(usize::from(count));
+        for _ in 0..count {
+            let (len, rest) = match payload {
+                [l0, l1, rest @ ..] => (usize::from(u16::from_le_bytes([*l0, *l1])), rest),
+                _ => return Err(FrameError::BadPayload),
+            };
+            packets.push(rest.get(..len).ok_or(FrameError::BadPayload)?.to_vec());
+            payload = &rest[len..];
+        }
+        if !payload.is_empty() {
+            return Err(FrameError::BadPayload);
+        }
+        self.buf.drain(..end);
+        let (lost, stale) = self.tracker.observe(sequence);
+        Ok(Some(Frame {
+            sequence,
+            lost,
+            stale,
+            packets,
+        }))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batching_compression_and_loss() {
+        // The same advertising report over and over with a changing RSSI.
+        let report = |rssi: u8| {
+            let mut packet = vec![0x04, 0x3E, 0x1A, 0x02, 0x01, 0x00, 0x00];
+            packet.extend_from_slice(&[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x0E]);
+            packet.extend_from_slice(b"\x02\x01\x06\x0A\x09btle-node");
+            packet.push(rssi);
+            packet
+        };
+        let mut encoder = FrameEncoder::new(1024).with_compression(true);
+        let mut frames = Vec::new();
+        for rssi in 0..200 {
+            frames.extend(encoder.push(&report(rssi)).unwrap());
+        }
+        frames.extend(encoder.flush());
+        assert_eq!(frames.len(), 7);
+        assert!(frames[0].len() < 1024 / 3);
+
+        let mut decoder = FrameDecoder::new();
+        // Drop the second frame and feed the rest a few bytes at a time.
+        for chunk in frames[..1]
+            .iter()
+            .chain(&frames[2..])
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>()
+            .chunks(5)
+        {
+            decoder.feed(chunk);
+        }
+        let mut received = Vec::new();
+        while let Some(frame) = decoder.next_frame().unwrap() {
+            assert_eq!(frame.lost, u16::from(frame.sequence == 2));
+            received.extend(frame.packets);
+        }
+        assert_eq!(decoder.tracker().total_lost(), 1);
+        assert_eq!(received.len(), 200 - 33);
+        assert_eq!(received[0], report(0));
+        assert_eq!(received.last(), Some(&report(199)));
+
+        decoder.feed(&[MAGIC, FLAG_COMPRESSED, 0, 0, 1, 3, 0, 0x80, 0x01, 0x00]);
+        assert_eq!(decoder.next_frame(), Err(FrameError::BadPayload));
+    }
+}
@@ -1,4 +1,6 @@
 //! Remote HCI Controller (WIP).
+pub mod frame;
+
 use std::{io, net};
 pub struct Client(pub net::TcpStream);
 impl io::Write for Client {
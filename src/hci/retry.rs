@@ -0,0 +1,235 @@
+//! Retrying after transient transport errors. [`RetryPolicy`] says how many attempts an operation
+//! gets and how long to back off between them. [`RetryHooks`] provides the backoff delay and a
+//! transport reconnect (reopening a replugged USB dongle for example). Set on an
+//! [`Adapter`](crate::hci::adapters::Adapter) with `set_retry` (or
+//! [`HostBuilder::retry`](crate::host::HostBuilder::retry)), command sends survive a serial port
+//! returning `EAGAIN` or a dongle being unplugged and plugged back in.
+use crate::error::IOError;
+use crate::hci::timer::Timer;
+use crate::hci::{adapter, StreamError};
+use core::time::Duration;
+use futures_util::future::{self, LocalBoxFuture};
+
+/// How an error is handled by a [`RetryPolicy`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ErrorClass {
+    /// Try again after a backoff (`EAGAIN`, interrupted or timed out transport IO).
+    Transient,
+    /// The transport is gone. Try again after reconnecting it.
+    Disconnected,
+    /// Retrying won't help (bad parameters, HCI error codes, malformed packets, ..).
+    Fatal,
+}
+impl ErrorClass {
+    pub fn of(error: adapter::Error) -> ErrorClass {
+        match error {
+            adapter::Error::IOError(
+                IOError::WouldBlock | IOError::Interrupted | IOError::TimedOut,
+            ) => ErrorClass::Transient,
+            adapter::Error::IOError(
+                IOError::NotConnected | IOError::Closed | IOError::Pipe | IOError::NotFound,
+            )
+            | adapter::Error::StreamError(StreamError::StreamClosed | StreamError::Disconnected) => {
+                ErrorClass::Disconnected
+            }
+            _ => ErrorClass::Fatal,
+        }
+    }
+}
+/// Attempts and exponential backoff for operations failing with transient errors.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct RetryPolicy {
+    /// Attempts in total (the first try included). `1` never retries.
+    pub max_attempts: u8,
+    /// Backoff before the first retry. Doubles every retry.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Reconnect the transport on [`ErrorClass::Disconnected`] errors.
+    pub reconnect: bool,
+}
+impl RetryPolicy {
+    pub const DEFAULT: RetryPolicy = RetryPolicy {
+        max_attempts: 4,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(500),
+        reconnect: true,
+    };
+    /// Backoff before retry number `retry` (starting at 1).
+    pub fn backoff(&self, retry: u8) -> Duration {
+        let factor = 1_u32 << u32::from(retry.saturating_sub(1)).min(16);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+    /// Whether `error`, the `failures`th failure in a row, should be retried.
+    pub fn should_retry(&self, error: adapter::Error, failures: u8) -> bool {
+        failures < self.max_attempts
+            && match ErrorClass::of(error) {
+                ErrorClass::Transient => true,
+                ErrorClass::Disconnected => self.reconnect,
+                ErrorClass::Fatal => false,
+            }
+    }
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::DEFAULT
+    }
+}
+/// Reported to [`RetryHooks::event`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum RetryEvent {
+    Retrying {
+        retry: u8,
+        error: adapter::Error,
+        backoff: Duration,
+    },
+    Reconnecting {
+        retry: u8,
+        error: adapter::Error,
+    },
+    Reconnected,
+    GaveUp(adapter::Error),
+}
+/// The parts of retrying that depend on the transport and the runtime. [`Timer::delay`] waits out
+/// the backoff.
+pub trait RetryHooks<A>: Timer {
+    /// Reopens the transport. Return `Ok(false)` if it can't be done.
+    fn reconnect<'a>(
+        &'a mut self,
+        _adapter: &'a mut A,
+    ) -> LocalBoxFuture<'a, Result<bool, adapter::Error>> {
+        Box::pin(future::ready(Ok(false)))
+    }
+    fn event(&mut self, _event: RetryEvent) {}
+}
+/// What a retry did.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum Retried {
+    /// Waited out the backoff. The transport still holds its state.
+    Backoff,
+    /// Reconnected the transport. Anything in flight is lost and has to be sent again.
+    Reconnected,
+}
+/// Handles the `failures`th failure in a row of an operation on `adapter`: waits out the backoff
+/// or reconnects.
+/// # Errors
+/// returns `error` if the policy doesn't retry it (or the reconnect didn't work).
+pub async fn retry_after<A, H: RetryHooks<A> + ?Sized>(
+    policy: &RetryPolicy,
+    hooks: &mut H,
+    adapter: &mut A,
+    error: adapter::Error,
+    failures: u8,
+) -> Result<Retried, adapter::Error> {
+    if !policy.should_retry(error, failures) {
+        if ErrorClass::of(error) != ErrorClass::Fatal {
+            hci_warn!("giving up after {} attempts: {:?}", failures, error);
+            hooks.event(RetryEvent::GaveUp(error));
+        }
+        return Err(error);
+    }
+    let backoff = policy.backoff(failures);
+    if ErrorClass::of(error) == ErrorClass::Transient {
+        hci_debug!("retry {} in {:?} after {:?}", failures, backoff, error);
+        hooks.event(RetryEvent::Retrying {
+            retry: failures,
+            error,
+            backoff,
+        });
+        hooks.delay(backoff).await;
+        return Ok(Retried::Backoff);
+    }
+    hci_warn!("transport lost ({:?}), reconnecting", error);
+    hooks.event(RetryEvent::Reconnecting {
+        retry: failures,
+        error,
+    });
+    hooks.delay(backoff).await;
+    if hooks.reconnect(adapter).await? {
+        hooks.event(RetryEvent::Reconnected);
+        Ok(Retried::Reconnected)
+    } else {
+        hooks.event(RetryEvent::GaveUp(error));
+        Err(error)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::adapter;
+    use crate::hci::adapters::Adapter;
+    use crate::hci::adapters::DummyUnrecognizedEventHandler;
+    use crate::hci::baseband::Reset;
+    use crate::hci::command::Command;
+    use crate::hci::command::CommandPacket;
+    use crate::hci::event::EventPacket;
+    use crate::hci::io::HCIPort;
+    use crate::testing::mock::{command_complete, MockHandle, MockTransport};
+    use futures_util::FutureExt;
+
+    /// Fails the first `failures` writes with `EAGAIN`.
+    struct Flaky {
+        port: HCIPort<MockTransport>,
+        failures: usize,
+    }
+    impl adapter::Adapter for Flaky {
+        fn write_command<'s, 'p: 's>(
+            &'s mut self,
+            packet: CommandPacket<&'p [u8]>,
+        ) -> LocalBoxFuture<'s, Result<(), adapter::Error>> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Box::pin(future::ready(Err(IOError::WouldBlock.into())));
+            }
+            self.port.write_command(packet)
+        }
+
+        fn read_event<'s, 'p: 's, Buf: crate::bytes::Storage<u8> + 'p>(
+            &'s mut self,
+        ) -> LocalBoxFuture<'s, Result<EventPacket<Buf>, adapter::Error>> {
+            self.port.read_event()
+        }
+    }
+    struct Immediate(Vec<RetryEvent>);
+    impl Timer for Immediate {
+        fn delay(&mut self, _duration: Duration) -> LocalBoxFuture<'static, ()> {
+            Box::pin(future::ready(()))
+        }
+    }
+    impl RetryHooks<Flaky> for Immediate {
+        fn event(&mut self, event: RetryEvent) {
+            self.0.push(event);
+        }
+    }
+    fn flaky(failures: usize) -> (Adapter<Flaky, DummyUnrecognizedEventHandler>, MockHandle) {
+        let (transport, handle) = MockTransport::new();
+        let flaky = Flaky {
+            port: HCIPort::new(transport),
+            failures,
+        };
+        (Adapter::new(flaky), handle)
+    }
+
+    #[test]
+    fn test_retry_would_block() {
+        assert_eq!(RetryPolicy::DEFAULT.backoff(3), Duration::from_millis(40));
+        assert_eq!(
+            RetryPolicy::DEFAULT.backoff(10),
+            RetryPolicy::DEFAULT.max_backoff
+        );
+        let (mut adapter, handle) = flaky(2);
+        adapter.set_retry(RetryPolicy::DEFAULT, Immediate(Vec::new()));
+        handle.inject(&command_complete(Reset::opcode(), &[0x00]));
+        adapter.reset().now_or_never().unwrap().unwrap();
+        // Sent once, after two failed writes.
+        assert_eq!(handle.take_written(), [0x01, 0x03, 0x0C, 0x00]);
+
+        // Without a policy the first error is returned.
+        let (mut adapter, _handle) = flaky(1);
+        assert_eq!(
+            adapter.reset().now_or_never().unwrap(),
+            Err(IOError::WouldBlock.into())
+        );
+    }
+}
@@ -0,0 +1,495 @@
+//! Linux rfkill. A blocked radio makes bringing a BlueZ adapter up fail with `ERFKILL` (and USB
+//! controllers simply don't answer), so [`prepare`] checks the kill switch of a controller before
+//! opening it and can lift a soft block (the BlueZ [`Manager`](crate::hci::bluez_socket::Manager)
+//! does this for its sockets). [`RfkillWatcher`] streams the switch changes at runtime and
+//! [`RfkillAdapter`] surfaces them through the adapter: reads and writes fail with
+//! `IOError::Blocked` while the radio is blocked.
+use crate::bytes::Storage;
+use crate::error::{IOError, Rfkill};
+use crate::hci::acl::ACLPacket;
+use crate::hci::adapter;
+use crate::hci::command::CommandPacket;
+use crate::hci::event::EventPacket;
+use crate::sync::Mutex;
+use alloc::collections::VecDeque;
+use core::convert::TryFrom;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_util::future::{poll_fn, select, Either, LocalBoxFuture};
+use futures_util::task::AtomicWaker;
+use futures_util::{FutureExt, StreamExt};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// Character device with the rfkill events (and for changing soft blocks).
+pub const RFKILL_DEVICE: &str = "/dev/rfkill";
+/// Length of the original `struct rfkill_event`. Newer kernels append fields, which are ignored.
+pub const EVENT_LEN: usize = 8;
+/// `RFKILL_TYPE_BLUETOOTH`.
+pub const TYPE_BLUETOOTH: u8 = 2;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum RfkillOp {
+    /// A switch was added (or already existed when the device was opened).
+    Add = 0,
+    Del = 1,
+    Change = 2,
+    ChangeAll = 3,
+}
+impl TryFrom<u8> for RfkillOp {
+    type Error = crate::ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(RfkillOp::Add),
+            1 => Ok(RfkillOp::Del),
+            2 => Ok(RfkillOp::Change),
+            3 => Ok(RfkillOp::ChangeAll),
+            _ => Err(crate::ConversionError(())),
+        }
+    }
+}
+/// `struct rfkill_event`. `index` is the `M` of `/sys/class/rfkill/rfkillM`.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct RfkillEvent {
+    pub index: u32,
+    pub switch_type: u8,
+    pub op: RfkillOp,
+    pub soft: bool,
+    pub hard: bool,
+}
+impl RfkillEvent {
+    /// Returns `None` if `buf` is shorter than [`EVENT_LEN`] or has an unknown op.
+    pub fn unpack(buf: &[u8]) -> Option<RfkillEvent> {
+        let buf = buf.get(..EVENT_LEN)?;
+        Some(RfkillEvent {
+            index: u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            switch_type: buf[4],
+            op: RfkillOp::try_from(buf[5]).ok()?,
+            soft: buf[6] != 0,
+            hard: buf[7] != 0,
+        })
+    }
+    pub fn pack(&self) -> [u8; EVENT_LEN] {
+        let index = self.index.to_ne_bytes();
+        [
+            index[0],
+            index[1],
+            index[2],
+            index[3],
+            self.switch_type,
+            self.op as u8,
+            self.soft.into(),
+            self.hard.into(),
+        ]
+    }
+    pub fn is_bluetooth(&self) -> bool {
+        self.switch_type == TYPE_BLUETOOTH
+    }
+    /// Block of the switch. `None` if it isn't blocked.
+    pub fn block(&self) -> Option<Rfkill> {
+        if self.hard {
+            Some(Rfkill::Hard)
+        } else if self.soft {
+            Some(Rfkill::Soft)
+        } else {
+            None
+        }
+    }
+}
+/// rfkill switch of controller `hciN` (`None` if it has none).
+pub fn switch_index(controller: u16) -> Result<Option<u32>, IOError> {
+    let entries = match std::fs::read_dir(format!("/sys/class/bluetooth/hci{}", controller)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(IOError::NotFound),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let name = entry?.file_name();
+        if let Some(index) = name.to_str().and_then(|n| n.strip_prefix("rfkill")) {
+            if let Ok(index) = index.parse() {
+                return Ok(Some(index));
+            }
+        }
+    }
+    Ok(None)
+}
+/// Current block of switch `index`.
+pub fn switch_block(index: u32) -> Result<Option<Rfkill>, IOError> {
+    let read = |file: &str| -> Result<bool, IOError> {
+        let path = format!("/sys/class/rfkill/rfkill{}/{}", index, file);
+        Ok(std::fs::read_to_string(path)?.trim() != "0")
+    };
+    Ok(if read("hard")? {
+        Some(Rfkill::Hard)
+    } else if read("soft")? {
+        Some(Rfkill::Soft)
+    } else {
+        None
+    })
+}
+/// Current block of controller `hciN`. `None` if it isn't blocked (or has no switch).
+pub fn controller_block(controller: u16) -> Result<Option<Rfkill>, IOError> {
+    match switch_index(controller)? {
+        Some(index) => switch_block(index),
+        None => Ok(None),
+    }
+}
+/// Soft blocks (or unblocks) switch `index`. Needs write access to [`RFKILL_DEVICE`].
+pub fn set_soft_block(index: u32, blocked: bool) -> Result<(), IOError> {
+    let event = RfkillEvent {
+        index,
+        switch_type: TYPE_BLUETOOTH,
+        op: RfkillOp::Change,
+        soft: blocked,
+        hard: false,
+    };
+    OpenOptions::new()
+        .write(true)
+        .open(RFKILL_DEVICE)?
+        .write_all(&event.pack())?;
+    Ok(())
+}
+/// Checks the kill switch of controller `hciN` before opening it. A soft block is lifted if
+/// `unblock` is set.
+/// # Errors
+/// returns `IOError::Blocked` if the radio stays blocked (always for hard blocks).
+pub fn prepare(controller: u16, unblock: bool) -> Result<(), IOError> {
+    let index = match switch_index(controller)? {
+        Some(index) => index,
+        None => return Ok(()),
+    };
+    match switch_block(index)? {
+        None => Ok(()),
+        Some(Rfkill::Soft) if unblock => set_soft_block(index, false),
+        Some(block) => Err(IOError::Blocked(block)),
+    }
+}
+#[derive(Default)]
+struct Events {
+    queue: VecDeque<RfkillEvent>,
+    error: Option<IOError>,
+    ended: bool,
+}
+#[derive(Default)]
+struct Shared {
+    events: Mutex<Events>,
+    waker: AtomicWaker,
+}
+impl Shared {
+    /// Reads events until `device` fails or `wake` becomes readable (the watcher was dropped).
+    fn reader_thread(&self, mut device: File, wake: &File, index: Option<u32>) {
+        let mut fds = [
+            libc::pollfd {
+                fd: device.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: wake.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        let error = loop {
+            // Safety: `fds` outlives the call and its length is passed along.
+            if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } < 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                break e.into();
+            }
+            if fds[1].revents != 0 {
+                return;
+            }
+            if let Err(e) = self.read_events(&mut device, index) {
+                break e;
+            }
+        };
+        self.events.lock(|events| events.error = Some(error));
+        self.waker.wake();
+    }
+    /// Queues the events waiting on the (non blocking) `device`.
+    fn read_events(&self, device: &mut File, index: Option<u32>) -> Result<(), IOError> {
+        let mut buf = [0_u8; EVENT_LEN];
+        loop {
+            // Every read returns one event, cut to the length asked for.
+            let amount = match device.read(&mut buf) {
+                Ok(0) => return Err(IOError::Closed),
+                Ok(amount) => amount,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let event = RfkillEvent::unpack(&buf[..amount]).ok_or(IOError::InvalidData)?;
+            if event.is_bluetooth() && index.map_or(true, |i| i == event.index) {
+                self.events.lock(|events| events.queue.push_back(event));
+                self.waker.wake();
+            }
+        }
+    }
+}
+/// Non blocking pipe, read end first.
+fn pipe() -> Result<(File, File), IOError> {
+    let mut fds = [0; 2];
+    // Safety: `fds` has room for the two descriptors.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // Safety: both descriptors were just opened and nothing else owns them.
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+/// Stream of the Bluetooth rfkill events. Starts with an [`RfkillOp::Add`] event for every
+/// existing switch (the current state). Reading happens on a thread, which is woken up and joined
+/// when the watcher is dropped. Ends after the first error.
+pub struct RfkillWatcher {
+    shared: Arc<Shared>,
+    wake: File,
+    reader: Option<JoinHandle<()>>,
+}
+impl RfkillWatcher {
+    /// Watches the switch of controller `hciN` or every Bluetooth switch if `controller` is
+    /// `None`.
+    pub fn open(controller: Option<u16>) -> Result<RfkillWatcher, IOError> {
+        let index = match controller {
+            Some(controller) => Some(switch_index(controller)?.ok_or(IOError::NotFound)?),
+            None => None,
+        };
+        let device = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open(RFKILL_DEVICE)?;
+        RfkillWatcher::from_device(device, index)
+    }
+    /// Reads the events from `device`, which has to be non blocking.
+    fn from_device(device: File, index: Option<u32>) -> Result<RfkillWatcher, IOError> {
+        let (wake_reader, wake) = pipe()?;
+        let shared = Arc::new(Shared::default());
+        let reader = shared.clone();
+        let reader = std::thread::Builder::new()
+            .name("btle-rfkill".into())
+            .spawn(move || reader.reader_thread(device, &wake_reader, index))?;
+        Ok(RfkillWatcher {
+            shared,
+            wake,
+            reader: Some(reader),
+        })
+    }
+}
+impl futures_util::Stream for RfkillWatcher {
+    type Item = Result<RfkillEvent, IOError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // Register first so an event arriving while the queue is checked still wakes us.
+        self.shared.waker.register(cx.waker());
+        self.shared.events.lock(|events| {
+            if let Some(event) = events.queue.pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+            if events.ended {
+                return Poll::Ready(None);
+            }
+            match events.error.take() {
+                Some(e) => {
+                    events.ended = true;
+                    match e {
+                        IOError::Closed => Poll::Ready(None),
+                        e => Poll::Ready(Some(Err(e))),
+                    }
+                }
+                None => Poll::Pending,
+            }
+        })
+    }
+}
+impl Drop for RfkillWatcher {
+    fn drop(&mut self) {
+        // The reader is either polling (and sees the byte) or already gone.
+        let _ = self.wake.write(&[0]);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+impl core::fmt::Debug for RfkillWatcher {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("RfkillWatcher { .. }")
+    }
+}
+/// [`adapter::Adapter`] failing with `IOError::Blocked` while the radio of its controller is
+/// blocked. A read waiting for an event returns as soon as the block happens, so the loop
+/// driving the adapter sees block changes with the rest of its errors. If the watcher fails the
+/// block isn't checked anymore.
+#[derive(Debug)]
+pub struct RfkillAdapter<A: adapter::Adapter> {
+    pub adapter: A,
+    watcher: RfkillWatcher,
+    block: Option<Rfkill>,
+}
+impl<A: adapter::Adapter> RfkillAdapter<A> {
+    /// Watches the switch of controller `hciN`, the one `adapter` talks to.
+    pub fn open(adapter: A, controller: u16) -> Result<RfkillAdapter<A>, IOError> {
+        Ok(RfkillAdapter::new(
+            adapter,
+            RfkillWatcher::open(Some(controller))?,
+        ))
+    }
+    pub fn new(adapter: A, watcher: RfkillWatcher) -> RfkillAdapter<A> {
+        RfkillAdapter {
+            adapter,
+            watcher,
+            block: None,
+        }
+    }
+    /// Block of the radio as of the last switch change seen.
+    pub fn block(&mut self) -> Option<Rfkill> {
+        while let Some(Some(Ok(event))) = self.watcher.next().now_or_never() {
+            self.block = event.block();
+        }
+        self.block
+    }
+    fn check(&mut self) -> Result<(), adapter::Error> {
+        match self.block() {
+            Some(block) => Err(IOError::Blocked(block).into()),
+            None => Ok(()),
+        }
+    }
+}
+impl<A: adapter::Adapter> adapter::Adapter for RfkillAdapter<A> {
+    fn write_command<'s, 'p: 's>(
+        &'s mut self,
+        packet: CommandPacket<&'p [u8]>,
+    ) -> LocalBoxFuture<'s, Result<(), adapter::Error>> {
+        Box::pin(async move {
+            self.check()?;
+            self.adapter.write_command(packet).await
+        })
+    }
+
+    fn read_event<'s, 'p: 's, S: Storage<u8> + 'p>(
+        &'s mut self,
+    ) -> LocalBoxFuture<'s, Result<EventPacket<S>, adapter::Error>> {
+        Box::pin(async move {
+            self.check()?;
+            let RfkillAdapter {
+                adapter,
+                watcher,
+                block,
+            } = self;
+            let blocked = poll_fn(|cx| loop {
+                match watcher.poll_next_unpin(cx) {
+                    Poll::Ready(Some(Ok(event))) => {
+                        *block = event.block();
+                        if let Some(block) = *block {
+                            return Poll::Ready(block);
+                        }
+                    }
+                    _ => return Poll::Pending,
+                }
+            });
+            match select(adapter.read_event::<S>(), blocked).await {
+                Either::Left((event, _)) => event,
+                Either::Right((block, _)) => Err(IOError::Blocked(block).into()),
+            }
+        })
+    }
+
+    fn write_acl<'s, 'p: 's>(
+        &'s mut self,
+        packet: ACLPacket<&'p [u8]>,
+    ) -> LocalBoxFuture<'s, Result<(), adapter::Error>> {
+        Box::pin(async move {
+            self.check()?;
+            self.adapter.write_acl(packet).await
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::event::EventCode;
+    use crate::hci::io::HCIPort;
+    use crate::testing::block_on;
+    use crate::testing::mock::MockTransport;
+    use core::time::Duration;
+
+    #[test]
+    fn test_event() {
+        let mut buf = [0_u8; 9];
+        buf[..4].copy_from_slice(&3_u32.to_ne_bytes());
+        buf[4..8].copy_from_slice(&[TYPE_BLUETOOTH, 2, 1, 0]);
+        let event = RfkillEvent::unpack(&buf).unwrap();
+        assert_eq!(event.op, RfkillOp::Change);
+        assert_eq!(event.block(), Some(Rfkill::Soft));
+        assert_eq!(event.pack(), buf[..EVENT_LEN]);
+        let hard = RfkillEvent {
+            hard: true,
+            ..event
+        };
+        assert_eq!(hard.block(), Some(Rfkill::Hard));
+        assert!(RfkillEvent::unpack(&buf[..7]).is_none());
+    }
+    fn event(index: u32, op: RfkillOp, soft: bool) -> [u8; EVENT_LEN] {
+        RfkillEvent {
+            index,
+            switch_type: TYPE_BLUETOOTH,
+            op,
+            soft,
+            hard: false,
+        }
+        .pack()
+    }
+    #[test]
+    fn test_watcher_ends_after_error() {
+        let (device, mut writer) = pipe().unwrap();
+        let mut watcher = RfkillWatcher::from_device(device, None).unwrap();
+        writer.write_all(&event(0, RfkillOp::Add, false)).unwrap();
+        assert_eq!(block_on(watcher.next()).unwrap().unwrap().op, RfkillOp::Add);
+        // Too short for an event.
+        writer.write_all(&[0x00, 0x01]).unwrap();
+        assert_eq!(block_on(watcher.next()), Some(Err(IOError::InvalidData)));
+        assert_eq!(block_on(watcher.next()), None);
+    }
+    #[test]
+    fn test_adapter_blocked() {
+        let (device, mut writer) = pipe().unwrap();
+        let watcher = RfkillWatcher::from_device(device, Some(0)).unwrap();
+        let (transport, handle) = MockTransport::new();
+        let mut adapter = RfkillAdapter::new(HCIPort::new(transport), watcher);
+        writer.write_all(&event(0, RfkillOp::Add, false)).unwrap();
+        // Other switches are ignored.
+        writer.write_all(&event(1, RfkillOp::Change, true)).unwrap();
+        // No event is coming, the read only returns because of the block.
+        let soft_block = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            writer.write_all(&event(0, RfkillOp::Change, true)).unwrap();
+            writer
+        });
+        let read = block_on(adapter::Adapter::read_event::<Box<[u8]>>(&mut adapter));
+        assert_eq!(
+            read.unwrap_err(),
+            adapter::Error::IOError(IOError::Blocked(Rfkill::Soft))
+        );
+        assert_eq!(adapter.block(), Some(Rfkill::Soft));
+
+        let mut writer = soft_block.join().unwrap();
+        writer
+            .write_all(&event(0, RfkillOp::Change, false))
+            .unwrap();
+        for _ in 0..100 {
+            if adapter.block().is_none() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(adapter.block(), None);
+        handle.inject_event(EventCode::HardwareError, &[0x01]);
+        let read = block_on(adapter::Adapter::read_event::<Box<[u8]>>(&mut adapter));
+        assert_eq!(read.unwrap().event_code, EventCode::HardwareError);
+    }
+}
@@ -0,0 +1,168 @@
+//! HCI SCO data packets. Carry synchronous (voice) traffic of BR/EDR SCO/eSCO links for
+//! controllers that route it over HCI instead of a PCM/I2S interface.
+use crate::bytes::Storage;
+use crate::hci::packet::{PacketType, RawPacket};
+use crate::le::connection::ConnectionHandle;
+use crate::{ConversionError, PackError};
+use core::convert::TryFrom;
+
+/// Handle + flags (2 bytes) and data length (1 byte).
+pub const SCO_HEADER_LEN: usize = 3;
+/// Longest SCO data (the length field has 8 bits).
+pub const MAX_SCO_DATA_LEN: usize = 0xFF;
+/// Packet Status flag. Only set by the controller when erroneous data reporting is enabled
+/// (host to controller packets use `Correct`).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum SCOPacketStatus {
+    Correct = 0b00,
+    /// Some data may have errors.
+    PossiblyInvalid = 0b01,
+    /// No data received. The data is all zeros.
+    NoData = 0b10,
+    /// Only parts of the data received.
+    PartiallyLost = 0b11,
+}
+impl From<SCOPacketStatus> for u8 {
+    fn from(status: SCOPacketStatus) -> Self {
+        status as u8
+    }
+}
+impl TryFrom<u8> for SCOPacketStatus {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0b00 => Ok(SCOPacketStatus::Correct),
+            0b01 => Ok(SCOPacketStatus::PossiblyInvalid),
+            0b10 => Ok(SCOPacketStatus::NoData),
+            0b11 => Ok(SCOPacketStatus::PartiallyLost),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// HCI SCO data packet (without the H4 packet type).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct SCOPacket<Buf> {
+    pub connection_handle: ConnectionHandle,
+    pub status: SCOPacketStatus,
+    pub data: Buf,
+}
+impl<Buf: AsRef<[u8]>> SCOPacket<Buf> {
+    pub fn new(connection_handle: ConnectionHandle, data: Buf) -> Self {
+        SCOPacket {
+            connection_handle,
+            status: SCOPacketStatus::Correct,
+            data,
+        }
+    }
+    pub fn as_ref(&self) -> SCOPacket<&[u8]> {
+        SCOPacket {
+            connection_handle: self.connection_handle,
+            status: self.status,
+            data: self.data.as_ref(),
+        }
+    }
+    pub fn to_new_storage<NewBuf: Storage<u8>>(&self) -> SCOPacket<NewBuf> {
+        SCOPacket {
+            connection_handle: self.connection_handle,
+            status: self.status,
+            data: NewBuf::from_slice(self.data.as_ref()),
+        }
+    }
+    /// Header + data len.
+    pub fn byte_len(&self) -> usize {
+        SCO_HEADER_LEN + self.data.as_ref().len()
+    }
+    pub fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        let data = self.data.as_ref();
+        let data_len = u8::try_from(data.len()).map_err(|_| PackError::InvalidFields)?;
+        let handle = u16::from(self.connection_handle) | (u16::from(u8::from(self.status)) << 12);
+        let (header, rest) = buf.split_at_mut(SCO_HEADER_LEN);
+        header[..2].copy_from_slice(&handle.to_le_bytes());
+        header[2] = data_len;
+        rest.copy_from_slice(data);
+        Ok(())
+    }
+    /// Packs the H4 framed packet (packet type first).
+    pub fn pack_as_raw_packet<NewBuf: Storage<u8>>(&self) -> Result<NewBuf, PackError> {
+        let len = self.byte_len() + 1;
+        if NewBuf::max_len() < len {
+            return Err(PackError::BadLength {
+                expected: len,
+                got: NewBuf::max_len(),
+            });
+        }
+        let mut out = NewBuf::with_size(len);
+        let (packet_type, rest) = out
+            .as_mut()
+            .split_first_mut()
+            .ok_or(PackError::bad_index(0))?;
+        *packet_type = PacketType::SCOData.into();
+        self.pack_into(rest)?;
+        Ok(out)
+    }
+}
+impl<'a> SCOPacket<&'a [u8]> {
+    pub fn unpack_from(buf: &'a [u8]) -> Result<Self, PackError> {
+        PackError::atleast_length(SCO_HEADER_LEN, buf)?;
+        let (header, data) = buf.split_at(SCO_HEADER_LEN);
+        let handle = u16::from_le_bytes([header[0], header[1]]);
+        let data_len = usize::from(header[2]);
+        if data_len != data.len() {
+            return Err(PackError::BadLength {
+                expected: SCO_HEADER_LEN + data_len,
+                got: buf.len(),
+            });
+        }
+        let connection_handle =
+            ConnectionHandle::new_checked(handle & 0x0FFF).ok_or(PackError::bad_index(0))?;
+        let status = SCOPacketStatus::try_from(((handle >> 12) & 0b11) as u8)
+            .map_err(|_| PackError::bad_index(1))?;
+        Ok(SCOPacket {
+            connection_handle,
+            status,
+            data,
+        })
+    }
+}
+impl<'a> TryFrom<RawPacket<&'a [u8]>> for SCOPacket<&'a [u8]> {
+    type Error = PackError;
+
+    fn try_from(packet: RawPacket<&'a [u8]>) -> Result<Self, Self::Error> {
+        if packet.packet_type != PacketType::SCOData {
+            Err(PackError::BadOpcode)
+        } else {
+            SCOPacket::unpack_from(packet.buf)
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::acl::IncomingPacket;
+    use crate::hci::stream::Stream;
+    use crate::testing::mock::MockTransport;
+    use futures_util::FutureExt;
+
+    #[test]
+    fn test_sco_round_trip() {
+        let (transport, handle) = MockTransport::new();
+        let mut stream = Stream::new(Box::pin(transport));
+        let samples = [0x11, 0x22, 0x33, 0x44];
+        let packet = SCOPacket::new(ConnectionHandle::new(0x0006), &samples[..]);
+        stream.send_sco(packet).now_or_never().unwrap().unwrap();
+        let written = handle.take_written();
+        assert_eq!(written, [0x03, 0x06, 0x00, 0x04, 0x11, 0x22, 0x33, 0x44]);
+
+        // Controller reported packet with the Packet Status flag set.
+        handle.inject(&[0x03, 0x06, 0x10, 0x02, 0xAA, 0xBB]);
+        match stream.read_incoming::<Box<[u8]>>().now_or_never().unwrap() {
+            Ok(IncomingPacket::SCO(sco)) => {
+                assert_eq!(sco.status, SCOPacketStatus::PossiblyInvalid);
+                assert_eq!(sco.data.as_ref(), &[0xAA, 0xBB]);
+            }
+            other => panic!("expected an SCO packet, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! HCI over a desktop serial port (UART dev kits, USB to serial adapters, Zephyr `hci_uart` or
+//! nRF boards). Enabled with the `serialport` feature. Unlike [`crate::hci::uart`] this needs
+//! `std`, but works the same on Linux, macOS and Windows.
+//!
+//! The [`serialport`] crate is blocking so [`SerialTransport`] reads the port from its own thread
+//! and wakes the reading task when bytes arrive. Writes go straight to the port. H4 commands are
+//! small so they block only for as long as it takes the UART to take them.
+use crate::error::IOError;
+use crate::hci::adapter;
+use crate::hci::device_id::{select, ControllerInfo, DeviceId, TransportKind};
+use crate::hci::packet::{FramingRecovery, H4Receiver};
+use crate::hci::stream::{HCIReader, HCIWriter, Stream};
+use crate::hci::{StreamError, MAX_FRAME_SIZE};
+use core::convert::TryFrom;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use futures_util::task::AtomicWaker;
+use serialport::{ClearBuffer, SerialPort, SerialPortType};
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub use serialport::FlowControl;
+
+/// How long a blocking port read or write waits before giving up. Bounds how long the reader
+/// thread takes to notice the transport was dropped.
+pub const PORT_TIMEOUT: Duration = Duration::from_millis(100);
+
+impl From<serialport::Error> for IOError {
+    fn from(e: serialport::Error) -> Self {
+        match e.kind() {
+            serialport::ErrorKind::NoDevice => IOError::NotFound,
+            serialport::ErrorKind::InvalidInput => IOError::InvalidArgument,
+            serialport::ErrorKind::Io(kind) => IOError::from(kind),
+            serialport::ErrorKind::Unknown => IOError::Unknown,
+        }
+    }
+}
+impl From<serialport::Error> for adapter::Error {
+    fn from(e: serialport::Error) -> Self {
+        IOError::from(e).into()
+    }
+}
+/// Serial ports (any of them could have a controller behind it), with the USB IDs of USB to
+/// serial adapters.
+pub fn controllers() -> Result<Vec<ControllerInfo>, IOError> {
+    Ok(serialport::available_ports()?
+        .into_iter()
+        .enumerate()
+        .map(|(index, port)| {
+            let (name, usb) = match port.port_type {
+                SerialPortType::UsbPort(usb) => (
+                    usb.product,
+                    Some(DeviceId::Usb {
+                        vendor_id: usb.vid,
+                        product_id: usb.pid,
+                        serial: usb.serial_number,
+                    }),
+                ),
+                _ => (None, None),
+            };
+            ControllerInfo {
+                id: DeviceId::Path(port.port_name),
+                transport: TransportKind::Serial,
+                index: u16::try_from(index).unwrap_or(u16::MAX),
+                address: None,
+                name,
+                usb,
+            }
+        })
+        .collect())
+}
+/// Drives the controller's reset line. Called with `true` to hold the controller in reset and
+/// `false` to release it.
+pub type ResetCallback = Box<dyn FnMut(bool) + Send>;
+/// Serial port settings. H4 is always 8 data bits, no parity and 1 stop bit.
+pub struct SerialConfig {
+    pub path: String,
+    pub baud_rate: u32,
+    /// Most H4 controllers expect RTS/CTS ([`FlowControl::Hardware`], the default).
+    pub flow_control: FlowControl,
+    reset: Option<ResetCallback>,
+    reset_hold: Duration,
+    boot_delay: Duration,
+}
+impl SerialConfig {
+    pub fn new<P: Into<String>>(path: P, baud_rate: u32) -> SerialConfig {
+        SerialConfig {
+            path: path.into(),
+            baud_rate,
+            flow_control: FlowControl::Hardware,
+            reset: None,
+            reset_hold: Duration::from_millis(10),
+            boot_delay: Duration::from_millis(100),
+        }
+    }
+    pub fn flow_control(mut self, flow_control: FlowControl) -> SerialConfig {
+        self.flow_control = flow_control;
+        self
+    }
+    /// Resets the controller with `reset` (usually a GPIO wired to its reset pin) when the port
+    /// is opened and on [`SerialTransport::hardware_reset`].
+    pub fn reset_gpio<F: FnMut(bool) + Send + 'static>(mut self, reset: F) -> SerialConfig {
+        self.reset = Some(Box::new(reset));
+        self
+    }
+    /// How long reset is held and how long the controller takes to boot after it's released.
+    pub fn reset_timing(mut self, hold: Duration, boot_delay: Duration) -> SerialConfig {
+        self.reset_hold = hold;
+        self.boot_delay = boot_delay;
+        self
+    }
+}
+impl core::fmt::Debug for SerialConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SerialConfig")
+            .field("path", &self.path)
+            .field("baud_rate", &self.baud_rate)
+            .field("flow_control", &self.flow_control)
+            .field("reset", &self.reset.is_some())
+            .field("reset_hold", &self.reset_hold)
+            .field("boot_delay", &self.boot_delay)
+            .finish()
+    }
+}
+/// Bytes from the reader thread.
+#[derive(Default)]
+struct Received {
+    bytes: Vec<u8>,
+    error: Option<adapter::Error>,
+}
+#[derive(Default)]
+struct Shared {
+    received: Mutex<Received>,
+    waker: AtomicWaker,
+    stop: AtomicBool,
+}
+impl Shared {
+    fn reader_thread<R: Read>(&self, mut reader: R) {
+        let mut buf = [0_u8; 256];
+        while !self.stop.load(Ordering::Relaxed) {
+            let error = match reader.read(&mut buf) {
+                Ok(0) => StreamError::StreamClosed.into(),
+                Ok(amount) => {
+                    self.lock().bytes.extend_from_slice(&buf[..amount]);
+                    self.waker.wake();
+                    continue;
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => e.into(),
+            };
+            self.lock().error = Some(error);
+            self.waker.wake();
+            return;
+        }
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, Received> {
+        // The reader thread never panics while holding the lock.
+        self.received
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+/// H4 framing over a serial port. See the module docs.
+pub struct SerialTransport {
+    shared: Arc<Shared>,
+    writer: Box<dyn Write + Send>,
+    port: Option<Box<dyn SerialPort>>,
+    rx: H4Receiver<Box<[u8]>>,
+    reset: Option<ResetCallback>,
+    reset_hold: Duration,
+    boot_delay: Duration,
+}
+/// HCI [`Stream`] over a [`SerialTransport`].
+pub type SerialStream = Stream<SerialTransport, Box<SerialTransport>>;
+impl SerialTransport {
+    /// Opens the port, resets the controller if there is a reset callback and starts reading.
+    pub fn open(config: SerialConfig) -> Result<SerialTransport, adapter::Error> {
+        let port = serialport::new(config.path.as_str(), config.baud_rate)
+            .flow_control(config.flow_control)
+            .timeout(PORT_TIMEOUT)
+            .open()?;
+        let reader = port.try_clone()?;
+        let writer = port.try_clone()?;
+        let mut transport = SerialTransport::with_io(reader, Box::new(writer));
+        transport.port = Some(port);
+        transport.reset = config.reset;
+        transport.reset_hold = config.reset_hold;
+        transport.boot_delay = config.boot_delay;
+        if transport.reset.is_some() {
+            transport.hardware_reset()?;
+        }
+        Ok(transport)
+    }
+    /// Opens the port selected by `id`: a [`DeviceId::Path`] or the [`DeviceId::Usb`] IDs of a
+    /// USB to serial adapter.
+    pub fn open_device(id: &DeviceId, baud_rate: u32) -> Result<SerialTransport, adapter::Error> {
+        let path = match id {
+            DeviceId::Path(path) => path.clone(),
+            _ => match select(&controllers()?, id) {
+                Some(controller) => controller.id.to_string(),
+                None => return Err(IOError::NotFound.into()),
+            },
+        };
+        SerialTransport::open(SerialConfig::new(path, baud_rate))
+    }
+    fn with_io<R: Read + Send + 'static>(
+        reader: R,
+        writer: Box<dyn Write + Send>,
+    ) -> SerialTransport {
+        let shared = Arc::new(Shared::default());
+        let thread_shared = Arc::clone(&shared);
+        std::thread::spawn(move || thread_shared.reader_thread(reader));
+        SerialTransport {
+            shared,
+            writer,
+            port: None,
+            rx: H4Receiver::new(vec![0_u8; MAX_FRAME_SIZE].into_boxed_slice()),
+            reset: None,
+            reset_hold: Duration::default(),
+            boot_delay: Duration::default(),
+        }
+    }
+    pub fn into_stream(self) -> SerialStream {
+        Stream::new(Box::pin(self))
+    }
+    /// The underlying port (to change the baud rate after a vendor command for example).
+    pub fn port_mut(&mut self) -> Option<&mut (dyn SerialPort + 'static)> {
+        self.port.as_deref_mut()
+    }
+    /// Pulses the reset line and drops whatever the controller sent before it came back up.
+    /// Blocks for the reset timing. Does nothing without a reset callback.
+    pub fn hardware_reset(&mut self) -> Result<(), adapter::Error> {
+        let reset = match self.reset.as_mut() {
+            Some(reset) => reset,
+            None => return Ok(()),
+        };
+        reset(true);
+        std::thread::sleep(self.reset_hold);
+        reset(false);
+        std::thread::sleep(self.boot_delay);
+        if let Some(port) = self.port.as_ref() {
+            port.clear(ClearBuffer::All)?;
+        }
+        self.shared.lock().bytes.clear();
+        self.rx.reset();
+        Ok(())
+    }
+    /// What to do after a framing error. [`FramingRecovery::Fail`] by default.
+    pub fn set_framing_recovery(&mut self, recovery: FramingRecovery) {
+        self.rx.set_recovery(recovery);
+    }
+    /// Moves received bytes into `rx`.
+    fn fill(&mut self) -> Result<usize, adapter::Error> {
+        let mut received = self.shared.lock();
+        let unfilled = self.rx.unfilled();
+        let amount = received.bytes.len().min(unfilled.len());
+        if amount == 0 {
+            return received.error.map_or(Ok(0), Err);
+        }
+        unfilled[..amount].copy_from_slice(&received.bytes[..amount]);
+        received.bytes.drain(..amount);
+        self.rx.filled(amount);
+        Ok(amount)
+    }
+}
+impl Drop for SerialTransport {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+    }
+}
+impl HCIReader for SerialTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let this = self.get_mut();
+        loop {
+            match this.rx.read_packet(buf) {
+                Ok(Some(len)) => return Poll::Ready(Ok(len)),
+                Ok(None) => (),
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+            // Register first so bytes arriving between `fill` and returning still wake us.
+            this.shared.waker.register(cx.waker());
+            match this.fill() {
+                Ok(0) => return Poll::Pending,
+                Ok(_) => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+}
+impl HCIWriter for SerialTransport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        match self.get_mut().writer.write(buf) {
+            Ok(amount) => Poll::Ready(Ok(amount)),
+            // The controller is holding CTS. Try again later.
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(e.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), adapter::Error>> {
+        Poll::Ready(self.get_mut().writer.flush().map_err(adapter::Error::from))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::task::noop_waker_ref;
+
+    #[test]
+    fn test_read_packets_from_thread() {
+        let complete = [0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00];
+        let acl = [0x02, 0x40, 0x00, 0x01, 0x00, 0xAA];
+        let bytes = [&complete[..], &acl[..]].concat();
+        let mut transport = SerialTransport::with_io(io::Cursor::new(bytes), Box::new(io::sink()));
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut out = [0_u8; 64];
+        let mut read = |transport: &mut SerialTransport| loop {
+            match Pin::new(&mut *transport).poll_read(&mut cx, &mut out) {
+                Poll::Ready(r) => return r.map(|len| out[..len].to_vec()),
+                Poll::Pending => std::thread::yield_now(),
+            }
+        };
+        assert_eq!(read(&mut transport), Ok(complete.to_vec()));
+        assert_eq!(read(&mut transport), Ok(acl.to_vec()));
+        assert_eq!(read(&mut transport), Err(StreamError::StreamClosed.into()));
+    }
+}
@@ -0,0 +1,297 @@
+//! Full-duplex [`Stream`]s. [`Stream::split`] gives an [`EventReader`] and a [`CommandWriter`]
+//! that are owned separately (and can be moved to different tasks), so events can be read while
+//! a command write is pending. Both halves share the transport behind a lock that is only held
+//! while polling it, never across an `.await`. The transport has to keep its read and write
+//! wakers apart (most do) for both halves to be woken correctly.
+//!
+//! The writer only writes. Command returns arrive on the reader like any other event. With the
+//! `sink` feature it is a `Sink` of [`CommandPacket`]s and [`ACLPacket`]s, so it can be fed by
+//! `Stream`/`Sink` combinators (`forward`, `buffer`, ...).
+use crate::bytes::Storage;
+use crate::hci::acl::ACLPacket;
+use crate::hci::adapter;
+use crate::hci::command::{Command, CommandPacket};
+use crate::hci::event::{EventPacket, StaticHCIBuffer};
+use crate::hci::packet::RawPacket;
+use crate::hci::stream::{HCIReader, HCIWriter, Stream};
+use crate::hci::StreamError;
+use crate::sync::Mutex;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_util::future::poll_fn;
+use futures_util::ready;
+#[cfg(feature = "sink")]
+use futures_util::sink::Sink;
+use std::sync::Arc;
+
+#[derive(Debug)]
+struct Shared<B> {
+    stream: Mutex<Pin<B>>,
+}
+/// Read half of a split [`Stream`].
+#[derive(Debug)]
+pub struct EventReader<B> {
+    shared: Arc<Shared<B>>,
+}
+/// Write half of a split [`Stream`].
+#[derive(Debug)]
+pub struct CommandWriter<B> {
+    shared: Arc<Shared<B>>,
+    /// Packet given to `Sink::start_send` and how much of it is written.
+    pending: Option<(Box<[u8]>, usize)>,
+}
+impl<S: HCIReader + HCIWriter, B: Deref<Target = S> + DerefMut> Stream<S, B> {
+    pub fn split(self) -> (EventReader<B>, CommandWriter<B>) {
+        let shared = Arc::new(Shared {
+            stream: Mutex::new(self.stream),
+        });
+        (
+            EventReader {
+                shared: shared.clone(),
+            },
+            CommandWriter {
+                shared,
+                pending: None,
+            },
+        )
+    }
+}
+/// Puts the halves of the same [`Stream`] back together. Gives them back if they came from
+/// different streams.
+pub fn reunite<S: HCIReader, B: Deref<Target = S> + DerefMut>(
+    reader: EventReader<B>,
+    writer: CommandWriter<B>,
+) -> Result<Stream<S, B>, (EventReader<B>, CommandWriter<B>)> {
+    if !Arc::ptr_eq(&reader.shared, &writer.shared) {
+        return Err((reader, writer));
+    }
+    drop(writer);
+    let shared =
+        Arc::try_unwrap(reader.shared).unwrap_or_else(|_| unreachable!("both halves were given"));
+    Ok(Stream::new(shared.stream.into_inner()))
+}
+impl<S: HCIReader, B: Deref<Target = S> + DerefMut> EventReader<B> {
+    fn stream(&mut self) -> Stream<Self, &mut Self> {
+        Stream::new(Pin::new(self))
+    }
+    /// See [`Stream::read_packet`].
+    pub async fn read_packet<Buf: Storage<u8>>(
+        &mut self,
+    ) -> Result<RawPacket<Buf>, adapter::Error> {
+        self.stream().read_packet().await
+    }
+    /// See [`Stream::read_event`].
+    pub async fn read_event<Buf: Storage<u8>>(
+        &mut self,
+    ) -> Result<EventPacket<Buf>, adapter::Error> {
+        self.stream().read_event().await
+    }
+}
+impl<S: HCIReader, B: Deref<Target = S> + DerefMut> HCIReader for EventReader<B> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        self.shared
+            .stream
+            .lock(|stream| stream.as_mut().poll_read(cx, buf))
+    }
+}
+impl<S: HCIWriter, B: Deref<Target = S> + DerefMut> CommandWriter<B> {
+    pub async fn send_exact(&mut self, mut buf: &[u8]) -> Result<(), adapter::Error> {
+        while !buf.is_empty() {
+            let amount = poll_fn(|cx| Pin::new(&mut *self).poll_write(cx, buf)).await?;
+            buf = &buf[amount..];
+        }
+        poll_fn(|cx| HCIWriter::poll_flush(Pin::new(&mut *self), cx)).await
+    }
+    pub async fn send_command_packet(
+        &mut self,
+        packet: CommandPacket<&[u8]>,
+    ) -> Result<(), adapter::Error> {
+        let out = packet
+            .pack_as_raw_packet::<StaticHCIBuffer>()
+            .map_err(StreamError::CommandError)?;
+        self.send_exact(out.as_ref()).await
+    }
+    /// Writes `command` without waiting for its return (read it from the [`EventReader`]).
+    pub async fn send_command<Cmd: Command>(&mut self, command: Cmd) -> Result<(), adapter::Error> {
+        let packet = command
+            .pack_command_packet::<StaticHCIBuffer>()
+            .map_err(StreamError::CommandError)?;
+        self.send_command_packet(packet.as_ref()).await
+    }
+    pub async fn send_acl(&mut self, packet: ACLPacket<&[u8]>) -> Result<(), adapter::Error> {
+        let out = packet
+            .pack_as_raw_packet::<StaticHCIBuffer>()
+            .map_err(StreamError::DataError)?;
+        self.send_exact(out.as_ref()).await
+    }
+    /// Writes out the packet queued by `Sink::start_send`.
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), adapter::Error>> {
+        while let Some((packet, sent)) = self.pending.as_mut() {
+            if *sent >= packet.len() {
+                self.pending = None;
+                break;
+            }
+            let amount = ready!(self
+                .shared
+                .stream
+                .lock(|stream| stream.as_mut().poll_write(cx, &packet.as_ref()[*sent..])))?;
+            *sent += amount;
+        }
+        Poll::Ready(Ok(()))
+    }
+    #[cfg(feature = "sink")]
+    fn poll_flush_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), adapter::Error>> {
+        ready!(self.poll_pending(cx))?;
+        self.shared
+            .stream
+            .lock(|stream| stream.as_mut().poll_flush(cx))
+    }
+}
+#[cfg(feature = "sink")]
+impl<S: HCIWriter, B: Deref<Target = S> + DerefMut, Buf: AsRef<[u8]>> Sink<CommandPacket<Buf>>
+    for CommandWriter<B>
+{
+    type Error = adapter::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, packet: CommandPacket<Buf>) -> Result<(), Self::Error> {
+        let out = packet
+            .pack_as_raw_packet::<Box<[u8]>>()
+            .map_err(StreamError::CommandError)?;
+        self.get_mut().pending = Some((out, 0));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_flush_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_flush_pending(cx)
+    }
+}
+#[cfg(feature = "sink")]
+impl<S: HCIWriter, B: Deref<Target = S> + DerefMut, Buf: AsRef<[u8]>> Sink<ACLPacket<Buf>>
+    for CommandWriter<B>
+{
+    type Error = adapter::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, packet: ACLPacket<Buf>) -> Result<(), Self::Error> {
+        let out = packet
+            .pack_as_raw_packet::<Box<[u8]>>()
+            .map_err(StreamError::DataError)?;
+        self.get_mut().pending = Some((out, 0));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_flush_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_flush_pending(cx)
+    }
+}
+impl<S: HCIWriter, B: Deref<Target = S> + DerefMut> HCIWriter for CommandWriter<B> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        // A packet from the `Sink` goes out whole first.
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        this.shared
+            .stream
+            .lock(|stream| stream.as_mut().poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), adapter::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        this.shared
+            .stream
+            .lock(|stream| stream.as_mut().poll_flush(cx))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::baseband::Reset;
+    use crate::testing::mock::{command_complete, MockTransport};
+    use futures_util::task::noop_waker_ref;
+    use futures_util::FutureExt;
+
+    #[test]
+    fn test_read_while_writing() {
+        let (transport, handle) = MockTransport::new();
+        let complete = command_complete(Reset::opcode(), &[0x00]);
+        handle.expect_command(&Reset {}, &[&complete]);
+        let (mut reader, mut writer) = Stream::new(Box::pin(transport)).split();
+
+        let mut read = Box::pin(reader.read_event::<Box<[u8]>>());
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert!(read.poll_unpin(&mut cx).is_pending());
+        writer
+            .send_command(Reset {})
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let event = match read.poll_unpin(&mut cx) {
+            Poll::Ready(event) => event.unwrap(),
+            Poll::Pending => panic!("return not read"),
+        };
+        assert_eq!(event.parameters.as_ref(), &complete[3..]);
+        drop(read);
+        handle.assert_done();
+        assert!(reunite(reader, writer).is_ok());
+    }
+    #[cfg(feature = "sink")]
+    #[test]
+    fn test_sink_forward() {
+        use crate::hci::acl::{ACLPacket, PacketBoundary};
+        use crate::hci::baseband::{EventMask, SetEventMask};
+        use crate::le::connection::ConnectionHandle;
+        use futures_util::{stream, SinkExt, StreamExt};
+
+        let (transport, handle) = MockTransport::new();
+        let (_reader, mut writer) = Stream::new(Box::pin(transport)).split();
+        let commands = [
+            Reset {}.pack_command_packet::<Box<[u8]>>().unwrap(),
+            SetEventMask(EventMask::default())
+                .pack_command_packet::<Box<[u8]>>()
+                .unwrap(),
+        ];
+        let mut expected = Vec::new();
+        for command in &commands {
+            expected
+                .extend_from_slice(&command.as_ref().pack_as_raw_packet::<Box<[u8]>>().unwrap());
+        }
+        stream::iter(commands)
+            .map(Ok)
+            .forward(&mut writer)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let acl = ACLPacket::new(
+            ConnectionHandle::new(0x0001),
+            PacketBoundary::FirstNonFlushable,
+            &[0xAA, 0xBB][..],
+        );
+        writer.send(acl).now_or_never().unwrap().unwrap();
+        expected.extend_from_slice(&[0x02, 0x01, 0x00, 0x02, 0x00, 0xAA, 0xBB]);
+        assert_eq!(handle.take_written(), expected);
+    }
+}
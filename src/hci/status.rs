@@ -0,0 +1,338 @@
+//! HCI Status Parameters commands (controller clock, link quality, etc).
+use crate::hci::command::Command;
+use crate::hci::event::{CommandComplete, ReturnParameters};
+use crate::hci::{ErrorCode, Opcode, OCF, OGF};
+use crate::le::connection::ConnectionHandle;
+use crate::{ConversionError, PackError};
+use core::convert::{TryFrom, TryInto};
+use core::time::Duration;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+#[repr(u16)]
+pub enum StatusParametersOpcode {
+    ReadFailedContactCounter = 0x0001,
+    ResetFailedContactCounter = 0x0002,
+    ReadLinkQuality = 0x0003,
+    ReadRSSI = 0x0005,
+    ReadAFHChannelMap = 0x0006,
+    ReadClock = 0x0007,
+    ReadEncryptionKeySize = 0x0008,
+}
+impl StatusParametersOpcode {
+    pub const fn ogf() -> OGF {
+        OGF::StatusParameters
+    }
+}
+impl TryFrom<OCF> for StatusParametersOpcode {
+    type Error = ConversionError;
+
+    fn try_from(ocf: OCF) -> Result<Self, Self::Error> {
+        match u16::from(ocf) {
+            0x0001 => Ok(StatusParametersOpcode::ReadFailedContactCounter),
+            0x0002 => Ok(StatusParametersOpcode::ResetFailedContactCounter),
+            0x0003 => Ok(StatusParametersOpcode::ReadLinkQuality),
+            0x0005 => Ok(StatusParametersOpcode::ReadRSSI),
+            0x0006 => Ok(StatusParametersOpcode::ReadAFHChannelMap),
+            0x0007 => Ok(StatusParametersOpcode::ReadClock),
+            0x0008 => Ok(StatusParametersOpcode::ReadEncryptionKeySize),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+impl From<StatusParametersOpcode> for OCF {
+    fn from(opcode: StatusParametersOpcode) -> Self {
+        OCF::new(opcode as u16)
+    }
+}
+impl From<StatusParametersOpcode> for Opcode {
+    fn from(opcode: StatusParametersOpcode) -> Self {
+        Opcode(OGF::StatusParameters, opcode.into())
+    }
+}
+/// Which clock [`ReadClock`] should return.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+#[repr(u8)]
+pub enum WhichClock {
+    /// The local controller clock. `connection_handle` is ignored.
+    Local = 0x00,
+    /// The piconet clock of the connection.
+    Piconet = 0x01,
+}
+impl WhichClock {
+    pub const BYTE_LEN: usize = 1;
+}
+impl From<WhichClock> for u8 {
+    fn from(w: WhichClock) -> Self {
+        w as u8
+    }
+}
+impl TryFrom<u8> for WhichClock {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(WhichClock::Local),
+            0x01 => Ok(WhichClock::Piconet),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// 28-bit Bluetooth controller clock. Each tick is half a slot (312.5 microseconds) and the clock
+/// wraps around every `2^28` ticks (~23.3 hours).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct ControllerClock(u32);
+impl ControllerClock {
+    pub const BYTE_LEN: usize = 4;
+    pub const MASK: u32 = (1 << 28) - 1;
+    /// Nanoseconds per clock tick (312.5 us).
+    pub const TICK_NANOS: u64 = 312_500;
+    /// Creates a new `ControllerClock` by masking `ticks` to 28 bits.
+    pub const fn new_masked(ticks: u32) -> ControllerClock {
+        ControllerClock(ticks & Self::MASK)
+    }
+    pub const fn ticks(self) -> u32 {
+        self.0
+    }
+    /// Number of ticks from `earlier` to `self`, accounting for one wrap around.
+    pub fn ticks_since(self, earlier: ControllerClock) -> u32 {
+        self.0.wrapping_sub(earlier.0) & Self::MASK
+    }
+    /// Time from `earlier` to `self`, accounting for one wrap around.
+    pub fn duration_since(self, earlier: ControllerClock) -> Duration {
+        Duration::from_nanos(u64::from(self.ticks_since(earlier)) * Self::TICK_NANOS)
+    }
+}
+impl From<ControllerClock> for u32 {
+    fn from(c: ControllerClock) -> Self {
+        c.0
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ReadClock {
+    pub connection_handle: ConnectionHandle,
+    pub which_clock: WhichClock,
+}
+impl ReadClock {
+    pub const BYTE_LEN: usize = ConnectionHandle::BYTE_LEN + WhichClock::BYTE_LEN;
+    pub const OPCODE: StatusParametersOpcode = StatusParametersOpcode::ReadClock;
+    /// Read the local controller clock.
+    pub fn local() -> ReadClock {
+        ReadClock {
+            connection_handle: ConnectionHandle::MIN,
+            which_clock: WhichClock::Local,
+        }
+    }
+}
+impl Command for ReadClock {
+    type Return = CommandComplete<ReadClockReturn>;
+
+    fn opcode() -> Opcode {
+        Self::OPCODE.into()
+    }
+
+    fn byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf[0..2].copy_from_slice(&u16::from(self.connection_handle).to_le_bytes()[..]);
+        buf[2] = self.which_clock.into();
+        Ok(())
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        let connection_handle = ConnectionHandle::new_checked(u16::from_le_bytes(
+            (&buf[0..2]).try_into().expect("len checked above"),
+        ))
+        .ok_or_else(|| PackError::bad_index(0))?;
+        let which_clock = WhichClock::try_from(buf[2]).map_err(|_| PackError::bad_index(2))?;
+        Ok(ReadClock {
+            connection_handle,
+            which_clock,
+        })
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ReadClockReturn {
+    pub status: ErrorCode,
+    pub connection_handle: ConnectionHandle,
+    pub clock: ControllerClock,
+    /// Maximum error of `clock` in clock ticks (`0xFFFF` means unknown). Always `0` for the
+    /// local clock.
+    pub accuracy: u16,
+}
+impl ReadClockReturn {
+    pub const BYTE_LEN: usize =
+        ErrorCode::BYTE_LEN + ConnectionHandle::BYTE_LEN + ControllerClock::BYTE_LEN + 2;
+}
+impl ReturnParameters for ReadClockReturn {
+    fn byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf[0] = self.status.into();
+        buf[1..3].copy_from_slice(&u16::from(self.connection_handle).to_le_bytes()[..]);
+        buf[3..7].copy_from_slice(&u32::from(self.clock).to_le_bytes()[..]);
+        buf[7..9].copy_from_slice(&self.accuracy.to_le_bytes()[..]);
+        Ok(())
+    }
+
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        let status = ErrorCode::try_from(buf[0]).map_err(|_| PackError::bad_index(0))?;
+        let connection_handle = ConnectionHandle::new_checked(u16::from_le_bytes(
+            (&buf[1..3]).try_into().expect("len checked above"),
+        ))
+        .ok_or_else(|| PackError::bad_index(1))?;
+        let clock = ControllerClock::new_masked(u32::from_le_bytes(
+            (&buf[3..7]).try_into().expect("len checked above"),
+        ));
+        let accuracy = u16::from_le_bytes((&buf[7..9]).try_into().expect("len checked above"));
+        Ok(ReadClockReturn {
+            status,
+            connection_handle,
+            clock,
+            accuracy,
+        })
+    }
+}
+/// Maps a [`ControllerClock`] onto host monotonic time. Host time is any `Duration` since an
+/// arbitrary (but fixed) epoch, for example `Instant::now() - start`.
+///
+/// The mapping is anchored on one `(clock, host)` sample pair. Feeding later samples through
+/// [`ClockMapping::update`] estimates the drift between the two clocks so long running sessions
+/// stay aligned. Only one controller clock wrap (~23.3 hours) between the anchor and the mapped
+/// clock is accounted for, so re-anchor with [`ClockMapping::new`] periodically.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ClockMapping {
+    anchor_clock: ControllerClock,
+    anchor_host: Duration,
+    /// Host nanoseconds gained (or lost if negative) per second of controller time, in parts per
+    /// billion.
+    drift_ppb: i64,
+}
+impl ClockMapping {
+    /// Largest drift [`ClockMapping::update`] estimates (1000 ppm). Crystals are specified to a
+    /// few dozen ppm, anything above is a bad sample.
+    pub const MAX_DRIFT_PPB: i64 = 1_000_000;
+    pub fn new(clock: ControllerClock, host: Duration) -> ClockMapping {
+        ClockMapping {
+            anchor_clock: clock,
+            anchor_host: host,
+            drift_ppb: 0,
+        }
+    }
+    pub fn anchor(&self) -> (ControllerClock, Duration) {
+        (self.anchor_clock, self.anchor_host)
+    }
+    /// Estimated drift of the host clock relative to the controller clock in parts per billion.
+    pub fn drift_ppb(&self) -> i64 {
+        self.drift_ppb
+    }
+    /// Re-estimate the drift from a new `(clock, host)` sample pair. The anchor is kept. Samples
+    /// taken before the host or controller clock moved past the anchor are ignored and the drift
+    /// is clamped to [`ClockMapping::MAX_DRIFT_PPB`].
+    pub fn update(&mut self, clock: ControllerClock, host: Duration) {
+        let controller_nanos = Self::nanos(clock.duration_since(self.anchor_clock));
+        let host_nanos = match host.checked_sub(self.anchor_host) {
+            Some(host_nanos) if controller_nanos != 0 && host_nanos.as_nanos() != 0 => {
+                Self::nanos(host_nanos)
+            }
+            _ => return,
+        };
+        let error = i128::from(host_nanos) - i128::from(controller_nanos);
+        let drift = (error * 1_000_000_000 / i128::from(controller_nanos)).clamp(
+            -i128::from(Self::MAX_DRIFT_PPB),
+            i128::from(Self::MAX_DRIFT_PPB),
+        );
+        self.drift_ppb = i64::try_from(drift).unwrap_or(0);
+    }
+    /// Maps a controller clock value onto host time.
+    pub fn to_host(&self, clock: ControllerClock) -> Duration {
+        let controller_nanos = Self::nanos(clock.duration_since(self.anchor_clock));
+        let corrected = i128::from(controller_nanos)
+            + i128::from(controller_nanos) * i128::from(self.drift_ppb) / 1_000_000_000;
+        let host = i128::from(Self::nanos(self.anchor_host)) + corrected;
+        Duration::from_nanos(u64::try_from(host.max(0)).unwrap_or(u64::MAX))
+    }
+    /// Maps host time onto the controller clock. Returns `None` if `host` is before the anchor.
+    pub fn to_controller(&self, host: Duration) -> Option<ControllerClock> {
+        let host_nanos = host.checked_sub(self.anchor_host)?;
+        let host_nanos = i128::from(Self::nanos(host_nanos));
+        let controller_nanos =
+            host_nanos * 1_000_000_000 / (1_000_000_000 + i128::from(self.drift_ppb));
+        let ticks = controller_nanos / i128::from(ControllerClock::TICK_NANOS)
+            % (i128::from(ControllerClock::MASK) + 1);
+        let ticks = u32::try_from(ticks).ok()?;
+        Some(ControllerClock::new_masked(
+            self.anchor_clock.ticks().wrapping_add(ticks),
+        ))
+    }
+    fn nanos(d: Duration) -> i64 {
+        i64::try_from(d.as_nanos()).unwrap_or(i64::MAX)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_clock() {
+        let read = ReadClock {
+            connection_handle: ConnectionHandle::new_checked(0x0040).unwrap(),
+            which_clock: WhichClock::Piconet,
+        };
+        let mut buf = [0_u8; ReadClock::BYTE_LEN];
+        read.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x40, 0x00, 0x01]);
+        assert_eq!(ReadClock::unpack_from(&buf), Ok(read));
+
+        let ret = ReadClockReturn {
+            status: ErrorCode::Ok,
+            connection_handle: read.connection_handle,
+            clock: ControllerClock::new_masked(0x0123_4567),
+            accuracy: 0xFFFF,
+        };
+        let mut buf = [0_u8; ReadClockReturn::BYTE_LEN];
+        ret.pack_into(&mut buf).unwrap();
+        assert_eq!(ReadClockReturn::unpack_from(&buf), Ok(ret));
+    }
+    #[test]
+    fn test_clock_mapping() {
+        let anchor = ControllerClock::new_masked(ControllerClock::MASK - 10);
+        let mut mapping = ClockMapping::new(anchor, Duration::from_secs(100));
+        // 3200 ticks are one second. The host clock runs 100 ppm fast.
+        let clock = ControllerClock::new_masked(anchor.ticks().wrapping_add(3200 * 10));
+        mapping.update(
+            clock,
+            Duration::from_secs(100) + Duration::from_micros(10_001_000),
+        );
+        assert_eq!(mapping.drift_ppb(), 100_000);
+        let host = mapping.to_host(clock);
+        assert_eq!(host, Duration::from_micros(110_001_000));
+        assert_eq!(mapping.to_controller(host), Some(clock));
+        assert_eq!(mapping.to_controller(Duration::from_secs(99)), None);
+
+        // Samples without host time passing are ignored instead of dividing by zero later.
+        let before = mapping;
+        mapping.update(clock, Duration::from_secs(100));
+        mapping.update(clock, Duration::from_secs(50));
+        mapping.update(anchor, Duration::from_secs(200));
+        assert_eq!(mapping, before);
+        // Absurd samples are clamped.
+        mapping.update(clock, Duration::from_secs(100) + Duration::from_nanos(1));
+        assert_eq!(mapping.drift_ppb(), -ClockMapping::MAX_DRIFT_PPB);
+        let host = mapping.to_host(clock);
+        assert_eq!(mapping.to_controller(host), Some(clock));
+    }
+}
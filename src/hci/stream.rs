@@ -1,11 +1,14 @@
 //! HCI Stream. Abstracts over byte read/write functions to allow for reading events and writting
 //! commands.
-use crate::bytes::Storage;
+use crate::bytes::{HexDump, Storage};
 use crate::error;
+use crate::hci::acl::{ACLPacket, IncomingPacket};
 use crate::hci::command::CommandPacket;
 use crate::hci::event::{EventCode, EventPacket, StaticHCIBuffer, MAX_HCI_PACKET_SIZE};
+use crate::hci::iso::ISOPacket;
 use crate::hci::packet::{PacketType, RawPacket};
-use crate::hci::{adapter, Opcode, StreamError};
+use crate::hci::sco::SCOPacket;
+use crate::hci::{adapter, MalformedPacket, Opcode, StreamError};
 use crate::PackError;
 use core::convert::{TryFrom, TryInto};
 use core::ops::Deref;
@@ -20,7 +23,29 @@ impl From<PackError> for StreamError {
         StreamError::CommandError(e)
     }
 }
-impl error::Error for StreamError {}
+impl error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            StreamError::EventError(e)
+            | StreamError::CommandError(e)
+            | StreamError::DataError(e)
+            | StreamError::Malformed(MalformedPacket { error: e, .. }) => Some(e),
+            _ => None,
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl std::error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamError::EventError(e)
+            | StreamError::CommandError(e)
+            | StreamError::DataError(e)
+            | StreamError::Malformed(MalformedPacket { error: e, .. }) => Some(e),
+            _ => None,
+        }
+    }
+}
 /// HCI Filter. Sets what kind of HCI Packets and HCI Events are received by the HCI Stream.\
 /// Designed around the BlueZ socket filter so this type may change in the future be more
 /// platform agnostic.
@@ -113,6 +138,44 @@ impl Filter {
     pub fn opcode_mut(&mut self) -> &mut Opcode {
         &mut self.opcode
     }
+    /// Lets every packet through.
+    pub fn pass_all() -> Filter {
+        Filter {
+            type_mask: u32::MAX,
+            event_mask: [u32::MAX, u32::MAX],
+            opcode: Opcode::nop(),
+        }
+    }
+    /// Whether the H4 framed `packet` passes the filter, the same way the BlueZ kernel filter
+    /// decides. For transports without a kernel filter.
+    pub fn matches(&self, packet: &[u8]) -> bool {
+        let indicator = match packet.first() {
+            Some(&indicator) => indicator,
+            None => return false,
+        };
+        if self.type_mask & (1_u32 << (indicator & 0x1F)) == 0 {
+            return false;
+        }
+        if indicator != u8::from(PacketType::Event) {
+            return true;
+        }
+        let code = match packet.get(1) {
+            Some(&code) => code & 0x3F,
+            None => return false,
+        };
+        if self.event_mask[usize::from(code / 32)] & (1_u32 << (code % 32)) == 0 {
+            return false;
+        }
+        let opcode = match EventCode::try_from(code) {
+            _ if self.opcode == Opcode::nop() => return true,
+            Ok(EventCode::CommandComplete) => packet.get(4..6),
+            Ok(EventCode::CommandStatus) => packet.get(5..7),
+            _ => return true,
+        };
+        opcode.map_or(false, |opcode| {
+            Opcode::unpack(opcode).ok() == Some(self.opcode)
+        })
+    }
 }
 /// Set IOCTL HCI filter. See [`Filter`] for more.
 pub trait HCIFilterable {
@@ -133,6 +196,11 @@ pub trait HCIWriter {
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), adapter::Error>>;
 }
 /// Asynchronous HCI byte stream reader.
+///
+/// # Cancel safety
+/// Bytes of a packet that hasn't fully arrived stay buffered in the reader, never in the caller's
+/// future. A read dropped half way (a command timeout, a `select!`) loses nothing and the next read
+/// continues the packet. The H4 transports only hand out (and consume) complete packets.
 pub trait HCIReader: Unpin {
     /// Read some bytes into `buf`. Mirrors an `AsyncRead` trait. Returns `Ok(usize)` with actual
     /// bytes read.
@@ -160,6 +228,7 @@ impl<S: HCIReader, B: Deref<Target = S> + DerefMut> Stream<S, B> {
     where
         S: HCIWriter,
     {
+        hci_trace!("hci tx [{}] {}", buf.len(), HexDump::new(buf));
         while !buf.is_empty() {
             let amount = poll_fn(|cx| self.stream_pinned().poll_write(cx, buf)).await?;
             buf = &buf[amount..];
@@ -169,15 +238,76 @@ impl<S: HCIReader, B: Deref<Target = S> + DerefMut> Stream<S, B> {
     pub async fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, adapter::Error> {
         poll_fn(|cx| self.stream_pinned().poll_read(cx, buf)).await
     }
+    /// Reads one full H4 packet (packet type byte first). Every read of the underlying stream
+    /// has to return exactly one packet (like BlueZ sockets do).
+    pub async fn read_packet<Buf: Storage<u8>>(
+        &mut self,
+    ) -> Result<RawPacket<Buf>, adapter::Error> {
+        let mut packet_buf = StaticHCIBuffer::with_size(MAX_HCI_PACKET_SIZE);
+        let packet = self.read_packet_into(packet_buf.as_mut()).await?;
+        Ok(packet.clone_buf())
+    }
+    /// Like [`Stream::read_packet`] but reads into the caller's `buf` (which should hold
+    /// [`MAX_HCI_PACKET_SIZE`] bytes) instead of allocating.
+    pub async fn read_packet_into<'b>(
+        &mut self,
+        buf: &'b mut [u8],
+    ) -> Result<RawPacket<&'b [u8]>, adapter::Error> {
+        let len = self.read_bytes(buf).await?;
+        hci_trace!("hci rx [{}] {}", len, HexDump::new(&buf[..len]));
+        RawPacket::try_from(&buf[..len]).map_err(|_| {
+            hci_warn!("hci rx bad packet indicator {:?}", buf.first());
+            StreamError::BadPacketCode.into()
+        })
+    }
+    /// Reads the next event packet. Other packet types (ACL/SCO/ISO data, which arrive
+    /// unfiltered on a BlueZ user channel) are skipped. Cancel safe (see [`HCIReader`]).
     pub async fn read_event<Buf: Storage<u8>>(
         &mut self,
     ) -> Result<EventPacket<Buf>, adapter::Error> {
-        let mut event_buf = StaticHCIBuffer::with_size(MAX_HCI_PACKET_SIZE);
-        let len = self.read_bytes(event_buf.as_mut()).await?;
-        let packet = RawPacket::try_from(&event_buf.as_ref()[..len])
-            .map_err(|_| StreamError::BadPacketCode)?;
-        let event_packet = EventPacket::try_from(packet).map_err(StreamError::EventError)?;
-        Ok(event_packet.to_new_storage())
+        let mut packet_buf = StaticHCIBuffer::with_size(MAX_HCI_PACKET_SIZE);
+        let event = self.read_event_into(packet_buf.as_mut()).await?;
+        Ok(event.to_new_storage())
+    }
+    /// Like [`Stream::read_event`] but the returned event borrows the caller's `buf` (which
+    /// should hold [`MAX_HCI_PACKET_SIZE`] bytes), so reading events needs no allocation.
+    pub async fn read_event_into<'b>(
+        &mut self,
+        buf: &'b mut [u8],
+    ) -> Result<EventPacket<&'b [u8]>, adapter::Error> {
+        for _ in 0..HCI_EVENT_READ_TRIES {
+            let len = self.read_bytes(buf).await?;
+            hci_trace!("hci rx [{}] {}", len, HexDump::new(&buf[..len]));
+            let packet_type = RawPacket::try_from(&buf[..len])
+                .map_err(|_| {
+                    hci_warn!("hci rx bad packet indicator {:?}", buf.first());
+                    StreamError::BadPacketCode
+                })?
+                .packet_type;
+            if packet_type == PacketType::Event {
+                // Parsed again so the returned packet borrows `buf` only on this path.
+                let packet =
+                    RawPacket::try_from(&buf[..len]).map_err(|_| StreamError::BadPacketCode)?;
+                return EventPacket::try_from(packet).map_err(|e| {
+                    let malformed = MalformedPacket::new(e, &buf[..len]);
+                    hci_warn!("hci rx bad event {}", malformed);
+                    StreamError::Malformed(malformed).into()
+                });
+            }
+        }
+        hci_warn!("no event in {} packets", HCI_EVENT_READ_TRIES);
+        Err(StreamError::StreamFailed.into())
+    }
+    /// Sets the transport's packet filter. See [`HCIFilterable`].
+    pub fn set_filter(&mut self, filter: &Filter) -> Result<(), adapter::Error>
+    where
+        S: HCIFilterable,
+    {
+        hci_debug!("hci filter {:?}", filter);
+        self.stream_pinned().set_filter(filter).map_err(|e| {
+            hci_warn!("setting hci filter failed: {:?}", e);
+            e
+        })
     }
     pub async fn send_command_packet(
         &mut self,
@@ -186,9 +316,62 @@ impl<S: HCIReader, B: Deref<Target = S> + DerefMut> Stream<S, B> {
     where
         S: HCIWriter,
     {
-        let out = packet.pack_as_raw_packet::<StaticHCIBuffer>();
+        let out = packet
+            .pack_as_raw_packet::<StaticHCIBuffer>()
+            .map_err(StreamError::CommandError)?;
+        self.send_exact(out.as_ref()).await
+    }
+    pub async fn send_acl(&mut self, packet: ACLPacket<&[u8]>) -> Result<(), adapter::Error>
+    where
+        S: HCIWriter,
+    {
+        let out = packet
+            .pack_as_raw_packet::<StaticHCIBuffer>()
+            .map_err(StreamError::DataError)?;
         self.send_exact(out.as_ref()).await
     }
+    pub async fn send_sco(&mut self, packet: SCOPacket<&[u8]>) -> Result<(), adapter::Error>
+    where
+        S: HCIWriter,
+    {
+        let out = packet
+            .pack_as_raw_packet::<StaticHCIBuffer>()
+            .map_err(StreamError::DataError)?;
+        self.send_exact(out.as_ref()).await
+    }
+    /// Reads the next event, ACL, ISO or SCO data packet. Data packets longer than
+    /// [`MAX_HCI_PACKET_SIZE`] don't fit the read buffer.
+    pub async fn read_incoming<Buf: Storage<u8>>(
+        &mut self,
+    ) -> Result<IncomingPacket<Buf>, adapter::Error> {
+        for _ in 0..HCI_EVENT_READ_TRIES {
+            let packet: RawPacket<StaticHCIBuffer> = self.read_packet().await?;
+            match packet.packet_type {
+                PacketType::Event => {
+                    let event =
+                        EventPacket::try_from(packet.as_ref()).map_err(StreamError::EventError)?;
+                    return Ok(IncomingPacket::Event(event.to_new_storage()));
+                }
+                PacketType::ACLData => {
+                    let acl =
+                        ACLPacket::try_from(packet.as_ref()).map_err(StreamError::DataError)?;
+                    return Ok(IncomingPacket::ACL(acl.to_new_storage()));
+                }
+                PacketType::ISOData => {
+                    let iso =
+                        ISOPacket::try_from(packet.as_ref()).map_err(StreamError::DataError)?;
+                    return Ok(IncomingPacket::ISO(iso.to_new_storage()));
+                }
+                PacketType::SCOData => {
+                    let sco =
+                        SCOPacket::try_from(packet.as_ref()).map_err(StreamError::DataError)?;
+                    return Ok(IncomingPacket::SCO(sco.to_new_storage()));
+                }
+                _ => {}
+            }
+        }
+        Err(StreamError::StreamFailed.into())
+    }
 }
 impl<S: HCIWriter + HCIReader, B: Deref<Target = S> + DerefMut> adapter::Adapter for Stream<S, B> {
     fn write_command<'s, 'p: 's>(
@@ -203,4 +386,71 @@ impl<S: HCIWriter + HCIReader, B: Deref<Target = S> + DerefMut> adapter::Adapter
     ) -> LocalBoxFuture<'s, Result<EventPacket<Buf>, adapter::Error>> {
         Box::pin(self.read_event())
     }
+
+    fn write_acl<'s, 'p: 's>(
+        &'s mut self,
+        packet: ACLPacket<&'p [u8]>,
+    ) -> LocalBoxFuture<'s, Result<(), adapter::Error>> {
+        Box::pin(self.send_acl(packet))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock::MockTransport;
+    use futures_util::FutureExt;
+
+    #[test]
+    fn test_read_event_into() {
+        let (transport, handle) = MockTransport::new();
+        let mut stream = Stream::new(Box::pin(transport));
+        // ACL data is skipped.
+        handle.inject(&[0x02, 0x40, 0x20, 0x01, 0x00, 0xAA]);
+        handle.inject_event(EventCode::HardwareError, &[0x03]);
+        let mut buf = [0_u8; MAX_HCI_PACKET_SIZE];
+        let event = stream
+            .read_event_into(&mut buf)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.event_code, EventCode::HardwareError);
+        assert_eq!(event.parameters, &[0x03]);
+    }
+    #[test]
+    fn test_read_packets_on_user_channel() {
+        let (transport, handle) = MockTransport::new();
+        let mut stream = Stream::new(Box::pin(transport));
+        let acl = [0x02, 0x40, 0x20, 0x01, 0x00, 0xAA];
+        handle.inject(&acl);
+        let packet: RawPacket<Box<[u8]>> = stream.read_packet().now_or_never().unwrap().unwrap();
+        assert_eq!(packet.packet_type, PacketType::ACLData);
+        assert_eq!(&packet.buf[..], &acl[1..]);
+        handle.inject(&[0x17, 0x00]);
+        assert_eq!(
+            stream
+                .read_packet::<Box<[u8]>>()
+                .now_or_never()
+                .unwrap()
+                .map(|_| ()),
+            Err(StreamError::BadPacketCode.into())
+        );
+        // SCO and ISO data are skipped too.
+        handle.inject(&[0x03, 0x40, 0x00, 0x01, 0xAA]);
+        handle.inject(&[0x05, 0x40, 0x00, 0x01, 0x00, 0xAA]);
+        handle.inject_event(EventCode::HardwareError, &[0x03]);
+        let event: EventPacket<Box<[u8]>> = stream.read_event().now_or_never().unwrap().unwrap();
+        assert_eq!(event.event_code, EventCode::HardwareError);
+        // Gives up on a stream of data only.
+        for _ in 0..HCI_EVENT_READ_TRIES {
+            handle.inject(&acl);
+        }
+        assert_eq!(
+            stream
+                .read_event::<Box<[u8]>>()
+                .now_or_never()
+                .unwrap()
+                .map(|_| ()),
+            Err(StreamError::StreamFailed.into())
+        );
+    }
 }
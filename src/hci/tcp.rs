@@ -0,0 +1,188 @@
+//! H4 over TCP for emulated and virtual controllers (Zephyr `bt-virtual`/QEMU serial sockets,
+//! Bumble's `tcp-server` transport, Android's root canal). Enabled with the `tcp` feature.
+//! [`TcpTransport`] reassembles the byte stream so each [`HCIReader::poll_read`] returns one whole
+//! packet and filters packets in software since there is no kernel filter. Any tokio stream works
+//! (a `UnixStream` to a local socket for example).
+use crate::error::IOError;
+use crate::hci::adapter;
+use crate::hci::device_id::DeviceId;
+use crate::hci::packet::{FramingRecovery, H4Receiver};
+use crate::hci::stream::{Filter, HCIFilterable, HCIReader, HCIWriter, Stream};
+use crate::hci::{StreamError, MAX_FRAME_SIZE};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+/// HCI [`Stream`] over a [`TcpTransport`].
+pub type TcpStreamTransport = Stream<TcpTransport, Box<TcpTransport>>;
+/// H4 framing over `S`. See the module docs.
+#[derive(Debug)]
+pub struct TcpTransport<S = TcpStream> {
+    stream: S,
+    rx: H4Receiver<Box<[u8]>>,
+    filter: Option<Filter>,
+}
+impl TcpTransport {
+    /// Connects to a virtual controller listening on `addr`.
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<TcpTransport, IOError> {
+        let stream = TcpStream::connect(addr).await?;
+        // Commands are small and latency matters more than throughput.
+        stream.set_nodelay(true)?;
+        Ok(TcpTransport::new(stream))
+    }
+    /// [`TcpTransport::connect`] to the `host:port` of a [`DeviceId::Path`].
+    pub async fn connect_device(id: &DeviceId) -> Result<TcpTransport, IOError> {
+        match id {
+            DeviceId::Path(addr) => Self::connect(addr.as_str()).await,
+            _ => Err(IOError::InvalidArgument),
+        }
+    }
+    pub fn into_stream(self) -> TcpStreamTransport {
+        Stream::new(Box::pin(self))
+    }
+}
+impl<S> TcpTransport<S> {
+    pub fn new(stream: S) -> TcpTransport<S> {
+        TcpTransport {
+            stream,
+            rx: H4Receiver::new(vec![0_u8; MAX_FRAME_SIZE].into_boxed_slice()),
+            filter: None,
+        }
+    }
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+    /// What to do after a framing error. [`FramingRecovery::Fail`] by default.
+    pub fn set_framing_recovery(&mut self, recovery: FramingRecovery) {
+        self.rx.set_recovery(recovery);
+    }
+}
+impl<S: AsyncRead + Unpin> HCIReader for TcpTransport<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let this = self.get_mut();
+        loop {
+            let len = match this.rx.packet_len() {
+                Ok(len) => len,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            if let Some(len) = len {
+                let packet = &this.rx.buffered()[..len];
+                let passes = this.filter.map_or(true, |filter| filter.matches(packet));
+                let result = if !passes {
+                    None
+                } else if let Some(out) = buf.get_mut(..len) {
+                    out.copy_from_slice(packet);
+                    Some(Ok(len))
+                } else {
+                    Some(Err(IOError::Overflow.into()))
+                };
+                this.rx.consume(len);
+                match result {
+                    Some(result) => return Poll::Ready(result),
+                    None => continue,
+                }
+            }
+            match Pin::new(&mut this.stream).poll_read(cx, this.rx.unfilled()) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(StreamError::StreamClosed.into())),
+                Poll::Ready(Ok(amount)) => this.rx.filled(amount),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+impl<S: AsyncWrite + Unpin> HCIWriter for TcpTransport<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        Pin::new(&mut self.get_mut().stream)
+            .poll_write(cx, buf)
+            .map_err(adapter::Error::from)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), adapter::Error>> {
+        Pin::new(&mut self.get_mut().stream)
+            .poll_flush(cx)
+            .map_err(adapter::Error::from)
+    }
+}
+impl<S: Unpin> HCIFilterable for TcpTransport<S> {
+    fn set_filter(self: Pin<&mut Self>, filter: &Filter) -> Result<(), adapter::Error> {
+        self.get_mut().filter = Some(*filter);
+        Ok(())
+    }
+
+    /// [`Filter::pass_all`] until a filter is set.
+    fn get_filter(self: Pin<&Self>) -> Result<Filter, adapter::Error> {
+        Ok(self.get_ref().filter.unwrap_or_else(Filter::pass_all))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::event::EventCode;
+    use crate::hci::packet::PacketType;
+    use futures_util::task::noop_waker_ref;
+
+    /// Reader handing out `chunks` one per read.
+    struct Chunks(Vec<Vec<u8>>);
+    impl AsyncRead for Chunks {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            if self.0.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Poll::Ready(Ok(chunk.len()))
+        }
+    }
+    #[test]
+    fn test_reassembly_and_filter() {
+        let complete = [0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00];
+        let disconnect = [0x04, 0x05, 0x04, 0x00, 0x40, 0x00, 0x13];
+        let acl = [0x02, 0x40, 0x00, 0x01, 0x00, 0xAA];
+        // One read carries the end of a packet and the start of the next.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&complete);
+        bytes.extend_from_slice(&disconnect);
+        bytes.extend_from_slice(&acl);
+        let chunks = vec![
+            bytes[..2].to_vec(),
+            bytes[2..9].to_vec(),
+            bytes[9..].to_vec(),
+        ];
+        let mut transport = TcpTransport::new(Chunks(chunks));
+        let mut filter = Filter::default();
+        filter.enable_type(PacketType::Event);
+        filter.enable_type(PacketType::ACLData);
+        filter.enable_event(EventCode::CommandComplete);
+        Pin::new(&mut transport).set_filter(&filter).unwrap();
+
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut out = [0_u8; 64];
+        let mut read = |transport: &mut TcpTransport<Chunks>| match Pin::new(transport)
+            .poll_read(&mut cx, &mut out)
+        {
+            Poll::Ready(r) => r.map(|len| out[..len].to_vec()),
+            Poll::Pending => panic!("reader is always ready"),
+        };
+        assert_eq!(read(&mut transport), Ok(complete.to_vec()));
+        // The Disconnection Complete event is filtered out.
+        assert_eq!(read(&mut transport), Ok(acl.to_vec()));
+        assert_eq!(read(&mut transport), Err(StreamError::StreamClosed.into()));
+    }
+}
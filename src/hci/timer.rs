@@ -0,0 +1,75 @@
+//! Runtime agnostic timeouts. The crate doesn't depend on an executor so anything that waits for
+//! a while takes a [`Timer`] (backed by `tokio::time::sleep`, an embedded alarm, ..).
+use crate::hci::StreamError;
+use core::future::Future;
+use core::time::Duration;
+use futures_util::future::{self, Either, LocalBoxFuture};
+
+pub trait Timer {
+    /// Finishes after `duration`.
+    fn delay(&mut self, duration: Duration) -> LocalBoxFuture<'static, ()>;
+}
+impl<T: Timer + ?Sized> Timer for Box<T> {
+    fn delay(&mut self, duration: Duration) -> LocalBoxFuture<'static, ()> {
+        (**self).delay(duration)
+    }
+}
+/// Runs `future` for at most `duration`. `future` is polled first, so it still wins if it is
+/// ready when the timer fires.
+/// # Errors
+/// returns `StreamError::Timeout` if `future` didn't finish in time (it is dropped).
+pub async fn timeout<T: Timer + ?Sized, F: Future>(
+    timer: &mut T,
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, StreamError> {
+    timeout_after(timer.delay(duration), future).await
+}
+/// [`timeout`] with the delay already started, for when the timer can't stay borrowed while
+/// `future` runs.
+/// # Errors
+/// returns `StreamError::Timeout` if `delay` finished first.
+pub async fn timeout_after<F: Future>(
+    delay: LocalBoxFuture<'static, ()>,
+    future: F,
+) -> Result<F::Output, StreamError> {
+    match future::select(Box::pin(future), delay).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(((), _)) => Err(StreamError::Timeout),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::adapter;
+    use crate::hci::adapters::Adapter;
+    use crate::hci::baseband::Reset;
+    use crate::hci::command::Command;
+    use crate::hci::io::HCIPort;
+    use crate::testing::mock::{command_complete, MockTransport};
+    use futures_util::FutureExt;
+
+    /// Fires as soon as it's polled.
+    struct Expired;
+    impl Timer for Expired {
+        fn delay(&mut self, _duration: Duration) -> LocalBoxFuture<'static, ()> {
+            Box::pin(future::ready(()))
+        }
+    }
+
+    #[test]
+    fn test_command_timeout() {
+        let (transport, handle) = MockTransport::new();
+        let mut adapter = Adapter::new(HCIPort::new(transport));
+        adapter.set_command_timeout(Expired, Duration::from_secs(1));
+        // Answered commands still go through.
+        handle.inject(&command_complete(Reset::opcode(), &[0x00]));
+        adapter.reset().now_or_never().unwrap().unwrap();
+        // A wedged controller.
+        assert_eq!(
+            adapter.reset().now_or_never().unwrap(),
+            Err(adapter::Error::StreamError(StreamError::Timeout))
+        );
+        assert_eq!(adapter.command_credits(), 1);
+    }
+}
@@ -0,0 +1,239 @@
+//! H4 (UART) transport for controllers wired straight to a serial port. [`H4Transport`] works
+//! with any [`embedded_io`] serial peripheral (no `std` needed) and reassembles the byte stream
+//! into whole packets so each [`HCIReader::poll_read`] returns exactly one packet with its packet
+//! indicator byte, like [`Stream`](crate::hci::stream::Stream) expects. Enabled with the `uart`
+//! feature.
+//!
+//! The peripheral is polled through [`ReadReady`]/[`WriteReady`]. When it isn't ready the task is
+//! woken again right away, so interrupt driven UARTs should feed an
+//! [`IsrRing`](crate::hci::isr_ring::IsrRing) instead.
+use crate::error::IOError;
+use crate::hci::adapter;
+use crate::hci::packet::{FramingRecovery, H4Receiver};
+use crate::hci::stream::{HCIReader, HCIWriter};
+use crate::hci::{StreamError, MAX_FRAME_SIZE};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use embedded_io::{ErrorKind, Read, ReadReady, Write, WriteReady};
+
+pub use crate::hci::packet::ISO_DATA_INDICATOR;
+/// Big enough for the largest ACL packet (with its indicator byte).
+pub const DEFAULT_BUFFER_LEN: usize = MAX_FRAME_SIZE;
+
+fn io_error<E: embedded_io::Error>(e: &E) -> adapter::Error {
+    match e.kind() {
+        ErrorKind::TimedOut => IOError::TimedOut,
+        ErrorKind::Interrupted => IOError::Interrupted,
+        ErrorKind::InvalidData => IOError::InvalidData,
+        ErrorKind::InvalidInput => IOError::InvalidArgument,
+        ErrorKind::NotConnected => IOError::NotConnected,
+        ErrorKind::BrokenPipe => IOError::Pipe,
+        ErrorKind::OutOfMemory => IOError::OutOfMemory,
+        ErrorKind::PermissionDenied => IOError::PermissionDenied,
+        ErrorKind::Unsupported => IOError::NotImplemented,
+        _ => IOError::Other,
+    }
+    .into()
+}
+/// H4 framing over a serial peripheral `U` with an `N` byte receive buffer.
+pub struct H4Transport<U, const N: usize = DEFAULT_BUFFER_LEN> {
+    uart: U,
+    rx: H4Receiver<[u8; N]>,
+}
+impl<U, const N: usize> H4Transport<U, N> {
+    pub fn new(uart: U) -> H4Transport<U, N> {
+        H4Transport {
+            uart,
+            rx: H4Receiver::new([0_u8; N]),
+        }
+    }
+    pub fn uart(&self) -> &U {
+        &self.uart
+    }
+    pub fn uart_mut(&mut self) -> &mut U {
+        &mut self.uart
+    }
+    pub fn into_inner(self) -> U {
+        self.uart
+    }
+    /// Bytes of a partly received packet.
+    pub fn pending_len(&self) -> usize {
+        self.rx.buffered().len()
+    }
+    /// Drops a partly received packet (after a framing error for example).
+    pub fn reset(&mut self) {
+        self.rx.reset();
+    }
+    /// What to do after a framing error. [`FramingRecovery::Fail`] by default.
+    pub fn set_framing_recovery(&mut self, recovery: FramingRecovery) {
+        self.rx.set_recovery(recovery);
+    }
+}
+impl<U: Read + ReadReady + Unpin, const N: usize> HCIReader for H4Transport<U, N> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let this = self.get_mut();
+        loop {
+            match this.rx.read_packet(buf) {
+                Ok(Some(len)) => return Poll::Ready(Ok(len)),
+                Ok(None) => (),
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+            match this.uart.read_ready() {
+                Ok(true) => (),
+                Ok(false) => {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Err(e) => return Poll::Ready(Err(io_error(&e))),
+            }
+            match this.uart.read(this.rx.unfilled()) {
+                Ok(0) => return Poll::Ready(Err(StreamError::StreamClosed.into())),
+                Ok(amount) => this.rx.filled(amount),
+                Err(e) => return Poll::Ready(Err(io_error(&e))),
+            }
+        }
+    }
+}
+impl<U: Write + WriteReady + Unpin, const N: usize> HCIWriter for H4Transport<U, N> {
+    /// `buf` is sent as is. Packets from [`Stream`](crate::hci::stream::Stream) already start
+    /// with their packet indicator.
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let uart = &mut self.get_mut().uart;
+        match uart.write_ready() {
+            Ok(true) => Poll::Ready(uart.write(buf).map_err(|e| io_error(&e))),
+            Ok(false) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Err(e) => Poll::Ready(Err(io_error(&e))),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), adapter::Error>> {
+        Poll::Ready(self.get_mut().uart.flush().map_err(|e| io_error(&e)))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use core::convert::Infallible;
+    use futures_util::task::noop_waker_ref;
+    /// UART returning at most `chunk` bytes per read and sometimes not being ready.
+    struct ChunkedUart {
+        rx: Vec<u8>,
+        chunk: usize,
+        ready: bool,
+    }
+    impl embedded_io::ErrorType for ChunkedUart {
+        type Error = Infallible;
+    }
+    impl ReadReady for ChunkedUart {
+        fn read_ready(&mut self) -> Result<bool, Infallible> {
+            self.ready = !self.ready;
+            Ok(self.ready && !self.rx.is_empty())
+        }
+    }
+    impl Read for ChunkedUart {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            let amount = buf.len().min(self.chunk).min(self.rx.len());
+            buf[..amount].copy_from_slice(&self.rx[..amount]);
+            self.rx.drain(..amount);
+            Ok(amount)
+        }
+    }
+    #[test]
+    fn test_h4_reassembly() {
+        // Command Complete event, an empty ACL packet then an unknown indicator.
+        let event = [0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00];
+        let acl = [0x02, 0x40, 0x00, 0x00, 0x00];
+        let mut rx = Vec::new();
+        rx.extend_from_slice(&event);
+        rx.extend_from_slice(&acl);
+        rx.push(0x09);
+        let mut transport: H4Transport<_, 64> = H4Transport::new(ChunkedUart {
+            rx,
+            chunk: 2,
+            ready: false,
+        });
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut out = [0_u8; 64];
+        let mut read = |transport: &mut H4Transport<ChunkedUart, 64>, out: &mut [u8]| loop {
+            if let Poll::Ready(r) = Pin::new(&mut *transport).poll_read(&mut cx, out) {
+                break r;
+            }
+        };
+        assert_eq!(read(&mut transport, &mut out), Ok(event.len()));
+        assert_eq!(out[..event.len()], event);
+        assert_eq!(read(&mut transport, &mut out), Ok(acl.len()));
+        assert_eq!(out[..acl.len()], acl);
+        assert_eq!(
+            read(&mut transport, &mut out),
+            Err(StreamError::UnsupportedPacketType(0x09).into())
+        );
+        assert_eq!(transport.pending_len(), 0);
+    }
+    #[test]
+    fn test_h4_resync() {
+        // A glitched byte, a vendor event, an ACL header with an impossible length then the
+        // Command Complete event.
+        let vendor = [0x04, 0xFF, 0x01, 0x00];
+        let event = [0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00];
+        let mut rx = vec![0x17];
+        rx.extend_from_slice(&vendor);
+        rx.extend_from_slice(&[0x02, 0x40, 0x00, 0xFF, 0x3F]);
+        rx.extend_from_slice(&event);
+        let mut transport: H4Transport<_, 64> = H4Transport::new(ChunkedUart {
+            rx,
+            chunk: 3,
+            ready: false,
+        });
+        transport.set_framing_recovery(FramingRecovery::Resync);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut out = [0_u8; 64];
+        let mut read = |transport: &mut H4Transport<ChunkedUart, 64>, out: &mut [u8]| loop {
+            if let Poll::Ready(r) = Pin::new(&mut *transport).poll_read(&mut cx, out) {
+                break r;
+            }
+        };
+        assert_eq!(read(&mut transport, &mut out), Ok(vendor.len()));
+        assert_eq!(out[..vendor.len()], vendor);
+        assert_eq!(read(&mut transport, &mut out), Ok(event.len()));
+        assert_eq!(out[..event.len()], event);
+    }
+    #[test]
+    fn test_dropped_read_keeps_partial_packet() {
+        use crate::hci::io::HCIPort;
+        use futures_util::FutureExt;
+
+        let event = [0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00];
+        let transport: H4Transport<_, 64> = H4Transport::new(ChunkedUart {
+            rx: event.to_vec(),
+            chunk: 2,
+            ready: false,
+        });
+        let mut port = HCIPort::new(transport);
+        let mut buf = [0_u8; 64];
+        // Dropped (a timeout for example) after the first chunk arrived.
+        assert!(port.read_event_into(&mut buf).now_or_never().is_none());
+        assert_ne!(port.transport.pending_len(), 0);
+        let read = loop {
+            let read = port
+                .read_event_into(&mut buf)
+                .now_or_never()
+                .map(|r| r.map(|event| event.to_new_storage::<Box<[u8]>>()));
+            if let Some(read) = read {
+                break read;
+            }
+        };
+        assert_eq!(read.unwrap().parameters(), &event[3..]);
+    }
+}
@@ -5,6 +5,7 @@ use crate::hci::command::CommandPacket;
 use crate::hci::event::{EventCode, EventPacket, StaticHCIBuffer};
 use crate::hci::usb::device::has_bluetooth_interface;
 use crate::hci::usb::Error;
+use alloc::vec::Vec;
 use core::convert::TryFrom;
 use core::time::Duration;
 use futures_util::future::LocalBoxFuture;
@@ -50,6 +51,9 @@ pub const INTERFACE_NUM: u8 = 0x00;
 pub struct Adapter {
     handle: SingleTransferDevice,
     device_descriptor: DeviceDescriptor,
+    /// Event bytes received but not returned yet. Kept here (not in the read future) so a
+    /// dropped [`Adapter::read_event_packet`] loses nothing.
+    event_rx: Vec<u8>,
     _private: (),
 }
 impl core::fmt::Debug for Adapter {
@@ -79,6 +83,7 @@ impl Adapter {
     pub(crate) fn from_parts(device_descriptor: DeviceDescriptor, handle: AsyncDevice) -> Adapter {
         Adapter {
             handle: SingleTransferDevice::new(handle),
+            event_rx: Vec::new(),
             _private: (),
             device_descriptor,
         }
@@ -146,6 +151,20 @@ impl Adapter {
         }
         Ok(())
     }
+    pub async fn write_acl_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut index = 0;
+        while index < bytes.len() {
+            let amount = self
+                .handle
+                .bulk_write(ACL_DATA_OUT_ENDPOINT, &bytes[index..], Self::TIMEOUT)
+                .await?;
+            if amount == 0 {
+                return Err(Error(IOError::TimedOut));
+            }
+            index += amount;
+        }
+        Ok(())
+    }
     pub async fn read_some_event_bytes(
         &mut self,
         buf: &mut [u8],
@@ -188,6 +207,7 @@ impl Adapter {
         // more reliable but also a longer time to flush.
         const FLUSH_TIMEOUT: Duration = Duration::from_millis(5);
 
+        self.event_rx.clear();
         let mut buf = vec![0_u8; 256];
         while match self
             .read_some_event_bytes(buf.as_mut_slice(), FLUSH_TIMEOUT)
@@ -200,21 +220,38 @@ impl Adapter {
         {}
         Ok(())
     }
+    /// Reads the next event. Cancel safe: bytes of a partly received event stay buffered in the
+    /// adapter when the future is dropped and the next read picks them up.
     pub async fn read_event_packet<Buf: Storage<u8>>(
         &mut self,
     ) -> Result<EventPacket<Buf>, hci::adapter::Error> {
-        let mut header = [0u8; 2];
-        self.read_event_bytes(&mut header[..]).await?;
-        let len = header[1];
-        let mut buf = Buf::with_size(len.into());
-        // Even if the event code is wrong, still read so we don't leave data in buffer
-        self.read_event_bytes(buf.as_mut()).await?;
-        let event_code =
-            EventCode::try_from(header[0]).map_err(|_| hci::StreamError::BadEventCode)?;
-        Ok(EventPacket {
-            event_code,
-            parameters: buf,
-        })
+        loop {
+            if let [code, len, ..] = self.event_rx[..] {
+                let end = 2 + usize::from(len);
+                if self.event_rx.len() >= end {
+                    // Even if the event code is wrong, still consume it so we don't leave data
+                    // in the buffer.
+                    let parameters = Buf::from_slice(&self.event_rx[2..end]);
+                    self.event_rx.drain(..end);
+                    let event_code =
+                        EventCode::try_from(code).map_err(|_| hci::StreamError::BadEventCode)?;
+                    return Ok(EventPacket {
+                        event_code,
+                        parameters,
+                    });
+                }
+            }
+            let mut chunk = [0_u8; 64];
+            let amount = match self
+                .read_some_event_bytes(&mut chunk[..], Self::TIMEOUT)
+                .await
+            {
+                Ok(amount) => amount,
+                Err(Error(IOError::TimedOut)) => 0,
+                Err(e) => return Err(e.into()),
+            };
+            self.event_rx.extend_from_slice(&chunk[..amount]);
+        }
     }
     pub fn reset(&mut self) -> Result<(), Error> {
         self.handle.device().handle_ref().reset()?;
@@ -237,7 +274,16 @@ impl hci::adapter::Adapter for Adapter {
         &'s mut self,
         packet: CommandPacket<&'p [u8]>,
     ) -> LocalBoxFuture<'s, Result<(), hci::adapter::Error>> {
-        let packed = packet.to_raw_packet::<StaticHCIBuffer>();
+        let packed = match packet.to_raw_packet::<StaticHCIBuffer>() {
+            Ok(packed) => packed,
+            Err(e) => {
+                return Box::pin(async move {
+                    Err(hci::adapter::Error::StreamError(
+                        hci::StreamError::CommandError(e),
+                    ))
+                })
+            }
+        };
         Box::pin(async move {
             self.write_hci_command_bytes(packed.buf.as_ref())
                 .await
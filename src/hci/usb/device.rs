@@ -1,28 +1,30 @@
+use crate::hci::device_id::{ControllerInfo, DeviceId, TransportKind};
 use crate::hci::usb::Error;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
 use usbw::libusb::device::Device;
 
 const WIRELESS_CONTROLLER_CLASS: u8 = 0xE0;
 const SUBCLASS: u8 = 0x01;
 const BLUETOOTH_PROGRAMMING_INTERFACE_PROTOCOL: u8 = 0x01;
+/// Whether any interface (or alternate setting) of the active configuration is a Bluetooth
+/// Programming Interface (class `0xE0`, subclass `0x01`, protocol `0x01`). Composite devices
+/// don't always put it first.
 pub fn has_bluetooth_interface(device: &Device) -> Result<bool, Error> {
     match device.active_config_descriptor() {
-        Ok(config) => Ok(config
-            .interfaces()
-            .iter()
-            .next()
-            .and_then(|i| {
-                i.descriptors().iter().next().map(|d| {
-                    d.class_code() == WIRELESS_CONTROLLER_CLASS
-                        && d.sub_class_code() == SUBCLASS
-                        && d.protocol_code() == BLUETOOTH_PROGRAMMING_INTERFACE_PROTOCOL
-                })
+        Ok(config) => Ok(config.interfaces().iter().any(|i| {
+            i.descriptors().iter().any(|d| {
+                d.class_code() == WIRELESS_CONTROLLER_CLASS
+                    && d.sub_class_code() == SUBCLASS
+                    && d.protocol_code() == BLUETOOTH_PROGRAMMING_INTERFACE_PROTOCOL
             })
-            .unwrap_or(false)),
+        })),
         Err(usbw::libusb::error::Error::NotFound) => Ok(false),
         Err(e) => Err(Error::from(e)),
     }
 }
 
+/// Filters `i` (a device list) down to Bluetooth controllers.
 pub fn bluetooth_adapters<'a>(
     i: impl Iterator<Item = Device> + 'a,
 ) -> impl Iterator<Item = Result<Device, Error>> + 'a {
@@ -32,3 +34,55 @@ pub fn bluetooth_adapters<'a>(
         Err(e) => Some(Err(e)),
     })
 }
+/// [`ControllerInfo`] of each Bluetooth controller in `devices`. Serial numbers need the device
+/// open so they're left out.
+pub fn controllers_in(devices: impl Iterator<Item = Device>) -> Result<Vec<ControllerInfo>, Error> {
+    let mut controllers = Vec::new();
+    for (index, device) in bluetooth_adapters(devices).enumerate() {
+        let descriptor = device?.device_descriptor()?;
+        let id = DeviceId::Usb {
+            vendor_id: descriptor.vendor_id(),
+            product_id: descriptor.product_id(),
+            serial: None,
+        };
+        controllers.push(ControllerInfo {
+            id: id.clone(),
+            transport: TransportKind::USB,
+            index: u16::try_from(index).unwrap_or(u16::MAX),
+            address: None,
+            name: None,
+            usb: Some(id),
+        });
+    }
+    Ok(controllers)
+}
+/// Bluetooth controllers on the default libusb context.
+pub fn controllers() -> Result<Vec<ControllerInfo>, Error> {
+    let context = usbw::libusb::context::Context::default()?;
+    let context = context.start_async();
+    let device_list = context.context_ref().device_list();
+    controllers_in(device_list.iter())
+}
+/// The Bluetooth controller in `devices` selected by `id` ([`DeviceId::Usb`] without the serial
+/// number or [`DeviceId::Index`]), ready for [`Device::open`] and
+/// [`Adapter::open`](crate::hci::usb::adapter::Adapter::open).
+pub fn find_device(
+    devices: impl Iterator<Item = Device>,
+    id: &DeviceId,
+) -> Result<Option<Device>, Error> {
+    for (index, device) in bluetooth_adapters(devices).enumerate() {
+        let device = device?;
+        let selected = match id {
+            DeviceId::Index(wanted) => usize::from(*wanted) == index,
+            DeviceId::Usb { .. } => {
+                let descriptor = device.device_descriptor()?;
+                id.matches_usb(descriptor.vendor_id(), descriptor.product_id(), None)
+            }
+            _ => false,
+        };
+        if selected {
+            return Ok(Some(device));
+        }
+    }
+    Ok(None)
+}
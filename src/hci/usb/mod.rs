@@ -1,8 +1,10 @@
 pub mod adapter;
 pub mod device;
 pub mod supported;
+pub mod transport;
+pub mod winusb;
 
-use crate::error::IOError;
+use crate::error::{BusyOwner, IOError};
 
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
 pub struct Error(pub IOError);
@@ -19,7 +21,7 @@ impl From<usbw::libusb::error::Error> for Error {
             usbw::libusb::error::Error::Access => IOError::AccessDenied,
             usbw::libusb::error::Error::NoDevice => IOError::NotConnected,
             usbw::libusb::error::Error::NotFound => IOError::NotFound,
-            usbw::libusb::error::Error::Busy => IOError::Refused,
+            usbw::libusb::error::Error::Busy => IOError::Busy(BusyOwner::Unknown),
             usbw::libusb::error::Error::Timeout => IOError::TimedOut,
             usbw::libusb::error::Error::Overflow => IOError::Overflow,
             usbw::libusb::error::Error::Pipe => IOError::Pipe,
@@ -0,0 +1,422 @@
+//! [`HCIReader`]/[`HCIWriter`] over a USB [`Adapter`] so a dongle can be used with
+//! [`Stream`](crate::hci::stream::Stream) like any other byte stream transport. Packets are
+//! framed like H4: commands (`0x01`) go out as control transfers and ACL data (`0x02`) on the bulk
+//! OUT endpoint. Events from the interrupt endpoint and ACL data from the bulk IN endpoint come
+//! back with their packet indicator byte prepended.
+//!
+//! The adapter only runs one transfer at a time so the endpoints are polled in turn with
+//! [`UsbTransport::POLL_TIMEOUT`]. The transfers go through [`UsbEndpoints`], which [`Adapter`]
+//! implements.
+use crate::error::IOError;
+use crate::hci::adapter;
+use crate::hci::packet::PacketType;
+use crate::hci::stream::{HCIReader, HCIWriter};
+use crate::hci::usb::adapter::Adapter;
+use crate::hci::usb::Error;
+use crate::hci::{StreamError, MAX_FRAME_SIZE};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use futures_util::future::LocalBoxFuture;
+use futures_util::FutureExt;
+
+/// Byte level transfers on the four HCI endpoints.
+pub trait UsbEndpoints: Unpin + 'static {
+    /// Sends all of `bytes` as HCI command control transfers.
+    fn command_out<'s>(&'s mut self, bytes: &'s [u8]) -> LocalBoxFuture<'s, Result<(), Error>>;
+    /// Sends all of `bytes` on the bulk ACL OUT endpoint.
+    fn acl_out<'s>(&'s mut self, bytes: &'s [u8]) -> LocalBoxFuture<'s, Result<(), Error>>;
+    /// Reads some event bytes from the interrupt endpoint.
+    fn event_in<'s>(
+        &'s mut self,
+        buf: &'s mut [u8],
+        timeout: Duration,
+    ) -> LocalBoxFuture<'s, Result<usize, Error>>;
+    /// Reads some ACL bytes from the bulk IN endpoint.
+    fn acl_in<'s>(
+        &'s mut self,
+        buf: &'s mut [u8],
+        timeout: Duration,
+    ) -> LocalBoxFuture<'s, Result<usize, Error>>;
+}
+impl UsbEndpoints for Adapter {
+    fn command_out<'s>(&'s mut self, bytes: &'s [u8]) -> LocalBoxFuture<'s, Result<(), Error>> {
+        Box::pin(self.write_hci_command_bytes(bytes))
+    }
+    fn acl_out<'s>(&'s mut self, bytes: &'s [u8]) -> LocalBoxFuture<'s, Result<(), Error>> {
+        Box::pin(self.write_acl_bytes(bytes))
+    }
+    fn event_in<'s>(
+        &'s mut self,
+        buf: &'s mut [u8],
+        timeout: Duration,
+    ) -> LocalBoxFuture<'s, Result<usize, Error>> {
+        Box::pin(self.read_some_event_bytes(buf, timeout))
+    }
+    fn acl_in<'s>(
+        &'s mut self,
+        buf: &'s mut [u8],
+        timeout: Duration,
+    ) -> LocalBoxFuture<'s, Result<usize, Error>> {
+        Box::pin(self.read_some_acl_bytes(buf, timeout))
+    }
+}
+/// Length of the whole packet (without the indicator) once `bytes` holds its header.
+fn packet_len(packet_type: PacketType, bytes: &[u8]) -> Option<usize> {
+    match packet_type {
+        PacketType::Event => bytes.get(1).map(|&len| 2 + usize::from(len)),
+        _ => bytes
+            .get(2..4)
+            .map(|len| 4 + usize::from(u16::from_le_bytes([len[0], len[1]]))),
+    }
+}
+/// Reads the rest of a packet that started with the `amount` bytes already in `buf[1..]`.
+async fn finish_packet<A: UsbEndpoints>(
+    adapter: &mut A,
+    mut buf: Vec<u8>,
+    packet_type: PacketType,
+    mut amount: usize,
+) -> Result<Vec<u8>, Error> {
+    buf[0] = packet_type.into();
+    loop {
+        let needed = packet_len(packet_type, &buf[1..=amount]);
+        match needed {
+            Some(needed) if needed + 1 > buf.len() => return Err(Error(IOError::Overflow)),
+            Some(needed) if amount >= needed => {
+                buf.truncate(needed + 1);
+                return Ok(buf);
+            }
+            _ => (),
+        }
+        let rest = &mut buf[1 + amount..];
+        amount += match packet_type {
+            PacketType::Event => adapter.event_in(rest, Adapter::TIMEOUT).await?,
+            _ => adapter.acl_in(rest, Adapter::TIMEOUT).await?,
+        };
+    }
+}
+/// Waits up to `timeout` on each IN endpoint for the start of a packet.
+async fn read_packet<A: UsbEndpoints>(
+    adapter: &mut A,
+    timeout: Duration,
+) -> Result<Option<Vec<u8>>, Error> {
+    let mut buf = vec![0_u8; MAX_FRAME_SIZE];
+    for &packet_type in &[PacketType::Event, PacketType::ACLData] {
+        let read = match packet_type {
+            PacketType::Event => adapter.event_in(&mut buf[1..], timeout).await,
+            _ => adapter.acl_in(&mut buf[1..], timeout).await,
+        };
+        match read {
+            Ok(0) | Err(Error(IOError::TimedOut)) => (),
+            Ok(amount) => {
+                return finish_packet(adapter, buf, packet_type, amount)
+                    .await
+                    .map(Some)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+async fn write_packet<A: UsbEndpoints>(
+    adapter: &mut A,
+    packet: &[u8],
+) -> Result<usize, adapter::Error> {
+    let (&indicator, bytes) = packet.split_first().ok_or(IOError::InvalidArgument)?;
+    match PacketType::try_from(indicator) {
+        Ok(PacketType::Command) => adapter.command_out(bytes).await?,
+        Ok(PacketType::ACLData) => adapter.acl_out(bytes).await?,
+        _ => return Err(StreamError::UnsupportedPacketType(indicator).into()),
+    }
+    Ok(packet.len())
+}
+type Transfer<A, T> = LocalBoxFuture<'static, (A, T)>;
+enum State<A> {
+    Idle(A),
+    Reading(Transfer<A, Result<Option<Vec<u8>>, Error>>),
+    Writing(Transfer<A, Result<usize, adapter::Error>>),
+    /// Only seen while switching states.
+    Busy,
+}
+/// USB HCI transport. See the module docs.
+pub struct UsbTransport<A: UsbEndpoints = Adapter> {
+    state: State<A>,
+    /// Packet (or error) a write had to wait for.
+    received: Option<Result<Vec<u8>, adapter::Error>>,
+    /// Result of a write finished by `poll_read` or `poll_flush`, returned by the next
+    /// `poll_write`.
+    written: Option<Result<usize, adapter::Error>>,
+}
+impl<A: UsbEndpoints> UsbTransport<A> {
+    /// How long each IN endpoint is waited on before trying the other one.
+    pub const POLL_TIMEOUT: Duration = Duration::from_millis(10);
+    pub fn new(adapter: A) -> UsbTransport<A> {
+        UsbTransport {
+            state: State::Idle(adapter),
+            received: None,
+            written: None,
+        }
+    }
+    pub fn adapter(&self) -> Option<&A> {
+        match &self.state {
+            State::Idle(adapter) => Some(adapter),
+            _ => None,
+        }
+    }
+    /// Returns `Err(self)` if a transfer is still running.
+    pub fn into_adapter(self) -> Result<A, UsbTransport<A>> {
+        match self.state {
+            State::Idle(adapter) => Ok(adapter),
+            state => Err(UsbTransport { state, ..self }),
+        }
+    }
+    /// Drives an unfinished read or write to completion. Reads are kept for the next
+    /// `poll_read` and write results for the next `poll_write`.
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<A> {
+        match core::mem::replace(&mut self.state, State::Busy) {
+            State::Idle(adapter) => Poll::Ready(adapter),
+            State::Reading(mut transfer) => match transfer.poll_unpin(cx) {
+                Poll::Ready((adapter, received)) => {
+                    self.received = received.map_err(adapter::Error::from).transpose();
+                    Poll::Ready(adapter)
+                }
+                Poll::Pending => {
+                    self.state = State::Reading(transfer);
+                    Poll::Pending
+                }
+            },
+            State::Writing(mut transfer) => match transfer.poll_unpin(cx) {
+                Poll::Ready((adapter, written)) => {
+                    self.written = Some(written);
+                    Poll::Ready(adapter)
+                }
+                Poll::Pending => {
+                    self.state = State::Writing(transfer);
+                    Poll::Pending
+                }
+            },
+            State::Busy => unreachable!("usb transport state not restored"),
+        }
+    }
+}
+impl<A: UsbEndpoints + core::fmt::Debug> core::fmt::Debug for UsbTransport<A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.state {
+            State::Idle(adapter) => write!(f, "UsbTransport({:?})", adapter),
+            _ => write!(f, "UsbTransport(<transfer running>)"),
+        }
+    }
+}
+impl<A: UsbEndpoints> HCIReader for UsbTransport<A> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(received) = this.received.take() {
+                let packet = received?;
+                let out = buf
+                    .get_mut(..packet.len())
+                    .ok_or(adapter::Error::from(IOError::Overflow))?;
+                out.copy_from_slice(&packet);
+                return Poll::Ready(Ok(packet.len()));
+            }
+            let was_reading = matches!(this.state, State::Reading(_));
+            let mut adapter = match this.poll_idle(cx) {
+                Poll::Ready(adapter) => adapter,
+                Poll::Pending => return Poll::Pending,
+            };
+            if was_reading && this.received.is_none() {
+                // Nothing came in. Give writes a chance before reading again.
+                this.state = State::Idle(adapter);
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            if this.received.is_none() {
+                this.state = State::Reading(
+                    async move {
+                        let received = read_packet(&mut adapter, Self::POLL_TIMEOUT).await;
+                        (adapter, received)
+                    }
+                    .boxed_local(),
+                );
+            } else {
+                this.state = State::Idle(adapter);
+            }
+        }
+    }
+}
+impl<A: UsbEndpoints> HCIWriter for UsbTransport<A> {
+    /// `buf` has to be one whole packet starting with its packet indicator. After
+    /// `Poll::Pending` the same packet has to be passed again (it isn't sent twice).
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(written) = this.written.take() {
+                return Poll::Ready(written);
+            }
+            let mut adapter = match this.poll_idle(cx) {
+                Poll::Ready(adapter) => adapter,
+                Poll::Pending => return Poll::Pending,
+            };
+            if this.written.is_some() {
+                // Finished the write started by an earlier call.
+                this.state = State::Idle(adapter);
+                continue;
+            }
+            let packet = buf.to_vec();
+            this.state = State::Writing(
+                async move {
+                    let written = write_packet(&mut adapter, &packet).await;
+                    (adapter, written)
+                }
+                .boxed_local(),
+            );
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), adapter::Error>> {
+        let this = self.get_mut();
+        match this.state {
+            State::Writing(_) => match this.poll_idle(cx) {
+                Poll::Ready(adapter) => {
+                    this.state = State::Idle(adapter);
+                    Poll::Ready(Ok(()))
+                }
+                Poll::Pending => Poll::Pending,
+            },
+            _ => Poll::Ready(Ok(())),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::event::EventCode;
+    use crate::testing::mock::{event_packet, MockTransport};
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+    use futures_util::future::poll_fn;
+    use futures_util::task::noop_waker_ref;
+
+    /// Endpoints backed by a [`MockTransport`]. Writes stay pending while `hold_writes` is set
+    /// and fail while `fail_writes` is.
+    struct MockEndpoints {
+        mock: MockTransport,
+        hold_writes: Rc<Cell<bool>>,
+        fail_writes: Rc<Cell<bool>>,
+    }
+    impl MockEndpoints {
+        fn write<'s>(
+            &'s mut self,
+            packet_type: PacketType,
+            bytes: &'s [u8],
+        ) -> LocalBoxFuture<'s, Result<(), Error>> {
+            let mut packet = vec![packet_type.into()];
+            packet.extend_from_slice(bytes);
+            Box::pin(poll_fn(move |cx| {
+                if self.hold_writes.get() {
+                    return Poll::Pending;
+                }
+                if self.fail_writes.get() {
+                    return Poll::Ready(Err(Error(IOError::Pipe)));
+                }
+                Pin::new(&mut self.mock)
+                    .poll_write(cx, &packet)
+                    .map(|r| r.map(|_| ()).map_err(|_| Error(IOError::Other)))
+            }))
+        }
+    }
+    impl UsbEndpoints for MockEndpoints {
+        fn command_out<'s>(&'s mut self, bytes: &'s [u8]) -> LocalBoxFuture<'s, Result<(), Error>> {
+            self.write(PacketType::Command, bytes)
+        }
+        fn acl_out<'s>(&'s mut self, bytes: &'s [u8]) -> LocalBoxFuture<'s, Result<(), Error>> {
+            self.write(PacketType::ACLData, bytes)
+        }
+        fn event_in<'s>(
+            &'s mut self,
+            buf: &'s mut [u8],
+            _timeout: Duration,
+        ) -> LocalBoxFuture<'s, Result<usize, Error>> {
+            Box::pin(poll_fn(move |cx| {
+                let mut packet = [0_u8; MAX_FRAME_SIZE];
+                match Pin::new(&mut self.mock).poll_read(cx, &mut packet) {
+                    Poll::Ready(Ok(len)) => {
+                        buf[..len - 1].copy_from_slice(&packet[1..len]);
+                        Poll::Ready(Ok(len - 1))
+                    }
+                    Poll::Ready(Err(_)) => Poll::Ready(Err(Error(IOError::Other))),
+                    Poll::Pending => Poll::Ready(Err(Error(IOError::TimedOut))),
+                }
+            }))
+        }
+        fn acl_in<'s>(
+            &'s mut self,
+            _buf: &'s mut [u8],
+            _timeout: Duration,
+        ) -> LocalBoxFuture<'s, Result<usize, Error>> {
+            Box::pin(async { Err(Error(IOError::TimedOut)) })
+        }
+    }
+    #[test]
+    fn test_read_during_pending_write() {
+        let (mock, handle) = MockTransport::new();
+        let hold_writes = Rc::new(Cell::new(true));
+        let fail_writes = Rc::new(Cell::new(false));
+        let mut transport = UsbTransport::new(MockEndpoints {
+            mock,
+            hold_writes: hold_writes.clone(),
+            fail_writes: fail_writes.clone(),
+        });
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let reset = [0x01, 0x03, 0x0C, 0x00];
+        let complete = event_packet(EventCode::CommandComplete, &[0x01, 0x03, 0x0C, 0x00]);
+        handle.expect(&reset, &[&complete]);
+
+        assert!(Pin::new(&mut transport)
+            .poll_write(&mut cx, &reset)
+            .is_pending());
+        let mut buf = [0_u8; MAX_FRAME_SIZE];
+        assert!(Pin::new(&mut transport)
+            .poll_read(&mut cx, &mut buf)
+            .is_pending());
+        // The read finishes the write and then reads its response.
+        hold_writes.set(false);
+        assert_eq!(
+            Pin::new(&mut transport).poll_read(&mut cx, &mut buf),
+            Poll::Ready(Ok(complete.len()))
+        );
+        assert_eq!(buf[..complete.len()], complete[..]);
+        // The finished write is reported instead of sent again.
+        assert_eq!(
+            Pin::new(&mut transport).poll_write(&mut cx, &reset),
+            Poll::Ready(Ok(reset.len()))
+        );
+        handle.assert_done();
+
+        // Write errors aren't lost either.
+        hold_writes.set(true);
+        fail_writes.set(true);
+        assert!(Pin::new(&mut transport)
+            .poll_write(&mut cx, &reset)
+            .is_pending());
+        hold_writes.set(false);
+        assert_eq!(
+            Pin::new(&mut transport).poll_flush(&mut cx),
+            Poll::Ready(Ok(()))
+        );
+        assert_eq!(
+            Pin::new(&mut transport).poll_write(&mut cx, &reset),
+            Poll::Ready(Err(IOError::Pipe.into()))
+        );
+    }
+}
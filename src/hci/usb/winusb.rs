@@ -0,0 +1,128 @@
+//! Raw HCI on Windows. Windows has no HCI socket. The stock `BTHUSB` driver owns the controller
+//! and only exposes it through the WinRT APIs ([`crate::windows`]). For exclusive HCI access
+//! switch the dongle's driver to WinUSB (with [Zadig](https://zadig.akeo.ie) or `libwdi`). libusb
+//! then opens it like on any other platform and [`WinUsbTransport`] gives the usual
+//! [`HCIReader`]/[`HCIWriter`] stream. Windows stops using the dongle as its own Bluetooth radio
+//! until the driver is switched back.
+//!
+//! [`classify_open_error`] turns the libusb errors seen when that hasn't been done into something
+//! that can be shown to a user.
+//!
+//! ```ignore
+//! let context = usbw::libusb::context::Context::default()?.start_async();
+//! let devices = context.context_ref().device_list();
+//! let device = usb::device::find_device(devices.iter(), &DeviceId::Index(0))?
+//!     .ok_or(IOError::NotFound)?;
+//! let handle = context.make_async_device(device.open().map_err(classify_open_error)?);
+//! let adapter = adapters::Adapter::new(HCIPort::new(WinUsbTransport::claim(handle)?));
+//! ```
+use crate::hci::adapter;
+use crate::hci::stream::{HCIReader, HCIWriter};
+use crate::hci::usb::adapter::{Adapter, INTERFACE_NUM};
+use crate::hci::usb::transport::UsbTransport;
+use crate::hci::usb::Error;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use usbw::libusb::async_device::AsyncDevice;
+use usbw::libusb::error::Error as LibUsbError;
+
+/// Why opening a Bluetooth USB device failed.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum OpenError {
+    /// The device is bound to a driver libusb can't use (`BTHUSB` on Windows). Install WinUSB
+    /// for it.
+    NeedsWinUsbDriver,
+    /// Another process (or the kernel driver on Linux) has the device claimed.
+    InUse,
+    /// Not allowed to open the device (missing udev rule or admin rights).
+    AccessDenied,
+    Other(Error),
+}
+impl OpenError {
+    /// Short hint on how to fix the error.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            OpenError::NeedsWinUsbDriver => {
+                "the device isn't using the WinUSB driver, switch it with Zadig or libwdi"
+            }
+            OpenError::InUse => "the device is in use by another driver or process",
+            OpenError::AccessDenied => "no permission to open the device",
+            OpenError::Other(_) => "couldn't open the device",
+        }
+    }
+}
+impl core::fmt::Display for OpenError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            OpenError::Other(e) => write!(f, "{}: {}", self.hint(), e),
+            _ => f.write_str(self.hint()),
+        }
+    }
+}
+impl crate::error::Error for OpenError {}
+#[cfg(feature = "std")]
+impl std::error::Error for OpenError {}
+/// Classifies an error from opening a device or claiming its interface.
+pub fn classify_open_error(error: LibUsbError) -> OpenError {
+    match error {
+        // libusb on Windows reports devices without a WinUSB (or libusbK) driver as unsupported.
+        LibUsbError::NotSupported | LibUsbError::NotFound if cfg!(windows) => {
+            OpenError::NeedsWinUsbDriver
+        }
+        LibUsbError::Busy => OpenError::InUse,
+        LibUsbError::Access => OpenError::AccessDenied,
+        e => OpenError::Other(Error::from(e)),
+    }
+}
+/// HCI transport over a controller using the WinUSB driver. See the module docs.
+#[derive(Debug)]
+pub struct WinUsbTransport {
+    transport: UsbTransport<Adapter>,
+}
+impl WinUsbTransport {
+    /// Resets the device and claims its HCI interface. Errors are classified like
+    /// [`classify_open_error`].
+    pub fn claim(mut handle: AsyncDevice) -> Result<WinUsbTransport, OpenError> {
+        let descriptor = handle
+            .device()
+            .device_descriptor()
+            .map_err(classify_open_error)?;
+        handle.handle_mut().reset().map_err(classify_open_error)?;
+        handle
+            .handle_mut()
+            .claim_interface(INTERFACE_NUM)
+            .map_err(classify_open_error)?;
+        Ok(WinUsbTransport {
+            transport: UsbTransport::new(Adapter::from_parts(descriptor, handle)),
+        })
+    }
+    pub fn transport(&self) -> &UsbTransport<Adapter> {
+        &self.transport
+    }
+    pub fn into_transport(self) -> UsbTransport<Adapter> {
+        self.transport
+    }
+}
+impl HCIReader for WinUsbTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        Pin::new(&mut self.get_mut().transport).poll_read(cx, buf)
+    }
+}
+impl HCIWriter for WinUsbTransport {
+    /// See [`UsbTransport`]'s `poll_write`.
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        Pin::new(&mut self.get_mut().transport).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), adapter::Error>> {
+        Pin::new(&mut self.get_mut().transport).poll_flush(cx)
+    }
+}
@@ -0,0 +1,475 @@
+//! One-stop host setup. [`Host::builder`] collects the transport, unrecognized event handler, bond
+//! store, [`SecurityConfig`], crypto backend, event masks, ACL buffer sizes, command timer and
+//! startup toggles in one place and [`HostBuilder::start`] checks the configuration and brings the controller up
+//! (reset, event masks, buffer sizes and ACL flow control) so the [`Host`] is ready to scan,
+//! advertise and connect through its [`Central`] and [`Peripheral`] handles. With
+//! [`HostBuilder::retry`], commands (the startup ones included) are retried on transient transport
+//! errors.
+//!
+//! ```ignore
+//! let mut host = Host::builder(stream)
+//!     .bond_store(FlashBonds::open()?)
+//!     .security(SecurityConfig::new().bondable(true))
+//!     .start()
+//!     .await?;
+//! let _scanning = host.le().start_scanning(true).await?;
+//! ```
+use crate::hci::adapter;
+use crate::hci::adapters::le::LEAdapter;
+use crate::hci::adapters::{Adapter, DummyUnrecognizedEventHandler, UnrecognizedEventHandler};
+use crate::hci::baseband::{EventMask, EventMaskFlags};
+use crate::hci::flow::DataCredits;
+use crate::hci::le::mask::MetaEventMask;
+use crate::hci::retry::{RetryHooks, RetryPolicy};
+use crate::hci::timer::Timer;
+use crate::le::advertiser::Advertiser;
+use crate::le::connection::central::Central;
+use crate::le::connection::peripheral::Peripheral;
+use crate::le::crypto::CryptoBackend;
+use crate::le::identity::IdentityResolver;
+use crate::le::privacy::ResolvingList;
+use crate::le::scan::Observer;
+use crate::le::smp::bond::{BondStore, MemoryBondStore};
+use crate::le::smp::{SecurityConfig, SecurityConfigError};
+use core::time::Duration;
+
+/// Meta events enabled by default (the controller's default mask: connection complete,
+/// advertising report, connection update complete, read remote features and LTK request).
+const DEFAULT_META_EVENT_MASK: u64 = 0x1F;
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum HostError {
+    Security(SecurityConfigError),
+    Adapter(adapter::Error),
+    /// The controller reports no ACL buffers (neither LE nor shared BR/EDR ones) and none were
+    /// configured with [`HostBuilder::acl_buffers`].
+    NoACLBuffers,
+}
+impl From<SecurityConfigError> for HostError {
+    fn from(e: SecurityConfigError) -> Self {
+        HostError::Security(e)
+    }
+}
+impl From<adapter::Error> for HostError {
+    fn from(e: adapter::Error) -> Self {
+        HostError::Adapter(e)
+    }
+}
+impl core::fmt::Display for HostError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "host setup error {:?}", self)
+    }
+}
+impl crate::error::Error for HostError {}
+#[cfg(feature = "std")]
+impl std::error::Error for HostError {}
+/// ACL buffers of the controller used for LE (the shared BR/EDR ones if it has no LE buffers).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ACLBuffers {
+    /// Max ACL data packet payload length.
+    pub packet_len: u16,
+    pub packet_count: u16,
+}
+/// Configuration of a [`Host`]. Made with [`Host::builder`].
+pub struct HostBuilder<A: adapter::Adapter, H: UnrecognizedEventHandler, S: BondStore> {
+    adapter: A,
+    event_handler: H,
+    bonds: S,
+    security: SecurityConfig,
+    reset: bool,
+    event_mask: EventMask,
+    meta_event_mask: MetaEventMask,
+    acl_buffers: Option<ACLBuffers>,
+    resolving_list: ResolvingList,
+    retry: Option<(RetryPolicy, Box<dyn RetryHooks<A> + Send>)>,
+    command_timeout: Option<(Box<dyn Timer + Send>, Duration)>,
+    crypto: Option<Box<dyn CryptoBackend + Send>>,
+}
+impl<A: adapter::Adapter, H: UnrecognizedEventHandler, S: BondStore> HostBuilder<A, H, S> {
+    #[must_use]
+    pub fn event_handler<H2: UnrecognizedEventHandler>(
+        self,
+        event_handler: H2,
+    ) -> HostBuilder<A, H2, S> {
+        HostBuilder {
+            adapter: self.adapter,
+            event_handler,
+            bonds: self.bonds,
+            security: self.security,
+            reset: self.reset,
+            event_mask: self.event_mask,
+            meta_event_mask: self.meta_event_mask,
+            acl_buffers: self.acl_buffers,
+            resolving_list: self.resolving_list,
+            retry: self.retry,
+            command_timeout: self.command_timeout,
+            crypto: self.crypto,
+        }
+    }
+    #[must_use]
+    pub fn bond_store<S2: BondStore>(self, bonds: S2) -> HostBuilder<A, H, S2> {
+        HostBuilder {
+            adapter: self.adapter,
+            event_handler: self.event_handler,
+            bonds,
+            security: self.security,
+            reset: self.reset,
+            event_mask: self.event_mask,
+            meta_event_mask: self.meta_event_mask,
+            acl_buffers: self.acl_buffers,
+            resolving_list: self.resolving_list,
+            retry: self.retry,
+            command_timeout: self.command_timeout,
+            crypto: self.crypto,
+        }
+    }
+    /// Checked by [`HostBuilder::start`].
+    #[must_use]
+    pub fn security(mut self, security: SecurityConfig) -> Self {
+        self.security = security;
+        self
+    }
+    /// Reset the controller on start (the default).
+    #[must_use]
+    pub fn reset(mut self, reset: bool) -> Self {
+        self.reset = reset;
+        self
+    }
+    /// `LEMetaEvent` is always added.
+    #[must_use]
+    pub fn event_mask(mut self, event_mask: EventMask) -> Self {
+        self.event_mask = event_mask;
+        self
+    }
+    #[must_use]
+    pub fn meta_event_mask(mut self, meta_event_mask: MetaEventMask) -> Self {
+        self.meta_event_mask = meta_event_mask;
+        self
+    }
+    /// Uses these ACL buffer sizes instead of reading them from the controller.
+    #[must_use]
+    pub fn acl_buffers(mut self, acl_buffers: ACLBuffers) -> Self {
+        self.acl_buffers = Some(acl_buffers);
+        self
+    }
+    /// Local IRK and peer entries used to resolve peer addresses.
+    #[must_use]
+    pub fn resolving_list(mut self, resolving_list: ResolvingList) -> Self {
+        self.resolving_list = resolving_list;
+        self
+    }
+    /// Retries commands failing with transient transport errors (see [`crate::hci::retry`]).
+    #[must_use]
+    pub fn retry<R: RetryHooks<A> + Send + 'static>(
+        mut self,
+        policy: RetryPolicy,
+        hooks: R,
+    ) -> Self {
+        self.retry = Some((policy, Box::new(hooks)));
+        self
+    }
+    /// Gives up on commands (the startup ones included) whose return doesn't arrive within
+    /// `duration`. See [`Adapter::set_command_timeout`].
+    #[must_use]
+    pub fn command_timeout<T: Timer + Send + 'static>(
+        mut self,
+        timer: T,
+        duration: Duration,
+    ) -> Self {
+        self.command_timeout = Some((Box::new(timer), duration));
+        self
+    }
+    /// Random source (and AES) for the security manager. See [`Host::crypto`].
+    #[must_use]
+    pub fn crypto<C: CryptoBackend + Send + 'static>(mut self, crypto: C) -> Self {
+        self.crypto = Some(Box::new(crypto));
+        self
+    }
+    /// Checks the configuration and sets the controller up.
+    pub async fn start(self) -> Result<Host<A, H, S>, HostError> {
+        let security = self.security.build()?;
+        let mut adapter = Adapter::new_with_handler(self.adapter, self.event_handler);
+        if let Some((policy, hooks)) = self.retry {
+            adapter.set_retry_boxed(policy, hooks);
+        }
+        if let Some((timer, duration)) = self.command_timeout {
+            adapter.set_command_timeout(timer, duration);
+        }
+        let mut le = LEAdapter::new(adapter);
+        if self.reset {
+            le.adapter.reset().await?;
+        }
+        let mut event_mask = self.event_mask;
+        event_mask.enable_event(EventMaskFlags::LEMetaEvent);
+        le.adapter.set_event_mask(event_mask).await?;
+        le.set_meta_event_mask(self.meta_event_mask).await?;
+        let acl_buffers = match self.acl_buffers {
+            Some(acl_buffers) => acl_buffers,
+            None => read_acl_buffers(&mut le).await?,
+        };
+        le.adapter
+            .set_acl_credits(Some(DataCredits::new(acl_buffers.packet_count)));
+        Ok(Host {
+            le,
+            bonds: self.bonds,
+            security,
+            acl_buffers,
+            resolving_list: self.resolving_list,
+            identities: IdentityResolver::new(),
+            crypto: self.crypto,
+        })
+    }
+}
+/// LE buffers, or the shared BR/EDR ones if the controller has no LE buffers.
+async fn read_acl_buffers<A: adapter::Adapter, H: UnrecognizedEventHandler>(
+    le: &mut LEAdapter<A, H>,
+) -> Result<ACLBuffers, HostError> {
+    let size = le.read_buffer_size_v1().await?;
+    let buffers = if size.le_acl_data_packet_len == 0 || size.total_num_le_acl_data_packets == 0 {
+        let size = le.adapter.read_buffer_size().await?;
+        ACLBuffers {
+            packet_len: size.acl_data_packet_len,
+            packet_count: size.total_num_acl_data_packets,
+        }
+    } else {
+        ACLBuffers {
+            packet_len: size.le_acl_data_packet_len,
+            packet_count: size.total_num_le_acl_data_packets.into(),
+        }
+    };
+    if buffers.packet_len == 0 || buffers.packet_count == 0 {
+        return Err(HostError::NoACLBuffers);
+    }
+    Ok(buffers)
+}
+/// A started host. See the module docs.
+pub struct Host<A: adapter::Adapter, H: UnrecognizedEventHandler, S: BondStore> {
+    le: LEAdapter<A, H>,
+    bonds: S,
+    security: SecurityConfig,
+    acl_buffers: ACLBuffers,
+    resolving_list: ResolvingList,
+    identities: IdentityResolver,
+    crypto: Option<Box<dyn CryptoBackend + Send>>,
+}
+impl<A: adapter::Adapter> Host<A, DummyUnrecognizedEventHandler, MemoryBondStore> {
+    /// Builder with an in memory bond store, non-bondable Just Works security, the default event
+    /// masks and a reset on start.
+    pub fn builder(adapter: A) -> HostBuilder<A, DummyUnrecognizedEventHandler, MemoryBondStore> {
+        HostBuilder {
+            adapter,
+            event_handler: DummyUnrecognizedEventHandler::new(),
+            bonds: MemoryBondStore::new(),
+            security: SecurityConfig::new(),
+            reset: true,
+            event_mask: EventMask::DEFAULT,
+            meta_event_mask: MetaEventMask::new(DEFAULT_META_EVENT_MASK),
+            acl_buffers: None,
+            resolving_list: ResolvingList::new(None, true),
+            retry: None,
+            command_timeout: None,
+            crypto: None,
+        }
+    }
+}
+impl<A: adapter::Adapter, H: UnrecognizedEventHandler, S: BondStore> Host<A, H, S> {
+    /// The LE adapter for everything the handles don't cover.
+    pub fn le(&mut self) -> &mut LEAdapter<A, H> {
+        &mut self.le
+    }
+    pub fn scanner(&mut self) -> &mut impl Observer {
+        &mut self.le
+    }
+    pub fn advertiser(&mut self) -> &mut impl Advertiser {
+        &mut self.le
+    }
+    pub fn central(&mut self) -> Central<'_, A, H> {
+        Central::new(&mut self.le)
+    }
+    pub fn peripheral(&mut self) -> Peripheral<'_, A, H> {
+        Peripheral::new(&mut self.le)
+    }
+    pub fn security(&self) -> &SecurityConfig {
+        &self.security
+    }
+    pub fn acl_buffers(&self) -> ACLBuffers {
+        self.acl_buffers
+    }
+    pub fn bonds(&self) -> &S {
+        &self.bonds
+    }
+    pub fn bonds_mut(&mut self) -> &mut S {
+        &mut self.bonds
+    }
+    pub fn resolving_list(&self) -> &ResolvingList {
+        &self.resolving_list
+    }
+    pub fn resolving_list_mut(&mut self) -> &mut ResolvingList {
+        &mut self.resolving_list
+    }
+    /// Connections (and their peer identities) seen so far. Feed it events with
+    /// [`IdentityResolver::tag_event`] and [`Host::resolving_list`].
+    pub fn identities(&mut self) -> &mut IdentityResolver {
+        &mut self.identities
+    }
+    /// The backend set with [`HostBuilder::crypto`], if any.
+    pub fn crypto(&mut self) -> Option<&mut (dyn CryptoBackend + Send + 'static)> {
+        self.crypto.as_deref_mut()
+    }
+    pub fn into_parts(self) -> (LEAdapter<A, H>, S) {
+        (self.le, self.bonds)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::baseband::{Reset, SetEventMask};
+    use crate::hci::command::Command;
+    use crate::hci::informational::ReadBufferSize;
+    use crate::hci::io::HCIPort;
+    use crate::hci::le::connection::{CreateConnectionCancel, ReadBufferSizeV1};
+    use crate::hci::le::mask::SetMetaEventMask;
+    use crate::hci::stream::Stream;
+    use crate::hci::StreamError;
+    use crate::le::smp::IOCapability;
+    use crate::testing::mock::{command_complete, MockTransport};
+    use crate::testing::virtual_controller::VirtualController;
+    use crate::BTAddress;
+    use futures_util::future::LocalBoxFuture;
+    use futures_util::FutureExt;
+
+    /// Not random at all.
+    struct Counter(u8);
+    impl CryptoBackend for Counter {
+        fn fill_random(&mut self, buf: &mut [u8]) {
+            for byte in buf {
+                *byte = self.0;
+                self.0 = self.0.wrapping_add(1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_builder() {
+        let (controller, handle) = VirtualController::new(BTAddress([1, 2, 3, 4, 5, 6]));
+        let error = Host::builder(Stream::new(Box::pin(controller)))
+            .security(SecurityConfig::new().mitm(true))
+            .start()
+            .now_or_never()
+            .unwrap()
+            .err();
+        assert_eq!(
+            error,
+            Some(HostError::Security(SecurityConfigError::MITMUnsatisfiable))
+        );
+        assert!(handle.commands().is_empty());
+
+        let (controller, handle) = VirtualController::new(BTAddress([1, 2, 3, 4, 5, 6]));
+        let buffers = ACLBuffers {
+            packet_len: 251,
+            packet_count: 4,
+        };
+        let mut host = Host::builder(Stream::new(Box::pin(controller)))
+            .security(
+                SecurityConfig::new()
+                    .io_capability(IOCapability::DisplayOnly)
+                    .mitm(true),
+            )
+            .acl_buffers(buffers)
+            .crypto(Counter(0))
+            .start()
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(handle.commands().len(), 3);
+        assert_eq!(host.acl_buffers(), buffers);
+        assert_eq!(
+            host.le().adapter.acl_credits().map(DataCredits::total),
+            Some(4)
+        );
+        host.scanner()
+            .set_scan_enable(true, false)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(handle.is_scanning());
+        let mut random = [0_u8; 3];
+        host.crypto().unwrap().fill_random(&mut random);
+        assert_eq!(random, [0, 1, 2]);
+    }
+    #[test]
+    fn test_shared_acl_buffers() {
+        let (transport, handle) = MockTransport::new();
+        for (opcode, parameters) in [
+            (Reset::opcode(), &[0x00][..]),
+            (SetEventMask::opcode(), &[0x00]),
+            (SetMetaEventMask::opcode(), &[0x00]),
+            // No LE buffers.
+            (ReadBufferSizeV1::opcode(), &[0x00, 0x00, 0x00, 0x00]),
+            (
+                ReadBufferSize::opcode(),
+                &[0x00, 0x1B, 0x00, 0x40, 0x06, 0x00, 0x00, 0x00],
+            ),
+        ] {
+            handle.inject(&command_complete(opcode, parameters));
+        }
+        let mut host = Host::builder(HCIPort::new(transport))
+            .start()
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            host.acl_buffers(),
+            ACLBuffers {
+                packet_len: 27,
+                packet_count: 6,
+            }
+        );
+        assert_eq!(
+            host.le().adapter.acl_credits().map(DataCredits::total),
+            Some(6)
+        );
+        handle.take_written();
+        handle.expect_command(
+            &CreateConnectionCancel {},
+            &[&command_complete(CreateConnectionCancel::opcode(), &[0x00])],
+        );
+        assert_eq!(
+            host.central().cancel_connect().now_or_never().unwrap(),
+            Ok(())
+        );
+
+        let (transport, handle) = MockTransport::new();
+        handle.inject(&command_complete(SetEventMask::opcode(), &[0x00]));
+        handle.inject(&command_complete(SetMetaEventMask::opcode(), &[0x00]));
+        handle.inject(&command_complete(
+            ReadBufferSizeV1::opcode(),
+            &[0x00, 0x00, 0x00, 0x00],
+        ));
+        handle.inject(&command_complete(ReadBufferSize::opcode(), &[0x00; 8]));
+        let error = Host::builder(HCIPort::new(transport))
+            .reset(false)
+            .start()
+            .now_or_never()
+            .unwrap()
+            .err();
+        assert_eq!(error, Some(HostError::NoACLBuffers));
+    }
+    #[test]
+    fn test_command_timeout() {
+        /// Fires as soon as it's polled.
+        struct Expired;
+        impl Timer for Expired {
+            fn delay(&mut self, _duration: Duration) -> LocalBoxFuture<'static, ()> {
+                Box::pin(futures_util::future::ready(()))
+            }
+        }
+        let (transport, _handle) = MockTransport::new();
+        let error = Host::builder(HCIPort::new(transport))
+            .command_timeout(Expired, Duration::from_secs(1))
+            .start()
+            .now_or_never()
+            .unwrap()
+            .err();
+        assert_eq!(error, Some(HostError::Adapter(StreamError::Timeout.into())));
+    }
+}
@@ -0,0 +1,247 @@
+//! Adaptive scanning. Backs off the scan duty cycle when no new devices are being discovered and
+//! ramps it back up once discovery activity resumes. Saves a lot of power on battery powered
+//! centrals that scan continuously.
+use crate::hci::adapter;
+use crate::le::scan::{Observer, ScanInterval, ScanParameters, ScanWindow};
+use crate::BTAddress;
+use alloc::collections::BTreeMap;
+use core::time::Duration;
+
+/// Discovery activity seen by [`AdaptiveScanner::poll`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct ScanActivity {
+    /// Number of never seen before devices since the last poll.
+    pub new_devices: usize,
+    /// Time since the last new device was seen.
+    pub idle: Duration,
+    /// Time since the scan parameters were last changed.
+    pub since_change: Duration,
+}
+/// Decides the scan parameters based on discovery activity. Implement this to customize how
+/// [`AdaptiveScanner`] adapts.
+pub trait AdaptationPolicy {
+    /// Returns the new scan parameters or `None` to keep using `current`.
+    fn adapt(
+        &mut self,
+        current: &ScanParameters,
+        activity: &ScanActivity,
+    ) -> Option<ScanParameters>;
+}
+/// Default [`AdaptationPolicy`]. Uses `active` parameters while devices are being discovered and
+/// doubles the scan interval (keeping the window) every `idle_after` without any new devices, up
+/// to `max_interval`.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct BackoffPolicy {
+    pub active: ScanParameters,
+    pub idle_after: Duration,
+    pub max_interval: ScanInterval,
+}
+impl BackoffPolicy {
+    pub const DEFAULT_IDLE_AFTER: Duration = Duration::from_secs(10);
+    pub fn new(active: ScanParameters) -> BackoffPolicy {
+        BackoffPolicy {
+            active,
+            idle_after: Self::DEFAULT_IDLE_AFTER,
+            max_interval: ScanInterval::MAX,
+        }
+    }
+    fn backoff(&self, current: ScanParameters) -> Option<ScanParameters> {
+        let interval = u16::from(current.scan_interval);
+        let max = u16::from(self.max_interval);
+        if interval >= max {
+            return None;
+        }
+        let new_interval = interval.saturating_mul(2).min(max);
+        let window = u16::from(current.scan_window).min(new_interval);
+        Some(ScanParameters {
+            scan_interval: ScanInterval::new(new_interval),
+            scan_window: ScanWindow::new(window),
+            ..current
+        })
+    }
+}
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        BackoffPolicy::new(ScanParameters::DEFAULT)
+    }
+}
+impl AdaptationPolicy for BackoffPolicy {
+    fn adapt(
+        &mut self,
+        current: &ScanParameters,
+        activity: &ScanActivity,
+    ) -> Option<ScanParameters> {
+        if activity.new_devices > 0 {
+            if *current == self.active {
+                None
+            } else {
+                Some(self.active)
+            }
+        } else if activity.idle >= self.idle_after && activity.since_change >= self.idle_after {
+            self.backoff(*current)
+        } else {
+            None
+        }
+    }
+}
+/// Tracks discovered devices and feeds the resulting [`ScanActivity`] into an
+/// [`AdaptationPolicy`]. Time is any monotonic `Duration` since a fixed epoch supplied by the
+/// caller.
+pub struct AdaptiveScanner<P: AdaptationPolicy = BackoffPolicy> {
+    policy: P,
+    current: ScanParameters,
+    /// Last time each device was seen.
+    seen: BTreeMap<BTAddress, Duration>,
+    max_devices: usize,
+    new_devices: usize,
+    last_new_device: Duration,
+    last_change: Duration,
+}
+impl<P: AdaptationPolicy> AdaptiveScanner<P> {
+    /// Devices remembered by default. Dense environments have more, see
+    /// [`AdaptiveScanner::set_max_devices`].
+    pub const DEFAULT_MAX_DEVICES: usize = 256;
+    pub fn new(policy: P, initial: ScanParameters, now: Duration) -> Self {
+        AdaptiveScanner {
+            policy,
+            current: initial,
+            seen: BTreeMap::new(),
+            max_devices: Self::DEFAULT_MAX_DEVICES,
+            new_devices: 0,
+            last_new_device: now,
+            last_change: now,
+        }
+    }
+    /// The scan parameters currently in use.
+    pub fn current(&self) -> &ScanParameters {
+        &self.current
+    }
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+    /// Number of unique devices seen so far.
+    pub fn devices_seen(&self) -> usize {
+        self.seen.len()
+    }
+    /// Forget all seen devices so they count as new again.
+    pub fn forget_devices(&mut self) {
+        self.seen.clear();
+    }
+    /// Most devices remembered. Once full the device seen least recently is forgotten (and counts
+    /// as new if it shows up again).
+    pub fn set_max_devices(&mut self, max_devices: usize) {
+        self.max_devices = max_devices.max(1);
+        while self.seen.len() > self.max_devices {
+            self.forget_oldest();
+        }
+    }
+    /// Record an advertisement from `address`. Returns `true` if the device wasn't seen before.
+    pub fn report(&mut self, address: BTAddress, now: Duration) -> bool {
+        if let Some(last_seen) = self.seen.get_mut(&address) {
+            *last_seen = now;
+            return false;
+        }
+        if self.seen.len() >= self.max_devices {
+            self.forget_oldest();
+        }
+        self.seen.insert(address, now);
+        self.new_devices += 1;
+        self.last_new_device = now;
+        true
+    }
+    fn forget_oldest(&mut self) {
+        if let Some(oldest) = self
+            .seen
+            .iter()
+            .min_by_key(|(_, &last_seen)| last_seen)
+            .map(|(&address, _)| address)
+        {
+            self.seen.remove(&oldest);
+        }
+    }
+    /// Current discovery activity.
+    pub fn activity(&self, now: Duration) -> ScanActivity {
+        ScanActivity {
+            new_devices: self.new_devices,
+            idle: now.checked_sub(self.last_new_device).unwrap_or_default(),
+            since_change: now.checked_sub(self.last_change).unwrap_or_default(),
+        }
+    }
+    /// Ask the policy if the scan parameters should change. Returns the new parameters if they
+    /// did. The caller is responsible for applying them (see [`AdaptiveScanner::apply`]).
+    pub fn poll(&mut self, now: Duration) -> Option<ScanParameters> {
+        let activity = self.activity(now);
+        self.new_devices = 0;
+        let new_parameters = self.policy.adapt(&self.current, &activity)?;
+        self.current = new_parameters;
+        self.last_change = now;
+        Some(new_parameters)
+    }
+    /// Polls the policy and restarts scanning on `observer` with the new parameters if they
+    /// changed. Returns `true` if scanning was restarted.
+    pub async fn apply<O: Observer>(
+        &mut self,
+        observer: &mut O,
+        now: Duration,
+        filter_duplicates: bool,
+    ) -> Result<bool, adapter::Error> {
+        match self.poll(now) {
+            Some(parameters) => {
+                observer.set_scan_enable(false, filter_duplicates).await?;
+                observer.set_scan_parameters(parameters).await?;
+                observer.set_scan_enable(true, filter_duplicates).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+    #[test]
+    fn test_backoff() {
+        let active = ScanParameters::DEFAULT;
+        let mut scanner = AdaptiveScanner::new(BackoffPolicy::new(active), active, secs(0));
+        // New devices while already active don't change anything (or keep back-off away).
+        assert!(scanner.report(BTAddress::from_u64(1), secs(1)));
+        assert!(!scanner.report(BTAddress::from_u64(1), secs(2)));
+        assert_eq!(scanner.poll(secs(2)), None);
+        assert_eq!(scanner.poll(secs(5)), None);
+        // Idle for `idle_after`.
+        let backed_off = scanner.poll(secs(11)).unwrap();
+        assert_eq!(u16::from(backed_off.scan_interval), 0x0020);
+        assert_eq!(backed_off.scan_window, active.scan_window);
+        assert_eq!(scanner.poll(secs(15)), None);
+        assert_eq!(
+            scanner.poll(secs(21)).map(|p| u16::from(p.scan_interval)),
+            Some(0x0040)
+        );
+        // Activity resumes.
+        assert!(scanner.report(BTAddress::from_u64(2), secs(22)));
+        assert_eq!(scanner.poll(secs(22)), Some(active));
+        assert_eq!(scanner.poll(secs(31)), None);
+        assert!(scanner.poll(secs(32)).is_some());
+    }
+    #[test]
+    fn test_max_devices() {
+        let mut scanner =
+            AdaptiveScanner::new(BackoffPolicy::default(), ScanParameters::DEFAULT, secs(0));
+        scanner.set_max_devices(2);
+        assert!(scanner.report(BTAddress::from_u64(1), secs(1)));
+        assert!(scanner.report(BTAddress::from_u64(2), secs(2)));
+        assert!(!scanner.report(BTAddress::from_u64(1), secs(3)));
+        // 2 was seen least recently.
+        assert!(scanner.report(BTAddress::from_u64(3), secs(4)));
+        assert_eq!(scanner.devices_seen(), 2);
+        assert!(!scanner.report(BTAddress::from_u64(1), secs(5)));
+        assert!(scanner.report(BTAddress::from_u64(2), secs(6)));
+    }
+}
@@ -1,6 +1,18 @@
 //! BLE Advertisements. Provides processing of Advertisement Structs.
+#![forbid(unsafe_code)]
+#![cfg_attr(
+    all(feature = "panic-free", not(test)),
+    deny(
+        clippy::indexing_slicing,
+        clippy::expect_used,
+        clippy::unwrap_used,
+        clippy::panic,
+        clippy::unreachable
+    )
+)]
 
-use crate::bytes::{StaticBuf, Storage};
+use crate::bytes::{HexDump, StaticBuf, Storage};
+use crate::strictness::Strictness;
 use crate::PackError;
 use core::convert::TryFrom;
 use core::mem;
@@ -53,6 +65,7 @@ pub enum AdType {
     MeshBeacon = 0x2B,
     BIGInfo = 0x2C,
     BroadcastCode = 0x2D,
+    ResolvableSetIdentifier = 0x2E,
     Information3DData = 0x3D,
     ManufacturerData = 0xFF,
 }
@@ -108,6 +121,7 @@ impl TryFrom<u8> for AdType {
             0x2B => Ok(AdType::MeshBeacon),
             0x2C => Ok(AdType::BIGInfo),
             0x2D => Ok(AdType::BroadcastCode),
+            0x2E => Ok(AdType::ResolvableSetIdentifier),
             0x3D => Ok(AdType::Information3DData),
             0xFF => Ok(AdType::ManufacturerData),
             _ => Err(AdStructureError(())),
@@ -152,6 +166,12 @@ impl<StructBuf: AsRef<[u8]>> RawAdStructureBuffer<StructBuf> {
         }
     }
 }
+/// `AdType: data` with the data hex dumped (see [`HexDump`]).
+impl<StructBuf: AsRef<[u8]>> core::fmt::Display for RawAdStructureBuffer<StructBuf> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}: {}", self.ad_type, HexDump::new(self.buf.as_ref()))
+    }
+}
 impl<StructBuf: AsRef<[u8]>> AdStructureType for RawAdStructureBuffer<StructBuf> {
     fn ad_type(&self) -> AdType {
         self.ad_type
@@ -198,21 +218,28 @@ impl RawAdvertisement<StaticAdvBuffer> {
             });
         }
         self.0.resize(current_len + total_struct_len);
-        let len = ad_struct.byte_len();
+        // The length field counts the AdType too.
+        let len = ad_struct.byte_len() + 1;
         // The AdStruct byte len should always be less than MAX_AD_LEN (30) and so it should always
         // be able to fit in a u8. If the usize -> u8 conversion fails, then theres something really
         // wrong with the ad structure.
         let len_u8 = u8::try_from(len).map_err(|_| PackError::InvalidFields)?;
-        ad_struct.pack_into(&mut self.0.as_mut()[current_len + 2..])?;
-        self.0.as_mut()[current_len] = len_u8;
-        self.0.as_mut()[current_len + 1] = ad_struct.ad_type().into();
-        Ok(())
+        match self.0.as_mut().get_mut(current_len..) {
+            Some([struct_len, ad_type, rest @ ..]) => {
+                ad_struct.pack_into(rest)?;
+                *struct_len = len_u8;
+                *ad_type = ad_struct.ad_type().into();
+                Ok(())
+            }
+            _ => Err(PackError::bad_index(current_len)),
+        }
     }
 }
 impl<Buf: AsRef<[u8]>> RawAdvertisement<Buf> {
     pub fn iter(&self) -> AdStructureIterator<'_> {
         AdStructureIterator {
             data: self.as_ref(),
+            strictness: Strictness::default(),
         }
     }
 }
@@ -221,40 +248,67 @@ impl<Buf: AsRef<[u8]>> AsRef<[u8]> for RawAdvertisement<Buf> {
         self.0.as_ref()
     }
 }
+/// Every AD structure (see [`RawAdStructureBuffer`]'s `Display`) separated by `, `.
+impl<Buf: AsRef<[u8]>> core::fmt::Display for RawAdvertisement<Buf> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, ad_struct) in self.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", ad_struct)?;
+        }
+        Ok(())
+    }
+}
 
 pub struct OutgoingAdvertisement {
     adv: RawAdvertisement,
 }
+/// Stops at the first malformed AD structure. With a tolerant [`Strictness`] (see
+/// [`AdStructureIterator::with_strictness`]) AD structures with an unknown type or too much
+/// data are skipped instead. A length running past the end always stops it.
 pub struct AdStructureIterator<'a> {
     data: &'a [u8],
+    strictness: Strictness,
+}
+impl<'a> AdStructureIterator<'a> {
+    #[must_use]
+    pub fn with_strictness(self, strictness: Strictness) -> AdStructureIterator<'a> {
+        AdStructureIterator { strictness, ..self }
+    }
 }
 
 impl<'a> Iterator for AdStructureIterator<'a> {
     type Item = RawAdStructureBuffer;
 
     fn next(&mut self) -> Option<RawAdStructureBuffer> {
-        if self.data.len() < 2 {
-            return None;
-        }
-        let d = mem::replace(&mut self.data, &[]);
-        let len = usize::from(d[0]);
-        if len + 1 > d.len() {
-            return None;
+        loop {
+            let d = mem::replace(&mut self.data, &[]);
+            let (&len, rest) = d.split_first()?;
+            let ad_struct = rest.get(..usize::from(len))?;
+            self.data = rest.get(usize::from(len)..)?;
+            // Drop the ad_type from the front of the ad structure.
+            let (&ad_type, data) = ad_struct.split_first()?;
+            let parsed = AdType::try_from(ad_type).ok().and_then(|ad_type| {
+                Some(RawAdStructureBuffer::new(
+                    ad_type,
+                    StaticAdvStructBuf::try_from_slice(data)?,
+                ))
+            });
+            match parsed {
+                Some(ad_struct) => return Some(ad_struct),
+                None => self
+                    .strictness
+                    .violation(PackError::BadBytes { index: None })
+                    .ok()?,
+            }
         }
-        let (data, rest) = d.split_at(len + 1);
-        self.data = rest;
-        let ad_type = AdType::try_from(data[1]).ok()?;
-        // Drop the len and ad_type from the front of the ad structure.
-        let data = &data[2..];
-        Some(RawAdStructureBuffer::new(
-            ad_type,
-            StaticAdvStructBuf::from_slice(data),
-        ))
     }
 }
 #[cfg(test)]
 mod tests {
-    use super::AdType;
+    use super::{AdType, RawAdvertisement};
+    use crate::strictness::Strictness;
     use core::convert::TryFrom;
     #[test]
     fn test_ad_type_try_into() {
@@ -265,4 +319,16 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn test_skip_unknown_ad_type() {
+        // Unknown type 0x00 between Flags and Shortened Local Name.
+        let adv = RawAdvertisement(&[0x02, 0x01, 0x06, 0x02, 0x00, 0xFF, 0x02, 0x08, b'a'][..]);
+        assert_eq!(adv.iter().count(), 1);
+        let types: Vec<AdType> = adv
+            .iter()
+            .with_strictness(Strictness::Permissive)
+            .map(|ad_struct| ad_struct.ad_type)
+            .collect();
+        assert_eq!(types, [AdType::Flags, AdType::ShortenLocalName]);
+    }
 }
@@ -1,7 +1,8 @@
 //! Generic BLE Advertiser (WIP)
 use crate::hci::adapter;
-use crate::BTAddress;
+pub use crate::le::scan::OwnAddressType;
 use crate::ConversionError;
+use crate::{BTAddress, PackError};
 use core::convert::TryFrom;
 use futures_util::future::LocalBoxFuture;
 use std::convert::TryInto;
@@ -145,39 +146,6 @@ impl TryFrom<u8> for PeerAddressType {
     }
 }
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
-pub enum OwnAddressType {
-    PublicDevice = 0x00,
-    RandomDevice = 0x01,
-    PrivateOrPublic = 0x02,
-    PrivateOrRandom = 0x03,
-}
-impl OwnAddressType {
-    pub const DEFAULT: OwnAddressType = OwnAddressType::PublicDevice;
-}
-impl Default for OwnAddressType {
-    fn default() -> Self {
-        Self::DEFAULT
-    }
-}
-impl From<OwnAddressType> for u8 {
-    fn from(t: OwnAddressType) -> Self {
-        t as u8
-    }
-}
-impl TryFrom<u8> for OwnAddressType {
-    type Error = ConversionError;
-
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            0x00 => Ok(OwnAddressType::PublicDevice),
-            0x01 => Ok(OwnAddressType::RandomDevice),
-            0x02 => Ok(OwnAddressType::PrivateOrPublic),
-            0x03 => Ok(OwnAddressType::PrivateOrRandom),
-            _ => Err(ConversionError(())),
-        }
-    }
-}
-#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
 pub enum Channels {
     Channel37 = 0x00,
     Channel38 = 0x01,
@@ -197,11 +165,15 @@ impl ChannelMap {
     pub const DEFAULT: ChannelMap = ChannelMap::ALL;
     /// Creates a new `ChannelMap`.
     /// # Panics
-    /// Panics if `map > u16::from(ChannelMap::ALL)`;
+    /// Panics if `map > u8::from(ChannelMap::ALL)`;
     pub fn new(map: u8) -> ChannelMap {
-        assert!(map > Self::ALL_U8, "invalid channel map {}", map);
+        assert!(map <= Self::ALL_U8, "invalid channel map {}", map);
         ChannelMap(map)
     }
+    /// `true` if no channel is enabled. Controllers reject empty channel maps.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
     pub fn enable_channel(&mut self, channel: Channels) {
         self.0 |= 1u8 << u8::from(channel);
     }
@@ -299,6 +271,29 @@ impl AdvertisingParameters {
         channel_map: ChannelMap::DEFAULT,
         filter_policy: FilterPolicy::DEFAULT,
     };
+    /// Checks the fields against the LE Set Advertising Parameters command limits. The interval
+    /// range isn't checked for high duty cycle directed advertising (the controller ignores it).
+    /// # Errors
+    /// returns `PackError::InvalidFields` if the interval range is inverted or too short for
+    /// non-connectable advertising or if the channel map is empty.
+    pub fn validate(&self) -> Result<(), PackError> {
+        if self.channel_map.is_empty() {
+            return Err(PackError::InvalidFields);
+        }
+        match self.advertising_type {
+            AdvertisingType::AdvDirectIndHighDutyCycle => return Ok(()),
+            AdvertisingType::AdvNonnConnInd
+                if self.interval_min < AdvertisingInterval::MIN_NON_CONN =>
+            {
+                return Err(PackError::InvalidFields)
+            }
+            _ => (),
+        }
+        if self.interval_max < self.interval_min {
+            return Err(PackError::InvalidFields);
+        }
+        Ok(())
+    }
     /// Creates a new `AdvertisingParameters` from `self` with `self.address` set to the
     /// `address` parameters.
     pub const fn with_address(self, address: BTAddress) -> AdvertisingParameters {
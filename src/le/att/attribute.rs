@@ -1,6 +1,9 @@
 use super::authentication;
 use crate::le::att::Opcode;
+use crate::le::service_filter::BASE_UUID;
 use crate::uuid;
+use crate::PackError;
+use core::convert::TryInto;
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
 pub struct Handle(u16);
@@ -22,6 +25,62 @@ impl From<Handle> for u16 {
 pub enum TypeUUID {
     UUID128(uuid::UUID),
     UUID32(uuid::UUID32),
+    UUID16(uuid::UUID16),
+}
+impl TypeUUID {
+    pub fn byte_len(self) -> usize {
+        match self {
+            TypeUUID::UUID128(_) => 16,
+            TypeUUID::UUID32(_) => 4,
+            TypeUUID::UUID16(_) => 2,
+        }
+    }
+    /// Packs the UUID in little endian.
+    pub fn pack_into(self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        match self {
+            TypeUUID::UUID128(u) => buf.copy_from_slice(u.as_ref()),
+            TypeUUID::UUID32(u) => buf.copy_from_slice(&u.0.to_le_bytes()),
+            TypeUUID::UUID16(u) => buf.copy_from_slice(&u.0.to_le_bytes()),
+        }
+        Ok(())
+    }
+    /// Unpacks a little endian 16 or 128-bit UUID (the sizes ATT PDUs carry).
+    pub fn unpack_from(buf: &[u8]) -> Result<TypeUUID, PackError> {
+        match buf.len() {
+            2 => Ok(TypeUUID::UUID16(uuid::UUID16(u16::from_le_bytes(
+                buf.try_into().expect("len checked above"),
+            )))),
+            16 => Ok(TypeUUID::UUID128(uuid::UUID(
+                buf.try_into().expect("len checked above"),
+            ))),
+            got => Err(PackError::BadLength { expected: 16, got }),
+        }
+    }
+    /// The UUID as a 128-bit number. 16 and 32-bit UUIDs are expanded with [`BASE_UUID`] so
+    /// different sizes of the same UUID compare equal.
+    pub fn as_u128(self) -> u128 {
+        match self {
+            TypeUUID::UUID128(u) => u128::from_le_bytes(u.0),
+            TypeUUID::UUID32(u) => BASE_UUID | (u128::from(u.0) << 96),
+            TypeUUID::UUID16(u) => BASE_UUID | (u128::from(u.0) << 96),
+        }
+    }
+}
+impl From<uuid::UUID16> for TypeUUID {
+    fn from(u: uuid::UUID16) -> Self {
+        TypeUUID::UUID16(u)
+    }
+}
+impl From<uuid::UUID32> for TypeUUID {
+    fn from(u: uuid::UUID32) -> Self {
+        TypeUUID::UUID32(u)
+    }
+}
+impl From<uuid::UUID> for TypeUUID {
+    fn from(u: uuid::UUID) -> Self {
+        TypeUUID::UUID128(u)
+    }
 }
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Default, Hash)]
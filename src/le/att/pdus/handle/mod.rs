@@ -0,0 +1,102 @@
+//! Handle Value Notification/Indication/Confirmation PDUs.
+use crate::bytes::Storage;
+use crate::le::att::attribute::{Handle, Value};
+use crate::le::att::pdus::{PackablePDU, UnpackablePDU};
+use crate::le::att::Opcode;
+use crate::PackError;
+use core::convert::TryInto;
+
+fn pack_handle_value(handle: Handle, value: &[u8], buf: &mut [u8]) -> Result<(), PackError> {
+    PackError::expect_length(2 + value.len(), buf)?;
+    buf[..2].copy_from_slice(&handle.inner().to_le_bytes());
+    buf[2..].copy_from_slice(value);
+    Ok(())
+}
+fn unpack_handle(buf: &[u8]) -> Result<Handle, PackError> {
+    if buf.len() < 2 {
+        return Err(PackError::BadLength {
+            expected: 2,
+            got: buf.len(),
+        });
+    }
+    Ok(Handle::new(u16::from_le_bytes(
+        (&buf[..2]).try_into().expect("len checked above"),
+    )))
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct HandleValueNtf<B> {
+    pub handle: Handle,
+    pub value: Value<B>,
+}
+impl<B: AsRef<[u8]>> PackablePDU for HandleValueNtf<B> {
+    const OPCODE: Opcode = Opcode::HandleValueNtf;
+
+    fn byte_len(&self) -> usize {
+        2 + self.value.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        pack_handle_value(self.handle, self.value.as_ref(), buf)
+    }
+}
+impl<B: Storage<u8>> UnpackablePDU for HandleValueNtf<B> {
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        Ok(HandleValueNtf {
+            handle: unpack_handle(buf)?,
+            value: Value::new(B::from_slice(&buf[2..])),
+        })
+    }
+}
+/// Same as [`HandleValueNtf`] but the client has to respond with a [`HandleValueCfm`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct HandleValueInd<B> {
+    pub handle: Handle,
+    pub value: Value<B>,
+}
+impl<B: AsRef<[u8]>> PackablePDU for HandleValueInd<B> {
+    const OPCODE: Opcode = Opcode::HandleValueInd;
+
+    fn byte_len(&self) -> usize {
+        2 + self.value.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        pack_handle_value(self.handle, self.value.as_ref(), buf)
+    }
+}
+impl<B: Storage<u8>> UnpackablePDU for HandleValueInd<B> {
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        Ok(HandleValueInd {
+            handle: unpack_handle(buf)?,
+            value: Value::new(B::from_slice(&buf[2..])),
+        })
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct HandleValueCfm;
+impl PackablePDU for HandleValueCfm {
+    const OPCODE: Opcode = Opcode::HandleValueCfm;
+
+    fn byte_len(&self) -> usize {
+        0
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(0, buf)
+    }
+}
+impl UnpackablePDU for HandleValueCfm {
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(0, buf)?;
+        Ok(HandleValueCfm)
+    }
+}
@@ -0,0 +1,65 @@
+//! Read Request/Response PDUs.
+use crate::bytes::Storage;
+use crate::le::att::attribute::{Handle, Value};
+use crate::le::att::pdus::{PackablePDU, Request, Response, UnpackablePDU};
+use crate::le::att::Opcode;
+use crate::PackError;
+use alloc::boxed::Box;
+use core::convert::TryInto;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ReadReq(pub Handle);
+impl ReadReq {
+    pub const BYTE_LEN: usize = 2;
+}
+impl PackablePDU for ReadReq {
+    const OPCODE: Opcode = Opcode::ReadReq;
+
+    fn byte_len(&self) -> usize {
+        Self::BYTE_LEN
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf.copy_from_slice(&self.0.inner().to_le_bytes());
+        Ok(())
+    }
+}
+impl UnpackablePDU for ReadReq {
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        Ok(ReadReq(Handle::new(u16::from_le_bytes(
+            buf.try_into().expect("length checked above"),
+        ))))
+    }
+}
+impl Request for ReadReq {
+    type Response = ReadRsp<Box<[u8]>>;
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ReadRsp<B>(pub Value<B>);
+impl<B: AsRef<[u8]>> PackablePDU for ReadRsp<B> {
+    const OPCODE: Opcode = Opcode::ReadRsp;
+
+    fn byte_len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.0.len(), buf)?;
+        buf.copy_from_slice(self.0.as_ref());
+        Ok(())
+    }
+}
+impl<B: Storage<u8>> UnpackablePDU for ReadRsp<B> {
+    fn unpack_from(buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        Ok(ReadRsp(Value::new(B::from_slice(buf))))
+    }
+}
+impl<B: AsRef<[u8]>> Response for ReadRsp<B> {}
@@ -0,0 +1,607 @@
+//! Audio Stream Control Service (ASCS). Every Audio Stream Endpoint (ASE) of a server is a Sink
+//! or Source ASE characteristic holding its state. The client moves ASEs through the state
+//! machine (codec configured, QoS configured, enabling, streaming, ..) by writing operations to
+//! the ASE Control Point and learns the outcome from notifications of both.
+//!
+//! [`ASCSClient`] keeps the last known state of every ASE and refuses operations the state
+//! machine doesn't allow before anything is sent.
+use crate::hci::definitions::HCIField;
+use crate::hci::informational::CodecId;
+use crate::le::att::attribute::Handle;
+use crate::le::audio::{u24_bytes, u24_from};
+use crate::le::gatt::client::{AttributeIndex, ClientError};
+use crate::uuid::UUID16;
+use crate::{ConversionError, PackError};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+pub const AUDIO_STREAM_CONTROL_SERVICE: UUID16 = UUID16(0x184E);
+pub const SINK_ASE: UUID16 = UUID16(0x2BC4);
+pub const SOURCE_ASE: UUID16 = UUID16(0x2BC5);
+pub const ASE_CONTROL_POINT: UUID16 = UUID16(0x2BC6);
+
+/// Audio direction, seen from the server. Sink ASEs receive audio from the client.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ASEDirection {
+    Sink,
+    Source,
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ASEStateCode {
+    Idle = 0x00,
+    CodecConfigured = 0x01,
+    QoSConfigured = 0x02,
+    Enabling = 0x03,
+    Streaming = 0x04,
+    Disabling = 0x05,
+    Releasing = 0x06,
+}
+impl From<ASEStateCode> for u8 {
+    fn from(code: ASEStateCode) -> Self {
+        code as u8
+    }
+}
+impl TryFrom<u8> for ASEStateCode {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(ASEStateCode::Idle),
+            0x01 => Ok(ASEStateCode::CodecConfigured),
+            0x02 => Ok(ASEStateCode::QoSConfigured),
+            0x03 => Ok(ASEStateCode::Enabling),
+            0x04 => Ok(ASEStateCode::Streaming),
+            0x05 => Ok(ASEStateCode::Disabling),
+            0x06 => Ok(ASEStateCode::Releasing),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+impl ASEStateCode {
+    /// If an ASE in this state accepts `opcode`. Receiver Start/Stop Ready are only written for
+    /// Source ASEs (the client is the receiver).
+    pub fn allows(self, opcode: ASEOpcode, direction: ASEDirection) -> bool {
+        use ASEStateCode::*;
+        match opcode {
+            ASEOpcode::ConfigCodec => matches!(self, Idle | CodecConfigured | QoSConfigured),
+            ASEOpcode::ConfigQoS => matches!(self, CodecConfigured | QoSConfigured),
+            ASEOpcode::Enable => self == QoSConfigured,
+            ASEOpcode::ReceiverStartReady => self == Enabling && direction == ASEDirection::Source,
+            ASEOpcode::Disable | ASEOpcode::UpdateMetadata => matches!(self, Enabling | Streaming),
+            ASEOpcode::ReceiverStopReady => self == Disabling && direction == ASEDirection::Source,
+            ASEOpcode::Release => !matches!(self, Idle | Releasing),
+        }
+    }
+}
+/// Server preferences reported in the Codec Configured state.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct CodecConfiguration {
+    /// `0`: unframed ISOAL PDUs supported, `1`: not supported.
+    pub framing: u8,
+    pub preferred_phy: u8,
+    pub preferred_retransmission_number: u8,
+    /// Milliseconds.
+    pub max_transport_latency: u16,
+    /// Microseconds (24 bits).
+    pub presentation_delay_min: u32,
+    pub presentation_delay_max: u32,
+    pub preferred_presentation_delay_min: u32,
+    pub preferred_presentation_delay_max: u32,
+    pub codec_id: CodecId,
+    /// Codec specific configuration (LTV structures).
+    pub configuration: Vec<u8>,
+}
+impl CodecConfiguration {
+    const FIXED_LEN: usize = 1 + 1 + 1 + 2 + 3 * 4 + CodecId::BYTE_LEN + 1;
+    fn unpack_from(buf: &[u8]) -> Result<CodecConfiguration, PackError> {
+        PackError::atleast_length(Self::FIXED_LEN, buf)?;
+        let len = usize::from(buf[Self::FIXED_LEN - 1]);
+        PackError::expect_length(Self::FIXED_LEN + len, buf)?;
+        Ok(CodecConfiguration {
+            framing: buf[0],
+            preferred_phy: buf[1],
+            preferred_retransmission_number: buf[2],
+            max_transport_latency: u16::from_le_bytes([buf[3], buf[4]]),
+            presentation_delay_min: u24_from(&buf[5..8]),
+            presentation_delay_max: u24_from(&buf[8..11]),
+            preferred_presentation_delay_min: u24_from(&buf[11..14]),
+            preferred_presentation_delay_max: u24_from(&buf[14..17]),
+            codec_id: CodecId::unpack_field(&buf[17..22]).ok_or(PackError::bad_index(17))?,
+            configuration: buf[Self::FIXED_LEN..].to_vec(),
+        })
+    }
+}
+/// CIS and stream parameters. Written by Config QoS and reported in the QoS Configured state.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct QoSConfiguration {
+    pub cig_id: u8,
+    pub cis_id: u8,
+    /// Microseconds (24 bits).
+    pub sdu_interval: u32,
+    /// `0`: unframed, `1`: framed.
+    pub framing: u8,
+    pub phy: u8,
+    pub max_sdu: u16,
+    pub retransmission_number: u8,
+    /// Milliseconds.
+    pub max_transport_latency: u16,
+    /// Microseconds (24 bits).
+    pub presentation_delay: u32,
+}
+impl QoSConfiguration {
+    pub const BYTE_LEN: usize = 1 + 1 + 3 + 1 + 1 + 2 + 1 + 2 + 3;
+    fn unpack_from(buf: &[u8]) -> Result<QoSConfiguration, PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        Ok(QoSConfiguration {
+            cig_id: buf[0],
+            cis_id: buf[1],
+            sdu_interval: u24_from(&buf[2..5]),
+            framing: buf[5],
+            phy: buf[6],
+            max_sdu: u16::from_le_bytes([buf[7], buf[8]]),
+            retransmission_number: buf[9],
+            max_transport_latency: u16::from_le_bytes([buf[10], buf[11]]),
+            presentation_delay: u24_from(&buf[12..15]),
+        })
+    }
+    fn pack_into(&self, out: &mut Vec<u8>) {
+        out.push(self.cig_id);
+        out.push(self.cis_id);
+        out.extend_from_slice(&u24_bytes(self.sdu_interval));
+        out.push(self.framing);
+        out.push(self.phy);
+        out.extend_from_slice(&self.max_sdu.to_le_bytes());
+        out.push(self.retransmission_number);
+        out.extend_from_slice(&self.max_transport_latency.to_le_bytes());
+        out.extend_from_slice(&u24_bytes(self.presentation_delay));
+    }
+}
+/// CIS of an enabling, streaming or disabling ASE and its metadata (LTV structures).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct StreamParameters {
+    pub cig_id: u8,
+    pub cis_id: u8,
+    pub metadata: Vec<u8>,
+}
+impl StreamParameters {
+    fn unpack_from(buf: &[u8]) -> Result<StreamParameters, PackError> {
+        PackError::atleast_length(3, buf)?;
+        PackError::expect_length(3 + usize::from(buf[2]), buf)?;
+        Ok(StreamParameters {
+            cig_id: buf[0],
+            cis_id: buf[1],
+            metadata: buf[3..].to_vec(),
+        })
+    }
+}
+/// State of an ASE with its state specific parameters.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ASEState {
+    Idle,
+    CodecConfigured(CodecConfiguration),
+    QoSConfigured(QoSConfiguration),
+    Enabling(StreamParameters),
+    Streaming(StreamParameters),
+    Disabling(StreamParameters),
+    Releasing,
+}
+impl ASEState {
+    pub fn code(&self) -> ASEStateCode {
+        match self {
+            ASEState::Idle => ASEStateCode::Idle,
+            ASEState::CodecConfigured(_) => ASEStateCode::CodecConfigured,
+            ASEState::QoSConfigured(_) => ASEStateCode::QoSConfigured,
+            ASEState::Enabling(_) => ASEStateCode::Enabling,
+            ASEState::Streaming(_) => ASEStateCode::Streaming,
+            ASEState::Disabling(_) => ASEStateCode::Disabling,
+            ASEState::Releasing => ASEStateCode::Releasing,
+        }
+    }
+}
+/// Value of a Sink/Source ASE characteristic.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ASE {
+    pub id: u8,
+    pub state: ASEState,
+}
+impl ASE {
+    pub fn unpack_from(value: &[u8]) -> Result<ASE, PackError> {
+        PackError::atleast_length(2, value)?;
+        let code = ASEStateCode::try_from(value[1]).map_err(|_| PackError::bad_index(1))?;
+        let parameters = &value[2..];
+        let state = match code {
+            ASEStateCode::Idle | ASEStateCode::Releasing => {
+                PackError::expect_length(2, value)?;
+                if code == ASEStateCode::Idle {
+                    ASEState::Idle
+                } else {
+                    ASEState::Releasing
+                }
+            }
+            ASEStateCode::CodecConfigured => {
+                ASEState::CodecConfigured(CodecConfiguration::unpack_from(parameters)?)
+            }
+            ASEStateCode::QoSConfigured => {
+                ASEState::QoSConfigured(QoSConfiguration::unpack_from(parameters)?)
+            }
+            ASEStateCode::Enabling => {
+                ASEState::Enabling(StreamParameters::unpack_from(parameters)?)
+            }
+            ASEStateCode::Streaming => {
+                ASEState::Streaming(StreamParameters::unpack_from(parameters)?)
+            }
+            ASEStateCode::Disabling => {
+                ASEState::Disabling(StreamParameters::unpack_from(parameters)?)
+            }
+        };
+        Ok(ASE {
+            id: value[0],
+            state,
+        })
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ASEOpcode {
+    ConfigCodec = 0x01,
+    ConfigQoS = 0x02,
+    Enable = 0x03,
+    ReceiverStartReady = 0x04,
+    Disable = 0x05,
+    ReceiverStopReady = 0x06,
+    UpdateMetadata = 0x07,
+    Release = 0x08,
+}
+impl From<ASEOpcode> for u8 {
+    fn from(opcode: ASEOpcode) -> Self {
+        opcode as u8
+    }
+}
+impl TryFrom<u8> for ASEOpcode {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(ASEOpcode::ConfigCodec),
+            0x02 => Ok(ASEOpcode::ConfigQoS),
+            0x03 => Ok(ASEOpcode::Enable),
+            0x04 => Ok(ASEOpcode::ReceiverStartReady),
+            0x05 => Ok(ASEOpcode::Disable),
+            0x06 => Ok(ASEOpcode::ReceiverStopReady),
+            0x07 => Ok(ASEOpcode::UpdateMetadata),
+            0x08 => Ok(ASEOpcode::Release),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// One ASE's part of an ASE Control Point operation.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ASEOperation {
+    ConfigCodec {
+        /// `0x01`: low latency, `0x02`: balanced, `0x03`: high reliability.
+        target_latency: u8,
+        target_phy: u8,
+        codec_id: CodecId,
+        /// Codec specific configuration (LTV structures).
+        configuration: Vec<u8>,
+    },
+    ConfigQoS(QoSConfiguration),
+    Enable {
+        metadata: Vec<u8>,
+    },
+    ReceiverStartReady,
+    Disable,
+    ReceiverStopReady,
+    UpdateMetadata {
+        metadata: Vec<u8>,
+    },
+    Release,
+}
+impl ASEOperation {
+    pub fn opcode(&self) -> ASEOpcode {
+        match self {
+            ASEOperation::ConfigCodec { .. } => ASEOpcode::ConfigCodec,
+            ASEOperation::ConfigQoS(_) => ASEOpcode::ConfigQoS,
+            ASEOperation::Enable { .. } => ASEOpcode::Enable,
+            ASEOperation::ReceiverStartReady => ASEOpcode::ReceiverStartReady,
+            ASEOperation::Disable => ASEOpcode::Disable,
+            ASEOperation::ReceiverStopReady => ASEOpcode::ReceiverStopReady,
+            ASEOperation::UpdateMetadata { .. } => ASEOpcode::UpdateMetadata,
+            ASEOperation::Release => ASEOpcode::Release,
+        }
+    }
+    fn pack_into(&self, ase_id: u8, out: &mut Vec<u8>) -> Result<(), PackError> {
+        out.push(ase_id);
+        match self {
+            ASEOperation::ConfigCodec {
+                target_latency,
+                target_phy,
+                codec_id,
+                configuration,
+            } => {
+                out.push(*target_latency);
+                out.push(*target_phy);
+                let mut id = [0_u8; CodecId::BYTE_LEN];
+                codec_id.pack_field(&mut id);
+                out.extend_from_slice(&id);
+                push_length_prefixed(out, configuration)?;
+            }
+            ASEOperation::ConfigQoS(qos) => qos.pack_into(out),
+            ASEOperation::Enable { metadata } | ASEOperation::UpdateMetadata { metadata } => {
+                push_length_prefixed(out, metadata)?;
+            }
+            ASEOperation::ReceiverStartReady
+            | ASEOperation::Disable
+            | ASEOperation::ReceiverStopReady
+            | ASEOperation::Release => (),
+        }
+        Ok(())
+    }
+}
+fn push_length_prefixed(out: &mut Vec<u8>, data: &[u8]) -> Result<(), PackError> {
+    out.push(u8::try_from(data.len()).map_err(|_| PackError::InvalidFields)?);
+    out.extend_from_slice(data);
+    Ok(())
+}
+/// Packs an ASE Control Point value. Every operation needs the same opcode.
+/// # Errors
+/// returns `ASCSError::MixedOperations` for mixed (or no) opcodes.
+pub fn control_point_value(operations: &[(u8, ASEOperation)]) -> Result<Vec<u8>, ASCSError> {
+    let opcode = match operations.first() {
+        Some((_, operation)) => operation.opcode(),
+        None => return Err(ASCSError::MixedOperations),
+    };
+    let count = u8::try_from(operations.len()).map_err(|_| ASCSError::MixedOperations)?;
+    let mut out = vec![opcode.into(), count];
+    for (ase_id, operation) in operations {
+        if operation.opcode() != opcode {
+            return Err(ASCSError::MixedOperations);
+        }
+        operation.pack_into(*ase_id, &mut out)?;
+    }
+    Ok(out)
+}
+/// Per ASE result in an ASE Control Point notification.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ASEResponse {
+    pub ase_id: u8,
+    /// `0x00` is success.
+    pub response_code: u8,
+    pub reason: u8,
+}
+impl ASEResponse {
+    pub fn is_success(&self) -> bool {
+        self.response_code == 0
+    }
+}
+/// ASE Control Point notification.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ControlPointResponse {
+    pub opcode: ASEOpcode,
+    pub responses: Vec<ASEResponse>,
+}
+impl ControlPointResponse {
+    pub fn unpack_from(value: &[u8]) -> Result<ControlPointResponse, PackError> {
+        PackError::atleast_length(2, value)?;
+        let opcode = ASEOpcode::try_from(value[0]).map_err(|_| PackError::bad_index(0))?;
+        // 0xFF: the whole operation was rejected (truncated or unsupported opcode).
+        let count = if value[1] == 0xFF { 1 } else { value[1] };
+        PackError::expect_length(2 + usize::from(count) * 3, value)?;
+        Ok(ControlPointResponse {
+            opcode,
+            responses: value[2..]
+                .chunks_exact(3)
+                .map(|r| ASEResponse {
+                    ase_id: r[0],
+                    response_code: r[1],
+                    reason: r[2],
+                })
+                .collect(),
+        })
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ASCSError {
+    /// No ASE with this id is known (or its state hasn't been read yet).
+    UnknownASE(u8),
+    /// The ASE state machine doesn't allow the operation in the current state.
+    InvalidTransition {
+        ase_id: u8,
+        state: ASEStateCode,
+        opcode: ASEOpcode,
+    },
+    /// A control point write needs at least one operation and all with the same opcode.
+    MixedOperations,
+    Client(ClientError),
+    Pack(PackError),
+}
+impl core::fmt::Display for ASCSError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "ascs error {:?}", self)
+    }
+}
+impl crate::error::Error for ASCSError {}
+#[cfg(feature = "std")]
+impl std::error::Error for ASCSError {}
+impl From<ClientError> for ASCSError {
+    fn from(e: ClientError) -> Self {
+        ASCSError::Client(e)
+    }
+}
+impl From<PackError> for ASCSError {
+    fn from(e: PackError) -> Self {
+        ASCSError::Pack(e)
+    }
+}
+#[derive(Clone, Debug)]
+struct Endpoint {
+    direction: ASEDirection,
+    ase: Option<ASE>,
+}
+/// Client side view of a server's ASCS. Feed it the ASE values read or notified with
+/// [`ASCSClient::update`].
+#[derive(Clone, Debug)]
+pub struct ASCSClient {
+    control_point: Handle,
+    /// Keyed by ASE value handle.
+    endpoints: BTreeMap<Handle, Endpoint>,
+}
+impl ASCSClient {
+    /// Finds the ASE and ASE Control Point characteristics in `index`. `None` if the server has
+    /// no control point.
+    pub fn from_index(index: &AttributeIndex) -> Option<ASCSClient> {
+        let control_point = index.find_characteristic(ASE_CONTROL_POINT)?.value_handle;
+        let mut endpoints = BTreeMap::new();
+        for (uuid, direction) in [
+            (SINK_ASE, ASEDirection::Sink),
+            (SOURCE_ASE, ASEDirection::Source),
+        ] {
+            for characteristic in index.find_characteristics(uuid) {
+                endpoints.insert(
+                    characteristic.value_handle,
+                    Endpoint {
+                        direction,
+                        ase: None,
+                    },
+                );
+            }
+        }
+        Some(ASCSClient {
+            control_point,
+            endpoints,
+        })
+    }
+    pub fn control_point(&self) -> Handle {
+        self.control_point
+    }
+    /// Value handles of the ASEs and their directions (to read them or enable notifications).
+    pub fn ase_handles(&self) -> impl Iterator<Item = (Handle, ASEDirection)> + '_ {
+        self.endpoints.iter().map(|(h, e)| (*h, e.direction))
+    }
+    /// Takes a read or notified value of the ASE with `value_handle`. Returns `None` for handles
+    /// that aren't ASEs.
+    /// # Errors
+    /// returns a `PackError` if `value` is malformed (the old state is kept).
+    pub fn update(
+        &mut self,
+        value_handle: Handle,
+        value: &[u8],
+    ) -> Result<Option<&ASE>, PackError> {
+        let endpoint = match self.endpoints.get_mut(&value_handle) {
+            Some(endpoint) => endpoint,
+            None => return Ok(None),
+        };
+        endpoint.ase = Some(ASE::unpack_from(value)?);
+        Ok(endpoint.ase.as_ref())
+    }
+    /// Last known state and direction of the ASE with `ase_id`.
+    pub fn ase(&self, ase_id: u8) -> Option<(&ASE, ASEDirection)> {
+        self.endpoints.values().find_map(|e| match &e.ase {
+            Some(ase) if ase.id == ase_id => Some((ase, e.direction)),
+            _ => None,
+        })
+    }
+    /// ATT write PDU for the control point operations, if every ASE's state allows them.
+    /// # Errors
+    /// returns `ASCSError::UnknownASE`/`ASCSError::InvalidTransition` for ASEs that can't take
+    /// their operation and `ASCSError::Client` if the control point can't be written.
+    pub fn request(
+        &self,
+        index: &AttributeIndex,
+        operations: &[(u8, ASEOperation)],
+        with_response: bool,
+    ) -> Result<Vec<u8>, ASCSError> {
+        for (ase_id, operation) in operations {
+            let (ase, direction) = self.ase(*ase_id).ok_or(ASCSError::UnknownASE(*ase_id))?;
+            let state = ase.state.code();
+            let opcode = operation.opcode();
+            if !state.allows(opcode, direction) {
+                return Err(ASCSError::InvalidTransition {
+                    ase_id: *ase_id,
+                    state,
+                    opcode,
+                });
+            }
+        }
+        let value = control_point_value(operations)?;
+        Ok(index.write_request(self.control_point, &value, with_response)?)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::informational::CodingFormat;
+
+    #[test]
+    fn test_ase_state_machine() {
+        let mut index = AttributeIndex::new();
+        index
+            .add_services_from_response(&[6, 0x01, 0x00, 0x0A, 0x00, 0x4E, 0x18], true)
+            .unwrap();
+        // Sink ASE (read, notify), Source ASE (read, notify), control point (write, notify).
+        index
+            .add_characteristics_from_response(&[
+                7, 0x02, 0x00, 0x12, 0x03, 0x00, 0xC4, 0x2B, 0x05, 0x00, 0x12, 0x06, 0x00, 0xC5,
+                0x2B, 0x08, 0x00, 0x1C, 0x09, 0x00, 0xC6, 0x2B,
+            ])
+            .unwrap();
+        let mut client = ASCSClient::from_index(&index).unwrap();
+        assert_eq!(client.ase_handles().count(), 2);
+        client.update(Handle::new(0x03), &[0x01, 0x00]).unwrap();
+        client.update(Handle::new(0x06), &[0x02, 0x00]).unwrap();
+
+        let config = ASEOperation::ConfigCodec {
+            target_latency: 0x02,
+            target_phy: 0x02,
+            codec_id: CodecId::new(CodingFormat::LC3),
+            configuration: vec![0x02, 0x01, 0x08],
+        };
+        assert_eq!(
+            client.request(&index, &[(1, config)], true),
+            Ok(vec![
+                0x12, 0x09, 0x00, 0x01, 0x01, 0x01, 0x02, 0x02, 0x06, 0x00, 0x00, 0x00, 0x00, 0x03,
+                0x02, 0x01, 0x08
+            ])
+        );
+        assert_eq!(
+            client.request(
+                &index,
+                &[(2, ASEOperation::Enable { metadata: vec![] })],
+                true
+            ),
+            Err(ASCSError::InvalidTransition {
+                ase_id: 2,
+                state: ASEStateCode::Idle,
+                opcode: ASEOpcode::Enable,
+            })
+        );
+
+        // Sink ASE 1 notified as QoS configured, then streaming.
+        let mut qos = vec![
+            0x01, 0x02, 0x00, 0x00, 0x10, 0x27, 0x00, 0x00, 0x02, 0x78, 0x00,
+        ];
+        qos.extend_from_slice(&[0x02, 0x0A, 0x00, 0x40, 0x9C, 0x00]);
+        let ase = client.update(Handle::new(0x03), &qos).unwrap().unwrap();
+        match &ase.state {
+            ASEState::QoSConfigured(qos) => {
+                assert_eq!(qos.sdu_interval, 10_000);
+                assert_eq!(qos.presentation_delay, 40_000);
+            }
+            other => panic!("expected QoS configured, got {:?}", other),
+        }
+        client
+            .update(Handle::new(0x03), &[0x01, 0x04, 0x00, 0x00, 0x00])
+            .unwrap();
+        assert!(client
+            .request(&index, &[(1, ASEOperation::Disable)], false)
+            .is_ok());
+        // Receiver Stop Ready is for Source ASEs.
+        assert!(client
+            .request(&index, &[(1, ASEOperation::ReceiverStopReady)], false)
+            .is_err());
+
+        let response = ControlPointResponse::unpack_from(&[0x05, 0x01, 0x01, 0x00, 0x00]).unwrap();
+        assert_eq!(response.opcode, ASEOpcode::Disable);
+        assert!(response.responses[0].is_success());
+    }
+}
@@ -0,0 +1,367 @@
+//! Coordinated Set Identification Profile (CSIP), set coordinator side. Devices of a set (earbuds,
+//! speakers) share a Set Identity Resolving Key (SIRK) and advertise Resolvable Set Identifiers
+//! (RSIs) generated from it. After reading the SIRK from one member, the others are found by
+//! resolving RSIs in scan reports ([`SetMemberDiscovery`]). [`LockCoordinator`] orders the lock
+//! requests so two coordinators can't deadlock each other.
+use crate::le::advertisement::AdType;
+use crate::le::att::attribute::Handle;
+use crate::le::crypto;
+use crate::le::gatt::client::{AttributeIndex, ClientError};
+use crate::le::report::ReportInfo;
+use crate::le::smp::bond::LongTermKey;
+use crate::uuid::UUID16;
+use crate::{BTAddress, ConversionError, PackError};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+pub const COORDINATED_SET_IDENTIFICATION_SERVICE: UUID16 = UUID16(0x1846);
+pub const SET_IDENTITY_RESOLVING_KEY: UUID16 = UUID16(0x2B84);
+pub const COORDINATED_SET_SIZE: UUID16 = UUID16(0x2B85);
+pub const SET_MEMBER_LOCK: UUID16 = UUID16(0x2B86);
+pub const SET_MEMBER_RANK: UUID16 = UUID16(0x2B87);
+/// ATT error of lock requests while another coordinator holds the lock.
+pub const LOCK_DENIED: u8 = 0x80;
+/// ATT error of lock releases by a coordinator that doesn't hold the lock.
+pub const LOCK_RELEASE_NOT_ALLOWED: u8 = 0x81;
+pub const INVALID_LOCK_VALUE: u8 = 0x82;
+/// ATT error of SIRK reads when the SIRK is only available out of band.
+pub const OOB_SIRK_ONLY: u8 = 0x83;
+pub const LOCK_ALREADY_GRANTED: u8 = 0x84;
+pub const RSI_LEN: usize = 6;
+
+/// Set Identity Resolving Key. Little endian (like it's read from the characteristic).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Default)]
+pub struct SetIdentityResolvingKey(pub [u8; crypto::KEY_LEN]);
+crypto::key_material!(SetIdentityResolvingKey);
+impl SetIdentityResolvingKey {
+    pub const BYTE_LEN: usize = crypto::KEY_LEN;
+    /// Decrypts an encrypted SIRK value with the LTK of the link it was read over (`sdf`).
+    pub fn decrypt(
+        ltk: &LongTermKey,
+        encrypted: &[u8; crypto::KEY_LEN],
+    ) -> SetIdentityResolvingKey {
+        let mut key = ltk.0;
+        key.reverse();
+        let salt = crypto::s1(b"SIRKenc");
+        let mut mask = crypto::k1(&key, &salt, b"csis");
+        crypto::wipe(&mut key);
+        mask.reverse();
+        let mut sirk = [0_u8; crypto::KEY_LEN];
+        for ((out, enc), mask) in sirk.iter_mut().zip(encrypted).zip(&mask) {
+            *out = enc ^ mask;
+        }
+        crypto::wipe(&mut mask);
+        SetIdentityResolvingKey(sirk)
+    }
+    /// Set identity hash of `prand` (`sih`, 24-bit).
+    pub fn hash(&self, prand: u32) -> u32 {
+        let mut key = self.0;
+        key.reverse();
+        let hash = crypto::ah(&key, prand);
+        crypto::wipe(&mut key);
+        hash
+    }
+    /// Returns `true` if `rsi` (as advertised, little endian) was generated from this SIRK.
+    pub fn resolves(&self, rsi: &[u8]) -> bool {
+        if rsi.len() != RSI_LEN {
+            return false;
+        }
+        let hash = u32::from_le_bytes([rsi[0], rsi[1], rsi[2], 0]);
+        let prand = u32::from_le_bytes([rsi[3], rsi[4], rsi[5], 0]);
+        prand >> 22 == 0b01 && self.hash(prand) == hash
+    }
+    /// RSI from the random part of `prand` (the two most significant bits are set to `0b01`).
+    pub fn rsi(&self, prand: u32) -> [u8; RSI_LEN] {
+        let prand = (prand & 0x003F_FFFF) | 0x0040_0000;
+        let hash = self.hash(prand).to_le_bytes();
+        let prand = prand.to_le_bytes();
+        [hash[0], hash[1], hash[2], prand[0], prand[1], prand[2]]
+    }
+}
+/// Set Identity Resolving Key characteristic value.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum SIRKValue {
+    /// Decrypt with [`SetIdentityResolvingKey::decrypt`].
+    Encrypted([u8; crypto::KEY_LEN]),
+    Plain(SetIdentityResolvingKey),
+}
+impl SIRKValue {
+    pub const BYTE_LEN: usize = 1 + crypto::KEY_LEN;
+    pub fn unpack_from(value: &[u8]) -> Result<SIRKValue, PackError> {
+        PackError::expect_length(Self::BYTE_LEN, value)?;
+        let mut key = [0_u8; crypto::KEY_LEN];
+        key.copy_from_slice(&value[1..]);
+        match value[0] {
+            0x00 => Ok(SIRKValue::Encrypted(key)),
+            0x01 => Ok(SIRKValue::Plain(SetIdentityResolvingKey(key))),
+            _ => Err(PackError::bad_index(0)),
+        }
+    }
+    /// The SIRK, decrypted with `ltk` if needed. `None` for encrypted values without a LTK.
+    pub fn sirk(&self, ltk: Option<&LongTermKey>) -> Option<SetIdentityResolvingKey> {
+        match self {
+            SIRKValue::Encrypted(encrypted) => {
+                Some(SetIdentityResolvingKey::decrypt(ltk?, encrypted))
+            }
+            SIRKValue::Plain(sirk) => Some(sirk.clone()),
+        }
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum SetMemberLock {
+    Unlocked = 0x01,
+    Locked = 0x02,
+}
+impl TryFrom<u8> for SetMemberLock {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(SetMemberLock::Unlocked),
+            0x02 => Ok(SetMemberLock::Locked),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// What a read or notified value means, see [`CSISClient::update`].
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum CSISValue {
+    SIRK(SIRKValue),
+    Size(u8),
+    Lock(SetMemberLock),
+    Rank(u8),
+}
+/// Client side view of one set member's CSIS.
+#[derive(Clone, Debug)]
+pub struct CSISClient {
+    sirk: Handle,
+    size: Option<Handle>,
+    lock: Option<Handle>,
+    rank: Option<Handle>,
+}
+impl CSISClient {
+    pub fn from_index(index: &AttributeIndex) -> Option<CSISClient> {
+        let value_handle = |uuid| index.find_characteristic(uuid).map(|c| c.value_handle);
+        Some(CSISClient {
+            sirk: value_handle(SET_IDENTITY_RESOLVING_KEY)?,
+            size: value_handle(COORDINATED_SET_SIZE),
+            lock: value_handle(SET_MEMBER_LOCK),
+            rank: value_handle(SET_MEMBER_RANK),
+        })
+    }
+    /// Value handles to read: SIRK, then the optional size, lock and rank.
+    pub fn handles(&self) -> impl Iterator<Item = Handle> {
+        core::iter::once(self.sirk)
+            .chain(self.size)
+            .chain(self.lock)
+            .chain(self.rank)
+    }
+    /// Takes a read or notified value. Returns `None` for other handles.
+    pub fn update(
+        &self,
+        value_handle: Handle,
+        value: &[u8],
+    ) -> Result<Option<CSISValue>, PackError> {
+        let byte = || {
+            PackError::expect_length(1, value)?;
+            Ok(value[0])
+        };
+        Ok(Some(if value_handle == self.sirk {
+            CSISValue::SIRK(SIRKValue::unpack_from(value)?)
+        } else if Some(value_handle) == self.size {
+            CSISValue::Size(byte()?)
+        } else if Some(value_handle) == self.lock {
+            let lock = SetMemberLock::try_from(byte()?).map_err(|_| PackError::bad_index(0))?;
+            CSISValue::Lock(lock)
+        } else if Some(value_handle) == self.rank {
+            CSISValue::Rank(byte()?)
+        } else {
+            return Ok(None);
+        }))
+    }
+    /// ATT Write Request PDU writing the Set Member Lock. `None` if the member has no lock.
+    pub fn lock_request(
+        &self,
+        index: &AttributeIndex,
+        lock: SetMemberLock,
+    ) -> Option<Result<Vec<u8>, ClientError>> {
+        Some(index.write_request(self.lock?, &[lock as u8], true))
+    }
+}
+/// Finds the other members of a set in scan reports.
+#[derive(Clone, Debug)]
+pub struct SetMemberDiscovery {
+    sirk: SetIdentityResolvingKey,
+    size: Option<u8>,
+    members: Vec<BTAddress>,
+}
+impl SetMemberDiscovery {
+    /// `first` is the member the SIRK (and `size`, if the set has one) was read from.
+    pub fn new(
+        sirk: SetIdentityResolvingKey,
+        size: Option<u8>,
+        first: BTAddress,
+    ) -> SetMemberDiscovery {
+        SetMemberDiscovery {
+            sirk,
+            size,
+            members: vec![first],
+        }
+    }
+    /// Returns `true` if `report` is from a member not found before.
+    pub fn process_report<T: AsRef<[u8]>>(&mut self, report: &ReportInfo<T>) -> bool {
+        if self.members.contains(&report.address) {
+            return false;
+        }
+        let is_member = report.data.iter().any(|ad| {
+            ad.ad_type == AdType::ResolvableSetIdentifier && self.sirk.resolves(ad.buf.as_ref())
+        });
+        if is_member {
+            self.members.push(report.address);
+        }
+        is_member
+    }
+    pub fn members(&self) -> &[BTAddress] {
+        &self.members[..]
+    }
+    /// `true` once every member of the set was found (never for sets without a known size).
+    pub fn is_complete(&self) -> bool {
+        self.size
+            .map_or(false, |size| self.members.len() >= usize::from(size))
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum LockState {
+    /// Lock requests are being sent, lowest rank first.
+    Locking,
+    /// Every member granted the lock.
+    Locked,
+    /// Locks are being released, highest rank first (after a denial or [`LockCoordinator::release`]).
+    Releasing,
+    /// Nothing is locked.
+    Unlocked,
+}
+/// Orders the lock procedures of a set: lock requests go to members in ascending rank order, one
+/// at a time, and releases in descending order. A denied request releases the locks already
+/// granted. `M` identifies a member (address, connection handle, ..).
+#[derive(Clone, Debug)]
+pub struct LockCoordinator<M> {
+    /// `(rank, member, locked)` sorted by rank.
+    members: Vec<(u8, M, bool)>,
+    state: LockState,
+}
+impl<M: Copy + Eq> LockCoordinator<M> {
+    pub fn new(members: impl IntoIterator<Item = (M, u8)>) -> LockCoordinator<M> {
+        let mut members: Vec<_> = members
+            .into_iter()
+            .map(|(member, rank)| (rank, member, false))
+            .collect();
+        members.sort_by_key(|&(rank, _, _)| rank);
+        LockCoordinator {
+            members,
+            state: LockState::Locking,
+        }
+    }
+    pub fn state(&self) -> LockState {
+        self.state
+    }
+    /// Member to send the next lock request (or release, while `Releasing`) to. `None` when done.
+    pub fn next(&self) -> Option<M> {
+        match self.state {
+            LockState::Locking => self.members.iter().find(|m| !m.2).map(|m| m.1),
+            LockState::Releasing => self.members.iter().rev().find(|m| m.2).map(|m| m.1),
+            LockState::Locked | LockState::Unlocked => None,
+        }
+    }
+    /// Records the answer of `member` to its lock request (`granted` is `false` for
+    /// [`LOCK_DENIED`]). [`LOCK_ALREADY_GRANTED`] counts as granted.
+    pub fn lock_response(&mut self, member: M, granted: bool) {
+        if self.state != LockState::Locking {
+            return;
+        }
+        if !granted {
+            self.state = LockState::Releasing;
+            self.update_release_state();
+            return;
+        }
+        if let Some(m) = self.members.iter_mut().find(|m| m.1 == member) {
+            m.2 = true;
+        }
+        if self.members.iter().all(|m| m.2) {
+            self.state = LockState::Locked;
+        }
+    }
+    /// Starts releasing the granted locks.
+    pub fn release(&mut self) {
+        self.state = LockState::Releasing;
+        self.update_release_state();
+    }
+    /// Records a released (or disconnected) member.
+    pub fn released(&mut self, member: M) {
+        if let Some(m) = self.members.iter_mut().find(|m| m.1 == member) {
+            m.2 = false;
+        }
+        if self.state == LockState::Releasing {
+            self.update_release_state();
+        }
+    }
+    fn update_release_state(&mut self) {
+        if self.members.iter().all(|m| !m.2) {
+            self.state = LockState::Unlocked;
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::le::advertisement::RawAdvertisement;
+
+    #[test]
+    fn test_set_members() {
+        let sirk = SetIdentityResolvingKey([
+            0xcd, 0xcc, 0x72, 0xdd, 0x86, 0x8c, 0xcd, 0xce, 0x22, 0xfd, 0xa1, 0x21, 0x09, 0x7d,
+            0x7d, 0x45,
+        ]);
+        let ltk = LongTermKey([0x42; 16]);
+        let mut encrypted = sirk.0;
+        let SetIdentityResolvingKey(mask) =
+            SetIdentityResolvingKey::decrypt(&ltk, &[0_u8; crypto::KEY_LEN]);
+        for (e, m) in encrypted.iter_mut().zip(&mask) {
+            *e ^= m;
+        }
+        let mut value = vec![0x00];
+        value.extend_from_slice(&encrypted);
+        assert_eq!(
+            SIRKValue::unpack_from(&value).unwrap().sirk(Some(&ltk)),
+            Some(sirk.clone())
+        );
+
+        let rsi = sirk.rsi(0x12_3456);
+        assert!(sirk.resolves(&rsi));
+        assert!(!SetIdentityResolvingKey::default().resolves(&rsi));
+        let mut discovery = SetMemberDiscovery::new(sirk, Some(2), BTAddress([1, 2, 3, 4, 5, 6]));
+        // Flags and the RSI.
+        let mut data = vec![0x02, 0x01, 0x06, 0x07, 0x2E];
+        data.extend_from_slice(&rsi);
+        let report = ReportInfo {
+            address: BTAddress([6, 5, 4, 3, 2, 1]),
+            data: RawAdvertisement(&data[..]),
+            ..ReportInfo::default()
+        };
+        assert!(!discovery.is_complete());
+        assert!(discovery.process_report(&report));
+        assert!(!discovery.process_report(&report));
+        assert!(discovery.is_complete());
+
+        // The rank 2 member denies, the rank 1 member is released again.
+        let mut coordinator = LockCoordinator::new(vec![('b', 2), ('a', 1)]);
+        assert_eq!(coordinator.next(), Some('a'));
+        coordinator.lock_response('a', true);
+        assert_eq!(coordinator.next(), Some('b'));
+        coordinator.lock_response('b', false);
+        assert_eq!(coordinator.state(), LockState::Releasing);
+        assert_eq!(coordinator.next(), Some('a'));
+        coordinator.released('a');
+        assert_eq!(coordinator.state(), LockState::Unlocked);
+    }
+}
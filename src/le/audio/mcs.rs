@@ -0,0 +1,322 @@
+//! Media Control Service (MCS) and Generic Media Control Service (GMCS, the device wide player).
+//! Both have the same characteristics: player and track information, the Media State and the
+//! Media Control Point taking play/pause/seek/track commands.
+use crate::le::att::attribute::Handle;
+use crate::le::gatt::client::{AttributeIndex, ClientError};
+use crate::uuid::UUID16;
+use crate::{ConversionError, PackError};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+pub const MEDIA_CONTROL_SERVICE: UUID16 = UUID16(0x1848);
+pub const GENERIC_MEDIA_CONTROL_SERVICE: UUID16 = UUID16(0x1849);
+pub const MEDIA_PLAYER_NAME: UUID16 = UUID16(0x2B93);
+pub const TRACK_CHANGED: UUID16 = UUID16(0x2B96);
+pub const TRACK_TITLE: UUID16 = UUID16(0x2B97);
+pub const TRACK_DURATION: UUID16 = UUID16(0x2B98);
+pub const TRACK_POSITION: UUID16 = UUID16(0x2B99);
+pub const MEDIA_STATE: UUID16 = UUID16(0x2BA3);
+pub const MEDIA_CONTROL_POINT: UUID16 = UUID16(0x2BA4);
+pub const MEDIA_CONTROL_POINT_OPCODES_SUPPORTED: UUID16 = UUID16(0x2BA5);
+/// Track Duration/Position value of an unknown duration or position.
+pub const UNKNOWN_TIME: i32 = -1;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum MediaState {
+    Inactive = 0x00,
+    Playing = 0x01,
+    Paused = 0x02,
+    Seeking = 0x03,
+}
+impl TryFrom<u8> for MediaState {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(MediaState::Inactive),
+            0x01 => Ok(MediaState::Playing),
+            0x02 => Ok(MediaState::Paused),
+            0x03 => Ok(MediaState::Seeking),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// Media Control Point commands. Offsets are in 10 ms units, indexes count from 1 (negative
+/// from the end).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum MediaCommand {
+    Play,
+    Pause,
+    FastRewind,
+    FastForward,
+    Stop,
+    MoveRelative(i32),
+    PreviousSegment,
+    NextSegment,
+    FirstSegment,
+    LastSegment,
+    GotoSegment(i32),
+    PreviousTrack,
+    NextTrack,
+    FirstTrack,
+    LastTrack,
+    GotoTrack(i32),
+    PreviousGroup,
+    NextGroup,
+    FirstGroup,
+    LastGroup,
+    GotoGroup(i32),
+}
+impl MediaCommand {
+    pub fn opcode(self) -> u8 {
+        match self {
+            MediaCommand::Play => 0x01,
+            MediaCommand::Pause => 0x02,
+            MediaCommand::FastRewind => 0x03,
+            MediaCommand::FastForward => 0x04,
+            MediaCommand::Stop => 0x05,
+            MediaCommand::MoveRelative(_) => 0x10,
+            MediaCommand::PreviousSegment => 0x20,
+            MediaCommand::NextSegment => 0x21,
+            MediaCommand::FirstSegment => 0x22,
+            MediaCommand::LastSegment => 0x23,
+            MediaCommand::GotoSegment(_) => 0x24,
+            MediaCommand::PreviousTrack => 0x30,
+            MediaCommand::NextTrack => 0x31,
+            MediaCommand::FirstTrack => 0x32,
+            MediaCommand::LastTrack => 0x33,
+            MediaCommand::GotoTrack(_) => 0x34,
+            MediaCommand::PreviousGroup => 0x40,
+            MediaCommand::NextGroup => 0x41,
+            MediaCommand::FirstGroup => 0x42,
+            MediaCommand::LastGroup => 0x43,
+            MediaCommand::GotoGroup(_) => 0x44,
+        }
+    }
+    /// Bit of the command in Media Control Point Opcodes Supported.
+    pub fn supported_bit(self) -> u32 {
+        let bit = match self.opcode() {
+            opcode @ 0x01..=0x05 => opcode - 0x01,
+            0x10 => 5,
+            opcode @ 0x20..=0x24 => opcode - 0x20 + 6,
+            opcode @ 0x30..=0x34 => opcode - 0x30 + 11,
+            opcode => opcode - 0x40 + 16,
+        };
+        1 << bit
+    }
+    pub fn pack(self) -> Vec<u8> {
+        let mut value = vec![self.opcode()];
+        match self {
+            MediaCommand::MoveRelative(n)
+            | MediaCommand::GotoSegment(n)
+            | MediaCommand::GotoTrack(n)
+            | MediaCommand::GotoGroup(n) => value.extend_from_slice(&n.to_le_bytes()),
+            _ => (),
+        }
+        value
+    }
+}
+/// Media Control Point notification result codes.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum CommandResult {
+    Success = 0x01,
+    OpcodeNotSupported = 0x02,
+    MediaPlayerInactive = 0x03,
+    CannotBeCompleted = 0x04,
+}
+impl TryFrom<u8> for CommandResult {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(CommandResult::Success),
+            0x02 => Ok(CommandResult::OpcodeNotSupported),
+            0x03 => Ok(CommandResult::MediaPlayerInactive),
+            0x04 => Ok(CommandResult::CannotBeCompleted),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// What a read or notified value means, see [`MCSClient::update`].
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum MediaEvent {
+    State(MediaState),
+    TrackChanged,
+    TrackTitle(String),
+    /// 10 ms units, `None` if unknown.
+    TrackDuration(Option<i32>),
+    /// 10 ms units, `None` if unknown.
+    TrackPosition(Option<i32>),
+    OpcodesSupported(u32),
+    CommandResult {
+        opcode: u8,
+        result: CommandResult,
+    },
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum MCSError {
+    /// The player said it doesn't support the command.
+    NotSupported(MediaCommand),
+    Client(ClientError),
+}
+impl core::fmt::Display for MCSError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "mcs error {:?}", self)
+    }
+}
+impl crate::error::Error for MCSError {}
+#[cfg(feature = "std")]
+impl std::error::Error for MCSError {}
+impl From<ClientError> for MCSError {
+    fn from(e: ClientError) -> Self {
+        MCSError::Client(e)
+    }
+}
+fn time_from(value: &[u8]) -> Result<Option<i32>, PackError> {
+    PackError::expect_length(4, value)?;
+    let time = i32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+    Ok(if time == UNKNOWN_TIME {
+        None
+    } else {
+        Some(time)
+    })
+}
+/// Client side view of a media player. Feed it reads and notifications with
+/// [`MCSClient::update`].
+#[derive(Clone, Debug)]
+pub struct MCSClient {
+    /// `(value handle, characteristic UUID)` of every characteristic the client understands.
+    handles: Vec<(Handle, UUID16)>,
+    control_point: Handle,
+    state: Option<MediaState>,
+    opcodes_supported: Option<u32>,
+}
+impl MCSClient {
+    /// Finds the player characteristics in `index`. `None` without a Media Control Point.
+    pub fn from_index(index: &AttributeIndex) -> Option<MCSClient> {
+        let control_point = index.find_characteristic(MEDIA_CONTROL_POINT)?.value_handle;
+        let handles = [
+            TRACK_CHANGED,
+            TRACK_TITLE,
+            TRACK_DURATION,
+            TRACK_POSITION,
+            MEDIA_STATE,
+            MEDIA_CONTROL_POINT,
+            MEDIA_CONTROL_POINT_OPCODES_SUPPORTED,
+        ]
+        .iter()
+        .filter_map(|&uuid| Some((index.find_characteristic(uuid)?.value_handle, uuid)))
+        .collect();
+        Some(MCSClient {
+            handles,
+            control_point,
+            state: None,
+            opcodes_supported: None,
+        })
+    }
+    /// Value handles to enable notifications for (or read).
+    pub fn handles(&self) -> impl Iterator<Item = (Handle, UUID16)> + '_ {
+        self.handles.iter().copied()
+    }
+    pub fn state(&self) -> Option<MediaState> {
+        self.state
+    }
+    /// Takes a read or notified value. Returns `None` for other handles.
+    pub fn update(
+        &mut self,
+        value_handle: Handle,
+        value: &[u8],
+    ) -> Result<Option<MediaEvent>, PackError> {
+        let uuid = match self.handles.iter().find(|(h, _)| *h == value_handle) {
+            Some((_, uuid)) => *uuid,
+            None => return Ok(None),
+        };
+        let event = match uuid {
+            MEDIA_STATE => {
+                PackError::expect_length(1, value)?;
+                let state = MediaState::try_from(value[0]).map_err(|_| PackError::bad_index(0))?;
+                self.state = Some(state);
+                MediaEvent::State(state)
+            }
+            TRACK_CHANGED => MediaEvent::TrackChanged,
+            TRACK_TITLE => MediaEvent::TrackTitle(
+                String::from_utf8(value.to_vec()).map_err(|_| PackError::bad_index(0))?,
+            ),
+            TRACK_DURATION => MediaEvent::TrackDuration(time_from(value)?),
+            TRACK_POSITION => MediaEvent::TrackPosition(time_from(value)?),
+            MEDIA_CONTROL_POINT_OPCODES_SUPPORTED => {
+                PackError::expect_length(4, value)?;
+                let supported = u32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+                self.opcodes_supported = Some(supported);
+                MediaEvent::OpcodesSupported(supported)
+            }
+            _ => {
+                PackError::expect_length(2, value)?;
+                MediaEvent::CommandResult {
+                    opcode: value[0],
+                    result: CommandResult::try_from(value[1])
+                        .map_err(|_| PackError::bad_index(1))?,
+                }
+            }
+        };
+        Ok(Some(event))
+    }
+    /// ATT Write Request PDU for `command`. Commands the player said it doesn't support (once
+    /// Opcodes Supported has been read) are refused.
+    pub fn request(
+        &self,
+        index: &AttributeIndex,
+        command: MediaCommand,
+    ) -> Result<Vec<u8>, MCSError> {
+        if let Some(supported) = self.opcodes_supported {
+            if supported & command.supported_bit() == 0 {
+                return Err(MCSError::NotSupported(command));
+            }
+        }
+        Ok(index.write_request(self.control_point, &command.pack(), true)?)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_media_control() {
+        let mut index = AttributeIndex::new();
+        index
+            .add_services_from_response(&[6, 0x01, 0x00, 0x0A, 0x00, 0x49, 0x18], true)
+            .unwrap();
+        // Media State, Media Control Point and Opcodes Supported.
+        index
+            .add_characteristics_from_response(&[
+                7, 0x02, 0x00, 0x12, 0x03, 0x00, 0xA3, 0x2B, 0x05, 0x00, 0x18, 0x06, 0x00, 0xA4,
+                0x2B, 0x08, 0x00, 0x02, 0x09, 0x00, 0xA5, 0x2B,
+            ])
+            .unwrap();
+        let mut client = MCSClient::from_index(&index).unwrap();
+        assert_eq!(
+            client.update(Handle::new(0x03), &[0x02]),
+            Ok(Some(MediaEvent::State(MediaState::Paused)))
+        );
+        // Play, Pause, Previous Track and Next Track.
+        client
+            .update(Handle::new(0x09), &[0x03, 0x18, 0x00, 0x00])
+            .unwrap();
+        assert_eq!(
+            client.request(&index, MediaCommand::Play),
+            Ok(vec![0x12, 0x06, 0x00, 0x01])
+        );
+        assert_eq!(
+            client.request(&index, MediaCommand::GotoTrack(2)),
+            Err(MCSError::NotSupported(MediaCommand::GotoTrack(2)))
+        );
+        assert_eq!(
+            client.update(Handle::new(0x06), &[0x31, 0x01]),
+            Ok(Some(MediaEvent::CommandResult {
+                opcode: 0x31,
+                result: CommandResult::Success
+            }))
+        );
+    }
+}
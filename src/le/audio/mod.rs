@@ -0,0 +1,89 @@
+//! LE Audio services, client side. The control plane of unicast audio: [`pacs`] tells what a
+//! device can decode/encode and [`ascs`] configures and starts its Audio Stream Endpoints. The
+//! audio data itself flows over the ISO data path ([`crate::hci::iso`]). [`vcs`] and [`mcs`]
+//! remote control the volume and media player of a device and [`csip`] groups devices of a set.
+//!
+//! Like [`crate::le::gatt::client`] these only parse characteristic values and build request
+//! PDUs, sending them (and routing notifications back) is up to the caller.
+pub mod ascs;
+pub mod csip;
+pub mod mcs;
+pub mod pacs;
+pub mod vcs;
+
+use crate::PackError;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// Length-Type-Value structures used by codec capabilities, codec configurations and metadata.
+/// Returns `(type, value)` pairs.
+/// # Errors
+/// returns `PackError::BadLength` if an entry runs past the end of `buf` and
+/// `PackError::BadBytes` for zero length entries (they have no type).
+pub fn ltvs(buf: &[u8]) -> Result<Vec<(u8, &[u8])>, PackError> {
+    let mut out = Vec::new();
+    let mut rest = buf;
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = usize::from(len);
+        if len == 0 {
+            return Err(PackError::bad_index(buf.len() - rest.len()));
+        }
+        PackError::atleast_length(len, tail)?;
+        out.push((tail[0], &tail[1..len]));
+        rest = &tail[len..];
+    }
+    Ok(out)
+}
+/// Appends one LTV structure to `out`.
+/// # Panics
+/// Panics if `value` is longer than 254 bytes.
+pub fn push_ltv(out: &mut Vec<u8>, ltv_type: u8, value: &[u8]) {
+    let len = u8::try_from(value.len() + 1).expect("LTV value longer than 254 bytes");
+    out.push(len);
+    out.push(ltv_type);
+    out.extend_from_slice(value);
+}
+pub(crate) fn u24_from(buf: &[u8]) -> u32 {
+    u32::from_le_bytes([buf[0], buf[1], buf[2], 0])
+}
+pub(crate) fn u24_bytes(value: u32) -> [u8; 3] {
+    let [b0, b1, b2, _] = value.to_le_bytes();
+    [b0, b1, b2]
+}
+/// Audio Context Types bit field (what audio is for).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct AudioContexts(pub u16);
+impl AudioContexts {
+    pub const UNSPECIFIED: u16 = 0x0001;
+    pub const CONVERSATIONAL: u16 = 0x0002;
+    pub const MEDIA: u16 = 0x0004;
+    pub const GAME: u16 = 0x0008;
+    pub const INSTRUCTIONAL: u16 = 0x0010;
+    pub const VOICE_ASSISTANTS: u16 = 0x0020;
+    pub const LIVE: u16 = 0x0040;
+    pub const SOUND_EFFECTS: u16 = 0x0080;
+    pub const NOTIFICATIONS: u16 = 0x0100;
+    pub const RINGTONE: u16 = 0x0200;
+    pub const ALERTS: u16 = 0x0400;
+    pub const EMERGENCY_ALARM: u16 = 0x0800;
+    pub const fn contains(self, contexts: u16) -> bool {
+        self.0 & contexts == contexts
+    }
+}
+/// Audio Locations bit field (front left, front right, ..). `0` is mono/unspecified.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct AudioLocations(pub u32);
+impl AudioLocations {
+    pub const FRONT_LEFT: u32 = 0x0000_0001;
+    pub const FRONT_RIGHT: u32 = 0x0000_0002;
+    pub const FRONT_CENTER: u32 = 0x0000_0004;
+    pub const BACK_LEFT: u32 = 0x0000_0010;
+    pub const BACK_RIGHT: u32 = 0x0000_0020;
+    pub const fn contains(self, locations: u32) -> bool {
+        self.0 & locations == locations
+    }
+    /// Number of locations (audio channels) set.
+    pub const fn count(self) -> u32 {
+        self.0.count_ones()
+    }
+}
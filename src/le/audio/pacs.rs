@@ -0,0 +1,194 @@
+//! Published Audio Capabilities Service (PACS). The Sink/Source PAC characteristics list the
+//! codecs (and their capability ranges) a device supports in each direction.
+use crate::hci::definitions::HCIField;
+use crate::hci::informational::{CodecId, CodingFormat};
+use crate::le::audio::{ltvs, AudioContexts, AudioLocations};
+use crate::uuid::UUID16;
+use crate::PackError;
+use alloc::vec::Vec;
+
+pub const PUBLISHED_AUDIO_CAPABILITIES_SERVICE: UUID16 = UUID16(0x1850);
+pub const SINK_PAC: UUID16 = UUID16(0x2BC9);
+pub const SINK_AUDIO_LOCATIONS: UUID16 = UUID16(0x2BCA);
+pub const SOURCE_PAC: UUID16 = UUID16(0x2BCB);
+pub const SOURCE_AUDIO_LOCATIONS: UUID16 = UUID16(0x2BCC);
+pub const AVAILABLE_AUDIO_CONTEXTS: UUID16 = UUID16(0x2BCD);
+pub const SUPPORTED_AUDIO_CONTEXTS: UUID16 = UUID16(0x2BCE);
+
+/// LC3 Supported_Sampling_Frequencies bits, lowest first.
+pub const SAMPLING_FREQUENCIES_HZ: [u32; 13] = [
+    8_000, 11_025, 16_000, 22_050, 24_000, 32_000, 44_100, 48_000, 88_200, 96_000, 176_400,
+    192_000, 384_000,
+];
+/// One Published Audio Capability record.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct PACRecord {
+    pub codec_id: CodecId,
+    /// Codec specific capabilities (LTV structures).
+    pub capabilities: Vec<u8>,
+    /// Metadata (LTV structures).
+    pub metadata: Vec<u8>,
+}
+impl PACRecord {
+    /// Capabilities of an LC3 record. `None` for other codecs.
+    /// # Errors
+    /// returns a `PackError` if the capabilities are malformed.
+    pub fn lc3_capabilities(&self) -> Result<Option<LC3Capabilities>, PackError> {
+        if self.codec_id.coding_format != CodingFormat::LC3 {
+            return Ok(None);
+        }
+        LC3Capabilities::unpack_from(&self.capabilities).map(Some)
+    }
+}
+/// Parses a Sink PAC or Source PAC value.
+/// # Errors
+/// returns a `PackError` if the value is truncated or has trailing bytes.
+pub fn unpack_pac_records(value: &[u8]) -> Result<Vec<PACRecord>, PackError> {
+    let (&count, mut rest) = value.split_first().ok_or(PackError::bad_index(0))?;
+    let mut records = Vec::with_capacity(usize::from(count));
+    for _ in 0..count {
+        PackError::atleast_length(CodecId::BYTE_LEN + 1, rest)?;
+        let codec_id = CodecId::unpack_field(&rest[..CodecId::BYTE_LEN])
+            .ok_or(PackError::bad_index(value.len() - rest.len()))?;
+        let (capabilities, tail) = length_prefixed(&rest[CodecId::BYTE_LEN..])?;
+        let (metadata, tail) = length_prefixed(tail)?;
+        records.push(PACRecord {
+            codec_id,
+            capabilities: capabilities.to_vec(),
+            metadata: metadata.to_vec(),
+        });
+        rest = tail;
+    }
+    if !rest.is_empty() {
+        return Err(PackError::BadLength {
+            expected: value.len() - rest.len(),
+            got: value.len(),
+        });
+    }
+    Ok(records)
+}
+/// Splits `len, data[len], rest`.
+fn length_prefixed(buf: &[u8]) -> Result<(&[u8], &[u8]), PackError> {
+    let (&len, rest) = buf.split_first().ok_or(PackError::bad_index(0))?;
+    PackError::atleast_length(usize::from(len), rest)?;
+    Ok(rest.split_at(usize::from(len)))
+}
+/// Parses a Sink/Source Audio Locations value.
+pub fn unpack_audio_locations(value: &[u8]) -> Result<AudioLocations, PackError> {
+    PackError::expect_length(4, value)?;
+    Ok(AudioLocations(u32::from_le_bytes([
+        value[0], value[1], value[2], value[3],
+    ])))
+}
+/// Parses an Available/Supported Audio Contexts value into its `(sink, source)` contexts.
+pub fn unpack_audio_contexts(value: &[u8]) -> Result<(AudioContexts, AudioContexts), PackError> {
+    PackError::expect_length(4, value)?;
+    Ok((
+        AudioContexts(u16::from_le_bytes([value[0], value[1]])),
+        AudioContexts(u16::from_le_bytes([value[2], value[3]])),
+    ))
+}
+/// LC3 codec specific capabilities. Absent channel counts and frames per SDU default to 1.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct LC3Capabilities {
+    /// Bit `n` set: [`SAMPLING_FREQUENCIES_HZ`]`[n]` supported.
+    pub sampling_frequencies: u16,
+    /// Bit 0: 7.5 ms, bit 1: 10 ms, bit 4/5: 7.5/10 ms preferred.
+    pub frame_durations: u8,
+    /// Bit `n` set: `n + 1` channels supported.
+    pub audio_channel_counts: u8,
+    pub min_octets_per_frame: u16,
+    pub max_octets_per_frame: u16,
+    pub max_frames_per_sdu: u8,
+}
+impl LC3Capabilities {
+    pub const SAMPLING_FREQUENCIES: u8 = 0x01;
+    pub const FRAME_DURATIONS: u8 = 0x02;
+    pub const AUDIO_CHANNEL_COUNTS: u8 = 0x03;
+    pub const OCTETS_PER_FRAME: u8 = 0x04;
+    pub const MAX_FRAMES_PER_SDU: u8 = 0x05;
+    /// Parses the capability LTVs. Unknown types are skipped.
+    /// # Errors
+    /// returns a `PackError` for malformed LTVs or known types with the wrong length.
+    pub fn unpack_from(capabilities: &[u8]) -> Result<LC3Capabilities, PackError> {
+        let mut out = LC3Capabilities {
+            sampling_frequencies: 0,
+            frame_durations: 0,
+            audio_channel_counts: 0b1,
+            min_octets_per_frame: 0,
+            max_octets_per_frame: 0,
+            max_frames_per_sdu: 1,
+        };
+        for (ltv_type, value) in ltvs(capabilities)? {
+            match ltv_type {
+                Self::SAMPLING_FREQUENCIES => {
+                    PackError::expect_length(2, value)?;
+                    out.sampling_frequencies = u16::from_le_bytes([value[0], value[1]]);
+                }
+                Self::FRAME_DURATIONS => {
+                    PackError::expect_length(1, value)?;
+                    out.frame_durations = value[0];
+                }
+                Self::AUDIO_CHANNEL_COUNTS => {
+                    PackError::expect_length(1, value)?;
+                    out.audio_channel_counts = value[0];
+                }
+                Self::OCTETS_PER_FRAME => {
+                    PackError::expect_length(4, value)?;
+                    out.min_octets_per_frame = u16::from_le_bytes([value[0], value[1]]);
+                    out.max_octets_per_frame = u16::from_le_bytes([value[2], value[3]]);
+                }
+                Self::MAX_FRAMES_PER_SDU => {
+                    PackError::expect_length(1, value)?;
+                    out.max_frames_per_sdu = value[0];
+                }
+                _ => (),
+            }
+        }
+        Ok(out)
+    }
+    pub fn supports_sampling_frequency(&self, hz: u32) -> bool {
+        SAMPLING_FREQUENCIES_HZ
+            .iter()
+            .position(|&f| f == hz)
+            .map_or(false, |bit| self.sampling_frequencies & (1 << bit) != 0)
+    }
+    /// `true` for 7.5 ms frames, `false` for 10 ms frames.
+    pub fn supports_frame_duration(&self, short: bool) -> bool {
+        self.frame_durations & if short { 0b01 } else { 0b10 } != 0
+    }
+    pub fn supports_channel_count(&self, channels: u8) -> bool {
+        (1..=8).contains(&channels) && self.audio_channel_counts & (1 << (channels - 1)) != 0
+    }
+    pub fn supports_octets_per_frame(&self, octets: u16) -> bool {
+        (self.min_octets_per_frame..=self.max_octets_per_frame).contains(&octets)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lc3_pac_record() {
+        // One LC3 record: 16/24/48 kHz, 10 ms, 1 channel, 40-120 octets, 2 frames per SDU and
+        // a Preferred Audio Contexts metadata entry.
+        let value = [
+            0x01, 0x06, 0x00, 0x00, 0x00, 0x00, 0x10, 0x03, 0x01, 0x94, 0x00, 0x02, 0x02, 0x02,
+            0x05, 0x04, 0x28, 0x00, 0x78, 0x00, 0x02, 0x05, 0x02, 0x04, 0x03, 0x01, 0x04, 0x00,
+        ];
+        let records = unpack_pac_records(&value).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            ltvs(&records[0].metadata).unwrap(),
+            [(0x01, &[0x04, 0x00][..])]
+        );
+        let lc3 = records[0].lc3_capabilities().unwrap().unwrap();
+        assert!(lc3.supports_sampling_frequency(48_000));
+        assert!(!lc3.supports_sampling_frequency(32_000));
+        assert!(lc3.supports_frame_duration(false) && !lc3.supports_frame_duration(true));
+        assert!(lc3.supports_channel_count(1) && !lc3.supports_channel_count(2));
+        assert!(lc3.supports_octets_per_frame(100) && !lc3.supports_octets_per_frame(155));
+        assert_eq!(lc3.max_frames_per_sdu, 2);
+        assert!(unpack_pac_records(&value[..value.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Volume Control Service (VCS). The Volume State characteristic holds the volume, mute and a
+//! change counter. Every Volume Control Point operation has to carry the current change counter
+//! or the server rejects it (someone else changed the volume in the meantime).
+use crate::le::att::attribute::Handle;
+use crate::le::gatt::client::{AttributeIndex, ClientError};
+use crate::uuid::UUID16;
+use crate::PackError;
+use alloc::vec::Vec;
+
+pub const VOLUME_CONTROL_SERVICE: UUID16 = UUID16(0x1844);
+pub const VOLUME_STATE: UUID16 = UUID16(0x2B7D);
+pub const VOLUME_CONTROL_POINT: UUID16 = UUID16(0x2B7E);
+pub const VOLUME_FLAGS: UUID16 = UUID16(0x2B7F);
+/// ATT error of control point writes with an outdated change counter.
+pub const INVALID_CHANGE_COUNTER: u8 = 0x80;
+/// ATT error of unsupported control point opcodes.
+pub const OPCODE_NOT_SUPPORTED: u8 = 0x81;
+
+/// Value of the Volume State characteristic.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct VolumeState {
+    /// `0` (minimum) to `255` (maximum).
+    pub volume_setting: u8,
+    pub mute: bool,
+    pub change_counter: u8,
+}
+impl VolumeState {
+    pub const BYTE_LEN: usize = 3;
+    pub fn unpack_from(value: &[u8]) -> Result<VolumeState, PackError> {
+        PackError::expect_length(Self::BYTE_LEN, value)?;
+        Ok(VolumeState {
+            volume_setting: value[0],
+            mute: match value[1] {
+                0 => false,
+                1 => true,
+                _ => return Err(PackError::bad_index(1)),
+            },
+            change_counter: value[2],
+        })
+    }
+}
+/// Volume Control Point operations.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum VolumeOperation {
+    RelativeVolumeDown,
+    RelativeVolumeUp,
+    UnmuteRelativeVolumeDown,
+    UnmuteRelativeVolumeUp,
+    SetAbsoluteVolume(u8),
+    Unmute,
+    Mute,
+}
+impl VolumeOperation {
+    pub fn opcode(self) -> u8 {
+        match self {
+            VolumeOperation::RelativeVolumeDown => 0x00,
+            VolumeOperation::RelativeVolumeUp => 0x01,
+            VolumeOperation::UnmuteRelativeVolumeDown => 0x02,
+            VolumeOperation::UnmuteRelativeVolumeUp => 0x03,
+            VolumeOperation::SetAbsoluteVolume(_) => 0x04,
+            VolumeOperation::Unmute => 0x05,
+            VolumeOperation::Mute => 0x06,
+        }
+    }
+    /// Control point value for a server at `change_counter`.
+    pub fn pack(self, change_counter: u8) -> Vec<u8> {
+        let mut value = vec![self.opcode(), change_counter];
+        if let VolumeOperation::SetAbsoluteVolume(volume) = self {
+            value.push(volume);
+        }
+        value
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum VCSError {
+    /// The Volume State (and its change counter) hasn't been read or notified yet.
+    StateUnknown,
+    Client(ClientError),
+}
+impl core::fmt::Display for VCSError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "vcs error {:?}", self)
+    }
+}
+impl crate::error::Error for VCSError {}
+#[cfg(feature = "std")]
+impl std::error::Error for VCSError {}
+impl From<ClientError> for VCSError {
+    fn from(e: ClientError) -> Self {
+        VCSError::Client(e)
+    }
+}
+/// Client side view of a server's VCS. Feed it Volume State reads and notifications with
+/// [`VCSClient::update`]. After an [`INVALID_CHANGE_COUNTER`] error, read the Volume State again
+/// before retrying.
+#[derive(Copy, Clone, Debug)]
+pub struct VCSClient {
+    volume_state: Handle,
+    control_point: Handle,
+    state: Option<VolumeState>,
+}
+impl VCSClient {
+    pub fn from_index(index: &AttributeIndex) -> Option<VCSClient> {
+        Some(VCSClient {
+            volume_state: index.find_characteristic(VOLUME_STATE)?.value_handle,
+            control_point: index
+                .find_characteristic(VOLUME_CONTROL_POINT)?
+                .value_handle,
+            state: None,
+        })
+    }
+    /// Value handle of the Volume State (to read it or enable notifications).
+    pub fn volume_state_handle(&self) -> Handle {
+        self.volume_state
+    }
+    pub fn state(&self) -> Option<VolumeState> {
+        self.state
+    }
+    /// Takes a read or notified value. Returns `None` for other handles.
+    pub fn update(
+        &mut self,
+        value_handle: Handle,
+        value: &[u8],
+    ) -> Result<Option<VolumeState>, PackError> {
+        if value_handle != self.volume_state {
+            return Ok(None);
+        }
+        self.state = Some(VolumeState::unpack_from(value)?);
+        Ok(self.state)
+    }
+    /// ATT Write Request PDU for `operation` with the last known change counter.
+    pub fn request(
+        &self,
+        index: &AttributeIndex,
+        operation: VolumeOperation,
+    ) -> Result<Vec<u8>, VCSError> {
+        let state = self.state.ok_or(VCSError::StateUnknown)?;
+        let value = operation.pack(state.change_counter);
+        Ok(index.write_request(self.control_point, &value, true)?)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volume_control() {
+        let mut index = AttributeIndex::new();
+        index
+            .add_services_from_response(&[6, 0x01, 0x00, 0x06, 0x00, 0x44, 0x18], true)
+            .unwrap();
+        index
+            .add_characteristics_from_response(&[
+                7, 0x02, 0x00, 0x12, 0x03, 0x00, 0x7D, 0x2B, 0x05, 0x00, 0x08, 0x06, 0x00, 0x7E,
+                0x2B,
+            ])
+            .unwrap();
+        let mut client = VCSClient::from_index(&index).unwrap();
+        assert_eq!(
+            client.request(&index, VolumeOperation::Mute),
+            Err(VCSError::StateUnknown)
+        );
+        let state = client
+            .update(Handle::new(0x03), &[0x80, 0x00, 0x07])
+            .unwrap();
+        assert_eq!(
+            state,
+            Some(VolumeState {
+                volume_setting: 0x80,
+                mute: false,
+                change_counter: 7,
+            })
+        );
+        assert_eq!(
+            client.request(&index, VolumeOperation::SetAbsoluteVolume(0x40)),
+            Ok(vec![0x12, 0x06, 0x00, 0x04, 0x07, 0x40])
+        );
+    }
+}
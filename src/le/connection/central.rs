@@ -1,6 +1,48 @@
-use crate::hci::adapters::DummyUnrecognizedEventHandler;
+//! Central role handle. Borrows an [`LEAdapter`] and only exposes what a central does: scan,
+//! initiate connections and manage the connections it made.
+use crate::hci::adapter;
+use crate::hci::adapters::le::LEAdapter;
+use crate::hci::adapters::UnrecognizedEventHandler;
+use crate::hci::le::connection::CreateConnection;
+use crate::hci::ErrorCode;
+use crate::le::connection::{ConnectionHandle, ConnectionParameters};
+use crate::le::scan::Observer;
 
-pub struct Central<A: crate::hci::adapter::Adapter> {
-    pub hci_adapter: crate::hci::adapters::le::LEAdapter<A, DummyUnrecognizedEventHandler>,
+/// Made with [`Host::central`](crate::host::Host::central) or [`Central::new`].
+pub struct Central<'a, A: adapter::Adapter, H: UnrecognizedEventHandler> {
+    le: &'a mut LEAdapter<A, H>,
+}
+impl<'a, A: adapter::Adapter, H: UnrecognizedEventHandler> Central<'a, A, H> {
+    pub fn new(le: &'a mut LEAdapter<A, H>) -> Self {
+        Central { le }
+    }
+    pub fn scanner(&mut self) -> &mut impl Observer {
+        &mut *self.le
+    }
+    /// Starts connecting. See [`LEAdapter::create_connection`].
+    pub async fn connect(
+        &mut self,
+        create_connection: CreateConnection,
+    ) -> Result<(), adapter::Error> {
+        self.le.create_connection(create_connection).await
+    }
+    pub async fn cancel_connect(&mut self) -> Result<(), adapter::Error> {
+        self.le.create_connection_cancel().await
+    }
+    pub async fn update(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        parameters: ConnectionParameters,
+    ) -> Result<(), adapter::Error> {
+        self.le
+            .connection_update(connection_handle, parameters)
+            .await
+    }
+    pub async fn disconnect(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        reason: ErrorCode,
+    ) -> Result<(), adapter::Error> {
+        self.le.adapter.disconnect(connection_handle, reason).await
+    }
 }
-impl<A: crate::hci::adapter::Adapter> Central<A> {}
@@ -0,0 +1,264 @@
+//! ATT bearer idle policies. Either disconnect links without any ATT traffic for a while or keep
+//! them alive by periodically reading a heartbeat characteristic. Time is any monotonic `Duration`
+//! since a fixed epoch supplied by the caller.
+//!
+//! [`IdleManager`] follows connections from the HCI events fed to
+//! [`IdleManager::handle_event`], so it can sit next to the other connection trackers (like
+//! [`IdentityResolver`](crate::le::identity::IdentityResolver)) in the event loop.
+use crate::hci::adapters::dispatcher::connection_handle_of;
+use crate::hci::event::{EventCode, EventPacket};
+use crate::hci::le::MetaEventCode;
+use crate::le::att::attribute::Handle;
+use crate::le::att::pdus::read::ReadReq;
+use crate::le::connection::ConnectionHandle;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::time::Duration;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum IdlePolicy {
+    /// Never act on idle links.
+    Ignore,
+    /// Disconnect after the given time without ATT traffic.
+    DisconnectAfter(Duration),
+    /// Read `handle` every `period` without ATT traffic. Disconnect after `max_missed` heartbeats
+    /// went unanswered. `max_missed` has to be at least 1.
+    Heartbeat {
+        handle: Handle,
+        period: Duration,
+        max_missed: u8,
+    },
+}
+impl IdlePolicy {
+    pub fn is_valid(&self) -> bool {
+        !matches!(self, IdlePolicy::Heartbeat { max_missed: 0, .. })
+    }
+}
+impl Default for IdlePolicy {
+    fn default() -> Self {
+        IdlePolicy::Ignore
+    }
+}
+/// What should be done with an idle connection.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum IdleAction {
+    Disconnect,
+    /// Send the heartbeat read.
+    Read(ReadReq),
+}
+/// Idle state of one connection.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct IdleTracker {
+    policy: IdlePolicy,
+    last_traffic: Duration,
+    last_heartbeat: Option<Duration>,
+    missed: u8,
+}
+impl IdleTracker {
+    /// # Panics
+    /// Panics if `policy` isn't valid ([`IdlePolicy::is_valid`]).
+    pub fn new(policy: IdlePolicy, now: Duration) -> IdleTracker {
+        assert!(policy.is_valid(), "invalid idle policy");
+        IdleTracker {
+            policy,
+            last_traffic: now,
+            last_heartbeat: None,
+            missed: 0,
+        }
+    }
+    pub fn policy(&self) -> IdlePolicy {
+        self.policy
+    }
+    /// # Panics
+    /// Panics if `policy` isn't valid ([`IdlePolicy::is_valid`]).
+    pub fn set_policy(&mut self, policy: IdlePolicy) {
+        assert!(policy.is_valid(), "invalid idle policy");
+        self.policy = policy;
+    }
+    /// Call on every ATT PDU received on the bearer (including heartbeat responses). PDUs sent
+    /// don't count, they don't show the peer is still there (and the heartbeat reads would reset
+    /// the missed heartbeats).
+    pub fn traffic(&mut self, now: Duration) {
+        self.last_traffic = now;
+        self.last_heartbeat = None;
+        self.missed = 0;
+    }
+    pub fn idle_for(&self, now: Duration) -> Duration {
+        now.checked_sub(self.last_traffic).unwrap_or_default()
+    }
+    /// Returns the action to take (if any) at `now`.
+    pub fn poll(&mut self, now: Duration) -> Option<IdleAction> {
+        match self.policy {
+            IdlePolicy::Ignore => None,
+            IdlePolicy::DisconnectAfter(timeout) => {
+                if self.idle_for(now) >= timeout {
+                    Some(IdleAction::Disconnect)
+                } else {
+                    None
+                }
+            }
+            IdlePolicy::Heartbeat {
+                handle,
+                period,
+                max_missed,
+            } => {
+                let since = self.last_heartbeat.unwrap_or(self.last_traffic);
+                if now.checked_sub(since).unwrap_or_default() < period {
+                    return None;
+                }
+                if self.last_heartbeat.is_some() {
+                    self.missed = self.missed.saturating_add(1);
+                }
+                if self.missed >= max_missed {
+                    Some(IdleAction::Disconnect)
+                } else {
+                    self.last_heartbeat = Some(now);
+                    Some(IdleAction::Read(ReadReq(handle)))
+                }
+            }
+        }
+    }
+}
+/// Idle state of every connection. Meant to be driven by whatever owns the connections: report
+/// traffic with [`IdleManager::traffic`] and act on what [`IdleManager::poll`] returns.
+#[derive(Clone, Debug, Default)]
+pub struct IdleManager {
+    default_policy: IdlePolicy,
+    connections: BTreeMap<ConnectionHandle, IdleTracker>,
+}
+impl IdleManager {
+    /// # Panics
+    /// Panics if `default_policy` isn't valid ([`IdlePolicy::is_valid`]).
+    pub fn new(default_policy: IdlePolicy) -> IdleManager {
+        assert!(default_policy.is_valid(), "invalid idle policy");
+        IdleManager {
+            default_policy,
+            connections: BTreeMap::new(),
+        }
+    }
+    /// Start tracking `handle` with the default policy.
+    pub fn connected(&mut self, handle: ConnectionHandle, now: Duration) {
+        self.connections
+            .insert(handle, IdleTracker::new(self.default_policy, now));
+    }
+    pub fn disconnected(&mut self, handle: ConnectionHandle) {
+        self.connections.remove(&handle);
+    }
+    /// Tracks connections from (enhanced) connection complete and disconnection complete events.
+    /// Other events are ignored.
+    pub fn handle_event(&mut self, event: EventPacket<&[u8]>, now: Duration) {
+        let parameters = event.parameters;
+        let status = match event.event_code {
+            EventCode::DisconnectionComplete => parameters.first(),
+            EventCode::LEMeta => match MetaEventCode::try_from(*parameters.first().unwrap_or(&0)) {
+                Ok(
+                    MetaEventCode::ConnectionComplete | MetaEventCode::EnhancedConnectionComplete,
+                ) => parameters.get(1),
+                _ => return,
+            },
+            _ => return,
+        };
+        if status != Some(&0) {
+            return;
+        }
+        let disconnected = event.event_code == EventCode::DisconnectionComplete;
+        if let Some(handle) = connection_handle_of(event) {
+            if disconnected {
+                self.disconnected(handle);
+            } else {
+                self.connected(handle, now);
+            }
+        }
+    }
+    /// Override the policy of one connection.
+    /// # Panics
+    /// Panics if `policy` isn't valid ([`IdlePolicy::is_valid`]).
+    pub fn set_policy(&mut self, handle: ConnectionHandle, policy: IdlePolicy) {
+        if let Some(tracker) = self.connections.get_mut(&handle) {
+            tracker.set_policy(policy);
+        }
+    }
+    pub fn tracker(&self, handle: ConnectionHandle) -> Option<&IdleTracker> {
+        self.connections.get(&handle)
+    }
+    pub fn traffic(&mut self, handle: ConnectionHandle, now: Duration) {
+        if let Some(tracker) = self.connections.get_mut(&handle) {
+            tracker.traffic(now);
+        }
+    }
+    /// Actions to take for every connection at `now`.
+    pub fn poll(&mut self, now: Duration) -> Vec<(ConnectionHandle, IdleAction)> {
+        self.connections
+            .iter_mut()
+            .filter_map(|(&handle, tracker)| tracker.poll(now).map(|action| (handle, action)))
+            .collect()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+    #[test]
+    fn test_heartbeat() {
+        let handle = Handle::new(0x0010);
+        let policy = IdlePolicy::Heartbeat {
+            handle,
+            period: secs(5),
+            max_missed: 2,
+        };
+        assert!(!IdlePolicy::Heartbeat {
+            handle,
+            period: secs(5),
+            max_missed: 0,
+        }
+        .is_valid());
+        let mut tracker = IdleTracker::new(policy, secs(0));
+        assert_eq!(tracker.poll(secs(4)), None);
+        assert_eq!(
+            tracker.poll(secs(5)),
+            Some(IdleAction::Read(ReadReq(handle)))
+        );
+        // Answered.
+        tracker.traffic(secs(6));
+        assert_eq!(tracker.poll(secs(10)), None);
+        assert_eq!(
+            tracker.poll(secs(11)),
+            Some(IdleAction::Read(ReadReq(handle)))
+        );
+        // Unanswered.
+        assert_eq!(
+            tracker.poll(secs(16)),
+            Some(IdleAction::Read(ReadReq(handle)))
+        );
+        assert_eq!(tracker.poll(secs(20)), None);
+        assert_eq!(tracker.poll(secs(21)), Some(IdleAction::Disconnect));
+    }
+    #[test]
+    fn test_manager_lifecycle() {
+        let mut manager = IdleManager::new(IdlePolicy::DisconnectAfter(secs(30)));
+        let handle = ConnectionHandle::new(0x0040);
+        let mut complete = vec![0x01, 0x00, 0x40, 0x00, 0x00, 0x00];
+        complete.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+        complete.extend_from_slice(&[0x18, 0x00, 0x00, 0x00, 0xC8, 0x00, 0x00]);
+        manager.handle_event(EventPacket::new(EventCode::LEMeta, &complete[..]), secs(0));
+        assert!(manager.tracker(handle).is_some());
+        manager.traffic(handle, secs(20));
+        assert!(manager.poll(secs(49)).is_empty());
+        assert_eq!(manager.poll(secs(50)), [(handle, IdleAction::Disconnect)]);
+
+        let disconnect = [0x00, 0x40, 0x00, 0x13];
+        manager.handle_event(
+            EventPacket::new(EventCode::DisconnectionComplete, &disconnect[..]),
+            secs(51),
+        );
+        assert!(manager.tracker(handle).is_none());
+        // Failed connections aren't tracked.
+        complete[1] = 0x3E;
+        manager.handle_event(EventPacket::new(EventCode::LEMeta, &complete[..]), secs(52));
+        assert!(manager.poll(secs(100)).is_empty());
+    }
+}
@@ -1,4 +1,6 @@
 pub mod central;
+pub mod idle;
+pub mod peripheral;
 
 use crate::ConversionError;
 use core::convert::TryFrom;
@@ -46,6 +48,10 @@ impl ConnectionInterval {
             None => panic!("connection interval out of range (`{}`)", value),
         }
     }
+    /// Interval as a `Duration` (1.25 ms per unit).
+    pub fn as_duration(self) -> core::time::Duration {
+        core::time::Duration::from_micros(u64::from(self.0) * 1250)
+    }
     pub fn new_checked(value: u16) -> Option<Self> {
         if value > Self::MAX_U16 || value < Self::MIN_U16 {
             None
@@ -128,6 +134,100 @@ impl From<ConnectionLatency> for u16 {
     }
 }
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ConnectionParametersError {
+    IntervalMinAboveMax,
+    CELengthMinAboveMax,
+    /// The supervision timeout has to be longer than `(1 + latency) * interval_max * 2`.
+    SupervisionTimeoutTooShort,
+    /// A scan window (while initiating) is larger than its scan interval.
+    WindowLargerThanInterval,
+    /// No initiating PHY has parameters or only LE 2M does (it can't be scanned on).
+    NoInitiatingPHYs,
+}
+impl core::fmt::Display for ConnectionParametersError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "connection parameters error {:?}", self)
+    }
+}
+impl crate::error::Error for ConnectionParametersError {}
+#[cfg(feature = "std")]
+impl std::error::Error for ConnectionParametersError {}
+/// Connection interval range, latency, supervision timeout and connection event length the
+/// central asks for (when connecting or updating a connection).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ConnectionParameters {
+    pub interval_min: ConnectionInterval,
+    pub interval_max: ConnectionInterval,
+    pub latency: ConnectionLatency,
+    pub supervision_timeout: SupervisionTimeout,
+    pub min_ce_len: CELength,
+    pub max_ce_len: CELength,
+}
+impl ConnectionParameters {
+    pub const BYTE_LEN: usize = ConnectionInterval::BYTE_LEN * 2
+        + ConnectionLatency::BYTE_LEN
+        + SupervisionTimeout::BYTE_LEN
+        + CELength::BYTE_LEN * 2;
+    /// 30 ms to 50 ms interval, no latency and a 4 s supervision timeout.
+    pub const DEFAULT: ConnectionParameters = ConnectionParameters {
+        interval_min: ConnectionInterval(0x0018),
+        interval_max: ConnectionInterval(0x0028),
+        latency: ConnectionLatency(0),
+        supervision_timeout: SupervisionTimeout(0x0190),
+        min_ce_len: CELength::MIN,
+        max_ce_len: CELength::MIN,
+    };
+    pub fn validate(&self) -> Result<(), ConnectionParametersError> {
+        if self.interval_min > self.interval_max {
+            return Err(ConnectionParametersError::IntervalMinAboveMax);
+        }
+        if self.min_ce_len > self.max_ce_len {
+            return Err(ConnectionParametersError::CELengthMinAboveMax);
+        }
+        // Timeout in 10 ms units, interval in 1.25 ms units: timeout * 10 > (1 + latency) *
+        // interval * 1.25 * 2.
+        let min_timeout =
+            (1 + u32::from(self.latency.0)) * u32::from(self.interval_max.0) * 2 * 125 / 1000;
+        if u32::from(self.supervision_timeout.0) <= min_timeout {
+            return Err(ConnectionParametersError::SupervisionTimeoutTooShort);
+        }
+        Ok(())
+    }
+    /// Packs the six fields (little endian) in HCI command order.
+    pub fn pack_into(&self, buf: &mut [u8]) -> Result<(), crate::PackError> {
+        crate::PackError::expect_length(Self::BYTE_LEN, buf)?;
+        for (out, value) in buf.chunks_exact_mut(2).zip([
+            self.interval_min.0,
+            self.interval_max.0,
+            self.latency.0,
+            self.supervision_timeout.0,
+            self.min_ce_len.0,
+            self.max_ce_len.0,
+        ]) {
+            out.copy_from_slice(&value.to_le_bytes());
+        }
+        Ok(())
+    }
+    pub fn unpack_from(buf: &[u8]) -> Result<ConnectionParameters, crate::PackError> {
+        crate::PackError::expect_length(Self::BYTE_LEN, buf)?;
+        let field = |i: usize| u16::from_le_bytes([buf[i * 2], buf[i * 2 + 1]]);
+        let bad = crate::PackError::bad_index;
+        Ok(ConnectionParameters {
+            interval_min: ConnectionInterval::new_checked(field(0)).ok_or(bad(0))?,
+            interval_max: ConnectionInterval::new_checked(field(1)).ok_or(bad(2))?,
+            latency: ConnectionLatency::new_checked(field(2)).ok_or(bad(4))?,
+            supervision_timeout: SupervisionTimeout::new_checked(field(3)).ok_or(bad(6))?,
+            min_ce_len: CELength(field(4)),
+            max_ce_len: CELength(field(5)),
+        })
+    }
+}
+impl Default for ConnectionParameters {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
 pub struct ConnectionHandle(u16);
 impl ConnectionHandle {
     pub fn new(value: u16) -> Self {
@@ -155,6 +255,31 @@ impl From<ConnectionHandle> for u16 {
         h.0
     }
 }
+/// 16-bit connection event counter (`connEventCounter`). HCI only exposes it in some LE events
+/// (like [`ConnectionIQReport`](crate::hci::le::cte::ConnectionIQReport)) so it has to be tracked
+/// from those.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct ConnectionEventCounter(pub u16);
+impl ConnectionEventCounter {
+    pub const BYTE_LEN: usize = 2;
+    /// Number of connection events from `earlier` to `self`, accounting for one wrap around.
+    pub fn events_since(self, earlier: ConnectionEventCounter) -> u16 {
+        self.0.wrapping_sub(earlier.0)
+    }
+    /// Time from `earlier` to `self` assuming `interval` didn't change in between.
+    pub fn duration_since(
+        self,
+        earlier: ConnectionEventCounter,
+        interval: ConnectionInterval,
+    ) -> core::time::Duration {
+        interval.as_duration() * u32::from(self.events_since(earlier))
+    }
+}
+impl From<ConnectionEventCounter> for u16 {
+    fn from(c: ConnectionEventCounter) -> Self {
+        c.0
+    }
+}
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
 #[repr(u8)]
 pub enum InitiatorFilterPolicy {
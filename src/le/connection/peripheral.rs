@@ -0,0 +1,40 @@
+//! Peripheral role handle. Borrows an [`LEAdapter`] and only exposes what a peripheral does:
+//! advertise and manage the connections made to it.
+use crate::hci::adapter;
+use crate::hci::adapters::le::LEAdapter;
+use crate::hci::adapters::UnrecognizedEventHandler;
+use crate::hci::le::connection::RemoteConnectionParameterRequest;
+use crate::hci::ErrorCode;
+use crate::le::advertiser::Advertiser;
+use crate::le::connection::{ConnectionHandle, ConnectionParameters};
+
+/// Made with [`Host::peripheral`](crate::host::Host::peripheral) or [`Peripheral::new`].
+pub struct Peripheral<'a, A: adapter::Adapter, H: UnrecognizedEventHandler> {
+    le: &'a mut LEAdapter<A, H>,
+}
+impl<'a, A: adapter::Adapter, H: UnrecognizedEventHandler> Peripheral<'a, A, H> {
+    pub fn new(le: &'a mut LEAdapter<A, H>) -> Self {
+        Peripheral { le }
+    }
+    pub fn advertiser(&mut self) -> &mut impl Advertiser {
+        &mut *self.le
+    }
+    /// Answers a central's connection parameter request. See
+    /// [`LEAdapter::answer_connection_parameter_request`].
+    pub async fn answer_connection_parameter_request(
+        &mut self,
+        request: &RemoteConnectionParameterRequest,
+        accept: impl FnOnce(&ConnectionParameters) -> Result<ConnectionParameters, ErrorCode>,
+    ) -> Result<(), adapter::Error> {
+        self.le
+            .answer_connection_parameter_request(request, accept)
+            .await
+    }
+    pub async fn disconnect(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        reason: ErrorCode,
+    ) -> Result<(), adapter::Error> {
+        self.le.adapter.disconnect(connection_handle, reason).await
+    }
+}
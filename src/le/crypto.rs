@@ -0,0 +1,318 @@
+//! LE security toolbox functions (Core Spec Vol 3, Part H, 2.2). Uses a small software AES-128 so
+//! it works without a controller (or on controllers without LE Encrypt).
+//!
+//! Keys and plaintext are in the spec's most significant octet first order. Values coming from HCI
+//! (little endian) have to be reversed first.
+use alloc::boxed::Box;
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+const RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+pub const KEY_LEN: usize = 16;
+pub const BLOCK_LEN: usize = 16;
+pub type Key = [u8; KEY_LEN];
+pub type Block = [u8; BLOCK_LEN];
+
+/// Marker for key types (LTK, IRK, CSRK, link keys). They only print `<redacted>` with `Debug`,
+/// have no `Display` or `Serialize` impl and are zeroed on drop with the `zeroize` feature.
+pub trait KeyMaterial {
+    fn key_bytes(&self) -> &[u8; KEY_LEN];
+}
+/// Zeroes pairing intermediate values with the `zeroize` feature (a no-op otherwise).
+#[allow(unused_variables)]
+pub(crate) fn wipe(bytes: &mut [u8]) {
+    #[cfg(feature = "zeroize")]
+    zeroize::Zeroize::zeroize(bytes);
+}
+/// Implements [`KeyMaterial`], redacted `Debug` and zeroize on drop for a `struct $name([u8; 16])`.
+macro_rules! key_material {
+    ($name:ident) => {
+        impl crate::le::crypto::KeyMaterial for $name {
+            fn key_bytes(&self) -> &[u8; crate::le::crypto::KEY_LEN] {
+                &self.0
+            }
+        }
+        impl core::fmt::Debug for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.write_str(concat!(stringify!($name), "(<redacted>)"))
+            }
+        }
+        #[cfg(feature = "zeroize")]
+        impl Drop for $name {
+            fn drop(&mut self) {
+                zeroize::Zeroize::zeroize(&mut self.0);
+            }
+        }
+    };
+}
+pub(crate) use key_material;
+
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ (if b & 0x80 != 0 { 0x1b } else { 0x00 })
+}
+fn expand_key(key: &Key) -> [[u8; 16]; 11] {
+    let mut round_keys = [[0_u8; 16]; 11];
+    round_keys[0] = *key;
+    for round in 1..11 {
+        let prev = round_keys[round - 1];
+        let mut word = [prev[13], prev[14], prev[15], prev[12]];
+        for b in &mut word {
+            *b = SBOX[usize::from(*b)];
+        }
+        word[0] ^= RCON[round - 1];
+        let mut next = [0_u8; 16];
+        for i in 0..16 {
+            let w = if i < 4 { word[i] } else { next[i - 4] };
+            next[i] = prev[i] ^ w;
+        }
+        round_keys[round] = next;
+    }
+    round_keys
+}
+fn add_round_key(state: &mut Block, round_key: &[u8; 16]) {
+    for (s, k) in state.iter_mut().zip(round_key.iter()) {
+        *s ^= *k;
+    }
+}
+fn sub_bytes_shift_rows(state: &mut Block) {
+    let old = *state;
+    for column in 0..4 {
+        for row in 0..4 {
+            state[column * 4 + row] = SBOX[usize::from(old[((column + row) % 4) * 4 + row])];
+        }
+    }
+}
+fn mix_columns(state: &mut Block) {
+    for column in state.chunks_exact_mut(4) {
+        let all = column[0] ^ column[1] ^ column[2] ^ column[3];
+        let first = column[0];
+        column[0] ^= all ^ xtime(column[0] ^ column[1]);
+        column[1] ^= all ^ xtime(column[1] ^ column[2]);
+        column[2] ^= all ^ xtime(column[2] ^ column[3]);
+        column[3] ^= all ^ xtime(column[3] ^ first);
+    }
+}
+/// AES-128 encrypt one block.
+pub fn aes128(key: &Key, plaintext: &Block) -> Block {
+    let round_keys = expand_key(key);
+    let mut state = *plaintext;
+    add_round_key(&mut state, &round_keys[0]);
+    for round_key in &round_keys[1..10] {
+        sub_bytes_shift_rows(&mut state);
+        mix_columns(&mut state);
+        add_round_key(&mut state, round_key);
+    }
+    sub_bytes_shift_rows(&mut state);
+    add_round_key(&mut state, &round_keys[10]);
+    state
+}
+/// Randomness (and optionally AES) used by the security manager for pairing randoms, keys and
+/// private addresses. The crate has no entropy source of its own, so the platform (or the
+/// controller's LE Rand) has to provide one. [`aes128`] is used unless overridden, for example
+/// to use a hardware AES block.
+pub trait CryptoBackend {
+    /// Fills `buf` with cryptographically secure random bytes.
+    fn fill_random(&mut self, buf: &mut [u8]);
+    /// Security function `e`.
+    fn aes128(&mut self, key: &Key, plaintext: &Block) -> Block {
+        aes128(key, plaintext)
+    }
+}
+impl<C: CryptoBackend + ?Sized> CryptoBackend for Box<C> {
+    fn fill_random(&mut self, buf: &mut [u8]) {
+        (**self).fill_random(buf);
+    }
+    fn aes128(&mut self, key: &Key, plaintext: &Block) -> Block {
+        (**self).aes128(key, plaintext)
+    }
+}
+/// Security function `e`.
+pub fn e(key: &Key, plaintext: &Block) -> Block {
+    aes128(key, plaintext)
+}
+/// Random address hash function `ah`. Only the lower 24 bits of `r` are used and the result is
+/// 24 bits.
+pub fn ah(irk: &Key, r: u32) -> u32 {
+    let mut r_prime = [0_u8; BLOCK_LEN];
+    r_prime[13..].copy_from_slice(&r.to_be_bytes()[1..]);
+    let out = e(irk, &r_prime);
+    u32::from_be_bytes([0, out[13], out[14], out[15]])
+}
+/// Doubles `block` in GF(2^128) (CMAC subkey generation).
+fn double(block: &Block) -> Block {
+    let mut out = [0_u8; BLOCK_LEN];
+    for i in 0..BLOCK_LEN {
+        let carry = block.get(i + 1).map_or(0, |next| next >> 7);
+        out[i] = (block[i] << 1) | carry;
+    }
+    if block[0] & 0x80 != 0 {
+        out[BLOCK_LEN - 1] ^= 0x87;
+    }
+    out
+}
+/// AES-CMAC (RFC 4493) of `message` with `key`.
+pub fn aes_cmac(key: &Key, message: &[u8]) -> Block {
+    let mut k1 = double(&aes128(key, &[0_u8; BLOCK_LEN]));
+    let mut k2 = double(&k1);
+    let blocks = message.chunks(BLOCK_LEN).count().max(1);
+    let mut x = [0_u8; BLOCK_LEN];
+    for (i, chunk) in message.chunks(BLOCK_LEN).enumerate() {
+        if i + 1 == blocks {
+            break;
+        }
+        for (x, m) in x.iter_mut().zip(chunk) {
+            *x ^= m;
+        }
+        x = aes128(key, &x);
+    }
+    let last = &message[(blocks - 1) * BLOCK_LEN..];
+    let mut padded = [0_u8; BLOCK_LEN];
+    padded[..last.len()].copy_from_slice(last);
+    let subkey = if last.len() == BLOCK_LEN {
+        &k1
+    } else {
+        padded[last.len()] = 0x80;
+        &k2
+    };
+    for ((x, m), k) in x.iter_mut().zip(&padded).zip(subkey) {
+        *x ^= m ^ k;
+    }
+    let mac = aes128(key, &x);
+    for secret in [&mut k1, &mut k2, &mut x, &mut padded] {
+        wipe(secret);
+    }
+    mac
+}
+/// Link key conversion function `h6`.
+pub fn h6(w: &Key, key_id: [u8; 4]) -> Block {
+    aes_cmac(w, &key_id)
+}
+/// Link key conversion function `h7`.
+pub fn h7(salt: &Key, w: &Key) -> Block {
+    aes_cmac(salt, w)
+}
+/// Salt generation function `s1` (Coordinated Set Identification and Mesh).
+pub fn s1(m: &[u8]) -> Block {
+    aes_cmac(&[0_u8; KEY_LEN], m)
+}
+/// Key derivation function `k1`.
+pub fn k1(n: &Key, salt: &Key, p: &[u8]) -> Block {
+    let mut t = aes_cmac(salt, n);
+    let out = aes_cmac(&t, p);
+    wipe(&mut t);
+    out
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_ah() {
+        // FIPS-197 Appendix C.1.
+        let key = [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+            0x0e, 0x0f,
+        ];
+        let plaintext = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+            0xee, 0xff,
+        ];
+        assert_eq!(
+            aes128(&key, &plaintext),
+            [
+                0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+                0xc5, 0x5a
+            ]
+        );
+        // Core Spec Vol 3, Part H, D.7.
+        let irk = [
+            0xec, 0x02, 0x34, 0xa3, 0x57, 0xc8, 0xad, 0x05, 0x34, 0x10, 0x10, 0xa6, 0x0a, 0x39,
+            0x7d, 0x9b,
+        ];
+        assert_eq!(ah(&irk, 0x0070_8194), 0x000d_fbaa);
+        // RFC 4493 section 4.
+        let key = [
+            0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf,
+            0x4f, 0x3c,
+        ];
+        assert_eq!(
+            aes_cmac(&key, &[]),
+            [
+                0xbb, 0x1d, 0x69, 0x29, 0xe9, 0x59, 0x37, 0x28, 0x7f, 0xa3, 0x7d, 0x12, 0x9b, 0x75,
+                0x67, 0x46
+            ]
+        );
+        let message = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+            0x17, 0x2a,
+        ];
+        assert_eq!(
+            aes_cmac(&key, &message),
+            [
+                0x07, 0x0a, 0x16, 0xb4, 0x6b, 0x4d, 0x41, 0x44, 0xf7, 0x9b, 0xdd, 0x9d, 0xd0, 0x4a,
+                0x28, 0x7c
+            ]
+        );
+        // Core Spec Vol 3, Part H, D.4 and D.5.
+        assert_eq!(
+            h6(&irk, *b"lebr"),
+            [
+                0x2d, 0x9a, 0xe1, 0x02, 0xe7, 0x6d, 0xc9, 0x1c, 0xe8, 0xd3, 0xa9, 0xe2, 0x80, 0xb1,
+                0x63, 0x99
+            ]
+        );
+        let mut salt = [0_u8; 16];
+        salt[12..].copy_from_slice(b"tmp1");
+        assert_eq!(
+            h7(&salt, &irk),
+            [
+                0xfb, 0x17, 0x35, 0x97, 0xc6, 0xa3, 0xc0, 0xec, 0xd2, 0x99, 0x8c, 0x2a, 0x75, 0xa5,
+                0x70, 0x11
+            ]
+        );
+        // Mesh Profile 8.1.1.
+        assert_eq!(
+            s1(b"test"),
+            [
+                0xb7, 0x3c, 0xef, 0xbd, 0x64, 0x1e, 0xf2, 0xea, 0x59, 0x8c, 0x2b, 0x6e, 0xfb, 0x62,
+                0xf7, 0x9c
+            ]
+        );
+        // Mesh Profile 8.1.2.
+        let n = [
+            0x32, 0x16, 0xd1, 0x50, 0x98, 0x84, 0xb5, 0x33, 0x24, 0x85, 0x41, 0x79, 0x2b, 0x87,
+            0x7f, 0x98,
+        ];
+        let salt = [
+            0x2b, 0xa1, 0x4f, 0xfa, 0x0d, 0xf8, 0x4a, 0x28, 0x31, 0x93, 0x8d, 0x57, 0xd2, 0x76,
+            0xca, 0xb4,
+        ];
+        let p = [
+            0x5a, 0x09, 0xd6, 0x07, 0x97, 0xee, 0xb4, 0x47, 0x8a, 0xad, 0xa5, 0x9d, 0xb3, 0x35,
+            0x2a, 0x0d,
+        ];
+        assert_eq!(
+            k1(&n, &salt, &p),
+            [
+                0xf6, 0xed, 0x15, 0xa8, 0x93, 0x4a, 0xfb, 0xe7, 0xd8, 0x3e, 0x8d, 0xcb, 0x57, 0xfc,
+                0xf5, 0xd7
+            ]
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Cache of discovered devices (name, last advertisement data and RSSI, bonding hint) built from
+//! scan reports. A [`CachePersistence`] snapshots and restores the cache so devices stay known
+//! across restarts. [`FileCachePersistence`] (with `std`) stores it in a file.
+use crate::le::advertisement::AdType;
+use crate::le::report::{AddressType, ReportInfo};
+use crate::{BTAddress, PackError, RSSI};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct CachedDevice {
+    pub address_type: AddressType,
+    pub address: BTAddress,
+    /// Complete (or else shortened) local name from the last advertisement that had one.
+    pub name: Option<String>,
+    /// AD data of the last advertisement.
+    pub last_data: Vec<u8>,
+    pub last_rssi: Option<RSSI>,
+    /// Whether the device is (or was) bonded. Only a hint, the bond itself lives elsewhere.
+    pub bonded: bool,
+}
+impl CachedDevice {
+    pub fn new(address_type: AddressType, address: BTAddress) -> CachedDevice {
+        CachedDevice {
+            address_type,
+            address,
+            name: None,
+            last_data: Vec::new(),
+            last_rssi: None,
+            bonded: false,
+        }
+    }
+    /// `address_type (1) | address (6) | rssi (1) | bonded (1) | name_len (1) | name |
+    /// data_len (1) | data`.
+    pub fn byte_len(&self) -> usize {
+        1 + BTAddress::LEN
+            + 1
+            + 1
+            + 1
+            + self.name.as_ref().map_or(0, String::len)
+            + 1
+            + self.last_data.len()
+    }
+    pub fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        let name = self.name.as_ref().map_or(&[][..], String::as_bytes);
+        buf[0] = self.address_type.into();
+        self.address.pack_into(&mut buf[1..7])?;
+        buf[7] = self
+            .last_rssi
+            .map_or(RSSI::UNSUPPORTED_RSSI.to_le_bytes()[0], u8::from);
+        buf[8] = self.bonded.into();
+        buf[9] = u8::try_from(name.len()).map_err(|_| PackError::bad_index(9))?;
+        let data_len_index = 10 + name.len();
+        buf[10..data_len_index].copy_from_slice(name);
+        buf[data_len_index] =
+            u8::try_from(self.last_data.len()).map_err(|_| PackError::bad_index(data_len_index))?;
+        buf[data_len_index + 1..].copy_from_slice(&self.last_data);
+        Ok(())
+    }
+    /// Unpacks one device from the front of `buf`. Returns the device and the bytes used.
+    pub fn unpack_from(buf: &[u8]) -> Result<(CachedDevice, usize), PackError> {
+        PackError::atleast_length(10, buf)?;
+        let name_len = usize::from(buf[9]);
+        let data_len_index = 10 + name_len;
+        PackError::atleast_length(data_len_index + 1, buf)?;
+        let len = data_len_index + 1 + usize::from(buf[data_len_index]);
+        PackError::atleast_length(len, buf)?;
+        let name = match name_len {
+            0 => None,
+            _ => Some(
+                core::str::from_utf8(&buf[10..data_len_index])
+                    .map_err(|_| PackError::bad_index(10))?
+                    .into(),
+            ),
+        };
+        Ok((
+            CachedDevice {
+                address_type: AddressType::try_from(buf[0]).map_err(|_| PackError::bad_index(0))?,
+                address: BTAddress::unpack_from(&buf[1..7])?,
+                name,
+                last_data: buf[data_len_index + 1..len].to_vec(),
+                last_rssi: RSSI::maybe_rssi(i8::from_le_bytes([buf[7]]))
+                    .map_err(|_| PackError::bad_index(7))?,
+                bonded: match buf[8] {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(PackError::bad_index(8)),
+                },
+            },
+            len,
+        ))
+    }
+}
+/// Loads and stores [`DeviceCache`] snapshots.
+pub trait CachePersistence {
+    type Error;
+    fn save(&mut self, devices: &[CachedDevice]) -> Result<(), Self::Error>;
+    /// Returns no devices if nothing was saved yet.
+    fn load(&mut self) -> Result<Vec<CachedDevice>, Self::Error>;
+}
+/// Snapshot format version. Snapshots are `version (1) | device*`.
+pub const SNAPSHOT_VERSION: u8 = 1;
+pub fn encode_snapshot(devices: &[CachedDevice]) -> Result<Vec<u8>, PackError> {
+    let mut out = Vec::with_capacity(1 + devices.iter().map(CachedDevice::byte_len).sum::<usize>());
+    out.push(SNAPSHOT_VERSION);
+    for device in devices {
+        let start = out.len();
+        out.resize(start + device.byte_len(), 0);
+        device.pack_into(&mut out[start..])?;
+    }
+    Ok(out)
+}
+pub fn decode_snapshot(buf: &[u8]) -> Result<Vec<CachedDevice>, PackError> {
+    match buf.first() {
+        None => return Ok(Vec::new()),
+        Some(&SNAPSHOT_VERSION) => (),
+        Some(_) => return Err(PackError::bad_index(0)),
+    }
+    let mut rest = &buf[1..];
+    let mut devices = Vec::new();
+    while !rest.is_empty() {
+        let (device, len) = CachedDevice::unpack_from(rest)?;
+        devices.push(device);
+        rest = &rest[len..];
+    }
+    Ok(devices)
+}
+#[derive(Clone, Debug, Default)]
+pub struct DeviceCache {
+    devices: BTreeMap<BTAddress, CachedDevice>,
+}
+impl DeviceCache {
+    pub fn new() -> DeviceCache {
+        DeviceCache::default()
+    }
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+    pub fn get(&self, address: &BTAddress) -> Option<&CachedDevice> {
+        self.devices.get(address)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &CachedDevice> + '_ {
+        self.devices.values()
+    }
+    pub fn remove(&mut self, address: &BTAddress) -> Option<CachedDevice> {
+        self.devices.remove(address)
+    }
+    /// Returns `false` if the device isn't cached.
+    pub fn set_bonded(&mut self, address: &BTAddress, bonded: bool) -> bool {
+        match self.devices.get_mut(address) {
+            Some(device) => {
+                device.bonded = bonded;
+                true
+            }
+            None => false,
+        }
+    }
+    /// Updates (or adds) the device that sent `report`.
+    pub fn update<T: AsRef<[u8]>>(&mut self, report: &ReportInfo<T>) -> &CachedDevice {
+        let device = self
+            .devices
+            .entry(report.address)
+            .or_insert_with(|| CachedDevice::new(report.address_type, report.address));
+        device.address_type = report.address_type;
+        device.last_data.clear();
+        device.last_data.extend_from_slice(report.data.as_ref());
+        device.last_rssi = report.rssi;
+        let mut shortened = None;
+        for ad in report.data.iter() {
+            match ad.ad_type {
+                AdType::CompleteLocalName => {
+                    if let Ok(name) = core::str::from_utf8(ad.buf.as_ref()) {
+                        device.name = Some(name.into());
+                        shortened = None;
+                        break;
+                    }
+                }
+                AdType::ShortenLocalName => {
+                    shortened = core::str::from_utf8(ad.buf.as_ref()).ok().map(String::from);
+                }
+                _ => (),
+            }
+        }
+        if let Some(name) = shortened {
+            device.name = Some(name);
+        }
+        device
+    }
+    pub fn snapshot(&self) -> Vec<CachedDevice> {
+        self.devices.values().cloned().collect()
+    }
+    /// Replaces the cache contents with `devices`.
+    pub fn restore(&mut self, devices: Vec<CachedDevice>) {
+        self.devices = devices.into_iter().map(|d| (d.address, d)).collect();
+    }
+    pub fn save<P: CachePersistence>(&self, persistence: &mut P) -> Result<(), P::Error> {
+        persistence.save(&self.snapshot())
+    }
+    pub fn load<P: CachePersistence>(&mut self, persistence: &mut P) -> Result<(), P::Error> {
+        self.restore(persistence.load()?);
+        Ok(())
+    }
+}
+/// Stores snapshots in a file. Saving writes a temporary file next to it and renames it over the
+/// old snapshot so a crash never leaves a half written cache.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug)]
+pub struct FileCachePersistence {
+    path: std::path::PathBuf,
+}
+#[cfg(feature = "std")]
+impl FileCachePersistence {
+    pub fn new<P: Into<std::path::PathBuf>>(path: P) -> FileCachePersistence {
+        FileCachePersistence { path: path.into() }
+    }
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+#[cfg(feature = "std")]
+impl CachePersistence for FileCachePersistence {
+    type Error = crate::error::IOError;
+
+    fn save(&mut self, devices: &[CachedDevice]) -> Result<(), Self::Error> {
+        let bytes = encode_snapshot(devices).map_err(|_| crate::error::IOError::InvalidData)?;
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    fn load(&mut self) -> Result<Vec<CachedDevice>, Self::Error> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => decode_snapshot(&bytes).map_err(|_| crate::error::IOError::InvalidData),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::le::advertisement::RawAdvertisement;
+    use crate::le::report::EventType;
+    #[test]
+    fn test_cache_snapshot() {
+        let mut cache = DeviceCache::new();
+        let address = BTAddress::from_u64(0xC655_4433_2211);
+        cache.update(&ReportInfo {
+            event_type: EventType::AdvInd,
+            address_type: AddressType::RandomDevice,
+            address,
+            data: RawAdvertisement(&[0x02, 0x01, 0x06, 0x05, 0x09, b'b', b't', b'l', b'e'][..]),
+            rssi: Some(RSSI::new(-60)),
+        });
+        assert!(cache.set_bonded(&address, true));
+        assert_eq!(cache.get(&address).unwrap().name.as_deref(), Some("btle"));
+        let bytes = encode_snapshot(&cache.snapshot()).unwrap();
+        let mut restored = DeviceCache::new();
+        restored.restore(decode_snapshot(&bytes).unwrap());
+        assert_eq!(restored.snapshot(), cache.snapshot());
+        assert!(decode_snapshot(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
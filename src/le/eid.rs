@@ -0,0 +1,237 @@
+//! Beacons with rotating ephemeral identifiers (EIDs, like Eddystone-EID). The advertised
+//! identifier is derived from an identity key shared with the resolving service and the beacon
+//! time, so only key holders can tell two identifiers of the same beacon apart.
+//!
+//! A [`BeaconTemplate`] is an advertisement with room for the identifier, an [`EIDGenerator`]
+//! computes the identifier of a beacon time and an [`EIDScheduler`] pushes a new advertisement to
+//! an [`Advertiser`] every time the identifier rotates.
+use crate::bytes::Storage;
+use crate::hci::adapter;
+use crate::hci::timer::Timer;
+use crate::le::advertisement::{RawAdvertisement, StaticAdvBuffer, MAX_ADV_LEN};
+use crate::le::advertiser::Advertiser;
+use crate::le::crypto::{aes128, Block, Key, BLOCK_LEN};
+use crate::PackError;
+use core::time::Duration;
+
+pub const EID_LEN: usize = 8;
+/// Largest rotation period exponent (`2^15` seconds, about 9 hours).
+pub const MAX_ROTATION_EXPONENT: u8 = 15;
+pub const EDDYSTONE_UUID: u16 = 0xFEAA;
+pub const EDDYSTONE_EID_FRAME: u8 = 0x30;
+
+/// Advertisement data with [`EID_LEN`] bytes at `id_offset` replaced by the current identifier.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct BeaconTemplate {
+    data: StaticAdvBuffer,
+    id_offset: usize,
+}
+impl BeaconTemplate {
+    /// # Errors
+    /// returns `PackError::BadLength` if `data` is longer than an advertisement or doesn't have
+    /// room for the identifier at `id_offset`.
+    pub fn new(data: &[u8], id_offset: usize) -> Result<BeaconTemplate, PackError> {
+        if data.len() > MAX_ADV_LEN || id_offset + EID_LEN > data.len() {
+            return Err(PackError::BadLength {
+                expected: id_offset + EID_LEN,
+                got: data.len(),
+            });
+        }
+        Ok(BeaconTemplate {
+            data: StaticAdvBuffer::from_slice(data),
+            id_offset,
+        })
+    }
+    /// Eddystone-EID frame (Flags, the Eddystone service UUID and its service data) with the
+    /// calibrated TX power at 0 m.
+    pub fn eddystone(tx_power: i8) -> BeaconTemplate {
+        let [uuid_lo, uuid_hi] = EDDYSTONE_UUID.to_le_bytes();
+        let flags = [0x02, 0x01, 0x06];
+        let uuids = [0x03, 0x03, uuid_lo, uuid_hi];
+        #[allow(clippy::cast_sign_loss)]
+        let tx_power = tx_power as u8;
+        let service_data = [0x0D, 0x16, uuid_lo, uuid_hi, EDDYSTONE_EID_FRAME, tx_power];
+        let mut data = [0_u8; 3 + 4 + 6 + EID_LEN];
+        data[..3].copy_from_slice(&flags);
+        data[3..7].copy_from_slice(&uuids);
+        data[7..13].copy_from_slice(&service_data);
+        BeaconTemplate {
+            data: StaticAdvBuffer::from_slice(&data),
+            id_offset: data.len() - EID_LEN,
+        }
+    }
+    /// The advertisement carrying `eid`.
+    pub fn render(&self, eid: &[u8; EID_LEN]) -> RawAdvertisement {
+        let mut data = self.data;
+        data.as_mut()[self.id_offset..self.id_offset + EID_LEN].copy_from_slice(eid);
+        RawAdvertisement(data)
+    }
+}
+/// Computes Eddystone-EID identifiers. The beacon time counts seconds (from when the beacon was
+/// registered with the resolver) and the identifier changes every `2^rotation_exponent` seconds.
+#[derive(Clone)]
+pub struct EIDGenerator {
+    identity_key: Key,
+    rotation_exponent: u8,
+}
+impl EIDGenerator {
+    /// `rotation_exponent` is clamped to [`MAX_ROTATION_EXPONENT`].
+    pub fn new(identity_key: Key, rotation_exponent: u8) -> EIDGenerator {
+        EIDGenerator {
+            identity_key,
+            rotation_exponent: rotation_exponent.min(MAX_ROTATION_EXPONENT),
+        }
+    }
+    pub fn rotation_period(&self) -> u32 {
+        1 << self.rotation_exponent
+    }
+    /// Start of the rotation period `time` is in.
+    pub fn epoch(&self, time: u32) -> u32 {
+        time >> self.rotation_exponent << self.rotation_exponent
+    }
+    /// Seconds from `time` until the next identifier.
+    pub fn until_rotation(&self, time: u32) -> u32 {
+        self.rotation_period() - (time - self.epoch(time))
+    }
+    /// Identifier of beacon time `time`.
+    pub fn eid(&self, time: u32) -> [u8; EID_LEN] {
+        // Temporary key, changes every 2^16 seconds.
+        let mut temporary: Block = [0; BLOCK_LEN];
+        temporary[11] = 0xFF;
+        temporary[14..].copy_from_slice(&time.to_be_bytes()[..2]);
+        let mut temporary_key = aes128(&self.identity_key, &temporary);
+
+        let mut block: Block = [0; BLOCK_LEN];
+        block[11] = self.rotation_exponent;
+        block[12..].copy_from_slice(&self.epoch(time).to_be_bytes());
+        let encrypted = aes128(&temporary_key, &block);
+        crate::le::crypto::wipe(&mut temporary_key);
+        let mut eid = [0_u8; EID_LEN];
+        eid.copy_from_slice(&encrypted[..EID_LEN]);
+        eid
+    }
+}
+impl core::fmt::Debug for EIDGenerator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EIDGenerator")
+            .field("rotation_exponent", &self.rotation_exponent)
+            .finish_non_exhaustive()
+    }
+}
+/// Keeps an advertiser's data on the current identifier.
+#[derive(Debug)]
+pub struct EIDScheduler {
+    generator: EIDGenerator,
+    template: BeaconTemplate,
+    /// Epoch of the advertised identifier.
+    advertised: Option<u32>,
+}
+impl EIDScheduler {
+    pub fn new(generator: EIDGenerator, template: BeaconTemplate) -> EIDScheduler {
+        EIDScheduler {
+            generator,
+            template,
+            advertised: None,
+        }
+    }
+    pub fn generator(&self) -> &EIDGenerator {
+        &self.generator
+    }
+    /// `true` if the advertised identifier is not the one of `time`.
+    pub fn is_due(&self, time: u32) -> bool {
+        self.advertised != Some(self.generator.epoch(time))
+    }
+    /// Sets the advertising data to the identifier of `time` if it rotated. Returns if it did.
+    /// # Errors
+    /// returns the advertiser error. The update is retried on the next call.
+    pub async fn update<A: Advertiser>(
+        &mut self,
+        advertiser: &mut A,
+        time: u32,
+    ) -> Result<bool, adapter::Error> {
+        if !self.is_due(time) {
+            return Ok(false);
+        }
+        let advertisement = self.template.render(&self.generator.eid(time));
+        advertiser
+            .set_advertising_data(advertisement.as_ref())
+            .await?;
+        hci_debug!("rotated beacon identifier at {}", time);
+        self.advertised = Some(self.generator.epoch(time));
+        Ok(true)
+    }
+    /// Updates the advertiser and sleeps until the next rotation, forever. `clock` returns the
+    /// beacon time in seconds.
+    /// # Errors
+    /// returns the first advertiser error.
+    pub async fn run<A: Advertiser, T: Timer, C: FnMut() -> u32>(
+        &mut self,
+        advertiser: &mut A,
+        timer: &mut T,
+        mut clock: C,
+    ) -> Result<(), adapter::Error> {
+        loop {
+            let time = clock();
+            self.update(advertiser, time).await?;
+            let wait = self.generator.until_rotation(time);
+            timer.delay(Duration::from_secs(u64::from(wait))).await;
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::le::advertiser::AdvertisingParameters;
+    use futures_util::future::{self, LocalBoxFuture};
+    use futures_util::FutureExt;
+
+    #[derive(Default)]
+    struct Recorder {
+        data: Vec<Vec<u8>>,
+    }
+    impl Advertiser for Recorder {
+        fn set_advertising_enable<'a>(
+            &'a mut self,
+            _is_enabled: bool,
+        ) -> LocalBoxFuture<'a, Result<(), adapter::Error>> {
+            Box::pin(future::ready(Ok(())))
+        }
+        fn set_advertising_parameters<'a>(
+            &'a mut self,
+            _advertising_parameters: AdvertisingParameters,
+        ) -> LocalBoxFuture<'a, Result<(), adapter::Error>> {
+            Box::pin(future::ready(Ok(())))
+        }
+        fn set_advertising_data<'d, 'a: 'd>(
+            &'a mut self,
+            data: &'d [u8],
+        ) -> LocalBoxFuture<'d, Result<(), adapter::Error>> {
+            self.data.push(data.to_vec());
+            Box::pin(future::ready(Ok(())))
+        }
+    }
+
+    #[test]
+    fn test_rotation() {
+        let generator = EIDGenerator::new([0x5A; 16], 10);
+        assert_eq!(generator.eid(1024), generator.eid(2047));
+        assert_ne!(generator.eid(2047), generator.eid(2048));
+        assert_eq!(generator.until_rotation(2000), 48);
+
+        let template = BeaconTemplate::eddystone(-20);
+        let mut scheduler = EIDScheduler::new(generator.clone(), template);
+        let mut advertiser = Recorder::default();
+        for time in [1500, 1600, 2048] {
+            scheduler
+                .update(&mut advertiser, time)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+        assert_eq!(advertiser.data.len(), 2);
+        let last = &advertiser.data[1];
+        assert_eq!(last.len(), 21);
+        assert_eq!(&last[7..13], [0x0D, 0x16, 0xAA, 0xFE, 0x30, 0xEC]);
+        assert_eq!(last[13..], generator.eid(2048));
+    }
+}
@@ -0,0 +1,302 @@
+//! ATT request handling for a [`GattServer`]. [`AttServer`] keeps the per bearer state (the
+//! negotiated ATT_MTU) and answers the client's request PDUs from the attribute table.
+use crate::le::att::attribute::{Handle, TypeUUID};
+use crate::le::att::error::Code;
+use crate::le::att::Opcode;
+use crate::le::connection::MTU;
+use crate::le::gatt::server::{Attribute, AttributePermissions, GattServer, HandleRange};
+use crate::le::gatt::{PRIMARY_SERVICE, SECONDARY_SERVICE};
+use alloc::vec::Vec;
+use core::convert::{TryFrom, TryInto};
+
+/// Bit 6 of the opcode marks commands. Commands never get a response, not even an error.
+pub const COMMAND_FLAG: u8 = 0x40;
+/// Longest attribute value (Core Spec Vol 3, Part F, 3.2.9).
+pub const MAX_VALUE_LEN: usize = 512;
+/// Longest value in one Read By Type Response entry (the length field is one byte).
+const MAX_TYPE_VALUE_LEN: usize = 253;
+/// Longest value in one Read By Group Type Response entry.
+const MAX_GROUP_VALUE_LEN: usize = 251;
+
+/// Handle and error code of an Error Response.
+type Outcome = Result<Vec<u8>, (u16, Code)>;
+
+/// Returns `true` if a server has to answer a PDU starting with `opcode`. Unknown opcodes are
+/// answered (with Request Not Supported) unless they are commands.
+pub fn expects_response(opcode: u8) -> bool {
+    match Opcode::try_from(opcode) {
+        Ok(opcode) => matches!(
+            opcode,
+            Opcode::ExchangeMTUReq
+                | Opcode::FindInformationReq
+                | Opcode::FindByTypeValueReq
+                | Opcode::FindByTypeReq
+                | Opcode::ReadReq
+                | Opcode::ReadBlobReq
+                | Opcode::ReadMultipleReq
+                | Opcode::ReadByGroupTypeReq
+                | Opcode::WriteReq
+                | Opcode::PrepareWriteReq
+                | Opcode::ExecuteWriteReq
+                | Opcode::ReadMultipleVariableReq
+        ),
+        Err(_) => opcode & COMMAND_FLAG == 0,
+    }
+}
+fn error_rsp(request_opcode: u8, handle: u16, code: Code) -> Vec<u8> {
+    let mut pdu = vec![Opcode::ErrorRsp.into(), request_opcode];
+    pdu.extend_from_slice(&handle.to_le_bytes());
+    pdu.push(code.into());
+    pdu
+}
+fn unpack_u16(buf: &[u8]) -> u16 {
+    u16::from_le_bytes(buf[..2].try_into().expect("caller checks the length"))
+}
+/// Starting and ending handle parameters followed by the rest of the PDU.
+fn unpack_range(params: &[u8]) -> Result<(HandleRange, &[u8]), (u16, Code)> {
+    if params.len() < HandleRange::BYTE_LEN {
+        return Err((0, Code::InvalidPDU));
+    }
+    let (start, end) = (unpack_u16(params), unpack_u16(&params[2..]));
+    if start == 0 || start > end {
+        return Err((start, Code::InvalidHandle));
+    }
+    Ok((
+        HandleRange::new(Handle::new(start), Handle::new(end)),
+        &params[HandleRange::BYTE_LEN..],
+    ))
+}
+/// 16-bit UUIDs stay 16-bit, everything else is sent as 128-bit.
+fn uuid_len(uuid: TypeUUID) -> usize {
+    match uuid {
+        TypeUUID::UUID16(_) => 2,
+        _ => 16,
+    }
+}
+fn pack_uuid(uuid: TypeUUID, pdu: &mut Vec<u8>) {
+    match uuid {
+        TypeUUID::UUID16(u) => pdu.extend_from_slice(&u.0.to_le_bytes()),
+        _ => pdu.extend_from_slice(&uuid.as_u128().to_le_bytes()),
+    }
+}
+/// Attribute type parameter of Read By Type and Read By Group Type.
+fn unpack_type(params: &[u8]) -> Result<TypeUUID, (u16, Code)> {
+    TypeUUID::unpack_from(params).map_err(|_| (0, Code::InvalidPDU))
+}
+/// Checked before anything else about the request (like BlueZ), so reading a value without
+/// read permission fails with Read Not Permitted even at an invalid offset.
+fn check_permission(attribute: &Attribute, handle: u16, permission: u8) -> Result<(), (u16, Code)> {
+    if attribute.permissions.contains(permission) {
+        Ok(())
+    } else if permission == AttributePermissions::WRITE {
+        Err((handle, Code::WriteNotPermitted))
+    } else {
+        Err((handle, Code::ReadNotPermitted))
+    }
+}
+fn write(server: &mut GattServer, params: &[u8]) -> Result<(), (u16, Code)> {
+    if params.len() < 2 {
+        return Err((0, Code::InvalidPDU));
+    }
+    let handle = unpack_u16(params);
+    let value = &params[2..];
+    let attribute = server
+        .get_mut(Handle::new(handle))
+        .ok_or((handle, Code::InvalidHandle))?;
+    check_permission(attribute, handle, AttributePermissions::WRITE)?;
+    if value.len() > MAX_VALUE_LEN {
+        return Err((handle, Code::InvalidAttributeValueLength));
+    }
+    attribute.value = value.into();
+    Ok(())
+}
+/// ATT server side of one bearer.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct AttServer {
+    server_mtu: u16,
+    mtu: u16,
+}
+impl AttServer {
+    /// `server_mtu` is the Server Rx MTU sent in the Exchange MTU Response.
+    pub fn new(server_mtu: MTU) -> AttServer {
+        AttServer {
+            server_mtu: u16::from(server_mtu).max(MTU::DEFAULT_U16),
+            mtu: MTU::DEFAULT_U16,
+        }
+    }
+    /// Current ATT_MTU of the bearer.
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+    fn max_pdu_len(&self) -> usize {
+        usize::from(self.mtu)
+    }
+    /// Answers one PDU from the client. Returns `None` for commands, confirmations and other
+    /// PDUs a server doesn't respond to.
+    pub fn handle(&mut self, server: &mut GattServer, pdu: &[u8]) -> Option<Vec<u8>> {
+        let (&opcode, params) = pdu.split_first()?;
+        if !expects_response(opcode) {
+            if opcode == u8::from(Opcode::WriteCmd) {
+                // Write Commands fail silently.
+                let _ = write(server, params);
+            }
+            return None;
+        }
+        let outcome = match Opcode::try_from(opcode) {
+            Ok(Opcode::ExchangeMTUReq) => self.exchange_mtu(params),
+            Ok(Opcode::FindInformationReq) => self.find_information(server, params),
+            Ok(Opcode::FindByTypeReq) => self.read_by_type(server, params),
+            Ok(Opcode::ReadReq) => self.read(server, params),
+            Ok(Opcode::ReadBlobReq) => self.read_blob(server, params),
+            Ok(Opcode::ReadByGroupTypeReq) => self.read_by_group_type(server, params),
+            Ok(Opcode::WriteReq) => write(server, params).map(|()| vec![Opcode::WriteRsp.into()]),
+            _ => Err((0, Code::RequestNotSupported)),
+        };
+        Some(outcome.unwrap_or_else(|(handle, code)| error_rsp(opcode, handle, code)))
+    }
+    fn exchange_mtu(&mut self, params: &[u8]) -> Outcome {
+        if params.len() != MTU::BYTE_LEN {
+            return Err((0, Code::InvalidPDU));
+        }
+        self.mtu = unpack_u16(params)
+            .max(MTU::DEFAULT_U16)
+            .min(self.server_mtu);
+        let mut pdu = vec![Opcode::ExchangeMTURsp.into()];
+        pdu.extend_from_slice(&self.server_mtu.to_le_bytes());
+        Ok(pdu)
+    }
+    fn find_information(&self, server: &GattServer, params: &[u8]) -> Outcome {
+        if params.len() != HandleRange::BYTE_LEN {
+            return Err((0, Code::InvalidPDU));
+        }
+        let (range, _) = unpack_range(params)?;
+        let mut attributes = server.table().range(range).peekable();
+        let first_len = match attributes.peek() {
+            Some((_, a)) => uuid_len(a.attribute_type),
+            None => return Err((range.start.inner(), Code::AttributeNotFound)),
+        };
+        let format = if first_len == 2 { 0x01 } else { 0x02 };
+        let mut pdu = vec![Opcode::FindInformationRsp.into(), format];
+        for (handle, attribute) in attributes {
+            if uuid_len(attribute.attribute_type) != first_len
+                || pdu.len() + 2 + first_len > self.max_pdu_len()
+            {
+                break;
+            }
+            pdu.extend_from_slice(&handle.inner().to_le_bytes());
+            pack_uuid(attribute.attribute_type, &mut pdu);
+        }
+        Ok(pdu)
+    }
+    fn read_by_type(&self, server: &GattServer, params: &[u8]) -> Outcome {
+        let (range, rest) = unpack_range(params)?;
+        let attribute_type = unpack_type(rest)?.as_u128();
+        let value_limit = MAX_TYPE_VALUE_LEN.min(self.max_pdu_len() - 4);
+        let mut pdu = vec![Opcode::FindByTypeRsp.into(), 0];
+        let mut value_len = None;
+        for (handle, attribute) in server
+            .table()
+            .range(range)
+            .filter(|(_, a)| a.attribute_type.as_u128() == attribute_type)
+        {
+            // Like BlueZ: an unreadable first match is an error, a later one ends the list.
+            if let Err(e) = check_permission(attribute, handle.inner(), AttributePermissions::READ)
+            {
+                if value_len.is_none() {
+                    return Err(e);
+                }
+                break;
+            }
+            let value = &attribute.value[..attribute.value.len().min(value_limit)];
+            if *value_len.get_or_insert(value.len()) != value.len()
+                || pdu.len() + 2 + value.len() > self.max_pdu_len()
+            {
+                break;
+            }
+            pdu.extend_from_slice(&handle.inner().to_le_bytes());
+            pdu.extend_from_slice(value);
+        }
+        match value_len {
+            Some(len) => {
+                pdu[1] = u8::try_from(2 + len).expect("value_limit fits a u8");
+                Ok(pdu)
+            }
+            None => Err((range.start.inner(), Code::AttributeNotFound)),
+        }
+    }
+    fn read(&self, server: &GattServer, params: &[u8]) -> Outcome {
+        if params.len() != 2 {
+            return Err((0, Code::InvalidPDU));
+        }
+        let handle = unpack_u16(params);
+        let attribute = server
+            .table()
+            .get(Handle::new(handle))
+            .ok_or((handle, Code::InvalidHandle))?;
+        check_permission(attribute, handle, AttributePermissions::READ)?;
+        let len = attribute.value.len().min(self.max_pdu_len() - 1);
+        let mut pdu = vec![Opcode::ReadRsp.into()];
+        pdu.extend_from_slice(&attribute.value[..len]);
+        Ok(pdu)
+    }
+    fn read_blob(&self, server: &GattServer, params: &[u8]) -> Outcome {
+        if params.len() != 4 {
+            return Err((0, Code::InvalidPDU));
+        }
+        let (handle, offset) = (unpack_u16(params), usize::from(unpack_u16(&params[2..])));
+        let attribute = server
+            .table()
+            .get(Handle::new(handle))
+            .ok_or((handle, Code::InvalidHandle))?;
+        check_permission(attribute, handle, AttributePermissions::READ)?;
+        let value = attribute
+            .value
+            .get(offset..)
+            .ok_or((handle, Code::InvalidOffset))?;
+        let len = value.len().min(self.max_pdu_len() - 1);
+        let mut pdu = vec![Opcode::ReadBlobRsp.into()];
+        pdu.extend_from_slice(&value[..len]);
+        Ok(pdu)
+    }
+    fn read_by_group_type(&self, server: &GattServer, params: &[u8]) -> Outcome {
+        let (range, rest) = unpack_range(params)?;
+        let group_type = unpack_type(rest)?.as_u128();
+        if group_type != TypeUUID::from(PRIMARY_SERVICE).as_u128()
+            && group_type != TypeUUID::from(SECONDARY_SERVICE).as_u128()
+        {
+            return Err((range.start.inner(), Code::UnsupportedGroupType));
+        }
+        let value_limit = MAX_GROUP_VALUE_LEN.min(self.max_pdu_len() - 6);
+        let mut pdu = vec![Opcode::ReadByGroupTypeRsp.into(), 0];
+        let mut value_len = None;
+        let table = server.table();
+        let groups = table
+            .services()
+            .filter(|s| range.contains(s.start))
+            .filter_map(|s| Some((s, table.get(s.start)?)))
+            .filter(|(_, a)| a.attribute_type.as_u128() == group_type);
+        for (service, attribute) in groups {
+            let value = &attribute.value[..attribute.value.len().min(value_limit)];
+            if *value_len.get_or_insert(value.len()) != value.len()
+                || pdu.len() + HandleRange::BYTE_LEN + value.len() > self.max_pdu_len()
+            {
+                break;
+            }
+            pdu.extend_from_slice(&service.start.inner().to_le_bytes());
+            pdu.extend_from_slice(&service.end.inner().to_le_bytes());
+            pdu.extend_from_slice(value);
+        }
+        match value_len {
+            Some(len) => {
+                pdu[1] = u8::try_from(HandleRange::BYTE_LEN + len).expect("value_limit fits a u8");
+                Ok(pdu)
+            }
+            None => Err((range.start.inner(), Code::AttributeNotFound)),
+        }
+    }
+}
+impl Default for AttServer {
+    fn default() -> Self {
+        AttServer::new(MTU::DEFAULT)
+    }
+}
@@ -0,0 +1,506 @@
+//! GATT client side cache of discovered services and characteristics. [`AttributeIndex`] is
+//! filled from the responses of service and characteristic discovery and indexes the
+//! characteristics by UUID and by handle, so [`AttributeIndex::find_characteristic`] gives the
+//! handles without rediscovering. Cloning an index is cheap (the data is shared until one of
+//! the clones changes it), so application layers can each keep one.
+//!
+//! The request builders ([`AttributeIndex::read_request`], [`AttributeIndex::write_request`])
+//! check the operation against the discovered characteristic properties first, so writing a
+//! notify-only characteristic fails right away instead of timing out against a silent peer.
+use crate::le::att::attribute::{Handle, TypeUUID};
+use crate::le::att::Opcode;
+use crate::le::gatt::server::HandleRange;
+use crate::le::gatt::CharacteristicProperties;
+use crate::PackError;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct DiscoveredService {
+    pub range: HandleRange,
+    pub uuid: TypeUUID,
+    pub primary: bool,
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct DiscoveredCharacteristic {
+    /// Handle of the characteristic declaration.
+    pub declaration: Handle,
+    pub value_handle: Handle,
+    /// Last handle of the characteristic (its descriptors end there).
+    pub end: Handle,
+    pub properties: CharacteristicProperties,
+    pub uuid: TypeUUID,
+}
+impl DiscoveredCharacteristic {
+    pub fn check(&self, operation: CharacteristicOperation) -> Result<(), ClientError> {
+        if self.properties.contains(operation.required_property()) {
+            Ok(())
+        } else {
+            Err(ClientError::NotPermitted {
+                operation,
+                value_handle: self.value_handle,
+                uuid: self.uuid,
+                properties: self.properties,
+            })
+        }
+    }
+    /// Handles of the descriptors (if there are any).
+    pub fn descriptor_range(&self) -> Option<HandleRange> {
+        if self.end > self.value_handle {
+            Some(HandleRange::new(
+                Handle::new(self.value_handle.inner() + 1),
+                self.end,
+            ))
+        } else {
+            None
+        }
+    }
+}
+/// What a client does with a characteristic value.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum CharacteristicOperation {
+    Read,
+    WriteWithResponse,
+    WriteWithoutResponse,
+    SignedWrite,
+    Notify,
+    Indicate,
+}
+impl CharacteristicOperation {
+    /// Property bit (see [`CharacteristicProperties`]) the operation needs.
+    pub fn required_property(self) -> u8 {
+        match self {
+            CharacteristicOperation::Read => CharacteristicProperties::READ,
+            CharacteristicOperation::WriteWithResponse => CharacteristicProperties::WRITE,
+            CharacteristicOperation::WriteWithoutResponse => {
+                CharacteristicProperties::WRITE_WITHOUT_RESPONSE
+            }
+            CharacteristicOperation::SignedWrite => {
+                CharacteristicProperties::AUTHENTICATED_SIGNED_WRITES
+            }
+            CharacteristicOperation::Notify => CharacteristicProperties::NOTIFY,
+            CharacteristicOperation::Indicate => CharacteristicProperties::INDICATE,
+        }
+    }
+    fn name(self) -> &'static str {
+        match self {
+            CharacteristicOperation::Read => "read",
+            CharacteristicOperation::WriteWithResponse => "write with response",
+            CharacteristicOperation::WriteWithoutResponse => "write without response",
+            CharacteristicOperation::SignedWrite => "signed write",
+            CharacteristicOperation::Notify => "notify",
+            CharacteristicOperation::Indicate => "indicate",
+        }
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ClientError {
+    /// No discovered characteristic has this value handle.
+    UnknownCharacteristic(Handle),
+    /// The characteristic's properties don't allow the operation.
+    NotPermitted {
+        operation: CharacteristicOperation,
+        value_handle: Handle,
+        uuid: TypeUUID,
+        properties: CharacteristicProperties,
+    },
+}
+impl core::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ClientError::UnknownCharacteristic(handle) => write!(
+                f,
+                "no discovered characteristic with value handle 0x{:04X}",
+                handle.inner()
+            ),
+            ClientError::NotPermitted {
+                operation,
+                value_handle,
+                uuid,
+                properties,
+            } => {
+                write!(
+                    f,
+                    "characteristic {:?} (value handle 0x{:04X}) doesn't allow {}, properties:",
+                    uuid,
+                    value_handle.inner(),
+                    operation.name()
+                )?;
+                let allowed: Vec<&str> = OPERATIONS
+                    .iter()
+                    .filter(|o| properties.contains(o.required_property()))
+                    .map(|o| o.name())
+                    .collect();
+                if allowed.is_empty() {
+                    f.write_str(" none")
+                } else {
+                    write!(f, " {}", allowed.join(", "))
+                }
+            }
+        }
+    }
+}
+impl crate::error::Error for ClientError {}
+#[cfg(feature = "std")]
+impl std::error::Error for ClientError {}
+const OPERATIONS: [CharacteristicOperation; 6] = [
+    CharacteristicOperation::Read,
+    CharacteristicOperation::WriteWithResponse,
+    CharacteristicOperation::WriteWithoutResponse,
+    CharacteristicOperation::SignedWrite,
+    CharacteristicOperation::Notify,
+    CharacteristicOperation::Indicate,
+];
+#[derive(Clone, Debug, Default)]
+struct Index {
+    /// Keyed by start handle.
+    services: BTreeMap<Handle, DiscoveredService>,
+    /// Keyed by declaration handle.
+    characteristics: BTreeMap<Handle, DiscoveredCharacteristic>,
+    /// Declaration handles of the characteristics with a UUID (as [`TypeUUID::as_u128`]).
+    by_uuid: BTreeMap<u128, Vec<Handle>>,
+}
+impl Index {
+    /// Characteristic ends depend on the next declaration (or the end of the service).
+    fn update_ends(&mut self) {
+        let services = &self.services;
+        let declarations: Vec<Handle> = self.characteristics.keys().copied().collect();
+        for (i, declaration) in declarations.iter().enumerate() {
+            let service_end = services
+                .range(..=*declaration)
+                .next_back()
+                .map(|(_, s)| s.range)
+                .filter(|r| r.contains(*declaration))
+                .map_or(HandleRange::ALL.end, |r| r.end);
+            let end = match declarations.get(i + 1) {
+                Some(next) if next.inner() <= service_end.inner() => Handle::new(next.inner() - 1),
+                _ => service_end,
+            };
+            if let Some(characteristic) = self.characteristics.get_mut(declaration) {
+                characteristic.end = end.max(characteristic.value_handle);
+            }
+        }
+    }
+    fn rebuild_uuids(&mut self) {
+        self.by_uuid.clear();
+        for characteristic in self.characteristics.values() {
+            self.by_uuid
+                .entry(characteristic.uuid.as_u128())
+                .or_default()
+                .push(characteristic.declaration);
+        }
+    }
+}
+/// Discovered attributes of one GATT server. See the module docs.
+#[derive(Clone, Debug, Default)]
+pub struct AttributeIndex {
+    index: Arc<Index>,
+}
+impl AttributeIndex {
+    pub fn new() -> AttributeIndex {
+        AttributeIndex::default()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.index.services.is_empty() && self.index.characteristics.is_empty()
+    }
+    pub fn add_service(&mut self, service: DiscoveredService) {
+        let index = Arc::make_mut(&mut self.index);
+        index.services.insert(service.range.start, service);
+        index.update_ends();
+    }
+    pub fn add_characteristic(&mut self, characteristic: DiscoveredCharacteristic) {
+        let index = Arc::make_mut(&mut self.index);
+        if let Some(old) = index
+            .characteristics
+            .insert(characteristic.declaration, characteristic)
+        {
+            if let Some(handles) = index.by_uuid.get_mut(&old.uuid.as_u128()) {
+                handles.retain(|h| *h != old.declaration);
+            }
+        }
+        let handles = index
+            .by_uuid
+            .entry(characteristic.uuid.as_u128())
+            .or_default();
+        if let Err(at) = handles.binary_search(&characteristic.declaration) {
+            handles.insert(at, characteristic.declaration);
+        }
+        index.update_ends();
+    }
+    /// Adds the services of a Read By Group Type Response (without the opcode) to a primary (or
+    /// secondary) service discovery.
+    pub fn add_services_from_response(
+        &mut self,
+        parameters: &[u8],
+        primary: bool,
+    ) -> Result<(), PackError> {
+        for entry in entries(parameters, 4)? {
+            let start = Handle::new(u16::from_le_bytes([entry[0], entry[1]]));
+            let end = Handle::new(u16::from_le_bytes([entry[2], entry[3]]));
+            if start > end {
+                return Err(PackError::InvalidFields);
+            }
+            self.add_service(DiscoveredService {
+                range: HandleRange::new(start, end),
+                uuid: TypeUUID::unpack_from(&entry[4..])?,
+                primary,
+            });
+        }
+        Ok(())
+    }
+    /// Adds the characteristic declarations of a Read By Type Response (without the opcode).
+    pub fn add_characteristics_from_response(
+        &mut self,
+        parameters: &[u8],
+    ) -> Result<(), PackError> {
+        for entry in entries(parameters, 5)? {
+            let declaration = Handle::new(u16::from_le_bytes([entry[0], entry[1]]));
+            let value_handle = Handle::new(u16::from_le_bytes([entry[3], entry[4]]));
+            self.add_characteristic(DiscoveredCharacteristic {
+                declaration,
+                value_handle,
+                end: value_handle,
+                properties: CharacteristicProperties::new(entry[2]),
+                uuid: TypeUUID::unpack_from(&entry[5..])?,
+            });
+        }
+        Ok(())
+    }
+    /// Forgets everything in `range` (for example after a Service Changed indication).
+    pub fn invalidate(&mut self, range: HandleRange) {
+        let index = Arc::make_mut(&mut self.index);
+        index.services.retain(|_, s| !s.range.overlaps(range));
+        index
+            .characteristics
+            .retain(|h, c| !range.contains(*h) && !range.contains(c.value_handle));
+        index.rebuild_uuids();
+        index.update_ends();
+    }
+    pub fn services(&self) -> impl Iterator<Item = &DiscoveredService> {
+        self.index.services.values()
+    }
+    pub fn find_service(&self, uuid: impl Into<TypeUUID>) -> Option<&DiscoveredService> {
+        let uuid = uuid.into().as_u128();
+        self.services().find(|s| s.uuid.as_u128() == uuid)
+    }
+    /// Service `handle` belongs to.
+    pub fn service_of(&self, handle: Handle) -> Option<&DiscoveredService> {
+        self.index
+            .services
+            .range(..=handle)
+            .next_back()
+            .map(|(_, s)| s)
+            .filter(|s| s.range.contains(handle))
+    }
+    /// First (lowest handle) characteristic with `uuid`. 16, 32 and 128-bit forms of the same
+    /// UUID match.
+    pub fn find_characteristic(
+        &self,
+        uuid: impl Into<TypeUUID>,
+    ) -> Option<&DiscoveredCharacteristic> {
+        self.find_characteristics(uuid).next()
+    }
+    /// Every characteristic with `uuid`, lowest handle first.
+    pub fn find_characteristics(
+        &self,
+        uuid: impl Into<TypeUUID>,
+    ) -> impl Iterator<Item = &DiscoveredCharacteristic> {
+        let handles = self
+            .index
+            .by_uuid
+            .get(&uuid.into().as_u128())
+            .map_or(&[][..], Vec::as_slice);
+        let characteristics = &self.index.characteristics;
+        handles.iter().filter_map(move |h| characteristics.get(h))
+    }
+    /// Characteristic `handle` belongs to (its declaration, value or one of its descriptors).
+    pub fn characteristic_of(&self, handle: Handle) -> Option<&DiscoveredCharacteristic> {
+        self.index
+            .characteristics
+            .range(..=handle)
+            .next_back()
+            .map(|(_, c)| c)
+            .filter(|c| handle <= c.end)
+    }
+    pub fn characteristic_by_value_handle(
+        &self,
+        value_handle: Handle,
+    ) -> Option<&DiscoveredCharacteristic> {
+        self.characteristic_of(value_handle)
+            .filter(|c| c.value_handle == value_handle)
+    }
+    /// Checks that the characteristic with `value_handle` allows `operation`.
+    pub fn check(
+        &self,
+        value_handle: Handle,
+        operation: CharacteristicOperation,
+    ) -> Result<&DiscoveredCharacteristic, ClientError> {
+        let characteristic = self
+            .characteristic_by_value_handle(value_handle)
+            .ok_or(ClientError::UnknownCharacteristic(value_handle))?;
+        characteristic.check(operation)?;
+        Ok(characteristic)
+    }
+    /// ATT Read Request PDU for the characteristic value, if it is readable.
+    pub fn read_request(&self, value_handle: Handle) -> Result<Vec<u8>, ClientError> {
+        self.check(value_handle, CharacteristicOperation::Read)?;
+        let mut pdu = vec![Opcode::ReadReq.into()];
+        pdu.extend_from_slice(&value_handle.inner().to_le_bytes());
+        Ok(pdu)
+    }
+    /// ATT Write Request (or Write Command without a response) PDU for the characteristic
+    /// value, if the characteristic allows that kind of write.
+    pub fn write_request(
+        &self,
+        value_handle: Handle,
+        value: &[u8],
+        with_response: bool,
+    ) -> Result<Vec<u8>, ClientError> {
+        let (operation, opcode) = if with_response {
+            (CharacteristicOperation::WriteWithResponse, Opcode::WriteReq)
+        } else {
+            (
+                CharacteristicOperation::WriteWithoutResponse,
+                Opcode::WriteCmd,
+            )
+        };
+        self.check(value_handle, operation)?;
+        let mut pdu = Vec::with_capacity(3 + value.len());
+        pdu.push(opcode.into());
+        pdu.extend_from_slice(&value_handle.inner().to_le_bytes());
+        pdu.extend_from_slice(value);
+        Ok(pdu)
+    }
+    /// Characteristics declared in `range`.
+    pub fn characteristics_in(
+        &self,
+        range: HandleRange,
+    ) -> impl Iterator<Item = &DiscoveredCharacteristic> {
+        self.index
+            .characteristics
+            .range(range.start..=range.end)
+            .map(|(_, c)| c)
+    }
+}
+/// Splits an attribute data list (length byte then equal sized entries) into its entries.
+fn entries(
+    parameters: &[u8],
+    min_len: usize,
+) -> Result<core::slice::ChunksExact<'_, u8>, PackError> {
+    let (&len, list) = parameters.split_first().ok_or(PackError::bad_index(0))?;
+    let len = usize::from(len);
+    if len < min_len || list.is_empty() || list.len() % len != 0 {
+        return Err(PackError::InvalidFields);
+    }
+    Ok(list.chunks_exact(len))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uuid::UUID16;
+
+    #[test]
+    fn test_index_lookup() {
+        let mut index = AttributeIndex::new();
+        // Battery Service (0x0010-0x0015) and Device Information (0x0020-0x0024).
+        index
+            .add_services_from_response(
+                &[
+                    6, 0x10, 0x00, 0x15, 0x00, 0x0F, 0x18, 0x20, 0x00, 0x24, 0x00, 0x0A, 0x18,
+                ],
+                true,
+            )
+            .unwrap();
+        // Battery Level (notify) and two Manufacturer Name Strings.
+        index
+            .add_characteristics_from_response(&[
+                7, 0x11, 0x00, 0x12, 0x12, 0x00, 0x19, 0x2A, 0x21, 0x00, 0x02, 0x22, 0x00, 0x29,
+                0x2A,
+            ])
+            .unwrap();
+        index
+            .add_characteristics_from_response(&[7, 0x23, 0x00, 0x02, 0x24, 0x00, 0x29, 0x2A])
+            .unwrap();
+
+        let snapshot = index.clone();
+        let battery = index.find_characteristic(UUID16(0x2A19)).unwrap();
+        assert_eq!(battery.value_handle, Handle::new(0x12));
+        assert_eq!(battery.end, Handle::new(0x15));
+        assert_eq!(
+            battery.descriptor_range(),
+            Some(HandleRange::new(Handle::new(0x13), Handle::new(0x15)))
+        );
+        assert_eq!(index.find_characteristics(UUID16(0x2A29)).count(), 2);
+        assert_eq!(
+            index
+                .characteristic_of(Handle::new(0x14))
+                .map(|c| c.declaration),
+            Some(Handle::new(0x11))
+        );
+        assert_eq!(
+            index.service_of(Handle::new(0x22)).map(|s| s.uuid),
+            Some(TypeUUID::UUID16(UUID16(0x180A)))
+        );
+
+        assert_eq!(
+            index.read_request(Handle::new(0x12)),
+            Ok(vec![0x0A, 0x12, 0x00])
+        );
+        let error = index
+            .write_request(Handle::new(0x12), &[1], true)
+            .unwrap_err();
+        assert!(matches!(error, ClientError::NotPermitted { .. }));
+        assert!(error.to_string().ends_with("properties: read, notify"));
+
+        index.invalidate(HandleRange::new(Handle::new(0x20), Handle::new(0x24)));
+        assert_eq!(index.find_characteristics(UUID16(0x2A29)).count(), 0);
+        assert_eq!(snapshot.find_characteristics(UUID16(0x2A29)).count(), 2);
+    }
+    #[test]
+    fn test_request_properties() {
+        let mut index = AttributeIndex::new();
+        // Write without response only (0x11/0x12) and read + write (0x13/0x14).
+        index
+            .add_characteristics_from_response(&[
+                7, 0x11, 0x00, 0x04, 0x12, 0x00, 0x06, 0x2A, 0x13, 0x00, 0x0A, 0x14, 0x00, 0x07,
+                0x2A,
+            ])
+            .unwrap();
+
+        assert_eq!(
+            index.write_request(Handle::new(0x12), &[1, 2], false),
+            Ok(vec![0x52, 0x12, 0x00, 1, 2])
+        );
+        assert!(matches!(
+            index.write_request(Handle::new(0x12), &[1, 2], true),
+            Err(ClientError::NotPermitted {
+                operation: CharacteristicOperation::WriteWithResponse,
+                ..
+            })
+        ));
+        assert!(matches!(
+            index.read_request(Handle::new(0x12)),
+            Err(ClientError::NotPermitted {
+                operation: CharacteristicOperation::Read,
+                ..
+            })
+        ));
+        assert_eq!(
+            index.write_request(Handle::new(0x14), &[3], true),
+            Ok(vec![0x12, 0x14, 0x00, 3])
+        );
+        assert!(index
+            .check(Handle::new(0x14), CharacteristicOperation::Notify)
+            .is_err());
+        // Declaration handles aren't value handles.
+        assert_eq!(
+            index.read_request(Handle::new(0x13)),
+            Err(ClientError::UnknownCharacteristic(Handle::new(0x13)))
+        );
+        let error = index.read_request(Handle::new(0x12)).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("properties: write without response"));
+    }
+}
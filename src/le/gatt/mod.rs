@@ -1 +1,42 @@
+//! Generic Attribute Profile (GATT).
+pub mod att_server;
+pub mod client;
+pub mod server;
+pub mod service_changed;
 
+use crate::uuid::UUID16;
+
+pub const PRIMARY_SERVICE: UUID16 = UUID16(0x2800);
+pub const SECONDARY_SERVICE: UUID16 = UUID16(0x2801);
+pub const INCLUDE: UUID16 = UUID16(0x2802);
+pub const CHARACTERISTIC: UUID16 = UUID16(0x2803);
+pub const CLIENT_CHARACTERISTIC_CONFIGURATION: UUID16 = UUID16(0x2902);
+/// Generic Attribute Profile service UUID.
+pub const GATT_SERVICE: UUID16 = UUID16(0x1801);
+pub const SERVICE_CHANGED: UUID16 = UUID16(0x2A05);
+
+/// Characteristic Properties bit field found in the characteristic declaration.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct CharacteristicProperties(pub u8);
+impl CharacteristicProperties {
+    pub const BYTE_LEN: usize = 1;
+    pub const BROADCAST: u8 = 0x01;
+    pub const READ: u8 = 0x02;
+    pub const WRITE_WITHOUT_RESPONSE: u8 = 0x04;
+    pub const WRITE: u8 = 0x08;
+    pub const NOTIFY: u8 = 0x10;
+    pub const INDICATE: u8 = 0x20;
+    pub const AUTHENTICATED_SIGNED_WRITES: u8 = 0x40;
+    pub const EXTENDED_PROPERTIES: u8 = 0x80;
+    pub const fn new(properties: u8) -> CharacteristicProperties {
+        CharacteristicProperties(properties)
+    }
+    pub const fn contains(self, property: u8) -> bool {
+        self.0 & property == property
+    }
+}
+impl From<CharacteristicProperties> for u8 {
+    fn from(p: CharacteristicProperties) -> Self {
+        p.0
+    }
+}
@@ -0,0 +1,570 @@
+//! GATT Server attribute table.
+use crate::le::att::attribute::{Handle, TypeUUID};
+use crate::le::gatt::service_changed::{self, ServiceChangedTracker};
+use crate::le::gatt::{
+    CharacteristicProperties, CHARACTERISTIC, PRIMARY_SERVICE, SECONDARY_SERVICE,
+};
+use crate::{BTAddress, PackError};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+/// Inclusive range of attribute handles.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct HandleRange {
+    pub start: Handle,
+    pub end: Handle,
+}
+impl HandleRange {
+    pub const BYTE_LEN: usize = 4;
+    pub const ALL: HandleRange = HandleRange {
+        start: Handle::new(0x0001),
+        end: Handle::new(0xFFFF),
+    };
+    /// Creates a new `HandleRange`.
+    /// # Panics
+    /// Panics if `start > end`.
+    pub fn new(start: Handle, end: Handle) -> HandleRange {
+        assert!(start <= end, "handle range start after end");
+        HandleRange { start, end }
+    }
+    pub fn contains(self, handle: Handle) -> bool {
+        handle >= self.start && handle <= self.end
+    }
+    pub fn overlaps(self, other: HandleRange) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+    /// Number of handles in the range.
+    pub fn handle_count(self) -> usize {
+        usize::from(self.end.inner() - self.start.inner()) + 1
+    }
+    /// Smallest range covering both `self` and `other`.
+    #[must_use]
+    pub fn union(self, other: HandleRange) -> HandleRange {
+        HandleRange {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+    pub fn pack_into(self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf[..2].copy_from_slice(&self.start.inner().to_le_bytes());
+        buf[2..].copy_from_slice(&self.end.inner().to_le_bytes());
+        Ok(())
+    }
+    pub fn unpack_from(buf: &[u8]) -> Result<HandleRange, PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        let start = Handle::new(u16::from_le_bytes(
+            (&buf[..2]).try_into().expect("len checked above"),
+        ));
+        let end = Handle::new(u16::from_le_bytes(
+            (&buf[2..]).try_into().expect("len checked above"),
+        ));
+        if start < Self::ALL.start {
+            Err(PackError::bad_index(0))
+        } else if start > end {
+            Err(PackError::InvalidFields)
+        } else {
+            Ok(HandleRange { start, end })
+        }
+    }
+}
+/// Attributes following a service declaration.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ServiceAttribute {
+    /// Characteristic declaration followed by the characteristic value.
+    Characteristic {
+        uuid: TypeUUID,
+        properties: CharacteristicProperties,
+        value: Box<[u8]>,
+    },
+    Descriptor {
+        uuid: TypeUUID,
+        value: Box<[u8]>,
+    },
+}
+impl ServiceAttribute {
+    pub fn handle_count(&self) -> usize {
+        match self {
+            ServiceAttribute::Characteristic { .. } => 2,
+            ServiceAttribute::Descriptor { .. } => 1,
+        }
+    }
+}
+/// Service definition that can be added to an [`AttributeTable`].
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct Service {
+    pub uuid: TypeUUID,
+    pub primary: bool,
+    pub attributes: Vec<ServiceAttribute>,
+}
+impl Service {
+    pub fn new_primary(uuid: impl Into<TypeUUID>) -> Service {
+        Service {
+            uuid: uuid.into(),
+            primary: true,
+            attributes: Vec::new(),
+        }
+    }
+    pub fn new_secondary(uuid: impl Into<TypeUUID>) -> Service {
+        Service {
+            uuid: uuid.into(),
+            primary: false,
+            attributes: Vec::new(),
+        }
+    }
+    #[must_use]
+    pub fn with_characteristic(
+        mut self,
+        uuid: impl Into<TypeUUID>,
+        properties: CharacteristicProperties,
+        value: &[u8],
+    ) -> Service {
+        self.attributes.push(ServiceAttribute::Characteristic {
+            uuid: uuid.into(),
+            properties,
+            value: value.into(),
+        });
+        self
+    }
+    /// Adds a descriptor to the last added characteristic.
+    #[must_use]
+    pub fn with_descriptor(mut self, uuid: impl Into<TypeUUID>, value: &[u8]) -> Service {
+        self.attributes.push(ServiceAttribute::Descriptor {
+            uuid: uuid.into(),
+            value: value.into(),
+        });
+        self
+    }
+    /// Number of handles the service uses (including the service declaration).
+    pub fn handle_count(&self) -> usize {
+        1 + self
+            .attributes
+            .iter()
+            .map(ServiceAttribute::handle_count)
+            .sum::<usize>()
+    }
+}
+/// What a client may do with an [`Attribute`]. Declarations can only be read, characteristic
+/// values follow the characteristic properties and descriptors can be read and written (like
+/// BlueZ's database).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct AttributePermissions(pub u8);
+impl AttributePermissions {
+    pub const READ: u8 = 0x01;
+    pub const WRITE: u8 = 0x02;
+    pub const fn new(permissions: u8) -> AttributePermissions {
+        AttributePermissions(permissions)
+    }
+    pub const fn contains(self, permission: u8) -> bool {
+        self.0 & permission == permission
+    }
+    /// Permissions of a characteristic value with `properties`.
+    pub fn of_value(properties: CharacteristicProperties) -> AttributePermissions {
+        let mut permissions = 0;
+        if properties.contains(CharacteristicProperties::READ) {
+            permissions |= Self::READ;
+        }
+        if [
+            CharacteristicProperties::WRITE,
+            CharacteristicProperties::WRITE_WITHOUT_RESPONSE,
+            CharacteristicProperties::AUTHENTICATED_SIGNED_WRITES,
+        ]
+        .iter()
+        .any(|&p| properties.contains(p))
+        {
+            permissions |= Self::WRITE;
+        }
+        AttributePermissions(permissions)
+    }
+}
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct Attribute {
+    pub attribute_type: TypeUUID,
+    pub permissions: AttributePermissions,
+    pub value: Box<[u8]>,
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ServerError {
+    OutOfHandles,
+    ServiceNotFound,
+    HandleRangeInUse,
+    InvalidHandle,
+}
+impl core::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "attribute table error {:?}", self)
+    }
+}
+impl crate::error::Error for ServerError {}
+#[cfg(feature = "std")]
+impl std::error::Error for ServerError {}
+/// Attribute table of a GATT Server. Services can be added and removed at any time.
+#[derive(Clone, Debug, Default)]
+pub struct AttributeTable {
+    attributes: BTreeMap<Handle, Attribute>,
+    /// Service ranges keyed by their start handle.
+    services: BTreeMap<Handle, HandleRange>,
+}
+impl AttributeTable {
+    pub fn new() -> AttributeTable {
+        AttributeTable::default()
+    }
+    /// Adds `service` in the first free handle range big enough to hold it. Handles freed by
+    /// removed services get reused. Returns the handles the service occupies.
+    pub fn add_service(&mut self, service: Service) -> Result<HandleRange, ServerError> {
+        let count = service.handle_count();
+        let start = self
+            .free_ranges()
+            .find(|r| r.handle_count() >= count)
+            .ok_or(ServerError::OutOfHandles)?
+            .start;
+        let range = Self::range_for(start, &service)?;
+        self.insert_service(range, service);
+        Ok(range)
+    }
+    /// Adds `service` starting at `start`. Useful for keeping services at the same handles across
+    /// restarts so bonded clients don't have to rediscover them.
+    pub fn add_service_at(
+        &mut self,
+        start: Handle,
+        service: Service,
+    ) -> Result<HandleRange, ServerError> {
+        if start < HandleRange::ALL.start {
+            return Err(ServerError::InvalidHandle);
+        }
+        let range = Self::range_for(start, &service)?;
+        if self.services.values().any(|r| r.overlaps(range)) {
+            return Err(ServerError::HandleRangeInUse);
+        }
+        self.insert_service(range, service);
+        Ok(range)
+    }
+    /// Handle ranges not used by any service in handle order.
+    pub fn free_ranges(&self) -> impl Iterator<Item = HandleRange> + '_ {
+        let mut next_free = Some(HandleRange::ALL.start.inner());
+        self.services
+            .values()
+            .map(Some)
+            .chain(core::iter::once(None))
+            .filter_map(move |service| {
+                let start = next_free?;
+                if let Some(r) = service {
+                    next_free = r.end.inner().checked_add(1);
+                    if r.start.inner() > start {
+                        Some(HandleRange::new(
+                            Handle::new(start),
+                            Handle::new(r.start.inner() - 1),
+                        ))
+                    } else {
+                        None
+                    }
+                } else {
+                    next_free = None;
+                    Some(HandleRange::new(Handle::new(start), HandleRange::ALL.end))
+                }
+            })
+    }
+    /// Removes the service starting at `start` and all of its attributes.
+    pub fn remove_service(&mut self, start: Handle) -> Result<HandleRange, ServerError> {
+        let range = self
+            .services
+            .remove(&start)
+            .ok_or(ServerError::ServiceNotFound)?;
+        let handles: Vec<Handle> = self
+            .attributes
+            .range(range.start..=range.end)
+            .map(|(&h, _)| h)
+            .collect();
+        for handle in handles {
+            self.attributes.remove(&handle);
+        }
+        Ok(range)
+    }
+    /// Handle ranges of every service in the table in handle order.
+    pub fn services(&self) -> impl Iterator<Item = HandleRange> + '_ {
+        self.services.values().copied()
+    }
+    /// Range of the service containing `handle`.
+    pub fn service_of(&self, handle: Handle) -> Option<HandleRange> {
+        self.services
+            .range(..=handle)
+            .next_back()
+            .map(|(_, &r)| r)
+            .filter(|r| r.contains(handle))
+    }
+    pub fn get(&self, handle: Handle) -> Option<&Attribute> {
+        self.attributes.get(&handle)
+    }
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut Attribute> {
+        self.attributes.get_mut(&handle)
+    }
+    /// Attributes with handles in `range`.
+    pub fn range(&self, range: HandleRange) -> impl Iterator<Item = (Handle, &Attribute)> + '_ {
+        self.attributes
+            .range(range.start..=range.end)
+            .map(|(&h, a)| (h, a))
+    }
+    pub(crate) fn range_for(start: Handle, service: &Service) -> Result<HandleRange, ServerError> {
+        let count: u16 = service
+            .handle_count()
+            .try_into()
+            .map_err(|_| ServerError::OutOfHandles)?;
+        let end = start
+            .inner()
+            .checked_add(count - 1)
+            .ok_or(ServerError::OutOfHandles)?;
+        Ok(HandleRange::new(start, Handle::new(end)))
+    }
+    /// Inserts `service` at `range`. `range` must be free and come from [`Self::range_for`].
+    pub(crate) fn insert_service(&mut self, range: HandleRange, service: Service) {
+        let mut handle = range.start.inner();
+        let mut next = || {
+            let h = Handle::new(handle);
+            // Wraps after the last handle of a range ending at 0xFFFF, it isn't used then.
+            handle = handle.wrapping_add(1);
+            h
+        };
+        let mut declaration = vec![0_u8; service.uuid.byte_len()];
+        service
+            .uuid
+            .pack_into(&mut declaration[..])
+            .expect("buffer sized by byte_len");
+        let declaration_type = if service.primary {
+            PRIMARY_SERVICE
+        } else {
+            SECONDARY_SERVICE
+        };
+        self.attributes.insert(
+            next(),
+            Attribute {
+                attribute_type: declaration_type.into(),
+                permissions: AttributePermissions::new(AttributePermissions::READ),
+                value: declaration.into_boxed_slice(),
+            },
+        );
+        for attribute in service.attributes {
+            match attribute {
+                ServiceAttribute::Characteristic {
+                    uuid,
+                    properties,
+                    value,
+                } => {
+                    let declaration_handle = next();
+                    let value_handle = next();
+                    let mut declaration = vec![0_u8; 3 + uuid.byte_len()];
+                    declaration[0] = properties.into();
+                    declaration[1..3].copy_from_slice(&value_handle.inner().to_le_bytes());
+                    uuid.pack_into(&mut declaration[3..])
+                        .expect("buffer sized by byte_len");
+                    self.attributes.insert(
+                        declaration_handle,
+                        Attribute {
+                            attribute_type: CHARACTERISTIC.into(),
+                            permissions: AttributePermissions::new(AttributePermissions::READ),
+                            value: declaration.into_boxed_slice(),
+                        },
+                    );
+                    self.attributes.insert(
+                        value_handle,
+                        Attribute {
+                            attribute_type: uuid,
+                            permissions: AttributePermissions::of_value(properties),
+                            value,
+                        },
+                    );
+                }
+                ServiceAttribute::Descriptor { uuid, value } => {
+                    self.attributes.insert(
+                        next(),
+                        Attribute {
+                            attribute_type: uuid,
+                            permissions: AttributePermissions::new(
+                                AttributePermissions::READ | AttributePermissions::WRITE,
+                            ),
+                            value,
+                        },
+                    );
+                }
+            }
+        }
+        self.services.insert(range.start, range);
+    }
+}
+/// GATT Server state. Owns the [`AttributeTable`] (starting with the GATT service) and tracks
+/// which bonded clients need a Service Changed indication after services are added or removed.
+#[derive(Clone, Debug)]
+pub struct GattServer {
+    table: AttributeTable,
+    service_changed: ServiceChangedTracker,
+}
+impl GattServer {
+    pub fn new() -> GattServer {
+        let mut table = AttributeTable::new();
+        let range = table
+            .add_service(service_changed::gatt_service())
+            .expect("empty table has room for the GATT service");
+        GattServer {
+            table,
+            service_changed: ServiceChangedTracker::new(service_changed::value_handle(range)),
+        }
+    }
+    pub fn table(&self) -> &AttributeTable {
+        &self.table
+    }
+    /// Mutable access to attribute values. Use [`GattServer::add_service`] and
+    /// [`GattServer::remove_service`] to change the structure of the table.
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut Attribute> {
+        self.table.get_mut(handle)
+    }
+    pub fn service_changed(&self) -> &ServiceChangedTracker {
+        &self.service_changed
+    }
+    pub fn service_changed_mut(&mut self) -> &mut ServiceChangedTracker {
+        &mut self.service_changed
+    }
+    /// Adds a service at runtime and marks every bonded client as change-unaware.
+    pub fn add_service(&mut self, service: Service) -> Result<HandleRange, ServerError> {
+        let range = self.table.add_service(service)?;
+        self.service_changed.database_changed(range);
+        Ok(range)
+    }
+    /// Adds a service at runtime at a fixed handle. See [`AttributeTable::add_service_at`].
+    pub fn add_service_at(
+        &mut self,
+        start: Handle,
+        service: Service,
+    ) -> Result<HandleRange, ServerError> {
+        let range = self.table.add_service_at(start, service)?;
+        self.service_changed.database_changed(range);
+        Ok(range)
+    }
+    /// Removes a service at runtime and marks every bonded client as change-unaware.
+    pub fn remove_service(&mut self, start: Handle) -> Result<HandleRange, ServerError> {
+        let range = self.table.remove_service(start)?;
+        self.service_changed.database_changed(range);
+        Ok(range)
+    }
+    /// Shortcut for [`ServiceChangedTracker::add_bond`].
+    pub fn add_bond(&mut self, peer: BTAddress) {
+        self.service_changed.add_bond(peer);
+    }
+}
+impl Default for GattServer {
+    fn default() -> Self {
+        GattServer::new()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::le::att::pdus::PackablePDU;
+    use crate::uuid::UUID16;
+    #[test]
+    fn test_service_changed_indication() {
+        let peer = BTAddress::new(&[1, 2, 3, 4, 5, 6]);
+        let mut server = GattServer::new();
+        server.add_bond(peer);
+        server
+            .service_changed_mut()
+            .set_indications_enabled(&peer, true);
+        assert!(server.service_changed().is_change_aware(&peer));
+
+        let service = Service::new_primary(UUID16(0x180F)).with_characteristic(
+            UUID16(0x2A19),
+            CharacteristicProperties::new(CharacteristicProperties::READ),
+            &[100],
+        );
+        let range = server.add_service(service).unwrap();
+        assert_eq!(range, HandleRange::new(Handle::new(5), Handle::new(7)));
+        assert_eq!(
+            server.table().get(Handle::new(7)).unwrap().value.as_ref(),
+            &[100]
+        );
+        assert!(!server.service_changed().is_change_aware(&peer));
+
+        let indication = server.service_changed_mut().next_indication(&peer).unwrap();
+        assert_eq!(indication.handle, Handle::new(3));
+        let mut buf = [0_u8; 6];
+        indication.pack_into(&mut buf[..]).unwrap();
+        assert_eq!(buf, [3, 0, 5, 0, 7, 0]);
+        assert!(server
+            .service_changed_mut()
+            .next_indication(&peer)
+            .is_none());
+
+        server.service_changed_mut().confirmed(&peer);
+        assert!(server.service_changed().is_change_aware(&peer));
+
+        assert_eq!(server.remove_service(Handle::new(5)), Ok(range));
+        assert!(server.table().get(Handle::new(7)).is_none());
+        assert!(!server.service_changed().is_change_aware(&peer));
+
+        let far = server
+            .add_service_at(Handle::new(0x100), Service::new_primary(UUID16(0x1810)))
+            .unwrap();
+        assert_eq!(
+            far,
+            HandleRange::new(Handle::new(0x100), Handle::new(0x100))
+        );
+        let reused = server
+            .add_service(Service::new_primary(UUID16(0x1811)))
+            .unwrap();
+        assert_eq!(reused.start, Handle::new(5));
+        assert_eq!(
+            server.add_service_at(Handle::new(5), Service::new_primary(UUID16(0x1812))),
+            Err(ServerError::HandleRangeInUse)
+        );
+        let indication = server.service_changed_mut().next_indication(&peer).unwrap();
+        assert_eq!(indication.value.as_ref(), &[5, 0, 0, 1]);
+    }
+    #[test]
+    fn test_last_handles() {
+        let mut table = AttributeTable::new();
+        let service = Service::new_primary(UUID16(0x180F)).with_characteristic(
+            UUID16(0x2A19),
+            CharacteristicProperties::new(CharacteristicProperties::READ),
+            &[100],
+        );
+        let range = table
+            .add_service_at(Handle::new(0xFFFD), service.clone())
+            .unwrap();
+        assert_eq!(range.end, HandleRange::ALL.end);
+        assert_eq!(
+            table.get(Handle::new(0xFFFF)).unwrap().value.as_ref(),
+            &[100]
+        );
+        assert_eq!(
+            table.add_service_at(Handle::new(0xFFFF), Service::new_primary(UUID16(0x1810))),
+            Err(ServerError::HandleRangeInUse)
+        );
+        table.remove_service(range.start).unwrap();
+        let last = table
+            .add_service_at(Handle::new(0xFFFF), Service::new_primary(UUID16(0x1810)))
+            .unwrap();
+        assert_eq!(
+            last,
+            HandleRange::new(Handle::new(0xFFFF), Handle::new(0xFFFF))
+        );
+        assert_eq!(
+            table.add_service_at(Handle::new(0xFFFD), service),
+            Err(ServerError::HandleRangeInUse)
+        );
+    }
+    #[test]
+    fn test_handle_range() {
+        let mut buf = [0_u8; HandleRange::BYTE_LEN];
+        HandleRange::ALL.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x01, 0x00, 0xFF, 0xFF]);
+        assert_eq!(HandleRange::unpack_from(&buf), Ok(HandleRange::ALL));
+        assert_eq!(
+            HandleRange::unpack_from(&[0x00, 0x00, 0xFF, 0xFF]),
+            Err(PackError::bad_index(0))
+        );
+        assert_eq!(
+            HandleRange::unpack_from(&[0x05, 0x00, 0x04, 0x00]),
+            Err(PackError::InvalidFields)
+        );
+    }
+}
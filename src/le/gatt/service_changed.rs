@@ -0,0 +1,130 @@
+//! Generic Attribute Profile service and the Service Changed characteristic.
+use crate::le::att::attribute::{Handle, Value};
+use crate::le::att::pdus::handle::HandleValueInd;
+use crate::le::gatt::server::{HandleRange, Service};
+use crate::le::gatt::{
+    CharacteristicProperties, CLIENT_CHARACTERISTIC_CONFIGURATION, GATT_SERVICE, SERVICE_CHANGED,
+};
+use crate::BTAddress;
+use alloc::collections::BTreeMap;
+
+/// GATT service with the Service Changed characteristic (and its CCCD).
+pub fn gatt_service() -> Service {
+    Service::new_primary(GATT_SERVICE)
+        .with_characteristic(
+            SERVICE_CHANGED,
+            CharacteristicProperties::new(CharacteristicProperties::INDICATE),
+            &[0_u8; HandleRange::BYTE_LEN],
+        )
+        .with_descriptor(CLIENT_CHARACTERISTIC_CONFIGURATION, &[0_u8; 2])
+}
+/// Service Changed value handle of a [`gatt_service`] added at `range`.
+pub fn value_handle(range: HandleRange) -> Handle {
+    Handle::new(range.start.inner() + 2)
+}
+/// Service Changed awareness state of one bonded client.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct ClientState {
+    pub indications_enabled: bool,
+    pub change_aware: bool,
+    pending: Option<HandleRange>,
+    in_flight: Option<HandleRange>,
+}
+impl ClientState {
+    /// Affected range not yet indicated to the client.
+    pub fn pending(&self) -> Option<HandleRange> {
+        self.pending
+    }
+    /// Affected range indicated but not confirmed yet.
+    pub fn in_flight(&self) -> Option<HandleRange> {
+        self.in_flight
+    }
+}
+/// Tracks per bond Service Changed state. Call [`ServiceChangedTracker::database_changed`] when the
+/// attribute table changes and send what [`ServiceChangedTracker::next_indication`] returns to
+/// the client.
+#[derive(Clone, Debug)]
+pub struct ServiceChangedTracker {
+    value_handle: Handle,
+    clients: BTreeMap<BTAddress, ClientState>,
+}
+impl ServiceChangedTracker {
+    pub fn new(value_handle: Handle) -> ServiceChangedTracker {
+        ServiceChangedTracker {
+            value_handle,
+            clients: BTreeMap::new(),
+        }
+    }
+    pub fn value_handle(&self) -> Handle {
+        self.value_handle
+    }
+    /// Starts tracking `peer`. Newly bonded clients have discovered the current database so they
+    /// start change-aware.
+    pub fn add_bond(&mut self, peer: BTAddress) {
+        self.clients.entry(peer).or_insert(ClientState {
+            change_aware: true,
+            ..ClientState::default()
+        });
+    }
+    pub fn remove_bond(&mut self, peer: &BTAddress) -> Option<ClientState> {
+        self.clients.remove(peer)
+    }
+    pub fn client(&self, peer: &BTAddress) -> Option<&ClientState> {
+        self.clients.get(peer)
+    }
+    /// Called when `peer` writes the Service Changed CCCD.
+    pub fn set_indications_enabled(&mut self, peer: &BTAddress, enabled: bool) {
+        if let Some(client) = self.clients.get_mut(peer) {
+            client.indications_enabled = enabled;
+        }
+    }
+    pub fn is_change_aware(&self, peer: &BTAddress) -> bool {
+        self.clients.get(peer).map_or(false, |c| c.change_aware)
+    }
+    /// Marks `range` as changed for every bonded client.
+    pub fn database_changed(&mut self, range: HandleRange) {
+        for client in self.clients.values_mut() {
+            client.change_aware = false;
+            client.pending = Some(client.pending.map_or(range, |p| p.union(range)));
+        }
+    }
+    /// Returns the Service Changed indication to send to `peer` (if any). Only one indication is
+    /// in flight per client until [`ServiceChangedTracker::confirmed`] is called.
+    pub fn next_indication(
+        &mut self,
+        peer: &BTAddress,
+    ) -> Option<HandleValueInd<[u8; HandleRange::BYTE_LEN]>> {
+        let client = self.clients.get_mut(peer)?;
+        if !client.indications_enabled || client.in_flight.is_some() {
+            return None;
+        }
+        let range = client.pending.take()?;
+        client.in_flight = Some(range);
+        let mut value = [0_u8; HandleRange::BYTE_LEN];
+        range
+            .pack_into(&mut value[..])
+            .expect("buffer is HandleRange::BYTE_LEN");
+        Some(HandleValueInd {
+            handle: self.value_handle,
+            value: Value::new(value),
+        })
+    }
+    /// Called when `peer` confirms the Service Changed indication. The client becomes
+    /// change-aware if nothing else changed in the meantime.
+    pub fn confirmed(&mut self, peer: &BTAddress) {
+        if let Some(client) = self.clients.get_mut(peer) {
+            client.in_flight = None;
+            if client.pending.is_none() {
+                client.change_aware = true;
+            }
+        }
+    }
+    /// Called when `peer` disconnects. An unconfirmed indication is sent again on reconnection.
+    pub fn disconnected(&mut self, peer: &BTAddress) {
+        if let Some(client) = self.clients.get_mut(peer) {
+            if let Some(range) = client.in_flight.take() {
+                client.pending = Some(client.pending.map_or(range, |p| p.union(range)));
+            }
+        }
+    }
+}
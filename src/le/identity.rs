@@ -0,0 +1,233 @@
+//! Connection handle to peer identity mapping. Connection handles are reused and peers using
+//! privacy connect from a new address every time, so neither is a good key for application state.
+//! [`IdentityResolver`] follows connections (from the connection complete and disconnection
+//! complete events), resolves the peer address to its identity address and tags events with the
+//! [`PeerIdentity`], which is also the key of the peer's [`Bond`].
+use crate::hci::adapters::dispatcher::connection_handle_of;
+use crate::hci::event::{EventCode, EventPacket};
+use crate::hci::le::MetaEventCode;
+use crate::le::advertiser::PeerAddressType;
+use crate::le::connection::ConnectionHandle;
+use crate::le::privacy::ResolvingList;
+use crate::le::smp::bond::{Bond, BondStore, PeerIdentity};
+use crate::BTAddress;
+use alloc::collections::BTreeMap;
+use core::convert::TryFrom;
+
+/// Who is on the other end of a connection.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ConnectionIdentity {
+    pub connection_handle: ConnectionHandle,
+    /// Identity address. The connection address if it couldn't be resolved.
+    pub identity: PeerIdentity,
+    /// Address the peer connected from.
+    pub connection_address: PeerIdentity,
+    /// Whether `identity` came from resolving a private address.
+    pub resolved: bool,
+}
+impl ConnectionIdentity {
+    /// The peer's bond (the identity is the bond key).
+    pub fn bond<S: BondStore>(&self, bonds: &S) -> Option<Bond> {
+        bonds.get(&self.identity)
+    }
+}
+/// Something (an event, a notification) tagged with the identity of the peer it came from.
+/// `identity` is `None` if it isn't about a connection or the connection is unknown.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct Tagged<T> {
+    pub identity: Option<ConnectionIdentity>,
+    pub value: T,
+}
+#[derive(Clone, Debug, Default)]
+pub struct IdentityResolver {
+    connections: BTreeMap<ConnectionHandle, ConnectionIdentity>,
+}
+impl IdentityResolver {
+    pub fn new() -> IdentityResolver {
+        IdentityResolver::default()
+    }
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+    /// Records a new connection. A resolvable private `address` is resolved with
+    /// `resolving_list`.
+    pub fn connected(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        address_type: PeerAddressType,
+        address: BTAddress,
+        resolving_list: &ResolvingList,
+    ) -> ConnectionIdentity {
+        let connection_address = PeerIdentity {
+            address_type,
+            address,
+        };
+        let entry = match address_type {
+            PeerAddressType::Random => resolving_list.resolve(address),
+            PeerAddressType::Public => None,
+        };
+        let identity = ConnectionIdentity {
+            connection_handle,
+            identity: entry.map_or(connection_address, |entry| PeerIdentity {
+                address_type: entry.identity_address_type,
+                address: entry.identity_address,
+            }),
+            connection_address,
+            resolved: entry.is_some(),
+        };
+        self.connections.insert(connection_handle, identity);
+        identity
+    }
+    /// Sets the identity of a connection once it is known later on (from the identity address
+    /// the peer sent while pairing).
+    pub fn set_identity(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        identity: PeerIdentity,
+    ) -> Option<ConnectionIdentity> {
+        let connection = self.connections.get_mut(&connection_handle)?;
+        connection.resolved = identity != connection.connection_address;
+        connection.identity = identity;
+        Some(*connection)
+    }
+    pub fn disconnected(
+        &mut self,
+        connection_handle: ConnectionHandle,
+    ) -> Option<ConnectionIdentity> {
+        self.connections.remove(&connection_handle)
+    }
+    pub fn get(&self, connection_handle: ConnectionHandle) -> Option<&ConnectionIdentity> {
+        self.connections.get(&connection_handle)
+    }
+    /// Handle of the connection to `identity` (if connected).
+    pub fn connection_handle(&self, identity: &PeerIdentity) -> Option<ConnectionHandle> {
+        self.connections
+            .values()
+            .find(|connection| &connection.identity == identity)
+            .map(|connection| connection.connection_handle)
+    }
+    /// Tags `value` (a GATT notification for example) from `connection_handle`.
+    pub fn tag<T>(&self, connection_handle: ConnectionHandle, value: T) -> Tagged<T> {
+        Tagged {
+            identity: self.get(connection_handle).copied(),
+            value,
+        }
+    }
+    /// Tags an HCI event. Connection complete events add a connection and disconnection complete
+    /// events remove it (after tagging the event with it).
+    pub fn tag_event<B: AsRef<[u8]>>(
+        &mut self,
+        event: EventPacket<B>,
+        resolving_list: &ResolvingList,
+    ) -> Tagged<EventPacket<B>> {
+        let identity = match parse_connection(event.as_ref()) {
+            Some((connection_handle, address_type, address, identity_resolved)) => {
+                let mut identity =
+                    self.connected(connection_handle, address_type, address, resolving_list);
+                if identity_resolved {
+                    // The controller resolved the address already.
+                    identity.resolved = true;
+                    self.connections.insert(connection_handle, identity);
+                }
+                Some(identity)
+            }
+            None => connection_handle_of(event.as_ref()).and_then(|connection_handle| {
+                if event.event_code == EventCode::DisconnectionComplete {
+                    self.disconnected(connection_handle)
+                } else {
+                    self.get(connection_handle).copied()
+                }
+            }),
+        };
+        Tagged {
+            identity,
+            value: event,
+        }
+    }
+}
+/// Handle, peer address type, peer address and whether the controller resolved the address, of
+/// a successful (enhanced) connection complete event.
+fn parse_connection(
+    event: EventPacket<&[u8]>,
+) -> Option<(ConnectionHandle, PeerAddressType, BTAddress, bool)> {
+    if event.event_code != EventCode::LEMeta {
+        return None;
+    }
+    let parameters = event.parameters;
+    match MetaEventCode::try_from(*parameters.first()?).ok()? {
+        MetaEventCode::ConnectionComplete | MetaEventCode::EnhancedConnectionComplete => (),
+        _ => return None,
+    }
+    if *parameters.get(1)? != 0 {
+        return None;
+    }
+    let connection_handle = connection_handle_of(event)?;
+    // 0x02 and 0x03 are identity addresses resolved by the controller.
+    let (address_type, identity_resolved) = match *parameters.get(5)? {
+        0x00 => (PeerAddressType::Public, false),
+        0x01 => (PeerAddressType::Random, false),
+        0x02 => (PeerAddressType::Public, true),
+        0x03 => (PeerAddressType::Random, true),
+        _ => return None,
+    };
+    let address = BTAddress::unpack_from(parameters.get(6..12)?).ok()?;
+    Some((connection_handle, address_type, address, identity_resolved))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::le::privacy::{IdentityResolvingKey, ResolvingListEntry};
+
+    #[test]
+    fn test_tag_events() {
+        let irk = IdentityResolvingKey([0x11; 16]);
+        let prand = 0x40_1234;
+        let hash = irk.hash(prand);
+        let rpa = BTAddress([
+            hash as u8,
+            (hash >> 8) as u8,
+            (hash >> 16) as u8,
+            prand as u8,
+            (prand >> 8) as u8,
+            (prand >> 16) as u8,
+        ]);
+        let identity = PeerIdentity {
+            address_type: PeerAddressType::Public,
+            address: BTAddress([1, 2, 3, 4, 5, 6]),
+        };
+        let mut resolving_list = ResolvingList::new(None, true);
+        resolving_list.add(ResolvingListEntry {
+            identity_address_type: identity.address_type,
+            identity_address: identity.address,
+            peer_irk: irk,
+        });
+        let mut resolver = IdentityResolver::new();
+        let mut complete = vec![0x01, 0x00, 0x40, 0x00, 0x00, 0x01];
+        complete.extend_from_slice(&rpa.0);
+        complete.extend_from_slice(&[0x18, 0x00, 0x00, 0x00, 0xC8, 0x00, 0x00]);
+        let tagged = resolver.tag_event(
+            EventPacket::new(EventCode::LEMeta, &complete[..]),
+            &resolving_list,
+        );
+        let handle = ConnectionHandle::new(0x0040);
+        let connection = tagged.identity.unwrap();
+        assert_eq!(connection.identity, identity);
+        assert!(connection.resolved);
+        assert_eq!(resolver.connection_handle(&identity), Some(handle));
+        assert_eq!(
+            resolver.tag(handle, "notification").identity,
+            Some(connection)
+        );
+
+        let disconnect = [0x00, 0x40, 0x00, 0x13];
+        let tagged = resolver.tag_event(
+            EventPacket::new(EventCode::DisconnectionComplete, &disconnect[..]),
+            &resolving_list,
+        );
+        assert_eq!(tagged.identity, Some(connection));
+        assert!(resolver.is_empty());
+    }
+}
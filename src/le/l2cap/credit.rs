@@ -0,0 +1,946 @@
+//! Credit based L2CAP channels. Both the LE Credit Based Flow Control mode (LE CoC, one channel
+//! per request) and the Enhanced Credit Based Flow Control mode (ECFC, up to 5 channels per
+//! request and reconfigurable MTU/MPS, used by EATT) share the same data path: SDUs are split
+//! into K-frames of at most the peer's MPS and every K-frame uses up one credit.
+//!
+//! [`CreditChannels`] handles the signaling of one LE link. Feed it the packets of the LE
+//! signaling channel with [`CreditChannels::handle_signal`], send the packets it (and
+//! [`CreditChannels::next_signal`]) returns and take [`ChannelEvent`]s with
+//! [`CreditChannels::next_event`]. Data goes through the [`CreditChannel`]s themselves.
+use crate::le::l2cap::signaling::{
+    ConnectionResult, ReconfigureResult, Signal, SignalPacket, MAX_ECFC_CHANNELS, MAX_MPS,
+    MIN_ECFC_MTU, MIN_LE_COC_MTU, REJECT_INVALID_CID,
+};
+use crate::le::l2cap::{BasicFrame, FIRST_DYNAMIC_CID, LAST_DYNAMIC_CID};
+use crate::strictness::Strictness;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// SDU length field at the start of the first K-frame of every SDU.
+pub const SDU_LENGTH_LEN: usize = 2;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ChannelMode {
+    LECreditBased,
+    EnhancedCreditBased,
+}
+impl ChannelMode {
+    /// Smallest allowed MTU and MPS.
+    pub fn min_mtu(self) -> u16 {
+        match self {
+            ChannelMode::LECreditBased => MIN_LE_COC_MTU,
+            ChannelMode::EnhancedCreditBased => MIN_ECFC_MTU,
+        }
+    }
+}
+/// Receive side parameters of this device.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ChannelParameters {
+    /// Largest SDU.
+    pub mtu: u16,
+    /// Largest K-frame payload.
+    pub mps: u16,
+    /// Credits given to the peer when a channel opens.
+    pub initial_credits: u16,
+}
+impl ChannelParameters {
+    pub const DEFAULT: ChannelParameters = ChannelParameters {
+        mtu: 512,
+        mps: 247,
+        initial_credits: 8,
+    };
+    fn is_valid(self, mode: ChannelMode) -> bool {
+        self.mtu >= mode.min_mtu() && (mode.min_mtu()..=MAX_MPS).contains(&self.mps)
+    }
+}
+impl Default for ChannelParameters {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ChannelError {
+    /// The peer sent a K-frame without having a credit.
+    NoCredits,
+    /// Credits went over 65535.
+    CreditOverflow,
+    /// The SDU is longer than the receiver's MTU.
+    SDUTooLong,
+    /// The K-frame is longer than the receiver's MPS.
+    PDUTooLong,
+    /// The K-frames don't add up to the announced SDU length.
+    BadSDULength,
+    UnknownChannel,
+    NoFreeCIDs,
+    /// Not 1 to 5 channels in one request.
+    TooManyChannels,
+    /// MTU or MPS below the minimum of the mode (or a reduced MTU when reconfiguring).
+    InvalidParameters,
+    /// Only Enhanced Credit Based channels can be reconfigured.
+    WrongMode,
+}
+impl core::fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "credit based channel error {:?}", self)
+    }
+}
+impl crate::error::Error for ChannelError {}
+#[cfg(feature = "std")]
+impl std::error::Error for ChannelError {}
+/// One open credit based channel.
+#[derive(Clone, Debug)]
+pub struct CreditChannel {
+    mode: ChannelMode,
+    spsm: u16,
+    local_cid: u16,
+    peer_cid: u16,
+    local_mtu: u16,
+    local_mps: u16,
+    peer_mtu: u16,
+    peer_mps: u16,
+    /// K-frames this device may still send.
+    tx_credits: u16,
+    /// K-frames the peer may still send.
+    rx_credits: u16,
+    outgoing: VecDeque<Vec<u8>>,
+    /// Announced SDU length and the data so far.
+    reassembly: Option<(usize, Vec<u8>)>,
+    strictness: Strictness,
+}
+impl CreditChannel {
+    pub fn mode(&self) -> ChannelMode {
+        self.mode
+    }
+    pub fn spsm(&self) -> u16 {
+        self.spsm
+    }
+    pub fn local_cid(&self) -> u16 {
+        self.local_cid
+    }
+    pub fn peer_cid(&self) -> u16 {
+        self.peer_cid
+    }
+    /// `(mtu, mps)` this device receives with.
+    pub fn local_mtu_mps(&self) -> (u16, u16) {
+        (self.local_mtu, self.local_mps)
+    }
+    /// `(mtu, mps)` the peer receives with.
+    pub fn peer_mtu_mps(&self) -> (u16, u16) {
+        (self.peer_mtu, self.peer_mps)
+    }
+    pub fn tx_credits(&self) -> u16 {
+        self.tx_credits
+    }
+    pub fn rx_credits(&self) -> u16 {
+        self.rx_credits
+    }
+    /// K-frames waiting for credits.
+    pub fn queued_frames(&self) -> usize {
+        self.outgoing.len()
+    }
+    /// Splits `sdu` into K-frames and queues them. Take them with [`CreditChannel::next_frame`].
+    pub fn send(&mut self, sdu: &[u8]) -> Result<(), ChannelError> {
+        let sdu_len = u16::try_from(sdu.len()).map_err(|_| ChannelError::SDUTooLong)?;
+        if sdu_len > self.peer_mtu {
+            return Err(ChannelError::SDUTooLong);
+        }
+        let mps = usize::from(self.peer_mps);
+        let first_len = sdu.len().min(mps - SDU_LENGTH_LEN);
+        let mut first = Vec::with_capacity(SDU_LENGTH_LEN + first_len);
+        first.extend_from_slice(&sdu_len.to_le_bytes());
+        first.extend_from_slice(&sdu[..first_len]);
+        self.outgoing.push_back(first);
+        for chunk in sdu[first_len..].chunks(mps) {
+            self.outgoing.push_back(chunk.to_vec());
+        }
+        Ok(())
+    }
+    /// Next K-frame (as a B-frame to put in ACL data) if there is one and a credit for it.
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        if self.tx_credits == 0 {
+            return None;
+        }
+        let payload = self.outgoing.pop_front()?;
+        self.tx_credits -= 1;
+        BasicFrame::new(self.peer_cid, payload).pack().ok()
+    }
+    fn add_tx_credits(&mut self, credits: u16) -> Result<(), ChannelError> {
+        self.tx_credits = self
+            .tx_credits
+            .checked_add(credits)
+            .ok_or(ChannelError::CreditOverflow)?;
+        Ok(())
+    }
+    /// Takes the payload of a K-frame received on this channel. Returns the SDU once all of it
+    /// arrived. Errors are protocol violations and the channel should be disconnected.
+    ///
+    /// Unless the channel is [`Strictness::Strict`], K-frames without a credit or over the MPS
+    /// and SDUs over the MTU are taken and SDUs longer than announced are truncated.
+    pub fn receive(&mut self, payload: &[u8]) -> Result<Option<Vec<u8>>, ChannelError> {
+        if self.rx_credits == 0 {
+            self.strictness.violation(ChannelError::NoCredits)?;
+        }
+        self.rx_credits = self.rx_credits.saturating_sub(1);
+        if payload.len() > usize::from(self.local_mps) {
+            self.strictness.violation(ChannelError::PDUTooLong)?;
+        }
+        match self.reassembly.take() {
+            Some((sdu_len, sdu)) => self.append(sdu_len, sdu, payload),
+            None => {
+                if payload.len() < SDU_LENGTH_LEN {
+                    return Err(ChannelError::BadSDULength);
+                }
+                let (len, data) = payload.split_at(SDU_LENGTH_LEN);
+                let sdu_len = usize::from(u16::from_le_bytes([len[0], len[1]]));
+                if sdu_len > usize::from(self.local_mtu) {
+                    self.strictness.violation(ChannelError::SDUTooLong)?;
+                }
+                self.append(sdu_len, Vec::with_capacity(sdu_len), data)
+            }
+        }
+    }
+    fn append(
+        &mut self,
+        sdu_len: usize,
+        mut sdu: Vec<u8>,
+        data: &[u8],
+    ) -> Result<Option<Vec<u8>>, ChannelError> {
+        sdu.extend_from_slice(data);
+        if sdu.len() > sdu_len {
+            self.strictness.violation(ChannelError::BadSDULength)?;
+            sdu.truncate(sdu_len);
+            Ok(Some(sdu))
+        } else if sdu.len() == sdu_len {
+            Ok(Some(sdu))
+        } else {
+            self.reassembly = Some((sdu_len, sdu));
+            Ok(None)
+        }
+    }
+}
+/// Something that happened on a [`CreditChannels`] link.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ChannelEvent {
+    /// A channel opened (requested by either side).
+    Connected {
+        local_cid: u16,
+    },
+    /// The peer refused channels requested with `spsm`. A Command Reject is reported as
+    /// `SPSMNotSupported`.
+    Refused {
+        spsm: u16,
+        count: usize,
+        result: ConnectionResult,
+    },
+    /// Reconfiguration requested by this device finished.
+    Reconfigured {
+        local_cids: Vec<u16>,
+        result: ReconfigureResult,
+    },
+    /// The peer changed its MTU/MPS.
+    PeerReconfigured {
+        local_cid: u16,
+    },
+    Disconnected {
+        local_cid: u16,
+    },
+}
+#[derive(Clone, Debug)]
+enum Request {
+    Connect {
+        mode: ChannelMode,
+        spsm: u16,
+        local_cids: Vec<u16>,
+    },
+    Reconfigure {
+        local_cids: Vec<u16>,
+        mtu: u16,
+        mps: u16,
+    },
+    Disconnect {
+        local_cid: u16,
+    },
+}
+/// Credit based channels of one LE link. See the module docs.
+#[derive(Clone, Debug)]
+pub struct CreditChannels {
+    parameters: ChannelParameters,
+    listening: Vec<u16>,
+    channels: Vec<CreditChannel>,
+    pending: Vec<(u8, Request)>,
+    next_identifier: u8,
+    events: VecDeque<ChannelEvent>,
+    /// Requests to send that don't answer a peer packet.
+    signals: VecDeque<SignalPacket>,
+    strictness: Strictness,
+}
+impl CreditChannels {
+    pub fn new(parameters: ChannelParameters) -> CreditChannels {
+        CreditChannels {
+            parameters,
+            listening: Vec::new(),
+            channels: Vec::new(),
+            pending: Vec::new(),
+            next_identifier: 1,
+            events: VecDeque::new(),
+            signals: VecDeque::new(),
+            strictness: Strictness::default(),
+        }
+    }
+    /// How the open and future channels treat peers breaking the data path rules (see
+    /// [`CreditChannel::receive`]) and credit overflows.
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        self.strictness = strictness;
+        for channel in &mut self.channels {
+            channel.strictness = strictness;
+        }
+    }
+    /// Accepts channels the peer requests for `spsm`.
+    pub fn listen(&mut self, spsm: u16) {
+        if !self.listening.contains(&spsm) {
+            self.listening.push(spsm);
+        }
+    }
+    pub fn channel(&self, local_cid: u16) -> Option<&CreditChannel> {
+        self.channels.iter().find(|c| c.local_cid == local_cid)
+    }
+    pub fn channel_mut(&mut self, local_cid: u16) -> Option<&mut CreditChannel> {
+        self.channels.iter_mut().find(|c| c.local_cid == local_cid)
+    }
+    pub fn channels(&self) -> impl Iterator<Item = &CreditChannel> {
+        self.channels.iter()
+    }
+    pub fn next_event(&mut self) -> Option<ChannelEvent> {
+        self.events.pop_front()
+    }
+    /// Signaling packets to send that don't answer a packet given to
+    /// [`CreditChannels::handle_signal`] (disconnecting channels the peer opened with an invalid
+    /// MTU or MPS).
+    pub fn next_signal(&mut self) -> Option<SignalPacket> {
+        self.signals.pop_front()
+    }
+    fn identifier(&mut self) -> u8 {
+        let identifier = self.next_identifier;
+        self.next_identifier = self.next_identifier.checked_add(1).unwrap_or(1);
+        identifier
+    }
+    fn cid_in_use(&self, cid: u16) -> bool {
+        self.channels.iter().any(|c| c.local_cid == cid)
+            || self.pending.iter().any(|(_, r)| match r {
+                Request::Connect { local_cids, .. } => local_cids.contains(&cid),
+                Request::Disconnect { local_cid } => *local_cid == cid,
+                Request::Reconfigure { .. } => false,
+            })
+    }
+    fn allocate_cids(&self, count: usize) -> Vec<u16> {
+        (FIRST_DYNAMIC_CID..=LAST_DYNAMIC_CID)
+            .filter(|&cid| !self.cid_in_use(cid))
+            .take(count)
+            .collect()
+    }
+    fn request(&mut self, request: Request, signal: Signal) -> SignalPacket {
+        let identifier = self.identifier();
+        self.pending.push((identifier, request));
+        SignalPacket::new(identifier, signal)
+    }
+    /// Requests one LE Credit Based channel.
+    pub fn connect_le(&mut self, spsm: u16) -> Result<SignalPacket, ChannelError> {
+        if !self.parameters.is_valid(ChannelMode::LECreditBased) {
+            return Err(ChannelError::InvalidParameters);
+        }
+        let local_cids = self.allocate_cids(1);
+        let source_cid = *local_cids.first().ok_or(ChannelError::NoFreeCIDs)?;
+        let parameters = self.parameters;
+        Ok(self.request(
+            Request::Connect {
+                mode: ChannelMode::LECreditBased,
+                spsm,
+                local_cids,
+            },
+            Signal::LECreditBasedConnectionReq {
+                spsm,
+                source_cid,
+                mtu: parameters.mtu,
+                mps: parameters.mps,
+                initial_credits: parameters.initial_credits,
+            },
+        ))
+    }
+    /// Requests `count` (1 to 5) Enhanced Credit Based channels in one request.
+    pub fn connect(&mut self, spsm: u16, count: usize) -> Result<SignalPacket, ChannelError> {
+        if count == 0 || count > MAX_ECFC_CHANNELS {
+            return Err(ChannelError::TooManyChannels);
+        }
+        if !self.parameters.is_valid(ChannelMode::EnhancedCreditBased) {
+            return Err(ChannelError::InvalidParameters);
+        }
+        let local_cids = self.allocate_cids(count);
+        if local_cids.len() < count {
+            return Err(ChannelError::NoFreeCIDs);
+        }
+        let parameters = self.parameters;
+        Ok(self.request(
+            Request::Connect {
+                mode: ChannelMode::EnhancedCreditBased,
+                spsm,
+                local_cids: local_cids.clone(),
+            },
+            Signal::CreditBasedConnectionReq {
+                spsm,
+                mtu: parameters.mtu,
+                mps: parameters.mps,
+                initial_credits: parameters.initial_credits,
+                source_cids: local_cids,
+            },
+        ))
+    }
+    /// Changes the MTU/MPS this device receives with on Enhanced Credit Based channels. The MTU
+    /// can't shrink. The new values apply once the peer accepts.
+    pub fn reconfigure(
+        &mut self,
+        local_cids: &[u16],
+        mtu: u16,
+        mps: u16,
+    ) -> Result<SignalPacket, ChannelError> {
+        if local_cids.is_empty() || local_cids.len() > MAX_ECFC_CHANNELS {
+            return Err(ChannelError::TooManyChannels);
+        }
+        let mut destination_cids = Vec::with_capacity(local_cids.len());
+        for &cid in local_cids {
+            let channel = self.channel(cid).ok_or(ChannelError::UnknownChannel)?;
+            if channel.mode != ChannelMode::EnhancedCreditBased {
+                return Err(ChannelError::WrongMode);
+            }
+            let parameters = ChannelParameters {
+                mtu,
+                mps,
+                initial_credits: 0,
+            };
+            if mtu < channel.local_mtu || !parameters.is_valid(channel.mode) {
+                return Err(ChannelError::InvalidParameters);
+            }
+            destination_cids.push(channel.peer_cid);
+        }
+        Ok(self.request(
+            Request::Reconfigure {
+                local_cids: local_cids.to_vec(),
+                mtu,
+                mps,
+            },
+            Signal::CreditBasedReconfigureReq {
+                mtu,
+                mps,
+                destination_cids,
+            },
+        ))
+    }
+    pub fn disconnect(&mut self, local_cid: u16) -> Result<SignalPacket, ChannelError> {
+        let peer_cid = self
+            .channel(local_cid)
+            .ok_or(ChannelError::UnknownChannel)?
+            .peer_cid;
+        Ok(self.request(
+            Request::Disconnect { local_cid },
+            Signal::DisconnectionReq {
+                destination_cid: peer_cid,
+                source_cid: local_cid,
+            },
+        ))
+    }
+    /// Lets the peer send `credits` more K-frames on `local_cid`.
+    pub fn grant_credits(
+        &mut self,
+        local_cid: u16,
+        credits: u16,
+    ) -> Result<SignalPacket, ChannelError> {
+        let channel = self
+            .channel_mut(local_cid)
+            .ok_or(ChannelError::UnknownChannel)?;
+        channel.rx_credits = channel
+            .rx_credits
+            .checked_add(credits)
+            .ok_or(ChannelError::CreditOverflow)?;
+        let identifier = self.identifier();
+        Ok(SignalPacket::new(
+            identifier,
+            Signal::FlowControlCreditInd {
+                cid: local_cid,
+                credits,
+            },
+        ))
+    }
+    fn open(&mut self, mode: ChannelMode, spsm: u16, local_cid: u16, peer: (u16, u16, u16, u16)) {
+        let (peer_cid, peer_mtu, peer_mps, tx_credits) = peer;
+        self.channels.push(CreditChannel {
+            mode,
+            spsm,
+            local_cid,
+            peer_cid,
+            local_mtu: self.parameters.mtu,
+            local_mps: self.parameters.mps,
+            peer_mtu,
+            peer_mps,
+            tx_credits,
+            rx_credits: self.parameters.initial_credits,
+            outgoing: VecDeque::new(),
+            reassembly: None,
+            strictness: self.strictness,
+        });
+        self.events.push_back(ChannelEvent::Connected { local_cid });
+    }
+    /// Result of one channel the peer asked for.
+    fn accept(
+        &mut self,
+        mode: ChannelMode,
+        spsm: u16,
+        peer: (u16, u16, u16, u16),
+    ) -> Result<u16, ConnectionResult> {
+        let (peer_cid, ..) = peer;
+        if !(FIRST_DYNAMIC_CID..=LAST_DYNAMIC_CID).contains(&peer_cid) {
+            return Err(ConnectionResult::InvalidSourceCID);
+        }
+        if self.channels.iter().any(|c| c.peer_cid == peer_cid) {
+            return Err(ConnectionResult::SourceCIDAlreadyAllocated);
+        }
+        let local_cid = *self
+            .allocate_cids(1)
+            .first()
+            .ok_or(ConnectionResult::NoResourcesAvailable)?;
+        self.open(mode, spsm, local_cid, peer);
+        Ok(local_cid)
+    }
+    /// Handles a packet from the LE signaling channel. Returns the response to send (if any).
+    pub fn handle_signal(&mut self, packet: &SignalPacket) -> Option<SignalPacket> {
+        let identifier = packet.identifier;
+        let respond = |signal| Some(SignalPacket::new(identifier, signal));
+        match &packet.signal {
+            &Signal::LECreditBasedConnectionReq {
+                spsm,
+                source_cid,
+                mtu,
+                mps,
+                initial_credits,
+            } => {
+                let mode = ChannelMode::LECreditBased;
+                let result = if !self.listening.contains(&spsm) {
+                    Err(ConnectionResult::SPSMNotSupported)
+                } else if mtu < mode.min_mtu() || mps < mode.min_mtu() || mps > MAX_MPS {
+                    Err(ConnectionResult::UnacceptableParameters)
+                } else {
+                    self.accept(mode, spsm, (source_cid, mtu, mps, initial_credits))
+                };
+                let parameters = self.parameters;
+                respond(Signal::LECreditBasedConnectionRsp {
+                    destination_cid: result.unwrap_or(0),
+                    mtu: parameters.mtu,
+                    mps: parameters.mps,
+                    initial_credits: parameters.initial_credits,
+                    result: result.err().unwrap_or(ConnectionResult::Successful),
+                })
+            }
+            Signal::CreditBasedConnectionReq {
+                spsm,
+                mtu,
+                mps,
+                initial_credits,
+                source_cids,
+            } => {
+                let mode = ChannelMode::EnhancedCreditBased;
+                let refuse_all = if !self.listening.contains(spsm) {
+                    Some(ConnectionResult::SPSMNotSupported)
+                } else if *mtu < mode.min_mtu() || *mps < mode.min_mtu() || *mps > MAX_MPS {
+                    Some(ConnectionResult::InvalidParameters)
+                } else {
+                    None
+                };
+                let mut result = ConnectionResult::Successful;
+                let mut destination_cids = Vec::with_capacity(source_cids.len());
+                for &source_cid in source_cids {
+                    let accepted = match refuse_all {
+                        Some(refused) => Err(refused),
+                        None => {
+                            self.accept(mode, *spsm, (source_cid, *mtu, *mps, *initial_credits))
+                        }
+                    };
+                    match accepted {
+                        Ok(cid) => destination_cids.push(cid),
+                        Err(refused) => {
+                            result = refused;
+                            destination_cids.push(0);
+                        }
+                    }
+                }
+                let parameters = self.parameters;
+                respond(Signal::CreditBasedConnectionRsp {
+                    mtu: parameters.mtu,
+                    mps: parameters.mps,
+                    initial_credits: parameters.initial_credits,
+                    result,
+                    destination_cids,
+                })
+            }
+            &Signal::LECreditBasedConnectionRsp {
+                destination_cid,
+                mtu,
+                mps,
+                initial_credits,
+                result,
+            } => {
+                self.connected(
+                    identifier,
+                    &[destination_cid],
+                    (mtu, mps, initial_credits),
+                    result,
+                );
+                None
+            }
+            Signal::CreditBasedConnectionRsp {
+                mtu,
+                mps,
+                initial_credits,
+                result,
+                destination_cids,
+            } => {
+                self.connected(
+                    identifier,
+                    destination_cids,
+                    (*mtu, *mps, *initial_credits),
+                    *result,
+                );
+                None
+            }
+            &Signal::FlowControlCreditInd { cid, credits } => {
+                let channel = self.channels.iter_mut().find(|c| c.peer_cid == cid)?;
+                if let Err(e) = channel.add_tx_credits(credits) {
+                    if channel.strictness.violation(e).is_err() {
+                        let local_cid = channel.local_cid;
+                        return self.disconnect(local_cid).ok();
+                    }
+                    channel.tx_credits = u16::MAX;
+                }
+                None
+            }
+            Signal::CreditBasedReconfigureReq {
+                mtu,
+                mps,
+                destination_cids,
+            } => {
+                let result = self.peer_reconfigure(*mtu, *mps, destination_cids);
+                respond(Signal::CreditBasedReconfigureRsp { result })
+            }
+            &Signal::CreditBasedReconfigureRsp { result } => {
+                if let Some(Request::Reconfigure {
+                    local_cids,
+                    mtu,
+                    mps,
+                }) = self.take_pending(identifier)
+                {
+                    if result == ReconfigureResult::Successful {
+                        for channel in self
+                            .channels
+                            .iter_mut()
+                            .filter(|c| local_cids.contains(&c.local_cid))
+                        {
+                            channel.local_mtu = mtu;
+                            channel.local_mps = mps;
+                        }
+                    }
+                    self.events
+                        .push_back(ChannelEvent::Reconfigured { local_cids, result });
+                }
+                None
+            }
+            &Signal::DisconnectionReq {
+                destination_cid,
+                source_cid,
+            } => {
+                let before = self.channels.len();
+                self.channels
+                    .retain(|c| !(c.local_cid == destination_cid && c.peer_cid == source_cid));
+                if self.channels.len() == before {
+                    let mut data = destination_cid.to_le_bytes().to_vec();
+                    data.extend_from_slice(&source_cid.to_le_bytes());
+                    return respond(Signal::CommandRejectRsp {
+                        reason: REJECT_INVALID_CID,
+                        data,
+                    });
+                }
+                self.events.push_back(ChannelEvent::Disconnected {
+                    local_cid: destination_cid,
+                });
+                respond(Signal::DisconnectionRsp {
+                    destination_cid,
+                    source_cid,
+                })
+            }
+            Signal::DisconnectionRsp { .. } | Signal::CommandRejectRsp { .. } => {
+                match self.take_pending(identifier) {
+                    Some(Request::Disconnect { local_cid }) => {
+                        let before = self.channels.len();
+                        self.channels.retain(|c| c.local_cid != local_cid);
+                        // Channels refused for invalid parameters never opened.
+                        if self.channels.len() < before {
+                            self.events
+                                .push_back(ChannelEvent::Disconnected { local_cid });
+                        }
+                    }
+                    Some(Request::Connect {
+                        spsm, local_cids, ..
+                    }) => self.events.push_back(ChannelEvent::Refused {
+                        spsm,
+                        count: local_cids.len(),
+                        result: ConnectionResult::SPSMNotSupported,
+                    }),
+                    Some(Request::Reconfigure { local_cids, .. }) => {
+                        self.events.push_back(ChannelEvent::Reconfigured {
+                            local_cids,
+                            result: ReconfigureResult::UnacceptableParameters,
+                        })
+                    }
+                    None => (),
+                }
+                None
+            }
+        }
+    }
+    fn take_pending(&mut self, identifier: u8) -> Option<Request> {
+        let index = self.pending.iter().position(|(i, _)| *i == identifier)?;
+        Some(self.pending.remove(index).1)
+    }
+    /// Peer answered a connection request. Channels the peer accepted with an MTU or MPS out of
+    /// range are disconnected (see [`CreditChannels::next_signal`]) and reported as refused with
+    /// `UnacceptableParameters`.
+    fn connected(
+        &mut self,
+        identifier: u8,
+        destination_cids: &[u16],
+        (mtu, mps, initial_credits): (u16, u16, u16),
+        result: ConnectionResult,
+    ) {
+        let (mode, spsm, local_cids) = match self.take_pending(identifier) {
+            Some(Request::Connect {
+                mode,
+                spsm,
+                local_cids,
+            }) => (mode, spsm, local_cids),
+            _ => return,
+        };
+        let accepted = match mode {
+            ChannelMode::LECreditBased => result == ConnectionResult::Successful,
+            ChannelMode::EnhancedCreditBased => result != ConnectionResult::InvalidParameters,
+        };
+        let peer_parameters = ChannelParameters {
+            mtu,
+            mps,
+            initial_credits,
+        };
+        let valid = peer_parameters.is_valid(mode);
+        let mut refused = 0;
+        for (i, &local_cid) in local_cids.iter().enumerate() {
+            match destination_cids.get(i) {
+                Some(&peer_cid) if accepted && peer_cid != 0 && valid => {
+                    self.open(mode, spsm, local_cid, (peer_cid, mtu, mps, initial_credits));
+                }
+                Some(&peer_cid) if accepted && peer_cid != 0 => {
+                    refused += 1;
+                    let disconnect = self.request(
+                        Request::Disconnect { local_cid },
+                        Signal::DisconnectionReq {
+                            destination_cid: peer_cid,
+                            source_cid: local_cid,
+                        },
+                    );
+                    self.signals.push_back(disconnect);
+                }
+                _ => refused += 1,
+            }
+        }
+        if refused > 0 {
+            self.events.push_back(ChannelEvent::Refused {
+                spsm,
+                count: refused,
+                result: if accepted && !valid {
+                    ConnectionResult::UnacceptableParameters
+                } else {
+                    result
+                },
+            });
+        }
+    }
+    fn peer_reconfigure(&mut self, mtu: u16, mps: u16, local_cids: &[u16]) -> ReconfigureResult {
+        if mtu < MIN_ECFC_MTU || mps < MIN_ECFC_MTU || mps > MAX_MPS {
+            return ReconfigureResult::UnacceptableParameters;
+        }
+        for cid in local_cids {
+            let channel = match self.channel(*cid) {
+                Some(channel) if channel.mode == ChannelMode::EnhancedCreditBased => channel,
+                _ => return ReconfigureResult::InvalidDestinationCID,
+            };
+            if mtu < channel.peer_mtu {
+                return ReconfigureResult::MTUReductionNotAllowed;
+            }
+            if mps < channel.peer_mps && local_cids.len() > 1 {
+                return ReconfigureResult::MPSReductionNotAllowed;
+            }
+        }
+        for channel in self
+            .channels
+            .iter_mut()
+            .filter(|c| local_cids.contains(&c.local_cid))
+        {
+            channel.peer_mtu = mtu;
+            channel.peer_mps = mps;
+            self.events.push_back(ChannelEvent::PeerReconfigured {
+                local_cid: channel.local_cid,
+            });
+        }
+        ReconfigureResult::Successful
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::le::l2cap::EATT_SPSM;
+
+    /// Sends `packet` through the signaling packet codec.
+    fn over_the_air(packet: &SignalPacket) -> SignalPacket {
+        let bytes = packet.pack();
+        let (unpacked, len) = SignalPacket::unpack_from(&bytes).unwrap();
+        assert_eq!(len, bytes.len());
+        unpacked
+    }
+    #[test]
+    fn test_ecfc_connect_send_reconfigure() {
+        let mut central = CreditChannels::new(ChannelParameters {
+            mtu: 100,
+            mps: 64,
+            initial_credits: 4,
+        });
+        let mut peripheral = CreditChannels::new(ChannelParameters::DEFAULT);
+        peripheral.listen(EATT_SPSM);
+
+        let request = over_the_air(&central.connect(EATT_SPSM, 3).unwrap());
+        let response = over_the_air(&peripheral.handle_signal(&request).unwrap());
+        assert!(central.handle_signal(&response).is_none());
+        assert_eq!(central.channels().count(), 3);
+        assert_eq!(
+            central.next_event(),
+            Some(ChannelEvent::Connected { local_cid: 0x0040 })
+        );
+
+        // 300 byte SDU with a peer MPS of 247 is 2 K-frames.
+        let sdu: Vec<u8> = (0..300_u16).map(|i| i as u8).collect();
+        let channel = central.channel_mut(0x0040).unwrap();
+        channel.send(&sdu).unwrap();
+        let peer_cid = channel.peer_cid();
+        let mut frames = Vec::new();
+        while let Some(frame) = channel.next_frame() {
+            frames.push(frame);
+        }
+        assert_eq!(frames.len(), 2);
+        let receiver = peripheral.channel_mut(peer_cid).unwrap();
+        let first = BasicFrame::unpack_from(&frames[0]).unwrap();
+        assert_eq!(first.channel_id, peer_cid);
+        assert_eq!(receiver.receive(first.payload), Ok(None));
+        let second = BasicFrame::unpack_from(&frames[1]).unwrap();
+        assert_eq!(receiver.receive(second.payload), Ok(Some(sdu)));
+
+        let request = over_the_air(&central.reconfigure(&[0x0040, 0x0041], 200, 64).unwrap());
+        let response = over_the_air(&peripheral.handle_signal(&request).unwrap());
+        central.handle_signal(&response);
+        while let Some(event) = central.next_event() {
+            if let ChannelEvent::Reconfigured { result, .. } = event {
+                assert_eq!(result, ReconfigureResult::Successful);
+            }
+        }
+        assert_eq!(central.channel(0x0041).unwrap().local_mtu_mps(), (200, 64));
+        assert_eq!(
+            central.reconfigure(&[0x0040], 150, 64),
+            Err(ChannelError::InvalidParameters)
+        );
+    }
+    #[test]
+    fn test_lenient_receive() {
+        let mut central = CreditChannels::new(ChannelParameters::DEFAULT);
+        let mut peripheral = CreditChannels::new(ChannelParameters {
+            initial_credits: 1,
+            ..ChannelParameters::DEFAULT
+        });
+        peripheral.listen(EATT_SPSM);
+        let request = over_the_air(&central.connect_le(EATT_SPSM).unwrap());
+        let response = over_the_air(&peripheral.handle_signal(&request).unwrap());
+        central.handle_signal(&response);
+        let local_cid = peripheral.channels().next().unwrap().local_cid();
+
+        // Announces a 2 byte SDU, sends 3 bytes.
+        let frame = [0x02, 0x00, 0xAA, 0xBB, 0xCC];
+        let channel = peripheral.channel_mut(local_cid).unwrap();
+        assert_eq!(channel.receive(&frame), Err(ChannelError::BadSDULength));
+        peripheral.set_strictness(Strictness::Lenient);
+        let channel = peripheral.channel_mut(local_cid).unwrap();
+        // Out of credits as well.
+        assert_eq!(channel.receive(&frame), Ok(Some(vec![0xAA, 0xBB])));
+    }
+    #[test]
+    fn test_invalid_peer_mps() {
+        let mut central = CreditChannels::new(ChannelParameters::DEFAULT);
+        let request = central.connect_le(EATT_SPSM).unwrap();
+        let response = SignalPacket::new(
+            request.identifier,
+            Signal::LECreditBasedConnectionRsp {
+                destination_cid: 0x0050,
+                mtu: 64,
+                mps: 0,
+                initial_credits: 10,
+                result: ConnectionResult::Successful,
+            },
+        );
+        assert!(central.handle_signal(&over_the_air(&response)).is_none());
+        assert_eq!(central.channels().count(), 0);
+        assert_eq!(
+            central.next_event(),
+            Some(ChannelEvent::Refused {
+                spsm: EATT_SPSM,
+                count: 1,
+                result: ConnectionResult::UnacceptableParameters,
+            })
+        );
+        let disconnect = central.next_signal().unwrap();
+        assert_eq!(
+            disconnect.signal,
+            Signal::DisconnectionReq {
+                destination_cid: 0x0050,
+                source_cid: 0x0040,
+            }
+        );
+        // The CID stays taken until the peer confirms.
+        assert_eq!(central.allocate_cids(1), [0x0041]);
+        let confirm = SignalPacket::new(
+            disconnect.identifier,
+            Signal::DisconnectionRsp {
+                destination_cid: 0x0050,
+                source_cid: 0x0040,
+            },
+        );
+        assert!(central.handle_signal(&confirm).is_none());
+        assert_eq!(central.next_event(), None);
+
+        let request = central.connect(EATT_SPSM, 2).unwrap();
+        let response = SignalPacket::new(
+            request.identifier,
+            Signal::CreditBasedConnectionRsp {
+                mtu: 64,
+                mps: 1,
+                initial_credits: 10,
+                result: ConnectionResult::Successful,
+                destination_cids: vec![0x0050, 0x0051],
+            },
+        );
+        central.handle_signal(&over_the_air(&response));
+        assert_eq!(central.channels().count(), 0);
+        assert!(central.next_signal().is_some());
+        assert!(central.next_signal().is_some());
+        assert!(central.next_signal().is_none());
+    }
+}
@@ -0,0 +1,64 @@
+//! Logical Link Control and Adaptation Protocol (L2CAP) for LE. Every L2CAP frame starts with a
+//! basic header (payload length and channel id) and is carried in ACL data packets (see
+//! [`crate::hci::acl`]). [`signaling`] has the LE signaling channel PDUs and [`credit`] the
+//! credit based channels (LE Credit Based and Enhanced Credit Based Flow Control modes).
+pub mod credit;
+pub mod signaling;
+
+use crate::PackError;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// Payload length (2 bytes) and channel id (2 bytes).
+pub const BASIC_HEADER_LEN: usize = 4;
+/// Fixed channel ids on LE links.
+pub const ATT_CID: u16 = 0x0004;
+pub const LE_SIGNALING_CID: u16 = 0x0005;
+pub const SMP_CID: u16 = 0x0006;
+/// Dynamically allocated channel ids on LE links.
+pub const FIRST_DYNAMIC_CID: u16 = 0x0040;
+pub const LAST_DYNAMIC_CID: u16 = 0x007F;
+/// Simplified Protocol/Service Multiplexer of the Enhanced ATT bearer.
+pub const EATT_SPSM: u16 = 0x0027;
+
+/// L2CAP frame in Basic mode (B-frame). Credit based channels use it for their K-frames.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct BasicFrame<Buf> {
+    pub channel_id: u16,
+    pub payload: Buf,
+}
+impl<Buf: AsRef<[u8]>> BasicFrame<Buf> {
+    pub fn new(channel_id: u16, payload: Buf) -> Self {
+        BasicFrame {
+            channel_id,
+            payload,
+        }
+    }
+    pub fn pack(&self) -> Result<Vec<u8>, PackError> {
+        let payload = self.payload.as_ref();
+        let len = u16::try_from(payload.len()).map_err(|_| PackError::InvalidFields)?;
+        let mut out = Vec::with_capacity(BASIC_HEADER_LEN + payload.len());
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&self.channel_id.to_le_bytes());
+        out.extend_from_slice(payload);
+        Ok(out)
+    }
+}
+impl<'a> BasicFrame<&'a [u8]> {
+    /// `buf` has to hold exactly one complete frame.
+    pub fn unpack_from(buf: &'a [u8]) -> Result<Self, PackError> {
+        PackError::atleast_length(BASIC_HEADER_LEN, buf)?;
+        let (header, payload) = buf.split_at(BASIC_HEADER_LEN);
+        let len = usize::from(u16::from_le_bytes([header[0], header[1]]));
+        if len != payload.len() {
+            return Err(PackError::BadLength {
+                expected: BASIC_HEADER_LEN + len,
+                got: buf.len(),
+            });
+        }
+        Ok(BasicFrame {
+            channel_id: u16::from_le_bytes([header[2], header[3]]),
+            payload,
+        })
+    }
+}
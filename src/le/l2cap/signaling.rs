@@ -0,0 +1,403 @@
+//! LE signaling channel PDUs (Core Spec Vol 3, Part A, 4). Only the commands used by credit based
+//! channels are typed. Every signaling packet is `code, identifier, length, data`.
+use crate::{ConversionError, PackError};
+use alloc::vec::Vec;
+use core::convert::{TryFrom, TryInto};
+
+/// Code, identifier and data length.
+pub const SIGNAL_HEADER_LEN: usize = 4;
+/// Most channels one Credit Based Connection (or Reconfigure) Request can carry.
+pub const MAX_ECFC_CHANNELS: usize = 5;
+/// Smallest MTU and MPS of Enhanced Credit Based Flow Control channels.
+pub const MIN_ECFC_MTU: u16 = 64;
+/// Smallest MTU and MPS of LE Credit Based Flow Control channels.
+pub const MIN_LE_COC_MTU: u16 = 23;
+pub const MAX_MPS: u16 = 65533;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum SignalCode {
+    CommandRejectRsp = 0x01,
+    DisconnectionReq = 0x06,
+    DisconnectionRsp = 0x07,
+    LECreditBasedConnectionReq = 0x14,
+    LECreditBasedConnectionRsp = 0x15,
+    FlowControlCreditInd = 0x16,
+    CreditBasedConnectionReq = 0x17,
+    CreditBasedConnectionRsp = 0x18,
+    CreditBasedReconfigureReq = 0x19,
+    CreditBasedReconfigureRsp = 0x1A,
+}
+impl From<SignalCode> for u8 {
+    fn from(c: SignalCode) -> Self {
+        c as u8
+    }
+}
+impl TryFrom<u8> for SignalCode {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(SignalCode::CommandRejectRsp),
+            0x06 => Ok(SignalCode::DisconnectionReq),
+            0x07 => Ok(SignalCode::DisconnectionRsp),
+            0x14 => Ok(SignalCode::LECreditBasedConnectionReq),
+            0x15 => Ok(SignalCode::LECreditBasedConnectionRsp),
+            0x16 => Ok(SignalCode::FlowControlCreditInd),
+            0x17 => Ok(SignalCode::CreditBasedConnectionReq),
+            0x18 => Ok(SignalCode::CreditBasedConnectionRsp),
+            0x19 => Ok(SignalCode::CreditBasedReconfigureReq),
+            0x1A => Ok(SignalCode::CreditBasedReconfigureRsp),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// Result of a (LE) Credit Based Connection Request.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ConnectionResult {
+    Successful = 0x0000,
+    SPSMNotSupported = 0x0002,
+    /// Some (or all) of the channels were refused.
+    NoResourcesAvailable = 0x0004,
+    InsufficientAuthentication = 0x0005,
+    InsufficientAuthorization = 0x0006,
+    EncryptionKeySizeTooShort = 0x0007,
+    InsufficientEncryption = 0x0008,
+    InvalidSourceCID = 0x0009,
+    SourceCIDAlreadyAllocated = 0x000A,
+    UnacceptableParameters = 0x000B,
+    InvalidParameters = 0x000C,
+}
+impl From<ConnectionResult> for u16 {
+    fn from(r: ConnectionResult) -> Self {
+        r as u16
+    }
+}
+impl TryFrom<u16> for ConnectionResult {
+    type Error = ConversionError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0x0000 => Ok(ConnectionResult::Successful),
+            0x0002 => Ok(ConnectionResult::SPSMNotSupported),
+            0x0004 => Ok(ConnectionResult::NoResourcesAvailable),
+            0x0005 => Ok(ConnectionResult::InsufficientAuthentication),
+            0x0006 => Ok(ConnectionResult::InsufficientAuthorization),
+            0x0007 => Ok(ConnectionResult::EncryptionKeySizeTooShort),
+            0x0008 => Ok(ConnectionResult::InsufficientEncryption),
+            0x0009 => Ok(ConnectionResult::InvalidSourceCID),
+            0x000A => Ok(ConnectionResult::SourceCIDAlreadyAllocated),
+            0x000B => Ok(ConnectionResult::UnacceptableParameters),
+            0x000C => Ok(ConnectionResult::InvalidParameters),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// Result of a Credit Based Reconfigure Request.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ReconfigureResult {
+    Successful = 0x0000,
+    MTUReductionNotAllowed = 0x0001,
+    MPSReductionNotAllowed = 0x0002,
+    InvalidDestinationCID = 0x0003,
+    UnacceptableParameters = 0x0004,
+}
+impl From<ReconfigureResult> for u16 {
+    fn from(r: ReconfigureResult) -> Self {
+        r as u16
+    }
+}
+impl TryFrom<u16> for ReconfigureResult {
+    type Error = ConversionError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0x0000 => Ok(ReconfigureResult::Successful),
+            0x0001 => Ok(ReconfigureResult::MTUReductionNotAllowed),
+            0x0002 => Ok(ReconfigureResult::MPSReductionNotAllowed),
+            0x0003 => Ok(ReconfigureResult::InvalidDestinationCID),
+            0x0004 => Ok(ReconfigureResult::UnacceptableParameters),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// Command Reject reasons.
+pub const REJECT_NOT_UNDERSTOOD: u16 = 0x0000;
+pub const REJECT_INVALID_CID: u16 = 0x0002;
+
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum Signal {
+    CommandRejectRsp {
+        reason: u16,
+        data: Vec<u8>,
+    },
+    DisconnectionReq {
+        destination_cid: u16,
+        source_cid: u16,
+    },
+    DisconnectionRsp {
+        destination_cid: u16,
+        source_cid: u16,
+    },
+    LECreditBasedConnectionReq {
+        spsm: u16,
+        source_cid: u16,
+        mtu: u16,
+        mps: u16,
+        initial_credits: u16,
+    },
+    LECreditBasedConnectionRsp {
+        destination_cid: u16,
+        mtu: u16,
+        mps: u16,
+        initial_credits: u16,
+        result: ConnectionResult,
+    },
+    FlowControlCreditInd {
+        cid: u16,
+        credits: u16,
+    },
+    CreditBasedConnectionReq {
+        spsm: u16,
+        mtu: u16,
+        mps: u16,
+        initial_credits: u16,
+        /// 1 to [`MAX_ECFC_CHANNELS`] channels.
+        source_cids: Vec<u16>,
+    },
+    CreditBasedConnectionRsp {
+        mtu: u16,
+        mps: u16,
+        initial_credits: u16,
+        result: ConnectionResult,
+        /// `0` for every refused channel.
+        destination_cids: Vec<u16>,
+    },
+    CreditBasedReconfigureReq {
+        mtu: u16,
+        mps: u16,
+        destination_cids: Vec<u16>,
+    },
+    CreditBasedReconfigureRsp {
+        result: ReconfigureResult,
+    },
+}
+fn u16_at(data: &[u8], index: usize) -> Result<u16, PackError> {
+    data.get(index..index + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or(PackError::bad_index(SIGNAL_HEADER_LEN + index))
+}
+fn cids(data: &[u8]) -> Result<Vec<u16>, PackError> {
+    if data.is_empty() || data.len() % 2 != 0 || data.len() / 2 > MAX_ECFC_CHANNELS {
+        return Err(PackError::InvalidFields);
+    }
+    Ok(data
+        .chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .collect())
+}
+impl Signal {
+    pub fn code(&self) -> SignalCode {
+        match self {
+            Signal::CommandRejectRsp { .. } => SignalCode::CommandRejectRsp,
+            Signal::DisconnectionReq { .. } => SignalCode::DisconnectionReq,
+            Signal::DisconnectionRsp { .. } => SignalCode::DisconnectionRsp,
+            Signal::LECreditBasedConnectionReq { .. } => SignalCode::LECreditBasedConnectionReq,
+            Signal::LECreditBasedConnectionRsp { .. } => SignalCode::LECreditBasedConnectionRsp,
+            Signal::FlowControlCreditInd { .. } => SignalCode::FlowControlCreditInd,
+            Signal::CreditBasedConnectionReq { .. } => SignalCode::CreditBasedConnectionReq,
+            Signal::CreditBasedConnectionRsp { .. } => SignalCode::CreditBasedConnectionRsp,
+            Signal::CreditBasedReconfigureReq { .. } => SignalCode::CreditBasedReconfigureReq,
+            Signal::CreditBasedReconfigureRsp { .. } => SignalCode::CreditBasedReconfigureRsp,
+        }
+    }
+    fn pack_data(&self, out: &mut Vec<u8>) {
+        let mut put = |values: &[u16]| {
+            for value in values {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+        };
+        match self {
+            Signal::CommandRejectRsp { reason, data } => {
+                put(&[*reason]);
+                out.extend_from_slice(data);
+            }
+            Signal::DisconnectionReq {
+                destination_cid,
+                source_cid,
+            }
+            | Signal::DisconnectionRsp {
+                destination_cid,
+                source_cid,
+            } => put(&[*destination_cid, *source_cid]),
+            Signal::LECreditBasedConnectionReq {
+                spsm,
+                source_cid,
+                mtu,
+                mps,
+                initial_credits,
+            } => put(&[*spsm, *source_cid, *mtu, *mps, *initial_credits]),
+            Signal::LECreditBasedConnectionRsp {
+                destination_cid,
+                mtu,
+                mps,
+                initial_credits,
+                result,
+            } => put(&[
+                *destination_cid,
+                *mtu,
+                *mps,
+                *initial_credits,
+                u16::from(*result),
+            ]),
+            Signal::FlowControlCreditInd { cid, credits } => put(&[*cid, *credits]),
+            Signal::CreditBasedConnectionReq {
+                spsm,
+                mtu,
+                mps,
+                initial_credits,
+                source_cids,
+            } => {
+                put(&[*spsm, *mtu, *mps, *initial_credits]);
+                put(source_cids);
+            }
+            Signal::CreditBasedConnectionRsp {
+                mtu,
+                mps,
+                initial_credits,
+                result,
+                destination_cids,
+            } => {
+                put(&[*mtu, *mps, *initial_credits, u16::from(*result)]);
+                put(destination_cids);
+            }
+            Signal::CreditBasedReconfigureReq {
+                mtu,
+                mps,
+                destination_cids,
+            } => {
+                put(&[*mtu, *mps]);
+                put(destination_cids);
+            }
+            Signal::CreditBasedReconfigureRsp { result } => put(&[u16::from(*result)]),
+        }
+    }
+    fn unpack_data(code: SignalCode, data: &[u8]) -> Result<Signal, PackError> {
+        let expect = |len: usize| PackError::expect_length(len, data);
+        let result = |value: u16| {
+            ConnectionResult::try_from(value).map_err(|_| PackError::bad_index(SIGNAL_HEADER_LEN))
+        };
+        Ok(match code {
+            SignalCode::CommandRejectRsp => Signal::CommandRejectRsp {
+                reason: u16_at(data, 0)?,
+                data: data[2..].to_vec(),
+            },
+            SignalCode::DisconnectionReq => {
+                expect(4)?;
+                Signal::DisconnectionReq {
+                    destination_cid: u16_at(data, 0)?,
+                    source_cid: u16_at(data, 2)?,
+                }
+            }
+            SignalCode::DisconnectionRsp => {
+                expect(4)?;
+                Signal::DisconnectionRsp {
+                    destination_cid: u16_at(data, 0)?,
+                    source_cid: u16_at(data, 2)?,
+                }
+            }
+            SignalCode::LECreditBasedConnectionReq => {
+                expect(10)?;
+                Signal::LECreditBasedConnectionReq {
+                    spsm: u16_at(data, 0)?,
+                    source_cid: u16_at(data, 2)?,
+                    mtu: u16_at(data, 4)?,
+                    mps: u16_at(data, 6)?,
+                    initial_credits: u16_at(data, 8)?,
+                }
+            }
+            SignalCode::LECreditBasedConnectionRsp => {
+                expect(10)?;
+                Signal::LECreditBasedConnectionRsp {
+                    destination_cid: u16_at(data, 0)?,
+                    mtu: u16_at(data, 2)?,
+                    mps: u16_at(data, 4)?,
+                    initial_credits: u16_at(data, 6)?,
+                    result: result(u16_at(data, 8)?)?,
+                }
+            }
+            SignalCode::FlowControlCreditInd => {
+                expect(4)?;
+                Signal::FlowControlCreditInd {
+                    cid: u16_at(data, 0)?,
+                    credits: u16_at(data, 2)?,
+                }
+            }
+            SignalCode::CreditBasedConnectionReq => Signal::CreditBasedConnectionReq {
+                spsm: u16_at(data, 0)?,
+                mtu: u16_at(data, 2)?,
+                mps: u16_at(data, 4)?,
+                initial_credits: u16_at(data, 6)?,
+                source_cids: cids(&data[8..])?,
+            },
+            SignalCode::CreditBasedConnectionRsp => Signal::CreditBasedConnectionRsp {
+                mtu: u16_at(data, 0)?,
+                mps: u16_at(data, 2)?,
+                initial_credits: u16_at(data, 4)?,
+                result: result(u16_at(data, 6)?)?,
+                destination_cids: cids(&data[8..])?,
+            },
+            SignalCode::CreditBasedReconfigureReq => Signal::CreditBasedReconfigureReq {
+                mtu: u16_at(data, 0)?,
+                mps: u16_at(data, 2)?,
+                destination_cids: cids(&data[4..])?,
+            },
+            SignalCode::CreditBasedReconfigureRsp => {
+                expect(2)?;
+                Signal::CreditBasedReconfigureRsp {
+                    result: ReconfigureResult::try_from(u16_at(data, 0)?)
+                        .map_err(|_| PackError::bad_index(SIGNAL_HEADER_LEN))?,
+                }
+            }
+        })
+    }
+}
+/// One command on the LE signaling channel.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct SignalPacket {
+    /// Matches responses to requests. Never `0`.
+    pub identifier: u8,
+    pub signal: Signal,
+}
+impl SignalPacket {
+    pub fn new(identifier: u8, signal: Signal) -> SignalPacket {
+        SignalPacket { identifier, signal }
+    }
+    pub fn pack(&self) -> Vec<u8> {
+        let mut out = vec![self.signal.code().into(), self.identifier, 0, 0];
+        self.signal.pack_data(&mut out);
+        let len: u16 = (out.len() - SIGNAL_HEADER_LEN)
+            .try_into()
+            .expect("signals are shorter than 64k");
+        out[2..4].copy_from_slice(&len.to_le_bytes());
+        out
+    }
+    /// Unpacks the signaling packet at the start of `buf`. Returns it and its length (an LE
+    /// signaling frame holds exactly one). Errors with `BadOpcode` on unknown codes (answer them
+    /// with a Command Reject).
+    pub fn unpack_from(buf: &[u8]) -> Result<(SignalPacket, usize), PackError> {
+        PackError::atleast_length(SIGNAL_HEADER_LEN, buf)?;
+        let code = SignalCode::try_from(buf[0]).map_err(|_| PackError::BadOpcode)?;
+        let len = SIGNAL_HEADER_LEN + usize::from(u16::from_le_bytes([buf[2], buf[3]]));
+        let data = buf
+            .get(SIGNAL_HEADER_LEN..len)
+            .ok_or(PackError::BadLength {
+                expected: len,
+                got: buf.len(),
+            })?;
+        let packet = SignalPacket {
+            identifier: buf[1],
+            signal: Signal::unpack_data(code, data)?,
+        };
+        Ok((packet, len))
+    }
+}
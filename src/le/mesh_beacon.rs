@@ -0,0 +1,306 @@
+//! Bluetooth Mesh beacons (Mesh Profile 3.9) sent in the Mesh Beacon AD type. Unprovisioned
+//! devices announce themselves with an [`UnprovisionedDeviceBeacon`] and provisioned nodes tell
+//! the network its IV Index and Key Refresh state with an authenticated [`SecureNetworkBeacon`].
+//!
+//! [`broadcast`] advertises a beacon with any [`Advertiser`] and [`beacons`] picks the beacons out
+//! of an observed advertisement. Multi-octet fields are big endian like everywhere in Mesh.
+use crate::hci::adapter;
+use crate::le::advertisement::{
+    AdStructureType, AdType, ConstAdStructType, RawAdvertisement, UnpackableAdStructType,
+};
+use crate::le::advertiser::{
+    Advertiser, AdvertisingInterval, AdvertisingParameters, AdvertisingType,
+};
+use crate::le::crypto::{aes_cmac, k1, s1, Key, KEY_LEN};
+use crate::uuid::UUID;
+use crate::PackError;
+use core::convert::TryFrom;
+
+pub const UNPROVISIONED_DEVICE_BEACON: u8 = 0x00;
+pub const SECURE_NETWORK_BEACON: u8 = 0x01;
+pub const NETWORK_ID_LEN: usize = 8;
+pub const AUTHENTICATION_VALUE_LEN: usize = 8;
+/// Interval Mesh Profile 3.9.3.1 recommends for Secure Network beacons on an advertising bearer.
+pub const BEACON_INTERVAL: AdvertisingInterval = AdvertisingInterval::MIN_NON_CONN;
+
+/// Flags of a [`SecureNetworkBeacon`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct BeaconFlags {
+    /// The network is in Key Refresh phase 2.
+    pub key_refresh: bool,
+    /// An IV Update is in progress.
+    pub iv_update: bool,
+}
+impl BeaconFlags {
+    const KEY_REFRESH: u8 = 0x01;
+    const IV_UPDATE: u8 = 0x02;
+}
+impl From<BeaconFlags> for u8 {
+    fn from(flags: BeaconFlags) -> Self {
+        let mut out = 0;
+        if flags.key_refresh {
+            out |= BeaconFlags::KEY_REFRESH;
+        }
+        if flags.iv_update {
+            out |= BeaconFlags::IV_UPDATE;
+        }
+        out
+    }
+}
+impl TryFrom<u8> for BeaconFlags {
+    type Error = PackError;
+
+    /// Rejects the prohibited bits.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        if value & !(BeaconFlags::KEY_REFRESH | BeaconFlags::IV_UPDATE) != 0 {
+            return Err(PackError::bad_index(1));
+        }
+        Ok(BeaconFlags {
+            key_refresh: value & BeaconFlags::KEY_REFRESH != 0,
+            iv_update: value & BeaconFlags::IV_UPDATE != 0,
+        })
+    }
+}
+/// Sent by devices waiting to be provisioned.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct UnprovisionedDeviceBeacon {
+    /// Sent most significant octet first.
+    pub device_uuid: UUID,
+    /// OOB Information bit field (Mesh Profile 3.9.2).
+    pub oob_information: u16,
+    /// First 4 octets of `s1` of the URI the device advertises, if it does.
+    pub uri_hash: Option<[u8; 4]>,
+}
+impl UnprovisionedDeviceBeacon {
+    pub const MIN_BYTE_LEN: usize = 1 + 16 + 2;
+    pub fn byte_len(&self) -> usize {
+        Self::MIN_BYTE_LEN + self.uri_hash.map_or(0, |hash| hash.len())
+    }
+}
+/// Sent by nodes to spread the IV Index and Key Refresh state of a subnet.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct SecureNetworkBeacon {
+    pub flags: BeaconFlags,
+    pub network_id: [u8; NETWORK_ID_LEN],
+    pub iv_index: u32,
+    pub authentication_value: [u8; AUTHENTICATION_VALUE_LEN],
+}
+impl SecureNetworkBeacon {
+    pub const BYTE_LEN: usize = 1 + 1 + NETWORK_ID_LEN + 4 + AUTHENTICATION_VALUE_LEN;
+    /// Beacon authenticated with `beacon_key` (see [`beacon_key`]).
+    pub fn new(
+        beacon_key: &Key,
+        flags: BeaconFlags,
+        network_id: [u8; NETWORK_ID_LEN],
+        iv_index: u32,
+    ) -> SecureNetworkBeacon {
+        let mut beacon = SecureNetworkBeacon {
+            flags,
+            network_id,
+            iv_index,
+            authentication_value: [0; AUTHENTICATION_VALUE_LEN],
+        };
+        beacon.authentication_value = beacon.expected_authentication(beacon_key);
+        beacon
+    }
+    fn expected_authentication(&self, beacon_key: &Key) -> [u8; AUTHENTICATION_VALUE_LEN] {
+        let mut message = [0_u8; 1 + NETWORK_ID_LEN + 4];
+        message[0] = self.flags.into();
+        message[1..1 + NETWORK_ID_LEN].copy_from_slice(&self.network_id);
+        message[1 + NETWORK_ID_LEN..].copy_from_slice(&self.iv_index.to_be_bytes());
+        let mac = aes_cmac(beacon_key, &message);
+        let mut out = [0_u8; AUTHENTICATION_VALUE_LEN];
+        out.copy_from_slice(&mac[..AUTHENTICATION_VALUE_LEN]);
+        out
+    }
+    /// Checks the authentication value. Observed beacons must pass before their IV Index or flags
+    /// are used.
+    pub fn authenticate(&self, beacon_key: &Key) -> bool {
+        self.expected_authentication(beacon_key) == self.authentication_value
+    }
+}
+/// Beacon key of the subnet with `net_key` (`k1(NetKey, s1("nkbk"), "id128" || 0x01)`).
+pub fn beacon_key(net_key: &Key) -> Key {
+    k1(net_key, &s1(b"nkbk"), b"id128\x01")
+}
+/// Network ID of the subnet with `net_key` (`k3(NetKey)`).
+pub fn network_id(net_key: &Key) -> [u8; NETWORK_ID_LEN] {
+    let t = k1(net_key, &s1(b"smk3"), b"id64\x01");
+    let mut out = [0_u8; NETWORK_ID_LEN];
+    out.copy_from_slice(&t[KEY_LEN - NETWORK_ID_LEN..]);
+    out
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum MeshBeacon {
+    Unprovisioned(UnprovisionedDeviceBeacon),
+    SecureNetwork(SecureNetworkBeacon),
+}
+impl MeshBeacon {
+    pub const AD_TYPE: AdType = AdType::MeshBeacon;
+    pub fn beacon_type(&self) -> u8 {
+        match self {
+            MeshBeacon::Unprovisioned(_) => UNPROVISIONED_DEVICE_BEACON,
+            MeshBeacon::SecureNetwork(_) => SECURE_NETWORK_BEACON,
+        }
+    }
+}
+impl AdStructureType for MeshBeacon {
+    fn ad_type(&self) -> AdType {
+        Self::AD_TYPE
+    }
+
+    fn byte_len(&self) -> usize {
+        match self {
+            MeshBeacon::Unprovisioned(beacon) => beacon.byte_len(),
+            MeshBeacon::SecureNetwork(_) => SecureNetworkBeacon::BYTE_LEN,
+        }
+    }
+
+    fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(self.byte_len(), buf)?;
+        buf[0] = self.beacon_type();
+        match self {
+            MeshBeacon::Unprovisioned(beacon) => {
+                let mut uuid = beacon.device_uuid.0;
+                uuid.reverse();
+                buf[1..17].copy_from_slice(&uuid);
+                buf[17..19].copy_from_slice(&beacon.oob_information.to_be_bytes());
+                if let Some(hash) = beacon.uri_hash {
+                    buf[19..].copy_from_slice(&hash);
+                }
+            }
+            MeshBeacon::SecureNetwork(beacon) => {
+                buf[1] = beacon.flags.into();
+                buf[2..10].copy_from_slice(&beacon.network_id);
+                buf[10..14].copy_from_slice(&beacon.iv_index.to_be_bytes());
+                buf[14..].copy_from_slice(&beacon.authentication_value);
+            }
+        }
+        Ok(())
+    }
+}
+impl UnpackableAdStructType for MeshBeacon {
+    fn unpack_from(ad_type: AdType, buf: &[u8]) -> Result<Self, PackError>
+    where
+        Self: Sized,
+    {
+        if ad_type != Self::AD_TYPE {
+            return Err(PackError::InvalidFields);
+        }
+        match buf.first() {
+            Some(&UNPROVISIONED_DEVICE_BEACON) => {
+                let uri_hash = match buf.len() {
+                    UnprovisionedDeviceBeacon::MIN_BYTE_LEN => None,
+                    len if len == UnprovisionedDeviceBeacon::MIN_BYTE_LEN + 4 => {
+                        Some([buf[19], buf[20], buf[21], buf[22]])
+                    }
+                    _ => {
+                        return Err(PackError::BadLength {
+                            expected: UnprovisionedDeviceBeacon::MIN_BYTE_LEN,
+                            got: buf.len(),
+                        })
+                    }
+                };
+                let mut uuid = [0_u8; 16];
+                uuid.copy_from_slice(&buf[1..17]);
+                uuid.reverse();
+                Ok(MeshBeacon::Unprovisioned(UnprovisionedDeviceBeacon {
+                    device_uuid: UUID(uuid),
+                    oob_information: u16::from_be_bytes([buf[17], buf[18]]),
+                    uri_hash,
+                }))
+            }
+            Some(&SECURE_NETWORK_BEACON) => {
+                PackError::expect_length(SecureNetworkBeacon::BYTE_LEN, buf)?;
+                let mut network_id = [0_u8; NETWORK_ID_LEN];
+                network_id.copy_from_slice(&buf[2..10]);
+                let mut authentication_value = [0_u8; AUTHENTICATION_VALUE_LEN];
+                authentication_value.copy_from_slice(&buf[14..]);
+                Ok(MeshBeacon::SecureNetwork(SecureNetworkBeacon {
+                    flags: BeaconFlags::try_from(buf[1])?,
+                    network_id,
+                    iv_index: u32::from_be_bytes([buf[10], buf[11], buf[12], buf[13]]),
+                    authentication_value,
+                }))
+            }
+            Some(_) => Err(PackError::BadOpcode),
+            None => Err(PackError::bad_index(0)),
+        }
+    }
+}
+impl ConstAdStructType for MeshBeacon {
+    const AD_TYPE: AdType = AdType::MeshBeacon;
+}
+/// Well formed mesh beacons in `advertisement`.
+pub fn beacons<Buf: AsRef<[u8]>>(
+    advertisement: &RawAdvertisement<Buf>,
+) -> impl Iterator<Item = MeshBeacon> + '_ {
+    advertisement
+        .iter()
+        .filter(|ad_struct| ad_struct.ad_type == AdType::MeshBeacon)
+        .filter_map(|ad_struct| {
+            MeshBeacon::unpack_from(ad_struct.ad_type, ad_struct.buf.as_ref()).ok()
+        })
+}
+/// Advertises `beacon` non-connectable every `interval` (mesh beacons are sent alone).
+/// # Errors
+/// returns the adapter errors and `BadParameter` if the beacon doesn't fit.
+pub async fn broadcast<A: Advertiser>(
+    advertiser: &mut A,
+    beacon: &MeshBeacon,
+    interval: AdvertisingInterval,
+) -> Result<(), adapter::Error> {
+    let mut advertisement = RawAdvertisement::new();
+    advertisement
+        .insert(beacon)
+        .map_err(|_| adapter::Error::BadParameter)?;
+    let parameters = AdvertisingParameters {
+        advertising_type: AdvertisingType::AdvNonnConnInd,
+        ..AdvertisingParameters::DEFAULT
+    }
+    .with_interval(interval, interval);
+    advertiser.set_advertising_enable(false).await?;
+    advertiser.set_advertising_parameters(parameters).await?;
+    advertiser
+        .set_advertising_data(advertisement.as_ref())
+        .await?;
+    advertiser.set_advertising_enable(true).await
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secure_network_beacon() {
+        // Mesh Profile 8.2.2 and 8.4.
+        let net_key = [
+            0x7d, 0xd7, 0x36, 0x4c, 0xd8, 0x42, 0xad, 0x18, 0xc1, 0x7c, 0x2b, 0x82, 0x0c, 0x84,
+            0xc3, 0xd6,
+        ];
+        let network_id = network_id(&net_key);
+        assert_eq!(network_id, [0x3e, 0xca, 0xff, 0x67, 0x2f, 0x67, 0x33, 0x70]);
+        let key = beacon_key(&net_key);
+        let beacon = SecureNetworkBeacon::new(&key, BeaconFlags::default(), network_id, 0x12345678);
+        let mut advertisement = RawAdvertisement::new();
+        advertisement
+            .insert(&MeshBeacon::SecureNetwork(beacon))
+            .unwrap();
+        assert_eq!(
+            advertisement.as_ref(),
+            [
+                0x17, 0x2B, 0x01, 0x00, 0x3e, 0xca, 0xff, 0x67, 0x2f, 0x67, 0x33, 0x70, 0x12, 0x34,
+                0x56, 0x78, 0x8e, 0xa2, 0x61, 0x58, 0x2f, 0x36, 0x4f, 0x6f
+            ]
+        );
+        let observed: Vec<MeshBeacon> = beacons(&advertisement).collect();
+        match observed.as_slice() {
+            [MeshBeacon::SecureNetwork(observed)] => {
+                assert!(observed.authenticate(&key));
+                assert!(!observed.authenticate(&net_key));
+            }
+            other => panic!("expected a secure network beacon, got {:?}", other),
+        }
+        assert!(BeaconFlags::try_from(0x03).is_ok());
+        assert!(BeaconFlags::try_from(0x04).is_err());
+    }
+}
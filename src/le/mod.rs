@@ -1,10 +1,25 @@
 pub mod adapter;
+pub mod adaptive_scan;
 pub mod advertisement;
 pub mod advertisement_structures;
 pub mod advertiser;
 pub mod att;
+pub mod audio;
 pub mod connection;
+pub mod crypto;
+pub mod device_cache;
+pub mod eid;
 pub mod gatt;
+pub mod identity;
+pub mod l2cap;
 pub mod link;
+pub mod mesh_beacon;
+pub mod privacy;
 pub mod report;
+pub mod report_coalesce;
+pub mod report_sink;
 pub mod scan;
+pub mod scan_record;
+pub mod service_filter;
+pub mod smp;
+pub mod whitelist;
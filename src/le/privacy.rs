@@ -0,0 +1,201 @@
+//! Host side Resolvable Private Address resolution. Used when the controller doesn't support LL
+//! Privacy so reports of privacy enabled peers still carry their identity address like the
+//! controller would report them.
+use crate::hci::le::features::LEFeatures;
+use crate::le::advertiser::PeerAddressType;
+use crate::le::crypto;
+use crate::le::report::{AddressType, ReportInfo};
+use crate::le::scan::OwnAddressType;
+use crate::BTAddress;
+use alloc::vec::Vec;
+
+/// Identity Resolving Key. Little endian (like SMP and HCI send it).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Default)]
+pub struct IdentityResolvingKey(pub [u8; crypto::KEY_LEN]);
+crypto::key_material!(IdentityResolvingKey);
+impl IdentityResolvingKey {
+    pub const BYTE_LEN: usize = crypto::KEY_LEN;
+    /// Random address hash of `prand` (24-bit).
+    pub fn hash(&self, prand: u32) -> u32 {
+        let mut key = self.0;
+        key.reverse();
+        let hash = crypto::ah(&key, prand);
+        crypto::wipe(&mut key);
+        hash
+    }
+    /// Returns `true` if `address` is a resolvable private address generated from this key.
+    pub fn resolves(&self, address: BTAddress) -> bool {
+        match address.private_address_parts() {
+            Some((hash, prand)) => self.hash(prand) == hash,
+            None => false,
+        }
+    }
+}
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ResolvingListEntry {
+    pub identity_address_type: PeerAddressType,
+    pub identity_address: BTAddress,
+    pub peer_irk: IdentityResolvingKey,
+}
+impl ResolvingListEntry {
+    /// Address type the controller reports for a resolved address.
+    pub fn report_address_type(&self) -> AddressType {
+        match self.identity_address_type {
+            PeerAddressType::Public => AddressType::PublicIdentity,
+            PeerAddressType::Random => AddressType::RandomIdentity,
+        }
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum OwnAddressError {
+    /// `Random`/`PrivateOrRandom` without a random address set.
+    RandomAddressNotSet,
+    /// `PrivateOrPublic`/`PrivateOrRandom` without a local IRK (the controller would silently
+    /// use the fallback address).
+    LocalIRKNotSet,
+}
+impl core::fmt::Display for OwnAddressError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "own address type not configured {:?}", self)
+    }
+}
+impl crate::error::Error for OwnAddressError {}
+#[cfg(feature = "std")]
+impl std::error::Error for OwnAddressError {}
+/// Local addresses configured in the controller. Checked against the [`OwnAddressType`] of
+/// scanning, advertising and connection commands.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct LocalAddresses {
+    /// Set with LE Set Random Address.
+    pub random_address: Option<BTAddress>,
+    /// The controller's resolving list has a local IRK.
+    pub local_irk: bool,
+}
+impl LocalAddresses {
+    /// Takes the local IRK from the host copy of the resolving list.
+    pub fn set_resolving_list(&mut self, resolving_list: &ResolvingList) {
+        self.local_irk = resolving_list.local_irk().is_some();
+    }
+    pub fn check(&self, own_address_type: OwnAddressType) -> Result<(), OwnAddressError> {
+        if own_address_type.needs_local_irk() && !self.local_irk {
+            Err(OwnAddressError::LocalIRKNotSet)
+        } else if own_address_type.needs_random_address() && self.random_address.is_none() {
+            Err(OwnAddressError::RandomAddressNotSet)
+        } else {
+            Ok(())
+        }
+    }
+}
+/// Host copy of the resolving list.
+#[derive(Clone, Debug, Default)]
+pub struct ResolvingList {
+    entries: Vec<ResolvingListEntry>,
+    local_irk: Option<IdentityResolvingKey>,
+    host_resolution: bool,
+}
+impl ResolvingList {
+    pub fn new(local_irk: Option<IdentityResolvingKey>, host_resolution: bool) -> ResolvingList {
+        ResolvingList {
+            entries: Vec::new(),
+            local_irk,
+            host_resolution,
+        }
+    }
+    /// Enables host resolution if the controller doesn't support LL Privacy.
+    pub fn set_controller_features(&mut self, features: LEFeatures) {
+        self.host_resolution = !features.ll_privacy();
+    }
+    pub fn set_host_resolution(&mut self, host_resolution: bool) {
+        self.host_resolution = host_resolution;
+    }
+    pub fn host_resolution(&self) -> bool {
+        self.host_resolution
+    }
+    pub fn set_local_irk(&mut self, local_irk: Option<IdentityResolvingKey>) {
+        self.local_irk = local_irk;
+    }
+    pub fn local_irk(&self) -> Option<&IdentityResolvingKey> {
+        self.local_irk.as_ref()
+    }
+    /// Adds `entry`, replacing any entry with the same identity address.
+    pub fn add(&mut self, entry: ResolvingListEntry) {
+        self.remove(entry.identity_address_type, entry.identity_address);
+        self.entries.push(entry);
+    }
+    pub fn remove(
+        &mut self,
+        identity_address_type: PeerAddressType,
+        identity_address: BTAddress,
+    ) -> Option<ResolvingListEntry> {
+        let index = self.entries.iter().position(|e| {
+            e.identity_address_type == identity_address_type
+                && e.identity_address == identity_address
+        })?;
+        Some(self.entries.remove(index))
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+    pub fn entries(&self) -> &[ResolvingListEntry] {
+        &self.entries[..]
+    }
+    /// Finds the entry whose IRK resolves `address`.
+    pub fn resolve(&self, address: BTAddress) -> Option<&ResolvingListEntry> {
+        let (hash, prand) = address.private_address_parts()?;
+        self.entries.iter().find(|e| e.peer_irk.hash(prand) == hash)
+    }
+    /// Replaces the address of `report` with the identity address if host resolution is enabled
+    /// and the address resolves. Returns `true` if the report was changed.
+    pub fn resolve_report<T>(&self, report: &mut ReportInfo<T>) -> bool {
+        if !self.host_resolution || report.address_type != AddressType::RandomDevice {
+            return false;
+        }
+        match self.resolve(report.address) {
+            Some(entry) => {
+                report.address_type = entry.report_address_type();
+                report.address = entry.identity_address;
+                true
+            }
+            None => false,
+        }
+    }
+    /// Returns `true` if the target address of a directed advertisement is this device. `identity`
+    /// is the local identity address, RPAs are checked against the local IRK.
+    pub fn is_local_target(&self, target: BTAddress, identity: BTAddress) -> bool {
+        if target == identity {
+            return true;
+        }
+        match &self.local_irk {
+            Some(irk) => irk.resolves(target),
+            None => false,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_own_address_check() {
+        let mut addresses = LocalAddresses::default();
+        assert_eq!(addresses.check(OwnAddressType::Public), Ok(()));
+        assert_eq!(
+            addresses.check(OwnAddressType::Random),
+            Err(OwnAddressError::RandomAddressNotSet)
+        );
+        assert_eq!(
+            addresses.check(OwnAddressType::PrivateOrPublic),
+            Err(OwnAddressError::LocalIRKNotSet)
+        );
+        addresses.set_resolving_list(&ResolvingList::new(
+            Some(IdentityResolvingKey([0x11; crypto::KEY_LEN])),
+            false,
+        ));
+        assert_eq!(addresses.check(OwnAddressType::PrivateOrPublic), Ok(()));
+        assert_eq!(
+            addresses.check(OwnAddressType::PrivateOrRandom),
+            Err(OwnAddressError::RandomAddressNotSet)
+        );
+        addresses.random_address = Some(BTAddress::from_u64(0xC0_11_22_33_44_55));
+        assert_eq!(addresses.check(OwnAddressType::PrivateOrRandom), Ok(()));
+    }
+}
@@ -153,8 +153,9 @@ impl<T: AsRef<[u8]> + Default> Default for ReportInfo<T> {
 }
 impl<T: AsRef<[u8]>> ReportInfo<T> {
     pub fn byte_len(&self) -> usize {
-        // event_type (1) + address_type (1) + address (6) + data (data.len()) + rssi (1)
-        1 + 1 + BT_ADDRESS_LEN + self.data.as_ref().len() + 1
+        // event_type (1) + address_type (1) + address (6) + data_len (1) + data (data.len()) +
+        // rssi (1)
+        1 + 1 + BT_ADDRESS_LEN + 1 + self.data.as_ref().len() + 1
     }
     pub fn as_ref(&self) -> ReportInfo<&[u8]> {
         ReportInfo {
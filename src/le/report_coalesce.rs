@@ -0,0 +1,166 @@
+//! Coalescing advertising reports. In a dense RF environment every report waking the scanning
+//! task costs more CPU than handling the report. [`Coalesce`] wraps a report stream (like
+//! [`Observer::advertisement_stream`](crate::le::scan::Observer::advertisement_stream)) and
+//! yields the reports arriving within a window as one `Vec`. While a window is open the report
+//! stream isn't polled so it can't wake the task, only the [`Timer`] does. Use
+//! [`StreamExt::for_each`](futures_util::stream::StreamExt::for_each) for a batch callback.
+use crate::hci::timer::Timer;
+use crate::{LocalBoxFuture, Stream};
+use alloc::vec::Vec;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use futures_util::future::FutureExt;
+use futures_util::stream::FusedStream;
+
+/// Batches of the items of `reports`. A window opens with the first item after a batch and every
+/// item ready when it closes goes in the batch, up to `max_batch`. Items past `max_batch` stay in
+/// `reports` until the next batch (opening the next window), so in a burst longer than a batch
+/// they wait for more than `window`. Ends after the last batch once `reports` ended.
+///
+/// `reports` has to queue everything arriving in a window since it isn't polled until the window
+/// closes. Size a bounded source (like an
+/// [`EventRouter`](crate::hci::adapters::router::EventRouter) receiver) for a window of reports:
+/// a full `Block` queue stalls routing for every receiver and a lossy one drops reports.
+pub struct Coalesce<S: Stream, T> {
+    reports: S,
+    timer: T,
+    window: Duration,
+    max_batch: usize,
+    delay: Option<LocalBoxFuture<'static, ()>>,
+    batch: Vec<S::Item>,
+    ended: bool,
+}
+impl<S: Stream + Unpin, T: Timer> Coalesce<S, T> {
+    pub fn new(reports: S, timer: T, window: Duration, max_batch: usize) -> Coalesce<S, T> {
+        let max_batch = max_batch.max(1);
+        Coalesce {
+            reports,
+            timer,
+            window,
+            max_batch,
+            delay: None,
+            batch: Vec::with_capacity(max_batch),
+            ended: false,
+        }
+    }
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+    /// Changes the window from the next batch on.
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+    pub fn into_inner(self) -> S {
+        self.reports
+    }
+    fn take_batch(&mut self) -> Vec<S::Item> {
+        core::mem::replace(&mut self.batch, Vec::with_capacity(self.max_batch))
+    }
+}
+// Nothing is pinned structurally (`reports` is `Unpin`, the delay is boxed).
+impl<S: Stream + Unpin, T> Unpin for Coalesce<S, T> {}
+impl<S: Stream + Unpin, T: Timer> Stream for Coalesce<S, T> {
+    type Item = Vec<S::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let delay = match this.delay.as_mut() {
+            Some(delay) => delay,
+            None => {
+                if this.ended {
+                    return Poll::Ready(None);
+                }
+                match Pin::new(&mut this.reports).poll_next(cx) {
+                    Poll::Ready(Some(report)) => this.batch.push(report),
+                    Poll::Ready(None) => {
+                        this.ended = true;
+                        return Poll::Ready(None);
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
+                if this.batch.len() >= this.max_batch {
+                    return Poll::Ready(Some(this.take_batch()));
+                }
+                this.delay.insert(this.timer.delay(this.window))
+            }
+        };
+        if delay.poll_unpin(cx).is_pending() {
+            return Poll::Pending;
+        }
+        this.delay = None;
+        while this.batch.len() < this.max_batch {
+            match Pin::new(&mut this.reports).poll_next(cx) {
+                Poll::Ready(Some(report)) => this.batch.push(report),
+                Poll::Ready(None) => {
+                    this.ended = true;
+                    break;
+                }
+                Poll::Pending => break,
+            }
+        }
+        Poll::Ready(Some(this.take_batch()))
+    }
+}
+impl<S: Stream + Unpin, T: Timer> FusedStream for Coalesce<S, T> {
+    fn is_terminated(&self) -> bool {
+        self.ended && self.delay.is_none()
+    }
+}
+impl<S: Stream, T> core::fmt::Debug for Coalesce<S, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Coalesce")
+            .field("window", &self.window)
+            .field("max_batch", &self.max_batch)
+            .field("queued", &self.batch.len())
+            .field("ended", &self.ended)
+            .finish()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use alloc::vec;
+    use core::cell::Cell;
+    use futures_util::future;
+    use futures_util::stream::{self, StreamExt};
+    use futures_util::task::noop_waker_ref;
+
+    /// Windows stay open while the flag is set.
+    struct Gate(Rc<Cell<bool>>);
+    impl Timer for Gate {
+        fn delay(&mut self, _duration: Duration) -> LocalBoxFuture<'static, ()> {
+            let open = self.0.clone();
+            Box::pin(future::poll_fn(move |_| {
+                if open.get() {
+                    Poll::Pending
+                } else {
+                    Poll::Ready(())
+                }
+            }))
+        }
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let window = Rc::new(Cell::new(true));
+        let polls = Cell::new(0_u32);
+        let reports = stream::iter(0..10_u8).inspect(|_| polls.set(polls.get() + 1));
+        let mut batches =
+            Coalesce::new(reports, Gate(window.clone()), Duration::from_millis(50), 4);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        // The first report opens the window. The stream isn't polled again until it closes.
+        for _ in 0..3 {
+            assert!(batches.poll_next_unpin(&mut cx).is_pending());
+        }
+        assert_eq!(polls.get(), 1);
+        window.set(false);
+        let mut all = Vec::new();
+        while let Poll::Ready(Some(batch)) = batches.poll_next_unpin(&mut cx) {
+            all.push(batch);
+        }
+        assert_eq!(all, [vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]);
+        assert!(batches.is_terminated());
+    }
+}
@@ -0,0 +1,221 @@
+//! Publishing advertising reports somewhere else (a message broker, a database, a channel).
+//! A [`ReportSink`] takes reports in batches and applies backpressure by taking its time to
+//! finish [`ReportSink::publish`]. [`forward`] reads an advertisement stream (like
+//! [`Observer::advertisement_stream`](crate::le::scan::Observer::advertisement_stream)) into a
+//! sink. Reports that arrive while a batch is being published queue in a [`ReportBatcher`] and
+//! its [`OverflowPolicy`] decides what happens when the sink can't keep up.
+use crate::le::report::ReportInfo;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use futures_util::stream::{Stream, StreamExt};
+
+pub trait ReportSink {
+    type Error;
+    /// Publishes `batch`. The next batch isn't published before the returned future finishes.
+    fn publish<'a>(
+        &'a mut self,
+        batch: &'a [ReportInfo],
+    ) -> LocalBoxFuture<'a, Result<(), Self::Error>>;
+    /// Most reports to publish at once.
+    fn max_batch(&self) -> usize {
+        32
+    }
+}
+/// What to do with a report arriving when the queue is full.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum OverflowPolicy {
+    /// Stop reading reports until there is room. Reports back up into the transport (and are
+    /// eventually dropped by the controller).
+    Block,
+    /// Drop the oldest queued report. Keeps the view of the air current.
+    DropOldest,
+    /// Drop the new report.
+    DropNewest,
+}
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        OverflowPolicy::DropOldest
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct ForwardStats {
+    pub received: u64,
+    pub published: u64,
+    pub dropped: u64,
+}
+/// Bounded queue of reports waiting to be published.
+#[derive(Clone, Debug)]
+pub struct ReportBatcher {
+    queue: VecDeque<ReportInfo>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    stats: ForwardStats,
+}
+impl ReportBatcher {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> ReportBatcher {
+        ReportBatcher {
+            queue: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            policy,
+            stats: ForwardStats::default(),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+    pub fn is_full(&self) -> bool {
+        self.queue.len() >= self.capacity
+    }
+    pub fn stats(&self) -> ForwardStats {
+        self.stats
+    }
+    /// Queues `report`. Returns `false` if a report (this one or an older one) was dropped. With
+    /// [`OverflowPolicy::Block`] the caller should check [`ReportBatcher::is_full`] first. A push
+    /// to a full blocking batcher drops the new report.
+    pub fn push(&mut self, report: ReportInfo) -> bool {
+        self.stats.received += 1;
+        if !self.is_full() {
+            self.queue.push_back(report);
+            return true;
+        }
+        self.stats.dropped += 1;
+        if self.policy == OverflowPolicy::DropOldest {
+            self.queue.pop_front();
+            self.queue.push_back(report);
+        }
+        false
+    }
+    /// Takes up to `max` of the oldest reports.
+    pub fn take_batch(&mut self, max: usize) -> Vec<ReportInfo> {
+        let len = self.queue.len().min(max.max(1));
+        self.queue.drain(..len).collect()
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ForwardError<E, S> {
+    /// The report stream failed.
+    Stream(E),
+    Sink(S),
+}
+impl<E: core::fmt::Display, S: core::fmt::Display> core::fmt::Display for ForwardError<E, S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ForwardError::Stream(e) => write!(f, "report stream error: {}", e),
+            ForwardError::Sink(e) => write!(f, "report sink error: {}", e),
+        }
+    }
+}
+impl<E: core::fmt::Debug, S: core::fmt::Debug> crate::error::Error for ForwardError<E, S> {}
+#[cfg(feature = "std")]
+impl<E, S> std::error::Error for ForwardError<E, S>
+where
+    E: core::fmt::Debug + core::fmt::Display,
+    S: core::fmt::Debug + core::fmt::Display,
+{
+}
+/// Forwards `reports` into `sink` until `reports` ends. Reports are batched by whatever is
+/// already available when the previous batch finishes publishing, so a quiet scan publishes
+/// reports one by one while a busy one publishes full batches.
+pub async fn forward<R, E, S>(
+    mut reports: R,
+    sink: &mut S,
+    batcher: &mut ReportBatcher,
+) -> Result<ForwardStats, ForwardError<E, S::Error>>
+where
+    R: Stream<Item = Result<ReportInfo, E>> + Unpin,
+    S: ReportSink,
+{
+    let mut ended = false;
+    loop {
+        if batcher.is_empty() && !ended {
+            match reports.next().await {
+                Some(report) => {
+                    batcher.push(report.map_err(ForwardError::Stream)?);
+                }
+                None => ended = true,
+            }
+        }
+        // Take what is ready without waiting. Bounded so a flood can't starve the sink.
+        for _ in 0..batcher.capacity {
+            if ended || (batcher.policy == OverflowPolicy::Block && batcher.is_full()) {
+                break;
+            }
+            match reports.next().now_or_never() {
+                Some(Some(report)) => {
+                    batcher.push(report.map_err(ForwardError::Stream)?);
+                }
+                Some(None) => ended = true,
+                None => break,
+            }
+        }
+        if batcher.is_empty() {
+            return Ok(batcher.stats);
+        }
+        let batch = batcher.take_batch(sink.max_batch());
+        sink.publish(&batch).await.map_err(ForwardError::Sink)?;
+        batcher.stats.published += batch.len() as u64;
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BTAddress;
+    use core::convert::Infallible;
+    use futures_util::stream;
+
+    struct VecSink(Vec<usize>);
+    impl ReportSink for VecSink {
+        type Error = Infallible;
+        fn publish<'a>(
+            &'a mut self,
+            batch: &'a [ReportInfo],
+        ) -> LocalBoxFuture<'a, Result<(), Infallible>> {
+            self.0.push(batch.len());
+            async { Ok(()) }.boxed_local()
+        }
+        fn max_batch(&self) -> usize {
+            4
+        }
+    }
+    #[test]
+    fn test_forward_batches_and_drops() {
+        let reports = (0..10_u8).map(|i| {
+            Ok::<_, Infallible>(ReportInfo {
+                address: BTAddress([i; 6]),
+                ..ReportInfo::default()
+            })
+        });
+        let mut sink = VecSink(Vec::new());
+        let mut batcher = ReportBatcher::new(6, OverflowPolicy::DropOldest);
+        // Every report and publish is ready right away so one poll runs it to the end.
+        let stats = forward(stream::iter(reports), &mut sink, &mut batcher)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        // The first round reads 7 reports into 6 slots and drops the oldest.
+        assert_eq!(sink.0, [4, 4, 1]);
+        assert_eq!(
+            stats,
+            ForwardStats {
+                received: 10,
+                published: 9,
+                dropped: 1,
+            }
+        );
+
+        let mut batcher = ReportBatcher::new(2, OverflowPolicy::DropOldest);
+        for i in 0..3_u8 {
+            batcher.push(ReportInfo {
+                address: BTAddress([i; 6]),
+                ..ReportInfo::default()
+            });
+        }
+        let kept: Vec<_> = batcher.take_batch(8).iter().map(|r| r.address).collect();
+        assert_eq!(kept, [BTAddress([1; 6]), BTAddress([2; 6])]);
+        assert_eq!(batcher.stats().dropped, 1);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::ConversionError;
 
 use crate::hci::adapter;
+use crate::hci::le::features::LEFeatures;
 use crate::le::advertisement::StaticAdvBuffer;
 use crate::le::report::ReportInfo;
 use core::convert::TryFrom;
@@ -53,6 +54,13 @@ impl ScanInterval {
         );
         ScanInterval(interval)
     }
+    pub fn new_checked(interval: u16) -> Option<ScanInterval> {
+        if (INTERVAL_MIN..=INTERVAL_MAX).contains(&interval) {
+            Some(ScanInterval(interval))
+        } else {
+            None
+        }
+    }
     pub fn as_microseconds(self) -> u32 {
         u32::from(u16::from(self)) * 625
     }
@@ -83,6 +91,13 @@ impl ScanWindow {
         );
         ScanWindow(window)
     }
+    pub fn new_checked(window: u16) -> Option<ScanWindow> {
+        if (INTERVAL_MIN..=INTERVAL_MAX).contains(&window) {
+            Some(ScanWindow(window))
+        } else {
+            None
+        }
+    }
     pub fn as_microseconds(self) -> u32 {
         u32::from(u16::from(self)) * 625
     }
@@ -98,6 +113,104 @@ impl Default for ScanWindow {
     }
 }
 
+const EXTENDED_INTERVAL_MIN: u16 = 0x0004;
+const EXTENDED_INTERVAL_MAX: u16 = 0xFFFF;
+/// Scan interval of extended scanning ([`PHYScanParameters`]).
+/// Range 0x0004 --> 0xFFFF
+/// Time = N *  0.625 ms
+/// Time Range 2.5 ms --> 40.96 s
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ExtendedScanInterval(u16);
+impl ExtendedScanInterval {
+    pub const MIN: ExtendedScanInterval = ExtendedScanInterval(EXTENDED_INTERVAL_MIN);
+    pub const MAX: ExtendedScanInterval = ExtendedScanInterval(EXTENDED_INTERVAL_MAX);
+    pub const BYTE_LEN: usize = 2;
+    pub const DEFAULT: ExtendedScanInterval = ExtendedScanInterval(0x0010);
+    /// # Panics
+    /// Panics if `interval < 0x0004`.
+    pub fn new(interval: u16) -> ExtendedScanInterval {
+        assert!(
+            interval >= EXTENDED_INTERVAL_MIN,
+            "interval '{}' is out of range",
+            interval
+        );
+        ExtendedScanInterval(interval)
+    }
+    pub fn new_checked(interval: u16) -> Option<ExtendedScanInterval> {
+        if interval >= EXTENDED_INTERVAL_MIN {
+            Some(ExtendedScanInterval(interval))
+        } else {
+            None
+        }
+    }
+    pub fn as_microseconds(self) -> u32 {
+        u32::from(u16::from(self)) * 625
+    }
+}
+impl From<ExtendedScanInterval> for u16 {
+    fn from(i: ExtendedScanInterval) -> u16 {
+        i.0
+    }
+}
+impl From<ScanInterval> for ExtendedScanInterval {
+    fn from(i: ScanInterval) -> ExtendedScanInterval {
+        ExtendedScanInterval(i.0)
+    }
+}
+impl Default for ExtendedScanInterval {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+/// Scan window of extended scanning ([`PHYScanParameters`]). Same range as
+/// [`ExtendedScanInterval`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ExtendedScanWindow(u16);
+impl ExtendedScanWindow {
+    pub const MIN: ExtendedScanWindow = ExtendedScanWindow(EXTENDED_INTERVAL_MIN);
+    pub const MAX: ExtendedScanWindow = ExtendedScanWindow(EXTENDED_INTERVAL_MAX);
+    pub const BYTE_LEN: usize = 2;
+    pub const DEFAULT: ExtendedScanWindow = ExtendedScanWindow(0x0010);
+    /// # Panics
+    /// Panics if `window < 0x0004`.
+    pub fn new(window: u16) -> ExtendedScanWindow {
+        assert!(
+            window >= EXTENDED_INTERVAL_MIN,
+            "window '{}' is out of range",
+            window
+        );
+        ExtendedScanWindow(window)
+    }
+    pub fn new_checked(window: u16) -> Option<ExtendedScanWindow> {
+        if window >= EXTENDED_INTERVAL_MIN {
+            Some(ExtendedScanWindow(window))
+        } else {
+            None
+        }
+    }
+    pub fn as_microseconds(self) -> u32 {
+        u32::from(u16::from(self)) * 625
+    }
+}
+impl From<ExtendedScanWindow> for u16 {
+    fn from(w: ExtendedScanWindow) -> u16 {
+        w.0
+    }
+}
+impl From<ScanWindow> for ExtendedScanWindow {
+    fn from(w: ScanWindow) -> ExtendedScanWindow {
+        ExtendedScanWindow(w.0)
+    }
+}
+impl Default for ExtendedScanWindow {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+/// Address the controller uses when scanning, advertising or initiating. The `Private*` types use
+/// a Resolvable Private Address generated from the local IRK in the controller's resolving list
+/// and fall back to the public or random (set with LE Set Random Address) address. See
+/// [`LocalAddresses`](crate::le::privacy::LocalAddresses) for checking they're configured.
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
 pub enum OwnAddressType {
     Public = 0x00,
@@ -105,6 +218,29 @@ pub enum OwnAddressType {
     PrivateOrPublic = 0x02,
     PrivateOrRandom = 0x03,
 }
+impl OwnAddressType {
+    pub const BYTE_LEN: usize = 1;
+    pub const DEFAULT: OwnAddressType = OwnAddressType::Public;
+    /// The controller needs a random address to use this type.
+    pub fn needs_random_address(self) -> bool {
+        matches!(
+            self,
+            OwnAddressType::Random | OwnAddressType::PrivateOrRandom
+        )
+    }
+    /// The controller needs a local IRK to use this type.
+    pub fn needs_local_irk(self) -> bool {
+        matches!(
+            self,
+            OwnAddressType::PrivateOrPublic | OwnAddressType::PrivateOrRandom
+        )
+    }
+}
+impl Default for OwnAddressType {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
 
 impl From<OwnAddressType> for u8 {
     fn from(s: OwnAddressType) -> Self {
@@ -162,12 +298,143 @@ impl ScanParameters {
         own_address_type: OwnAddressType::Public,
         scanning_filter_policy: ScanningFilterPolicy::All,
     };
+    /// `true` if the scan window fits in the scan interval.
+    pub fn is_valid(&self) -> bool {
+        u16::from(self.scan_window) <= u16::from(self.scan_interval)
+    }
 }
 impl Default for ScanParameters {
     fn default() -> Self {
         Self::DEFAULT
     }
 }
+/// Scan type, interval and window used on one PHY during extended scanning.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct PHYScanParameters {
+    pub scan_type: ScanType,
+    pub scan_interval: ExtendedScanInterval,
+    pub scan_window: ExtendedScanWindow,
+}
+impl PHYScanParameters {
+    pub const BYTE_LEN: usize = 1 + ExtendedScanInterval::BYTE_LEN + ExtendedScanWindow::BYTE_LEN;
+    pub const DEFAULT: PHYScanParameters = PHYScanParameters {
+        scan_type: ScanType::Passive,
+        scan_interval: ExtendedScanInterval::DEFAULT,
+        scan_window: ExtendedScanWindow::DEFAULT,
+    };
+}
+impl Default for PHYScanParameters {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ExtendedScanError {
+    /// Neither PHY has parameters.
+    NoPHYs,
+    /// A scan window is larger than its scan interval.
+    WindowLargerThanInterval,
+    /// Controller doesn't support extended scanning.
+    ExtendedScanningUnsupported,
+    /// Controller doesn't support the LE Coded PHY.
+    CodedPHYUnsupported,
+}
+impl core::fmt::Display for ExtendedScanError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "extended scan error {:?}", self)
+    }
+}
+impl crate::error::Error for ExtendedScanError {}
+#[cfg(feature = "std")]
+impl std::error::Error for ExtendedScanError {}
+/// Extended scan parameters. Each PHY is scanned if it has parameters (so setting both scans
+/// 1M and Coded at the same time).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ExtendedScanParameters {
+    pub own_address_type: OwnAddressType,
+    pub scanning_filter_policy: ScanningFilterPolicy,
+    pub le_1m: Option<PHYScanParameters>,
+    pub le_coded: Option<PHYScanParameters>,
+}
+impl ExtendedScanParameters {
+    pub const LE_1M_BIT: u8 = 0x01;
+    pub const LE_CODED_BIT: u8 = 0x04;
+    pub const DEFAULT: ExtendedScanParameters = ExtendedScanParameters {
+        own_address_type: OwnAddressType::Public,
+        scanning_filter_policy: ScanningFilterPolicy::All,
+        le_1m: Some(PHYScanParameters::DEFAULT),
+        le_coded: None,
+    };
+    /// `Scanning_PHYs` bit field.
+    pub fn scanning_phys(&self) -> u8 {
+        let mut phys = 0;
+        if self.le_1m.is_some() {
+            phys |= Self::LE_1M_BIT;
+        }
+        if self.le_coded.is_some() {
+            phys |= Self::LE_CODED_BIT;
+        }
+        phys
+    }
+    /// Parameters for each scanned PHY in the order they are sent to the controller.
+    pub fn phys(&self) -> impl Iterator<Item = &PHYScanParameters> + '_ {
+        self.le_1m.iter().chain(self.le_coded.iter())
+    }
+    /// Checks the parameters themselves without any controller capabilities.
+    pub fn validate(&self) -> Result<(), ExtendedScanError> {
+        if self.le_1m.is_none() && self.le_coded.is_none() {
+            return Err(ExtendedScanError::NoPHYs);
+        }
+        if self
+            .phys()
+            .any(|p| u16::from(p.scan_window) > u16::from(p.scan_interval))
+        {
+            return Err(ExtendedScanError::WindowLargerThanInterval);
+        }
+        Ok(())
+    }
+    /// Checks the parameters against the controller's supported `features`.
+    pub fn check_supported(&self, features: LEFeatures) -> Result<(), ExtendedScanError> {
+        self.validate()?;
+        if !features.extended_advertising() {
+            return Err(ExtendedScanError::ExtendedScanningUnsupported);
+        }
+        if self.le_coded.is_some() && !features.le_coded_phy() {
+            return Err(ExtendedScanError::CodedPHYUnsupported);
+        }
+        Ok(())
+    }
+}
+impl Default for ExtendedScanParameters {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+/// Extended scanning duplicate filtering.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum FilterDuplicates {
+    Disabled = 0x00,
+    Enabled = 0x01,
+    /// Enabled but reset at the start of every scan period.
+    ResetEachPeriod = 0x02,
+}
+impl From<FilterDuplicates> for u8 {
+    fn from(f: FilterDuplicates) -> Self {
+        f as u8
+    }
+}
+impl TryFrom<u8> for FilterDuplicates {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(FilterDuplicates::Disabled),
+            1 => Ok(FilterDuplicates::Enabled),
+            2 => Ok(FilterDuplicates::ResetEachPeriod),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
 pub enum ObserverError {
     AdapterError(adapter::Error),
@@ -0,0 +1,315 @@
+//! Versioned export format for scan results. A [`ScanRecord`] holds what a scanner saw of one
+//! advertiser (address, first/last seen timestamps, RSSI, the raw advertising data and its AD
+//! structures decoded where the type is known) so scanners built on this crate can share data
+//! without each inventing a format.
+//!
+//! With `serde-1` records (de)serialize with any serde format. The `json` feature adds
+//! [`ScanRecord::to_json`]/[`ScanRecord::from_json`] and the `postcard` feature a compact binary
+//! encoding ([`ScanRecord::to_postcard`]/[`ScanRecord::from_postcard`]). Addresses are
+//! `AA:BB:CC:DD:EE:FF` strings in human readable formats and 6 bytes otherwise.
+//!
+//! Every record carries its schema `version`. Fields are only ever added (as optional) within
+//! a version, decoders reject records of a newer version than [`SCAN_RECORD_VERSION`].
+use crate::le::report::ReportInfo;
+use crate::uuid::UUID;
+use crate::BTAddress;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// Schema version of the records this crate writes.
+pub const SCAN_RECORD_VERSION: u16 = 1;
+
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScanRecord {
+    pub version: u16,
+    #[cfg_attr(feature = "serde-1", serde(with = "address"))]
+    pub address: BTAddress,
+    /// Raw HCI address type (`0x00` public, `0x01` random, `0x02`/`0x03` resolved identity).
+    pub address_type: u8,
+    /// Raw HCI advertising event type.
+    pub event_type: u8,
+    /// Microseconds since the Unix epoch.
+    pub first_seen_us: u64,
+    /// Microseconds since the Unix epoch.
+    pub last_seen_us: u64,
+    /// RSSI of the last report in dBm.
+    pub rssi: Option<i8>,
+    pub structures: Vec<AdRecord>,
+    /// Advertising data as received.
+    pub raw: Vec<u8>,
+}
+impl ScanRecord {
+    /// Record of `report` seen at `timestamp_us`.
+    pub fn from_report<T: AsRef<[u8]>>(report: &ReportInfo<T>, timestamp_us: u64) -> ScanRecord {
+        let raw = report.data.as_ref().to_vec();
+        ScanRecord {
+            version: SCAN_RECORD_VERSION,
+            address: report.address,
+            address_type: report.address_type.into(),
+            event_type: report.event_type.into(),
+            first_seen_us: timestamp_us,
+            last_seen_us: timestamp_us,
+            rssi: report.rssi.map(i8::from),
+            structures: AdRecord::parse_all(&raw),
+            raw,
+        }
+    }
+    /// Updates the record with a newer report of the same advertiser. The data is replaced, the
+    /// first seen timestamp is kept.
+    pub fn update<T: AsRef<[u8]>>(&mut self, report: &ReportInfo<T>, timestamp_us: u64) {
+        let first_seen_us = self.first_seen_us.min(timestamp_us);
+        *self = ScanRecord {
+            first_seen_us,
+            ..ScanRecord::from_report(report, timestamp_us)
+        };
+    }
+    /// First decoded AD structure matching `f`.
+    pub fn find<R>(&self, f: impl FnMut(&DecodedAd) -> Option<R>) -> Option<R> {
+        self.structures
+            .iter()
+            .filter_map(|s| s.decoded.as_ref())
+            .find_map(f)
+    }
+    /// Complete (or else shortened) local name.
+    pub fn local_name(&self) -> Option<&str> {
+        let mut shortened = None;
+        for decoded in self.structures.iter().filter_map(|s| s.decoded.as_ref()) {
+            if let DecodedAd::LocalName { complete, name } = decoded {
+                if *complete {
+                    return Some(name);
+                }
+                shortened = Some(name.as_str());
+            }
+        }
+        shortened
+    }
+    fn check_version(self) -> Result<ScanRecord, ScanRecordError> {
+        if self.version > SCAN_RECORD_VERSION {
+            return Err(ScanRecordError::UnsupportedVersion(self.version));
+        }
+        Ok(self)
+    }
+}
+#[cfg(feature = "json")]
+impl ScanRecord {
+    pub fn to_json(&self) -> Result<String, ScanRecordError> {
+        serde_json::to_string(self).map_err(|_| ScanRecordError::Encoding)
+    }
+    /// # Errors
+    /// returns `ScanRecordError::UnsupportedVersion` for records of a newer schema.
+    pub fn from_json(json: &str) -> Result<ScanRecord, ScanRecordError> {
+        serde_json::from_str::<ScanRecord>(json)
+            .map_err(|_| ScanRecordError::Encoding)?
+            .check_version()
+    }
+}
+#[cfg(feature = "postcard")]
+impl ScanRecord {
+    pub fn to_postcard(&self) -> Result<Vec<u8>, ScanRecordError> {
+        postcard::to_allocvec(self).map_err(|_| ScanRecordError::Encoding)
+    }
+    /// # Errors
+    /// returns `ScanRecordError::UnsupportedVersion` for records of a newer schema.
+    pub fn from_postcard(bytes: &[u8]) -> Result<ScanRecord, ScanRecordError> {
+        postcard::from_bytes::<ScanRecord>(bytes)
+            .map_err(|_| ScanRecordError::Encoding)?
+            .check_version()
+    }
+}
+/// One AD structure. `decoded` is `None` for types this version doesn't decode (or malformed
+/// data), `data` always has the bytes.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdRecord {
+    pub ad_type: u8,
+    pub data: Vec<u8>,
+    pub decoded: Option<DecodedAd>,
+}
+impl AdRecord {
+    pub fn new(ad_type: u8, data: &[u8]) -> AdRecord {
+        AdRecord {
+            ad_type,
+            data: data.to_vec(),
+            decoded: DecodedAd::decode(ad_type, data),
+        }
+    }
+    /// Every AD structure of `data`, up to the first one running past the end. Zero length
+    /// structures (padding) are skipped.
+    pub fn parse_all(data: &[u8]) -> Vec<AdRecord> {
+        let mut structures = Vec::new();
+        let mut rest = data;
+        while let Some((&len, after)) = rest.split_first() {
+            let (ad_struct, next) = match after.get(..usize::from(len)) {
+                Some(ad_struct) => (ad_struct, &after[usize::from(len)..]),
+                None => break,
+            };
+            if let Some((&ad_type, data)) = ad_struct.split_first() {
+                structures.push(AdRecord::new(ad_type, data));
+            }
+            rest = next;
+        }
+        structures
+    }
+}
+/// Decoded value of a known AD type.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
+pub enum DecodedAd {
+    Flags(u8),
+    LocalName { complete: bool, name: String },
+    ServiceUUIDs16 { complete: bool, uuids: Vec<u16> },
+    ServiceUUIDs32 { complete: bool, uuids: Vec<u32> },
+    ServiceUUIDs128 { complete: bool, uuids: Vec<UUID> },
+    TxPowerLevel(i8),
+    ServiceData16 { uuid: u16, data: Vec<u8> },
+    Appearance(u16),
+    ManufacturerData { company_id: u16, data: Vec<u8> },
+}
+impl DecodedAd {
+    pub fn decode(ad_type: u8, data: &[u8]) -> Option<DecodedAd> {
+        let u16_at = |i: usize| Some(u16::from_le_bytes([*data.get(i)?, *data.get(i + 1)?]));
+        Some(match ad_type {
+            0x01 if data.len() == 1 => DecodedAd::Flags(data[0]),
+            0x02 | 0x03 if data.len() % 2 == 0 => DecodedAd::ServiceUUIDs16 {
+                complete: ad_type == 0x03,
+                uuids: data
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect(),
+            },
+            0x04 | 0x05 if data.len() % 4 == 0 => DecodedAd::ServiceUUIDs32 {
+                complete: ad_type == 0x05,
+                uuids: data
+                    .chunks_exact(4)
+                    .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                    .collect(),
+            },
+            0x06 | 0x07 if data.len() % 16 == 0 => DecodedAd::ServiceUUIDs128 {
+                complete: ad_type == 0x07,
+                uuids: data
+                    .chunks_exact(16)
+                    .map(UUID::try_from)
+                    .collect::<Result<_, _>>()
+                    .ok()?,
+            },
+            0x08 | 0x09 => DecodedAd::LocalName {
+                complete: ad_type == 0x09,
+                name: String::from(core::str::from_utf8(data).ok()?),
+            },
+            0x0A if data.len() == 1 => DecodedAd::TxPowerLevel(i8::from_le_bytes([data[0]])),
+            0x16 => DecodedAd::ServiceData16 {
+                uuid: u16_at(0)?,
+                data: data[2..].to_vec(),
+            },
+            0x19 if data.len() == 2 => DecodedAd::Appearance(u16_at(0)?),
+            0xFF => DecodedAd::ManufacturerData {
+                company_id: u16_at(0)?,
+                data: data[2..].to_vec(),
+            },
+            _ => return None,
+        })
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ScanRecordError {
+    /// The record was written by a newer schema version.
+    UnsupportedVersion(u16),
+    /// The encoder or decoder failed (malformed input for decoding).
+    Encoding,
+}
+impl core::fmt::Display for ScanRecordError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "scan record error {:?}", self)
+    }
+}
+impl crate::error::Error for ScanRecordError {}
+#[cfg(feature = "std")]
+impl std::error::Error for ScanRecordError {}
+/// `BTAddress` as a string in human readable formats and as bytes otherwise.
+#[cfg(feature = "serde-1")]
+mod address {
+    use crate::BTAddress;
+    use alloc::string::String;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(address: &BTAddress, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(address)
+        } else {
+            address.0.serialize(serializer)
+        }
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTAddress, D::Error> {
+        if deserializer.is_human_readable() {
+            String::deserialize(deserializer)?
+                .parse()
+                .map_err(|_| serde::de::Error::custom("invalid Bluetooth address"))
+        } else {
+            <[u8; 6]>::deserialize(deserializer).map(BTAddress)
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::le::advertisement::RawAdvertisement;
+    use crate::le::report::{AddressType, EventType};
+    use crate::RSSI;
+
+    #[test]
+    fn test_record_from_report() {
+        let data = [
+            0x02, 0x01, 0x06, 0x03, 0x03, 0x0F, 0x18, 0x05, 0x09, b'b', b't', b'l', b'e', 0x05,
+            0xFF, 0x4C, 0x00, 0x02, 0x15, 0x02, 0x77, 0x01,
+        ];
+        let report = ReportInfo {
+            event_type: EventType::AdvInd,
+            address_type: AddressType::RandomDevice,
+            address: BTAddress([0xC6, 0x55, 0x44, 0x33, 0x22, 0x11]),
+            data: RawAdvertisement(&data[..]),
+            rssi: Some(RSSI::new(-60)),
+        };
+        let mut record = ScanRecord::from_report(&report, 1_000);
+        record.update(&report, 2_000);
+        assert_eq!((record.first_seen_us, record.last_seen_us), (1_000, 2_000));
+        assert_eq!(record.rssi, Some(-60));
+        assert_eq!(record.local_name(), Some("btle"));
+        assert_eq!(
+            record.structures[1].decoded,
+            Some(DecodedAd::ServiceUUIDs16 {
+                complete: true,
+                uuids: alloc::vec![0x180F],
+            })
+        );
+        assert_eq!(
+            record.structures[3].decoded,
+            Some(DecodedAd::ManufacturerData {
+                company_id: 0x004C,
+                data: alloc::vec![0x02, 0x15],
+            })
+        );
+        // Unknown type: kept raw.
+        assert_eq!(record.structures[4].ad_type, 0x77);
+        assert_eq!(record.structures[4].decoded, None);
+        assert_eq!(record.raw, data);
+
+        #[cfg(feature = "json")]
+        {
+            let json = record.to_json().unwrap();
+            assert!(json.contains(r#""address":"C6:55:44:33:22:11""#));
+            assert_eq!(ScanRecord::from_json(&json), Ok(record.clone()));
+            let newer = json.replace(r#""version":1"#, r#""version":2"#);
+            assert_eq!(
+                ScanRecord::from_json(&newer),
+                Err(ScanRecordError::UnsupportedVersion(2))
+            );
+        }
+        #[cfg(feature = "postcard")]
+        assert_eq!(
+            ScanRecord::from_postcard(&record.to_postcard().unwrap()),
+            Ok(record)
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Host side service UUID filtering of advertisements. [`ServiceFilter`] keeps the wanted UUIDs
+//! as sorted machine words and scans the AD data in place (no per structure copies) so matching
+//! stays cheap when thousands of advertisers are reporting.
+use crate::le::advertisement::AdType;
+use crate::uuid::{UUID16, UUID32};
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+/// Bluetooth Base UUID (`00000000-0000-1000-8000-00805F9B34FB`). 16 and 32-bit UUIDs are this
+/// with the short UUID in bits 96..128.
+pub const BASE_UUID: u128 = 0x0000_0000_0000_1000_8000_0080_5F9B_34FB;
+const SHORT_UUID_SHIFT: u32 = 96;
+/// At or below this many entries a linear scan beats a binary search.
+const LINEAR_SEARCH_MAX: usize = 8;
+fn insert_sorted<T: Ord>(words: &mut Vec<T>, word: T) -> bool {
+    match words.binary_search(&word) {
+        Ok(_) => false,
+        Err(i) => {
+            words.insert(i, word);
+            true
+        }
+    }
+}
+fn contains_word<T: Ord + Copy>(words: &[T], word: T) -> bool {
+    if words.len() <= LINEAR_SEARCH_MAX {
+        words.contains(&word)
+    } else {
+        words.binary_search(&word).is_ok()
+    }
+}
+/// Matches advertisements listing (complete or incomplete lists) any of the wanted service UUIDs.
+/// An empty filter matches every advertisement.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct ServiceFilter {
+    uuid16: Vec<u16>,
+    uuid32: Vec<u32>,
+    uuid128: Vec<u128>,
+}
+impl ServiceFilter {
+    pub fn new() -> ServiceFilter {
+        ServiceFilter::default()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.uuid16.is_empty() && self.uuid32.is_empty() && self.uuid128.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.uuid16.clear();
+        self.uuid32.clear();
+        self.uuid128.clear();
+    }
+    /// Returns `false` if `uuid` was already in the filter.
+    pub fn add_uuid16(&mut self, uuid: UUID16) -> bool {
+        insert_sorted(&mut self.uuid16, uuid.0)
+    }
+    /// Returns `false` if `uuid` was already in the filter. 32-bit UUIDs that fit in 16 bits are
+    /// stored as 16-bit UUIDs so they match either encoding.
+    pub fn add_uuid32(&mut self, uuid: UUID32) -> bool {
+        match uuid.0.try_into() {
+            Ok(short) => self.add_uuid16(UUID16(short)),
+            Err(_) => insert_sorted(&mut self.uuid32, uuid.0),
+        }
+    }
+    /// `uuid` is the numeric value (most significant bits first like the written form). UUIDs
+    /// derived from [`BASE_UUID`] are stored as their short form.
+    pub fn add_uuid128(&mut self, uuid: u128) -> bool {
+        match Self::short_form(uuid) {
+            Some(short) => self.add_uuid32(UUID32(short)),
+            None => insert_sorted(&mut self.uuid128, uuid),
+        }
+    }
+    fn short_form(uuid: u128) -> Option<u32> {
+        let base_mask = (1_u128 << SHORT_UUID_SHIFT) - 1;
+        if uuid & base_mask == BASE_UUID {
+            (uuid >> SHORT_UUID_SHIFT).try_into().ok()
+        } else {
+            None
+        }
+    }
+    fn contains32(&self, uuid: u32) -> bool {
+        match uuid.try_into() {
+            Ok(short) => contains_word(&self.uuid16, short),
+            Err(_) => contains_word(&self.uuid32, uuid),
+        }
+    }
+    fn contains128(&self, uuid: u128) -> bool {
+        match Self::short_form(uuid) {
+            Some(short) => self.contains32(short),
+            None => contains_word(&self.uuid128, uuid),
+        }
+    }
+    fn list_matches(&self, ad_type: u8, data: &[u8]) -> bool {
+        const INCOMPLETE_16: u8 = AdType::IncompleteList16bitUUID as u8;
+        const COMPLETE_16: u8 = AdType::CompleteList16bitUUID as u8;
+        const INCOMPLETE_32: u8 = AdType::IncompleteList32bitUUID as u8;
+        const COMPLETE_32: u8 = AdType::CompleteList32bitUUID as u8;
+        const INCOMPLETE_128: u8 = AdType::IncompleteList128bitUUID as u8;
+        const COMPLETE_128: u8 = AdType::CompleteList128bitUUID as u8;
+        match ad_type {
+            INCOMPLETE_16 | COMPLETE_16 => data
+                .chunks_exact(2)
+                .any(|c| contains_word(&self.uuid16, u16::from_le_bytes([c[0], c[1]]))),
+            INCOMPLETE_32 | COMPLETE_32 => data.chunks_exact(4).any(|c| {
+                self.contains32(u32::from_le_bytes(
+                    c.try_into().expect("chunks are 4 bytes"),
+                ))
+            }),
+            INCOMPLETE_128 | COMPLETE_128 => data.chunks_exact(16).any(|c| {
+                self.contains128(u128::from_le_bytes(
+                    c.try_into().expect("chunks are 16 bytes"),
+                ))
+            }),
+            _ => false,
+        }
+    }
+    /// Returns `true` if the raw AD `data` (as found in a report) lists a wanted service.
+    /// Malformed trailing structures are ignored.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let mut rest = data;
+        while let [len, ad_type, ..] = *rest {
+            let len = usize::from(len);
+            if len == 0 || len + 1 > rest.len() {
+                return false;
+            }
+            if self.list_matches(ad_type, &rest[2..=len]) {
+                return true;
+            }
+            rest = &rest[len + 1..];
+        }
+        false
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_service_filter() {
+        let mut filter = ServiceFilter::new();
+        assert!(filter.matches(&[]));
+        filter.add_uuid16(UUID16(0x180F));
+        assert!(!filter.add_uuid128(BASE_UUID | (0x180F << SHORT_UUID_SHIFT)));
+        filter.add_uuid128(0x6E40_0001_B5A3_F393_E0A9_E50E_24DC_CA9E);
+        // Flags then 16-bit list with Heart Rate and Battery.
+        let battery = [0x02, 0x01, 0x06, 0x05, 0x03, 0x0D, 0x18, 0x0F, 0x18];
+        assert!(filter.matches(&battery));
+        let heart_rate = [0x03, 0x03, 0x0D, 0x18];
+        assert!(!filter.matches(&heart_rate));
+        let mut uart = [0_u8; 18];
+        uart[..2].copy_from_slice(&[0x11, 0x07]);
+        uart[2..].copy_from_slice(&0x6E40_0001_B5A3_F393_E0A9_E50E_24DC_CA9E_u128.to_le_bytes());
+        assert!(filter.matches(&uart));
+        // Battery as a 128-bit UUID.
+        uart[2..].copy_from_slice(&(BASE_UUID | (0x180F << SHORT_UUID_SHIFT)).to_le_bytes());
+        assert!(filter.matches(&uart));
+    }
+}
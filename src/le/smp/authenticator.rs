@@ -0,0 +1,187 @@
+//! [`Authenticator`] is the user interface side of pairing (showing and entering passkeys) plus
+//! SMP Keypress Notification PDUs (Core Spec Vol 3, Part H, 3.5.8) so the display side can show
+//! progress while the remote user types the passkey.
+use crate::le::smp::{Code, PairingParameters};
+use crate::{ConversionError, PackError};
+use core::convert::TryFrom;
+
+/// Largest 6 digit passkey.
+pub const MAX_PASSKEY: u32 = 999_999;
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum KeypressNotificationType {
+    EntryStarted = 0x00,
+    DigitEntered = 0x01,
+    DigitErased = 0x02,
+    Cleared = 0x03,
+    EntryCompleted = 0x04,
+}
+impl From<KeypressNotificationType> for u8 {
+    fn from(t: KeypressNotificationType) -> Self {
+        t as u8
+    }
+}
+impl TryFrom<u8> for KeypressNotificationType {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(KeypressNotificationType::EntryStarted),
+            0x01 => Ok(KeypressNotificationType::DigitEntered),
+            0x02 => Ok(KeypressNotificationType::DigitErased),
+            0x03 => Ok(KeypressNotificationType::Cleared),
+            0x04 => Ok(KeypressNotificationType::EntryCompleted),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// Keypress Notification PDU (including the SMP code byte).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct KeypressNotification {
+    pub notification_type: KeypressNotificationType,
+}
+impl KeypressNotification {
+    pub const CODE: Code = Code::PairingKeypressNotification;
+    pub const BYTE_LEN: usize = 2;
+    pub fn new(notification_type: KeypressNotificationType) -> KeypressNotification {
+        KeypressNotification { notification_type }
+    }
+    pub fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf[0] = Self::CODE.into();
+        buf[1] = self.notification_type.into();
+        Ok(())
+    }
+    pub fn unpack_from(buf: &[u8]) -> Result<Self, PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        if Code::try_from(buf[0]) != Ok(Self::CODE) {
+            return Err(PackError::bad_index(0));
+        }
+        Ok(KeypressNotification {
+            notification_type: KeypressNotificationType::try_from(buf[1])
+                .map_err(|_| PackError::bad_index(1))?,
+        })
+    }
+}
+/// Keypress notifications are only sent if both sides set the keypress `AuthReq` flag.
+pub fn uses_keypress_notifications(
+    initiator: &PairingParameters,
+    responder: &PairingParameters,
+) -> bool {
+    initiator.auth_req.keypress() && responder.auth_req.keypress()
+}
+/// User interface hooks called during pairing. Every method has a default so implementations
+/// only provide what their IO capabilities support.
+pub trait Authenticator {
+    /// Show `passkey` (at most [`MAX_PASSKEY`]) to the user.
+    fn display_passkey(&mut self, _passkey: u32) {}
+    /// Show `value` and ask the user if it matches the remote device. `None` means no answer yet.
+    fn confirm_numeric_comparison(&mut self, _value: u32) -> Option<bool> {
+        Some(false)
+    }
+    /// The remote user pressed a key while typing the passkey shown by [`display_passkey`].
+    ///
+    /// [`display_passkey`]: Authenticator::display_passkey
+    fn remote_keypress(&mut self, _notification: KeypressNotificationType, _digits: u8) {}
+}
+/// Tracks passkey entry in both directions. Feed local key presses to [`local`] (which returns
+/// the PDU to send, if notifications are enabled) and received PDUs to [`remote`] (which
+/// forwards them to the [`Authenticator`]).
+///
+/// [`local`]: KeypressTracker::local
+/// [`remote`]: KeypressTracker::remote
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct KeypressTracker {
+    enabled: bool,
+    local_digits: u8,
+    remote_digits: u8,
+}
+impl KeypressTracker {
+    pub const PASSKEY_DIGITS: u8 = 6;
+    pub fn new(initiator: &PairingParameters, responder: &PairingParameters) -> KeypressTracker {
+        KeypressTracker {
+            enabled: uses_keypress_notifications(initiator, responder),
+            ..KeypressTracker::default()
+        }
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    /// Digits the local user has typed.
+    pub fn local_digits(&self) -> u8 {
+        self.local_digits
+    }
+    /// Digits the remote user has typed (as reported by its notifications).
+    pub fn remote_digits(&self) -> u8 {
+        self.remote_digits
+    }
+    fn apply(digits: &mut u8, notification: KeypressNotificationType) {
+        *digits = match notification {
+            KeypressNotificationType::EntryStarted | KeypressNotificationType::Cleared => 0,
+            KeypressNotificationType::DigitEntered => {
+                digits.saturating_add(1).min(Self::PASSKEY_DIGITS)
+            }
+            KeypressNotificationType::DigitErased => digits.saturating_sub(1),
+            KeypressNotificationType::EntryCompleted => *digits,
+        }
+    }
+    /// Record a local key press. Returns the PDU to send to the remote device.
+    pub fn local(
+        &mut self,
+        notification: KeypressNotificationType,
+    ) -> Option<KeypressNotification> {
+        Self::apply(&mut self.local_digits, notification);
+        if self.enabled {
+            Some(KeypressNotification::new(notification))
+        } else {
+            None
+        }
+    }
+    /// Handle a received Keypress Notification PDU. Returns `false` (and ignores it) if
+    /// notifications were not negotiated.
+    pub fn remote<A: Authenticator>(
+        &mut self,
+        notification: KeypressNotification,
+        authenticator: &mut A,
+    ) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        Self::apply(&mut self.remote_digits, notification.notification_type);
+        authenticator.remote_keypress(notification.notification_type, self.remote_digits);
+        true
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::le::smp::AuthReq;
+    use alloc::vec::Vec;
+    #[derive(Default)]
+    struct Recorder(Vec<(KeypressNotificationType, u8)>);
+    impl Authenticator for Recorder {
+        fn remote_keypress(&mut self, notification: KeypressNotificationType, digits: u8) {
+            self.0.push((notification, digits));
+        }
+    }
+    #[test]
+    fn test_keypress_tracker() {
+        use KeypressNotificationType::{DigitEntered, DigitErased, EntryStarted};
+        let mut params = PairingParameters::default();
+        params.auth_req.set(AuthReq::KEYPRESS, true);
+        let mut tracker = KeypressTracker::new(&params, &params);
+        let pdu = tracker.local(DigitEntered).unwrap();
+        let mut buf = [0_u8; KeypressNotification::BYTE_LEN];
+        pdu.pack_into(&mut buf).unwrap();
+        assert_eq!(buf, [0x0E, 0x01]);
+        assert_eq!(KeypressNotification::unpack_from(&buf), Ok(pdu));
+        let mut recorder = Recorder::default();
+        for t in [EntryStarted, DigitEntered, DigitEntered, DigitErased].iter() {
+            assert!(tracker.remote(KeypressNotification::new(*t), &mut recorder));
+        }
+        assert_eq!(recorder.0.last(), Some(&(DigitErased, 1)));
+        assert_eq!(tracker.local_digits(), 1);
+        let mut disabled = KeypressTracker::new(&params, &PairingParameters::default());
+        assert_eq!(disabled.local(DigitEntered), None);
+        assert!(!disabled.remote(pdu, &mut recorder));
+    }
+}
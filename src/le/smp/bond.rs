@@ -0,0 +1,112 @@
+//! Keys kept after bonding. [`BondStore`] is the storage interface and [`MemoryBondStore`] a
+//! simple in memory implementation.
+use crate::le::advertiser::PeerAddressType;
+use crate::le::crypto;
+use crate::le::privacy::IdentityResolvingKey;
+use crate::BTAddress;
+use alloc::collections::BTreeMap;
+
+/// LE Long Term Key. Little endian (like SMP and HCI send it).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Default)]
+pub struct LongTermKey(pub [u8; crypto::KEY_LEN]);
+crypto::key_material!(LongTermKey);
+/// BR/EDR link key. Little endian (like HCI sends it).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Default)]
+pub struct LinkKey(pub [u8; crypto::KEY_LEN]);
+crypto::key_material!(LinkKey);
+/// Connection Signature Resolving Key used to verify signed writes. Little endian (like SMP
+/// sends it).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Default)]
+pub struct SignatureResolvingKey(pub [u8; crypto::KEY_LEN]);
+crypto::key_material!(SignatureResolvingKey);
+/// Identity address of a bonded peer.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct PeerIdentity {
+    pub address_type: PeerAddressType,
+    pub address: BTAddress,
+}
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct Bond {
+    pub ltk: Option<LongTermKey>,
+    /// Whether `ltk` came from an authenticated (MITM protected) pairing.
+    pub authenticated: bool,
+    /// Whether `ltk` came from LE Secure Connections pairing.
+    pub secure_connections: bool,
+    pub link_key: Option<LinkKey>,
+    pub irk: Option<IdentityResolvingKey>,
+    pub csrk: Option<SignatureResolvingKey>,
+}
+/// Where bonds are kept (flash, a file, a database, ...).
+pub trait BondStore {
+    fn get(&self, peer: &PeerIdentity) -> Option<Bond>;
+    fn insert(&mut self, peer: PeerIdentity, bond: Bond);
+    fn remove(&mut self, peer: &PeerIdentity) -> Option<Bond>;
+}
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBondStore {
+    bonds: BTreeMap<PeerIdentity, Bond>,
+}
+impl MemoryBondStore {
+    pub fn new() -> MemoryBondStore {
+        MemoryBondStore::default()
+    }
+    pub fn len(&self) -> usize {
+        self.bonds.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bonds.is_empty()
+    }
+}
+impl BondStore for MemoryBondStore {
+    fn get(&self, peer: &PeerIdentity) -> Option<Bond> {
+        self.bonds.get(peer).cloned()
+    }
+    fn insert(&mut self, peer: PeerIdentity, bond: Bond) {
+        self.bonds.insert(peer, bond);
+    }
+    fn remove(&mut self, peer: &PeerIdentity) -> Option<Bond> {
+        self.bonds.remove(peer)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    #[test]
+    fn test_keys_redacted() {
+        let bond = Bond {
+            ltk: Some(LongTermKey([0xA5; crypto::KEY_LEN])),
+            csrk: Some(SignatureResolvingKey([0xA5; crypto::KEY_LEN])),
+            ..Bond::default()
+        };
+        let debug = format!("{:?}", bond);
+        assert!(debug.contains("LongTermKey(<redacted>)"));
+        assert!(debug.contains("SignatureResolvingKey(<redacted>)"));
+        assert!(!debug.contains("165"));
+    }
+    #[test]
+    fn test_memory_bond_store() {
+        let peer = PeerIdentity {
+            address_type: PeerAddressType::Random,
+            address: BTAddress::from_u64(0xC0_0000_0001),
+        };
+        let bond = Bond {
+            ltk: Some(LongTermKey([0x01; crypto::KEY_LEN])),
+            authenticated: true,
+            ..Bond::default()
+        };
+        let mut store = MemoryBondStore::new();
+        assert!(store.is_empty());
+        store.insert(peer, bond.clone());
+        assert_eq!(store.get(&peer).as_ref(), Some(&bond));
+        // The same address with the other address type is another peer.
+        let public = PeerIdentity {
+            address_type: PeerAddressType::Public,
+            ..peer
+        };
+        assert_eq!(store.get(&public), None);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.remove(&peer), Some(bond));
+        assert!(store.is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+//! Cross Transport Key Derivation (Core Spec Vol 3, Part H, 2.4.2.4 and 2.4.2.5). Lets one
+//! LE Secure Connections pairing also produce the BR/EDR link key (or one Secure Connections
+//! BR/EDR pairing produce the LE LTK) so dual mode devices only pair once.
+use crate::le::crypto::{self, Key};
+use crate::le::smp::bond::{Bond, BondStore, LinkKey, LongTermKey, PeerIdentity};
+use crate::le::smp::KeyDistribution;
+
+/// Which key derivation was done.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum Derived {
+    LinkKeyFromLTK,
+    LTKFromLinkKey,
+}
+fn salt(key_id: [u8; 4]) -> Key {
+    let mut salt = [0_u8; crypto::KEY_LEN];
+    salt[12..].copy_from_slice(&key_id);
+    salt
+}
+/// Intermediate key. `ct2` (both sides set the `CT2` `AuthReq` bit) selects `h7` over `h6`.
+fn intermediate_key(key: &Key, key_id: [u8; 4], ct2: bool) -> Key {
+    if ct2 {
+        crypto::h7(&salt(key_id), key)
+    } else {
+        crypto::h6(key, key_id)
+    }
+}
+/// Keys are stored little endian but the toolbox functions work most significant octet first.
+fn reversed(mut key: Key) -> Key {
+    key.reverse();
+    key
+}
+pub fn link_key_from_ltk(ltk: &LongTermKey, ct2: bool) -> LinkKey {
+    let mut key = reversed(ltk.0);
+    let mut ilk = intermediate_key(&key, *b"tmp1", ct2);
+    let link_key = LinkKey(reversed(crypto::h6(&ilk, *b"lebr")));
+    crypto::wipe(&mut key);
+    crypto::wipe(&mut ilk);
+    link_key
+}
+pub fn ltk_from_link_key(link_key: &LinkKey, ct2: bool) -> LongTermKey {
+    let mut key = reversed(link_key.0);
+    let mut ilk = intermediate_key(&key, *b"tmp2", ct2);
+    let ltk = LongTermKey(reversed(crypto::h6(&ilk, *b"brle")));
+    crypto::wipe(&mut key);
+    crypto::wipe(&mut ilk);
+    ltk
+}
+/// Fills in the missing transport's key of `bond` if both sides agreed to distribute
+/// [`KeyDistribution::LINK_KEY`]. Only keys from Secure Connections pairing may be converted.
+pub fn derive_missing_key(bond: &mut Bond, agreed: KeyDistribution, ct2: bool) -> Option<Derived> {
+    if !agreed.get(KeyDistribution::LINK_KEY) || !bond.secure_connections {
+        return None;
+    }
+    match (&bond.ltk, &bond.link_key) {
+        (Some(ltk), None) => {
+            bond.link_key = Some(link_key_from_ltk(ltk, ct2));
+            Some(Derived::LinkKeyFromLTK)
+        }
+        (None, Some(link_key)) => {
+            bond.ltk = Some(ltk_from_link_key(link_key, ct2));
+            Some(Derived::LTKFromLinkKey)
+        }
+        _ => None,
+    }
+}
+/// Runs [`derive_missing_key`] on `bond` at the end of key distribution and stores it for
+/// `peer` in `store` so the bond covers both transports.
+pub fn complete_key_distribution<S: BondStore>(
+    store: &mut S,
+    peer: PeerIdentity,
+    mut bond: Bond,
+    agreed: KeyDistribution,
+    ct2: bool,
+) -> Option<Derived> {
+    let derived = derive_missing_key(&mut bond, agreed, ct2);
+    store.insert(peer, bond);
+    derived
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::le::advertiser::PeerAddressType;
+    use crate::le::smp::bond::MemoryBondStore;
+    use crate::BTAddress;
+
+    // Core Spec Vol 3, Part H, D.10 to D.13 (most significant octet first).
+    const LTK: Key = [
+        0x36, 0x8d, 0xf9, 0xbc, 0xe3, 0x26, 0x4b, 0x58, 0xbd, 0x06, 0x6c, 0x33, 0x33, 0x4f, 0xbf,
+        0x64,
+    ];
+    const LINK_KEY_H6: Key = [
+        0xbc, 0x1c, 0xa4, 0xef, 0x63, 0x3f, 0xc1, 0xbd, 0x0d, 0x82, 0x30, 0xaf, 0xee, 0x38, 0x8f,
+        0xb0,
+    ];
+    const LINK_KEY_H7: Key = [
+        0x28, 0x7a, 0xd3, 0x79, 0xdc, 0xa4, 0x02, 0x53, 0x0a, 0x39, 0xf1, 0xf4, 0x30, 0x47, 0xb8,
+        0x35,
+    ];
+    const LINK_KEY: Key = [
+        0x05, 0x04, 0x03, 0x02, 0x01, 0x00, 0x09, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01,
+        0x00,
+    ];
+    const LTK_H6: Key = [
+        0xa8, 0x13, 0xfb, 0x72, 0xf1, 0xa3, 0xdf, 0xa1, 0x8a, 0x2c, 0x9a, 0x43, 0xf1, 0x0d, 0x0a,
+        0x30,
+    ];
+    const LTK_H7: Key = [
+        0xe8, 0x5e, 0x09, 0xeb, 0x5e, 0xcc, 0xb3, 0xe2, 0x69, 0x41, 0x8a, 0x13, 0x32, 0x11, 0xbc,
+        0x79,
+    ];
+
+    #[test]
+    fn test_spec_vectors() {
+        let ltk = LongTermKey(reversed(LTK));
+        assert_eq!(link_key_from_ltk(&ltk, false).0, reversed(LINK_KEY_H6));
+        assert_eq!(link_key_from_ltk(&ltk, true).0, reversed(LINK_KEY_H7));
+        let link_key = LinkKey(reversed(LINK_KEY));
+        assert_eq!(ltk_from_link_key(&link_key, false).0, reversed(LTK_H6));
+        assert_eq!(ltk_from_link_key(&link_key, true).0, reversed(LTK_H7));
+    }
+    #[test]
+    fn test_derive_missing_key() {
+        let le_bond = Bond {
+            ltk: Some(LongTermKey(reversed(LTK))),
+            secure_connections: true,
+            ..Bond::default()
+        };
+        let link_key = KeyDistribution(KeyDistribution::LINK_KEY);
+        // Nothing to do unless both agreed and the LTK came from Secure Connections.
+        let mut bond = le_bond.clone();
+        assert_eq!(
+            derive_missing_key(&mut bond, KeyDistribution(KeyDistribution::ENC_KEY), false),
+            None
+        );
+        let mut legacy = Bond {
+            secure_connections: false,
+            ..le_bond
+        };
+        assert_eq!(derive_missing_key(&mut legacy, link_key, false), None);
+        assert_eq!(legacy.link_key, None);
+
+        assert_eq!(
+            derive_missing_key(&mut bond, link_key, false),
+            Some(Derived::LinkKeyFromLTK)
+        );
+        assert_eq!(bond.link_key, Some(LinkKey(reversed(LINK_KEY_H6))));
+        // Both keys there now.
+        assert_eq!(derive_missing_key(&mut bond, link_key, false), None);
+
+        let peer = PeerIdentity {
+            address_type: PeerAddressType::Public,
+            address: BTAddress::from_u64(0x11),
+        };
+        let bredr_bond = Bond {
+            link_key: Some(LinkKey(reversed(LINK_KEY))),
+            secure_connections: true,
+            ..Bond::default()
+        };
+        let mut store = MemoryBondStore::new();
+        assert_eq!(
+            complete_key_distribution(&mut store, peer, bredr_bond, link_key, true),
+            Some(Derived::LTKFromLinkKey)
+        );
+        let stored = store.get(&peer).unwrap();
+        assert_eq!(stored.ltk, Some(LongTermKey(reversed(LTK_H7))));
+        assert_eq!(stored.link_key, Some(LinkKey(reversed(LINK_KEY))));
+    }
+}
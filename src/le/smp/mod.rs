@@ -0,0 +1,518 @@
+//! Security Manager Protocol (SMP) pairing configuration. [`SecurityConfig`] holds what this
+//! device asks for when pairing (IO capabilities, bonding, MITM protection, key size and which
+//! keys to distribute) and [`pairing_method`] picks the association model from both sides'
+//! [`PairingParameters`] (Core Spec Vol 3, Part H, 2.3).
+pub mod authenticator;
+pub mod bond;
+pub mod ctkd;
+
+use crate::le::connection::ConnectionHandle;
+use crate::{ConversionError, PackError};
+use alloc::collections::BTreeMap;
+use core::convert::TryFrom;
+
+/// SMP PDU codes.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum Code {
+    PairingRequest = 0x01,
+    PairingResponse = 0x02,
+    PairingConfirm = 0x03,
+    PairingRandom = 0x04,
+    PairingFailed = 0x05,
+    EncryptionInformation = 0x06,
+    CentralIdentification = 0x07,
+    IdentityInformation = 0x08,
+    IdentityAddressInformation = 0x09,
+    SigningInformation = 0x0A,
+    SecurityRequest = 0x0B,
+    PairingPublicKey = 0x0C,
+    PairingDHKeyCheck = 0x0D,
+    PairingKeypressNotification = 0x0E,
+}
+impl From<Code> for u8 {
+    fn from(c: Code) -> Self {
+        c as u8
+    }
+}
+impl TryFrom<u8> for Code {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Code::PairingRequest),
+            0x02 => Ok(Code::PairingResponse),
+            0x03 => Ok(Code::PairingConfirm),
+            0x04 => Ok(Code::PairingRandom),
+            0x05 => Ok(Code::PairingFailed),
+            0x06 => Ok(Code::EncryptionInformation),
+            0x07 => Ok(Code::CentralIdentification),
+            0x08 => Ok(Code::IdentityInformation),
+            0x09 => Ok(Code::IdentityAddressInformation),
+            0x0A => Ok(Code::SigningInformation),
+            0x0B => Ok(Code::SecurityRequest),
+            0x0C => Ok(Code::PairingPublicKey),
+            0x0D => Ok(Code::PairingDHKeyCheck),
+            0x0E => Ok(Code::PairingKeypressNotification),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum IOCapability {
+    DisplayOnly = 0x00,
+    DisplayYesNo = 0x01,
+    KeyboardOnly = 0x02,
+    NoInputNoOutput = 0x03,
+    KeyboardDisplay = 0x04,
+}
+impl IOCapability {
+    pub fn can_input(self) -> bool {
+        matches!(
+            self,
+            IOCapability::KeyboardOnly | IOCapability::KeyboardDisplay
+        )
+    }
+    pub fn can_display(self) -> bool {
+        matches!(
+            self,
+            IOCapability::DisplayOnly | IOCapability::DisplayYesNo | IOCapability::KeyboardDisplay
+        )
+    }
+}
+impl Default for IOCapability {
+    fn default() -> Self {
+        IOCapability::NoInputNoOutput
+    }
+}
+impl From<IOCapability> for u8 {
+    fn from(c: IOCapability) -> Self {
+        c as u8
+    }
+}
+impl TryFrom<u8> for IOCapability {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(IOCapability::DisplayOnly),
+            0x01 => Ok(IOCapability::DisplayYesNo),
+            0x02 => Ok(IOCapability::KeyboardOnly),
+            0x03 => Ok(IOCapability::NoInputNoOutput),
+            0x04 => Ok(IOCapability::KeyboardDisplay),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+/// `AuthReq` flags of a pairing request or response.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct AuthReq(pub u8);
+impl AuthReq {
+    pub const BONDING: u8 = 0x01;
+    pub const MITM: u8 = 0x04;
+    pub const SECURE_CONNECTIONS: u8 = 0x08;
+    pub const KEYPRESS: u8 = 0x10;
+    pub const CT2: u8 = 0x20;
+    pub fn get(self, flag: u8) -> bool {
+        self.0 & flag != 0
+    }
+    pub fn set(&mut self, flag: u8, value: bool) {
+        if value {
+            self.0 |= flag;
+        } else {
+            self.0 &= !flag;
+        }
+    }
+    pub fn bonding(self) -> bool {
+        self.get(Self::BONDING)
+    }
+    pub fn mitm(self) -> bool {
+        self.get(Self::MITM)
+    }
+    pub fn secure_connections(self) -> bool {
+        self.get(Self::SECURE_CONNECTIONS)
+    }
+    pub fn keypress(self) -> bool {
+        self.get(Self::KEYPRESS)
+    }
+}
+/// Keys a side distributes after pairing.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct KeyDistribution(pub u8);
+impl KeyDistribution {
+    /// LTK (legacy pairing only) and EDIV/Rand.
+    pub const ENC_KEY: u8 = 0x01;
+    /// IRK and identity address.
+    pub const ID_KEY: u8 = 0x02;
+    /// CSRK.
+    pub const SIGN_KEY: u8 = 0x04;
+    /// Derive the BR/EDR link key from the LTK (cross transport key derivation).
+    pub const LINK_KEY: u8 = 0x08;
+    pub const NONE: KeyDistribution = KeyDistribution(0);
+    pub const ALL: KeyDistribution = KeyDistribution(0x0F);
+    pub fn get(self, key: u8) -> bool {
+        self.0 & key != 0
+    }
+    /// Keys both sides agree on (the responder may only clear bits of the request).
+    #[must_use]
+    pub fn intersect(self, other: KeyDistribution) -> KeyDistribution {
+        KeyDistribution(self.0 & other.0)
+    }
+}
+/// Body of a Pairing Request or Pairing Response PDU.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct PairingParameters {
+    pub io_capability: IOCapability,
+    pub oob_data: bool,
+    pub auth_req: AuthReq,
+    pub max_encryption_key_size: u8,
+    pub initiator_key_distribution: KeyDistribution,
+    pub responder_key_distribution: KeyDistribution,
+}
+impl PairingParameters {
+    pub const BYTE_LEN: usize = 6;
+    pub fn pack_into(&self, buf: &mut [u8]) -> Result<(), PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        buf[0] = self.io_capability.into();
+        buf[1] = self.oob_data.into();
+        buf[2] = self.auth_req.0;
+        buf[3] = self.max_encryption_key_size;
+        buf[4] = self.initiator_key_distribution.0;
+        buf[5] = self.responder_key_distribution.0;
+        Ok(())
+    }
+    pub fn unpack_from(buf: &[u8]) -> Result<Self, PackError> {
+        PackError::expect_length(Self::BYTE_LEN, buf)?;
+        Ok(PairingParameters {
+            io_capability: IOCapability::try_from(buf[0]).map_err(|_| PackError::bad_index(0))?,
+            oob_data: match buf[1] {
+                0 => false,
+                1 => true,
+                _ => return Err(PackError::bad_index(1)),
+            },
+            auth_req: AuthReq(buf[2]),
+            max_encryption_key_size: buf[3],
+            initiator_key_distribution: KeyDistribution(buf[4]),
+            responder_key_distribution: KeyDistribution(buf[5]),
+        })
+    }
+    /// Encryption key size both sides support or `None` if it is below the spec minimum.
+    pub fn negotiated_key_size(&self, other: &PairingParameters) -> Option<u8> {
+        let size = self
+            .max_encryption_key_size
+            .min(other.max_encryption_key_size);
+        if size >= SecurityConfig::MIN_KEY_SIZE {
+            Some(size)
+        } else {
+            None
+        }
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum SecurityConfigError {
+    /// `max_key_size` isn't in `7..=16`.
+    KeySizeOutOfRange,
+    /// MITM protection needs a display or keyboard (or OOB data) but the IO capabilities have
+    /// neither.
+    MITMUnsatisfiable,
+    /// Keys can only be distributed when bonding.
+    KeysWithoutBonding,
+    /// Deriving the BR/EDR link key needs LE Secure Connections.
+    LinkKeyWithoutSecureConnections,
+    /// Keypress notifications are only sent during passkey entry which needs a keyboard or a
+    /// display.
+    KeypressWithoutPasskeyEntry,
+}
+/// SMP settings this device pairs with. Build one with the `with_*`/`*` setters and check it
+/// with [`SecurityConfig::build`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct SecurityConfig {
+    pub io_capability: IOCapability,
+    pub bondable: bool,
+    pub mitm: bool,
+    pub secure_connections: bool,
+    pub keypress_notifications: bool,
+    pub oob_data: bool,
+    pub max_key_size: u8,
+    pub initiator_keys: KeyDistribution,
+    pub responder_keys: KeyDistribution,
+}
+impl SecurityConfig {
+    pub const MIN_KEY_SIZE: u8 = 7;
+    pub const MAX_KEY_SIZE: u8 = 16;
+    /// Non-bondable Just Works with LE Secure Connections.
+    pub const fn new() -> SecurityConfig {
+        SecurityConfig {
+            io_capability: IOCapability::NoInputNoOutput,
+            bondable: false,
+            mitm: false,
+            secure_connections: true,
+            keypress_notifications: false,
+            oob_data: false,
+            max_key_size: Self::MAX_KEY_SIZE,
+            initiator_keys: KeyDistribution::NONE,
+            responder_keys: KeyDistribution::NONE,
+        }
+    }
+    #[must_use]
+    pub fn io_capability(mut self, io_capability: IOCapability) -> SecurityConfig {
+        self.io_capability = io_capability;
+        self
+    }
+    /// Bond (store keys). Also asks for the identity and encryption keys from both sides.
+    #[must_use]
+    pub fn bondable(mut self, bondable: bool) -> SecurityConfig {
+        self.bondable = bondable;
+        let keys = if bondable {
+            KeyDistribution(KeyDistribution::ENC_KEY | KeyDistribution::ID_KEY)
+        } else {
+            KeyDistribution::NONE
+        };
+        self.initiator_keys = keys;
+        self.responder_keys = keys;
+        self
+    }
+    #[must_use]
+    pub fn mitm(mut self, mitm: bool) -> SecurityConfig {
+        self.mitm = mitm;
+        self
+    }
+    #[must_use]
+    pub fn secure_connections(mut self, secure_connections: bool) -> SecurityConfig {
+        self.secure_connections = secure_connections;
+        self
+    }
+    #[must_use]
+    pub fn keypress_notifications(mut self, keypress_notifications: bool) -> SecurityConfig {
+        self.keypress_notifications = keypress_notifications;
+        self
+    }
+    #[must_use]
+    pub fn oob_data(mut self, oob_data: bool) -> SecurityConfig {
+        self.oob_data = oob_data;
+        self
+    }
+    #[must_use]
+    pub fn max_key_size(mut self, max_key_size: u8) -> SecurityConfig {
+        self.max_key_size = max_key_size;
+        self
+    }
+    #[must_use]
+    pub fn key_distribution(
+        mut self,
+        initiator_keys: KeyDistribution,
+        responder_keys: KeyDistribution,
+    ) -> SecurityConfig {
+        self.initiator_keys = initiator_keys;
+        self.responder_keys = responder_keys;
+        self
+    }
+    /// Checks the config against the spec constraints.
+    pub fn validate(&self) -> Result<(), SecurityConfigError> {
+        if !(Self::MIN_KEY_SIZE..=Self::MAX_KEY_SIZE).contains(&self.max_key_size) {
+            return Err(SecurityConfigError::KeySizeOutOfRange);
+        }
+        if self.mitm && !self.oob_data && self.io_capability == IOCapability::NoInputNoOutput {
+            return Err(SecurityConfigError::MITMUnsatisfiable);
+        }
+        let keys = self.initiator_keys.0 | self.responder_keys.0;
+        if !self.bondable && keys != 0 {
+            return Err(SecurityConfigError::KeysWithoutBonding);
+        }
+        if !self.secure_connections && keys & KeyDistribution::LINK_KEY != 0 {
+            return Err(SecurityConfigError::LinkKeyWithoutSecureConnections);
+        }
+        if self.keypress_notifications
+            && !(self.io_capability.can_input() || self.io_capability.can_display())
+        {
+            return Err(SecurityConfigError::KeypressWithoutPasskeyEntry);
+        }
+        Ok(())
+    }
+    /// Returns the config if it is valid.
+    pub fn build(self) -> Result<SecurityConfig, SecurityConfigError> {
+        self.validate().map(|()| self)
+    }
+    /// Parameters to put in a Pairing Request (or Pairing Response).
+    pub fn pairing_parameters(&self) -> PairingParameters {
+        let mut auth_req = AuthReq::default();
+        auth_req.set(AuthReq::BONDING, self.bondable);
+        auth_req.set(AuthReq::MITM, self.mitm);
+        auth_req.set(AuthReq::SECURE_CONNECTIONS, self.secure_connections);
+        auth_req.set(AuthReq::KEYPRESS, self.keypress_notifications);
+        PairingParameters {
+            io_capability: self.io_capability,
+            oob_data: self.oob_data,
+            auth_req,
+            max_encryption_key_size: self.max_key_size,
+            initiator_key_distribution: self.initiator_keys,
+            responder_key_distribution: self.responder_keys,
+        }
+    }
+}
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        SecurityConfig::new()
+    }
+}
+/// Default [`SecurityConfig`] of an adapter plus per connection overrides.
+#[derive(Clone, Debug, Default)]
+pub struct SecurityPolicy {
+    default: SecurityConfig,
+    connections: BTreeMap<ConnectionHandle, SecurityConfig>,
+}
+impl SecurityPolicy {
+    pub fn new(default: SecurityConfig) -> Result<SecurityPolicy, SecurityConfigError> {
+        Ok(SecurityPolicy {
+            default: default.build()?,
+            connections: BTreeMap::new(),
+        })
+    }
+    pub fn default_config(&self) -> &SecurityConfig {
+        &self.default
+    }
+    pub fn set_default(&mut self, config: SecurityConfig) -> Result<(), SecurityConfigError> {
+        self.default = config.build()?;
+        Ok(())
+    }
+    pub fn set_for_connection(
+        &mut self,
+        connection_handle: ConnectionHandle,
+        config: SecurityConfig,
+    ) -> Result<(), SecurityConfigError> {
+        self.connections.insert(connection_handle, config.build()?);
+        Ok(())
+    }
+    /// Drops the override of `connection_handle` (call it when the connection is closed).
+    pub fn remove_connection(&mut self, connection_handle: ConnectionHandle) {
+        self.connections.remove(&connection_handle);
+    }
+    pub fn config_for(&self, connection_handle: ConnectionHandle) -> &SecurityConfig {
+        self.connections
+            .get(&connection_handle)
+            .unwrap_or(&self.default)
+    }
+}
+/// Who types the passkey.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum PasskeyEntry {
+    /// Initiator displays, responder types.
+    InitiatorDisplays,
+    /// Responder displays, initiator types.
+    ResponderDisplays,
+    /// Both type the same passkey.
+    BothInput,
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum PairingMethod {
+    JustWorks,
+    PasskeyEntry(PasskeyEntry),
+    NumericComparison,
+    OutOfBand,
+}
+impl PairingMethod {
+    /// Whether the method protects against man in the middle attacks.
+    pub fn is_authenticated(self) -> bool {
+        self != PairingMethod::JustWorks
+    }
+}
+/// Returns `true` if both sides support LE Secure Connections.
+pub fn uses_secure_connections(
+    initiator: &PairingParameters,
+    responder: &PairingParameters,
+) -> bool {
+    initiator.auth_req.secure_connections() && responder.auth_req.secure_connections()
+}
+/// Picks the association model from the pairing request and response.
+pub fn pairing_method(
+    initiator: &PairingParameters,
+    responder: &PairingParameters,
+) -> PairingMethod {
+    use IOCapability::{DisplayOnly, DisplayYesNo, KeyboardDisplay, KeyboardOnly, NoInputNoOutput};
+    use PasskeyEntry::{BothInput, InitiatorDisplays, ResponderDisplays};
+    let secure_connections = uses_secure_connections(initiator, responder);
+    let oob = if secure_connections {
+        initiator.oob_data || responder.oob_data
+    } else {
+        initiator.oob_data && responder.oob_data
+    };
+    if oob {
+        return PairingMethod::OutOfBand;
+    }
+    if !initiator.auth_req.mitm() && !responder.auth_req.mitm() {
+        return PairingMethod::JustWorks;
+    }
+    let comparison_or = |passkey| {
+        if secure_connections {
+            PairingMethod::NumericComparison
+        } else {
+            PairingMethod::PasskeyEntry(passkey)
+        }
+    };
+    match (initiator.io_capability, responder.io_capability) {
+        (NoInputNoOutput, _) | (_, NoInputNoOutput) => PairingMethod::JustWorks,
+        (DisplayOnly, DisplayOnly | DisplayYesNo) | (DisplayYesNo, DisplayOnly) => {
+            PairingMethod::JustWorks
+        }
+        (DisplayYesNo, DisplayYesNo) => {
+            if secure_connections {
+                PairingMethod::NumericComparison
+            } else {
+                PairingMethod::JustWorks
+            }
+        }
+        (DisplayYesNo | KeyboardDisplay, KeyboardDisplay) => comparison_or(InitiatorDisplays),
+        (KeyboardDisplay, DisplayYesNo) => comparison_or(ResponderDisplays),
+        (DisplayOnly | DisplayYesNo | KeyboardDisplay, KeyboardOnly | KeyboardDisplay) => {
+            PairingMethod::PasskeyEntry(InitiatorDisplays)
+        }
+        (KeyboardOnly, KeyboardOnly) => PairingMethod::PasskeyEntry(BothInput),
+        (KeyboardOnly | KeyboardDisplay, _) => PairingMethod::PasskeyEntry(ResponderDisplays),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_config_and_pairing_method() {
+        assert_eq!(
+            SecurityConfig::new().mitm(true).build(),
+            Err(SecurityConfigError::MITMUnsatisfiable)
+        );
+        assert_eq!(
+            SecurityConfig::new().max_key_size(6).build(),
+            Err(SecurityConfigError::KeySizeOutOfRange)
+        );
+        assert_eq!(
+            SecurityConfig::new()
+                .key_distribution(KeyDistribution::ALL, KeyDistribution::NONE)
+                .build(),
+            Err(SecurityConfigError::KeysWithoutBonding)
+        );
+        let keyboard = SecurityConfig::new()
+            .io_capability(IOCapability::KeyboardDisplay)
+            .bondable(true)
+            .mitm(true)
+            .build()
+            .unwrap();
+        let display = keyboard.io_capability(IOCapability::DisplayYesNo);
+        let just_works = SecurityConfig::new();
+        let (k, d, j) = (
+            keyboard.pairing_parameters(),
+            display.pairing_parameters(),
+            just_works.pairing_parameters(),
+        );
+        assert_eq!(pairing_method(&k, &d), PairingMethod::NumericComparison);
+        assert_eq!(pairing_method(&k, &j), PairingMethod::JustWorks);
+        let (k, d) = (
+            keyboard.secure_connections(false).pairing_parameters(),
+            display.secure_connections(false).pairing_parameters(),
+        );
+        assert_eq!(
+            pairing_method(&k, &d),
+            PairingMethod::PasskeyEntry(PasskeyEntry::ResponderDisplays)
+        );
+        let mut buf = [0_u8; PairingParameters::BYTE_LEN];
+        k.pack_into(&mut buf).unwrap();
+        assert_eq!(PairingParameters::unpack_from(&buf), Ok(k));
+    }
+}
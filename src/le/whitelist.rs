@@ -0,0 +1,192 @@
+//! LE White List. Uses the controller white list when it can hold every entry and falls back to
+//! filtering on the host (with the same semantics) when it can't.
+use crate::le::report::{AddressType, ReportInfo};
+use crate::le::scan::ScanningFilterPolicy;
+use crate::{BTAddress, ConversionError};
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum WhitelistAddressType {
+    Public = 0x00,
+    Random = 0x01,
+    /// Devices sending anonymous (address-less) extended advertisements.
+    Anonymous = 0xFF,
+}
+impl WhitelistAddressType {
+    pub const BYTE_LEN: usize = 1;
+}
+impl From<WhitelistAddressType> for u8 {
+    fn from(t: WhitelistAddressType) -> Self {
+        t as u8
+    }
+}
+impl TryFrom<u8> for WhitelistAddressType {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(WhitelistAddressType::Public),
+            0x01 => Ok(WhitelistAddressType::Random),
+            0xFF => Ok(WhitelistAddressType::Anonymous),
+            _ => Err(ConversionError(())),
+        }
+    }
+}
+impl From<AddressType> for WhitelistAddressType {
+    fn from(a: AddressType) -> Self {
+        match a {
+            AddressType::PublicDevice | AddressType::PublicIdentity => WhitelistAddressType::Public,
+            AddressType::RandomDevice | AddressType::RandomIdentity => WhitelistAddressType::Random,
+        }
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct WhitelistEntry {
+    pub address_type: WhitelistAddressType,
+    /// Ignored for `WhitelistAddressType::Anonymous`.
+    pub address: BTAddress,
+}
+impl WhitelistEntry {
+    pub const BYTE_LEN: usize = WhitelistAddressType::BYTE_LEN + BTAddress::LEN;
+    pub fn new(address_type: WhitelistAddressType, address: BTAddress) -> WhitelistEntry {
+        WhitelistEntry {
+            address_type,
+            address,
+        }
+    }
+    pub fn anonymous() -> WhitelistEntry {
+        WhitelistEntry::new(WhitelistAddressType::Anonymous, BTAddress::ZEROED)
+    }
+    fn normalized(self) -> WhitelistEntry {
+        match self.address_type {
+            WhitelistAddressType::Anonymous => WhitelistEntry::anonymous(),
+            _ => self,
+        }
+    }
+    /// Address type and address packed into one word (address type above the 48 address bits)
+    /// so host filtering compares a single `u64` per entry.
+    fn key(self) -> u64 {
+        let entry = self.normalized();
+        (u64::from(u8::from(entry.address_type)) << 48) | entry.address.to_u64()
+    }
+}
+/// Where whitelist filtering happens.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum FilterLocation {
+    Controller,
+    Host,
+}
+/// White list that is kept in the controller when it fits and emulated on the host otherwise.
+#[derive(Clone, Debug, Default)]
+pub struct Whitelist {
+    entries: BTreeSet<WhitelistEntry>,
+    /// Sorted [`WhitelistEntry::key`]s of `entries`, searched on every report when filtering on
+    /// the host.
+    keys: Vec<u64>,
+    /// Controller white list size or `None` if the controller can't be used (unsupported or
+    /// not usable in the current mode).
+    controller_capacity: Option<usize>,
+}
+impl Whitelist {
+    pub fn new(controller_capacity: Option<usize>) -> Whitelist {
+        Whitelist {
+            entries: BTreeSet::new(),
+            keys: Vec::new(),
+            controller_capacity,
+        }
+    }
+    pub fn set_controller_capacity(&mut self, controller_capacity: Option<usize>) {
+        self.controller_capacity = controller_capacity;
+    }
+    pub fn add(&mut self, entry: WhitelistEntry) -> bool {
+        let key = entry.key();
+        if let Err(i) = self.keys.binary_search(&key) {
+            self.keys.insert(i, key);
+        }
+        self.entries.insert(entry.normalized())
+    }
+    pub fn remove(&mut self, entry: &WhitelistEntry) -> bool {
+        if let Ok(i) = self.keys.binary_search(&entry.key()) {
+            self.keys.remove(i);
+        }
+        self.entries.remove(&entry.normalized())
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.keys.clear();
+    }
+    pub fn entries(&self) -> impl Iterator<Item = &WhitelistEntry> + '_ {
+        self.entries.iter()
+    }
+    pub fn location(&self) -> FilterLocation {
+        match self.controller_capacity {
+            Some(capacity) if self.entries.len() <= capacity => FilterLocation::Controller,
+            _ => FilterLocation::Host,
+        }
+    }
+    /// Scanning filter policy to send to the controller for the `requested` policy. Whitelist
+    /// policies get downgraded when the host is doing the filtering.
+    pub fn scanning_filter_policy(&self, requested: ScanningFilterPolicy) -> ScanningFilterPolicy {
+        match (self.location(), requested) {
+            (FilterLocation::Host, ScanningFilterPolicy::Whitelisted) => ScanningFilterPolicy::All,
+            (FilterLocation::Host, ScanningFilterPolicy::DirectedWhitelisted) => {
+                ScanningFilterPolicy::DirectedAll
+            }
+            (_, policy) => policy,
+        }
+    }
+    pub fn contains(&self, address_type: WhitelistAddressType, address: BTAddress) -> bool {
+        self.keys
+            .binary_search(&WhitelistEntry::new(address_type, address).key())
+            .is_ok()
+    }
+    /// Returns `true` if `report` should be delivered under `policy`. Always `true` when the
+    /// controller is doing the filtering.
+    pub fn allows<T>(&self, policy: ScanningFilterPolicy, report: &ReportInfo<T>) -> bool {
+        match (self.location(), policy) {
+            (FilterLocation::Controller, _)
+            | (_, ScanningFilterPolicy::All | ScanningFilterPolicy::DirectedAll) => true,
+            (FilterLocation::Host, _) => self.contains(report.address_type.into(), report.address),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_filtering() {
+        let address = BTAddress::from_u64(0x11);
+        let mut whitelist = Whitelist::new(Some(1));
+        whitelist.add(WhitelistEntry::new(WhitelistAddressType::Public, address));
+        // The address is ignored for anonymous entries.
+        whitelist.add(WhitelistEntry::new(
+            WhitelistAddressType::Anonymous,
+            BTAddress::from_u64(0x22),
+        ));
+        assert_eq!(whitelist.entries().count(), 2);
+        assert_eq!(whitelist.location(), FilterLocation::Host);
+        assert!(whitelist.contains(WhitelistAddressType::Anonymous, BTAddress::ZEROED));
+
+        let mut report: ReportInfo = ReportInfo {
+            address_type: AddressType::PublicDevice,
+            address,
+            ..ReportInfo::default()
+        };
+        assert!(whitelist.allows(ScanningFilterPolicy::Whitelisted, &report));
+        report.address_type = AddressType::RandomDevice;
+        assert!(!whitelist.allows(ScanningFilterPolicy::Whitelisted, &report));
+        assert!(whitelist.allows(ScanningFilterPolicy::All, &report));
+
+        // The controller filters once it fits.
+        whitelist.remove(&WhitelistEntry::anonymous());
+        assert_eq!(whitelist.location(), FilterLocation::Controller);
+        assert!(whitelist.allows(ScanningFilterPolicy::Whitelisted, &report));
+        assert_eq!(
+            whitelist.scanning_filter_policy(ScanningFilterPolicy::Whitelisted),
+            ScanningFilterPolicy::Whitelisted
+        );
+    }
+}
@@ -28,6 +28,8 @@ pub type LocalBoxFuture<'a, T> = core::pin::Pin<Box<dyn core::future::Future<Out
 /// Workaround for returning streams from async Traits.
 pub type BoxStream<'a, T> = core::pin::Pin<Box<dyn Stream<Item = T> + 'a>>;
 extern crate core;
+#[macro_use]
+mod trace;
 pub mod bytes;
 pub mod channel;
 #[cfg(feature = "classic")]
@@ -37,7 +39,13 @@ pub mod dbus;
 pub mod error;
 #[cfg(feature = "hci")]
 pub mod hci;
+#[cfg(feature = "hci")]
+pub mod host;
 pub mod le;
+pub mod strictness;
+pub mod sync;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod uri;
 pub mod uuid;
 #[cfg(feature = "winrt_drivers")]
@@ -53,6 +61,8 @@ pub enum PackError {
     BadLength { expected: usize, got: usize },
     BadBytes { index: Option<usize> },
     InvalidFields,
+    /// HCI command parameters longer than the 8-bit length field can describe.
+    ParametersTooLong { len: usize },
 }
 impl PackError {
     /// Ensure `buf.len() == expected`. Returns `Ok(())` if they are equal or
@@ -72,7 +82,7 @@ impl PackError {
     /// `Err(HCIPackError::BadLength)` not.
     #[inline]
     pub fn atleast_length(expected: usize, buf: &[u8]) -> Result<(), PackError> {
-        if buf.len() == expected {
+        if buf.len() >= expected {
             Ok(())
         } else {
             Err(PackError::BadLength {
@@ -86,8 +96,24 @@ impl PackError {
     pub fn bad_index(index: usize) -> PackError {
         PackError::BadBytes { index: Some(index) }
     }
+    /// Shifts the index of a `BadBytes` error by `offset` (for errors of a field unpacked from a
+    /// sub slice).
+    #[inline]
+    pub fn offset_index(self, offset: usize) -> PackError {
+        match self {
+            PackError::BadBytes { index: Some(index) } => PackError::bad_index(index + offset),
+            e => e,
+        }
+    }
+}
+impl core::fmt::Display for PackError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "pack error {:?}", self)
+    }
 }
 impl crate::error::Error for PackError {}
+#[cfg(feature = "std")]
+impl std::error::Error for PackError {}
 
 /// Basic `ConversionError` for when primitives can't be converted to/from bytes because of invalid
 /// states. Most modules use their own errors for when there is more information to report.
@@ -295,3 +321,28 @@ impl crate::bytes::ToFromBytesEndian for CompanyID {
         Some(CompanyID(u16::from_bytes_be(bytes)?))
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atleast_length() {
+        assert_eq!(PackError::atleast_length(2, &[0x01, 0x02]), Ok(()));
+        // Longer buffers carry more fields after the checked ones.
+        assert_eq!(PackError::atleast_length(2, &[0x01, 0x02, 0x03]), Ok(()));
+        assert_eq!(
+            PackError::atleast_length(2, &[0x01]),
+            Err(PackError::BadLength {
+                expected: 2,
+                got: 1
+            })
+        );
+        assert_eq!(
+            PackError::expect_length(2, &[0x01, 0x02, 0x03]),
+            Err(PackError::BadLength {
+                expected: 2,
+                got: 3
+            })
+        );
+    }
+}
@@ -0,0 +1,50 @@
+//! How closely peers are held to the spec. Real devices send bad lengths and unexpected PDUs
+//! often enough that refusing them all makes some of them unusable, so parsers and protocol state
+//! machines that see a violation they can recover from ask a [`Strictness`] what to do.
+//!
+//! Violations that can't be recovered from (a packet too short to read for example) are errors
+//! whatever the policy.
+
+/// What to do with a recoverable spec violation from a peer.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub enum Strictness {
+    /// Return the error (the default).
+    #[default]
+    Strict,
+    /// Log the violation and recover.
+    Lenient,
+    /// Recover without logging.
+    Permissive,
+}
+impl Strictness {
+    /// `Err(error)` if violations are errors. Otherwise logs `error` (if `Lenient`) and returns
+    /// `Ok(())` so the caller recovers.
+    /// # Errors
+    /// returns `error` if `self` is `Strict`.
+    pub fn violation<E: core::fmt::Debug>(self, error: E) -> Result<(), E> {
+        match self {
+            Strictness::Strict => Err(error),
+            Strictness::Lenient => {
+                hci_warn!("tolerating spec violation {:?}", error);
+                Ok(())
+            }
+            Strictness::Permissive => Ok(()),
+        }
+    }
+    /// `true` if violations are recovered from.
+    pub fn tolerates(self) -> bool {
+        self != Strictness::Strict
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::Strictness;
+
+    #[test]
+    fn test_violation() {
+        assert_eq!(Strictness::Strict.violation(1_u8), Err(1));
+        assert_eq!(Strictness::Lenient.violation(1_u8), Ok(()));
+        assert_eq!(Strictness::Permissive.violation(1_u8), Ok(()));
+        assert!(!Strictness::default().tolerates());
+    }
+}
@@ -0,0 +1,143 @@
+//! Locks for state shared between adapter handles, picked by feature so the same modules work
+//! thread safe on `std` and interrupt safe on embedded targets.
+//!
+//! [`Mutex`] guards short, non blocking critical sections through a closure (a critical section
+//! can't hand out a guard):
+//!
+//! | feature            | backend                                   |
+//! |--------------------|-------------------------------------------|
+//! | `critical-section` | `critical_section::Mutex<RefCell<T>>`     |
+//! | `parking_lot`      | `parking_lot::Mutex`                      |
+//! | `std`              | `std::sync::Mutex` (poisoning is ignored) |
+//! | none               | `RefCell` (single threaded, not `Sync`)   |
+//!
+//! [`AsyncMutex`] is held across `.await`s (for example for a whole command and its return). It is
+//! `tokio::sync::Mutex` with the `tokio-mutex` feature and `futures_util`'s mutex otherwise.
+
+#[cfg(feature = "critical-section")]
+type Inner<T> = critical_section::Mutex<core::cell::RefCell<T>>;
+#[cfg(all(not(feature = "critical-section"), feature = "parking_lot"))]
+type Inner<T> = parking_lot::Mutex<T>;
+#[cfg(all(
+    not(feature = "critical-section"),
+    not(feature = "parking_lot"),
+    feature = "std"
+))]
+type Inner<T> = std::sync::Mutex<T>;
+#[cfg(not(any(feature = "critical-section", feature = "parking_lot", feature = "std")))]
+type Inner<T> = core::cell::RefCell<T>;
+
+/// Mutual exclusion for short critical sections. See the module docs for the backends.
+pub struct Mutex<T> {
+    inner: Inner<T>,
+}
+impl<T> Mutex<T> {
+    // Only `critical-section` wraps the value (in a `RefCell`).
+    #[allow(clippy::useless_conversion)]
+    pub fn new(value: T) -> Mutex<T> {
+        Mutex {
+            inner: Inner::new(value.into()),
+        }
+    }
+}
+#[cfg(feature = "critical-section")]
+impl<T> Mutex<T> {
+    /// Runs `f` with exclusive access to the value, with interrupts disabled. Keep `f` short.
+    /// # Panics
+    /// Panics if `f` locks the same mutex again.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        critical_section::with(|cs| f(&mut self.inner.borrow_ref_mut(cs)))
+    }
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut().get_mut()
+    }
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner().into_inner()
+    }
+}
+#[cfg(all(not(feature = "critical-section"), feature = "parking_lot"))]
+impl<T> Mutex<T> {
+    /// Runs `f` with exclusive access to the value. `f` shouldn't block.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.inner.lock())
+    }
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+#[cfg(all(
+    not(feature = "critical-section"),
+    not(feature = "parking_lot"),
+    feature = "std"
+))]
+impl<T> Mutex<T> {
+    /// Runs `f` with exclusive access to the value. `f` shouldn't block. A panic in another
+    /// `f` doesn't poison the value.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self
+            .inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner))
+    }
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner
+            .get_mut()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+    pub fn into_inner(self) -> T {
+        self.inner
+            .into_inner()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+#[cfg(not(any(feature = "critical-section", feature = "parking_lot", feature = "std")))]
+impl<T> Mutex<T> {
+    /// Runs `f` with exclusive access to the value.
+    /// # Panics
+    /// Panics if `f` locks the same mutex again.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.inner.borrow_mut())
+    }
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+impl<T> core::fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Mutex { .. }")
+    }
+}
+#[cfg(all(not(feature = "tokio-mutex"), feature = "std"))]
+pub use futures_util::lock::{Mutex as AsyncMutex, MutexGuard as AsyncMutexGuard};
+#[cfg(feature = "tokio-mutex")]
+pub use tokio::sync::{Mutex as AsyncMutex, MutexGuard as AsyncMutexGuard};
+
+#[cfg(test)]
+mod tests {
+    use super::Mutex;
+
+    #[test]
+    fn test_lock() {
+        let mut mutex = Mutex::new(1_u32);
+        assert_eq!(
+            mutex.lock(|value| {
+                *value += 1;
+                *value
+            }),
+            2
+        );
+        *mutex.get_mut() += 1;
+        assert_eq!(mutex.into_inner(), 3);
+    }
+}
@@ -0,0 +1,230 @@
+//! Differential testing of [`AttServer`] against ATT exchanges taken from another stack (BlueZ,
+//! qualification test tools). A corpus is plain text with one exchange per line:
+//!
+//! ```text
+//! # comment
+//! read_invalid_handle: 0a 00 01 => 01 0a 00 01 01
+//! write_cmd: 52 04 00 01 00 => -
+//! ```
+//!
+//! `-` means no response. Only the response codes are compared (response opcode or the error's
+//! request opcode, handle and code) since values and MTUs differ between stacks. [`fuzz`] mutates
+//! the corpus requests and checks the rules every response has to follow.
+//!
+//! The bundled `corpus/att_regression.txt` is hand written, so it only guards against regressions
+//! and doesn't prove agreement with another stack.
+use crate::le::att::Opcode;
+use crate::le::gatt::att_server::{self, AttServer};
+use crate::le::gatt::server::GattServer;
+use crate::testing::DeterministicRng;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct CorpusCase {
+    pub name: String,
+    pub request: Vec<u8>,
+    pub response: Option<Vec<u8>>,
+}
+/// Line (starting at 1) of a corpus that couldn't be parsed.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct CorpusError {
+    pub line: usize,
+}
+impl core::fmt::Display for CorpusError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "bad ATT corpus line {}", self.line)
+    }
+}
+impl crate::error::Error for CorpusError {}
+#[cfg(feature = "std")]
+impl std::error::Error for CorpusError {}
+fn parse_hex(bytes: &str) -> Option<Vec<u8>> {
+    bytes
+        .split_whitespace()
+        .map(|b| u8::from_str_radix(b, 16).ok())
+        .collect()
+}
+pub fn parse_corpus(corpus: &str) -> Result<Vec<CorpusCase>, CorpusError> {
+    let mut cases = Vec::new();
+    for (i, line) in corpus.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = CorpusError { line: i + 1 };
+        let (name, exchange) = line.split_once(':').ok_or(error)?;
+        let (request, response) = exchange.split_once("=>").ok_or(error)?;
+        let response = match response.trim() {
+            "-" => None,
+            response => Some(parse_hex(response).ok_or(error)?),
+        };
+        cases.push(CorpusCase {
+            name: name.trim().to_string(),
+            request: parse_hex(request).filter(|r| !r.is_empty()).ok_or(error)?,
+            response,
+        });
+    }
+    Ok(cases)
+}
+/// What the stacks are compared on.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ResponseCode {
+    NoResponse,
+    Response(u8),
+    Error {
+        request_opcode: u8,
+        handle: u16,
+        code: u8,
+    },
+    /// Error Response with the wrong length.
+    MalformedError,
+}
+impl ResponseCode {
+    pub fn of(response: Option<&[u8]>) -> ResponseCode {
+        match response {
+            None | Some([]) => ResponseCode::NoResponse,
+            Some(&[opcode, request_opcode, h0, h1, code])
+                if opcode == u8::from(Opcode::ErrorRsp) =>
+            {
+                ResponseCode::Error {
+                    request_opcode,
+                    handle: u16::from_le_bytes([h0, h1]),
+                    code,
+                }
+            }
+            Some([opcode, ..]) if *opcode == u8::from(Opcode::ErrorRsp) => {
+                ResponseCode::MalformedError
+            }
+            Some([opcode, ..]) => ResponseCode::Response(*opcode),
+        }
+    }
+}
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct Mismatch {
+    pub name: String,
+    pub expected: ResponseCode,
+    pub got: ResponseCode,
+}
+/// Runs every case in order against `server` (so an MTU exchange affects later cases) and
+/// returns the cases that got a different response code.
+pub fn run_corpus(
+    att: &mut AttServer,
+    server: &mut GattServer,
+    cases: &[CorpusCase],
+) -> Vec<Mismatch> {
+    cases
+        .iter()
+        .filter_map(|case| {
+            let expected = ResponseCode::of(case.response.as_deref());
+            let got = ResponseCode::of(att.handle(server, &case.request).as_deref());
+            if expected == got {
+                None
+            } else {
+                Some(Mismatch {
+                    name: case.name.clone(),
+                    expected,
+                    got,
+                })
+            }
+        })
+        .collect()
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ViolationKind {
+    /// A request got no response.
+    MissingResponse,
+    /// A command (or other PDU a server doesn't answer) got a response.
+    UnexpectedResponse,
+    /// The response is longer than the ATT_MTU.
+    TooLong,
+    /// The response isn't the matching response or an Error Response for the request.
+    WrongResponse,
+}
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    pub request: Vec<u8>,
+    pub response: Option<Vec<u8>>,
+}
+fn check(request: &[u8], response: Option<&[u8]>, mtu: u16) -> Option<ViolationKind> {
+    let opcode = *request.first()?;
+    match response {
+        None if att_server::expects_response(opcode) => Some(ViolationKind::MissingResponse),
+        None => None,
+        Some(_) if !att_server::expects_response(opcode) => Some(ViolationKind::UnexpectedResponse),
+        Some(response) if response.len() > usize::from(mtu) => Some(ViolationKind::TooLong),
+        Some(response) => match ResponseCode::of(Some(response)) {
+            ResponseCode::Error { request_opcode, .. } if request_opcode == opcode => None,
+            // Every implemented request's response opcode is the request opcode + 1.
+            ResponseCode::Response(r) if r == opcode.wrapping_add(1) => None,
+            _ => Some(ViolationKind::WrongResponse),
+        },
+    }
+}
+fn mutate(rng: &mut DeterministicRng, request: &mut Vec<u8>) {
+    let random_byte = |rng: &mut DeterministicRng| rng.next_u32().to_le_bytes()[0];
+    match rng.below(4) {
+        0 => {
+            let i = rng.below_usize(request.len());
+            request[i] ^= 1 << rng.below(8);
+        }
+        1 => request.truncate(rng.below_usize(request.len()) + 1),
+        2 => {
+            for _ in 0..=rng.below(24) {
+                request.push(random_byte(rng));
+            }
+        }
+        _ => request[0] = random_byte(rng),
+    }
+}
+/// Sends `iterations` mutated corpus requests to `server` and returns the first response that
+/// breaks the rules.
+pub fn fuzz(
+    att: &mut AttServer,
+    server: &mut GattServer,
+    cases: &[CorpusCase],
+    rng: &mut DeterministicRng,
+    iterations: usize,
+) -> Result<(), Violation> {
+    if cases.is_empty() {
+        return Ok(());
+    }
+    for _ in 0..iterations {
+        let mut request = cases[rng.below_usize(cases.len())].request.clone();
+        for _ in 0..=rng.below(2) {
+            mutate(rng, &mut request);
+        }
+        let response = att.handle(server, &request);
+        if let Some(kind) = check(&request, response.as_deref(), att.mtu()) {
+            return Err(Violation {
+                kind,
+                request,
+                response,
+            });
+        }
+    }
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const REGRESSION_CORPUS: &str = include_str!("corpus/att_regression.txt");
+    #[test]
+    fn test_regression_corpus() {
+        let cases = parse_corpus(REGRESSION_CORPUS).unwrap();
+        let mut server = GattServer::new();
+        let mut att = AttServer::default();
+        assert_eq!(run_corpus(&mut att, &mut server, &cases), Vec::new());
+        assert_eq!(
+            parse_corpus("bad: 0a zz => -"),
+            Err(CorpusError { line: 1 })
+        );
+
+        let mut rng = DeterministicRng::new(503);
+        assert_eq!(
+            fuzz(&mut att, &mut server, &cases, &mut rng, 10_000),
+            Ok(())
+        );
+    }
+}
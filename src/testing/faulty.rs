@@ -0,0 +1,235 @@
+//! [`FaultyTransport`] wraps any HCI byte transport and injects faults (partial writes, short
+//! reads, bit flips, stalls and EOF in the middle of a packet) to harden the parsers and adapters.
+//! Faults are picked by a [`DeterministicRng`] so a failing seed can be replayed.
+use crate::hci::adapter;
+use crate::hci::stream::{HCIReader, HCIWriter};
+use crate::testing::DeterministicRng;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Probabilities are per operation, in parts per [`FaultConfig::DENOMINATOR`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+pub struct FaultConfig {
+    /// Write only some of the bytes (the caller has to write the rest).
+    pub partial_write: u32,
+    /// Return only some of the bytes read. The rest are returned by the next read.
+    pub short_read: u32,
+    /// Flip one bit of the bytes read.
+    pub bit_flip: u32,
+    /// Return `Poll::Pending` once (waking immediately).
+    pub stall: u32,
+    /// Return part of a read and then end the stream.
+    pub eof: u32,
+}
+impl FaultConfig {
+    pub const DENOMINATOR: u32 = 1000;
+    pub const NONE: FaultConfig = FaultConfig {
+        partial_write: 0,
+        short_read: 0,
+        bit_flip: 0,
+        stall: 0,
+        eof: 0,
+    };
+}
+/// Fault that was injected. Kept in [`FaultyTransport::faults`] to help debug failures.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum Fault {
+    PartialWrite { requested: usize, written: usize },
+    ShortRead { read: usize, returned: usize },
+    BitFlip { index: usize, bit: u8 },
+    StallRead,
+    StallWrite,
+    EOF { read: usize, returned: usize },
+}
+#[derive(Clone, Debug)]
+pub struct FaultyTransport<T> {
+    inner: T,
+    config: FaultConfig,
+    seed: u64,
+    rng: DeterministicRng,
+    faults: Vec<Fault>,
+    /// Bytes held back by a short read.
+    pending: Vec<u8>,
+    eof: bool,
+}
+impl<T> FaultyTransport<T> {
+    pub fn new(inner: T, config: FaultConfig, seed: u64) -> FaultyTransport<T> {
+        FaultyTransport {
+            inner,
+            config,
+            seed,
+            rng: DeterministicRng::new(seed),
+            faults: Vec::new(),
+            pending: Vec::new(),
+            eof: false,
+        }
+    }
+    /// Seed the faults were picked with. Passing it to [`FaultyTransport::new`] with the same
+    /// config and traffic reproduces the same faults.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+    pub fn config(&self) -> &FaultConfig {
+        &self.config
+    }
+    pub fn config_mut(&mut self) -> &mut FaultConfig {
+        &mut self.config
+    }
+    pub fn faults(&self) -> &[Fault] {
+        &self.faults[..]
+    }
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+    fn roll(&mut self, probability: u32) -> bool {
+        self.rng.chance(probability, FaultConfig::DENOMINATOR)
+    }
+    /// Random length in `1..len` (for `len > 1`).
+    fn cut(&mut self, len: usize) -> usize {
+        1 + self.rng.below_usize(len - 1)
+    }
+    fn stall(&mut self, cx: &mut Context<'_>, fault: Fault) -> bool {
+        if self.roll(self.config.stall) {
+            self.faults.push(fault);
+            cx.waker().wake_by_ref();
+            true
+        } else {
+            false
+        }
+    }
+    /// Applies read faults to the `len` bytes in `buf`. Returns how many bytes to return.
+    fn corrupt_read(&mut self, buf: &mut [u8], len: usize) -> usize {
+        if len == 0 {
+            return 0;
+        }
+        if self.roll(self.config.bit_flip) {
+            let index = self.rng.below_usize(len);
+            let bit = u8::try_from(self.rng.below(8)).expect("less than 8");
+            buf[index] ^= 1 << bit;
+            self.faults.push(Fault::BitFlip { index, bit });
+        }
+        if len > 1 && self.roll(self.config.eof) {
+            let returned = self.cut(len);
+            self.faults.push(Fault::EOF {
+                read: len,
+                returned,
+            });
+            self.eof = true;
+            return returned;
+        }
+        if len > 1 && self.roll(self.config.short_read) {
+            let returned = self.cut(len);
+            self.faults.push(Fault::ShortRead {
+                read: len,
+                returned,
+            });
+            self.pending.extend_from_slice(&buf[returned..len]);
+            return returned;
+        }
+        len
+    }
+}
+impl<T: HCIReader> HCIReader for FaultyTransport<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let this = self.get_mut();
+        if this.eof {
+            return Poll::Ready(Ok(0));
+        }
+        if this.stall(cx, Fault::StallRead) {
+            return Poll::Pending;
+        }
+        if !this.pending.is_empty() {
+            let amount = this.pending.len().min(buf.len());
+            buf[..amount].copy_from_slice(&this.pending[..amount]);
+            this.pending.drain(..amount);
+            return Poll::Ready(Ok(amount));
+        }
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(len)) => Poll::Ready(Ok(this.corrupt_read(buf, len))),
+            other => other,
+        }
+    }
+}
+impl<T: HCIWriter + Unpin> HCIWriter for FaultyTransport<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let this = self.get_mut();
+        if this.eof {
+            return Poll::Ready(Err(adapter::Error::ChannelClosed));
+        }
+        if this.stall(cx, Fault::StallWrite) {
+            return Poll::Pending;
+        }
+        let mut len = buf.len();
+        if len > 1 && this.roll(this.config.partial_write) {
+            let written = this.cut(len);
+            this.faults.push(Fault::PartialWrite {
+                requested: len,
+                written,
+            });
+            len = written;
+        }
+        Pin::new(&mut this.inner).poll_write(cx, &buf[..len])
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), adapter::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::task::noop_waker_ref;
+    struct Loopback(Vec<u8>);
+    impl HCIReader for Loopback {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<Result<usize, adapter::Error>> {
+            let amount = self.0.len().min(buf.len());
+            buf[..amount].copy_from_slice(&self.0[..amount]);
+            Poll::Ready(Ok(amount))
+        }
+    }
+    fn read_all(seed: u64) -> (Vec<u8>, Vec<Fault>) {
+        let config = FaultConfig {
+            short_read: 500,
+            bit_flip: 500,
+            stall: 200,
+            ..FaultConfig::NONE
+        };
+        let mut transport = FaultyTransport::new(Loopback((0..32).collect()), config, seed);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut out = Vec::new();
+        let mut buf = [0_u8; 32];
+        for _ in 0..8 {
+            if let Poll::Ready(Ok(len)) = Pin::new(&mut transport).poll_read(&mut cx, &mut buf) {
+                out.extend_from_slice(&buf[..len]);
+            }
+        }
+        (out, transport.faults().to_vec())
+    }
+    #[test]
+    fn test_faults_reproducible() {
+        let (out, faults) = read_all(42);
+        assert!(!faults.is_empty());
+        assert_eq!(read_all(42), (out, faults));
+        assert_ne!(read_all(43).1, read_all(42).1);
+    }
+}
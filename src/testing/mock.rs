@@ -0,0 +1,252 @@
+//! [`MockTransport`] is a scripted fake controller. Tests queue the command bytes they expect the
+//! host to write (with the events to answer each one with) and inject events through a
+//! [`MockHandle`] while the transport itself is owned by a [`Stream`](crate::hci::stream::Stream).
+//! Set [`Filter`]s are recorded so tests can check them.
+use crate::error::IOError;
+use crate::hci::adapter;
+use crate::hci::command::Command;
+use crate::hci::event::EventCode;
+use crate::hci::packet::PacketType;
+use crate::hci::stream::{Filter, HCIFilterable, HCIReader, HCIWriter};
+use crate::hci::{Opcode, StreamError};
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::convert::TryFrom;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+/// H4 event packet with `parameters`.
+pub fn event_packet(event_code: EventCode, parameters: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(parameters.len() + 3);
+    packet.push(PacketType::Event.into());
+    packet.push(event_code.into());
+    packet.push(u8::try_from(parameters.len()).expect("event parameters too long"));
+    packet.extend_from_slice(parameters);
+    packet
+}
+/// H4 Command Complete event for `opcode` (allowing one more command) with `return_parameters`
+/// (starting with the status).
+pub fn command_complete(opcode: Opcode, return_parameters: &[u8]) -> Vec<u8> {
+    let mut parameters = Vec::with_capacity(return_parameters.len() + 3);
+    parameters.push(1);
+    parameters.extend_from_slice(&[0_u8; 2]);
+    opcode
+        .pack(&mut parameters[1..3])
+        .expect("hardcoded opcode length");
+    parameters.extend_from_slice(return_parameters);
+    event_packet(EventCode::CommandComplete, &parameters)
+}
+
+#[derive(Clone, Debug)]
+struct Expectation {
+    bytes: Vec<u8>,
+    responses: Vec<Vec<u8>>,
+}
+#[derive(Debug, Default)]
+struct MockState {
+    expectations: VecDeque<Expectation>,
+    /// Written bytes not matched to an expectation yet.
+    written: Vec<u8>,
+    to_read: VecDeque<Vec<u8>>,
+    filters: Vec<Filter>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+impl MockState {
+    fn queue_read(&mut self, packet: Vec<u8>) {
+        self.to_read.push_back(packet);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+    /// Matches written bytes against the expectations.
+    fn check_written(&mut self) {
+        while let Some(expectation) = self.expectations.front() {
+            let len = expectation.bytes.len().min(self.written.len());
+            assert_eq!(
+                &self.written[..len],
+                &expectation.bytes[..len],
+                "host wrote unexpected bytes"
+            );
+            if len < expectation.bytes.len() {
+                return;
+            }
+            self.written.drain(..len);
+            let expectation = self.expectations.pop_front().expect("checked above");
+            for response in expectation.responses {
+                self.queue_read(response);
+            }
+        }
+    }
+}
+/// Controls a [`MockTransport`]. Cloning gives another handle to the same transport.
+#[derive(Clone, Debug)]
+pub struct MockHandle {
+    state: Rc<RefCell<MockState>>,
+}
+impl MockHandle {
+    /// Expects the host to write `bytes` next and answers with the `responses` packets once it
+    /// has.
+    pub fn expect(&self, bytes: &[u8], responses: &[&[u8]]) {
+        let mut state = self.state.borrow_mut();
+        state.expectations.push_back(Expectation {
+            bytes: bytes.to_vec(),
+            responses: responses.iter().map(|r| r.to_vec()).collect(),
+        });
+        // The bytes might already be written.
+        state.check_written();
+    }
+    /// Expects `command` (as an H4 packet) to be sent next.
+    pub fn expect_command<C: Command>(&self, command: &C, responses: &[&[u8]]) {
+        let mut bytes = vec![0_u8; command.packet_byte_len()];
+        command
+            .packet_pack_into(&mut bytes)
+            .expect("command packs into its own length");
+        self.expect(&bytes, responses);
+    }
+    /// Queues an H4 packet (indicator byte first) for the host to read.
+    pub fn inject(&self, packet: &[u8]) {
+        self.state.borrow_mut().queue_read(packet.to_vec());
+    }
+    pub fn inject_event(&self, event_code: EventCode, parameters: &[u8]) {
+        self.inject(&event_packet(event_code, parameters));
+    }
+    /// Ends the read side after the already queued packets.
+    pub fn close(&self) {
+        let mut state = self.state.borrow_mut();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+    /// Every filter set, oldest first.
+    pub fn filters(&self) -> Vec<Filter> {
+        self.state.borrow().filters.clone()
+    }
+    /// Takes the written bytes no expectation matched (yet).
+    pub fn take_written(&self) -> Vec<u8> {
+        core::mem::take(&mut self.state.borrow_mut().written)
+    }
+    pub fn pending_expectations(&self) -> usize {
+        self.state.borrow().expectations.len()
+    }
+    /// Panics if an expectation wasn't met or bytes were written without being expected.
+    pub fn assert_done(&self) {
+        let state = self.state.borrow();
+        assert!(
+            state.expectations.is_empty(),
+            "{} expectations not met, next: {:02X?}",
+            state.expectations.len(),
+            state.expectations.front().map(|e| &e.bytes)
+        );
+        assert!(
+            state.written.is_empty(),
+            "unexpected bytes written: {:02X?}",
+            state.written
+        );
+    }
+}
+/// Fake HCI transport. See the module docs.
+#[derive(Debug)]
+pub struct MockTransport {
+    state: Rc<RefCell<MockState>>,
+}
+impl MockTransport {
+    pub fn new() -> (MockTransport, MockHandle) {
+        let transport = MockTransport {
+            state: Rc::default(),
+        };
+        let handle = transport.handle();
+        (transport, handle)
+    }
+    pub fn handle(&self) -> MockHandle {
+        MockHandle {
+            state: self.state.clone(),
+        }
+    }
+}
+impl HCIReader for MockTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let mut state = self.state.borrow_mut();
+        match state.to_read.pop_front() {
+            Some(packet) => Poll::Ready(match buf.get_mut(..packet.len()) {
+                Some(out) => {
+                    out.copy_from_slice(&packet);
+                    Ok(packet.len())
+                }
+                None => Err(IOError::Overflow.into()),
+            }),
+            None if state.closed => Poll::Ready(Err(StreamError::StreamClosed.into())),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+impl HCIWriter for MockTransport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let mut state = self.state.borrow_mut();
+        state.written.extend_from_slice(buf);
+        state.check_written();
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), adapter::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+impl HCIFilterable for MockTransport {
+    fn set_filter(self: Pin<&mut Self>, filter: &Filter) -> Result<(), adapter::Error> {
+        self.state.borrow_mut().filters.push(*filter);
+        Ok(())
+    }
+
+    fn get_filter(self: Pin<&Self>) -> Result<Filter, adapter::Error> {
+        Ok(self
+            .state
+            .borrow()
+            .filters
+            .last()
+            .copied()
+            .unwrap_or_default())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::adapters::Adapter;
+    use crate::hci::baseband::Reset;
+    use crate::hci::stream::Stream;
+    use futures_util::FutureExt;
+
+    #[test]
+    fn test_scripted_reset() {
+        let (mut transport, handle) = MockTransport::new();
+        Pin::new(&mut transport)
+            .set_filter(&Filter::all_events())
+            .unwrap();
+        let complete = command_complete(Reset::opcode(), &[0x00]);
+        handle.expect_command(&Reset {}, &[&complete]);
+        let mut adapter = Adapter::new(Stream::new(Box::pin(transport)));
+        // Every step is ready right away so one poll runs it to the end.
+        adapter.reset().now_or_never().unwrap().unwrap();
+        handle.assert_done();
+        assert_eq!(handle.filters(), [Filter::all_events()]);
+
+        handle.close();
+        let event: Result<crate::hci::event::EventPacket<Box<[u8]>>, _> =
+            adapter.hci_read_event().now_or_never().unwrap();
+        assert!(event.is_err());
+    }
+}
@@ -0,0 +1,80 @@
+//! Tools for testing code built on top of this crate without real hardware. Enabled with the
+//! `testing` feature.
+pub mod att_diff;
+pub mod faulty;
+pub mod mock;
+pub mod timing;
+pub mod virtual_controller;
+
+use core::convert::TryFrom;
+#[cfg(feature = "std")]
+use core::future::Future;
+#[cfg(feature = "std")]
+use core::task::{Context, Poll};
+
+/// Small deterministic PRNG (xorshift64*) so simulations and fault injection can be reproduced
+/// from a seed. Not for anything security related.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct DeterministicRng(u64);
+impl DeterministicRng {
+    const MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+    pub fn new(seed: u64) -> DeterministicRng {
+        // A zero state would only ever produce zeros.
+        match seed ^ Self::MIX {
+            0 => DeterministicRng(Self::MIX),
+            state => DeterministicRng(state),
+        }
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+    /// Uniform-ish value in `0..n`. Returns `0` if `n == 0`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        if n == 0 {
+            0
+        } else {
+            self.next_u64() % n
+        }
+    }
+    /// Same as [`DeterministicRng::below`] but for `usize`s (indices, lengths).
+    pub fn below_usize(&mut self, n: usize) -> usize {
+        usize::try_from(self.below(n as u64)).expect("less than a usize")
+    }
+    /// Returns `true` with probability `numerator / denominator`.
+    pub fn chance(&mut self, numerator: u32, denominator: u32) -> bool {
+        denominator != 0 && self.below(u64::from(denominator)) < u64::from(numerator)
+    }
+}
+/// Runs `future` to completion on the current thread, parking it while the future is pending.
+/// Enough for tests woken by other threads without pulling in an executor.
+#[cfg(feature = "std")]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    use alloc::boxed::Box;
+    use futures_util::task::{waker, ArcWake};
+    use std::sync::Arc;
+    use std::thread::{self, Thread};
+
+    struct Unpark(Thread);
+    impl ArcWake for Unpark {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.unpark();
+        }
+    }
+    let waker = waker(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
@@ -0,0 +1,236 @@
+//! Advertising channel timing model. Simulates one legacy advertiser (advertising interval plus
+//! the random 0-10 ms `advDelay`) against one scanner (scan interval/window rotating through the
+//! advertising channels) so tests can check discovery latency and reproduce timing dependent
+//! problems like scan responses arriving after the scan window closed.
+//!
+//! Everything runs on a simulated clock (a `Duration` since the start of the simulation) and all
+//! randomness comes from a seed.
+use crate::testing::DeterministicRng;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::time::Duration;
+
+/// Inter Frame Space.
+pub const T_IFS: Duration = Duration::from_micros(150);
+/// Maximum random delay added to every advertising interval.
+pub const MAX_ADV_DELAY: Duration = Duration::from_micros(MAX_ADV_DELAY_MICROS);
+const MAX_ADV_DELAY_MICROS: u64 = 10_000;
+pub const ADVERTISING_CHANNELS: [u8; 3] = [37, 38, 39];
+/// `SCAN_REQ` payload length (ScanA + AdvA).
+pub const SCAN_REQ_PAYLOAD_LEN: usize = 12;
+/// Air time of a LE 1M PDU with a `payload_len` byte payload (preamble, access address, header
+/// and CRC included).
+pub fn air_time_1m(payload_len: usize) -> Duration {
+    Duration::from_micros(((1 + 4 + 2 + payload_len + 3) * 8) as u64)
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct AdvertiserTiming {
+    pub interval: Duration,
+    /// Advertising PDU payload length (AdvA + AdvData).
+    pub adv_payload_len: usize,
+    /// Scan response payload length (AdvA + ScanRspData) or `None` if not scannable.
+    pub scan_rsp_payload_len: Option<usize>,
+    /// Extra time between the end of one channel's PDU (or scan response) and the next channel.
+    pub channel_gap: Duration,
+}
+impl AdvertiserTiming {
+    pub fn new(interval: Duration, adv_data_len: usize) -> AdvertiserTiming {
+        AdvertiserTiming {
+            interval,
+            adv_payload_len: 6 + adv_data_len,
+            scan_rsp_payload_len: None,
+            channel_gap: T_IFS + air_time_1m(SCAN_REQ_PAYLOAD_LEN) + T_IFS,
+        }
+    }
+    #[must_use]
+    pub fn with_scan_response(mut self, scan_rsp_data_len: usize) -> AdvertiserTiming {
+        self.scan_rsp_payload_len = Some(6 + scan_rsp_data_len);
+        self
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct ScannerTiming {
+    pub interval: Duration,
+    pub window: Duration,
+    /// Active scanners send `SCAN_REQ`s to scannable advertisers.
+    pub active: bool,
+}
+impl ScannerTiming {
+    /// Channel being scanned at `time` (`time` relative to when scanning started).
+    pub fn channel_at(&self, time: Duration) -> Option<u8> {
+        let interval = self.interval.as_nanos().max(1);
+        let n = time.as_nanos() / interval;
+        let into = time.as_nanos() % interval;
+        if into < self.window.as_nanos() {
+            Some(ADVERTISING_CHANNELS[(n % 3) as usize])
+        } else {
+            None
+        }
+    }
+    /// Returns `true` if the scanner is listening on `channel` for all of `start..end`.
+    pub fn hears(&self, channel: u8, start: Duration, end: Duration) -> bool {
+        let interval = self.interval.as_nanos().max(1);
+        self.channel_at(start) == Some(channel)
+            && start.as_nanos() / interval == end.as_nanos() / interval
+            && self.channel_at(end) == Some(channel)
+    }
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub enum ReceptionKind {
+    Advertisement,
+    ScanResponse,
+    /// `SCAN_REQ` was sent but the scan window closed (or the channel changed) before the
+    /// `SCAN_RSP` finished.
+    MissedScanResponse,
+}
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct Reception {
+    /// Simulated time the PDU finished.
+    pub at: Duration,
+    pub channel: u8,
+    pub kind: ReceptionKind,
+}
+/// One advertiser and one scanner on a simulated clock.
+#[derive(Clone, Debug)]
+pub struct TimingModel {
+    pub advertiser: AdvertiserTiming,
+    pub scanner: ScannerTiming,
+    /// When the scanner started relative to the first advertising event.
+    pub scanner_offset: Duration,
+    rng: DeterministicRng,
+}
+impl TimingModel {
+    pub fn new(advertiser: AdvertiserTiming, scanner: ScannerTiming, seed: u64) -> TimingModel {
+        TimingModel {
+            advertiser,
+            scanner,
+            scanner_offset: Duration::default(),
+            rng: DeterministicRng::new(seed),
+        }
+    }
+    /// Uses a (seeded) random scanner start offset within the first three scan intervals so
+    /// runs with different seeds start on different channels and phases.
+    #[must_use]
+    pub fn with_random_offset(mut self) -> TimingModel {
+        let range = u64::try_from(self.scanner.interval.as_micros()).unwrap_or(u64::MAX / 4) * 3;
+        self.scanner_offset = Duration::from_micros(self.rng.below(range));
+        self
+    }
+    fn adv_delay(&mut self) -> Duration {
+        Duration::from_micros(self.rng.below(MAX_ADV_DELAY_MICROS + 1))
+    }
+    fn scanner_hears(&self, channel: u8, start: Duration, end: Duration) -> bool {
+        match (
+            start.checked_sub(self.scanner_offset),
+            end.checked_sub(self.scanner_offset),
+        ) {
+            (Some(start), Some(end)) => self.scanner.hears(channel, start, end),
+            _ => false,
+        }
+    }
+    /// Simulates everything received by the scanner until `until`.
+    pub fn run(&mut self, until: Duration) -> Vec<Reception> {
+        let mut out = Vec::new();
+        let adv_air = air_time_1m(self.advertiser.adv_payload_len);
+        let mut event_start = Duration::default();
+        while event_start < until {
+            let mut t = event_start;
+            for &channel in &ADVERTISING_CHANNELS {
+                let adv_end = t + adv_air;
+                if adv_end > until {
+                    return out;
+                }
+                let mut next = adv_end + self.advertiser.channel_gap;
+                if self.scanner_hears(channel, t, adv_end) {
+                    out.push(Reception {
+                        at: adv_end,
+                        channel,
+                        kind: ReceptionKind::Advertisement,
+                    });
+                    if let (true, Some(rsp_len)) =
+                        (self.scanner.active, self.advertiser.scan_rsp_payload_len)
+                    {
+                        let req_end = adv_end + T_IFS + air_time_1m(SCAN_REQ_PAYLOAD_LEN);
+                        let rsp_start = req_end + T_IFS;
+                        let rsp_end = rsp_start + air_time_1m(rsp_len);
+                        let kind = if self.scanner_hears(channel, t, rsp_end) {
+                            ReceptionKind::ScanResponse
+                        } else {
+                            ReceptionKind::MissedScanResponse
+                        };
+                        out.push(Reception {
+                            at: rsp_end,
+                            channel,
+                            kind,
+                        });
+                        next = next.max(rsp_end + T_IFS);
+                    }
+                }
+                t = next;
+            }
+            event_start += self.advertiser.interval + self.adv_delay();
+        }
+        out
+    }
+    /// Time from the scanner starting until the first advertisement is received or `None` if
+    /// nothing was received before `until`.
+    pub fn first_discovery(&mut self, until: Duration) -> Option<Duration> {
+        let offset = self.scanner_offset;
+        self.run(until)
+            .into_iter()
+            .find(|r| r.kind == ReceptionKind::Advertisement)
+            .and_then(|r| r.at.checked_sub(offset))
+    }
+}
+/// First discovery latency for every seed in `seeds` (each with a random scanner offset).
+pub fn discovery_latencies(
+    advertiser: AdvertiserTiming,
+    scanner: ScannerTiming,
+    seeds: core::ops::Range<u64>,
+    until: Duration,
+) -> Vec<Option<Duration>> {
+    seeds
+        .map(|seed| {
+            TimingModel::new(advertiser, scanner, seed)
+                .with_random_offset()
+                .first_discovery(until)
+        })
+        .collect()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_discovery_and_missed_scan_response() {
+        let advertiser =
+            AdvertiserTiming::new(Duration::from_millis(100), 31).with_scan_response(31);
+        // Continuous active scanning discovers the first advertising event and gets the response.
+        let continuous = ScannerTiming {
+            interval: Duration::from_millis(30),
+            window: Duration::from_millis(30),
+            active: true,
+        };
+        for latency in discovery_latencies(advertiser, continuous, 0..16, Duration::from_secs(1)) {
+            assert!(latency.unwrap() < Duration::from_millis(115));
+        }
+        let mut model = TimingModel::new(advertiser, continuous, 1);
+        assert!(model
+            .run(Duration::from_secs(1))
+            .iter()
+            .any(|r| r.kind == ReceptionKind::ScanResponse));
+        // A window barely longer than the advertising PDU hears the advertisement but the scan
+        // response always ends after the window closes.
+        let narrow = ScannerTiming {
+            interval: Duration::from_millis(10),
+            window: air_time_1m(6 + 31) + Duration::from_micros(200),
+            active: true,
+        };
+        let receptions = TimingModel::new(advertiser, narrow, 7).run(Duration::from_secs(20));
+        assert!(receptions
+            .iter()
+            .any(|r| r.kind == ReceptionKind::MissedScanResponse));
+        assert!(receptions
+            .iter()
+            .all(|r| r.kind != ReceptionKind::ScanResponse));
+    }
+}
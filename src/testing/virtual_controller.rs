@@ -0,0 +1,507 @@
+//! [`VirtualController`] emulates a small part of a LE controller: Reset, Read BD_ADDR, the event
+//! masks, legacy scanning and advertising, LE Create Connection and Disconnect. Remote devices
+//! are [`VirtualPeer`]s added through a [`VirtualHandle`]. While scanning, every advertising peer
+//! is reported and a connectable peer can connect to (or be connected from) the controller.
+//! Unlike [`MockTransport`](crate::testing::mock::MockTransport) nothing has to be scripted so
+//! higher layers (scanners, GATT) can be tested without knowing the exact commands they send.
+//! Other commands are answered with `UnknownHCICommand` and ACL data is recorded.
+use crate::error::IOError;
+use crate::hci::adapter;
+use crate::hci::baseband::ControllerBasebandOpcode;
+use crate::hci::event::EventCode;
+use crate::hci::informational::InformationalParametersOpcode;
+use crate::hci::le::{LEControllerOpcode, MetaEventCode};
+use crate::hci::link_control::LinkControlOpcode;
+use crate::hci::packet::{h4_frame_len, PacketType};
+use crate::hci::stream::{Filter, HCIFilterable, HCIReader, HCIWriter};
+use crate::hci::{ErrorCode, Opcode, StreamError};
+use crate::le::advertiser::PeerAddressType;
+use crate::le::connection::ConnectionHandle;
+use crate::testing::mock::{command_complete, event_packet};
+use crate::BTAddress;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::convert::TryFrom;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+/// A remote device.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct VirtualPeer {
+    pub address_type: PeerAddressType,
+    pub address: BTAddress,
+    /// Advertising data. `None` if the peer isn't advertising.
+    pub advertising_data: Option<Vec<u8>>,
+    pub connectable: bool,
+    pub rssi: i8,
+}
+impl VirtualPeer {
+    /// Connectable advertiser with `advertising_data`.
+    pub fn advertiser(address: BTAddress, advertising_data: &[u8]) -> VirtualPeer {
+        VirtualPeer {
+            address_type: PeerAddressType::Public,
+            address,
+            advertising_data: Some(advertising_data.to_vec()),
+            connectable: true,
+            rssi: -60,
+        }
+    }
+}
+/// A connection from the controller's point of view.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
+pub struct VirtualConnection {
+    pub connection_handle: ConnectionHandle,
+    pub peer_address_type: PeerAddressType,
+    pub peer_address: BTAddress,
+    /// `true` if the host initiated the connection.
+    pub central: bool,
+}
+#[derive(Debug)]
+struct ControllerState {
+    address: BTAddress,
+    written: Vec<u8>,
+    to_read: VecDeque<Vec<u8>>,
+    waker: Option<Waker>,
+    closed: bool,
+    filter: Filter,
+    commands: Vec<Opcode>,
+    acl_data: Vec<Vec<u8>>,
+    scanning: bool,
+    advertising: bool,
+    advertising_data: Vec<u8>,
+    scan_response_data: Vec<u8>,
+    /// Peer the host is connecting to (LE Create Connection pending).
+    connecting: Option<(PeerAddressType, BTAddress)>,
+    peers: Vec<VirtualPeer>,
+    connections: BTreeMap<ConnectionHandle, VirtualConnection>,
+    next_handle: u16,
+}
+const FIRST_CONNECTION_HANDLE: u16 = 0x0040;
+/// Advertising report event type of `ADV_IND`/`ADV_NONCONN_IND`.
+const ADV_IND: u8 = 0x00;
+const ADV_NONCONN_IND: u8 = 0x03;
+impl ControllerState {
+    fn new(address: BTAddress) -> ControllerState {
+        ControllerState {
+            address,
+            written: Vec::new(),
+            to_read: VecDeque::new(),
+            waker: None,
+            closed: false,
+            filter: Filter::default(),
+            commands: Vec::new(),
+            acl_data: Vec::new(),
+            scanning: false,
+            advertising: false,
+            advertising_data: Vec::new(),
+            scan_response_data: Vec::new(),
+            connecting: None,
+            peers: Vec::new(),
+            connections: BTreeMap::new(),
+            next_handle: FIRST_CONNECTION_HANDLE,
+        }
+    }
+    /// Back to the state after power on (peers stay, they aren't part of the controller).
+    fn reset(&mut self) {
+        self.scanning = false;
+        self.advertising = false;
+        self.advertising_data.clear();
+        self.scan_response_data.clear();
+        self.connecting = None;
+        self.connections.clear();
+        self.next_handle = FIRST_CONNECTION_HANDLE;
+    }
+    fn queue_read(&mut self, packet: Vec<u8>) {
+        self.to_read.push_back(packet);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+    fn complete(&mut self, opcode: Opcode, return_parameters: &[u8]) {
+        self.queue_read(command_complete(opcode, return_parameters));
+    }
+    fn complete_status(&mut self, opcode: Opcode, status: ErrorCode) {
+        self.complete(opcode, &[status.into()]);
+    }
+    fn command_status(&mut self, opcode: Opcode, status: ErrorCode) {
+        let mut parameters = [status.into(), 1, 0, 0];
+        opcode
+            .pack(&mut parameters[2..])
+            .expect("hardcoded opcode length");
+        self.queue_read(event_packet(EventCode::CommandStatus, &parameters));
+    }
+    fn meta_event(&mut self, code: MetaEventCode, parameters: &[u8]) {
+        let mut buf = Vec::with_capacity(parameters.len() + 1);
+        buf.push(code.into());
+        buf.extend_from_slice(parameters);
+        self.queue_read(event_packet(EventCode::LEMeta, &buf));
+    }
+    fn report(&mut self, peer: &VirtualPeer) {
+        let data = match (&peer.advertising_data, self.scanning) {
+            (Some(data), true) => data,
+            _ => return,
+        };
+        let event_type = if peer.connectable {
+            ADV_IND
+        } else {
+            ADV_NONCONN_IND
+        };
+        let mut parameters = vec![1, event_type, peer.address_type.into()];
+        parameters.extend_from_slice(&peer.address.0);
+        parameters.push(u8::try_from(data.len()).expect("advertising data too long"));
+        parameters.extend_from_slice(data);
+        parameters.push(peer.rssi as u8);
+        self.meta_event(MetaEventCode::AdvertisingReport, &parameters);
+    }
+    fn connect(
+        &mut self,
+        peer_address_type: PeerAddressType,
+        peer_address: BTAddress,
+        central: bool,
+    ) -> ConnectionHandle {
+        let connection_handle = ConnectionHandle::new(self.next_handle);
+        self.next_handle += 1;
+        self.connections.insert(
+            connection_handle,
+            VirtualConnection {
+                connection_handle,
+                peer_address_type,
+                peer_address,
+                central,
+            },
+        );
+        let mut parameters = vec![ErrorCode::Ok.into()];
+        parameters.extend_from_slice(&u16::from(connection_handle).to_le_bytes());
+        parameters.push(if central { 0x00 } else { 0x01 });
+        parameters.push(peer_address_type.into());
+        parameters.extend_from_slice(&peer_address.0);
+        // 30 ms interval, no latency, 720 ms supervision timeout, 500 ppm.
+        parameters.extend_from_slice(&[0x18, 0x00, 0x00, 0x00, 0x48, 0x00, 0x00]);
+        self.meta_event(MetaEventCode::ConnectionComplete, &parameters);
+        connection_handle
+    }
+    fn disconnect(&mut self, connection_handle: ConnectionHandle, reason: ErrorCode) -> bool {
+        if self.connections.remove(&connection_handle).is_none() {
+            return false;
+        }
+        let mut parameters = vec![ErrorCode::Ok.into()];
+        parameters.extend_from_slice(&u16::from(connection_handle).to_le_bytes());
+        parameters.push(reason.into());
+        self.queue_read(event_packet(EventCode::DisconnectionComplete, &parameters));
+        true
+    }
+    /// Handles every complete packet written so far.
+    fn process_written(&mut self) -> Result<(), adapter::Error> {
+        loop {
+            let len = h4_frame_len(&self.written)?;
+            if self.written.len() < len {
+                return Ok(());
+            }
+            let packet: Vec<u8> = self.written.drain(..len).collect();
+            match PacketType::try_from(packet[0]) {
+                Ok(PacketType::Command) => {
+                    let opcode =
+                        Opcode::unpack(&packet[1..3]).map_err(|_| StreamError::BadOpcode)?;
+                    self.commands.push(opcode);
+                    self.command(opcode, &packet[4..]);
+                }
+                _ => self.acl_data.push(packet),
+            }
+        }
+    }
+    fn command(&mut self, opcode: Opcode, parameters: &[u8]) {
+        if opcode == ControllerBasebandOpcode::Reset.into() {
+            self.reset();
+            self.complete_status(opcode, ErrorCode::Ok);
+        } else if opcode == ControllerBasebandOpcode::SetEventMask.into()
+            || opcode == LEControllerOpcode::SetEventMask.into()
+            || opcode == LEControllerOpcode::SetScanParameters.into()
+            || opcode == LEControllerOpcode::SetAdvertisingParameters.into()
+        {
+            self.complete_status(opcode, ErrorCode::Ok);
+        } else if opcode == InformationalParametersOpcode::ReadBDADDR.into() {
+            let mut ret = vec![ErrorCode::Ok.into()];
+            ret.extend_from_slice(&self.address.0);
+            self.complete(opcode, &ret);
+        } else if opcode == LEControllerOpcode::SetAdvertisingData.into() {
+            self.advertising_data = significant_data(parameters);
+            self.complete_status(opcode, ErrorCode::Ok);
+        } else if opcode == LEControllerOpcode::SetScanResponseData.into() {
+            self.scan_response_data = significant_data(parameters);
+            self.complete_status(opcode, ErrorCode::Ok);
+        } else if opcode == LEControllerOpcode::SetAdvertisingEnable.into() {
+            self.advertising = parameters.first() == Some(&1);
+            self.complete_status(opcode, ErrorCode::Ok);
+        } else if opcode == LEControllerOpcode::SetScanEnable.into() {
+            let enable = parameters.first() == Some(&1);
+            let started = enable && !self.scanning;
+            self.scanning = enable;
+            self.complete_status(opcode, ErrorCode::Ok);
+            if started {
+                for peer in self.peers.clone() {
+                    self.report(&peer);
+                }
+            }
+        } else if opcode == LEControllerOpcode::CreateConnection.into() {
+            self.create_connection(opcode, parameters);
+        } else if opcode == LEControllerOpcode::CreateConnectionCancel.into() {
+            self.complete_status(opcode, ErrorCode::Ok);
+            if self.connecting.take().is_some() {
+                let mut parameters = vec![ErrorCode::NoConnection.into()];
+                parameters.extend_from_slice(&[0; 17]);
+                self.meta_event(MetaEventCode::ConnectionComplete, &parameters);
+            }
+        } else if opcode == LinkControlOpcode::Disconnect.into() {
+            let handle = match parameters {
+                [h0, h1, _] => ConnectionHandle::new_checked(u16::from_le_bytes([*h0, *h1])),
+                _ => None,
+            };
+            match handle.filter(|handle| self.connections.contains_key(handle)) {
+                Some(handle) => {
+                    self.command_status(opcode, ErrorCode::Ok);
+                    self.disconnect(handle, ErrorCode::ConnectionTerminatedByLocalHost);
+                }
+                None => self.command_status(opcode, ErrorCode::NoConnection),
+            }
+        } else {
+            self.complete_status(opcode, ErrorCode::UnknownHCICommand);
+        }
+    }
+    fn create_connection(&mut self, opcode: Opcode, parameters: &[u8]) {
+        let peer = match (parameters.get(5), parameters.get(6..12)) {
+            (Some(&address_type), Some(address)) => PeerAddressType::try_from(address_type)
+                .ok()
+                .zip(BTAddress::unpack_from(address).ok()),
+            _ => None,
+        };
+        let (address_type, address) = match peer {
+            Some(peer) if self.connecting.is_none() => peer,
+            Some(_) => return self.command_status(opcode, ErrorCode::CommandDisallowed),
+            None => return self.command_status(opcode, ErrorCode::InvalidHCICommandParameters),
+        };
+        self.command_status(opcode, ErrorCode::Ok);
+        let connectable = self.peers.iter().any(|peer| {
+            peer.address == address && peer.address_type == address_type && peer.connectable
+        });
+        if connectable {
+            self.connect(address_type, address, true);
+        } else {
+            // Keeps trying until cancelled, like a controller that never hears the peer.
+            self.connecting = Some((address_type, address));
+        }
+    }
+}
+/// Only the first `len` bytes of advertising/scan response data matter.
+fn significant_data(parameters: &[u8]) -> Vec<u8> {
+    match parameters.split_first() {
+        Some((&len, data)) => data[..usize::from(len).min(data.len())].to_vec(),
+        None => Vec::new(),
+    }
+}
+/// Controls a [`VirtualController`] and inspects its state. Cloning gives another handle to the
+/// same controller.
+#[derive(Clone, Debug)]
+pub struct VirtualHandle {
+    state: Rc<RefCell<ControllerState>>,
+}
+impl VirtualHandle {
+    /// Adds a remote device. It is reported right away if the controller is scanning.
+    pub fn add_peer(&self, peer: VirtualPeer) {
+        let mut state = self.state.borrow_mut();
+        state.report(&peer);
+        if state.connecting == Some((peer.address_type, peer.address)) && peer.connectable {
+            state.connecting = None;
+            state.connect(peer.address_type, peer.address, true);
+        }
+        state.peers.push(peer);
+    }
+    /// Removes every peer with `address`.
+    pub fn remove_peer(&self, address: BTAddress) {
+        self.state
+            .borrow_mut()
+            .peers
+            .retain(|peer| peer.address != address);
+    }
+    /// Reports every advertising peer again (if scanning).
+    pub fn advertise_peers(&self) {
+        let mut state = self.state.borrow_mut();
+        for peer in state.peers.clone() {
+            state.report(&peer);
+        }
+    }
+    /// A peer connects to the advertising controller. Returns `None` (and does nothing) if the
+    /// controller isn't advertising.
+    pub fn connect_from(
+        &self,
+        address_type: PeerAddressType,
+        address: BTAddress,
+    ) -> Option<ConnectionHandle> {
+        let mut state = self.state.borrow_mut();
+        if !state.advertising {
+            return None;
+        }
+        // Legacy advertising stops once connected.
+        state.advertising = false;
+        Some(state.connect(address_type, address, false))
+    }
+    /// The peer ends the connection. Returns `false` if there is no such connection.
+    pub fn disconnect(&self, connection_handle: ConnectionHandle, reason: ErrorCode) -> bool {
+        self.state
+            .borrow_mut()
+            .disconnect(connection_handle, reason)
+    }
+    /// Queues an H4 packet (indicator byte first) for the host to read.
+    pub fn inject(&self, packet: &[u8]) {
+        self.state.borrow_mut().queue_read(packet.to_vec());
+    }
+    /// Ends the read side after the already queued packets.
+    pub fn close(&self) {
+        let mut state = self.state.borrow_mut();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+    pub fn address(&self) -> BTAddress {
+        self.state.borrow().address
+    }
+    pub fn is_scanning(&self) -> bool {
+        self.state.borrow().scanning
+    }
+    pub fn is_advertising(&self) -> bool {
+        self.state.borrow().advertising
+    }
+    pub fn advertising_data(&self) -> Vec<u8> {
+        self.state.borrow().advertising_data.clone()
+    }
+    pub fn scan_response_data(&self) -> Vec<u8> {
+        self.state.borrow().scan_response_data.clone()
+    }
+    pub fn connections(&self) -> Vec<VirtualConnection> {
+        self.state.borrow().connections.values().copied().collect()
+    }
+    /// Opcodes of every command received, oldest first.
+    pub fn commands(&self) -> Vec<Opcode> {
+        self.state.borrow().commands.clone()
+    }
+    /// Takes the ACL (and other non command) packets written by the host.
+    pub fn take_acl_data(&self) -> Vec<Vec<u8>> {
+        core::mem::take(&mut self.state.borrow_mut().acl_data)
+    }
+}
+/// Emulated controller. See the module docs.
+#[derive(Debug)]
+pub struct VirtualController {
+    state: Rc<RefCell<ControllerState>>,
+}
+impl VirtualController {
+    pub fn new(address: BTAddress) -> (VirtualController, VirtualHandle) {
+        let controller = VirtualController {
+            state: Rc::new(RefCell::new(ControllerState::new(address))),
+        };
+        let handle = controller.handle();
+        (controller, handle)
+    }
+    pub fn handle(&self) -> VirtualHandle {
+        VirtualHandle {
+            state: self.state.clone(),
+        }
+    }
+}
+impl HCIReader for VirtualController {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let mut state = self.state.borrow_mut();
+        match state.to_read.pop_front() {
+            Some(packet) => Poll::Ready(match buf.get_mut(..packet.len()) {
+                Some(out) => {
+                    out.copy_from_slice(&packet);
+                    Ok(packet.len())
+                }
+                None => Err(IOError::Overflow.into()),
+            }),
+            None if state.closed => Poll::Ready(Err(StreamError::StreamClosed.into())),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+impl HCIWriter for VirtualController {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, adapter::Error>> {
+        let mut state = self.state.borrow_mut();
+        state.written.extend_from_slice(buf);
+        Poll::Ready(state.process_written().map(|()| buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), adapter::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+impl HCIFilterable for VirtualController {
+    fn set_filter(self: Pin<&mut Self>, filter: &Filter) -> Result<(), adapter::Error> {
+        self.state.borrow_mut().filter = *filter;
+        Ok(())
+    }
+
+    fn get_filter(self: Pin<&Self>) -> Result<Filter, adapter::Error> {
+        Ok(self.state.borrow().filter)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hci::adapters::le::LEAdapter;
+    use crate::hci::adapters::Adapter;
+    use crate::hci::stream::Stream;
+    use futures_util::{FutureExt, StreamExt};
+
+    #[test]
+    fn test_scan_and_connect() {
+        let (controller, handle) = VirtualController::new(BTAddress([1, 2, 3, 4, 5, 6]));
+        let peer = BTAddress([0xC0, 0, 0, 0, 0, 0x11]);
+        handle.add_peer(VirtualPeer::advertiser(peer, &[0x02, 0x01, 0x06]));
+        let mut adapter = LEAdapter::new(Adapter::new(Stream::new(Box::pin(controller))));
+        adapter.adapter.reset().now_or_never().unwrap().unwrap();
+        adapter
+            .set_scan_enable(true, false)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(handle.is_scanning());
+        let report = {
+            let stream = adapter.advertising_report_stream_without_mask::<Vec<_>>();
+            futures_util::pin_mut!(stream);
+            stream.next().now_or_never().unwrap().unwrap().unwrap()
+        };
+        let info = report.into_iter().next().unwrap();
+        assert_eq!(info.address, peer);
+        assert_eq!(info.data.as_ref(), [0x02, 0x01, 0x06]);
+
+        adapter
+            .set_advertising_enable(true)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let connection_handle = handle
+            .connect_from(PeerAddressType::Random, peer)
+            .expect("advertising");
+        assert_eq!(connection_handle, ConnectionHandle::new(0x0040));
+        assert!(!handle.is_advertising());
+        assert!(handle.disconnect(
+            connection_handle,
+            ErrorCode::OtherEndTerminatedConnectionUserEndedConnection
+        ));
+        assert!(handle.connections().is_empty());
+    }
+}
@@ -0,0 +1,27 @@
+//! Internal logging macros. Forward to `tracing` with the `tracing` feature, else to `log` with
+//! the `log` feature and compile to nothing (arguments still type checked) without either.
+
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+        #[cfg(all(feature = "log", not(feature = "tracing")))]
+        log::$level!($($arg)+);
+        #[cfg(not(any(feature = "log", feature = "tracing")))]
+        {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+/// Packet level detail (every packet sent or received).
+macro_rules! hci_trace {
+    ($($arg:tt)+) => { trace_event!(trace, $($arg)+) };
+}
+/// State changes (filters, routes).
+macro_rules! hci_debug {
+    ($($arg:tt)+) => { trace_event!(debug, $($arg)+) };
+}
+/// Errors that are returned (or dropped) after being logged.
+macro_rules! hci_warn {
+    ($($arg:tt)+) => { trace_event!(warn, $($arg)+) };
+}